@@ -1,8 +1,14 @@
 
 // use protoc_rust;
 
+use std::fs;
+use std::path::Path;
+
+use sha2::{Digest, Sha256};
+
 fn main() {
-    // TODO: Specify a rebuild-if
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=protobufs/feoblog.proto");
     protoc_rust::Codegen::new()
         .out_dir("src/protos")
         .inputs(&["protobufs/feoblog.proto"])
@@ -16,4 +22,49 @@ fn main() {
     // println!("cargo:warning=OUT_DIR={}", out_dir);
 
     // TODO: Build web-client first? I guess I've been manually doing this so far.
-}
\ No newline at end of file
+
+    write_asset_hashes();
+}
+
+/// Hash the files that the server embeds, so that it can send ETags for them
+/// without hashing them at runtime. See: src/server/assets.rs
+fn write_asset_hashes() {
+    let mut out = String::new();
+    for (name, folder) in &[("STATIC_FILES", "static"), ("WEB_CLIENT_FILES", "web-client/build")] {
+        println!("cargo:rerun-if-changed={}", folder);
+
+        let mut hashes = vec![];
+        hash_files(Path::new(folder), "", &mut hashes);
+        hashes.sort();
+
+        out += &format!("pub(crate) const {}: &[(&str, &str)] = &[\n", name);
+        for (path, hash) in hashes {
+            out += &format!("    ({:?}, {:?}),\n", path, hash);
+        }
+        out += "];\n";
+    }
+
+    let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR");
+    fs::write(Path::new(&out_dir).join("asset_hashes.rs"), out).expect("writing asset_hashes.rs");
+}
+
+fn hash_files(dir: &Path, prefix: &str, hashes: &mut Vec<(String, String)>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        // ex: The web client hasn't been built yet.
+        Err(_) => return,
+    };
+
+    for entry in entries {
+        let entry = entry.expect("reading directory");
+        let path = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        if entry.file_type().expect("file type").is_dir() {
+            hash_files(&entry.path(), &format!("{}/", path), hashes);
+            continue;
+        }
+
+        let bytes = fs::read(entry.path()).expect("reading file");
+        let hash: String = Sha256::digest(&bytes).iter().map(|b| format!("{:02x}", b)).collect();
+        hashes.push((path, hash));
+    }
+}
@@ -0,0 +1,60 @@
+//! Atom feeds, so that people can follow activity on FeoBlog from their
+//! ordinary feed readers.
+//!
+//! See: <https://tools.ietf.org/html/rfc4287>
+
+use crate::backend::Timestamp;
+use crate::follows::escape;
+
+/// An Atom `<feed>`.
+pub(crate) struct Feed {
+    /// A BCP 47 language tag for the feed's text. (ex: "en")
+    pub lang: String,
+
+    /// An absolute URL that uniquely identifies the feed. Also its `self` link.
+    pub id: String,
+    pub title: String,
+    pub updated: Timestamp,
+    pub entries: Vec<Entry>,
+}
+
+pub(crate) struct Entry {
+    /// An absolute URL that uniquely identifies the entry. Also its `alternate` link.
+    pub id: String,
+    pub title: String,
+    pub author: String,
+    pub updated: Timestamp,
+
+    /// HTML content for the entry. Must already be sanitized.
+    pub content_html: String,
+
+    /// An SPDX license identifier, or "" if the author didn't give one.
+    pub license: String,
+}
+
+impl Feed {
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        xml.push_str(&format!("<feed xmlns=\"http://www.w3.org/2005/Atom\" xml:lang=\"{}\">\n", escape(&self.lang)));
+        xml.push_str(&format!("  <id>{}</id>\n", escape(&self.id)));
+        xml.push_str(&format!("  <title>{}</title>\n", escape(&self.title)));
+        xml.push_str(&format!("  <updated>{}</updated>\n", self.updated.format_rfc3339()));
+        xml.push_str(&format!("  <link rel=\"self\" href=\"{}\"/>\n", escape(&self.id)));
+        for entry in &self.entries {
+            xml.push_str("  <entry>\n");
+            xml.push_str(&format!("    <id>{}</id>\n", escape(&entry.id)));
+            xml.push_str(&format!("    <title>{}</title>\n", escape(&entry.title)));
+            xml.push_str(&format!("    <author><name>{}</name></author>\n", escape(&entry.author)));
+            xml.push_str(&format!("    <updated>{}</updated>\n", entry.updated.format_rfc3339()));
+            xml.push_str(&format!("    <link rel=\"alternate\" href=\"{}\"/>\n", escape(&entry.id)));
+            xml.push_str(&format!("    <content type=\"html\">{}</content>\n", escape(&entry.content_html)));
+            if !entry.license.is_empty() {
+                xml.push_str(&format!("    <rights>{}</rights>\n", escape(&entry.license)));
+            }
+            xml.push_str("  </entry>\n");
+        }
+        xml.push_str("</feed>\n");
+        xml
+    }
+}
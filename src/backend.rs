@@ -1,8 +1,12 @@
 //! Types for data storage/retrieval.
 
+pub(crate) mod sharded;
 pub(crate) mod sqlite;
 
-use crate::protos::Item;
+#[cfg(test)]
+mod tests;
+
+use crate::protos::{Item, ItemType};
 use core::str::FromStr;
 use std::marker::PhantomData;
 use failure::{Error, ResultExt, bail, format_err};
@@ -28,6 +32,13 @@ pub trait Backend
     /// Set up the initial DB state, maybe running migrations.
     fn setup(&self) -> Result<(), Error>;
 
+    /// Write any pending changes back to the database file(s). (ex: before shutting down)
+    fn checkpoint(&self) -> Result<(), Error>;
+
+    /// Copy a consistent snapshot of the whole database to a new SQLite file
+    /// at `dest`, while the server keeps running. (ex: `GET /backup`)
+    fn backup(&self, dest: &std::path::Path) -> Result<(), Error>;
+
     /// Find most recent items for users flagged to be displayed on the
     /// home page, which have timestamps before `before`.
     /// Items are returned through callback, and will continue to be fetched while callback continues
@@ -43,19 +54,37 @@ pub trait Backend
     ) -> Result<(), Error>;
 
     /// Find the most recent items from users followed by the given user ID. Includes the users's own items too.
+    /// Excludes items from users that `viewer` has muted, if we know who's viewing.
     fn user_feed_items<'a>(
         &self,
         user_id: &UserID,
+        viewer: Option<&UserID>,
         before: Timestamp,
         callback: &'a mut dyn FnMut(ItemDisplayRow) -> Result<bool, Error>,
     ) -> Result<(), Error>;
 
     /// Find one particular UserItem
+    /// Like all item listings, excludes followers-only items. (See: `followers_only_item`)
     fn user_item(&self, user: &UserID, signature: &Signature) -> Result<Option<ItemRow>, Error>;
 
+    /// Like `user_item`, but also finds followers-only items, if `viewer` is
+    /// their author, or follows them.
+    fn followers_only_item(&self, user: &UserID, signature: &Signature, viewer: &UserID) -> Result<Option<ItemRow>, Error>;
+
+    /// A user's followers-only items, most recent first. Finds none unless
+    /// `viewer` is the user, or follows them.
+    fn followers_only_items<'a>(&self, user: &UserID, viewer: &UserID, before: Timestamp, cb: FnIter<'a, ItemRow>) -> Result<(), Error>;
+
+    /// Does `follower`'s latest profile follow `followed`?
+    fn follows(&self, follower: &UserID, followed: &UserID) -> Result<bool, Error>;
+
     /// Effieicntly check whether a user item exists:
     fn user_item_exists(&self, user: &UserID, signature: &Signature) -> Result<bool, Error>;
 
+    /// Did the user delete this item? (See: `Delete`)
+    /// Deleted items leave a tombstone, so that we don't accept them again.
+    fn user_item_deleted(&self, user: &UserID, signature: &Signature) -> Result<bool, Error>;
+
     /// Save an uploaded item to the data store.
     fn save_user_item(&mut self, item_row: &ItemRow, item: &Item) -> Result<(), Error>;
 
@@ -69,6 +98,16 @@ pub trait Backend
     /// Add a new "server user" who is explicitly allowed to post to this server.
     fn add_server_user(&self, server_user: &ServerUser) -> Result<(), Error>;
 
+    /// Stop allowing a user to post to this server. Their existing items are kept.
+    /// Returns false if they weren't a server user.
+    fn remove_server_user(&self, user: &UserID) -> Result<bool, Error>;
+
+    /// A server user's quota. None if they're not a server user.
+    fn user_quota(&self, user: &UserID) -> Result<Option<Quota>, Error>;
+
+    /// Set a server user's quota. Returns false if they're not a server user.
+    fn set_user_quota(&self, user: &UserID, quota: &Quota) -> Result<bool, Error>;
+
     /// Get the Item(Row) that represents the user's most recently saved profile, if it exists.
     fn user_profile(&self, user_id: &UserID) -> Result<Option<ItemRow>, Error>;
 
@@ -79,6 +118,370 @@ pub trait Backend
     /// * The user is followed by a "server user". (We want their content so we can create a feed.)
     fn user_known(&self, user_id: &UserID) -> Result<bool, Error>;
 
+    /// Soft-delete an item. It will no longer be served, but can be restored
+    /// with [`Backend::restore_user_item`] until it is purged.
+    /// Returns false if there was no such (un-removed) item.
+    fn remove_user_item(&self, user: &UserID, signature: &Signature) -> Result<bool, Error>;
+
+    /// Undo [`Backend::remove_user_item`].
+    /// Returns false if there was no such removed item.
+    fn restore_user_item(&self, user: &UserID, signature: &Signature) -> Result<bool, Error>;
+
+    /// Hold a newly-saved item until an operator approves it, like a removed
+    /// item. (See: `--quarantine-pow-items`)
+    /// Returns false if there was no such (un-removed) item.
+    fn quarantine_user_item(&self, user: &UserID, signature: &Signature) -> Result<bool, Error>;
+
+    /// Serve a quarantined item.
+    /// Returns false if the item isn't quarantined. (Including if it was
+    /// removed since, or its item events were pruned. Restore those instead.)
+    fn approve_user_item(&self, user: &UserID, signature: &Signature) -> Result<bool, Error>;
+
+    /// Items waiting for approval, oldest first. (Per shard, if sharded.)
+    fn quarantined_items<'a>(&self, cb: FnIter<'a, ItemRow>) -> Result<(), Error>;
+
+    /// Note that a peer accepted one of our items. (See: replication.rs)
+    fn record_item_synced(&self, user: &UserID, signature: &Signature, when: Timestamp) -> Result<(), Error>;
+
+    /// Rebuild the tables derived from items' bytes (profiles, follows, votes,
+    /// references, search, and summaries), and check that the results add up.
+    /// If they don't, nothing is changed.
+    fn reindex(&mut self) -> Result<ReindexReport, Error>;
+
+    /// Remove items whose authors set them to expire before `now`, as if by
+    /// [`Backend::remove_user_item`], so that they're purged with other removed items.
+    /// Returns the number of items that expired.
+    fn expire_items(&self, now: Timestamp) -> Result<usize, Error>;
+
+    /// Delete bookkeeping (item events, sync reports, stale queue entries)
+    /// older than `retention` keeps.
+    fn prune(&self, retention: &Retention, now: Timestamp) -> Result<PruneReport, Error>;
+
+    /// Permanently delete items that were removed before `removed_before`.
+    /// Returns the number of items deleted.
+    fn purge_removed_items(&self, removed_before: Timestamp) -> Result<usize, Error>;
+
+    /// Every item we have, including removed ones, in no particular order.
+    /// (ex: for `feoblog db verify`)
+    fn all_items<'a>(&self, cb: FnIter<'a, ItemRow>) -> Result<(), Error>;
+
+    /// Permanently delete an item (removed or not) and its attachments now.
+    /// Profiles, follows, and votes derived from it are left as they are, so
+    /// [`Backend::reindex`] afterward. Returns false if there was no such item.
+    fn delete_user_item(&self, user: &UserID, signature: &Signature) -> Result<bool, Error>;
+
+    /// List IP networks that are blocked from accessing this server.
+    fn ip_blocks<'a>(&self, cb: FnIter<'a, IpBlock>) -> Result<(), Error>;
+
+    /// Block an IP network. Replaces any existing block for the same CIDR.
+    fn add_ip_block(&self, block: &IpBlock) -> Result<(), Error>;
+
+    /// Unblock an IP network. Returns false if it wasn't blocked.
+    fn remove_ip_block(&self, cidr: &str) -> Result<bool, Error>;
+
+    /// Count one (anonymous) view of an item on the day of `when`.
+    fn record_item_view(&self, user: &UserID, signature: &Signature, when: Timestamp) -> Result<(), Error>;
+
+    /// List daily view counts for a user's items, most recent days first.
+    fn user_item_view_counts<'a>(&self, user: &UserID, cb: FnIter<'a, ItemViewCount>) -> Result<(), Error>;
+
+    /// Count a user's items, grouped by the (UTC) month they were posted. Most recent months first.
+    fn user_item_counts_by_month<'a>(&self, user: &UserID, cb: FnIter<'a, MonthCount>) -> Result<(), Error>;
+
+    /// A user's most-viewed items, of all time. Most views first.
+    fn user_top_viewed_items<'a>(&self, user: &UserID, cb: FnIter<'a, ItemViewCount>) -> Result<(), Error>;
+
+    /// How many users (whose profiles this server has) follow this user.
+    fn user_follower_count(&self, user: &UserID) -> Result<u64, Error>;
+
+    /// Count a user's current followers, grouped by the (UTC) month they
+    /// started following. (By their profile's timestamp.) Most recent months first.
+    fn user_follower_counts_by_month<'a>(&self, user: &UserID, cb: FnIter<'a, MonthCount>) -> Result<(), Error>;
+
+    /// Count reactions to a user's items, grouped by the (UTC) month they
+    /// were made. Most recent months first.
+    fn user_reaction_counts_by_month<'a>(&self, user: &UserID, cb: FnIter<'a, MonthCount>) -> Result<(), Error>;
+
+    /// The operator's current announcement, if any. May be expired.
+    fn announcement(&self) -> Result<Option<Announcement>, Error>;
+
+    /// Replace (or with None, clear) the announcement.
+    fn set_announcement(&self, announcement: Option<&Announcement>) -> Result<(), Error>;
+
+    /// Save an anonymous visitor's comment, for the item's author to review.
+    fn queue_comment(&self, comment: &QueuedComment) -> Result<(), Error>;
+
+    /// How many comments are waiting for review on an item.
+    fn queued_comment_count(&self, user: &UserID, signature: &Signature) -> Result<u64, Error>;
+
+    /// Comments waiting for review, oldest first.
+    fn queued_comments<'a>(&self, cb: FnIter<'a, QueuedComment>) -> Result<(), Error>;
+
+    /// Comments waiting for review on `user`'s items, oldest first.
+    fn user_queued_comments<'a>(&self, user: &UserID, cb: FnIter<'a, QueuedComment>) -> Result<(), Error>;
+
+    /// Returns false if there was no such comment.
+    fn remove_queued_comment(&self, id: i64) -> Result<bool, Error>;
+
+    /// Servers that we push new items to.
+    fn push_peers<'a>(&self, cb: FnIter<'a, PushPeer>) -> Result<(), Error>;
+
+    fn add_push_peer(&self, peer: &PushPeer) -> Result<(), Error>;
+
+    /// Stop pushing to a peer, and drop its queue. Returns false if it wasn't a peer.
+    fn remove_push_peer(&self, url: &str) -> Result<bool, Error>;
+
+    /// Servers whose signed requests may see followers-only items. (See: server/peer_auth.rs)
+    fn trusted_peers<'a>(&self, cb: FnIter<'a, TrustedPeer>) -> Result<(), Error>;
+
+    fn add_trusted_peer(&self, peer: &TrustedPeer) -> Result<(), Error>;
+
+    /// Returns false if it wasn't trusted.
+    fn remove_trusted_peer(&self, key: &UserID) -> Result<bool, Error>;
+
+    fn is_trusted_peer(&self, key: &UserID) -> Result<bool, Error>;
+
+    /// Record that a signed request header was used, so that it can't be
+    /// replayed. Returns false if it already was. (See: server/viewer.rs)
+    /// Forgets headers that have expired, since they'd be refused anyway.
+    fn use_request_signature(&self, signature: &Signature, expires: Timestamp) -> Result<bool, Error>;
+
+    /// Names for users, for addresses like `name@host`. Ordered by name.
+    fn user_aliases<'a>(&self, cb: FnIter<'a, UserAlias>) -> Result<(), Error>;
+
+    /// The user that has this alias, if any.
+    fn alias_user(&self, name: &str) -> Result<Option<UserID>, Error>;
+
+    /// Save an alias, replacing any existing one with the same name.
+    fn add_user_alias(&self, alias: &UserAlias) -> Result<(), Error>;
+
+    /// Returns false if there was no such alias.
+    fn remove_user_alias(&self, name: &str) -> Result<bool, Error>;
+
+    /// Queue an item to be pushed to every peer.
+    fn queue_push(&self, user: &UserID, signature: &Signature) -> Result<(), Error>;
+
+    /// Queued pushes whose next attempt is due by `now`. Oldest first.
+    fn due_pushes<'a>(&self, now: Timestamp, cb: FnIter<'a, QueuedPush>) -> Result<(), Error>;
+
+    /// Record a failed push, to be retried at `retry_at`.
+    fn push_failed(&self, push: &QueuedPush, retry_at: Timestamp, error: &str) -> Result<(), Error>;
+
+    /// Remove a push from the queue. (It succeeded, or we gave up.)
+    fn finish_push(&self, push: &QueuedPush) -> Result<(), Error>;
+
+    /// Keep a record of a comparison between this server and a peer.
+    fn save_sync_report(&self, report: &SyncReport) -> Result<(), Error>;
+
+    /// Keep a record of a possible injection attempt, for operators.
+    /// Only the newest `MAX_SECURITY_REPORTS` are kept.
+    fn save_security_report(&self, report: &SecurityReport) -> Result<(), Error>;
+
+    /// Security reports created before `before`, newest first.
+    fn security_reports<'a>(&self, before: Timestamp, cb: FnIter<'a, SecurityReport>) -> Result<(), Error>;
+
+    /// A summary of the (un-removed) items we have for a user.
+    fn user_summary(&self, user: &UserID) -> Result<UserSummary, Error>;
+
+    /// Counts of server users and their posts. `since` are the start times
+    /// of periods to count active users in.
+    fn server_stats(&self, since: &[Timestamp]) -> Result<ServerStats, Error>;
+
+    /// Count an upload that we refused, on the day of `when`.
+    /// (Items and attachments are counted as they're saved.)
+    fn record_rejection(&self, when: Timestamp) -> Result<(), Error>;
+
+    /// What the server received each day, starting at `since_day`. Oldest first.
+    /// Days with nothing are left out.
+    fn daily_stats<'a>(&self, since_day: i64, cb: FnIter<'a, DailyStats>) -> Result<(), Error>;
+
+    /// Hide `muted`'s items from the feeds that `viewer` views. (Theirs, or
+    /// anyone else's.) Other viewers still see them.
+    fn mute_user(&self, viewer: &UserID, muted: &UserID) -> Result<(), Error>;
+
+    /// Undo [`Backend::mute_user`]. Returns false if `muted` wasn't muted.
+    fn unmute_user(&self, viewer: &UserID, muted: &UserID) -> Result<bool, Error>;
+
+    /// Users whose items are hidden from feeds that `viewer` views.
+    fn muted_users<'a>(&self, viewer: &UserID, cb: FnIter<'a, UserID>) -> Result<(), Error>;
+
+    /// Save a named feed, replacing any existing feed with the same name.
+    fn save_feed(&self, feed: &SavedFeed) -> Result<(), Error>;
+
+    fn saved_feed(&self, user: &UserID, name: &str) -> Result<Option<SavedFeed>, Error>;
+
+    /// List a user's saved feeds, by name.
+    fn saved_feeds<'a>(&self, user: &UserID, cb: FnIter<'a, SavedFeed>) -> Result<(), Error>;
+
+    /// Returns false if there was no such feed.
+    fn delete_saved_feed(&self, user: &UserID, name: &str) -> Result<bool, Error>;
+
+    /// The most recent items from a saved feed's authors.
+    /// (Filtering by item type is up to the caller.)
+    fn saved_feed_items<'a>(
+        &self,
+        user: &UserID,
+        name: &str,
+        before: Timestamp,
+        cb: FnIter<'a, ItemDisplayRow>,
+    ) -> Result<(), Error>;
+
+    /// Find items that refer to a given item. (ex: votes on a poll)
+    /// Newest first.
+    fn item_references<'a>(
+        &self,
+        user: &UserID,
+        signature: &Signature,
+        before: Timestamp,
+        callback: &'a mut dyn FnMut(ItemDisplayRow) -> Result<bool, Error>,
+    ) -> Result<(), Error>;
+
+    /// Find the comments that reply directly to a given item, without loading them.
+    /// (ex: to count a thread's replies) In no particular order.
+    fn comment_replies<'a>(
+        &self,
+        user: &UserID,
+        signature: &Signature,
+        cb: FnIter<'a, (UserID, Signature)>,
+    ) -> Result<(), Error>;
+
+    /// Each voter's vote in a poll. In no particular order.
+    ///
+    /// Only votes that this server received by `closes` count, whatever time
+    /// the voter claims to have voted. Of those, each voter's latest counts.
+    /// (If two have the same timestamp, the one with the greater signature.)
+    fn poll_votes<'a>(
+        &self,
+        poll_user: &UserID,
+        poll_signature: &Signature,
+        closes: Timestamp,
+        cb: FnIter<'a, PollVote>,
+    ) -> Result<(), Error>;
+
+    /// Each user's latest reaction to an item. In no particular order.
+    fn item_reactions<'a>(
+        &self,
+        user: &UserID,
+        signature: &Signature,
+        cb: FnIter<'a, ItemReaction>,
+    ) -> Result<(), Error>;
+
+    /// Save an ActivityPub follower, replacing any existing one with the same actor.
+    fn add_activitypub_follower(&self, follower: &ActivityPubFollower) -> Result<(), Error>;
+
+    /// Returns false if `actor` wasn't following `user`.
+    fn remove_activitypub_follower(&self, user: &UserID, actor: &str) -> Result<bool, Error>;
+
+    fn activitypub_followers<'a>(&self, user: &UserID, cb: FnIter<'a, ActivityPubFollower>) -> Result<(), Error>;
+
+    /// Save a reply from an ActivityPub actor, replacing any existing one with the same ID.
+    fn add_activitypub_reply(&self, reply: &ActivityPubReply) -> Result<(), Error>;
+
+    /// Delete a reply, if it was made by `actor`. Returns false if there was no such reply.
+    fn remove_activitypub_reply(&self, user: &UserID, id: &str, actor: &str) -> Result<bool, Error>;
+
+    /// Replies to an item from ActivityPub actors. Oldest first.
+    fn activitypub_replies<'a>(&self, user: &UserID, signature: &Signature, cb: FnIter<'a, ActivityPubReply>) -> Result<(), Error>;
+
+    /// Queue an item to be delivered to an ActivityPub inbox. Ignored if it's already queued.
+    fn queue_activitypub_delivery(&self, delivery: &QueuedDelivery) -> Result<(), Error>;
+
+    /// Queued deliveries whose next attempt is due by `now`. Oldest first.
+    fn due_activitypub_deliveries<'a>(&self, now: Timestamp, cb: FnIter<'a, QueuedDelivery>) -> Result<(), Error>;
+
+    /// Record a failed delivery, to be retried at `retry_at`.
+    fn activitypub_delivery_failed(&self, delivery: &QueuedDelivery, retry_at: Timestamp, error: &str) -> Result<(), Error>;
+
+    /// Remove a delivery from the queue. (It succeeded, or we gave up.)
+    fn finish_activitypub_delivery(&self, delivery: &QueuedDelivery) -> Result<(), Error>;
+
+    /// The key we republish a user's posts to Nostr with, if they have one.
+    fn nostr_key(&self, user: &UserID) -> Result<Option<NostrKey>, Error>;
+
+    /// Users whose posts we republish to Nostr.
+    fn nostr_keys<'a>(&self, cb: FnIter<'a, NostrKey>) -> Result<(), Error>;
+
+    /// Save a Nostr key, replacing the user's existing one.
+    fn add_nostr_key(&self, key: &NostrKey) -> Result<(), Error>;
+
+    /// Returns false if the user had no Nostr key.
+    fn remove_nostr_key(&self, user: &UserID) -> Result<bool, Error>;
+
+    /// Services that a user's posts are cross-posted to.
+    fn crosspost_targets<'a>(&self, user: &UserID, cb: FnIter<'a, CrossPostTarget>) -> Result<(), Error>;
+
+    fn crosspost_target(&self, user: &UserID, name: &str) -> Result<Option<CrossPostTarget>, Error>;
+
+    /// Save a cross-posting target, replacing any existing one with the same user and name.
+    fn add_crosspost_target(&self, target: &CrossPostTarget) -> Result<(), Error>;
+
+    /// Returns false if there was no such target.
+    fn remove_crosspost_target(&self, user: &UserID, name: &str) -> Result<bool, Error>;
+
+    /// Queue a post to be cross-posted to a target. Ignored if it's already queued.
+    fn queue_crosspost(&self, crosspost: &QueuedCrossPost) -> Result<(), Error>;
+
+    /// Queued cross-posts whose next attempt is due by `now`. Oldest first.
+    fn due_crossposts<'a>(&self, now: Timestamp, cb: FnIter<'a, QueuedCrossPost>) -> Result<(), Error>;
+
+    /// Record a failed cross-post, to be retried at `retry_at`.
+    fn crosspost_failed(&self, crosspost: &QueuedCrossPost, retry_at: Timestamp, error: &str) -> Result<(), Error>;
+
+    /// Remove a cross-post from the queue. (It succeeded, or we gave up.)
+    fn finish_crosspost(&self, crosspost: &QueuedCrossPost) -> Result<(), Error>;
+
+    /// The IPFS CID of an item's bytes (with an empty `file_name`), or of one of its attachments.
+    fn ipfs_cid(&self, user: &UserID, signature: &Signature, file_name: &str) -> Result<Option<String>, Error>;
+
+    fn set_ipfs_cid(&self, user: &UserID, signature: &Signature, file_name: &str, cid: &str) -> Result<(), Error>;
+
+    /// Queue an item's bytes or attachment to be added to IPFS. Ignored if it's already queued.
+    fn queue_ipfs_pin(&self, pin: &QueuedIpfsPin) -> Result<(), Error>;
+
+    /// Queued IPFS pins whose next attempt is due by `now`. Oldest first.
+    fn due_ipfs_pins<'a>(&self, now: Timestamp, cb: FnIter<'a, QueuedIpfsPin>) -> Result<(), Error>;
+
+    /// Record a failed IPFS pin, to be retried at `retry_at`.
+    fn ipfs_pin_failed(&self, pin: &QueuedIpfsPin, retry_at: Timestamp, error: &str) -> Result<(), Error>;
+
+    /// Remove a pin from the queue. (It succeeded, or we gave up.)
+    fn finish_ipfs_pin(&self, pin: &QueuedIpfsPin) -> Result<(), Error>;
+
+    /// Find posts containing all of the words in `query`. Newest first.
+    fn search<'a>(&self, query: &str, before: Timestamp, cb: FnIter<'a, ItemDisplayRow>) -> Result<(), Error>;
+
+    /// Save an (already verified) file attached to an item.
+    fn save_attachment(&self, user: &UserID, signature: &Signature, name: &str, bytes: &[u8]) -> Result<(), Error>;
+
+    /// The bytes of a file attached to an item, if we have them.
+    /// Excludes followers-only items' files.
+    fn attachment(&self, user: &UserID, signature: &Signature, name: &str) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Like `attachment`, with the same rules as `followers_only_item`.
+    fn followers_only_attachment(&self, user: &UserID, signature: &Signature, name: &str, viewer: &UserID) -> Result<Option<Vec<u8>>, Error>;
+
+    fn attachment_exists(&self, user: &UserID, signature: &Signature, name: &str) -> Result<bool, Error>;
+
+    /// A cached map tile, if we have one.
+    fn map_tile(&self, z: u32, x: u32, y: u32) -> Result<Option<MapTile>, Error>;
+
+    /// Cache a map tile, replacing any older copy.
+    fn save_map_tile(&self, tile: &MapTile) -> Result<(), Error>;
+
+    /// Everything that has happened to a user's items on this server, oldest first.
+    fn user_item_events<'a>(&self, user: &UserID, cb: FnIter<'a, ItemEvent>) -> Result<(), Error>;
+
+    /// How much space a user's (un-removed) items and their attachments take up.
+    /// Newest first, or largest first if `by_size`.
+    fn user_item_sizes<'a>(&self, user: &UserID, by_size: bool, cb: FnIter<'a, ItemSize>) -> Result<(), Error>;
+
+    /// Replace what we know about an item's dead links with `links`, from a
+    /// new check. (See: `feoblog linkcheck`)
+    fn set_dead_links(&self, user: &UserID, signature: &Signature, links: &[DeadLink]) -> Result<(), Error>;
+
+    /// Dead links in a user's (un-removed) items, from the last check. Newest items first.
+    fn user_dead_links<'a>(&self, user: &UserID, cb: FnIter<'a, DeadLink>) -> Result<(), Error>;
+
     /// Check whether a user has remaiing quota/permissions to upload a particular item.
     fn quota_check_item(&self, user_id: &UserID, bytes: &[u8], item: &Item) -> Result<Option<QuotaDenyReason>, Error>;
 }
@@ -88,7 +491,7 @@ pub trait Backend
 type FnIter<'a, T> = &'a mut dyn FnMut(T) -> Result<bool, Error>; 
 
 /// A UserID is a nacl public key. (32 bytes)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct UserID {
     pub_key: sign::PublicKey,
 }
@@ -96,14 +499,24 @@ pub struct UserID {
 // Expect a 32-byte nacl public key:
 const USER_ID_BYTES: usize = 32;
 
+/// The longest base58 encoding of USER_ID_BYTES.
+const USER_ID_BASE58_MAX: usize = 44;
+
 impl UserID {
     pub fn to_base58(&self) -> String {
         bs58::encode(self.bytes()).into_string()
     }
 
     pub fn from_base58(value: &str) -> Result<Self, Error> {
+        // Decoding takes time proportional to the square of the length, so
+        // don't bother with values that are obviously too long:
+        if value.len() > USER_ID_BASE58_MAX {
+            bail!("Expected a user ID of at most {} characters but found {}", USER_ID_BASE58_MAX, value.len());
+        }
         let bytes = bs58::decode(value).into_vec()?;
-        Self::from_vec(bytes)
+        let user = Self::from_vec(bytes)?;
+        canonical_base58(value, user.bytes())?;
+        Ok(user)
     }
 
     pub fn from_vec(bytes: Vec<u8>) -> Result<Self, Error> {
@@ -132,13 +545,16 @@ impl FromStr for UserID {
 }
 
 /// Bytes representing a detached NaCl signature. (64 bytes)
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct Signature {
     signature: sign::Signature,
 }
 
 const SIGNATURE_BYTES: usize = 64;
 
+/// The longest base58 encoding of SIGNATURE_BYTES.
+const SIGNATURE_BASE58_MAX: usize = 88;
+
 impl Signature {
     pub fn from_vec(bytes: Vec<u8>) -> Result<Self, Error> {
         if bytes.len() != SIGNATURE_BYTES {
@@ -153,8 +569,13 @@ impl Signature {
     }
 
     pub fn from_base58(value: &str) -> Result<Self, Error> {
+        if value.len() > SIGNATURE_BASE58_MAX {
+            bail!("Expected a signature of at most {} characters but found {}", SIGNATURE_BASE58_MAX, value.len());
+        }
         let bytes = bs58::decode(value).into_vec()?;
-        Self::from_vec(bytes)
+        let signature = Self::from_vec(bytes)?;
+        canonical_base58(value, signature.bytes())?;
+        Ok(signature)
     }
 
     pub fn to_base58(&self) -> String {
@@ -173,6 +594,15 @@ impl Signature {
 
 }
 
+/// Make sure that `value` is the one way to write `bytes`, so that one ID (or
+/// signature) can't have several spellings. (ex: in URLs, or aliases)
+fn canonical_base58(value: &str, bytes: &[u8]) -> Result<(), Error> {
+    if bs58::encode(bytes).into_string() != value {
+        bail!("{:?} isn't in canonical base58. Expected {:?}", value, bs58::encode(bytes).into_string());
+    }
+    Ok(())
+}
+
 /// Allows easy destructuring from URLs. (in Warp)
 impl FromStr for Signature {
     type Err = failure::Error;
@@ -275,7 +705,532 @@ pub struct ServerUser {
     pub on_homepage: bool,
 }
 
-#[derive(Copy, Clone)]
+/// Limits on what a server user may post. 0 = unlimited.
+#[derive(Debug, Clone, Default)]
+pub struct Quota {
+    /// For all of their items and attachments.
+    pub max_bytes: u64,
+
+    /// Items received in the last 24 hours.
+    pub max_items_per_day: u64,
+}
+
+/// An IP network blocked from accessing this server.
+/// i.e.: A row in the ip_block table.
+#[derive(Debug, Clone)]
+pub struct IpBlock {
+    /// A normalized IP address or network in CIDR notation.
+    pub cidr: String,
+    pub notes: String,
+    pub created: Timestamp,
+}
+
+/// How many times an item was viewed on a particular day.
+pub struct ItemViewCount {
+    pub signature: Signature,
+
+    /// Days since 1970-01-01, UTC.
+    /// For all-time totals, this is the most recent day the item was viewed.
+    pub day: i64,
+    pub views: i64,
+}
+
+/// A count of items in a month.
+pub struct MonthCount {
+    /// Formatted as "YYYY-MM"
+    pub month: String,
+    pub count: i64,
+}
+
+/// Summarizes the set of items a server has for a user.
+/// If two servers have the same summary, they (almost certainly) have the same items.
+#[derive(PartialEq, Eq)]
+pub struct UserSummary {
+    pub item_count: u64,
+
+    /// The XOR of the SHA-256 hashes of each item's signature.
+    /// Since XOR is commutative, this doesn't depend on the order items were received.
+    pub digest: Vec<u8>,
+}
+
+/// A server-wide notice from the operator, shown on every page.
+#[derive(Clone)]
+pub struct Announcement {
+    pub message: String,
+    pub created: Timestamp,
+    pub expires: Option<Timestamp>,
+}
+
+impl Announcement {
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        self.expires.map(|e| e.unix_utc_ms <= now.unix_utc_ms).unwrap_or(false)
+    }
+}
+
+/// A comment from a visitor without a FeoBlog key. These aren't signed, so
+/// they're only published if the author quotes them in a reply of their own.
+pub struct QueuedComment {
+    /// Assigned by the backend. Ignored when queueing a comment.
+    pub id: i64,
+
+    /// The item being commented on:
+    pub user: UserID,
+    pub signature: Signature,
+
+    /// The name the visitor gave. Not verified.
+    pub name: String,
+    pub text: String,
+    pub created: Timestamp,
+}
+
+impl QueuedComment {
+    /// The comment as a Markdown quote, for the author to publish in a reply.
+    pub fn quote(&self) -> String {
+        let mut quote = String::new();
+        for line in self.text.lines() {
+            quote.push_str(&format!("> {}\n", line));
+        }
+        quote.push_str(">\n");
+        let name = if self.name.trim().is_empty() { "Anonymous" } else { self.name.trim() };
+        quote.push_str(&format!("> — {}\n", name));
+        quote
+    }
+}
+
+/// A server that we push new items to.
+pub struct PushPeer {
+    /// ex: "https://feo.example.com"
+    pub url: String,
+    pub created: Timestamp,
+}
+
+/// Another server, trusted to see followers-only items. (ex: a mirror)
+pub struct TrustedPeer {
+    /// The peer's server key. (Not a user, but the same kind of key.)
+    pub key: UserID,
+    pub notes: String,
+    pub created: Timestamp,
+}
+
+/// A secp256k1 key that the server signs Nostr events with, on behalf of a
+/// user. (Nostr can't use users' own ed25519 keys.)
+pub struct NostrKey {
+    pub user: UserID,
+    pub secret_key: Vec<u8>,
+    pub created: Timestamp,
+}
+
+/// Somewhere that a user's new posts are announced. (See: `feoblog crosspost`)
+pub struct CrossPostTarget {
+    pub user: UserID,
+
+    /// Chosen by the admin. Unique per user.
+    pub name: String,
+    pub service: CrossPostService,
+
+    /// Mastodon: the instance's URL. Bluesky: the PDS's URL. Webhook: the URL to POST to.
+    pub url: String,
+
+    /// Bluesky: the handle to log in as. Unused by the others.
+    pub account: String,
+
+    /// Mastodon: an access token. Bluesky: an app password.
+    /// Webhook: optional, sent as a bearer token.
+    pub token: String,
+    pub created: Timestamp,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CrossPostService {
+    Mastodon,
+    Bluesky,
+    Webhook,
+}
+
+impl CrossPostService {
+    pub fn as_str(self) -> &'static str {
+        use CrossPostService::*;
+        match self {
+            Mastodon => "mastodon",
+            Bluesky => "bluesky",
+            Webhook => "webhook",
+        }
+    }
+}
+
+impl FromStr for CrossPostService {
+    type Err = Error;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        use CrossPostService::*;
+        Ok(match value {
+            "mastodon" => Mastodon,
+            "bluesky" => Bluesky,
+            "webhook" => Webhook,
+            _ => bail!("Unknown service: {} (Expected mastodon, bluesky, or webhook.)", value),
+        })
+    }
+}
+
+/// A post waiting to be cross-posted.
+pub struct QueuedCrossPost {
+    pub user: UserID,
+    pub signature: Signature,
+
+    /// The name of the user's CrossPostTarget.
+    pub target: String,
+
+    /// The server's URL when the item was posted. Cross-posts link back to it.
+    pub base_url: String,
+
+    /// How many times we've already tried (and failed) to cross-post this.
+    pub attempts: u32,
+}
+
+/// Item bytes, or an attachment, waiting to be added to IPFS. (See: `feoblog serve --ipfs-api`)
+pub struct QueuedIpfsPin {
+    pub user: UserID,
+    pub signature: Signature,
+
+    /// The attachment's name. Empty for the item's own bytes.
+    pub file_name: String,
+
+    /// How many times we've already tried (and failed) to pin this.
+    pub attempts: u32,
+}
+
+/// How many days to keep bookkeeping for. 0 = forever.
+#[derive(Debug, Clone)]
+pub struct Retention {
+    /// Item events younger than this can't be deleted, even by hand.
+    pub item_event_days: u64,
+    pub sync_report_days: u64,
+    pub security_report_days: u64,
+
+    /// Queued pushes, deliveries, cross-posts, and IPFS pins that have been
+    /// due this long. (ex: after a feature was turned off)
+    pub stale_queue_days: u64,
+}
+
+/// `Backend::save_security_report` drops older reports past this many, so
+/// that anyone sending lots of reports can't fill up the disk.
+pub const MAX_SECURITY_REPORTS: u64 = 10_000;
+
+/// What `Backend::prune` deleted.
+#[derive(Debug, Default)]
+pub struct PruneReport {
+    pub item_events: u64,
+    pub sync_reports: u64,
+    pub security_reports: u64,
+    pub queued: u64,
+}
+
+/// What `Backend::reindex` rebuilt.
+#[derive(Default)]
+pub struct ReindexReport {
+    /// Including removed items.
+    pub items: u64,
+    pub profiles: u64,
+    pub follows: u64,
+    pub votes: u64,
+    pub reactions: u64,
+    pub references: u64,
+    pub posts: u64,
+
+    /// Users with any un-removed items.
+    pub users: u64,
+}
+
+/// Counts of the server's users and their items. (ex: for NodeInfo)
+pub struct ServerStats {
+    pub users: u64,
+
+    /// For each `since` time, how many users have posted any item since then.
+    pub active_users: Vec<u64>,
+
+    /// Un-removed posts from server users.
+    pub posts: u64,
+}
+
+/// What the server received in one (UTC) day. (See: `feoblog stats`)
+#[derive(Debug, Clone, Default)]
+pub struct DailyStats {
+    /// Days since 1970-01-01, UTC.
+    pub day: i64,
+    pub items: u64,
+    pub item_bytes: u64,
+    pub attachments: u64,
+    pub attachment_bytes: u64,
+    /// Uploads that we refused. (ex: from unknown users, or over quota)
+    pub rejections: u64,
+}
+
+impl DailyStats {
+    pub fn add(&mut self, other: &DailyStats) {
+        self.items += other.items;
+        self.item_bytes += other.item_bytes;
+        self.attachments += other.attachments;
+        self.attachment_bytes += other.attachment_bytes;
+        self.rejections += other.rejections;
+    }
+}
+
+/// A name for a user, so that they can be found as `name@host`. (ex: with WebFinger)
+pub struct UserAlias {
+    /// Lowercase.
+    pub name: String,
+    pub user: UserID,
+    pub created: Timestamp,
+}
+
+/// An ActivityPub actor (ex: a Mastodon user) following one of our users.
+pub struct ActivityPubFollower {
+    /// The FeoBlog user they follow.
+    pub user: UserID,
+
+    /// The follower's actor ID. (A URL)
+    pub actor: String,
+
+    /// Where we deliver new posts. Often a server's shared inbox.
+    pub inbox: String,
+    pub created: Timestamp,
+}
+
+/// A reply to one of our users' items from an ActivityPub actor.
+pub struct ActivityPubReply {
+    /// The item being replied to.
+    pub user: UserID,
+    pub signature: Signature,
+
+    /// The reply's object ID. (A URL)
+    pub id: String,
+
+    /// Who wrote it.
+    pub actor: String,
+    pub actor_name: String,
+
+    /// Where people can view the reply.
+    pub url: String,
+
+    /// Plain text, converted from the reply's HTML.
+    pub text: String,
+
+    /// As claimed by the remote server.
+    pub published: Timestamp,
+    pub received: Timestamp,
+}
+
+/// An item waiting to be pushed to a peer.
+pub struct QueuedPush {
+    pub peer_url: String,
+    pub user: UserID,
+    pub signature: Signature,
+
+    /// How many times we've already tried (and failed) to push this.
+    pub attempts: u32,
+}
+
+/// An item waiting to be delivered to an ActivityPub inbox.
+pub struct QueuedDelivery {
+    pub inbox: String,
+    pub user: UserID,
+    pub signature: Signature,
+
+    /// The server's URL when the item was posted. Activities' IDs are built from it.
+    pub base_url: String,
+
+    /// How many times we've already tried (and failed) to deliver this.
+    pub attempts: u32,
+}
+
+/// A stored record of `feoblog sync verify`.
+pub struct SyncReport {
+    pub peer: String,
+    pub created: Timestamp,
+
+    /// True if both servers had the same items.
+    pub complete: bool,
+
+    /// The human-readable report.
+    pub report: String,
+}
+
+/// A possible attempt to inject scripts or markup. (See: server/reports.rs)
+pub struct SecurityReport {
+    pub created: Timestamp,
+    pub kind: SecurityReportKind,
+
+    /// The item it's about, if any.
+    pub item: Option<(UserID, Signature)>,
+
+    /// ex: The directive a page violated, or the markup we removed from an item.
+    pub detail: String,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SecurityReportKind {
+    /// A browser reported a Content-Security-Policy violation.
+    Csp,
+    /// We removed markup or links from an item's markdown when rendering it.
+    Sanitizer,
+}
+
+impl SecurityReportKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SecurityReportKind::Csp => "csp",
+            SecurityReportKind::Sanitizer => "sanitizer",
+        }
+    }
+}
+
+impl FromStr for SecurityReportKind {
+    type Err = Error;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(match value {
+            "csp" => SecurityReportKind::Csp,
+            "sanitizer" => SecurityReportKind::Sanitizer,
+            _ => bail!("Unknown security report: {}", value),
+        })
+    }
+}
+
+/// A user's custom feed of items from particular authors.
+pub struct SavedFeed {
+    pub user: UserID,
+
+    /// Unique per user. Used in URLs: `/u/{userID}/feeds/{name}/`
+    pub name: String,
+    pub authors: Vec<UserID>,
+
+    /// Which item types to show. Empty means only those shown by default (posts, polls).
+    pub item_types: Vec<ItemType>,
+
+    /// Only show posts with at least one of these hashtags. (See: tags.rs)
+    /// Normalized, ex: "rust" for "#Rust". Empty means any post.
+    pub tags: Vec<String>,
+}
+
+impl SavedFeed {
+    /// May contain letters, numbers, "-" and "_".
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty() && name.len() <= 64 && name.chars().all(|c| {
+            c.is_ascii_alphanumeric() || c == '-' || c == '_'
+        })
+    }
+}
+
+/// A cached map image. See: server::maps
+pub struct MapTile {
+    pub z: u32,
+    pub x: u32,
+    pub y: u32,
+    pub bytes: Vec<u8>,
+    pub fetched: Timestamp,
+}
+
+pub struct PollVote {
+    pub voter: UserID,
+    /// An index into Poll.options. (Not necessarily a valid one!)
+    pub option: u32,
+}
+
+/// A user's reaction to an item. (See: Reaction in feoblog.proto)
+pub struct ItemReaction {
+    pub user: UserID,
+    pub emoji: String,
+}
+
+/// The space an item takes up on this server.
+pub struct ItemSize {
+    pub signature: Signature,
+    pub timestamp: Timestamp,
+
+    /// The size of the item's protobuf bytes.
+    pub item_bytes: u64,
+
+    /// The total size of the attachments we've received for the item.
+    pub attachment_bytes: u64,
+}
+
+/// A link in an item that didn't work when we last checked it.
+pub struct DeadLink {
+    pub signature: Signature,
+    pub url: String,
+
+    /// Why it didn't work. (ex: "404 Not Found")
+    pub error: String,
+    pub checked: Timestamp,
+}
+
+/// Something that happened to an item on this server.
+/// These are only ever appended to the log, never changed. Once they're older
+/// than `Retention::item_event_days`, they may be pruned.
+pub struct ItemEvent {
+    pub user: UserID,
+    pub signature: Signature,
+    pub kind: ItemEventKind,
+    pub created: Timestamp,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ItemEventKind {
+    /// We saved the item.
+    Received,
+    /// An admin removed the item. (`feoblog mod remove`)
+    Removed,
+    /// An admin restored a removed item.
+    Restored,
+    /// A removed item was permanently deleted.
+    Purged,
+    /// The item's author set it to expire, and it did.
+    Expired,
+    /// The item's author deleted it. (See: `Delete`)
+    Deleted,
+    /// We held the item for an operator to approve. (`--quarantine-pow-items`)
+    Quarantined,
+    /// An operator approved a quarantined item. (`feoblog mod approve`)
+    Approved,
+    /// A push peer accepted the item.
+    Synced,
+}
+
+impl ItemEventKind {
+    pub fn as_str(self) -> &'static str {
+        use ItemEventKind::*;
+        match self {
+            Received => "received",
+            Removed => "removed",
+            Restored => "restored",
+            Purged => "purged",
+            Expired => "expired",
+            Deleted => "deleted",
+            Quarantined => "quarantined",
+            Approved => "approved",
+            Synced => "synced",
+        }
+    }
+}
+
+impl FromStr for ItemEventKind {
+    type Err = Error;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        use ItemEventKind::*;
+        Ok(match value {
+            "received" => Received,
+            "removed" => Removed,
+            "restored" => Restored,
+            "purged" => Purged,
+            "expired" => Expired,
+            "deleted" => Deleted,
+            "quarantined" => Quarantined,
+            "approved" => Approved,
+            "synced" => Synced,
+            _ => bail!("Unknown item event: {}", value),
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
 pub struct Timestamp {
     /// UNIX time, at UTC, in milliseconds:
     pub unix_utc_ms: i64
@@ -290,7 +1245,17 @@ impl Timestamp {
         }
     }
 
+    /// Whole days since 1970-01-01, UTC.
+    pub fn unix_utc_days(self) -> i64 {
+        self.unix_utc_ms.div_euclid(24 * 60 * 60 * 1000)
+    }
+
     pub fn format_with_offset(self, minutes: i16) -> String {
+        self.format_with(minutes, "%Y-%m-%d %H:%M:%S %z")
+    }
+
+    /// Format at a given UTC offset, with a format string for the `time` crate.
+    pub fn format_with(self, minutes: i16, format: &str) -> String {
         use time::{Duration, UtcOffset, OffsetDateTime};
         use std::ops::Add;
 
@@ -299,7 +1264,38 @@ impl Timestamp {
         let offset = UtcOffset::minutes(minutes);
         let datetime = datetime.to_offset(offset);
 
-        datetime.format("%Y-%m-%d %H:%M:%S %z")
+        datetime.format(format)
+    }
+
+    /// Format as an RFC 2822 date in UTC. (ex: for RSS feeds)
+    pub fn format_rfc2822(self) -> String {
+        use time::{Duration, OffsetDateTime};
+        use std::ops::Add;
+
+        let ms = Duration::milliseconds(self.unix_utc_ms);
+        let datetime = OffsetDateTime::unix_epoch().add(ms);
+        datetime.format("%a, %d %b %Y %H:%M:%S +0000")
+    }
+
+    /// Parse an RFC 3339 date. (ex: from ActivityPub)
+    pub fn parse_rfc3339(value: &str) -> Option<Self> {
+        use time::{Format, OffsetDateTime};
+
+        let datetime = OffsetDateTime::parse(value, Format::Rfc3339).ok()?;
+        let delta = datetime - OffsetDateTime::unix_epoch();
+        Some(Timestamp {
+            unix_utc_ms: delta.whole_milliseconds() as i64,
+        })
+    }
+
+    /// Format as an RFC 3339 date in UTC. (ex: for Atom feeds)
+    pub fn format_rfc3339(self) -> String {
+        use time::{Duration, OffsetDateTime};
+        use std::ops::Add;
+
+        let ms = Duration::milliseconds(self.unix_utc_ms);
+        let datetime = OffsetDateTime::unix_epoch().add(ms);
+        datetime.format("%Y-%m-%dT%H:%M:%SZ")
     }
 }
 /// A reason why a user can't post an Item or file attachment.
@@ -316,6 +1312,21 @@ pub enum QuotaDenyReason {
     /// This user is not known to the server, so not allowed to post.
     UnknownUser,
 
+    /// Saving this item, and the attachments it lists, would exceed the user's byte quota.
+    MaxBytes {
+        max_bytes: u64,
+        /// By the user's existing items and attachments.
+        used_bytes: u64,
+        /// By this item and its attachments.
+        item_bytes: u64,
+    },
+
+    /// The user has already posted their quota of items in the last 24 hours.
+    MaxItemsPerDay {
+        max_items: u64,
+        items: u64,
+    },
+
     /// We already have a profile that proves that this userID has been revoked.
     ProfileRevoked,
 }
@@ -327,6 +1338,13 @@ impl std::fmt::Display for QuotaDenyReason {
                 write!(f, "Newer items exceed {} byte quota.", max_bytes),
             Self::UnknownUser => 
                 write!(f, "This user is not known to the server."),
+            Self::MaxBytes { max_bytes, used_bytes, item_bytes } =>
+                write!(
+                    f, "This item and its attachments need {} bytes, but only {} bytes of your {} byte quota are left. ({} bytes used.)",
+                    item_bytes, max_bytes.saturating_sub(*used_bytes), max_bytes, used_bytes,
+                ),
+            Self::MaxItemsPerDay { max_items, items } =>
+                write!(f, "You've posted {} items in the last 24 hours. Your quota is {} items per day.", items, max_items),
             Self::ProfileRevoked => 
                 write!(f, "This user ID has been revoked."),
         }
@@ -0,0 +1,1016 @@
+//! Spreads users' data across several SQLite files.
+//!
+//! A single SQLite file has one writer at a time, and gets unwieldy to back up
+//! as it grows. With `--shards N`, each user's items (and everything else
+//! about them) are stored in one of N files, chosen by the first byte of their
+//! user ID. Server-wide data (IP blocks, peers, the announcement, …) stays in
+//! the first shard, which is the usual `--sqlite-file`.
+//!
+//! Queries that span users (the home page, feeds, references to an item) ask
+//! each shard and merge the results, newest first.
+//!
+//! The number of shards is fixed when the database is created. Changing it
+//! would mean moving users between files, which we don't (yet) do.
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fs;
+use std::path::Path;
+
+use failure::{Error, ResultExt, bail};
+use protobuf::Message as _;
+
+use crate::backend::{self, FnIter, sqlite};
+use crate::backend::{
+    Backend, UserID, Signature, ItemRow, ItemDisplayRow, Timestamp, ServerUser, QuotaDenyReason,
+    IpBlock, ItemViewCount, MonthCount, SyncReport, SecurityReport, UserSummary, ItemEvent, SavedFeed, PollVote, ItemReaction,
+    MapTile, PushPeer, QueuedPush, QueuedComment, Announcement, ActivityPubFollower, ActivityPubReply,
+    UserAlias, ServerStats, NostrKey, ItemSize, QueuedDelivery,
+    CrossPostTarget, QueuedCrossPost, QueuedIpfsPin, ReindexReport, Quota, TrustedPeer, DailyStats,
+    Retention, PruneReport, DeadLink,
+};
+use crate::protos::Item;
+
+pub(crate) const MAX_SHARDS: usize = 256;
+
+/// How many rows to fetch from each shard at a time when merging.
+const MERGE_BATCH: usize = 50;
+
+#[derive(Clone)]
+pub(crate) struct Factory {
+    shards: Vec<sqlite::Factory>,
+}
+
+impl Factory {
+    /// With one shard, this is just a [`sqlite::Factory`].
+    pub fn new(sqlite_file: &str, shards: usize, cache_kib: u64) -> Result<Self, Error> {
+        if shards == 0 || shards > MAX_SHARDS {
+            bail!("--shards must be between 1 and {}", MAX_SHARDS);
+        }
+        check_shard_count(sqlite_file, shards)?;
+
+        let paths = shard_paths(sqlite_file, shards);
+        for path in &paths {
+            if Path::new(path).exists() {
+                sqlite::check_journal_mode(path)?;
+            }
+        }
+        let shards = paths.into_iter()
+            .map(|path| sqlite::Factory::new(path, cache_kib))
+            .collect();
+        Ok(Factory{ shards })
+    }
+
+    /// A single, in-memory shard. (See: sqlite::Factory::memory())
+    pub fn memory() -> Self {
+        Factory{ shards: vec![sqlite::Factory::memory()] }
+    }
+}
+
+/// The database files for each shard. The first is `sqlite_file` itself.
+pub(crate) fn shard_paths(sqlite_file: &str, shards: usize) -> Vec<String> {
+    (0..shards).map(|i| {
+        if i == 0 { sqlite_file.to_string() } else { format!("{}.shard{}", sqlite_file, i) }
+    }).collect()
+}
+
+/// Make sure we don't open an existing database with a different number of
+/// shards, which would look for users in the wrong files.
+fn check_shard_count(sqlite_file: &str, shards: usize) -> Result<(), Error> {
+    let count_file = format!("{}.shards", sqlite_file);
+    let existing = match fs::read_to_string(&count_file) {
+        Ok(text) => text.trim().parse::<usize>()
+            .with_context(|_| format!("Invalid shard count in {}", count_file))?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            if shards == 1 {
+                return Ok(());
+            }
+            if Path::new(sqlite_file).exists() {
+                bail!("{} was created without shards, and can't be sharded.", sqlite_file);
+            }
+            fs::write(&count_file, shards.to_string())
+                .with_context(|_| format!("Error writing {}", count_file))?;
+            return Ok(());
+        },
+        Err(err) => Err(err).with_context(|_| format!("Error reading {}", count_file))?,
+    };
+
+    if existing != shards {
+        bail!("{} was created with --shards {}", sqlite_file, existing);
+    }
+    Ok(())
+}
+
+/// Which shard a user's data lives in.
+pub(crate) fn shard_index(user: &UserID, shards: usize) -> usize {
+    usize::from(user.bytes()[0]) * shards / 256
+}
+
+impl backend::Factory for Factory {
+    fn open(&self) -> Result<Box<dyn Backend>, Error> {
+        if self.shards.len() == 1 {
+            return self.shards[0].open();
+        }
+
+        let shards = self.shards.iter()
+            .map(|shard| shard.open())
+            .collect::<Result<_, _>>()?;
+        Ok(Box::new(Connection{ shards }))
+    }
+}
+
+struct Connection {
+    shards: Vec<Box<dyn Backend>>,
+}
+
+impl Connection {
+    fn shard(&self, user: &UserID) -> &dyn Backend {
+        self.shards[shard_index(user, self.shards.len())].as_ref()
+    }
+
+    /// Where we keep data that doesn't belong to any one user.
+    fn main(&self) -> &dyn Backend {
+        self.shards[0].as_ref()
+    }
+
+    /// May `viewer` see `user`'s followers-only items?
+    fn may_view(&self, user: &UserID, viewer: &UserID) -> Result<bool, Error> {
+        Ok(viewer.bytes() == user.bytes() || self.follows(viewer, user)?)
+    }
+
+    /// A user's display name, from their latest profile.
+    fn display_name(&self, user: &UserID) -> Result<Option<String>, Error> {
+        let row = match self.shard(user).user_profile(user)? {
+            None => return Ok(None),
+            Some(row) => row,
+        };
+        let mut item = Item::new();
+        item.merge_from_bytes(&row.item_bytes)?;
+        let name = item.get_profile().get_display_name().trim();
+        Ok(if name.is_empty() { None } else { Some(name.to_string()) })
+    }
+
+    /// Merge items from several users, who may be in different shards.
+    fn users_items(
+        &self,
+        users: Vec<(UserID, Option<String>)>,
+        before: Timestamp,
+        callback: FnIter<ItemDisplayRow>,
+    ) -> Result<(), Error> {
+        let sources = users.into_iter().map(|(user, display_name)| {
+            let shard = self.shard(&user);
+            let source: Source<ItemDisplayRow> = Box::new(move |before, cb| {
+                shard.user_items(&user, before, &mut |item| {
+                    cb(ItemDisplayRow{ item, display_name: display_name.clone() })
+                })
+            });
+            source
+        }).collect();
+        merge_newest_first(sources, display_row_timestamp, before, callback)
+    }
+
+    /// Add up each shard's counts for each month. Most recent months first.
+    fn month_counts(
+        &self,
+        mut count: impl FnMut(&dyn Backend, FnIter<MonthCount>) -> Result<(), Error>,
+        cb: FnIter<MonthCount>,
+    ) -> Result<(), Error> {
+        let mut months: BTreeMap<String, i64> = BTreeMap::new();
+        for shard in &self.shards {
+            count(shard.as_ref(), &mut |month| {
+                *months.entry(month.month).or_default() += month.count;
+                Ok(true)
+            })?;
+        }
+        for (month, count) in months.into_iter().rev() {
+            if !cb(MonthCount{ month, count })? {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fetches rows from one place, newest first, starting before a timestamp.
+type Source<'s, T> = Box<dyn FnMut(Timestamp, FnIter<T>) -> Result<(), Error> + 's>;
+
+fn display_row_timestamp(row: &ItemDisplayRow) -> Timestamp { row.item.timestamp }
+
+/// Page through several sources at once, passing their rows to `callback`
+/// newest first.
+fn merge_newest_first<T>(
+    sources: Vec<Source<T>>,
+    timestamp: fn(&T) -> Timestamp,
+    before: Timestamp,
+    callback: FnIter<T>,
+) -> Result<(), Error> {
+    let mut cursors: Vec<Cursor<T>> = sources.into_iter()
+        .map(|source| Cursor{ source, rows: VecDeque::new(), before, done: false })
+        .collect();
+
+    loop {
+        for cursor in &mut cursors {
+            cursor.fill(timestamp)?;
+        }
+
+        let newest = cursors.iter_mut()
+            .filter(|c| !c.rows.is_empty())
+            .max_by_key(|c| c.rows.front().map(|row| timestamp(row).unix_utc_ms));
+        let row = match newest.and_then(|c| c.rows.pop_front()) {
+            None => return Ok(()),
+            Some(row) => row,
+        };
+        if !callback(row)? {
+            return Ok(());
+        }
+    }
+}
+
+struct Cursor<'s, T> {
+    source: Source<'s, T>,
+    rows: VecDeque<T>,
+
+    /// Where the next batch starts.
+    before: Timestamp,
+
+    /// The source has no more rows.
+    done: bool,
+}
+
+impl<T> Cursor<'_, T> {
+    /// Fetch the next batch, if we've used up the last one.
+    fn fill(&mut self, timestamp: fn(&T) -> Timestamp) -> Result<(), Error> {
+        if self.done || !self.rows.is_empty() {
+            return Ok(());
+        }
+
+        let rows = &mut self.rows;
+        let mut done = true;
+        (self.source)(self.before, &mut |row| {
+            // The next batch starts *before* the last timestamp we keep, so
+            // keep any rows that share it together in this batch:
+            let last = rows.back().map(|r| timestamp(r).unix_utc_ms);
+            if rows.len() >= MERGE_BATCH && last != Some(timestamp(&row).unix_utc_ms) {
+                done = false;
+                return Ok(false);
+            }
+            rows.push_back(row);
+            Ok(true)
+        })?;
+
+        self.done = done;
+        if let Some(row) = self.rows.back() {
+            self.before = timestamp(row);
+        }
+        Ok(())
+    }
+}
+
+impl Backend for Connection {
+    fn setup(&self) -> Result<(), Error> {
+        for (i, shard) in self.shards.iter().enumerate() {
+            shard.setup().with_context(|_| format!("Error setting up shard {}", i))?;
+        }
+        Ok(())
+    }
+
+    fn reindex(&mut self) -> Result<ReindexReport, Error> {
+        let mut total = ReindexReport::default();
+        for (i, shard) in self.shards.iter_mut().enumerate() {
+            let report = shard.reindex().with_context(|_| format!("Error reindexing shard {}", i))?;
+            total.items += report.items;
+            total.profiles += report.profiles;
+            total.follows += report.follows;
+            total.votes += report.votes;
+            total.references += report.references;
+            total.posts += report.posts;
+            total.users += report.users;
+        }
+        Ok(total)
+    }
+
+    fn checkpoint(&self) -> Result<(), Error> {
+        for (i, shard) in self.shards.iter().enumerate() {
+            shard.checkpoint().with_context(|_| format!("Error checkpointing shard {}", i))?;
+        }
+        Ok(())
+    }
+
+    fn backup(&self, _dest: &Path) -> Result<(), Error> {
+        // Shards are separate files, with no way to snapshot them all at the same moment:
+        bail!("Sharded databases can't be backed up to one file. Copy each shard's file during `feoblog maintenance` instead.");
+    }
+
+    fn homepage_items<'a>(&self, before: Timestamp, callback: &'a mut dyn FnMut(ItemDisplayRow) -> Result<bool,Error>) -> Result<(), Error> {
+        // Server users are stored in their own shards, with their items:
+        let sources = self.shards.iter().map(|shard| {
+            let source: Source<ItemDisplayRow> = Box::new(move |before, cb| shard.homepage_items(before, cb));
+            source
+        }).collect();
+        merge_newest_first(sources, display_row_timestamp, before, callback)
+    }
+
+    fn user_items<'a>(
+        &self,
+        user: &UserID,
+        before: Timestamp,
+        callback: &'a mut dyn FnMut(ItemRow) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
+        self.shard(user).user_items(user, before, callback)
+    }
+
+    fn user_feed_items<'a>(
+        &self,
+        user_id: &UserID,
+        viewer: Option<&UserID>,
+        before: Timestamp,
+        callback: &'a mut dyn FnMut(ItemDisplayRow) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
+        let mut muted = HashSet::new();
+        if let Some(viewer) = viewer {
+            self.muted_users(viewer, &mut |user| {
+                muted.insert(user.to_base58());
+                Ok(true)
+            })?;
+        }
+
+        let mut users = vec![(user_id.clone(), self.display_name(user_id)?)];
+        if let Some(row) = self.user_profile(user_id)? {
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item_bytes)?;
+            for follow in item.get_profile().get_follows() {
+                let user = UserID::from_vec(follow.get_user().get_bytes().to_vec())?;
+                if muted.contains(&user.to_base58()) {
+                    continue;
+                }
+                // Prefer displaying the name that this user has assigned to the follow.
+                let name = follow.get_display_name().trim();
+                let name = if name.is_empty() { self.display_name(&user)? } else { Some(name.to_string()) };
+                users.push((user, name));
+            }
+        }
+
+        self.users_items(users, before, callback)
+    }
+
+    fn user_item(&self, user: &UserID, signature: &Signature) -> Result<Option<ItemRow>, Error> {
+        self.shard(user).user_item(user, signature)
+    }
+
+    // Follows are stored with the follower, and items with their author, so
+    // check here. Then ask the author's shard as the author:
+
+    fn followers_only_item(&self, user: &UserID, signature: &Signature, viewer: &UserID) -> Result<Option<ItemRow>, Error> {
+        if !self.may_view(user, viewer)? {
+            return self.user_item(user, signature);
+        }
+        self.shard(user).followers_only_item(user, signature, user)
+    }
+
+    fn followers_only_items<'a>(&self, user: &UserID, viewer: &UserID, before: Timestamp, cb: FnIter<'a, ItemRow>) -> Result<(), Error> {
+        if !self.may_view(user, viewer)? {
+            return Ok(());
+        }
+        self.shard(user).followers_only_items(user, user, before, cb)
+    }
+
+    fn follows(&self, follower: &UserID, followed: &UserID) -> Result<bool, Error> {
+        self.shard(follower).follows(follower, followed)
+    }
+
+    fn user_item_exists(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        self.shard(user).user_item_exists(user, signature)
+    }
+
+    fn user_item_deleted(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        self.shard(user).user_item_deleted(user, signature)
+    }
+
+    fn save_user_item(&mut self, item_row: &ItemRow, item: &Item) -> Result<(), Error> {
+        let index = shard_index(&item_row.user, self.shards.len());
+        self.shards[index].save_user_item(item_row, item)
+    }
+
+    fn server_user(&self, user: &UserID) -> Result<Option<ServerUser>, Error> {
+        self.shard(user).server_user(user)
+    }
+
+    fn server_users<'a>(&self, cb: FnIter<'a, ServerUser>) -> Result<(), Error> {
+        for shard in &self.shards {
+            let mut more = true;
+            shard.server_users(&mut |user| {
+                more = cb(user)?;
+                Ok(more)
+            })?;
+            if !more { break; }
+        }
+        Ok(())
+    }
+
+    fn add_server_user(&self, server_user: &ServerUser) -> Result<(), Error> {
+        self.shard(&server_user.user).add_server_user(server_user)
+    }
+
+    fn remove_server_user(&self, user: &UserID) -> Result<bool, Error> {
+        self.shard(user).remove_server_user(user)
+    }
+
+    fn user_quota(&self, user: &UserID) -> Result<Option<Quota>, Error> {
+        self.shard(user).user_quota(user)
+    }
+
+    fn set_user_quota(&self, user: &UserID, quota: &Quota) -> Result<bool, Error> {
+        self.shard(user).set_user_quota(user, quota)
+    }
+
+    fn user_profile(&self, user_id: &UserID) -> Result<Option<ItemRow>, Error> {
+        self.shard(user_id).user_profile(user_id)
+    }
+
+    fn user_known(&self, user_id: &UserID) -> Result<bool, Error> {
+        // Server users' follows are stored in the server users' shards.
+        for shard in &self.shards {
+            if shard.user_known(user_id)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn remove_user_item(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        self.shard(user).remove_user_item(user, signature)
+    }
+
+    fn restore_user_item(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        self.shard(user).restore_user_item(user, signature)
+    }
+
+    fn quarantine_user_item(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        self.shard(user).quarantine_user_item(user, signature)
+    }
+
+    fn approve_user_item(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        self.shard(user).approve_user_item(user, signature)
+    }
+
+    fn quarantined_items<'a>(&self, cb: FnIter<'a, ItemRow>) -> Result<(), Error> {
+        // Oldest first within each shard:
+        for shard in &self.shards {
+            let mut more = true;
+            shard.quarantined_items(&mut |row| {
+                more = cb(row)?;
+                Ok(more)
+            })?;
+            if !more { break; }
+        }
+        Ok(())
+    }
+
+    fn record_item_synced(&self, user: &UserID, signature: &Signature, when: Timestamp) -> Result<(), Error> {
+        self.shard(user).record_item_synced(user, signature, when)
+    }
+
+    fn expire_items(&self, now: Timestamp) -> Result<usize, Error> {
+        let mut expired = 0;
+        for shard in &self.shards {
+            expired += shard.expire_items(now)?;
+        }
+        Ok(expired)
+    }
+
+    fn prune(&self, retention: &Retention, now: Timestamp) -> Result<PruneReport, Error> {
+        let mut report = PruneReport::default();
+        for shard in &self.shards {
+            let pruned = shard.prune(retention, now)?;
+            report.item_events += pruned.item_events;
+            report.sync_reports += pruned.sync_reports;
+            report.security_reports += pruned.security_reports;
+            report.queued += pruned.queued;
+        }
+        Ok(report)
+    }
+
+    fn purge_removed_items(&self, removed_before: Timestamp) -> Result<usize, Error> {
+        let mut purged = 0;
+        for shard in &self.shards {
+            purged += shard.purge_removed_items(removed_before)?;
+        }
+        Ok(purged)
+    }
+
+    fn all_items<'a>(&self, cb: FnIter<'a, ItemRow>) -> Result<(), Error> {
+        for shard in &self.shards {
+            let mut more = true;
+            shard.all_items(&mut |row| {
+                more = cb(row)?;
+                Ok(more)
+            })?;
+            if !more { break; }
+        }
+        Ok(())
+    }
+
+    fn delete_user_item(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        self.shard(user).delete_user_item(user, signature)
+    }
+
+    fn ip_blocks<'a>(&self, cb: FnIter<'a, IpBlock>) -> Result<(), Error> {
+        self.main().ip_blocks(cb)
+    }
+
+    fn add_ip_block(&self, block: &IpBlock) -> Result<(), Error> {
+        self.main().add_ip_block(block)
+    }
+
+    fn remove_ip_block(&self, cidr: &str) -> Result<bool, Error> {
+        self.main().remove_ip_block(cidr)
+    }
+
+    fn record_item_view(&self, user: &UserID, signature: &Signature, when: Timestamp) -> Result<(), Error> {
+        self.shard(user).record_item_view(user, signature, when)
+    }
+
+    fn user_item_view_counts<'a>(&self, user: &UserID, cb: FnIter<'a, ItemViewCount>) -> Result<(), Error> {
+        self.shard(user).user_item_view_counts(user, cb)
+    }
+
+    fn user_item_counts_by_month<'a>(&self, user: &UserID, cb: FnIter<'a, MonthCount>) -> Result<(), Error> {
+        self.shard(user).user_item_counts_by_month(user, cb)
+    }
+
+    fn user_top_viewed_items<'a>(&self, user: &UserID, cb: FnIter<'a, ItemViewCount>) -> Result<(), Error> {
+        self.shard(user).user_top_viewed_items(user, cb)
+    }
+
+    fn user_follower_count(&self, user: &UserID) -> Result<u64, Error> {
+        let mut count = 0;
+        for shard in &self.shards {
+            count += shard.user_follower_count(user)?;
+        }
+        Ok(count)
+    }
+
+    // Follows are stored with the follower:
+    fn user_follower_counts_by_month<'a>(&self, user: &UserID, cb: FnIter<'a, MonthCount>) -> Result<(), Error> {
+        self.month_counts(|shard, cb| shard.user_follower_counts_by_month(user, cb), cb)
+    }
+
+    // ... and reactions with whoever reacted:
+    fn user_reaction_counts_by_month<'a>(&self, user: &UserID, cb: FnIter<'a, MonthCount>) -> Result<(), Error> {
+        self.month_counts(|shard, cb| shard.user_reaction_counts_by_month(user, cb), cb)
+    }
+
+    fn announcement(&self) -> Result<Option<Announcement>, Error> {
+        self.main().announcement()
+    }
+
+    fn set_announcement(&self, announcement: Option<&Announcement>) -> Result<(), Error> {
+        self.main().set_announcement(announcement)
+    }
+
+    fn queue_comment(&self, comment: &QueuedComment) -> Result<(), Error> {
+        self.main().queue_comment(comment)
+    }
+
+    fn queued_comment_count(&self, user: &UserID, signature: &Signature) -> Result<u64, Error> {
+        self.main().queued_comment_count(user, signature)
+    }
+
+    fn queued_comments<'a>(&self, cb: FnIter<'a, QueuedComment>) -> Result<(), Error> {
+        self.main().queued_comments(cb)
+    }
+
+    fn user_queued_comments<'a>(&self, user: &UserID, cb: FnIter<'a, QueuedComment>) -> Result<(), Error> {
+        self.main().user_queued_comments(user, cb)
+    }
+
+    fn remove_queued_comment(&self, id: i64) -> Result<bool, Error> {
+        self.main().remove_queued_comment(id)
+    }
+
+    fn push_peers<'a>(&self, cb: FnIter<'a, PushPeer>) -> Result<(), Error> {
+        self.main().push_peers(cb)
+    }
+
+    fn add_push_peer(&self, peer: &PushPeer) -> Result<(), Error> {
+        self.main().add_push_peer(peer)
+    }
+
+    fn remove_push_peer(&self, url: &str) -> Result<bool, Error> {
+        self.main().remove_push_peer(url)
+    }
+
+    fn trusted_peers<'a>(&self, cb: FnIter<'a, TrustedPeer>) -> Result<(), Error> {
+        self.main().trusted_peers(cb)
+    }
+
+    fn add_trusted_peer(&self, peer: &TrustedPeer) -> Result<(), Error> {
+        self.main().add_trusted_peer(peer)
+    }
+
+    fn remove_trusted_peer(&self, key: &UserID) -> Result<bool, Error> {
+        self.main().remove_trusted_peer(key)
+    }
+
+    fn is_trusted_peer(&self, key: &UserID) -> Result<bool, Error> {
+        self.main().is_trusted_peer(key)
+    }
+
+    fn use_request_signature(&self, signature: &Signature, expires: Timestamp) -> Result<bool, Error> {
+        self.main().use_request_signature(signature, expires)
+    }
+
+    fn user_aliases<'a>(&self, cb: FnIter<'a, UserAlias>) -> Result<(), Error> {
+        self.main().user_aliases(cb)
+    }
+
+    fn alias_user(&self, name: &str) -> Result<Option<UserID>, Error> {
+        self.main().alias_user(name)
+    }
+
+    fn add_user_alias(&self, alias: &UserAlias) -> Result<(), Error> {
+        self.main().add_user_alias(alias)
+    }
+
+    fn remove_user_alias(&self, name: &str) -> Result<bool, Error> {
+        self.main().remove_user_alias(name)
+    }
+
+    fn queue_push(&self, user: &UserID, signature: &Signature) -> Result<(), Error> {
+        self.main().queue_push(user, signature)
+    }
+
+    fn due_pushes<'a>(&self, now: Timestamp, cb: FnIter<'a, QueuedPush>) -> Result<(), Error> {
+        self.main().due_pushes(now, cb)
+    }
+
+    fn push_failed(&self, push: &QueuedPush, retry_at: Timestamp, error: &str) -> Result<(), Error> {
+        self.main().push_failed(push, retry_at, error)
+    }
+
+    fn finish_push(&self, push: &QueuedPush) -> Result<(), Error> {
+        self.main().finish_push(push)
+    }
+
+    fn save_sync_report(&self, report: &SyncReport) -> Result<(), Error> {
+        self.main().save_sync_report(report)
+    }
+
+    fn save_security_report(&self, report: &SecurityReport) -> Result<(), Error> {
+        self.main().save_security_report(report)
+    }
+
+    fn security_reports<'a>(&self, before: Timestamp, cb: FnIter<'a, SecurityReport>) -> Result<(), Error> {
+        self.main().security_reports(before, cb)
+    }
+
+    fn user_summary(&self, user: &UserID) -> Result<UserSummary, Error> {
+        self.shard(user).user_summary(user)
+    }
+
+    fn server_stats(&self, since: &[Timestamp]) -> Result<ServerStats, Error> {
+        // Server users are in their own shards, with their items:
+        let mut stats = ServerStats {
+            users: 0,
+            active_users: vec![0; since.len()],
+            posts: 0,
+        };
+        for shard in &self.shards {
+            let shard_stats = shard.server_stats(since)?;
+            stats.users += shard_stats.users;
+            stats.posts += shard_stats.posts;
+            for (total, active) in stats.active_users.iter_mut().zip(shard_stats.active_users) {
+                *total += active;
+            }
+        }
+        Ok(stats)
+    }
+
+    fn record_rejection(&self, when: Timestamp) -> Result<(), Error> {
+        self.main().record_rejection(when)
+    }
+
+    fn daily_stats<'a>(&self, since_day: i64, cb: FnIter<'a, DailyStats>) -> Result<(), Error> {
+        // Each shard counts the items it saved:
+        let mut days: BTreeMap<i64, DailyStats> = BTreeMap::new();
+        for shard in &self.shards {
+            shard.daily_stats(since_day, &mut |stats| {
+                days.entry(stats.day)
+                    .or_insert_with(|| DailyStats{ day: stats.day, ..Default::default() })
+                    .add(&stats);
+                Ok(true)
+            })?;
+        }
+        for (_, stats) in days {
+            if !cb(stats)? { break; }
+        }
+        Ok(())
+    }
+
+    fn mute_user(&self, viewer: &UserID, muted: &UserID) -> Result<(), Error> {
+        self.shard(viewer).mute_user(viewer, muted)
+    }
+
+    fn unmute_user(&self, viewer: &UserID, muted: &UserID) -> Result<bool, Error> {
+        self.shard(viewer).unmute_user(viewer, muted)
+    }
+
+    fn muted_users<'a>(&self, viewer: &UserID, cb: FnIter<'a, UserID>) -> Result<(), Error> {
+        self.shard(viewer).muted_users(viewer, cb)
+    }
+
+    fn save_feed(&self, feed: &SavedFeed) -> Result<(), Error> {
+        self.shard(&feed.user).save_feed(feed)
+    }
+
+    fn saved_feed(&self, user: &UserID, name: &str) -> Result<Option<SavedFeed>, Error> {
+        self.shard(user).saved_feed(user, name)
+    }
+
+    fn saved_feeds<'a>(&self, user: &UserID, cb: FnIter<'a, SavedFeed>) -> Result<(), Error> {
+        self.shard(user).saved_feeds(user, cb)
+    }
+
+    fn delete_saved_feed(&self, user: &UserID, name: &str) -> Result<bool, Error> {
+        self.shard(user).delete_saved_feed(user, name)
+    }
+
+    fn saved_feed_items<'a>(
+        &self,
+        user: &UserID,
+        name: &str,
+        before: Timestamp,
+        cb: FnIter<'a, ItemDisplayRow>,
+    ) -> Result<(), Error> {
+        let feed = match self.saved_feed(user, name)? {
+            None => return Ok(()),
+            Some(feed) => feed,
+        };
+
+        let mut users = Vec::with_capacity(feed.authors.len());
+        for author in feed.authors {
+            let name = self.display_name(&author)?;
+            users.push((author, name));
+        }
+        self.users_items(users, before, cb)
+    }
+
+    fn item_references<'a>(
+        &self,
+        user: &UserID,
+        signature: &Signature,
+        before: Timestamp,
+        callback: &'a mut dyn FnMut(ItemDisplayRow) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
+        // References are stored with the item that makes them:
+        let sources = self.shards.iter().map(|shard| {
+            let source: Source<ItemDisplayRow> = Box::new(move |before, cb| {
+                shard.item_references(user, signature, before, cb)
+            });
+            source
+        }).collect();
+        merge_newest_first(sources, display_row_timestamp, before, callback)
+    }
+
+    fn comment_replies<'a>(
+        &self,
+        user: &UserID,
+        signature: &Signature,
+        cb: FnIter<'a, (UserID, Signature)>,
+    ) -> Result<(), Error> {
+        // References are stored with the item that makes them, and order doesn't matter:
+        for shard in &self.shards {
+            let mut more = true;
+            shard.comment_replies(user, signature, &mut |reply| {
+                more = cb(reply)?;
+                Ok(more)
+            })?;
+            if !more { break; }
+        }
+        Ok(())
+    }
+
+    fn poll_votes<'a>(
+        &self,
+        poll_user: &UserID,
+        poll_signature: &Signature,
+        closes: Timestamp,
+        cb: FnIter<'a, PollVote>,
+    ) -> Result<(), Error> {
+        // Each voter's votes are all in one shard, so each shard picks their latest:
+        for shard in &self.shards {
+            let mut more = true;
+            shard.poll_votes(poll_user, poll_signature, closes, &mut |vote| {
+                more = cb(vote)?;
+                Ok(more)
+            })?;
+            if !more { break; }
+        }
+        Ok(())
+    }
+
+    fn item_reactions<'a>(
+        &self,
+        user: &UserID,
+        signature: &Signature,
+        cb: FnIter<'a, ItemReaction>,
+    ) -> Result<(), Error> {
+        // Each user's reactions are all in one shard:
+        for shard in &self.shards {
+            let mut more = true;
+            shard.item_reactions(user, signature, &mut |reaction| {
+                more = cb(reaction)?;
+                Ok(more)
+            })?;
+            if !more { break; }
+        }
+        Ok(())
+    }
+
+    fn add_activitypub_follower(&self, follower: &ActivityPubFollower) -> Result<(), Error> {
+        self.shard(&follower.user).add_activitypub_follower(follower)
+    }
+
+    fn remove_activitypub_follower(&self, user: &UserID, actor: &str) -> Result<bool, Error> {
+        self.shard(user).remove_activitypub_follower(user, actor)
+    }
+
+    fn activitypub_followers<'a>(&self, user: &UserID, cb: FnIter<'a, ActivityPubFollower>) -> Result<(), Error> {
+        self.shard(user).activitypub_followers(user, cb)
+    }
+
+    fn add_activitypub_reply(&self, reply: &ActivityPubReply) -> Result<(), Error> {
+        self.shard(&reply.user).add_activitypub_reply(reply)
+    }
+
+    fn remove_activitypub_reply(&self, user: &UserID, id: &str, actor: &str) -> Result<bool, Error> {
+        self.shard(user).remove_activitypub_reply(user, id, actor)
+    }
+
+    fn activitypub_replies<'a>(&self, user: &UserID, signature: &Signature, cb: FnIter<'a, ActivityPubReply>) -> Result<(), Error> {
+        self.shard(user).activitypub_replies(user, signature, cb)
+    }
+
+    fn queue_activitypub_delivery(&self, delivery: &QueuedDelivery) -> Result<(), Error> {
+        self.main().queue_activitypub_delivery(delivery)
+    }
+
+    fn due_activitypub_deliveries<'a>(&self, now: Timestamp, cb: FnIter<'a, QueuedDelivery>) -> Result<(), Error> {
+        self.main().due_activitypub_deliveries(now, cb)
+    }
+
+    fn activitypub_delivery_failed(&self, delivery: &QueuedDelivery, retry_at: Timestamp, error: &str) -> Result<(), Error> {
+        self.main().activitypub_delivery_failed(delivery, retry_at, error)
+    }
+
+    fn finish_activitypub_delivery(&self, delivery: &QueuedDelivery) -> Result<(), Error> {
+        self.main().finish_activitypub_delivery(delivery)
+    }
+
+    fn nostr_key(&self, user: &UserID) -> Result<Option<NostrKey>, Error> {
+        self.shard(user).nostr_key(user)
+    }
+
+    fn nostr_keys<'a>(&self, cb: FnIter<'a, NostrKey>) -> Result<(), Error> {
+        for shard in &self.shards {
+            let mut more = true;
+            shard.nostr_keys(&mut |key| {
+                more = cb(key)?;
+                Ok(more)
+            })?;
+            if !more { break; }
+        }
+        Ok(())
+    }
+
+    fn add_nostr_key(&self, key: &NostrKey) -> Result<(), Error> {
+        self.shard(&key.user).add_nostr_key(key)
+    }
+
+    fn remove_nostr_key(&self, user: &UserID) -> Result<bool, Error> {
+        self.shard(user).remove_nostr_key(user)
+    }
+
+    fn crosspost_targets<'a>(&self, user: &UserID, cb: FnIter<'a, CrossPostTarget>) -> Result<(), Error> {
+        self.shard(user).crosspost_targets(user, cb)
+    }
+
+    fn crosspost_target(&self, user: &UserID, name: &str) -> Result<Option<CrossPostTarget>, Error> {
+        self.shard(user).crosspost_target(user, name)
+    }
+
+    fn add_crosspost_target(&self, target: &CrossPostTarget) -> Result<(), Error> {
+        self.shard(&target.user).add_crosspost_target(target)
+    }
+
+    fn remove_crosspost_target(&self, user: &UserID, name: &str) -> Result<bool, Error> {
+        self.shard(user).remove_crosspost_target(user, name)
+    }
+
+    fn queue_crosspost(&self, crosspost: &QueuedCrossPost) -> Result<(), Error> {
+        self.main().queue_crosspost(crosspost)
+    }
+
+    fn due_crossposts<'a>(&self, now: Timestamp, cb: FnIter<'a, QueuedCrossPost>) -> Result<(), Error> {
+        self.main().due_crossposts(now, cb)
+    }
+
+    fn crosspost_failed(&self, crosspost: &QueuedCrossPost, retry_at: Timestamp, error: &str) -> Result<(), Error> {
+        self.main().crosspost_failed(crosspost, retry_at, error)
+    }
+
+    fn finish_crosspost(&self, crosspost: &QueuedCrossPost) -> Result<(), Error> {
+        self.main().finish_crosspost(crosspost)
+    }
+
+    fn ipfs_cid(&self, user: &UserID, signature: &Signature, file_name: &str) -> Result<Option<String>, Error> {
+        self.shard(user).ipfs_cid(user, signature, file_name)
+    }
+
+    fn set_ipfs_cid(&self, user: &UserID, signature: &Signature, file_name: &str, cid: &str) -> Result<(), Error> {
+        self.shard(user).set_ipfs_cid(user, signature, file_name, cid)
+    }
+
+    fn queue_ipfs_pin(&self, pin: &QueuedIpfsPin) -> Result<(), Error> {
+        self.main().queue_ipfs_pin(pin)
+    }
+
+    fn due_ipfs_pins<'a>(&self, now: Timestamp, cb: FnIter<'a, QueuedIpfsPin>) -> Result<(), Error> {
+        self.main().due_ipfs_pins(now, cb)
+    }
+
+    fn ipfs_pin_failed(&self, pin: &QueuedIpfsPin, retry_at: Timestamp, error: &str) -> Result<(), Error> {
+        self.main().ipfs_pin_failed(pin, retry_at, error)
+    }
+
+    fn finish_ipfs_pin(&self, pin: &QueuedIpfsPin) -> Result<(), Error> {
+        self.main().finish_ipfs_pin(pin)
+    }
+
+    fn search<'a>(&self, query: &str, before: Timestamp, cb: FnIter<'a, ItemDisplayRow>) -> Result<(), Error> {
+        let sources = self.shards.iter().map(|shard| {
+            let source: Source<ItemDisplayRow> = Box::new(move |before, cb| shard.search(query, before, cb));
+            source
+        }).collect();
+        merge_newest_first(sources, display_row_timestamp, before, cb)
+    }
+
+    fn save_attachment(&self, user: &UserID, signature: &Signature, name: &str, bytes: &[u8]) -> Result<(), Error> {
+        self.shard(user).save_attachment(user, signature, name, bytes)
+    }
+
+    fn attachment(&self, user: &UserID, signature: &Signature, name: &str) -> Result<Option<Vec<u8>>, Error> {
+        self.shard(user).attachment(user, signature, name)
+    }
+
+    fn followers_only_attachment(&self, user: &UserID, signature: &Signature, name: &str, viewer: &UserID) -> Result<Option<Vec<u8>>, Error> {
+        if !self.may_view(user, viewer)? {
+            return self.attachment(user, signature, name);
+        }
+        self.shard(user).followers_only_attachment(user, signature, name, user)
+    }
+
+    fn attachment_exists(&self, user: &UserID, signature: &Signature, name: &str) -> Result<bool, Error> {
+        self.shard(user).attachment_exists(user, signature, name)
+    }
+
+    fn map_tile(&self, z: u32, x: u32, y: u32) -> Result<Option<MapTile>, Error> {
+        self.main().map_tile(z, x, y)
+    }
+
+    fn save_map_tile(&self, tile: &MapTile) -> Result<(), Error> {
+        self.main().save_map_tile(tile)
+    }
+
+    fn user_item_events<'a>(&self, user: &UserID, cb: FnIter<'a, ItemEvent>) -> Result<(), Error> {
+        self.shard(user).user_item_events(user, cb)
+    }
+
+    fn user_item_sizes<'a>(&self, user: &UserID, by_size: bool, cb: FnIter<'a, ItemSize>) -> Result<(), Error> {
+        self.shard(user).user_item_sizes(user, by_size, cb)
+    }
+
+    fn set_dead_links(&self, user: &UserID, signature: &Signature, links: &[DeadLink]) -> Result<(), Error> {
+        self.shard(user).set_dead_links(user, signature, links)
+    }
+
+    fn user_dead_links<'a>(&self, user: &UserID, cb: FnIter<'a, DeadLink>) -> Result<(), Error> {
+        self.shard(user).user_dead_links(user, cb)
+    }
+
+    fn quota_check_item(&self, user_id: &UserID, bytes: &[u8], item: &Item) -> Result<Option<QuotaDenyReason>, Error> {
+        // Server users have their own quotas, which follows don't override:
+        if self.user_quota(user_id)?.is_some() {
+            return self.shard(user_id).quota_check_item(user_id, bytes, item);
+        }
+
+        // Being followed by a server user in any shard is enough:
+        let mut deny_reason = None;
+        for shard in &self.shards {
+            match shard.quota_check_item(user_id, bytes, item)? {
+                None => return Ok(None),
+                Some(reason) => deny_reason = Some(reason),
+            }
+        }
+        Ok(deny_reason)
+    }
+}
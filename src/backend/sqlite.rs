@@ -5,16 +5,102 @@
 //! Mostly, this makes data management trivial since it's all in one file.
 //! But if performance is an issue we can implement a different backend.
 
-use crate::protos::Item;
+#[cfg(test)]
+mod tests;
+
+use crate::protos::{Item, ItemType, Visibility};
 use rusqlite::NO_PARAMS;
 use crate::backend::FnIter;
-use crate::backend::{self, UserID, Signature, ItemRow, ItemDisplayRow, Timestamp, ServerUser, QuotaDenyReason};
+use crate::backend::{self, UserID, Signature, ItemRow, ItemDisplayRow, Timestamp, ServerUser, QuotaDenyReason, IpBlock, ItemViewCount, MonthCount, SyncReport, SecurityReport, UserSummary, ItemEvent, ItemEventKind, SavedFeed, PollVote, ItemReaction, MapTile, PushPeer, QueuedPush, QueuedComment, Announcement, ActivityPubFollower, ActivityPubReply, UserAlias, ServerStats, NostrKey, ItemSize, QueuedDelivery, CrossPostTarget, QueuedCrossPost, QueuedIpfsPin, ReindexReport, Quota, TrustedPeer, DailyStats, Retention, PruneReport, DeadLink};
 
-use failure::{Error, bail, ResultExt};
+use failure::{Error, bail, format_err, ResultExt};
 use protobuf::Message as _;
+use protobuf::ProtobufEnum as _;
 use rusqlite::{params, OptionalExtension, Row};
+use sodiumoxide::crypto::hash::sha256;
+use std::collections::HashMap;
+use std::time::Duration;
+
+const CURRENT_VERSION: u32 = 41;
+
+/// How long to wait for another connection (possibly in another process) to
+/// release its lock on the database.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many times to retry a write that still failed with SQLITE_BUSY.
+const BUSY_RETRIES: u32 = 3;
+
+/// The latest event for an `item`. (ex: 'quarantined', until it's approved)
+const LATEST_ITEM_EVENT_SQL: &str = "
+    SELECT e.event
+    FROM item_event AS e
+    WHERE e.user_id = item.user_id
+    AND e.signature = item.signature
+    ORDER BY e.created_utc_ms DESC, e.rowid DESC
+    LIMIT 1
+";
+
+// Queries whose plans sqlite/tests.rs checks, so that listings keep using
+// the indexes from migrate_3_to_4() as the queries change.
+
+const HOMEPAGE_ITEMS_SQL: &str = "
+    SELECT
+        user_id
+        , i.signature
+        , unix_utc_ms
+        , received_utc_ms
+        , bytes
+        , p.display_name
+    FROM item AS i
+    LEFT OUTER JOIN profile AS p USING (user_id)
+    WHERE unix_utc_ms < ?
+    AND removed_utc_ms IS NULL
+    AND followers_only = 0
+    AND user_id IN (
+        SELECT user_id
+        FROM server_user
+        WHERE on_homepage = 1
+    )
+    ORDER BY unix_utc_ms DESC
+";
+
+const USER_ITEMS_SQL: &str = "
+    SELECT
+        user_id
+        , i.signature
+        , unix_utc_ms
+        , received_utc_ms
+        , bytes
+    FROM item AS i
+    WHERE
+        unix_utc_ms < ?
+        AND user_id = ?
+        AND removed_utc_ms IS NULL
+        AND followers_only = 0
+    ORDER BY unix_utc_ms DESC
+";
 
-const CURRENT_VERSION: u32 = 3;
+const FIND_ITEM_SQL: &str = "
+    SELECT
+        user_id
+        , signature
+        , unix_utc_ms
+        , received_utc_ms
+        , bytes
+    FROM item
+    WHERE user_id = ?
+    AND signature = ?
+    AND removed_utc_ms IS NULL
+    AND (followers_only = 0 OR ?)
+";
+
+const EXPIRED_ITEMS_SQL: &str = "
+    SELECT user_id, signature, expires_utc_ms
+    FROM item
+    WHERE expires_utc_ms IS NOT NULL
+    AND expires_utc_ms <= ?
+    AND removed_utc_ms IS NULL
+";
 
 type Pool = r2d2::Pool<r2d2_sqlite::SqliteConnectionManager>;
 type PConn = r2d2::PooledConnection<r2d2_sqlite::SqliteConnectionManager>;
@@ -26,12 +112,105 @@ pub(crate) struct Factory
 }
 
 impl Factory {
-    pub fn new(file_path: String) -> Self
+    /// `cache_kib` is the page cache size for each connection. 0 = SQLite's default.
+    pub fn new(file_path: String, cache_kib: u64) -> Self
     {
-        let manager = r2d2_sqlite::SqliteConnectionManager::file(file_path.as_str());
+        let manager = r2d2_sqlite::SqliteConnectionManager::file(file_path.as_str())
+            .with_init(move |conn| configure_connection(conn, cache_kib));
         let pool = r2d2::Pool::new(manager).expect("Creating SQLite connection pool");
         Factory{ pool }
     }
+
+    /// An in-memory database, for tests and demos. (`--backend memory`)
+    /// Nothing touches the disk, and everything is gone once the factory is.
+    ///
+    /// Each factory has its own database, on a single connection. Callers
+    /// (ex: actix workers) take turns with it, instead of sharing the database
+    /// between connections through SQLite's shared cache, where a read of a
+    /// table that another connection is writing fails with "database table is
+    /// locked".
+    pub fn memory() -> Self
+    {
+        let manager = r2d2_sqlite::SqliteConnectionManager::memory();
+
+        // The database disappears with its connection, so never close it:
+        let pool = r2d2::Pool::builder()
+            .max_size(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .build(manager)
+            .expect("Creating in-memory SQLite connection pool");
+        Factory{ pool }
+    }
+}
+
+/// Make sure an existing database file can use WAL mode, switching it over if
+/// it was created (or restored) in another mode. Do this at startup, so that a
+/// problem is reported once, clearly, instead of by every connection.
+pub(crate) fn check_journal_mode(file_path: &str) -> Result<(), Error> {
+    let conn = rusqlite::Connection::open(file_path)
+        .with_context(|_| format!("Error opening {}", file_path))?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+
+    let mode: String = conn.query_row("PRAGMA journal_mode", NO_PARAMS, |row| row.get(0))?;
+    if mode.eq_ignore_ascii_case("wal") {
+        return Ok(());
+    }
+
+    let new_mode: String = conn.pragma_update_and_check(None, "journal_mode", &"WAL", |row| row.get(0))?;
+    if !new_mode.eq_ignore_ascii_case("wal") {
+        bail!(
+            "Could not switch {} from \"{}\" to WAL mode. Stop other programs using it (ex: an older \
+            FeoBlog), and make sure it's not on a network filesystem.",
+            file_path, mode,
+        );
+    }
+    println!("Switched {} from \"{}\" to WAL mode.", file_path, mode);
+    Ok(())
+}
+
+/// Configure each connection so that multiple processes (ex: more than one
+/// server, or a server and `feoblog user add`) can safely share one database file.
+fn configure_connection(conn: &mut rusqlite::Connection, cache_kib: u64) -> Result<(), rusqlite::Error> {
+    // Wait for other writers instead of immediately failing with SQLITE_BUSY:
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+
+    // WAL lets readers continue while another connection writes. It requires
+    // shared memory between processes, so isn't available on some network
+    // filesystems. Refuse to run there rather than risk corrupting the DB.
+    let mode: String = conn.pragma_update_and_check(None, "journal_mode", &"WAL", |row| row.get(0))?;
+    if !mode.eq_ignore_ascii_case("wal") {
+        return Err(rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CANTOPEN),
+            Some(format!(
+                "Could not enable WAL mode (got \"{}\"). Is the database on a network filesystem?",
+                mode
+            )),
+        ));
+    }
+
+    // In WAL mode, this only risks losing the latest commits on power loss,
+    // never corruption. It saves an fsync() on every commit, which keeps
+    // put_item's write lock short.
+    conn.pragma_update(None, "synchronous", &"NORMAL")?;
+
+    if cache_kib > 0 {
+        // Negative sizes are in KiB, instead of pages:
+        conn.pragma_update(None, "cache_size", &-(cache_kib as i64))?;
+    }
+
+    Ok(())
+}
+
+/// True if this error was caused by another connection holding a lock.
+fn is_busy(err: &Error) -> bool {
+    match err.find_root_cause().downcast_ref::<rusqlite::Error>() {
+        Some(rusqlite::Error::SqliteFailure(e, _)) => {
+            e.code == rusqlite::ErrorCode::DatabaseBusy
+            || e.code == rusqlite::ErrorCode::DatabaseLocked
+        },
+        _ => false,
+    }
 }
 
 impl backend::Factory for Factory
@@ -52,6 +231,7 @@ pub(crate) struct Connection
 
 impl Connection
 {
+    /// Creates the version 3 schema. Later versions are applied by [`Self::migrate`].
     fn setup_new(&self) -> Result<(), Error>
     {
         self.run("
@@ -180,512 +360,4057 @@ impl Connection
         Ok(())
     }
 
-    fn run(&self, sql: &str) -> Result<(), Error>
+    /// Upgrade the database from `version` to `version + 1`.
+    fn migrate(&self, version: u32) -> Result<(), Error>
     {
-        self.conn.execute(sql, params![])?;
+        let tx = self.conn.unchecked_transaction()?;
+        match version {
+            3 => self.migrate_3_to_4()?,
+            4 => self.migrate_4_to_5()?,
+            5 => self.migrate_5_to_6()?,
+            6 => self.migrate_6_to_7()?,
+            7 => self.migrate_7_to_8()?,
+            8 => self.migrate_8_to_9()?,
+            9 => self.migrate_9_to_10()?,
+            10 => self.migrate_10_to_11()?,
+            11 => self.migrate_11_to_12()?,
+            12 => self.migrate_12_to_13()?,
+            13 => self.migrate_13_to_14()?,
+            14 => self.migrate_14_to_15()?,
+            15 => self.migrate_15_to_16()?,
+            16 => self.migrate_16_to_17()?,
+            17 => self.migrate_17_to_18()?,
+            18 => self.migrate_18_to_19()?,
+            19 => self.migrate_19_to_20()?,
+            20 => self.migrate_20_to_21()?,
+            21 => self.migrate_21_to_22()?,
+            22 => self.migrate_22_to_23()?,
+            23 => self.migrate_23_to_24()?,
+            24 => self.migrate_24_to_25()?,
+            25 => self.migrate_25_to_26()?,
+            26 => self.migrate_26_to_27()?,
+            27 => self.migrate_27_to_28()?,
+            28 => self.migrate_28_to_29()?,
+            29 => self.migrate_29_to_30()?,
+            30 => self.migrate_30_to_31()?,
+            31 => self.migrate_31_to_32()?,
+            32 => self.migrate_32_to_33()?,
+            33 => self.migrate_33_to_34()?,
+            34 => self.migrate_34_to_35()?,
+            35 => self.migrate_35_to_36()?,
+            36 => self.migrate_36_to_37()?,
+            37 => self.migrate_37_to_38()?,
+            38 => self.migrate_38_to_39()?,
+            39 => self.migrate_39_to_40()?,
+            40 => self.migrate_40_to_41()?,
+            _ => bail!("DB version {} is unknown. Migration not implemented.", version),
+        }
+        tx.execute("INSERT INTO version VALUES(?)", params![version + 1])?;
+        tx.commit()?;
         Ok(())
     }
 
-    fn get_version(&self) -> Result<Option<u32>, Error>
+    /// Replace the original chronological indexes with explicit (descending)
+    /// composite ones that match our listing queries, and allow looking up
+    /// items by signature alone.
+    fn migrate_3_to_4(&self) -> Result<(), Error>
     {
-        let table_count: u32  = self.conn.prepare(
-            "SELECT count()
-            FROM sqlite_master
-            WHERE type = 'table'
-            AND name = 'version'
-            "
-        )?.query_row(
-            params![],
-            |row|  Ok(row.get(0)?)
-        )?;
+        // Superseded by item_user_timestamp_idx and item_timestamp_idx:
+        self.run("DROP INDEX IF EXISTS item_user_chrono_idx")?;
+        self.run("DROP INDEX IF EXISTS item_unix_utc_idx")?;
 
-        if table_count == 0 {
-            return Ok(None);
-        }
+        self.run("
+            -- Used by user_items(), user_feed_items() and homepage_items(),
+            -- which all list a user's items newest-first.
+            CREATE INDEX item_user_timestamp_idx
+            ON item(user_id, unix_utc_ms DESC)
+        ")?;
+        self.run("
+            CREATE INDEX item_timestamp_idx
+            ON item(unix_utc_ms DESC)
+        ")?;
+        self.run("
+            CREATE INDEX item_signature_idx
+            ON item(signature)
+        ")?;
 
-        let  version = self.conn.prepare(
-            "SELECT MAX(version) from version"
-        )?.query_row(
-            params![],
-            |row| Ok(row.get(0)?)
-        )?;
+        // Note: The homepage flag lives in server_user, not item, so the homepage
+        // query is served by server_user_homepage_idx (on_homepage, user_id)
+        // joined against item_user_timestamp_idx.
 
-        Ok(version)
+        Ok(())
     }
 
-}
+    /// Allow soft-deleting items.
+    fn migrate_4_to_5(&self) -> Result<(), Error>
+    {
+        self.run("
+            -- When set, the time at which a server admin removed this item.
+            -- Removed items are not served, but can be restored until they
+            -- are purged.
+            ALTER TABLE item ADD COLUMN removed_utc_ms INTEGER
+        ")?;
+        self.run("
+            CREATE INDEX item_removed_idx
+            ON item(removed_utc_ms)
+            WHERE removed_utc_ms IS NOT NULL
+        ")?;
 
-/// We're saving a profile. If it's new, update the profile and follow tables.
-fn update_profile(conn: &rusqlite::Savepoint, item_row: &ItemRow, item: &Item) -> Result<(), Error> {
+        Ok(())
+    }
 
-    let prev_timestamp: Option<i64> =  
-        conn.prepare("
-            SELECT i.unix_utc_ms
-            FROM profile AS p
-            INNER JOIN item AS i USING (user_id, signature)
-            WHERE user_id = ?
-        ")?
-        .query(params![ item_row.user.bytes() ])?
-        .next()?
-        .map(|row| row.get(0))
-        .transpose()?
-    ;
+    /// Add a table of blocked IP addresses.
+    fn migrate_5_to_6(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE ip_block(
+                -- Requests from these IP networks are refused.
 
-    // Never replace a newer profile's metadata:
-    if let Some(previous) = prev_timestamp {
-        if previous >= item.timestamp_ms_utc {
-            return Ok(())
-        }
-    }
+                -- An IP address or network in CIDR notation.
+                cidr TEXT
 
-    // Replace all follows with new ones listed in the profile:
-    conn.execute("DELETE FROM follow WHERE source_user_id = ?", params![item_row.user.bytes()])?;
+                -- Notes for the server admin. (ex: why was this blocked?)
+                , notes TEXT
 
-    // Behavior is undefined if duplicate follows exist in a Profile. So we just replace:
-    let mut add_follow = conn.prepare("
-        INSERT OR REPLACE INTO follow (source_user_id, followed_user_id, display_name)
-        VALUES (?, ?, ?)
-    ")?;
+                , created_utc_ms INTEGER
+            )
+        ")?;
+        self.run("
+            CREATE UNIQUE INDEX ip_block_primary_idx
+            ON ip_block(cidr)
+        ")?;
 
-    for follow in item.get_profile().get_follows() {
-        add_follow.execute(params![
-            item_row.user.bytes(),
-            follow.get_user().get_bytes(),
-            follow.get_display_name(),
-        ])?;
+        Ok(())
     }
 
-    let mut add_profile = conn.prepare("
-        INSERT OR REPLACE INTO profile(user_id, signature, display_name)
-        VALUES (?,?,?)
-    ")?;
-    add_profile.execute(params![
-        item_row.user.bytes(),
-        item_row.signature.bytes(),
-        item.get_profile().get_display_name()
-    ])?;
+    /// Add anonymous, daily view counts for items.
+    fn migrate_6_to_7(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE item_view_day(
+                -- How many times an item was viewed on a given day.
+                -- We intentionally store nothing about *who* viewed it.
+                user_id BLOB
+                , signature BLOB
 
-    Ok(())
-}
+                -- Days since 1970-01-01, UTC.
+                , day INTEGER
+                , views INTEGER
+            )
+        ")?;
+        self.run("
+            CREATE UNIQUE INDEX item_view_day_primary_idx
+            ON item_view_day(user_id, signature, day)
+        ")?;
 
-impl backend::Backend for Connection
-{
+        Ok(())
+    }
 
-    fn setup(&self) -> Result<(), Error>
+    /// Store reports from `feoblog sync verify`.
+    fn migrate_7_to_8(&self) -> Result<(), Error>
     {
-        let version = match self.get_version()? {
-            None => {
-                // TODO: This shouldn't be automatic, should force user to
-                // explicitly create a new data store.
-                return self.setup_new();
-            },
-            Some(version) => version
-        };
-        if version == CURRENT_VERSION {
-            return Ok(());
-        }
-        if version > CURRENT_VERSION {
-            bail!(
-                "DB version ({}) newer than current version ({})",
-                version,
-                CURRENT_VERSION
-            );
-        }
+        self.run("
+            CREATE TABLE sync_report(
+                -- The base URL of the peer we compared against.
+                peer TEXT
+                , created_utc_ms INTEGER
 
-        // TODO:
-        bail!("DB version {} is unknown. Migration not implemented.", version);
-    }
+                -- bool 0/1 -- did both servers have the same items?
+                , complete INTEGER
 
-    fn homepage_items<'a>(
-        &self,
-        before: Timestamp,
-        callback: &'a mut dyn FnMut(ItemDisplayRow) -> Result<bool,Error>
-    ) -> Result<(), Error> {
-        let mut stmt = self.conn.prepare("
-            SELECT
-                user_id
-                , i.signature
-                , unix_utc_ms
-                , received_utc_ms
-                , bytes
-                , p.display_name
-            FROM item AS i
-            LEFT OUTER JOIN profile AS p USING (user_id)
-            WHERE unix_utc_ms < ?
-            AND user_id IN (
-                SELECT user_id
-                FROM server_user
-                WHERE on_homepage = 1
+                -- A human-readable report.
+                , report TEXT
             )
-            ORDER BY unix_utc_ms DESC
+        ")?;
+        self.run("
+            CREATE INDEX sync_report_peer_idx
+            ON sync_report(peer, created_utc_ms)
         ")?;
 
-        let mut rows = stmt.query(params![
-            before.unix_utc_ms,
-        ])?;
-
-        let to_item_profile_row = |row: &Row<'_>| -> Result<ItemDisplayRow, Error> {
+        Ok(())
+    }
 
-            let item = ItemRow{
-                user: UserID::from_vec(row.get(0)?)?,
-                signature: Signature::from_vec(row.get(1)?)?,
-                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
-                received: Timestamp{ unix_utc_ms: row.get(3)? },
-                item_bytes: row.get(4)?,
-            };
+    /// Keep a running summary of each user's items.
+    fn migrate_8_to_9(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE user_summary(
+                -- A summary of the (un-removed) items we have for a user.
+                -- Peers can compare summaries to cheaply check whether they
+                -- have the same items.
+                user_id BLOB
+                , item_count INTEGER
 
-            Ok(ItemDisplayRow{
-                item,
-                display_name: row.get(5)?
-            })
-        };
+                -- The XOR of the SHA-256 hashes of each item's signature.
+                , digest BLOB
+            )
+        ")?;
+        self.run("
+            CREATE UNIQUE INDEX user_summary_primary_idx
+            ON user_summary(user_id)
+        ")?;
 
+        // Summarize existing items:
+        let mut stmt = self.conn.prepare("
+            SELECT user_id, signature
+            FROM item
+            WHERE removed_utc_ms IS NULL
+        ")?;
+        let mut rows = stmt.query(NO_PARAMS)?;
         while let Some(row) = rows.next()? {
-            let item = to_item_profile_row(row)?;
-            let result = callback(item)?;
-            if !result { break; }
+            let user = UserID::from_vec(row.get(0)?)?;
+            let signature = Signature::from_vec(row.get(1)?)?;
+            update_summary(&self.conn, &user, &signature, 1)?;
         }
 
-        Ok( () )
+        Ok(())
     }
 
-    fn user_items<'a>(
-        &self,
-        user: &UserID,
-        before: Timestamp,
-        callback: &'a mut dyn FnMut(ItemRow) -> Result<bool,Error>
-    ) -> Result<(), Error> {
-        let mut stmt = self.conn.prepare("
-            SELECT
-                user_id
-                , i.signature
-                , unix_utc_ms
-                , received_utc_ms
-                , bytes
-            FROM item AS i
-            WHERE
-                unix_utc_ms < ?
-                AND user_id = ?
-            ORDER BY unix_utc_ms DESC
+    /// An append-only log of what happened to items.
+    fn migrate_9_to_10(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE item_event(
+                user_id BLOB
+                , signature BLOB
+                -- ex: 'received', 'removed'. See backend::ItemEventKind.
+                , event TEXT
+                , created_utc_ms INTEGER
+            )
+        ")?;
+        self.run("
+            CREATE INDEX item_event_user_idx
+            ON item_event(user_id, created_utc_ms)
+        ")?;
+        self.run("
+            CREATE TRIGGER item_event_no_update
+            BEFORE UPDATE ON item_event
+            BEGIN
+                SELECT RAISE(ABORT, 'item_event is append-only');
+            END
+        ")?;
+        self.run("
+            CREATE TRIGGER item_event_no_delete
+            BEFORE DELETE ON item_event
+            BEGIN
+                SELECT RAISE(ABORT, 'item_event is append-only');
+            END
         ")?;
 
-        let mut rows = stmt.query(params![
-            before.unix_utc_ms,
-            user.bytes(),
-        ])?;
-
-        let convert = |row: &Row<'_>| -> Result<ItemRow, Error> {
-            let item = ItemRow{
-                user: UserID::from_vec(row.get(0)?)?,
-                signature: Signature::from_vec(row.get(1)?)?,
-                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
-                received: Timestamp{ unix_utc_ms: row.get(3)? },
-                item_bytes: row.get(4)?,
-            };
+        // We don't know the history of existing items, but we do know when we received them:
+        self.run("
+            INSERT INTO item_event(user_id, signature, event, created_utc_ms)
+            SELECT user_id, signature, 'received', received_utc_ms
+            FROM item
+        ")?;
 
-            Ok(item)
-        };
+        Ok(())
+    }
 
-        while let Some(row) = rows.next()? {
-            let item = convert(row)?;
-            let result = callback(item)?;
+    /// Per-user mutes, for filtering feeds.
+    fn migrate_10_to_11(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE user_mute(
+                -- The user whose feed should hide items.
+                user_id BLOB
+                , muted_user_id BLOB
+                , created_utc_ms INTEGER
+            )
+        ")?;
+        self.run("
+            CREATE UNIQUE INDEX user_mute_primary_idx
+            ON user_mute(user_id, muted_user_id)
+        ")?;
+
+        Ok(())
+    }
+
+    /// Saved (custom) feeds.
+    fn migrate_11_to_12(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE saved_feed(
+                user_id BLOB
+                , name TEXT
+                -- Comma-separated ItemType values. Empty = default types.
+                , item_types TEXT
+                , created_utc_ms INTEGER
+            )
+        ")?;
+        self.run("
+            CREATE UNIQUE INDEX saved_feed_primary_idx
+            ON saved_feed(user_id, name)
+        ")?;
+        self.run("
+            CREATE TABLE saved_feed_author(
+                user_id BLOB
+                , name TEXT
+                , author_id BLOB
+            )
+        ")?;
+        self.run("
+            CREATE UNIQUE INDEX saved_feed_author_primary_idx
+            ON saved_feed_author(user_id, name, author_id)
+        ")?;
+
+        Ok(())
+    }
+
+    /// Index votes by the poll they're for.
+    fn migrate_12_to_13(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE poll_vote(
+                -- The Poll item:
+                poll_user_id BLOB
+                , poll_signature BLOB
+
+                -- The Vote item:
+                , voter_id BLOB
+                , vote_signature BLOB
+                , unix_utc_ms INTEGER
+
+                , option INTEGER
+            )
+        ")?;
+        self.run("
+            CREATE INDEX poll_vote_poll_idx
+            ON poll_vote(poll_user_id, poll_signature, voter_id, unix_utc_ms)
+        ")?;
+
+        Ok(())
+    }
+
+    /// A cache of map tiles.
+    fn migrate_13_to_14(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE map_tile(
+                z INTEGER
+                , x INTEGER
+                , y INTEGER
+                , bytes BLOB
+                , fetched_utc_ms INTEGER
+            )
+        ")?;
+        self.run("
+            CREATE UNIQUE INDEX map_tile_primary_idx
+            ON map_tile(z, x, y)
+        ")?;
+
+        Ok(())
+    }
+
+    /// Files attached to items.
+    fn migrate_14_to_15(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE attachment(
+                user_id BLOB
+                , signature BLOB
+                , name TEXT
+                , bytes BLOB
+                , received_utc_ms INTEGER
+            )
+        ")?;
+        self.run("
+            CREATE UNIQUE INDEX attachment_primary_idx
+            ON attachment(user_id, signature, name)
+        ")?;
+
+        Ok(())
+    }
+
+    /// An index of which items refer to which other items.
+    fn migrate_15_to_16(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE item_reference(
+                -- The referring item:
+                user_id BLOB
+                , signature BLOB
+                , unix_utc_ms INTEGER
+
+                -- The item it refers to:
+                , ref_user_id BLOB
+                , ref_signature BLOB
+            )
+        ")?;
+        self.run("
+            CREATE INDEX item_reference_ref_idx
+            ON item_reference(ref_user_id, ref_signature, unix_utc_ms)
+        ")?;
+
+        // Votes are the only items with references so far:
+        self.run("
+            INSERT INTO item_reference(user_id, signature, unix_utc_ms, ref_user_id, ref_signature)
+            SELECT voter_id, vote_signature, unix_utc_ms, poll_user_id, poll_signature
+            FROM poll_vote
+        ")?;
+
+        Ok(())
+    }
+
+    /// Peers to push new items to, and a queue of items to push.
+    fn migrate_16_to_17(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE push_peer(
+                url TEXT PRIMARY KEY
+                , created_utc_ms INTEGER
+            )
+        ")?;
+        self.run("
+            CREATE TABLE push_queue(
+                peer_url TEXT
+                , user_id BLOB
+                , signature BLOB
+                , attempts INTEGER
+                , next_attempt_utc_ms INTEGER
+                , last_error TEXT
+            )
+        ")?;
+        self.run("
+            CREATE UNIQUE INDEX push_queue_primary_idx
+            ON push_queue(peer_url, user_id, signature)
+        ")?;
+        self.run("
+            CREATE INDEX push_queue_next_attempt_idx
+            ON push_queue(next_attempt_utc_ms)
+        ")?;
+
+        Ok(())
+    }
+
+    /// Comments from anonymous visitors, waiting for review.
+    fn migrate_17_to_18(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE comment_queue(
+                id INTEGER PRIMARY KEY
+                , user_id BLOB
+                , signature BLOB
+                , name TEXT
+                , text TEXT
+                , created_utc_ms INTEGER
+            )
+        ")?;
+        self.run("
+            CREATE INDEX comment_queue_item_idx
+            ON comment_queue(user_id, signature)
+        ")?;
+
+        Ok(())
+    }
+
+    /// The operator's announcement. At most one row.
+    fn migrate_18_to_19(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE announcement(
+                message TEXT
+                , created_utc_ms INTEGER
+                , expires_utc_ms INTEGER
+            )
+        ")?;
+
+        Ok(())
+    }
+
+    /// Full-text search of posts.
+    fn migrate_19_to_20(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE VIRTUAL TABLE post_search USING fts5(
+                title
+                , body
+                , user_id UNINDEXED
+                , signature UNINDEXED
+            )
+        ")?;
+
+        // Index existing posts:
+        let mut stmt = self.conn.prepare("SELECT user_id, signature, bytes FROM item")?;
+        let mut rows = stmt.query(NO_PARAMS)?;
+        while let Some(row) = rows.next()? {
+            let bytes: Vec<u8> = row.get(2)?;
+            let mut item = Item::new();
+            item.merge_from_bytes(&bytes)?;
+            if item.has_post() {
+                let user = UserID::from_vec(row.get(0)?)?;
+                let signature = Signature::from_vec(row.get(1)?)?;
+                index_post(&self.conn, &user, &signature, &item)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn migrate_20_to_21(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE activitypub_follower(
+                user_id BLOB
+                , actor TEXT
+                , inbox TEXT
+                , created_utc_ms INTEGER
+                , PRIMARY KEY (user_id, actor)
+            )
+        ")?;
+
+        Ok(())
+    }
+
+    fn migrate_21_to_22(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE activitypub_reply(
+                id TEXT PRIMARY KEY
+                , user_id BLOB
+                , signature BLOB
+                , actor TEXT
+                , actor_name TEXT
+                , url TEXT
+                , text TEXT
+                , published_utc_ms INTEGER
+                , received_utc_ms INTEGER
+            )
+        ")?;
+        self.run("
+            CREATE INDEX activitypub_reply_item_idx
+            ON activitypub_reply(user_id, signature, published_utc_ms)
+        ")?;
+
+        Ok(())
+    }
+
+    fn migrate_22_to_23(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE user_alias(
+                name TEXT PRIMARY KEY
+                , user_id BLOB
+                , created_utc_ms INTEGER
+            )
+        ")?;
+
+        Ok(())
+    }
+
+    fn migrate_23_to_24(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE nostr_key(
+                user_id BLOB PRIMARY KEY
+                , secret_key BLOB
+                , created_utc_ms INTEGER
+            )
+        ")?;
+
+        Ok(())
+    }
+
+    /// A queue of items to deliver to ActivityPub inboxes, like push_queue.
+    fn migrate_24_to_25(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE activitypub_delivery(
+                inbox TEXT
+                , user_id BLOB
+                , signature BLOB
+                , base_url TEXT
+                , attempts INTEGER
+                , next_attempt_utc_ms INTEGER
+                , last_error TEXT
+            )
+        ")?;
+        self.run("
+            CREATE UNIQUE INDEX activitypub_delivery_primary_idx
+            ON activitypub_delivery(inbox, user_id, signature)
+        ")?;
+        self.run("
+            CREATE INDEX activitypub_delivery_next_attempt_idx
+            ON activitypub_delivery(next_attempt_utc_ms)
+        ")?;
+
+        Ok(())
+    }
+
+    /// Services to cross-post users' posts to, and a queue of posts to send.
+    fn migrate_25_to_26(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE crosspost_target(
+                user_id BLOB
+                , name TEXT
+                -- See: CrossPostService
+                , service TEXT
+                , url TEXT
+                , account TEXT
+                , token TEXT
+                , created_utc_ms INTEGER
+            )
+        ")?;
+        self.run("
+            CREATE UNIQUE INDEX crosspost_target_primary_idx
+            ON crosspost_target(user_id, name)
+        ")?;
+        self.run("
+            CREATE TABLE crosspost_queue(
+                user_id BLOB
+                , signature BLOB
+                , target TEXT
+                , base_url TEXT
+                , attempts INTEGER
+                , next_attempt_utc_ms INTEGER
+                , last_error TEXT
+            )
+        ")?;
+        self.run("
+            CREATE UNIQUE INDEX crosspost_queue_primary_idx
+            ON crosspost_queue(user_id, signature, target)
+        ")?;
+        self.run("
+            CREATE INDEX crosspost_queue_next_attempt_idx
+            ON crosspost_queue(next_attempt_utc_ms)
+        ")?;
+
+        Ok(())
+    }
+
+    /// IPFS CIDs of items and attachments, and a queue of those to add.
+    fn migrate_26_to_27(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE ipfs_cid(
+                user_id BLOB
+                , signature BLOB
+                -- Empty for the item's own bytes.
+                , file_name TEXT
+                , cid TEXT
+            )
+        ")?;
+        self.run("
+            CREATE UNIQUE INDEX ipfs_cid_primary_idx
+            ON ipfs_cid(user_id, signature, file_name)
+        ")?;
+        self.run("
+            CREATE TABLE ipfs_queue(
+                user_id BLOB
+                , signature BLOB
+                , file_name TEXT
+                , attempts INTEGER
+                , next_attempt_utc_ms INTEGER
+                , last_error TEXT
+            )
+        ")?;
+        self.run("
+            CREATE UNIQUE INDEX ipfs_queue_primary_idx
+            ON ipfs_queue(user_id, signature, file_name)
+        ")?;
+        self.run("
+            CREATE INDEX ipfs_queue_next_attempt_idx
+            ON ipfs_queue(next_attempt_utc_ms)
+        ")?;
+
+        Ok(())
+    }
+
+    /// Let authors set items to expire.
+    fn migrate_27_to_28(&self) -> Result<(), Error>
+    {
+        self.run("
+            -- Copied from the item's expires_ms_utc, if set.
+            -- Once it's passed, the item is removed. (See: expire_items)
+            ALTER TABLE item ADD COLUMN expires_utc_ms INTEGER
+        ")?;
+        self.run("
+            CREATE INDEX item_expires_idx
+            ON item(expires_utc_ms)
+            WHERE expires_utc_ms IS NOT NULL
+        ")?;
+
+        Ok(())
+    }
+
+    /// Let authors limit items to their followers.
+    fn migrate_28_to_29(&self) -> Result<(), Error>
+    {
+        self.run("
+            -- 1 if the item's visibility is VISIBILITY_FOLLOWERS.
+            -- These are left out of all listings, and only served to viewers
+            -- who may see them. (See: followers_only_item)
+            ALTER TABLE item ADD COLUMN followers_only INTEGER NOT NULL DEFAULT 0
+        ")?;
+        self.run("
+            CREATE INDEX item_followers_only_idx
+            ON item(user_id, unix_utc_ms)
+            WHERE followers_only = 1
+        ")?;
+
+        Ok(())
+    }
+
+    /// Server users' quotas. (max_bytes was already there, but unused.)
+    fn migrate_29_to_30(&self) -> Result<(), Error>
+    {
+        self.run("
+            -- How many items a server user may post in 24 hours.
+            -- NULL or 0 = unlimited.
+            ALTER TABLE server_user ADD COLUMN max_items_per_day INTEGER
+        ")?;
+
+        Ok(())
+    }
+
+    /// Other servers' keys, for peer authentication.
+    fn migrate_30_to_31(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE trusted_peer(
+                key BLOB PRIMARY KEY
+                , notes TEXT
+                , created_utc_ms INTEGER
+            )
+        ")?;
+
+        Ok(())
+    }
+
+    /// Per-day counts, for capacity planning.
+    fn migrate_31_to_32(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE daily_stats(
+                -- Days since 1970-01-01, UTC.
+                day INTEGER PRIMARY KEY
+                , items INTEGER NOT NULL DEFAULT 0
+                , item_bytes INTEGER NOT NULL DEFAULT 0
+                , attachments INTEGER NOT NULL DEFAULT 0
+                , attachment_bytes INTEGER NOT NULL DEFAULT 0
+                , rejections INTEGER NOT NULL DEFAULT 0
+            )
+        ")?;
+
+        Ok(())
+    }
+
+    /// Let old item events be pruned. (See: Backend::prune)
+    fn migrate_32_to_33(&self) -> Result<(), Error>
+    {
+        self.run("DROP TRIGGER item_event_no_delete")?;
+        // 2592000 = 30 days, as seconds. (migrate_39_to_40 makes this configurable.)
+        self.run("
+            CREATE TRIGGER item_event_no_delete
+            BEFORE DELETE ON item_event
+            WHEN OLD.created_utc_ms > (CAST(strftime('%s', 'now') AS INTEGER) - 2592000) * 1000
+            BEGIN
+                SELECT RAISE(ABORT, 'item_event is append-only, except for events over 30 days old');
+            END
+        ")?;
+
+        Ok(())
+    }
+
+    fn migrate_33_to_34(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE used_request_signature(
+                -- Signatures from FeoBlog-Viewer and FeoBlog-Peer headers
+                -- that have been used, so that they can't be replayed.
+                signature BLOB PRIMARY KEY,
+                -- When the header's timestamp is too old to be accepted,
+                -- and we can forget it.
+                expires_utc_ms INTEGER NOT NULL
+            )
+        ")?;
+        self.run("
+            CREATE INDEX used_request_signature_expires
+            ON used_request_signature(expires_utc_ms)
+        ")?;
+
+        Ok(())
+    }
+
+    fn migrate_34_to_35(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE dead_link(
+                -- Links in items that failed when last checked.
+                -- (See: feoblog linkcheck)
+                user_id BLOB NOT NULL
+                , signature BLOB NOT NULL
+                , url TEXT NOT NULL
+                , error TEXT NOT NULL
+                , checked_utc_ms INTEGER NOT NULL
+                , PRIMARY KEY (user_id, signature, url)
+            )
+        ")?;
+
+        Ok(())
+    }
+
+    fn migrate_35_to_36(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE deleted_item(
+                -- Items that their authors deleted. (See: Delete)
+                -- Kept after the item is gone, so that we won't accept it again.
+                user_id BLOB NOT NULL
+                , signature BLOB NOT NULL
+                -- The Delete item:
+                , delete_signature BLOB NOT NULL
+                , deleted_utc_ms INTEGER NOT NULL
+                , PRIMARY KEY (user_id, signature)
+            )
+        ")?;
+
+        Ok(())
+    }
+
+    /// Index reactions by the item they're for.
+    fn migrate_36_to_37(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE reaction(
+                -- The Reaction item:
+                user_id BLOB NOT NULL
+                , signature BLOB NOT NULL
+                , unix_utc_ms INTEGER NOT NULL
+
+                -- The item it reacts to:
+                , ref_user_id BLOB NOT NULL
+                , ref_signature BLOB NOT NULL
+
+                , emoji TEXT NOT NULL
+                , PRIMARY KEY (user_id, signature)
+            )
+        ")?;
+        self.run("
+            CREATE INDEX reaction_ref_idx
+            ON reaction(ref_user_id, ref_signature, user_id, unix_utc_ms)
+        ")?;
+
+        Ok(())
+    }
+
+    fn migrate_37_to_38(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE security_report(
+                -- Possible injection attempts. (See: server/reports.rs)
+                created_utc_ms INTEGER NOT NULL
+                , kind TEXT NOT NULL
+                -- The item it's about, if any:
+                , user_id BLOB
+                , signature BLOB
+                , detail TEXT NOT NULL
+            )
+        ")?;
+        self.run("
+            CREATE INDEX security_report_created_idx
+            ON security_report(created_utc_ms)
+        ")?;
+
+        Ok(())
+    }
+
+    fn migrate_38_to_39(&self) -> Result<(), Error>
+    {
+        self.run("
+            -- When the follower's profile first listed this follow. (By the
+            -- profile's timestamp.) For follower growth stats.
+            ALTER TABLE follow ADD COLUMN since_utc_ms INTEGER
+        ")?;
+        // We don't know when existing follows started, so say their current profile:
+        self.run("
+            UPDATE follow
+            SET since_utc_ms = (
+                SELECT i.unix_utc_ms
+                FROM profile AS p
+                INNER JOIN item AS i USING (user_id, signature)
+                WHERE p.user_id = follow.source_user_id
+            )
+        ")?;
+        self.run("
+            CREATE INDEX follow_followed_idx
+            ON follow(followed_user_id, since_utc_ms)
+        ")?;
+
+        Ok(())
+    }
+
+    /// Let item events be pruned after however long the server is configured
+    /// to keep them, instead of a fixed 30 days. (See: Backend::prune)
+    fn migrate_39_to_40(&self) -> Result<(), Error>
+    {
+        self.run("
+            CREATE TABLE item_event_retention(
+                -- How many days item events are kept. (--keep-item-events-days)
+                -- Younger events can't be deleted. 0 = keep them forever.
+                days INTEGER NOT NULL
+            )
+        ")?;
+        self.run("INSERT INTO item_event_retention VALUES(0)")?;
+
+        self.run("DROP TRIGGER item_event_no_delete")?;
+        self.run("
+            CREATE TRIGGER item_event_no_delete
+            BEFORE DELETE ON item_event
+            WHEN (SELECT days FROM item_event_retention) = 0
+            OR OLD.created_utc_ms > (
+                CAST(strftime('%s', 'now') AS INTEGER)
+                - (SELECT days FROM item_event_retention) * 86400
+            ) * 1000
+            BEGIN
+                SELECT RAISE(ABORT, 'item_event is append-only, except for events older than the retention period');
+            END
+        ")?;
+
+        Ok(())
+    }
+
+    /// Saved feeds can be limited to posts with certain tags.
+    fn migrate_40_to_41(&self) -> Result<(), Error>
+    {
+        self.run("
+            ALTER TABLE saved_feed
+            -- Comma-separated, normalized tags. (See: tags.rs) Empty = any post.
+            ADD COLUMN tags TEXT NOT NULL DEFAULT ''
+        ")?;
+
+        Ok(())
+    }
+
+    /// Stop serving an item, and log why. (`kind`)
+    fn hide_item(&self, user: &UserID, signature: &Signature, kind: ItemEventKind) -> Result<bool, Error> {
+        let tx = self.conn.unchecked_transaction()?;
+        let updated = tx.execute("
+            UPDATE item
+            SET removed_utc_ms = ?
+            WHERE user_id = ?
+            AND signature = ?
+            AND removed_utc_ms IS NULL
+        ", params![
+            Timestamp::now().unix_utc_ms,
+            user.bytes(),
+            signature.bytes(),
+        ])?;
+
+        if updated > 0 {
+            update_summary(&tx, user, signature, -1)?;
+            log_item_event(&tx, user, signature, kind, Timestamp::now())?;
+        }
+        tx.commit()?;
+
+        Ok(updated > 0)
+    }
+
+    /// Would saving `item` exceed a server user's quota?
+    fn check_quota(&self, user: &UserID, bytes: &[u8], item: &Item, quota: &Quota) -> Result<Option<QuotaDenyReason>, Error> {
+        if quota.max_items_per_day > 0 {
+            let day_ago = Timestamp::now().unix_utc_ms - 24 * 60 * 60 * 1000;
+            let items: i64 = self.conn.query_row("
+                SELECT COUNT(*)
+                FROM item
+                WHERE user_id = ?
+                AND received_utc_ms > ?
+            ", params![user.bytes(), day_ago], |row| row.get(0))?;
+            let items = items as u64;
+            if items >= quota.max_items_per_day {
+                return Ok(Some(QuotaDenyReason::MaxItemsPerDay{ max_items: quota.max_items_per_day, items }));
+            }
+        }
+
+        if quota.max_bytes > 0 {
+            let used_bytes: i64 = self.conn.query_row_named("
+                SELECT
+                    COALESCE((
+                        SELECT SUM(length(bytes))
+                        FROM item
+                        WHERE user_id = :user_id
+                        AND removed_utc_ms IS NULL
+                    ), 0)
+                    + COALESCE((
+                        SELECT SUM(length(bytes))
+                        FROM attachment
+                        WHERE user_id = :user_id
+                    ), 0)
+            ", &[(":user_id", &user.bytes())], |row| row.get(0))?;
+            let used_bytes = used_bytes as u64;
+
+            // Attachments are uploaded after the item, so count them now, while we can still say no:
+            let attachment_bytes: u64 = item.get_attachments().get_file().iter().map(|file| file.get_size()).sum();
+            let item_bytes = bytes.len() as u64 + attachment_bytes;
+            if used_bytes + item_bytes > quota.max_bytes {
+                return Ok(Some(QuotaDenyReason::MaxBytes{ max_bytes: quota.max_bytes, used_bytes, item_bytes }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Find an item, optionally including followers-only ones.
+    fn find_item(&self, user: &UserID, signature: &Signature, followers_only: bool) -> Result<Option<ItemRow>, Error> {
+        let mut stmt = self.conn.prepare(FIND_ITEM_SQL)?;
+
+        let mut rows = stmt.query(params![
+            user.bytes(),
+            signature.bytes(),
+            followers_only,
+        ])?;
+
+        let row = match rows.next()? {
+            None => return Ok(None),
+            Some(row) => row,
+        };
+
+        let item = ItemRow{
+            user: UserID::from_vec(row.get(0)?)?,
+            signature: Signature::from_vec(row.get(1)?)?,
+            timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+            received: Timestamp{ unix_utc_ms: row.get(3)? },
+            item_bytes: row.get(4)?,
+        };
+
+        if rows.next()?.is_some() {
+            bail!("Found multiple matching rows!? (user_id,signature) should be unique!");
+        }
+
+        Ok(Some(item))
+    }
+
+    /// Find an attachment, optionally including followers-only items' ones.
+    fn find_attachment(&self, user: &UserID, signature: &Signature, name: &str, followers_only: bool) -> Result<Option<Vec<u8>>, Error> {
+        let bytes = self.conn.query_row("
+            SELECT a.bytes
+            FROM attachment AS a
+            INNER JOIN item AS i USING (user_id, signature)
+            WHERE a.user_id = ? AND a.signature = ? AND a.name = ?
+            AND i.removed_utc_ms IS NULL
+            AND (i.followers_only = 0 OR ?)
+        ", params![user.bytes(), signature.bytes(), name, followers_only], |row| row.get(0)).optional()?;
+
+        Ok(bytes)
+    }
+
+    /// May `viewer` see `user`'s followers-only items?
+    fn may_view(&self, user: &UserID, viewer: &UserID) -> Result<bool, Error> {
+        Ok(viewer.bytes() == user.bytes() || backend::Backend::follows(self, viewer, user)?)
+    }
+
+    fn saved_feed_authors(&self, user: &UserID, name: &str) -> Result<Vec<UserID>, Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT author_id
+            FROM saved_feed_author
+            WHERE user_id = ? AND name = ?
+        ")?;
+        let mut rows = stmt.query(params![user.bytes(), name])?;
+        let mut authors = vec![];
+        while let Some(row) = rows.next()? {
+            authors.push(UserID::from_vec(row.get(0)?)?);
+        }
+        Ok(authors)
+    }
+
+    fn save_user_item_once(&mut self, row: &ItemRow, item: &Item) -> Result<(), Error>
+    {
+        let tx = self.conn.savepoint().context("getting a transaction")?;
+
+        if is_deleted(&tx, &row.user, &row.signature)? {
+            bail!("Item {} was deleted by its author", row.signature.to_base58());
+        }
+
+        let stmt = "
+            INSERT INTO item (
+                user_id
+                , signature
+                , unix_utc_ms
+                , received_utc_ms
+                , bytes
+                , expires_utc_ms
+                , followers_only
+            ) VALUES (?, ?, ?, ?, ?, ?, ?);
+       ";
+
+        let expires = if item.expires_ms_utc == 0 { None } else { Some(item.expires_ms_utc) };
+        let followers_only = item.get_visibility() == Visibility::VISIBILITY_FOLLOWERS;
+        tx.execute(stmt, params![
+            row.user.bytes(),
+            row.signature.bytes(),
+            row.timestamp.unix_utc_ms,
+            row.received.unix_utc_ms,
+            row.item_bytes.as_slice(),
+            expires,
+            followers_only,
+        ])?;
+
+        if item.has_profile() {
+            update_profile(&tx, row, item)?;
+        }
+
+        if item.has_vote() {
+            save_vote(&tx, row, item)?;
+        }
+        if item.has_reaction() {
+            save_reaction(&tx, row, item)?;
+        }
+        if item.has_delete() {
+            apply_delete(&tx, row, item)?;
+        }
+        save_references(&tx, row, item)?;
+
+        if item.has_post() {
+            index_post(&tx, &row.user, &row.signature, item)?;
+        }
+
+        update_summary(&tx, &row.user, &row.signature, 1)?;
+        log_item_event(&tx, &row.user, &row.signature, ItemEventKind::Received, row.received)?;
+        add_daily_stats(&tx, &DailyStats {
+            day: row.received.unix_utc_days(),
+            items: 1,
+            item_bytes: row.item_bytes.len() as u64,
+            ..Default::default()
+        })?;
+
+        tx.commit().context("committing")?;
+        Ok(())
+    }
+
+    fn run(&self, sql: &str) -> Result<(), Error>
+    {
+        self.conn.execute(sql, params![])?;
+        Ok(())
+    }
+
+    fn get_version(&self) -> Result<Option<u32>, Error>
+    {
+        let table_count: u32  = self.conn.prepare(
+            "SELECT count()
+            FROM sqlite_master
+            WHERE type = 'table'
+            AND name = 'version'
+            "
+        )?.query_row(
+            params![],
+            |row|  Ok(row.get(0)?)
+        )?;
+
+        if table_count == 0 {
+            return Ok(None);
+        }
+
+        let  version = self.conn.prepare(
+            "SELECT MAX(version) from version"
+        )?.query_row(
+            params![],
+            |row| Ok(row.get(0)?)
+        )?;
+
+        Ok(version)
+    }
+
+}
+
+fn save_vote(conn: &rusqlite::Connection, row: &ItemRow, item: &Item) -> Result<(), Error> {
+    let vote = item.get_vote();
+    conn.execute("
+        INSERT INTO poll_vote(
+            poll_user_id
+            , poll_signature
+            , voter_id
+            , vote_signature
+            , unix_utc_ms
+            , option
+        ) VALUES (?, ?, ?, ?, ?, ?)
+    ", params![
+        vote.get_poll().get_user_id().get_bytes(),
+        vote.get_poll().get_signature().get_bytes(),
+        row.user.bytes(),
+        row.signature.bytes(),
+        row.timestamp.unix_utc_ms,
+        vote.option,
+    ])?;
+    Ok(())
+}
+
+fn save_reaction(conn: &rusqlite::Connection, row: &ItemRow, item: &Item) -> Result<(), Error> {
+    let reaction = item.get_reaction();
+    conn.execute("
+        INSERT INTO reaction(user_id, signature, unix_utc_ms, ref_user_id, ref_signature, emoji)
+        VALUES (?, ?, ?, ?, ?, ?)
+    ", params![
+        row.user.bytes(),
+        row.signature.bytes(),
+        row.timestamp.unix_utc_ms,
+        reaction.get_item().get_user_id().get_bytes(),
+        reaction.get_item().get_signature().get_bytes(),
+        reaction.get_emoji(),
+    ])?;
+    Ok(())
+}
+
+/// Index the items that `item` refers to.
+/// (Reactions are indexed separately, so they don't show up as replies.)
+fn save_references(conn: &rusqlite::Connection, row: &ItemRow, item: &Item) -> Result<(), Error> {
+    let mut refs = vec![];
+    if item.has_vote() {
+        refs.push(item.get_vote().get_poll());
+    }
+    if item.has_comment() {
+        refs.push(item.get_comment().get_reply_to());
+    }
+
+    for item_ref in refs {
+        conn.execute("
+            INSERT INTO item_reference(user_id, signature, unix_utc_ms, ref_user_id, ref_signature)
+            VALUES (?, ?, ?, ?, ?)
+        ", params![
+            row.user.bytes(),
+            row.signature.bytes(),
+            row.timestamp.unix_utc_ms,
+            item_ref.get_user_id().get_bytes(),
+            item_ref.get_signature().get_bytes(),
+        ])?;
+    }
+    Ok(())
+}
+
+fn index_post(conn: &rusqlite::Connection, user: &UserID, signature: &Signature, item: &Item) -> Result<(), Error> {
+    let post = item.get_post();
+    conn.execute("
+        INSERT INTO post_search(title, body, user_id, signature)
+        VALUES (?, ?, ?, ?)
+    ", params![
+        post.get_title(),
+        post.get_body(),
+        user.bytes(),
+        signature.bytes(),
+    ])?;
+    Ok(())
+}
+
+/// Turn a visitor's search into an FTS5 query that matches posts containing
+/// all of its words. Returns None if there are no words to search for.
+///
+/// Each word is quoted, so FTS5 operators (`OR`, `NEAR`, `*`, etc.) are
+/// searched for literally instead of causing syntax errors.
+fn fts_query(search: &str) -> Option<String> {
+    let words: Vec<String> = search.split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect();
+    if words.is_empty() { None } else { Some(words.join(" ")) }
+}
+
+fn parse_item_types(value: &str) -> Result<Vec<ItemType>, Error> {
+    value.split(',')
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            let v: i32 = v.parse()?;
+            ItemType::from_i32(v).ok_or_else(|| format_err!("Unknown item type: {}", v))
+        })
+        .collect()
+}
+
+fn read_queued_comments(mut rows: rusqlite::Rows, cb: FnIter<QueuedComment>) -> Result<(), Error> {
+    while let Some(row) = rows.next()? {
+        let comment = QueuedComment {
+            id: row.get(0)?,
+            user: UserID::from_vec(row.get(1)?)?,
+            signature: Signature::from_vec(row.get(2)?)?,
+            name: row.get(3)?,
+            text: row.get(4)?,
+            created: Timestamp{ unix_utc_ms: row.get(5)? },
+        };
+        if !cb(comment)? { break; }
+    }
+    Ok(())
+}
+
+fn parse_tags(value: &str) -> Vec<String> {
+    value.split(',')
+        .filter(|v| !v.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn is_deleted(conn: &rusqlite::Connection, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+    let count: u32 = conn.query_row(
+        "SELECT COUNT(*) FROM deleted_item WHERE user_id = ? AND signature = ?",
+        params![user.bytes(), signature.bytes()],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+/// Delete the item that a Delete item refers to, and leave a tombstone in its place.
+fn apply_delete(conn: &rusqlite::Connection, row: &ItemRow, item: &Item) -> Result<(), Error> {
+    let signature = Signature::from_vec(item.get_delete().get_signature().get_bytes().into())?;
+    let user = &row.user;
+
+    let target: Option<(Vec<u8>, bool)> = conn.query_row(
+        "SELECT bytes, removed_utc_ms IS NOT NULL FROM item WHERE user_id = ? AND signature = ?",
+        params![user.bytes(), signature.bytes()],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional()?;
+
+    if let Some((bytes, removed)) = target {
+        let mut target = Item::new();
+        target.merge_from_bytes(&bytes)?;
+        if target.has_profile() {
+            bail!("Profiles can't be deleted. Post a new one instead.");
+        }
+        if target.has_delete() {
+            bail!("Deletions can't be deleted.");
+        }
+
+        if !removed {
+            update_summary(conn, user, &signature, -1)?;
+        }
+        conn.execute(
+            "DELETE FROM poll_vote WHERE voter_id = ? AND vote_signature = ?",
+            params![user.bytes(), signature.bytes()],
+        )?;
+        delete_item_rows(conn, user, &signature)?;
+        log_item_event(conn, user, &signature, ItemEventKind::Deleted, row.received)?;
+    }
+
+    // Even if we haven't seen the item yet, so that we won't accept it later.
+    // (ex: a Delete that arrives from one peer before the item does from
+    // another.) This can't be used to fill our database: each tombstone is for
+    // the Delete's own author, and costs them a stored Delete item, which
+    // counts against their quota like any other.
+    conn.execute("
+        INSERT OR IGNORE INTO deleted_item(user_id, signature, delete_signature, deleted_utc_ms)
+        VALUES (?, ?, ?, ?)
+    ", params![
+        user.bytes(),
+        signature.bytes(),
+        row.signature.bytes(),
+        row.received.unix_utc_ms,
+    ])?;
+
+    Ok(())
+}
+
+/// Delete an item and everything we've stored about it.
+fn delete_item_rows(conn: &rusqlite::Connection, user: &UserID, signature: &Signature) -> Result<(), Error> {
+    for table in &["attachment", "ipfs_cid", "post_search", "activitypub_reply", "dead_link", "item_reference", "reaction", "item"] {
+        conn.execute(
+            &format!("DELETE FROM {} WHERE user_id = ? AND signature = ?", table),
+            params![user.bytes(), signature.bytes()],
+        )?;
+    }
+    Ok(())
+}
+
+fn log_item_event(conn: &rusqlite::Connection, user: &UserID, signature: &Signature, kind: ItemEventKind, created: Timestamp) -> Result<(), Error> {
+    conn.execute("
+        INSERT INTO item_event(user_id, signature, event, created_utc_ms)
+        VALUES (?, ?, ?, ?)
+    ", params![
+        user.bytes(),
+        signature.bytes(),
+        kind.as_str(),
+        created.unix_utc_ms,
+    ])?;
+    Ok(())
+}
+
+/// Add `stats` to the counts for its day.
+fn add_daily_stats(conn: &rusqlite::Connection, stats: &DailyStats) -> Result<(), Error> {
+    conn.execute("
+        INSERT INTO daily_stats(day, items, item_bytes, attachments, attachment_bytes, rejections)
+        VALUES (?, ?, ?, ?, ?, ?)
+        ON CONFLICT (day) DO UPDATE SET
+            items = items + excluded.items
+            , item_bytes = item_bytes + excluded.item_bytes
+            , attachments = attachments + excluded.attachments
+            , attachment_bytes = attachment_bytes + excluded.attachment_bytes
+            , rejections = rejections + excluded.rejections
+    ", params![
+        stats.day,
+        stats.items as i64,
+        stats.item_bytes as i64,
+        stats.attachments as i64,
+        stats.attachment_bytes as i64,
+        stats.rejections as i64,
+    ])?;
+    Ok(())
+}
+
+/// Add (delta = 1) or remove (delta = -1) an item from a user's summary.
+fn update_summary(conn: &rusqlite::Connection, user: &UserID, signature: &Signature, delta: i64) -> Result<(), Error> {
+    let (count, mut digest): (i64, Vec<u8>) = conn.query_row(
+        "SELECT item_count, digest FROM user_summary WHERE user_id = ?",
+        params![user.bytes()],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    ).optional()?.unwrap_or_else(|| (0, vec![0; sha256::DIGESTBYTES]));
+
+    // XOR is its own inverse, so adding and removing are the same operation:
+    let hash = sha256::hash(signature.bytes());
+    for (d, h) in digest.iter_mut().zip(hash.as_ref()) {
+        *d ^= h;
+    }
+
+    conn.execute("
+        INSERT OR REPLACE INTO user_summary(user_id, item_count, digest)
+        VALUES (?, ?, ?)
+    ", params![
+        user.bytes(),
+        count + delta,
+        digest,
+    ])?;
+
+    Ok(())
+}
+
+/// We're saving a profile. If it's new, update the profile and follow tables.
+fn update_profile(conn: &rusqlite::Savepoint, item_row: &ItemRow, item: &Item) -> Result<(), Error> {
+
+    let prev_timestamp: Option<i64> =  
+        conn.prepare("
+            SELECT i.unix_utc_ms
+            FROM profile AS p
+            INNER JOIN item AS i USING (user_id, signature)
+            WHERE user_id = ?
+        ")?
+        .query(params![ item_row.user.bytes() ])?
+        .next()?
+        .map(|row| row.get(0))
+        .transpose()?
+    ;
+
+    // Never replace a newer profile's metadata:
+    if let Some(previous) = prev_timestamp {
+        if previous >= item.timestamp_ms_utc {
+            return Ok(())
+        }
+    }
+
+    // Keep when continuing follows started:
+    let mut since: HashMap<Vec<u8>, i64> = HashMap::new();
+    {
+        let mut stmt = conn.prepare("
+            SELECT followed_user_id, since_utc_ms
+            FROM follow
+            WHERE source_user_id = ?
+        ")?;
+        let mut rows = stmt.query(params![item_row.user.bytes()])?;
+        while let Some(row) = rows.next()? {
+            let followed: Vec<u8> = row.get(0)?;
+            let followed_since: Option<i64> = row.get(1)?;
+            if let Some(followed_since) = followed_since {
+                since.insert(followed, followed_since);
+            }
+        }
+    }
+
+    // Replace all follows with new ones listed in the profile:
+    conn.execute("DELETE FROM follow WHERE source_user_id = ?", params![item_row.user.bytes()])?;
+
+    // Behavior is undefined if duplicate follows exist in a Profile. So we just replace:
+    let mut add_follow = conn.prepare("
+        INSERT OR REPLACE INTO follow (source_user_id, followed_user_id, display_name, since_utc_ms)
+        VALUES (?, ?, ?, ?)
+    ")?;
+
+    for follow in item.get_profile().get_follows() {
+        let followed = follow.get_user().get_bytes();
+        add_follow.execute(params![
+            item_row.user.bytes(),
+            followed,
+            follow.get_display_name(),
+            since.get(followed).copied().unwrap_or(item.timestamp_ms_utc),
+        ])?;
+    }
+
+    let mut add_profile = conn.prepare("
+        INSERT OR REPLACE INTO profile(user_id, signature, display_name)
+        VALUES (?,?,?)
+    ")?;
+    add_profile.execute(params![
+        item_row.user.bytes(),
+        item_row.signature.bytes(),
+        item.get_profile().get_display_name()
+    ])?;
+
+    Ok(())
+}
+
+impl backend::Backend for Connection
+{
+
+    fn setup(&self) -> Result<(), Error>
+    {
+        let mut version = match self.get_version()? {
+            None => {
+                // TODO: This shouldn't be automatic, should force user to
+                // explicitly create a new data store.
+                self.setup_new()?;
+                3
+            },
+            Some(version) => version
+        };
+        if version > CURRENT_VERSION {
+            bail!(
+                "DB version ({}) newer than current version ({})",
+                version,
+                CURRENT_VERSION
+            );
+        }
+
+        while version < CURRENT_VERSION {
+            self.migrate(version).with_context(|_| {
+                format!("Error migrating DB from version {}", version)
+            })?;
+            version += 1;
+        }
+
+        Ok(())
+    }
+
+    fn checkpoint(&self) -> Result<(), Error> {
+        // TRUNCATE also empties the WAL file, so the .sqlite3 file can be copied alone.
+        self.conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", NO_PARAMS, |_| Ok(()))?;
+        Ok(())
+    }
+
+    fn backup(&self, dest: &std::path::Path) -> Result<(), Error> {
+        use rusqlite::backup::{Backup, StepResult};
+
+        let mut dest = rusqlite::Connection::open(dest)?;
+        let backup = Backup::new(&self.conn, &mut dest)?;
+        // All at once, in one read transaction. With WAL, that doesn't block
+        // writers, and they can't make the backup start over:
+        match backup.step(-1)? {
+            StepResult::Done => Ok(()),
+            result => bail!("Backup didn't finish: {:?}", result),
+        }
+    }
+
+    fn homepage_items<'a>(
+        &self,
+        before: Timestamp,
+        callback: &'a mut dyn FnMut(ItemDisplayRow) -> Result<bool,Error>
+    ) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare(HOMEPAGE_ITEMS_SQL)?;
+
+        let mut rows = stmt.query(params![
+            before.unix_utc_ms,
+        ])?;
+
+        let to_item_profile_row = |row: &Row<'_>| -> Result<ItemDisplayRow, Error> {
+
+            let item = ItemRow{
+                user: UserID::from_vec(row.get(0)?)?,
+                signature: Signature::from_vec(row.get(1)?)?,
+                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+                received: Timestamp{ unix_utc_ms: row.get(3)? },
+                item_bytes: row.get(4)?,
+            };
+
+            Ok(ItemDisplayRow{
+                item,
+                display_name: row.get(5)?
+            })
+        };
+
+        while let Some(row) = rows.next()? {
+            let item = to_item_profile_row(row)?;
+            let result = callback(item)?;
+            if !result { break; }
+        }
+
+        Ok( () )
+    }
+
+    fn user_items<'a>(
+        &self,
+        user: &UserID,
+        before: Timestamp,
+        callback: &'a mut dyn FnMut(ItemRow) -> Result<bool,Error>
+    ) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare(USER_ITEMS_SQL)?;
+
+        let mut rows = stmt.query(params![
+            before.unix_utc_ms,
+            user.bytes(),
+        ])?;
+
+        let convert = |row: &Row<'_>| -> Result<ItemRow, Error> {
+            let item = ItemRow{
+                user: UserID::from_vec(row.get(0)?)?,
+                signature: Signature::from_vec(row.get(1)?)?,
+                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+                received: Timestamp{ unix_utc_ms: row.get(3)? },
+                item_bytes: row.get(4)?,
+            };
+
+            Ok(item)
+        };
+
+        while let Some(row) = rows.next()? {
+            let item = convert(row)?;
+            let result = callback(item)?;
+            if !result { break; }
+        }
+
+        Ok( () )
+    }
+
+    fn user_feed_items<'a>(
+        &self,
+        user_id: &UserID,
+        viewer: Option<&UserID>,
+        before: Timestamp,
+        callback: &'a mut dyn FnMut(ItemDisplayRow) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT
+                user_id
+                , i.signature
+                , unix_utc_ms
+                , received_utc_ms
+                , bytes
+                , p.display_name
+                , f.display_name AS follow_display_name
+            FROM item AS i
+            LEFT OUTER JOIN profile AS p USING (user_id)
+            LEFT OUTER JOIN follow AS f ON (
+                i.user_id = f.followed_user_id
+                AND f.source_user_id = :user_id
+            )
+            WHERE unix_utc_ms < :timestamp
+            AND removed_utc_ms IS NULL
+            AND followers_only = 0
+            AND (
+                user_id IN (
+                    SELECT followed_user_id
+                    FROM follow
+                    WHERE source_user_id = :user_id
+                )
+                OR user_id = :user_id
+            )
+            AND user_id NOT IN (
+                SELECT muted_user_id
+                FROM user_mute
+                WHERE user_id = :viewer
+            )
+            ORDER BY unix_utc_ms DESC
+        ")?;
+
+        let mut rows = stmt.query_named(&[
+            (":timestamp", &before.unix_utc_ms),
+            (":user_id", &user_id.bytes()),
+            // NULL if nobody's signed in, which matches no mutes:
+            (":viewer", &viewer.map(UserID::bytes)),
+        ])?;
+
+        let to_item_profile_row = |row: &Row<'_>| -> Result<ItemDisplayRow, Error> {
+
+            let item = ItemRow{
+                user: UserID::from_vec(row.get(0)?)?,
+                signature: Signature::from_vec(row.get(1)?)?,
+                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+                received: Timestamp{ unix_utc_ms: row.get(3)? },
+                item_bytes: row.get(4)?,
+            };
+
+            let display_name: Option<String> = row.get(5)?;
+            let follow_display_name: Option<String> = row.get(6)?;
+            fn not_empty(it: &String) -> bool { !it.trim().is_empty() }
+
+            Ok(ItemDisplayRow{
+                item,
+                // Prefer displaying the name that this user has assigned to the follow.
+                // TODO: This seems maybe business-logic-y? Should we move it out of Backend?
+                display_name: follow_display_name.filter(not_empty).or(display_name).filter(not_empty),
+            })
+        };
+
+        while let Some(row) = rows.next()? {
+            let item = to_item_profile_row(row)?;
+            let result = callback(item)?;
             if !result { break; }
         }
 
-        Ok( () )
+        Ok( () )
+    }
+
+    fn server_user(&self, user: &UserID)
+    -> Result<Option<backend::ServerUser>, Error> 
+    { 
+        let mut stmt = self.conn.prepare("
+            SELECT notes, on_homepage
+            FROM server_user
+            WHERE user_id = ?
+        ")?;
+
+        let to_server_user = |row: &Row<'_>| {
+            let on_homepage: isize = row.get(1)?;
+             Ok(
+                 ServerUser {
+                    user: user.clone(),
+                    notes: row.get(0)?,
+                    on_homepage: on_homepage != 0,
+                }
+            )
+        };
+
+        let item = stmt.query_row(
+            params![user.bytes()],
+            to_server_user,
+        ).optional()?;
+
+        Ok(item)
+
+    }
+
+    fn server_users<'a>(&self, cb: FnIter<'a, ServerUser>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT 
+                user_id
+                , notes
+                , on_homepage
+            FROM server_user
+            ORDER BY on_homepage, user_id
+        ")?;
+
+        let mut rows = stmt.query(NO_PARAMS)?;
+
+        while let Some(row) = rows.next()? {
+            let on_homepage: isize = row.get(2)?;
+            let on_homepage = on_homepage != 0;
+
+            let user = ServerUser {
+                user: UserID::from_vec(row.get(0)?).compat()?,
+                notes: row.get(1)?,
+                on_homepage,
+            };
+            let more = cb(user)?;
+            if !more {break;}
+        }
+
+        Ok(())
+    }
+    
+    
+    fn user_item_exists(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> { 
+        let mut stmt = self.conn.prepare("
+            SELECT COUNT(*)
+            FROM item
+            WHERE user_id = ?
+            AND signature = ?
+        ")?;
+
+        let count: u32 = stmt.query_row(
+            params![
+                user.bytes(),
+                signature.bytes(),
+            ],
+            |row| { Ok(row.get(0)?) }
+        )?;
+
+        if count > 1 {
+            bail!("Found {} matches!? (user_id,signature) should be unique!", count);
+        }
+
+        Ok(count > 0)
+    }
+
+    fn user_item_deleted(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        is_deleted(&self.conn, user, signature)
+    }
+
+    fn user_item(&self, user: &UserID, signature: &Signature) -> Result<Option<ItemRow>, Error> { 
+        self.find_item(user, signature, false)
+    }
+
+    fn followers_only_item(&self, user: &UserID, signature: &Signature, viewer: &UserID) -> Result<Option<ItemRow>, Error> {
+        let may_view = self.may_view(user, viewer)?;
+        self.find_item(user, signature, may_view)
+    }
+
+    fn followers_only_items<'a>(&self, user: &UserID, viewer: &UserID, before: Timestamp, cb: FnIter<'a, ItemRow>) -> Result<(), Error> {
+        if !self.may_view(user, viewer)? {
+            return Ok(());
+        }
+
+        let mut stmt = self.conn.prepare("
+            SELECT
+                user_id
+                , signature
+                , unix_utc_ms
+                , received_utc_ms
+                , bytes
+            FROM item
+            WHERE user_id = ?
+            AND unix_utc_ms < ?
+            AND followers_only = 1
+            AND removed_utc_ms IS NULL
+            ORDER BY unix_utc_ms DESC
+        ")?;
+        let mut rows = stmt.query(params![user.bytes(), before.unix_utc_ms])?;
+        while let Some(row) = rows.next()? {
+            let item = ItemRow{
+                user: UserID::from_vec(row.get(0)?)?,
+                signature: Signature::from_vec(row.get(1)?)?,
+                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+                received: Timestamp{ unix_utc_ms: row.get(3)? },
+                item_bytes: row.get(4)?,
+            };
+            if !cb(item)? { break; }
+        }
+        Ok(())
+    }
+
+    fn follows(&self, follower: &UserID, followed: &UserID) -> Result<bool, Error> {
+        let follows: bool = self.conn.query_row("
+            SELECT EXISTS(
+                SELECT 1
+                FROM follow
+                WHERE source_user_id = ?
+                AND followed_user_id = ?
+            )
+        ", params![follower.bytes(), followed.bytes()], |row| row.get(0))?;
+        Ok(follows)
+    }
+
+    fn save_user_item(&mut self, row: &ItemRow, item: &Item) -> Result<(), Error>
+    {
+        // busy_timeout already waits for locks, but SQLite may still return
+        // SQLITE_BUSY early if waiting could deadlock. Those are safe to retry:
+        let mut attempt = 0;
+        loop {
+            match self.save_user_item_once(row, item) {
+                Err(err) if is_busy(&err) && attempt < BUSY_RETRIES => attempt += 1,
+                result => return result,
+            }
+        }
+    }
+
+    fn add_server_user(&self, server_user: &ServerUser) -> Result<(), Error> {
+
+        let stmt = "
+            INSERT INTO server_user(user_id, notes, on_homepage)
+            VALUES (?,?,?)
+        ";
+
+        let on_homepage = if server_user.on_homepage { 1 } else { 0 };
+
+        self.conn.execute(stmt, params![
+            server_user.user.bytes(),
+            server_user.notes.as_str(),
+            on_homepage
+        ])?;
+
+        Ok(())
+    }
+
+    fn user_quota(&self, user: &UserID) -> Result<Option<Quota>, Error> {
+        let quota = self.conn.query_row("
+            SELECT max_bytes, max_items_per_day
+            FROM server_user
+            WHERE user_id = ?
+        ", params![user.bytes()], |row| {
+            let max_bytes: Option<i64> = row.get(0)?;
+            let max_items_per_day: Option<i64> = row.get(1)?;
+            Ok(Quota {
+                max_bytes: max_bytes.unwrap_or(0) as u64,
+                max_items_per_day: max_items_per_day.unwrap_or(0) as u64,
+            })
+        }).optional()?;
+        Ok(quota)
+    }
+
+    fn set_user_quota(&self, user: &UserID, quota: &Quota) -> Result<bool, Error> {
+        let updated = self.conn.execute("
+            UPDATE server_user
+            SET max_bytes = ?, max_items_per_day = ?
+            WHERE user_id = ?
+        ", params![
+            quota.max_bytes as i64,
+            quota.max_items_per_day as i64,
+            user.bytes(),
+        ])?;
+        Ok(updated > 0)
+    }
+
+    fn remove_server_user(&self, user: &UserID) -> Result<bool, Error> {
+        let deleted = self.conn.execute(
+            "DELETE FROM server_user WHERE user_id = ?",
+            params![user.bytes()],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    fn user_profile(&self, user: &UserID) -> Result<Option<ItemRow>, Error> {
+
+        // TODO: I'm not crazy about making 2 queries here instead of a join, but it lets me
+        // re-use the user_item() loading logic.
+        let mut find_profile = self.conn.prepare("
+            SELECT user_id, signature
+            FROM profile
+            WHERE user_id = ?
+        ")?;
+
+        let mut rows = find_profile.query(params![user.bytes()])?;
+        let row = match rows.next()? {
+            None => return Ok(None),
+            Some(row) => row,
+        };
+
+        let user_id: Vec<u8> = row.get(0)?;
+        let signature: Vec<u8> = row.get(1)?;
+
+        let user_id = UserID::from_vec(user_id)?;
+        let signature = Signature::from_vec(signature)?;
+
+        self.user_item(&user_id, &signature)
+    }
+
+    fn user_known(&self, user_id: &UserID) -> Result<bool, Error> {
+        let mut query = self.conn.prepare("
+            SELECT
+                EXISTS(SELECT user_id FROM server_user WHERE user_id = :user_id)
+                OR EXISTS(
+                    SELECT followed_user_id
+                    FROM follow AS f
+                    INNER JOIN server_user AS su ON (f.source_user_id = su.user_id)
+                    WHERE followed_user_id = :user_id
+                )
+        ")?;
+
+        let mut result = query.query_named(&[
+            (":user_id", &user_id.bytes())
+        ])?;
+
+        let row = match result.next()? {
+            Some(row) => row,
+            None => bail!("Expected at least 1 row from SQLite."),
+        };
+
+        Ok(row.get(0)?)
+    }
+
+    fn remove_user_item(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        self.hide_item(user, signature, ItemEventKind::Removed)
+    }
+
+    fn restore_user_item(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        let tx = self.conn.unchecked_transaction()?;
+        // Expired items stay expired:
+        let updated = tx.execute("
+            UPDATE item
+            SET removed_utc_ms = NULL
+            WHERE user_id = ?
+            AND signature = ?
+            AND removed_utc_ms IS NOT NULL
+            AND (expires_utc_ms IS NULL OR expires_utc_ms > ?)
+        ", params![
+            user.bytes(),
+            signature.bytes(),
+            Timestamp::now().unix_utc_ms,
+        ])?;
+
+        if updated > 0 {
+            update_summary(&tx, user, signature, 1)?;
+            log_item_event(&tx, user, signature, ItemEventKind::Restored, Timestamp::now())?;
+        }
+        tx.commit()?;
+
+        Ok(updated > 0)
+    }
+
+    fn quarantine_user_item(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        self.hide_item(user, signature, ItemEventKind::Quarantined)
+    }
+
+    fn approve_user_item(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        let tx = self.conn.unchecked_transaction()?;
+        let updated = tx.execute(&format!("
+            UPDATE item
+            SET removed_utc_ms = NULL
+            WHERE user_id = ?
+            AND signature = ?
+            AND removed_utc_ms IS NOT NULL
+            AND (expires_utc_ms IS NULL OR expires_utc_ms > ?)
+            AND ({}) = 'quarantined'
+        ", LATEST_ITEM_EVENT_SQL), params![
+            user.bytes(),
+            signature.bytes(),
+            Timestamp::now().unix_utc_ms,
+        ])?;
+
+        if updated > 0 {
+            update_summary(&tx, user, signature, 1)?;
+            log_item_event(&tx, user, signature, ItemEventKind::Approved, Timestamp::now())?;
+        }
+        tx.commit()?;
+
+        Ok(updated > 0)
+    }
+
+    fn quarantined_items<'a>(&self, cb: FnIter<'a, ItemRow>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare(&format!("
+            SELECT user_id, signature, unix_utc_ms, received_utc_ms, bytes
+            FROM item
+            WHERE removed_utc_ms IS NOT NULL
+            AND ({}) = 'quarantined'
+            ORDER BY received_utc_ms
+        ", LATEST_ITEM_EVENT_SQL))?;
+
+        let mut rows = stmt.query(NO_PARAMS)?;
+        while let Some(row) = rows.next()? {
+            let item_row = ItemRow {
+                user: UserID::from_vec(row.get(0)?)?,
+                signature: Signature::from_vec(row.get(1)?)?,
+                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+                received: Timestamp{ unix_utc_ms: row.get(3)? },
+                item_bytes: row.get(4)?,
+            };
+            if !cb(item_row)? { break; }
+        }
+
+        Ok(())
+    }
+
+    fn record_item_synced(&self, user: &UserID, signature: &Signature, when: Timestamp) -> Result<(), Error> {
+        log_item_event(&self.conn, user, signature, ItemEventKind::Synced, when)
+    }
+
+    fn reindex(&mut self) -> Result<ReindexReport, Error> {
+        let tx = self.conn.savepoint()?;
+        for table in &["profile", "follow", "poll_vote", "reaction", "item_reference", "post_search", "user_summary"] {
+            tx.execute(&format!("DELETE FROM {}", table), NO_PARAMS)?;
+        }
+
+        // What we should end up with:
+        let mut items = 0;
+        let mut votes = 0;
+        let mut reactions = 0;
+        let mut references = 0;
+        let mut posts = 0;
+        let mut live_items = 0;
+        let mut profile_users = std::collections::HashSet::new();
+        let mut live_users = std::collections::HashSet::new();
+
+        {
+            // Removed items keep their derived rows (queries filter them out),
+            // but aren't counted in summaries. Same as when they're removed.
+            let mut stmt = tx.prepare("
+                SELECT user_id, signature, unix_utc_ms, received_utc_ms, bytes, removed_utc_ms IS NOT NULL
+                FROM item
+                ORDER BY unix_utc_ms
+            ")?;
+            let mut rows = stmt.query(NO_PARAMS)?;
+            while let Some(row) = rows.next()? {
+                let item_row = ItemRow {
+                    user: UserID::from_vec(row.get(0)?)?,
+                    signature: Signature::from_vec(row.get(1)?)?,
+                    timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+                    received: Timestamp{ unix_utc_ms: row.get(3)? },
+                    item_bytes: row.get(4)?,
+                };
+                let removed: bool = row.get(5)?;
+                let mut item = Item::new();
+                item.merge_from_bytes(&item_row.item_bytes)
+                    .with_context(|_| format!("Error parsing item {}", item_row.signature.to_base58()))?;
+                items += 1;
+
+                if item.has_profile() {
+                    update_profile(&tx, &item_row, &item)?;
+                    profile_users.insert(item_row.user.to_base58());
+                }
+                if item.has_vote() {
+                    save_vote(&tx, &item_row, &item)?;
+                    votes += 1;
+                    references += 1;
+                }
+                if item.has_reaction() {
+                    save_reaction(&tx, &item_row, &item)?;
+                    reactions += 1;
+                }
+                if item.has_comment() {
+                    references += 1;
+                }
+                save_references(&tx, &item_row, &item)?;
+                if item.has_post() {
+                    index_post(&tx, &item_row.user, &item_row.signature, &item)?;
+                    posts += 1;
+                }
+                if !removed {
+                    update_summary(&tx, &item_row.user, &item_row.signature, 1)?;
+                    live_items += 1;
+                    live_users.insert(item_row.user.to_base58());
+                }
+            }
+        }
+
+        let count = |sql: &str| -> Result<u64, Error> {
+            let count: i64 = tx.query_row(sql, NO_PARAMS, |row| row.get(0))?;
+            Ok(count as u64)
+        };
+        let report = ReindexReport {
+            items,
+            profiles: count("SELECT COUNT(*) FROM profile")?,
+            follows: count("SELECT COUNT(*) FROM follow")?,
+            votes: count("SELECT COUNT(*) FROM poll_vote")?,
+            reactions: count("SELECT COUNT(*) FROM reaction")?,
+            references: count("SELECT COUNT(*) FROM item_reference")?,
+            posts: count("SELECT COUNT(*) FROM post_search")?,
+            users: count("SELECT COUNT(*) FROM user_summary")?,
+        };
+        let checks = [
+            ("profiles", report.profiles, profile_users.len() as u64),
+            ("votes", report.votes, votes),
+            ("reactions", report.reactions, reactions),
+            ("references", report.references, references),
+            ("posts", report.posts, posts),
+            ("users", report.users, live_users.len() as u64),
+            ("summarized items", count("SELECT COALESCE(SUM(item_count), 0) FROM user_summary")?, live_items),
+        ];
+        for (name, actual, expected) in checks.iter() {
+            if actual != expected {
+                // Dropping the savepoint rolls back:
+                bail!("Found {} {} after reindexing, but expected {}.", actual, name, expected);
+            }
+        }
+
+        tx.commit()?;
+        Ok(report)
+    }
+
+    fn expire_items(&self, now: Timestamp) -> Result<usize, Error> {
+        let tx = self.conn.unchecked_transaction()?;
+        let mut expired = vec![];
+        {
+            let mut stmt = tx.prepare(EXPIRED_ITEMS_SQL)?;
+            let mut rows = stmt.query(params![now.unix_utc_ms])?;
+            while let Some(row) = rows.next()? {
+                let user = UserID::from_vec(row.get(0)?)?;
+                let signature = Signature::from_vec(row.get(1)?)?;
+                let expires = Timestamp{ unix_utc_ms: row.get(2)? };
+                expired.push((user, signature, expires));
+            }
+        }
+
+        for (user, signature, expires) in &expired {
+            // Counts as removed when it expired, not when we noticed, for purging:
+            tx.execute("
+                UPDATE item
+                SET removed_utc_ms = ?
+                WHERE user_id = ?
+                AND signature = ?
+            ", params![
+                expires.unix_utc_ms,
+                user.bytes(),
+                signature.bytes(),
+            ])?;
+            update_summary(&tx, user, signature, -1)?;
+            log_item_event(&tx, user, signature, ItemEventKind::Expired, *expires)?;
+        }
+        tx.commit()?;
+
+        Ok(expired.len())
+    }
+
+    fn prune(&self, retention: &Retention, now: Timestamp) -> Result<PruneReport, Error> {
+        const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+        let cutoff = |days: u64| now.unix_utc_ms - days as i64 * DAY_MS;
+        let mut report = PruneReport::default();
+
+        let tx = self.conn.unchecked_transaction()?;
+        // item_event_no_delete refuses to delete events younger than this:
+        tx.execute(
+            "UPDATE item_event_retention SET days = ?",
+            params![retention.item_event_days as i64],
+        )?;
+        if retention.item_event_days > 0 {
+            report.item_events = tx.execute(
+                "DELETE FROM item_event WHERE created_utc_ms < ?",
+                params![cutoff(retention.item_event_days)],
+            )? as u64;
+        }
+        if retention.sync_report_days > 0 {
+            report.sync_reports = tx.execute(
+                "DELETE FROM sync_report WHERE created_utc_ms < ?",
+                params![cutoff(retention.sync_report_days)],
+            )? as u64;
+        }
+        if retention.security_report_days > 0 {
+            report.security_reports = tx.execute(
+                "DELETE FROM security_report WHERE created_utc_ms < ?",
+                params![cutoff(retention.security_report_days)],
+            )? as u64;
+        }
+        if retention.stale_queue_days > 0 {
+            for table in &["push_queue", "activitypub_delivery", "crosspost_queue", "ipfs_queue"] {
+                report.queued += tx.execute(
+                    &format!("DELETE FROM {} WHERE next_attempt_utc_ms < ?", table),
+                    params![cutoff(retention.stale_queue_days)],
+                )? as u64;
+            }
+        }
+        tx.commit()?;
+
+        Ok(report)
+    }
+
+    fn purge_removed_items(&self, removed_before: Timestamp) -> Result<usize, Error> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("
+            INSERT INTO item_event(user_id, signature, event, created_utc_ms)
+            SELECT user_id, signature, ?, ?
+            FROM item
+            WHERE removed_utc_ms IS NOT NULL
+            AND removed_utc_ms < ?
+        ", params![
+            ItemEventKind::Purged.as_str(),
+            Timestamp::now().unix_utc_ms,
+            removed_before.unix_utc_ms,
+        ])?;
+        tx.execute("
+            DELETE FROM attachment
+            WHERE (user_id, signature) IN (
+                SELECT user_id, signature
+                FROM item
+                WHERE removed_utc_ms IS NOT NULL
+                AND removed_utc_ms < ?
+            )
+        ", params![
+            removed_before.unix_utc_ms,
+        ])?;
+        tx.execute("
+            DELETE FROM ipfs_cid
+            WHERE (user_id, signature) IN (
+                SELECT user_id, signature
+                FROM item
+                WHERE removed_utc_ms IS NOT NULL
+                AND removed_utc_ms < ?
+            )
+        ", params![
+            removed_before.unix_utc_ms,
+        ])?;
+        tx.execute("
+            DELETE FROM post_search
+            WHERE (user_id, signature) IN (
+                SELECT user_id, signature
+                FROM item
+                WHERE removed_utc_ms IS NOT NULL
+                AND removed_utc_ms < ?
+            )
+        ", params![
+            removed_before.unix_utc_ms,
+        ])?;
+        tx.execute("
+            DELETE FROM activitypub_reply
+            WHERE (user_id, signature) IN (
+                SELECT user_id, signature
+                FROM item
+                WHERE removed_utc_ms IS NOT NULL
+                AND removed_utc_ms < ?
+            )
+        ", params![
+            removed_before.unix_utc_ms,
+        ])?;
+        tx.execute("
+            DELETE FROM dead_link
+            WHERE (user_id, signature) IN (
+                SELECT user_id, signature
+                FROM item
+                WHERE removed_utc_ms IS NOT NULL
+                AND removed_utc_ms < ?
+            )
+        ", params![
+            removed_before.unix_utc_ms,
+        ])?;
+        tx.execute("
+            DELETE FROM item_reference
+            WHERE (user_id, signature) IN (
+                SELECT user_id, signature
+                FROM item
+                WHERE removed_utc_ms IS NOT NULL
+                AND removed_utc_ms < ?
+            )
+        ", params![
+            removed_before.unix_utc_ms,
+        ])?;
+        let deleted = tx.execute("
+            DELETE FROM item
+            WHERE removed_utc_ms IS NOT NULL
+            AND removed_utc_ms < ?
+        ", params![
+            removed_before.unix_utc_ms,
+        ])?;
+        tx.commit()?;
+
+        Ok(deleted)
+    }
+
+    fn all_items<'a>(&self, cb: FnIter<'a, ItemRow>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT user_id, signature, unix_utc_ms, received_utc_ms, bytes
+            FROM item
+        ")?;
+
+        let mut rows = stmt.query(NO_PARAMS)?;
+
+        while let Some(row) = rows.next()? {
+            let item_row = ItemRow {
+                user: UserID::from_vec(row.get(0)?)?,
+                signature: Signature::from_vec(row.get(1)?)?,
+                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+                received: Timestamp{ unix_utc_ms: row.get(3)? },
+                item_bytes: row.get(4)?,
+            };
+            if !cb(item_row)? { break; }
+        }
+
+        Ok(())
+    }
+
+    fn delete_user_item(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        let tx = self.conn.unchecked_transaction()?;
+        let removed: Option<bool> = tx.query_row("
+            SELECT removed_utc_ms IS NOT NULL
+            FROM item
+            WHERE user_id = ? AND signature = ?
+        ", params![user.bytes(), signature.bytes()], |row| row.get(0)).optional()?;
+        let removed = match removed {
+            None => return Ok(false),
+            Some(removed) => removed,
+        };
+
+        if !removed {
+            update_summary(&tx, user, signature, -1)?;
+        }
+        log_item_event(&tx, user, signature, ItemEventKind::Purged, Timestamp::now())?;
+        delete_item_rows(&tx, user, signature)?;
+        tx.commit()?;
+
+        Ok(true)
+    }
+
+    fn ip_blocks<'a>(&self, cb: FnIter<'a, IpBlock>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT cidr, notes, created_utc_ms
+            FROM ip_block
+            ORDER BY cidr
+        ")?;
+
+        let mut rows = stmt.query(NO_PARAMS)?;
+
+        while let Some(row) = rows.next()? {
+            let block = IpBlock {
+                cidr: row.get(0)?,
+                notes: row.get(1)?,
+                created: Timestamp{ unix_utc_ms: row.get(2)? },
+            };
+            let more = cb(block)?;
+            if !more {break;}
+        }
+
+        Ok(())
+    }
+
+    fn add_ip_block(&self, block: &IpBlock) -> Result<(), Error> {
+        self.conn.execute("
+            INSERT OR REPLACE INTO ip_block(cidr, notes, created_utc_ms)
+            VALUES (?,?,?)
+        ", params![
+            block.cidr.as_str(),
+            block.notes.as_str(),
+            block.created.unix_utc_ms,
+        ])?;
+
+        Ok(())
+    }
+
+    fn remove_ip_block(&self, cidr: &str) -> Result<bool, Error> {
+        let deleted = self.conn.execute(
+            "DELETE FROM ip_block WHERE cidr = ?",
+            params![cidr],
+        )?;
+
+        Ok(deleted > 0)
+    }
+
+    fn record_rejection(&self, when: Timestamp) -> Result<(), Error> {
+        add_daily_stats(&self.conn, &DailyStats {
+            day: when.unix_utc_days(),
+            rejections: 1,
+            ..Default::default()
+        })
+    }
+
+    fn daily_stats<'a>(&self, since_day: i64, cb: FnIter<'a, DailyStats>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT day, items, item_bytes, attachments, attachment_bytes, rejections
+            FROM daily_stats
+            WHERE day >= ?
+            ORDER BY day
+        ")?;
+        let mut rows = stmt.query(params![since_day])?;
+
+        while let Some(row) = rows.next()? {
+            let stats = DailyStats {
+                day: row.get(0)?,
+                items: row.get::<_, i64>(1)? as u64,
+                item_bytes: row.get::<_, i64>(2)? as u64,
+                attachments: row.get::<_, i64>(3)? as u64,
+                attachment_bytes: row.get::<_, i64>(4)? as u64,
+                rejections: row.get::<_, i64>(5)? as u64,
+            };
+            if !cb(stats)? { break; }
+        }
+
+        Ok(())
+    }
+
+    fn record_item_view(&self, user: &UserID, signature: &Signature, when: Timestamp) -> Result<(), Error> {
+        self.conn.execute("
+            INSERT INTO item_view_day(user_id, signature, day, views)
+            VALUES (?, ?, ?, 1)
+            ON CONFLICT (user_id, signature, day)
+            DO UPDATE SET views = views + 1
+        ", params![
+            user.bytes(),
+            signature.bytes(),
+            when.unix_utc_days(),
+        ])?;
+
+        Ok(())
+    }
+
+    fn user_item_view_counts<'a>(&self, user: &UserID, cb: FnIter<'a, ItemViewCount>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT signature, day, views
+            FROM item_view_day
+            WHERE user_id = ?
+            ORDER BY day DESC, views DESC
+        ")?;
+
+        let mut rows = stmt.query(params![user.bytes()])?;
+
+        while let Some(row) = rows.next()? {
+            let count = ItemViewCount {
+                signature: Signature::from_vec(row.get(0)?)?,
+                day: row.get(1)?,
+                views: row.get(2)?,
+            };
+            let more = cb(count)?;
+            if !more {break;}
+        }
+
+        Ok(())
+    }
+
+    fn user_item_counts_by_month<'a>(&self, user: &UserID, cb: FnIter<'a, MonthCount>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT
+                strftime('%Y-%m', unix_utc_ms / 1000, 'unixepoch') AS month
+                , COUNT(*)
+            FROM item
+            WHERE user_id = ?
+            AND removed_utc_ms IS NULL
+            AND followers_only = 0
+            GROUP BY month
+            ORDER BY month DESC
+        ")?;
+
+        let mut rows = stmt.query(params![user.bytes()])?;
+
+        while let Some(row) = rows.next()? {
+            let count = MonthCount {
+                month: row.get(0)?,
+                count: row.get(1)?,
+            };
+            let more = cb(count)?;
+            if !more {break;}
+        }
+
+        Ok(())
+    }
+
+    fn user_top_viewed_items<'a>(&self, user: &UserID, cb: FnIter<'a, ItemViewCount>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT signature, MAX(day), SUM(views) AS total
+            FROM item_view_day
+            WHERE user_id = ?
+            GROUP BY signature
+            ORDER BY total DESC
+        ")?;
+
+        let mut rows = stmt.query(params![user.bytes()])?;
+
+        while let Some(row) = rows.next()? {
+            let count = ItemViewCount {
+                signature: Signature::from_vec(row.get(0)?)?,
+                day: row.get(1)?,
+                views: row.get(2)?,
+            };
+            let more = cb(count)?;
+            if !more {break;}
+        }
+
+        Ok(())
+    }
+
+    fn user_follower_counts_by_month<'a>(&self, user: &UserID, cb: FnIter<'a, MonthCount>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT
+                strftime('%Y-%m', since_utc_ms / 1000, 'unixepoch') AS month
+                , COUNT(*)
+            FROM follow
+            WHERE followed_user_id = ?
+            AND since_utc_ms IS NOT NULL
+            GROUP BY month
+            ORDER BY month DESC
+        ")?;
+
+        let mut rows = stmt.query(params![user.bytes()])?;
+
+        while let Some(row) = rows.next()? {
+            let count = MonthCount {
+                month: row.get(0)?,
+                count: row.get(1)?,
+            };
+            let more = cb(count)?;
+            if !more {break;}
+        }
+
+        Ok(())
+    }
+
+    fn user_reaction_counts_by_month<'a>(&self, user: &UserID, cb: FnIter<'a, MonthCount>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT
+                strftime('%Y-%m', r.unix_utc_ms / 1000, 'unixepoch') AS month
+                , COUNT(*)
+            FROM reaction AS r
+            INNER JOIN item AS i USING (user_id, signature)
+            WHERE r.ref_user_id = ?
+            AND i.removed_utc_ms IS NULL
+            GROUP BY month
+            ORDER BY month DESC
+        ")?;
+
+        let mut rows = stmt.query(params![user.bytes()])?;
+
+        while let Some(row) = rows.next()? {
+            let count = MonthCount {
+                month: row.get(0)?,
+                count: row.get(1)?,
+            };
+            let more = cb(count)?;
+            if !more {break;}
+        }
+
+        Ok(())
+    }
+
+    fn user_follower_count(&self, user: &UserID) -> Result<u64, Error> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM follow WHERE followed_user_id = ?",
+            params![user.bytes()],
+            |row| row.get(0),
+        )?;
+
+        Ok(count as u64)
+    }
+
+    fn announcement(&self) -> Result<Option<Announcement>, Error> {
+        let announcement = self.conn.query_row("
+            SELECT message, created_utc_ms, expires_utc_ms
+            FROM announcement
+        ", NO_PARAMS, |row| {
+            let expires: Option<i64> = row.get(2)?;
+            Ok(Announcement {
+                message: row.get(0)?,
+                created: Timestamp{ unix_utc_ms: row.get(1)? },
+                expires: expires.map(|unix_utc_ms| Timestamp{ unix_utc_ms }),
+            })
+        }).optional()?;
+
+        Ok(announcement)
+    }
+
+    fn set_announcement(&self, announcement: Option<&Announcement>) -> Result<(), Error> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM announcement", NO_PARAMS)?;
+        if let Some(announcement) = announcement {
+            tx.execute("
+                INSERT INTO announcement(message, created_utc_ms, expires_utc_ms)
+                VALUES (?, ?, ?)
+            ", params![
+                announcement.message.as_str(),
+                announcement.created.unix_utc_ms,
+                announcement.expires.map(|e| e.unix_utc_ms),
+            ])?;
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn queue_comment(&self, comment: &QueuedComment) -> Result<(), Error> {
+        self.conn.execute("
+            INSERT INTO comment_queue(user_id, signature, name, text, created_utc_ms)
+            VALUES (?, ?, ?, ?, ?)
+        ", params![
+            comment.user.bytes(),
+            comment.signature.bytes(),
+            comment.name.as_str(),
+            comment.text.as_str(),
+            comment.created.unix_utc_ms,
+        ])?;
+
+        Ok(())
+    }
+
+    fn queued_comment_count(&self, user: &UserID, signature: &Signature) -> Result<u64, Error> {
+        let count: i64 = self.conn.query_row("
+            SELECT COUNT(*)
+            FROM comment_queue
+            WHERE user_id = ? AND signature = ?
+        ", params![user.bytes(), signature.bytes()], |row| row.get(0))?;
+
+        Ok(count as u64)
+    }
+
+    fn queued_comments<'a>(&self, cb: FnIter<'a, QueuedComment>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT id, user_id, signature, name, text, created_utc_ms
+            FROM comment_queue
+            ORDER BY id
+        ")?;
+
+        let rows = stmt.query(NO_PARAMS)?;
+        read_queued_comments(rows, cb)
+    }
+
+    fn user_queued_comments<'a>(&self, user: &UserID, cb: FnIter<'a, QueuedComment>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT id, user_id, signature, name, text, created_utc_ms
+            FROM comment_queue
+            WHERE user_id = ?
+            ORDER BY id
+        ")?;
+
+        let rows = stmt.query(params![user.bytes()])?;
+        read_queued_comments(rows, cb)
+    }
+
+    fn remove_queued_comment(&self, id: i64) -> Result<bool, Error> {
+        let deleted = self.conn.execute("DELETE FROM comment_queue WHERE id = ?", params![id])?;
+        Ok(deleted > 0)
+    }
+
+    fn push_peers<'a>(&self, cb: FnIter<'a, PushPeer>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT url, created_utc_ms
+            FROM push_peer
+            ORDER BY url
+        ")?;
+
+        let mut rows = stmt.query(NO_PARAMS)?;
+
+        while let Some(row) = rows.next()? {
+            let peer = PushPeer {
+                url: row.get(0)?,
+                created: Timestamp{ unix_utc_ms: row.get(1)? },
+            };
+            if !cb(peer)? { break; }
+        }
+
+        Ok(())
+    }
+
+    fn add_push_peer(&self, peer: &PushPeer) -> Result<(), Error> {
+        self.conn.execute("
+            INSERT OR REPLACE INTO push_peer(url, created_utc_ms)
+            VALUES (?, ?)
+        ", params![
+            peer.url.as_str(),
+            peer.created.unix_utc_ms,
+        ])?;
+
+        Ok(())
+    }
+
+    fn remove_push_peer(&self, url: &str) -> Result<bool, Error> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("DELETE FROM push_queue WHERE peer_url = ?", params![url])?;
+        let deleted = tx.execute("DELETE FROM push_peer WHERE url = ?", params![url])?;
+        tx.commit()?;
+
+        Ok(deleted > 0)
+    }
+
+    fn trusted_peers<'a>(&self, cb: FnIter<'a, TrustedPeer>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT key, notes, created_utc_ms
+            FROM trusted_peer
+            ORDER BY created_utc_ms
+        ")?;
+
+        let mut rows = stmt.query(NO_PARAMS)?;
+
+        while let Some(row) = rows.next()? {
+            let key: Vec<u8> = row.get(0)?;
+            let peer = TrustedPeer {
+                key: UserID::from_vec(key)?,
+                notes: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                created: Timestamp{ unix_utc_ms: row.get(2)? },
+            };
+            if !cb(peer)? { break; }
+        }
+
+        Ok(())
+    }
+
+    fn add_trusted_peer(&self, peer: &TrustedPeer) -> Result<(), Error> {
+        self.conn.execute("
+            INSERT OR REPLACE INTO trusted_peer(key, notes, created_utc_ms)
+            VALUES (?, ?, ?)
+        ", params![
+            peer.key.bytes(),
+            peer.notes.as_str(),
+            peer.created.unix_utc_ms,
+        ])?;
+
+        Ok(())
+    }
+
+    fn remove_trusted_peer(&self, key: &UserID) -> Result<bool, Error> {
+        let deleted = self.conn.execute("DELETE FROM trusted_peer WHERE key = ?", params![key.bytes()])?;
+        Ok(deleted > 0)
+    }
+
+    fn is_trusted_peer(&self, key: &UserID) -> Result<bool, Error> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM trusted_peer WHERE key = ?",
+            params![key.bytes()],
+            |row| row.get(0),
+        )?;
+        Ok(count > 0)
+    }
+
+    fn use_request_signature(&self, signature: &Signature, expires: Timestamp) -> Result<bool, Error> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute(
+            "DELETE FROM used_request_signature WHERE expires_utc_ms < ?",
+            params![Timestamp::now().unix_utc_ms],
+        )?;
+        let inserted = tx.execute("
+            INSERT OR IGNORE INTO used_request_signature(signature, expires_utc_ms)
+            VALUES (?, ?)
+        ", params![signature.bytes(), expires.unix_utc_ms])?;
+        tx.commit()?;
+
+        Ok(inserted > 0)
+    }
+
+    fn server_stats(&self, since: &[Timestamp]) -> Result<ServerStats, Error> {
+        let users: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM server_user",
+            NO_PARAMS,
+            |row| row.get(0),
+        )?;
+
+        let mut active_users = vec![];
+        for since in since {
+            let active: i64 = self.conn.query_row("
+                SELECT COUNT(DISTINCT user_id)
+                FROM item
+                WHERE unix_utc_ms >= ?
+                AND removed_utc_ms IS NULL
+                AND user_id IN (SELECT user_id FROM server_user)
+            ", params![since.unix_utc_ms], |row| row.get(0))?;
+            active_users.push(active as u64);
+        }
+
+        // Items don't have a type column, but posts are indexed for search:
+        let posts: i64 = self.conn.query_row("
+            SELECT COUNT(*)
+            FROM post_search AS ps
+            JOIN item AS i ON (i.user_id = ps.user_id AND i.signature = ps.signature)
+            WHERE i.removed_utc_ms IS NULL
+            AND i.user_id IN (SELECT user_id FROM server_user)
+        ", NO_PARAMS, |row| row.get(0))?;
+
+        Ok(ServerStats {
+            users: users as u64,
+            active_users,
+            posts: posts as u64,
+        })
+    }
+
+    fn user_aliases<'a>(&self, cb: FnIter<'a, UserAlias>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT name, user_id, created_utc_ms
+            FROM user_alias
+            ORDER BY name
+        ")?;
+
+        let mut rows = stmt.query(NO_PARAMS)?;
+
+        while let Some(row) = rows.next()? {
+            let alias = UserAlias {
+                name: row.get(0)?,
+                user: UserID::from_vec(row.get(1)?)?,
+                created: Timestamp{ unix_utc_ms: row.get(2)? },
+            };
+            if !cb(alias)? { break; }
+        }
+
+        Ok(())
+    }
+
+    fn alias_user(&self, name: &str) -> Result<Option<UserID>, Error> {
+        let user: Option<Vec<u8>> = self.conn.query_row(
+            "SELECT user_id FROM user_alias WHERE name = ?",
+            params![name],
+            |row| row.get(0),
+        ).optional()?;
+
+        Ok(match user {
+            Some(bytes) => Some(UserID::from_vec(bytes)?),
+            None => None,
+        })
+    }
+
+    fn add_user_alias(&self, alias: &UserAlias) -> Result<(), Error> {
+        self.conn.execute("
+            INSERT OR REPLACE INTO user_alias(name, user_id, created_utc_ms)
+            VALUES (?, ?, ?)
+        ", params![
+            alias.name.as_str(),
+            alias.user.bytes(),
+            alias.created.unix_utc_ms,
+        ])?;
+
+        Ok(())
+    }
+
+    fn remove_user_alias(&self, name: &str) -> Result<bool, Error> {
+        let deleted = self.conn.execute("DELETE FROM user_alias WHERE name = ?", params![name])?;
+
+        Ok(deleted > 0)
+    }
+
+    fn queue_push(&self, user: &UserID, signature: &Signature) -> Result<(), Error> {
+        self.conn.execute("
+            INSERT OR IGNORE INTO push_queue(peer_url, user_id, signature, attempts, next_attempt_utc_ms)
+            SELECT url, ?, ?, 0, ?
+            FROM push_peer
+        ", params![
+            user.bytes(),
+            signature.bytes(),
+            Timestamp::now().unix_utc_ms,
+        ])?;
+
+        Ok(())
+    }
+
+    fn due_pushes<'a>(&self, now: Timestamp, cb: FnIter<'a, QueuedPush>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT peer_url, user_id, signature, attempts
+            FROM push_queue
+            WHERE next_attempt_utc_ms <= ?
+            ORDER BY next_attempt_utc_ms
+        ")?;
+
+        let mut rows = stmt.query(params![now.unix_utc_ms])?;
+
+        while let Some(row) = rows.next()? {
+            let push = QueuedPush {
+                peer_url: row.get(0)?,
+                user: UserID::from_vec(row.get(1)?)?,
+                signature: Signature::from_vec(row.get(2)?)?,
+                attempts: row.get(3)?,
+            };
+            if !cb(push)? { break; }
+        }
+
+        Ok(())
+    }
+
+    fn push_failed(&self, push: &QueuedPush, retry_at: Timestamp, error: &str) -> Result<(), Error> {
+        self.conn.execute("
+            UPDATE push_queue
+            SET attempts = attempts + 1
+                , next_attempt_utc_ms = ?
+                , last_error = ?
+            WHERE peer_url = ? AND user_id = ? AND signature = ?
+        ", params![
+            retry_at.unix_utc_ms,
+            error,
+            push.peer_url.as_str(),
+            push.user.bytes(),
+            push.signature.bytes(),
+        ])?;
+
+        Ok(())
+    }
+
+    fn finish_push(&self, push: &QueuedPush) -> Result<(), Error> {
+        self.conn.execute("
+            DELETE FROM push_queue
+            WHERE peer_url = ? AND user_id = ? AND signature = ?
+        ", params![
+            push.peer_url.as_str(),
+            push.user.bytes(),
+            push.signature.bytes(),
+        ])?;
+
+        Ok(())
+    }
+
+    fn save_sync_report(&self, report: &SyncReport) -> Result<(), Error> {
+        self.conn.execute("
+            INSERT INTO sync_report(peer, created_utc_ms, complete, report)
+            VALUES (?, ?, ?, ?)
+        ", params![
+            report.peer.as_str(),
+            report.created.unix_utc_ms,
+            if report.complete { 1 } else { 0 },
+            report.report.as_str(),
+        ])?;
+
+        Ok(())
+    }
+
+    fn save_security_report(&self, report: &SecurityReport) -> Result<(), Error> {
+        let (user, signature) = match &report.item {
+            Some((user, signature)) => (Some(user.bytes()), Some(signature.bytes())),
+            None => (None, None),
+        };
+        self.conn.execute("
+            INSERT INTO security_report(created_utc_ms, kind, user_id, signature, detail)
+            VALUES (?, ?, ?, ?, ?)
+        ", params![
+            report.created.unix_utc_ms,
+            report.kind.as_str(),
+            user,
+            signature,
+            report.detail.as_str(),
+        ])?;
+
+        // Row IDs only grow, so this drops the oldest:
+        self.conn.execute("
+            DELETE FROM security_report
+            WHERE rowid <= ?
+        ", params![self.conn.last_insert_rowid() - backend::MAX_SECURITY_REPORTS as i64])?;
+
+        Ok(())
+    }
+
+    fn security_reports<'a>(&self, before: Timestamp, cb: FnIter<'a, SecurityReport>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT created_utc_ms, kind, user_id, signature, detail
+            FROM security_report
+            WHERE created_utc_ms < ?
+            ORDER BY created_utc_ms DESC
+        ")?;
+        let mut rows = stmt.query(params![before.unix_utc_ms])?;
+
+        while let Some(row) = rows.next()? {
+            let user: Option<Vec<u8>> = row.get(2)?;
+            let signature: Option<Vec<u8>> = row.get(3)?;
+            let item = match (user, signature) {
+                (Some(user), Some(signature)) => Some((UserID::from_vec(user)?, Signature::from_vec(signature)?)),
+                _ => None,
+            };
+            let report = SecurityReport {
+                created: Timestamp{ unix_utc_ms: row.get(0)? },
+                kind: row.get::<_, String>(1)?.parse()?,
+                item,
+                detail: row.get(4)?,
+            };
+            if !cb(report)? { break; }
+        }
+
+        Ok(())
+    }
+
+    fn user_summary(&self, user: &UserID) -> Result<UserSummary, Error> {
+        let summary = self.conn.query_row(
+            "SELECT item_count, digest FROM user_summary WHERE user_id = ?",
+            params![user.bytes()],
+            |row| Ok(UserSummary{
+                item_count: row.get::<_, i64>(0)? as u64,
+                digest: row.get(1)?,
+            }),
+        ).optional()?;
+
+        Ok(summary.unwrap_or_else(|| UserSummary{
+            item_count: 0,
+            digest: vec![0; sha256::DIGESTBYTES],
+        }))
+    }
+
+    fn mute_user(&self, viewer: &UserID, muted: &UserID) -> Result<(), Error> {
+        self.conn.execute("
+            INSERT OR IGNORE INTO user_mute(user_id, muted_user_id, created_utc_ms)
+            VALUES (?, ?, ?)
+        ", params![
+            viewer.bytes(),
+            muted.bytes(),
+            Timestamp::now().unix_utc_ms,
+        ])?;
+
+        Ok(())
+    }
+
+    fn unmute_user(&self, viewer: &UserID, muted: &UserID) -> Result<bool, Error> {
+        let deleted = self.conn.execute("
+            DELETE FROM user_mute
+            WHERE user_id = ?
+            AND muted_user_id = ?
+        ", params![
+            viewer.bytes(),
+            muted.bytes(),
+        ])?;
+
+        Ok(deleted > 0)
+    }
+
+    fn muted_users<'a>(&self, viewer: &UserID, cb: FnIter<'a, UserID>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT muted_user_id
+            FROM user_mute
+            WHERE user_id = ?
+            ORDER BY created_utc_ms
+        ")?;
+
+        let mut rows = stmt.query(params![viewer.bytes()])?;
+
+        while let Some(row) = rows.next()? {
+            let more = cb(UserID::from_vec(row.get(0)?)?)?;
+            if !more {break;}
+        }
+
+        Ok(())
     }
 
-    fn user_feed_items<'a>(
+    fn save_feed(&self, feed: &SavedFeed) -> Result<(), Error> {
+        let tx = self.conn.unchecked_transaction()?;
+        let item_types: Vec<String> = feed.item_types.iter()
+            .map(|t| t.value().to_string())
+            .collect();
+
+        tx.execute("
+            INSERT OR REPLACE INTO saved_feed(user_id, name, item_types, tags, created_utc_ms)
+            VALUES (?, ?, ?, ?, ?)
+        ", params![
+            feed.user.bytes(),
+            feed.name.as_str(),
+            item_types.join(","),
+            feed.tags.join(","),
+            Timestamp::now().unix_utc_ms,
+        ])?;
+        tx.execute("
+            DELETE FROM saved_feed_author
+            WHERE user_id = ? AND name = ?
+        ", params![feed.user.bytes(), feed.name.as_str()])?;
+        for author in &feed.authors {
+            tx.execute("
+                INSERT OR IGNORE INTO saved_feed_author(user_id, name, author_id)
+                VALUES (?, ?, ?)
+            ", params![feed.user.bytes(), feed.name.as_str(), author.bytes()])?;
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn saved_feed(&self, user: &UserID, name: &str) -> Result<Option<SavedFeed>, Error> {
+        let columns: Option<(String, String)> = self.conn.query_row(
+            "SELECT item_types, tags FROM saved_feed WHERE user_id = ? AND name = ?",
+            params![user.bytes(), name],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        ).optional()?;
+        let (item_types, tags) = match columns {
+            Some(columns) => columns,
+            None => return Ok(None),
+        };
+
+        Ok(Some(SavedFeed {
+            user: user.clone(),
+            name: name.to_string(),
+            authors: self.saved_feed_authors(user, name)?,
+            item_types: parse_item_types(&item_types)?,
+            tags: parse_tags(&tags),
+        }))
+    }
+
+    fn saved_feeds<'a>(&self, user: &UserID, cb: FnIter<'a, SavedFeed>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT name, item_types, tags
+            FROM saved_feed
+            WHERE user_id = ?
+            ORDER BY name
+        ")?;
+
+        let mut rows = stmt.query(params![user.bytes()])?;
+
+        while let Some(row) = rows.next()? {
+            let name: String = row.get(0)?;
+            let item_types: String = row.get(1)?;
+            let tags: String = row.get(2)?;
+            let feed = SavedFeed {
+                user: user.clone(),
+                authors: self.saved_feed_authors(user, &name)?,
+                name,
+                item_types: parse_item_types(&item_types)?,
+                tags: parse_tags(&tags),
+            };
+            let more = cb(feed)?;
+            if !more {break;}
+        }
+
+        Ok(())
+    }
+
+    fn delete_saved_feed(&self, user: &UserID, name: &str) -> Result<bool, Error> {
+        let tx = self.conn.unchecked_transaction()?;
+        let deleted = tx.execute("
+            DELETE FROM saved_feed
+            WHERE user_id = ? AND name = ?
+        ", params![user.bytes(), name])?;
+        tx.execute("
+            DELETE FROM saved_feed_author
+            WHERE user_id = ? AND name = ?
+        ", params![user.bytes(), name])?;
+        tx.commit()?;
+
+        Ok(deleted > 0)
+    }
+
+    fn saved_feed_items<'a>(
         &self,
-        user_id: &UserID,
+        user: &UserID,
+        name: &str,
         before: Timestamp,
-        callback: &'a mut dyn FnMut(ItemDisplayRow) -> Result<bool, Error>,
+        cb: FnIter<'a, ItemDisplayRow>,
     ) -> Result<(), Error> {
         let mut stmt = self.conn.prepare("
             SELECT
-                user_id
+                i.user_id
                 , i.signature
-                , unix_utc_ms
-                , received_utc_ms
-                , bytes
+                , i.unix_utc_ms
+                , i.received_utc_ms
+                , i.bytes
                 , p.display_name
-                , f.display_name AS follow_display_name
             FROM item AS i
             LEFT OUTER JOIN profile AS p USING (user_id)
-            LEFT OUTER JOIN follow AS f ON (
-                i.user_id = f.followed_user_id
-                AND f.source_user_id = :user_id
+            WHERE i.unix_utc_ms < :timestamp
+            AND i.removed_utc_ms IS NULL
+            AND i.followers_only = 0
+            AND i.user_id IN (
+                SELECT author_id
+                FROM saved_feed_author
+                WHERE user_id = :user_id
+                AND name = :name
             )
-            WHERE unix_utc_ms < :timestamp
-            AND (
-                user_id IN (
-                    SELECT followed_user_id
-                    FROM follow
-                    WHERE source_user_id = :user_id
+            ORDER BY i.unix_utc_ms DESC
+        ")?;
+
+        let mut rows = stmt.query_named(&[
+            (":timestamp", &before.unix_utc_ms),
+            (":user_id", &user.bytes()),
+            (":name", &name),
+        ])?;
+
+        while let Some(row) = rows.next()? {
+            let display_name: Option<String> = row.get(5)?;
+            let item = ItemDisplayRow {
+                item: ItemRow {
+                    user: UserID::from_vec(row.get(0)?)?,
+                    signature: Signature::from_vec(row.get(1)?)?,
+                    timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+                    received: Timestamp{ unix_utc_ms: row.get(3)? },
+                    item_bytes: row.get(4)?,
+                },
+                display_name: display_name.filter(|n| !n.trim().is_empty()),
+            };
+            let more = cb(item)?;
+            if !more {break;}
+        }
+
+        Ok(())
+    }
+
+    fn comment_replies<'a>(
+        &self,
+        user: &UserID,
+        signature: &Signature,
+        cb: FnIter<'a, (UserID, Signature)>,
+    ) -> Result<(), Error> {
+        // Comments and votes are the only items with references.
+        let mut stmt = self.conn.prepare("
+            SELECT r.user_id, r.signature
+            FROM item_reference AS r
+            INNER JOIN item AS i USING (user_id, signature)
+            WHERE r.ref_user_id = ?1
+            AND r.ref_signature = ?2
+            AND i.removed_utc_ms IS NULL
+            AND i.followers_only = 0
+            AND NOT EXISTS (
+                SELECT 1
+                FROM poll_vote AS v
+                WHERE v.poll_user_id = ?1
+                AND v.poll_signature = ?2
+                AND v.voter_id = r.user_id
+                AND v.vote_signature = r.signature
+            )
+        ")?;
+
+        let mut rows = stmt.query(params![user.bytes(), signature.bytes()])?;
+        while let Some(row) = rows.next()? {
+            let reply = (
+                UserID::from_vec(row.get(0)?)?,
+                Signature::from_vec(row.get(1)?)?,
+            );
+            if !cb(reply)? { break; }
+        }
+
+        Ok(())
+    }
+
+    fn poll_votes<'a>(
+        &self,
+        poll_user: &UserID,
+        poll_signature: &Signature,
+        closes: Timestamp,
+        cb: FnIter<'a, PollVote>,
+    ) -> Result<(), Error> {
+        // Voters choose unix_utc_ms, so they could backdate a late vote. We
+        // choose received_utc_ms.
+        let mut stmt = self.conn.prepare("
+            WITH live_vote AS (
+                SELECT v.voter_id, v.vote_signature, v.unix_utc_ms, v.option
+                FROM poll_vote AS v
+                INNER JOIN item AS i ON (
+                    i.user_id = v.voter_id
+                    AND i.signature = v.vote_signature
                 )
-                OR user_id = :user_id
+                WHERE v.poll_user_id = :poll_user_id
+                AND v.poll_signature = :poll_signature
+                AND i.received_utc_ms <= :closes
+                AND i.removed_utc_ms IS NULL
+            )
+            SELECT voter_id, option
+            FROM live_vote AS v
+            WHERE v.vote_signature = (
+                SELECT latest.vote_signature
+                FROM live_vote AS latest
+                WHERE latest.voter_id = v.voter_id
+                ORDER BY latest.unix_utc_ms DESC, latest.vote_signature DESC
+                LIMIT 1
             )
-            ORDER BY unix_utc_ms DESC
         ")?;
 
         let mut rows = stmt.query_named(&[
-            (":timestamp", &before.unix_utc_ms),
-            (":user_id", &user_id.bytes())
+            (":poll_user_id", &poll_user.bytes()),
+            (":poll_signature", &poll_signature.bytes()),
+            (":closes", &closes.unix_utc_ms),
+        ])?;
+
+        while let Some(row) = rows.next()? {
+            let vote = PollVote {
+                voter: UserID::from_vec(row.get(0)?)?,
+                option: row.get::<_, i64>(1)? as u32,
+            };
+            let more = cb(vote)?;
+            if !more {break;}
+        }
+
+        Ok(())
+    }
+
+    fn item_reactions<'a>(
+        &self,
+        user: &UserID,
+        signature: &Signature,
+        cb: FnIter<'a, ItemReaction>,
+    ) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            WITH live_reaction AS (
+                SELECT r.user_id, r.unix_utc_ms, r.emoji
+                FROM reaction AS r
+                INNER JOIN item AS i USING (user_id, signature)
+                WHERE r.ref_user_id = :user_id
+                AND r.ref_signature = :signature
+                AND i.removed_utc_ms IS NULL
+            )
+            SELECT user_id, emoji
+            FROM live_reaction AS r
+            WHERE r.unix_utc_ms = (
+                SELECT MAX(latest.unix_utc_ms)
+                FROM live_reaction AS latest
+                WHERE latest.user_id = r.user_id
+            )
+            GROUP BY user_id
+        ")?;
+
+        let mut rows = stmt.query_named(&[
+            (":user_id", &user.bytes()),
+            (":signature", &signature.bytes()),
+        ])?;
+
+        while let Some(row) = rows.next()? {
+            let reaction = ItemReaction {
+                user: UserID::from_vec(row.get(0)?)?,
+                emoji: row.get(1)?,
+            };
+            if !cb(reaction)? { break; }
+        }
+
+        Ok(())
+    }
+
+    fn item_references<'a>(
+        &self,
+        user: &UserID,
+        signature: &Signature,
+        before: Timestamp,
+        callback: &'a mut dyn FnMut(ItemDisplayRow) -> Result<bool, Error>,
+    ) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT
+                i.user_id
+                , i.signature
+                , i.unix_utc_ms
+                , i.received_utc_ms
+                , i.bytes
+                , p.display_name
+            FROM item_reference AS r
+            INNER JOIN item AS i USING (user_id, signature)
+            LEFT OUTER JOIN profile AS p ON (p.user_id = i.user_id)
+            WHERE r.ref_user_id = ?
+            AND r.ref_signature = ?
+            AND r.unix_utc_ms < ?
+            AND i.removed_utc_ms IS NULL
+            AND i.followers_only = 0
+            ORDER BY r.unix_utc_ms DESC
+        ")?;
+
+        let mut rows = stmt.query(params![
+            user.bytes(),
+            signature.bytes(),
+            before.unix_utc_ms,
+        ])?;
+
+        while let Some(row) = rows.next()? {
+            let display_row = ItemDisplayRow{
+                item: ItemRow{
+                    user: UserID::from_vec(row.get(0)?)?,
+                    signature: Signature::from_vec(row.get(1)?)?,
+                    timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+                    received: Timestamp{ unix_utc_ms: row.get(3)? },
+                    item_bytes: row.get(4)?,
+                },
+                display_name: row.get(5)?,
+            };
+            if !callback(display_row)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn add_activitypub_follower(&self, follower: &ActivityPubFollower) -> Result<(), Error> {
+        self.conn.execute("
+            INSERT OR REPLACE INTO activitypub_follower(user_id, actor, inbox, created_utc_ms)
+            VALUES (?, ?, ?, ?)
+        ", params![
+            follower.user.bytes(),
+            follower.actor.as_str(),
+            follower.inbox.as_str(),
+            follower.created.unix_utc_ms,
+        ])?;
+
+        Ok(())
+    }
+
+    fn remove_activitypub_follower(&self, user: &UserID, actor: &str) -> Result<bool, Error> {
+        let deleted = self.conn.execute("
+            DELETE FROM activitypub_follower
+            WHERE user_id = ? AND actor = ?
+        ", params![user.bytes(), actor])?;
+
+        Ok(deleted > 0)
+    }
+
+    fn activitypub_followers<'a>(&self, user: &UserID, cb: FnIter<'a, ActivityPubFollower>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT actor, inbox, created_utc_ms
+            FROM activitypub_follower
+            WHERE user_id = ?
+            ORDER BY created_utc_ms
+        ")?;
+
+        let mut rows = stmt.query(params![user.bytes()])?;
+
+        while let Some(row) = rows.next()? {
+            let follower = ActivityPubFollower {
+                user: user.clone(),
+                actor: row.get(0)?,
+                inbox: row.get(1)?,
+                created: Timestamp{ unix_utc_ms: row.get(2)? },
+            };
+            if !cb(follower)? { break; }
+        }
+
+        Ok(())
+    }
+
+    fn add_activitypub_reply(&self, reply: &ActivityPubReply) -> Result<(), Error> {
+        self.conn.execute("
+            INSERT OR REPLACE INTO activitypub_reply(
+                id, user_id, signature, actor, actor_name, url, text, published_utc_ms, received_utc_ms
+            )
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ", params![
+            reply.id.as_str(),
+            reply.user.bytes(),
+            reply.signature.bytes(),
+            reply.actor.as_str(),
+            reply.actor_name.as_str(),
+            reply.url.as_str(),
+            reply.text.as_str(),
+            reply.published.unix_utc_ms,
+            reply.received.unix_utc_ms,
+        ])?;
+
+        Ok(())
+    }
+
+    fn remove_activitypub_reply(&self, user: &UserID, id: &str, actor: &str) -> Result<bool, Error> {
+        let deleted = self.conn.execute("
+            DELETE FROM activitypub_reply
+            WHERE user_id = ? AND id = ? AND actor = ?
+        ", params![user.bytes(), id, actor])?;
+
+        Ok(deleted > 0)
+    }
+
+    fn nostr_key(&self, user: &UserID) -> Result<Option<NostrKey>, Error> {
+        let key = self.conn.query_row("
+            SELECT secret_key, created_utc_ms
+            FROM nostr_key
+            WHERE user_id = ?
+        ", params![user.bytes()], |row| {
+            Ok(NostrKey {
+                user: user.clone(),
+                secret_key: row.get(0)?,
+                created: Timestamp{ unix_utc_ms: row.get(1)? },
+            })
+        }).optional()?;
+
+        Ok(key)
+    }
+
+    fn nostr_keys<'a>(&self, cb: FnIter<'a, NostrKey>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT user_id, secret_key, created_utc_ms
+            FROM nostr_key
+            ORDER BY created_utc_ms
+        ")?;
+
+        let mut rows = stmt.query(NO_PARAMS)?;
+
+        while let Some(row) = rows.next()? {
+            let key = NostrKey {
+                user: UserID::from_vec(row.get(0)?)?,
+                secret_key: row.get(1)?,
+                created: Timestamp{ unix_utc_ms: row.get(2)? },
+            };
+            if !cb(key)? { break; }
+        }
+
+        Ok(())
+    }
+
+    fn add_nostr_key(&self, key: &NostrKey) -> Result<(), Error> {
+        self.conn.execute("
+            INSERT OR REPLACE INTO nostr_key(user_id, secret_key, created_utc_ms)
+            VALUES (?, ?, ?)
+        ", params![
+            key.user.bytes(),
+            key.secret_key.as_slice(),
+            key.created.unix_utc_ms,
+        ])?;
+
+        Ok(())
+    }
+
+    fn remove_nostr_key(&self, user: &UserID) -> Result<bool, Error> {
+        let deleted = self.conn.execute("DELETE FROM nostr_key WHERE user_id = ?", params![user.bytes()])?;
+
+        Ok(deleted > 0)
+    }
+
+    fn crosspost_targets<'a>(&self, user: &UserID, cb: FnIter<'a, CrossPostTarget>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT name, service, url, account, token, created_utc_ms
+            FROM crosspost_target
+            WHERE user_id = ?
+            ORDER BY name
+        ")?;
+
+        let mut rows = stmt.query(params![user.bytes()])?;
+
+        while let Some(row) = rows.next()? {
+            let target = CrossPostTarget {
+                user: user.clone(),
+                name: row.get(0)?,
+                service: row.get::<_, String>(1)?.parse()?,
+                url: row.get(2)?,
+                account: row.get(3)?,
+                token: row.get(4)?,
+                created: Timestamp{ unix_utc_ms: row.get(5)? },
+            };
+            if !cb(target)? { break; }
+        }
+
+        Ok(())
+    }
+
+    fn crosspost_target(&self, user: &UserID, name: &str) -> Result<Option<CrossPostTarget>, Error> {
+        let mut target = None;
+        self.crosspost_targets(user, &mut |t| {
+            if t.name == name {
+                target = Some(t);
+            }
+            Ok(target.is_none())
+        })?;
+
+        Ok(target)
+    }
+
+    fn add_crosspost_target(&self, target: &CrossPostTarget) -> Result<(), Error> {
+        self.conn.execute("
+            INSERT OR REPLACE INTO crosspost_target(user_id, name, service, url, account, token, created_utc_ms)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+        ", params![
+            target.user.bytes(),
+            target.name.as_str(),
+            target.service.as_str(),
+            target.url.as_str(),
+            target.account.as_str(),
+            target.token.as_str(),
+            target.created.unix_utc_ms,
+        ])?;
+
+        Ok(())
+    }
+
+    fn remove_crosspost_target(&self, user: &UserID, name: &str) -> Result<bool, Error> {
+        let deleted = self.conn.execute("
+            DELETE FROM crosspost_target
+            WHERE user_id = ? AND name = ?
+        ", params![user.bytes(), name])?;
+
+        Ok(deleted > 0)
+    }
+
+    fn queue_crosspost(&self, crosspost: &QueuedCrossPost) -> Result<(), Error> {
+        self.conn.execute("
+            INSERT OR IGNORE INTO crosspost_queue(user_id, signature, target, base_url, attempts, next_attempt_utc_ms)
+            VALUES (?, ?, ?, ?, ?, ?)
+        ", params![
+            crosspost.user.bytes(),
+            crosspost.signature.bytes(),
+            crosspost.target.as_str(),
+            crosspost.base_url.as_str(),
+            crosspost.attempts,
+            Timestamp::now().unix_utc_ms,
+        ])?;
+
+        Ok(())
+    }
+
+    fn due_crossposts<'a>(&self, now: Timestamp, cb: FnIter<'a, QueuedCrossPost>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT user_id, signature, target, base_url, attempts
+            FROM crosspost_queue
+            WHERE next_attempt_utc_ms <= ?
+            ORDER BY next_attempt_utc_ms
+        ")?;
+
+        let mut rows = stmt.query(params![now.unix_utc_ms])?;
+
+        while let Some(row) = rows.next()? {
+            let crosspost = QueuedCrossPost {
+                user: UserID::from_vec(row.get(0)?)?,
+                signature: Signature::from_vec(row.get(1)?)?,
+                target: row.get(2)?,
+                base_url: row.get(3)?,
+                attempts: row.get(4)?,
+            };
+            if !cb(crosspost)? { break; }
+        }
+
+        Ok(())
+    }
+
+    fn crosspost_failed(&self, crosspost: &QueuedCrossPost, retry_at: Timestamp, error: &str) -> Result<(), Error> {
+        self.conn.execute("
+            UPDATE crosspost_queue
+            SET attempts = attempts + 1
+                , next_attempt_utc_ms = ?
+                , last_error = ?
+            WHERE user_id = ? AND signature = ? AND target = ?
+        ", params![
+            retry_at.unix_utc_ms,
+            error,
+            crosspost.user.bytes(),
+            crosspost.signature.bytes(),
+            crosspost.target.as_str(),
+        ])?;
+
+        Ok(())
+    }
+
+    fn finish_crosspost(&self, crosspost: &QueuedCrossPost) -> Result<(), Error> {
+        self.conn.execute("
+            DELETE FROM crosspost_queue
+            WHERE user_id = ? AND signature = ? AND target = ?
+        ", params![
+            crosspost.user.bytes(),
+            crosspost.signature.bytes(),
+            crosspost.target.as_str(),
+        ])?;
+
+        Ok(())
+    }
+
+    fn ipfs_cid(&self, user: &UserID, signature: &Signature, file_name: &str) -> Result<Option<String>, Error> {
+        let cid = self.conn.query_row("
+            SELECT cid
+            FROM ipfs_cid
+            WHERE user_id = ? AND signature = ? AND file_name = ?
+        ", params![user.bytes(), signature.bytes(), file_name], |row| row.get(0)).optional()?;
+
+        Ok(cid)
+    }
+
+    fn set_ipfs_cid(&self, user: &UserID, signature: &Signature, file_name: &str, cid: &str) -> Result<(), Error> {
+        self.conn.execute("
+            INSERT OR REPLACE INTO ipfs_cid(user_id, signature, file_name, cid)
+            VALUES (?, ?, ?, ?)
+        ", params![user.bytes(), signature.bytes(), file_name, cid])?;
+
+        Ok(())
+    }
+
+    fn queue_ipfs_pin(&self, pin: &QueuedIpfsPin) -> Result<(), Error> {
+        self.conn.execute("
+            INSERT OR IGNORE INTO ipfs_queue(user_id, signature, file_name, attempts, next_attempt_utc_ms)
+            VALUES (?, ?, ?, ?, ?)
+        ", params![
+            pin.user.bytes(),
+            pin.signature.bytes(),
+            pin.file_name.as_str(),
+            pin.attempts,
+            Timestamp::now().unix_utc_ms,
         ])?;
 
-        let to_item_profile_row = |row: &Row<'_>| -> Result<ItemDisplayRow, Error> {
+        Ok(())
+    }
 
-            let item = ItemRow{
+    fn due_ipfs_pins<'a>(&self, now: Timestamp, cb: FnIter<'a, QueuedIpfsPin>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT user_id, signature, file_name, attempts
+            FROM ipfs_queue
+            WHERE next_attempt_utc_ms <= ?
+            ORDER BY next_attempt_utc_ms
+        ")?;
+
+        let mut rows = stmt.query(params![now.unix_utc_ms])?;
+
+        while let Some(row) = rows.next()? {
+            let pin = QueuedIpfsPin {
                 user: UserID::from_vec(row.get(0)?)?,
                 signature: Signature::from_vec(row.get(1)?)?,
-                timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
-                received: Timestamp{ unix_utc_ms: row.get(3)? },
-                item_bytes: row.get(4)?,
+                file_name: row.get(2)?,
+                attempts: row.get(3)?,
             };
+            if !cb(pin)? { break; }
+        }
 
-            let display_name: Option<String> = row.get(5)?;
-            let follow_display_name: Option<String> = row.get(6)?;
-            fn not_empty(it: &String) -> bool { !it.trim().is_empty() }
+        Ok(())
+    }
 
-            Ok(ItemDisplayRow{
-                item,
-                // Prefer displaying the name that this user has assigned to the follow.
-                // TODO: This seems maybe business-logic-y? Should we move it out of Backend?
-                display_name: follow_display_name.filter(not_empty).or(display_name).filter(not_empty),
-            })
-        };
+    fn ipfs_pin_failed(&self, pin: &QueuedIpfsPin, retry_at: Timestamp, error: &str) -> Result<(), Error> {
+        self.conn.execute("
+            UPDATE ipfs_queue
+            SET attempts = attempts + 1
+                , next_attempt_utc_ms = ?
+                , last_error = ?
+            WHERE user_id = ? AND signature = ? AND file_name = ?
+        ", params![
+            retry_at.unix_utc_ms,
+            error,
+            pin.user.bytes(),
+            pin.signature.bytes(),
+            pin.file_name.as_str(),
+        ])?;
 
-        while let Some(row) = rows.next()? {
-            let item = to_item_profile_row(row)?;
-            let result = callback(item)?;
-            if !result { break; }
-        }
+        Ok(())
+    }
 
-        Ok( () )
+    fn finish_ipfs_pin(&self, pin: &QueuedIpfsPin) -> Result<(), Error> {
+        self.conn.execute("
+            DELETE FROM ipfs_queue
+            WHERE user_id = ? AND signature = ? AND file_name = ?
+        ", params![
+            pin.user.bytes(),
+            pin.signature.bytes(),
+            pin.file_name.as_str(),
+        ])?;
+
+        Ok(())
     }
 
-    fn server_user(&self, user: &UserID)
-    -> Result<Option<backend::ServerUser>, Error> 
-    { 
+    fn activitypub_replies<'a>(&self, user: &UserID, signature: &Signature, cb: FnIter<'a, ActivityPubReply>) -> Result<(), Error> {
         let mut stmt = self.conn.prepare("
-            SELECT notes, on_homepage
-            FROM server_user
-            WHERE user_id = ?
+            SELECT id, actor, actor_name, url, text, published_utc_ms, received_utc_ms
+            FROM activitypub_reply
+            WHERE user_id = ? AND signature = ?
+            ORDER BY published_utc_ms
         ")?;
 
-        let to_server_user = |row: &Row<'_>| {
-            let on_homepage: isize = row.get(1)?;
-             Ok(
-                 ServerUser {
-                    user: user.clone(),
-                    notes: row.get(0)?,
-                    on_homepage: on_homepage != 0,
-                }
-            )
-        };
+        let mut rows = stmt.query(params![user.bytes(), signature.bytes()])?;
 
-        let item = stmt.query_row(
-            params![user.bytes()],
-            to_server_user,
-        ).optional()?;
+        while let Some(row) = rows.next()? {
+            let reply = ActivityPubReply {
+                user: user.clone(),
+                signature: signature.clone(),
+                id: row.get(0)?,
+                actor: row.get(1)?,
+                actor_name: row.get(2)?,
+                url: row.get(3)?,
+                text: row.get(4)?,
+                published: Timestamp{ unix_utc_ms: row.get(5)? },
+                received: Timestamp{ unix_utc_ms: row.get(6)? },
+            };
+            if !cb(reply)? { break; }
+        }
 
-        Ok(item)
+        Ok(())
+    }
+
+    fn queue_activitypub_delivery(&self, delivery: &QueuedDelivery) -> Result<(), Error> {
+        self.conn.execute("
+            INSERT OR IGNORE INTO activitypub_delivery(inbox, user_id, signature, base_url, attempts, next_attempt_utc_ms)
+            VALUES (?, ?, ?, ?, ?, ?)
+        ", params![
+            delivery.inbox.as_str(),
+            delivery.user.bytes(),
+            delivery.signature.bytes(),
+            delivery.base_url.as_str(),
+            delivery.attempts,
+            Timestamp::now().unix_utc_ms,
+        ])?;
 
+        Ok(())
     }
 
-    fn server_users<'a>(&self, cb: FnIter<'a, ServerUser>) -> Result<(), Error> {
+    fn due_activitypub_deliveries<'a>(&self, now: Timestamp, cb: FnIter<'a, QueuedDelivery>) -> Result<(), Error> {
         let mut stmt = self.conn.prepare("
-            SELECT 
-                user_id
-                , notes
-                , on_homepage
-            FROM server_user
-            ORDER BY on_homepage, user_id
+            SELECT inbox, user_id, signature, base_url, attempts
+            FROM activitypub_delivery
+            WHERE next_attempt_utc_ms <= ?
+            ORDER BY next_attempt_utc_ms
         ")?;
 
-        let mut rows = stmt.query(NO_PARAMS)?;
+        let mut rows = stmt.query(params![now.unix_utc_ms])?;
 
         while let Some(row) = rows.next()? {
-            let on_homepage: isize = row.get(2)?;
-            let on_homepage = on_homepage != 0;
-
-            let user = ServerUser {
-                user: UserID::from_vec(row.get(0)?).compat()?,
-                notes: row.get(1)?,
-                on_homepage,
+            let delivery = QueuedDelivery {
+                inbox: row.get(0)?,
+                user: UserID::from_vec(row.get(1)?)?,
+                signature: Signature::from_vec(row.get(2)?)?,
+                base_url: row.get(3)?,
+                attempts: row.get(4)?,
             };
-            let more = cb(user)?;
-            if !more {break;}
+            if !cb(delivery)? { break; }
         }
 
         Ok(())
     }
-    
-    
-    fn user_item_exists(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> { 
-        let mut stmt = self.conn.prepare("
-            SELECT COUNT(*)
-            FROM item
-            WHERE user_id = ?
-            AND signature = ?
-        ")?;
 
-        let count: u32 = stmt.query_row(
-            params![
-                user.bytes(),
-                signature.bytes(),
-            ],
-            |row| { Ok(row.get(0)?) }
-        )?;
+    fn activitypub_delivery_failed(&self, delivery: &QueuedDelivery, retry_at: Timestamp, error: &str) -> Result<(), Error> {
+        self.conn.execute("
+            UPDATE activitypub_delivery
+            SET attempts = attempts + 1
+                , next_attempt_utc_ms = ?
+                , last_error = ?
+            WHERE inbox = ? AND user_id = ? AND signature = ?
+        ", params![
+            retry_at.unix_utc_ms,
+            error,
+            delivery.inbox.as_str(),
+            delivery.user.bytes(),
+            delivery.signature.bytes(),
+        ])?;
 
-        if count > 1 {
-            bail!("Found {} matches!? (user_id,signature) should be unique!", count);
-        }
+        Ok(())
+    }
 
-        Ok(count > 0)
+    fn finish_activitypub_delivery(&self, delivery: &QueuedDelivery) -> Result<(), Error> {
+        self.conn.execute("
+            DELETE FROM activitypub_delivery
+            WHERE inbox = ? AND user_id = ? AND signature = ?
+        ", params![
+            delivery.inbox.as_str(),
+            delivery.user.bytes(),
+            delivery.signature.bytes(),
+        ])?;
+
+        Ok(())
     }
 
-    fn user_item(&self, user: &UserID, signature: &Signature) -> Result<Option<ItemRow>, Error> { 
+    fn search<'a>(&self, query: &str, before: Timestamp, cb: FnIter<'a, ItemDisplayRow>) -> Result<(), Error> {
+        let query = match fts_query(query) {
+            None => return Ok(()),
+            Some(query) => query,
+        };
+
         let mut stmt = self.conn.prepare("
             SELECT
-                user_id
-                , signature
-                , unix_utc_ms
-                , received_utc_ms
-                , bytes
-            FROM item
-            WHERE user_id = ?
-            AND signature = ?
+                i.user_id
+                , i.signature
+                , i.unix_utc_ms
+                , i.received_utc_ms
+                , i.bytes
+                , p.display_name
+            FROM post_search AS s
+            INNER JOIN item AS i ON (i.user_id = s.user_id AND i.signature = s.signature)
+            LEFT OUTER JOIN profile AS p ON (p.user_id = i.user_id)
+            WHERE post_search MATCH ?
+            AND i.unix_utc_ms < ?
+            AND i.removed_utc_ms IS NULL
+            AND i.followers_only = 0
+            ORDER BY i.unix_utc_ms DESC
         ")?;
 
         let mut rows = stmt.query(params![
+            query,
+            before.unix_utc_ms,
+        ])?;
+
+        while let Some(row) = rows.next()? {
+            let display_name: Option<String> = row.get(5)?;
+            let display_row = ItemDisplayRow{
+                item: ItemRow{
+                    user: UserID::from_vec(row.get(0)?)?,
+                    signature: Signature::from_vec(row.get(1)?)?,
+                    timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
+                    received: Timestamp{ unix_utc_ms: row.get(3)? },
+                    item_bytes: row.get(4)?,
+                },
+                display_name: display_name.filter(|n| !n.trim().is_empty()),
+            };
+            if !cb(display_row)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn save_attachment(&self, user: &UserID, signature: &Signature, name: &str, bytes: &[u8]) -> Result<(), Error> {
+        let now = Timestamp::now();
+        let tx = self.conn.unchecked_transaction()?;
+        let inserted = tx.execute("
+            INSERT OR IGNORE INTO attachment(user_id, signature, name, bytes, received_utc_ms)
+            VALUES (?, ?, ?, ?, ?)
+        ", params![
             user.bytes(),
             signature.bytes(),
+            name,
+            bytes,
+            now.unix_utc_ms,
         ])?;
+        if inserted > 0 {
+            add_daily_stats(&tx, &DailyStats {
+                day: now.unix_utc_days(),
+                attachments: 1,
+                attachment_bytes: bytes.len() as u64,
+                ..Default::default()
+            })?;
+        }
+        tx.commit()?;
 
-        let row = match rows.next()? {
-            None => return Ok(None),
-            Some(row) => row,
-        };
+        Ok(())
+    }
 
-        let item = ItemRow{
-            user: UserID::from_vec(row.get(0)?)?,
-            signature: Signature::from_vec(row.get(1)?)?,
-            timestamp: Timestamp{ unix_utc_ms: row.get(2)? },
-            received: Timestamp{ unix_utc_ms: row.get(3)? },
-            item_bytes: row.get(4)?,
-        };
+    fn attachment(&self, user: &UserID, signature: &Signature, name: &str) -> Result<Option<Vec<u8>>, Error> {
+        self.find_attachment(user, signature, name, false)
+    }
 
-        if rows.next()?.is_some() {
-            bail!("Found multiple matching rows!? (user_id,signature) should be unique!");
-        }
+    fn followers_only_attachment(&self, user: &UserID, signature: &Signature, name: &str, viewer: &UserID) -> Result<Option<Vec<u8>>, Error> {
+        let may_view = self.may_view(user, viewer)?;
+        self.find_attachment(user, signature, name, may_view)
+    }
 
-        Ok(Some(item))
+    fn attachment_exists(&self, user: &UserID, signature: &Signature, name: &str) -> Result<bool, Error> {
+        let count: i64 = self.conn.query_row("
+            SELECT COUNT(*)
+            FROM attachment
+            WHERE user_id = ? AND signature = ? AND name = ?
+        ", params![user.bytes(), signature.bytes(), name], |row| row.get(0))?;
+
+        Ok(count > 0)
     }
 
-    fn save_user_item(&mut self, row: &ItemRow, item: &Item) -> Result<(), Error>
-    {
-        let tx = self.conn.savepoint().context("getting a transaction")?;
+    fn map_tile(&self, z: u32, x: u32, y: u32) -> Result<Option<MapTile>, Error> {
+        let tile = self.conn.query_row("
+            SELECT bytes, fetched_utc_ms
+            FROM map_tile
+            WHERE z = ? AND x = ? AND y = ?
+        ", params![z, x, y], |row| Ok(MapTile {
+            z, x, y,
+            bytes: row.get(0)?,
+            fetched: Timestamp{ unix_utc_ms: row.get(1)? },
+        })).optional()?;
 
-        let stmt = "
-            INSERT INTO item (
-                user_id
-                , signature
-                , unix_utc_ms
-                , received_utc_ms
-                , bytes
-            ) VALUES (?, ?, ?, ?, ?);
-       ";
+        Ok(tile)
+    }
 
-        tx.execute(stmt, params![
-            row.user.bytes(),
-            row.signature.bytes(),
-            row.timestamp.unix_utc_ms,
-            row.received.unix_utc_ms,
-            row.item_bytes.as_slice(),
+    fn save_map_tile(&self, tile: &MapTile) -> Result<(), Error> {
+        self.conn.execute("
+            INSERT OR REPLACE INTO map_tile(z, x, y, bytes, fetched_utc_ms)
+            VALUES (?, ?, ?, ?, ?)
+        ", params![
+            tile.z,
+            tile.x,
+            tile.y,
+            tile.bytes.as_slice(),
+            tile.fetched.unix_utc_ms,
         ])?;
 
-        if item.has_profile() {
-            update_profile(&tx, row, item)?;
-        }
-
-        tx.commit().context("committing")?;
         Ok(())
     }
 
-    fn add_server_user(&self, server_user: &ServerUser) -> Result<(), Error> {
-
-        let stmt = "
-            INSERT INTO server_user(user_id, notes, on_homepage)
-            VALUES (?,?,?)
-        ";
+    fn user_item_events<'a>(&self, user: &UserID, cb: FnIter<'a, ItemEvent>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT signature, event, created_utc_ms
+            FROM item_event
+            WHERE user_id = ?
+            ORDER BY created_utc_ms, rowid
+        ")?;
 
-        let on_homepage = if server_user.on_homepage { 1 } else { 0 };
+        let mut rows = stmt.query(params![user.bytes()])?;
 
-        self.conn.execute(stmt, params![
-            server_user.user.bytes(),
-            server_user.notes.as_str(),
-            on_homepage
-        ])?;
+        while let Some(row) = rows.next()? {
+            let event = ItemEvent {
+                user: user.clone(),
+                signature: Signature::from_vec(row.get(0)?)?,
+                kind: row.get::<_, String>(1)?.parse()?,
+                created: Timestamp{ unix_utc_ms: row.get(2)? },
+            };
+            let more = cb(event)?;
+            if !more {break;}
+        }
 
         Ok(())
     }
 
-    fn user_profile(&self, user: &UserID) -> Result<Option<ItemRow>, Error> {
+    fn user_item_sizes<'a>(&self, user: &UserID, by_size: bool, cb: FnIter<'a, ItemSize>) -> Result<(), Error> {
+        let order = if by_size { "item_bytes + attachment_bytes DESC" } else { "unix_utc_ms DESC" };
+        let mut stmt = self.conn.prepare(&format!("
+            SELECT
+                i.signature
+                , i.unix_utc_ms
+                , length(i.bytes) AS item_bytes
+                , COALESCE(SUM(length(a.bytes)), 0) AS attachment_bytes
+            FROM item AS i
+            LEFT OUTER JOIN attachment AS a USING (user_id, signature)
+            WHERE i.user_id = ?
+            AND i.removed_utc_ms IS NULL
+            GROUP BY i.signature
+            ORDER BY {}
+        ", order))?;
 
-        // TODO: I'm not crazy about making 2 queries here instead of a join, but it lets me
-        // re-use the user_item() loading logic.
-        let mut find_profile = self.conn.prepare("
-            SELECT user_id, signature
-            FROM profile
-            WHERE user_id = ?
-        ")?;
+        let mut rows = stmt.query(params![user.bytes()])?;
 
-        let mut rows = find_profile.query(params![user.bytes()])?;
-        let row = match rows.next()? {
-            None => return Ok(None),
-            Some(row) => row,
-        };
+        while let Some(row) = rows.next()? {
+            let size = ItemSize {
+                signature: Signature::from_vec(row.get(0)?)?,
+                timestamp: Timestamp{ unix_utc_ms: row.get(1)? },
+                item_bytes: row.get::<_, i64>(2)? as u64,
+                attachment_bytes: row.get::<_, i64>(3)? as u64,
+            };
+            let more = cb(size)?;
+            if !more {break;}
+        }
 
-        let user_id: Vec<u8> = row.get(0)?;
-        let signature: Vec<u8> = row.get(1)?;
+        Ok(())
+    }
 
-        let user_id = UserID::from_vec(user_id)?;
-        let signature = Signature::from_vec(signature)?;
+    fn set_dead_links(&self, user: &UserID, signature: &Signature, links: &[DeadLink]) -> Result<(), Error> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("
+            DELETE FROM dead_link
+            WHERE user_id = ? AND signature = ?
+        ", params![user.bytes(), signature.bytes()])?;
 
-        self.user_item(&user_id, &signature)
+        for link in links {
+            tx.execute("
+                INSERT OR REPLACE INTO dead_link(user_id, signature, url, error, checked_utc_ms)
+                VALUES (?, ?, ?, ?, ?)
+            ", params![
+                user.bytes(),
+                signature.bytes(),
+                link.url.as_str(),
+                link.error.as_str(),
+                link.checked.unix_utc_ms,
+            ])?;
+        }
+        tx.commit()?;
+
+        Ok(())
     }
 
-    fn user_known(&self, user_id: &UserID) -> Result<bool, Error> {
-        let mut query = self.conn.prepare("
-            SELECT
-                EXISTS(SELECT user_id FROM server_user WHERE user_id = :user_id)
-                OR EXISTS(
-                    SELECT followed_user_id
-                    FROM follow AS f
-                    INNER JOIN server_user AS su ON (f.source_user_id = su.user_id)
-                    WHERE followed_user_id = :user_id
-                )
+    fn user_dead_links<'a>(&self, user: &UserID, cb: FnIter<'a, DeadLink>) -> Result<(), Error> {
+        let mut stmt = self.conn.prepare("
+            SELECT d.signature, d.url, d.error, d.checked_utc_ms
+            FROM dead_link AS d
+            INNER JOIN item AS i USING (user_id, signature)
+            WHERE d.user_id = ?
+            AND i.removed_utc_ms IS NULL
+            ORDER BY i.unix_utc_ms DESC, d.url
         ")?;
 
-        let mut result = query.query_named(&[
-            (":user_id", &user_id.bytes())
-        ])?;
+        let mut rows = stmt.query(params![user.bytes()])?;
 
-        let row = match result.next()? {
-            Some(row) => row,
-            None => bail!("Expected at least 1 row from SQLite."),
-        };
+        while let Some(row) = rows.next()? {
+            let link = DeadLink {
+                signature: Signature::from_vec(row.get(0)?)?,
+                url: row.get(1)?,
+                error: row.get(2)?,
+                checked: Timestamp{ unix_utc_ms: row.get(3)? },
+            };
+            if !cb(link)? { break; }
+        }
 
-        Ok(row.get(0)?)
+        Ok(())
     }
 
     fn quota_check_item(&self, user_id: &UserID, bytes: &[u8], item: &Item) -> Result<Option<QuotaDenyReason>, Error> {
         
-        if self.server_user(user_id)?.is_some() {
-            // TODO: Implement optional quotas for "server users".
-            // For now, there is no quota for them:
-            return Ok(None);
+        if let Some(quota) = self.user_quota(user_id)? {
+            return self.check_quota(user_id, bytes, item, &quota);
         };
 
         // Check those followed by "server users":
@@ -0,0 +1,281 @@
+//! Tests for the sqlite backend, run against an in-memory database.
+
+use super::Connection;
+use crate::backend::Backend;
+
+/// Open a fresh, fully-migrated, in-memory database.
+fn memory_connection() -> Connection {
+    let manager = r2d2_sqlite::SqliteConnectionManager::memory();
+    // Each in-memory connection is its own database, so only ever use one:
+    let pool = r2d2::Pool::builder().max_size(1).build(manager).expect("pool");
+    let conn = Connection{ conn: pool.get().expect("connection") };
+    conn.setup().expect("setup");
+    conn
+}
+
+/// Returns the "detail" column of `EXPLAIN QUERY PLAN` for some SQL.
+/// Any parameters are bound to NULL, which doesn't change the plan.
+fn query_plan(conn: &Connection, sql: &str) -> Vec<String> {
+    let mut stmt = conn.conn.prepare(&format!("EXPLAIN QUERY PLAN {}", sql)).expect("prepare");
+    let nulls = vec![rusqlite::types::Null; stmt.parameter_count()];
+    let rows = stmt.query_map(nulls, |row| row.get(3)).expect("query");
+    rows.collect::<Result<_,_>>().expect("rows")
+}
+
+fn assert_uses_index(conn: &Connection, sql: &str, index: &str) {
+    let plan = query_plan(conn, sql);
+    assert!(
+        plan.iter().any(|line| line.contains(index)),
+        "Expected query to use {}. Plan was: {:#?}", index, plan
+    );
+}
+
+#[test]
+fn migrates_to_current_version() {
+    let conn = memory_connection();
+    assert_eq!(Some(super::CURRENT_VERSION), conn.get_version().unwrap());
+
+    // Running setup on an up-to-date DB is a no-op:
+    conn.setup().unwrap();
+    assert_eq!(Some(super::CURRENT_VERSION), conn.get_version().unwrap());
+}
+
+#[test]
+fn user_items_uses_index() {
+    let conn = memory_connection();
+    assert_uses_index(&conn, super::USER_ITEMS_SQL, "item_user_timestamp_idx");
+}
+
+#[test]
+fn homepage_items_uses_indexes() {
+    let conn = memory_connection();
+    assert_uses_index(&conn, super::HOMEPAGE_ITEMS_SQL, "item_user_timestamp_idx");
+    assert_uses_index(&conn, super::HOMEPAGE_ITEMS_SQL, "server_user_homepage_idx");
+}
+
+#[test]
+fn signature_lookups_use_index() {
+    let conn = memory_connection();
+    // Nothing looks items up by signature alone yet, so there's no query of
+    // the backend's to check for this one:
+    assert_uses_index(&conn, "
+        SELECT user_id FROM item WHERE signature = x'00'
+    ", "item_signature_idx");
+    assert_uses_index(&conn, super::FIND_ITEM_SQL, "item_primary_idx");
+}
+
+#[test]
+fn expiring_items_uses_index() {
+    let conn = memory_connection();
+    assert_uses_index(&conn, super::EXPIRED_ITEMS_SQL, "item_expires_idx");
+}
+
+#[test]
+fn item_events_are_append_only() {
+    use crate::backend::Timestamp;
+
+    let conn = memory_connection();
+    conn.conn.execute("
+        INSERT INTO item_event(user_id, signature, event, created_utc_ms)
+        VALUES (x'00', x'00', 'received', ?)
+    ", rusqlite::params![Timestamp::now().unix_utc_ms]).unwrap();
+
+    assert!(conn.run("UPDATE item_event SET event = 'removed'").is_err());
+    assert!(conn.run("DELETE FROM item_event").is_err());
+
+    // ... except that old ones may be pruned, once there's a retention period:
+    conn.run("UPDATE item_event_retention SET days = 30").unwrap();
+    conn.run("
+        INSERT INTO item_event(user_id, signature, event, created_utc_ms)
+        VALUES (x'00', x'01', 'received', 1)
+    ").unwrap();
+    assert!(conn.run("UPDATE item_event SET event = 'removed' WHERE created_utc_ms = 1").is_err());
+    conn.run("DELETE FROM item_event WHERE created_utc_ms = 1").unwrap();
+}
+
+#[test]
+fn fts_queries_are_quoted() {
+    use super::fts_query;
+
+    assert_eq!(None, fts_query("  "));
+    assert_eq!(Some(r#""cats" "OR" "dogs*""#.to_string()), fts_query("cats OR dogs*"));
+    assert_eq!(Some(r#""say""hi""#.to_string()), fts_query(r#"say"hi"#));
+
+    // They're valid FTS5 syntax:
+    let conn = memory_connection();
+    let mut found = 0;
+    conn.search(r#"NEAR( "unbalanced"#, crate::backend::Timestamp::now(), &mut |_| {
+        found += 1;
+        Ok(true)
+    }).unwrap();
+    assert_eq!(0, found);
+}
+
+#[test]
+fn daily_stats_accumulate() {
+    use crate::backend::Timestamp;
+
+    let conn = memory_connection();
+    let day = Timestamp{ unix_utc_ms: 3 * 24 * 60 * 60 * 1000 + 1 };
+    conn.record_rejection(day).unwrap();
+    conn.record_rejection(day).unwrap();
+
+    let mut days = vec![];
+    conn.daily_stats(0, &mut |stats| {
+        days.push(stats);
+        Ok(true)
+    }).unwrap();
+    assert_eq!(1, days.len());
+    assert_eq!(3, days[0].day);
+    assert_eq!(2, days[0].rejections);
+    assert_eq!(0, days[0].items);
+}
+
+#[test]
+fn seeded_items_are_saved() {
+    sodiumoxide::init().unwrap();
+    let mut conn = memory_connection();
+    let report = crate::seed::seed(&mut conn, 3, 20, 1).unwrap();
+    assert_eq!(3, report.users.len());
+    // A profile for each user, plus their posts:
+    assert_eq!(3 + 3 * 20, report.items);
+
+    let mut saved = 0;
+    conn.daily_stats(0, &mut |stats| {
+        saved += stats.items;
+        Ok(true)
+    }).unwrap();
+    assert_eq!(report.items as u64, saved);
+}
+
+#[test]
+fn request_signatures_are_used_once() {
+    use crate::backend::{Signature, Timestamp};
+
+    let conn = memory_connection();
+    let now = Timestamp::now().unix_utc_ms;
+    let signature = Signature::from_vec(vec![1; 64]).unwrap();
+    let expires = Timestamp{ unix_utc_ms: now + 60_000 };
+    assert!(conn.use_request_signature(&signature, expires).unwrap());
+    assert!(!conn.use_request_signature(&signature, expires).unwrap());
+
+    // Once expired, it's forgotten:
+    let other = Signature::from_vec(vec![2; 64]).unwrap();
+    let expired = Timestamp{ unix_utc_ms: now - 1 };
+    assert!(conn.use_request_signature(&other, expired).unwrap());
+    assert!(conn.use_request_signature(&other, expired).unwrap());
+}
+
+#[test]
+fn comment_replies_skip_votes() {
+    use crate::backend::{ItemRow, Signature, Timestamp, UserID};
+    use crate::protos::Item;
+    use protobuf::Message as _;
+
+    let mut conn = memory_connection();
+    let mut save = |user: u8, signature: u8, item: &Item| {
+        let row = ItemRow{
+            user: UserID::from_vec(vec![user; 32]).unwrap(),
+            signature: Signature::from_vec(vec![signature; 64]).unwrap(),
+            timestamp: Timestamp{ unix_utc_ms: item.timestamp_ms_utc },
+            received: Timestamp::now(),
+            item_bytes: item.write_to_bytes().unwrap(),
+        };
+        conn.save_user_item(&row, item).unwrap();
+    };
+
+    let mut poll = Item::new();
+    poll.timestamp_ms_utc = 1000;
+    poll.mut_poll().question = "Yes?".into();
+    poll.mut_poll().options.push("Yes".into());
+    poll.mut_poll().options.push("No".into());
+    poll.mut_poll().close_ms_utc = 10_000;
+    save(1, 1, &poll);
+
+    let mut vote = Item::new();
+    vote.timestamp_ms_utc = 2000;
+    vote.mut_vote().mut_poll().mut_user_id().set_bytes(vec![1; 32]);
+    vote.mut_vote().mut_poll().mut_signature().set_bytes(vec![1; 64]);
+    save(2, 2, &vote);
+
+    let mut comment = Item::new();
+    comment.timestamp_ms_utc = 3000;
+    comment.mut_comment().text = "Maybe.".into();
+    comment.mut_comment().mut_reply_to().mut_user_id().set_bytes(vec![1; 32]);
+    comment.mut_comment().mut_reply_to().mut_signature().set_bytes(vec![1; 64]);
+    save(2, 3, &comment);
+
+    let mut replies = vec![];
+    let poll_user = UserID::from_vec(vec![1; 32]).unwrap();
+    let poll_signature = Signature::from_vec(vec![1; 64]).unwrap();
+    conn.comment_replies(&poll_user, &poll_signature, &mut |(_, signature)| {
+        replies.push(signature.bytes().to_vec());
+        Ok(true)
+    }).unwrap();
+    assert_eq!(vec![vec![3; 64]], replies);
+}
+
+#[test]
+fn item_events_are_kept_for_the_retention_period() {
+    use crate::backend::{Retention, Timestamp};
+    use rusqlite::params;
+
+    const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+    let conn = memory_connection();
+    let now = Timestamp::now();
+    let add_event = |days_ago: i64| {
+        conn.conn.execute(
+            "INSERT INTO item_event(user_id, signature, event, created_utc_ms) VALUES (?, ?, 'received', ?)",
+            params![vec![1u8; 32], vec![days_ago as u8; 64], now.unix_utc_ms - days_ago * DAY_MS],
+        ).unwrap();
+    };
+    add_event(2);
+    add_event(10);
+    let count = || -> i64 {
+        conn.conn.query_row("SELECT COUNT(*) FROM item_event", rusqlite::NO_PARAMS, |row| row.get(0)).unwrap()
+    };
+    let retention = |days| Retention{
+        item_event_days: days,
+        sync_report_days: 0,
+        security_report_days: 0,
+        stale_queue_days: 0,
+    };
+
+    // Kept forever by default:
+    assert!(conn.conn.execute("DELETE FROM item_event", rusqlite::NO_PARAMS).is_err());
+
+    assert_eq!(1, conn.prune(&retention(7), now).unwrap().item_events);
+    assert_eq!(1, count());
+
+    // Events younger than the retention period can't be deleted, even by hand:
+    assert!(conn.conn.execute("DELETE FROM item_event", rusqlite::NO_PARAMS).is_err());
+    assert_eq!(1, count());
+
+    assert_eq!(0, conn.prune(&retention(0), now).unwrap().item_events);
+    assert_eq!(1, count());
+}
+
+#[test]
+fn security_reports_are_capped() {
+    use crate::backend::{MAX_SECURITY_REPORTS, SecurityReport, SecurityReportKind, Timestamp};
+
+    let conn = memory_connection();
+    let extra = 5;
+    for n in 0..(MAX_SECURITY_REPORTS + extra) {
+        conn.save_security_report(&SecurityReport{
+            created: Timestamp{ unix_utc_ms: n as i64 },
+            kind: SecurityReportKind::Csp,
+            item: None,
+            detail: format!("Report {}", n),
+        }).unwrap();
+    }
+
+    let mut kept = vec![];
+    conn.security_reports(Timestamp{ unix_utc_ms: i64::MAX }, &mut |report| {
+        kept.push(report.created.unix_utc_ms);
+        Ok(true)
+    }).unwrap();
+    assert_eq!(MAX_SECURITY_REPORTS as usize, kept.len());
+    // The oldest were dropped:
+    assert_eq!(Some(&(extra as i64)), kept.last());
+}
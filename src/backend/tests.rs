@@ -0,0 +1,759 @@
+//! Behavior that every Backend must have.
+//!
+//! Each `check_*` function takes a Factory for a new, empty backend, so that
+//! any implementation can be tested against the same expectations. The tests
+//! at the bottom run them against the ones we have.
+
+use protobuf::Message as _;
+
+use crate::protos::Item;
+use super::{Backend, DeadLink, Factory, ItemEventKind, ItemRow, Quota, QuotaDenyReason, ServerUser, Signature, Timestamp, UserID};
+
+/// Run every check against backends from `new_factory`.
+pub(crate) fn check_all(new_factory: &dyn Fn() -> Box<dyn Factory>) {
+    check_item_storage(new_factory().as_ref());
+    check_profiles(new_factory().as_ref());
+    check_pagination(new_factory().as_ref());
+    check_quotas(new_factory().as_ref());
+    check_dead_links(new_factory().as_ref());
+    check_deleting(new_factory().as_ref());
+    check_quarantine(new_factory().as_ref());
+    check_author_deletes(new_factory().as_ref());
+    check_reactions(new_factory().as_ref());
+    check_followers_only(new_factory().as_ref());
+    check_mutes(new_factory().as_ref());
+    check_poll_votes(new_factory().as_ref());
+    check_monthly_stats(new_factory().as_ref());
+}
+
+/// Items can be saved, found, removed and restored.
+pub(crate) fn check_item_storage(factory: &dyn Factory) {
+    let mut conn = open(factory);
+    let author = user(0x10);
+
+    assert!(!conn.user_item_exists(&author, &signature(1)).unwrap());
+    let saved = save(conn.as_mut(), &author, 1, &post(1000, "Hello"));
+
+    assert!(conn.user_item_exists(&author, &signature(1)).unwrap());
+    let found = conn.user_item(&author, &signature(1)).unwrap().expect("saved item");
+    assert_eq!(saved.item_bytes, found.item_bytes);
+    assert_eq!(1000, found.timestamp.unix_utc_ms);
+    assert!(conn.user_item(&author, &signature(2)).unwrap().is_none());
+    assert!(conn.user_item(&user(0x90), &signature(1)).unwrap().is_none());
+
+    assert_eq!(1, count_user_items(conn.as_ref(), &author, i64::MAX));
+
+    assert!(conn.remove_user_item(&author, &signature(1)).unwrap());
+    assert!(conn.user_item(&author, &signature(1)).unwrap().is_none());
+    assert_eq!(0, count_user_items(conn.as_ref(), &author, i64::MAX));
+    assert!(!conn.remove_user_item(&author, &signature(1)).unwrap());
+
+    assert!(conn.restore_user_item(&author, &signature(1)).unwrap());
+    assert!(conn.user_item(&author, &signature(1)).unwrap().is_some());
+}
+
+/// Quarantined items aren't served until they're approved, and the item
+/// events say so.
+pub(crate) fn check_quarantine(factory: &dyn Factory) {
+    let mut conn = open(factory);
+    // In different shards, if there are some:
+    let first = user(0x10);
+    let second = user(0xF0);
+
+    save(conn.as_mut(), &first, 1, &post(1000, "First"));
+    save(conn.as_mut(), &second, 2, &post(2000, "Second"));
+    assert!(conn.quarantine_user_item(&first, &signature(1)).unwrap());
+    assert!(conn.quarantine_user_item(&second, &signature(2)).unwrap());
+    assert!(!conn.quarantine_user_item(&first, &signature(1)).unwrap());
+    assert!(conn.user_item(&first, &signature(1)).unwrap().is_none());
+
+    let mut quarantined = vec![];
+    conn.quarantined_items(&mut |row| {
+        quarantined.push(row.signature.bytes().to_vec());
+        Ok(true)
+    }).unwrap();
+    quarantined.sort();
+    assert_eq!(vec![signature(1).bytes().to_vec(), signature(2).bytes().to_vec()], quarantined);
+
+    assert!(conn.approve_user_item(&first, &signature(1)).unwrap());
+    assert!(conn.user_item(&first, &signature(1)).unwrap().is_some());
+    assert!(!conn.approve_user_item(&first, &signature(1)).unwrap());
+
+    // Removing an item isn't quarantining it:
+    assert!(conn.remove_user_item(&first, &signature(1)).unwrap());
+    assert!(!conn.approve_user_item(&first, &signature(1)).unwrap());
+
+    let mut count = 0;
+    conn.quarantined_items(&mut |row| {
+        assert_eq!(signature(2).bytes(), row.signature.bytes());
+        count += 1;
+        Ok(true)
+    }).unwrap();
+    assert_eq!(1, count);
+
+    conn.record_item_synced(&second, &signature(2), Timestamp::now()).unwrap();
+
+    let mut kinds = vec![];
+    conn.user_item_events(&first, &mut |event| {
+        kinds.push(event.kind);
+        Ok(true)
+    }).unwrap();
+    assert_eq!(vec![
+        ItemEventKind::Received,
+        ItemEventKind::Quarantined,
+        ItemEventKind::Approved,
+        ItemEventKind::Removed,
+    ], kinds);
+
+    let mut kinds = vec![];
+    conn.user_item_events(&second, &mut |event| {
+        kinds.push(event.kind);
+        Ok(true)
+    }).unwrap();
+    assert_eq!(vec![ItemEventKind::Received, ItemEventKind::Quarantined, ItemEventKind::Synced], kinds);
+}
+
+/// A user's profile is the one with the latest timestamp, whatever order
+/// they arrive in.
+pub(crate) fn check_profiles(factory: &dyn Factory) {
+    let mut conn = open(factory);
+    let author = user(0x10);
+    let followed = user(0x90);
+
+    assert!(conn.user_profile(&author).unwrap().is_none());
+
+    save(conn.as_mut(), &author, 2, &profile(2000, "Newer", &[&followed]));
+    save(conn.as_mut(), &author, 1, &profile(1000, "Older", &[]));
+
+    let row = conn.user_profile(&author).unwrap().expect("profile");
+    assert_eq!(signature(2).bytes(), row.signature.bytes());
+    let mut item = Item::new();
+    item.merge_from_bytes(&row.item_bytes).unwrap();
+    assert_eq!("Newer", item.get_profile().display_name);
+
+    // Follows come from the latest profile:
+    assert!(conn.follows(&author, &followed).unwrap());
+    assert!(!conn.follows(&followed, &author).unwrap());
+
+    save(conn.as_mut(), &author, 3, &profile(3000, "Newest", &[]));
+    assert!(!conn.follows(&author, &followed).unwrap());
+}
+
+/// Listings are newest first, start before the given time, and stop when
+/// the callback says to.
+pub(crate) fn check_pagination(factory: &dyn Factory) {
+    let mut conn = open(factory);
+    // In different shards, if there are some:
+    let first = user(0x10);
+    let second = user(0xF0);
+    for author in &[&first, &second] {
+        conn.add_server_user(&ServerUser{
+            user: (*author).clone(),
+            notes: String::new(),
+            on_homepage: true,
+        }).unwrap();
+    }
+
+    for i in 1..=5 {
+        save(conn.as_mut(), &first, i, &post(i as i64 * 1000, "First"));
+        save(conn.as_mut(), &second, 10 + i, &post(i as i64 * 1000 + 500, "Second"));
+    }
+
+    let mut timestamps = vec![];
+    conn.user_items(&first, Timestamp{ unix_utc_ms: 4000 }, &mut |row| {
+        timestamps.push(row.timestamp.unix_utc_ms);
+        Ok(true)
+    }).unwrap();
+    assert_eq!(vec![3000, 2000, 1000], timestamps);
+
+    let mut timestamps = vec![];
+    conn.homepage_items(Timestamp{ unix_utc_ms: 4000 }, &mut |row| {
+        timestamps.push(row.item.timestamp.unix_utc_ms);
+        Ok(timestamps.len() < 4)
+    }).unwrap();
+    assert_eq!(vec![3500, 3000, 2500, 2000], timestamps);
+
+    // The next page starts where that one stopped:
+    let mut timestamps = vec![];
+    conn.homepage_items(Timestamp{ unix_utc_ms: 2000 }, &mut |row| {
+        timestamps.push(row.item.timestamp.unix_utc_ms);
+        Ok(true)
+    }).unwrap();
+    assert_eq!(vec![1500, 1000], timestamps);
+}
+
+/// Only known users may post, within their quotas.
+pub(crate) fn check_quotas(factory: &dyn Factory) {
+    let mut conn = open(factory);
+    let author = user(0x10);
+    let item = post(1000, "Hello");
+    let bytes = item.write_to_bytes().unwrap();
+
+    let reason = conn.quota_check_item(&author, &bytes, &item).unwrap();
+    assert!(matches!(reason, Some(QuotaDenyReason::UnknownUser)), "{:?}", reason.map(|r| r.to_string()));
+
+    conn.add_server_user(&ServerUser{ user: author.clone(), notes: String::new(), on_homepage: false }).unwrap();
+    assert!(conn.quota_check_item(&author, &bytes, &item).unwrap().is_none());
+
+    // Users that server users follow may post too:
+    let friend = user(0x90);
+    save(conn.as_mut(), &author, 1, &profile(1000, "Author", &[&friend]));
+    assert!(conn.quota_check_item(&friend, &bytes, &item).unwrap().is_none());
+
+    assert!(conn.set_user_quota(&author, &Quota{ max_bytes: bytes.len() as u64 * 2, max_items_per_day: 0 }).unwrap());
+    save(conn.as_mut(), &author, 2, &post(2000, "Hello"));
+    let reason = conn.quota_check_item(&author, &bytes, &item).unwrap();
+    assert!(matches!(reason, Some(QuotaDenyReason::MaxBytes{..})), "{:?}", reason.map(|r| r.to_string()));
+
+    // Items received in the last day count, not their timestamps:
+    assert!(conn.set_user_quota(&author, &Quota{ max_bytes: 0, max_items_per_day: 2 }).unwrap());
+    let reason = conn.quota_check_item(&author, &bytes, &item).unwrap();
+    assert!(matches!(reason, Some(QuotaDenyReason::MaxItemsPerDay{ items: 2, .. })), "{:?}", reason.map(|r| r.to_string()));
+
+    assert!(!conn.set_user_quota(&friend, &Quota{ max_bytes: 1, max_items_per_day: 1 }).unwrap());
+}
+
+/// Each check replaces an item's dead links, and removed items' aren't listed.
+pub(crate) fn check_dead_links(factory: &dyn Factory) {
+    let mut conn = open(factory);
+    let author = user(0x10);
+    save(conn.as_mut(), &author, 1, &post(1000, "[one](https://example.com/1)"));
+    save(conn.as_mut(), &author, 2, &post(2000, "[two](https://example.com/2)"));
+
+    let dead = |n: u8, url: &str| DeadLink{
+        signature: signature(n),
+        url: url.into(),
+        error: "404 Not Found".into(),
+        checked: Timestamp::now(),
+    };
+    conn.set_dead_links(&author, &signature(1), &[dead(1, "https://example.com/1")]).unwrap();
+    conn.set_dead_links(&author, &signature(2), &[dead(2, "https://example.com/2")]).unwrap();
+
+    let list = |conn: &dyn Backend| {
+        let mut urls = vec![];
+        conn.user_dead_links(&author, &mut |link| {
+            urls.push(link.url);
+            Ok(true)
+        }).unwrap();
+        urls
+    };
+    assert_eq!(vec!["https://example.com/2", "https://example.com/1"], list(conn.as_ref()));
+
+    conn.set_dead_links(&author, &signature(2), &[]).unwrap();
+    assert_eq!(vec!["https://example.com/1"], list(conn.as_ref()));
+
+    conn.remove_user_item(&author, &signature(1)).unwrap();
+    assert!(list(conn.as_ref()).is_empty());
+}
+
+/// `all_items` lists removed items too, and deleted items are gone for good.
+pub(crate) fn check_deleting(factory: &dyn Factory) {
+    let mut conn = open(factory);
+    let first = user(0x10);
+    let second = user(0xF0);
+    save(conn.as_mut(), &first, 1, &post(1000, "One"));
+    save(conn.as_mut(), &second, 2, &post(2000, "Two"));
+    conn.remove_user_item(&second, &signature(2)).unwrap();
+
+    let count_all = |conn: &dyn Backend| {
+        let mut count = 0;
+        conn.all_items(&mut |_| {
+            count += 1;
+            Ok(true)
+        }).unwrap();
+        count
+    };
+    assert_eq!(2, count_all(conn.as_ref()));
+
+    assert!(conn.delete_user_item(&first, &signature(1)).unwrap());
+    assert!(conn.delete_user_item(&second, &signature(2)).unwrap());
+    assert!(!conn.delete_user_item(&first, &signature(1)).unwrap());
+    assert_eq!(0, count_all(conn.as_ref()));
+    assert!(!conn.restore_user_item(&second, &signature(2)).unwrap());
+
+    // Items can be saved again, since they're really gone:
+    save(conn.as_mut(), &first, 1, &post(1000, "One"));
+    assert_eq!(1, count_user_items(conn.as_ref(), &first, i64::MAX));
+}
+
+/// A `Delete` item deletes an earlier item, which then can't be saved again.
+pub(crate) fn check_author_deletes(factory: &dyn Factory) {
+    let mut conn = open(factory);
+    let author = user(0x10);
+    save(conn.as_mut(), &author, 1, &post(1000, "Oops"));
+    save(conn.as_mut(), &author, 2, &profile(1500, "Author", &[]));
+
+    save(conn.as_mut(), &author, 3, &delete(2000, 1));
+    assert!(conn.user_item(&author, &signature(1)).unwrap().is_none());
+    assert!(conn.user_item_deleted(&author, &signature(1)).unwrap());
+    assert!(!conn.user_item_deleted(&author, &signature(3)).unwrap());
+    // Just the profile and the Delete:
+    assert_eq!(2, count_user_items(conn.as_ref(), &author, i64::MAX));
+
+    let again = item_row(&author, 1, &post(1000, "Oops"));
+    assert!(conn.save_user_item(&again, &post(1000, "Oops")).is_err());
+
+    // Deleting an item we haven't seen yet keeps it from being saved later:
+    save(conn.as_mut(), &author, 4, &delete(3000, 5));
+    assert!(conn.user_item_deleted(&author, &signature(5)).unwrap());
+    let later = item_row(&author, 5, &post(2500, "Later"));
+    assert!(conn.save_user_item(&later, &post(2500, "Later")).is_err());
+
+    // Profiles and Deletes can't be deleted:
+    let row = item_row(&author, 6, &delete(4000, 2));
+    assert!(conn.save_user_item(&row, &delete(4000, 2)).is_err());
+    let row = item_row(&author, 7, &delete(4000, 3));
+    assert!(conn.save_user_item(&row, &delete(4000, 3)).is_err());
+    assert!(conn.user_profile(&author).unwrap().is_some());
+    assert!(!conn.user_item_deleted(&author, &signature(3)).unwrap());
+}
+
+/// Only each user's latest reaction to an item counts, until they delete it.
+pub(crate) fn check_reactions(factory: &dyn Factory) {
+    let mut conn = open(factory);
+    let author = user(0x10);
+    let fan = user(0x20);
+    let critic = user(0x30);
+    save(conn.as_mut(), &author, 1, &post(1000, "Hello"));
+
+    let reaction = |timestamp_ms_utc: i64, emoji: &str| {
+        let mut item = Item::new();
+        item.timestamp_ms_utc = timestamp_ms_utc;
+        let reaction = item.mut_reaction();
+        reaction.mut_item().mut_user_id().set_bytes(author.bytes().to_vec());
+        reaction.mut_item().mut_signature().set_bytes(signature(1).bytes().to_vec());
+        reaction.emoji = emoji.into();
+        item
+    };
+    save(conn.as_mut(), &fan, 2, &reaction(2000, "👍"));
+    save(conn.as_mut(), &fan, 3, &reaction(3000, "❤"));
+    save(conn.as_mut(), &critic, 4, &reaction(2000, ""));
+
+    let reactions = |conn: &dyn Backend| {
+        let mut found = vec![];
+        conn.item_reactions(&author, &signature(1), &mut |reaction| {
+            found.push((reaction.user.to_base58(), reaction.emoji));
+            Ok(true)
+        }).unwrap();
+        found.sort();
+        found
+    };
+    let mut expected = vec![(fan.to_base58(), "❤".to_string()), (critic.to_base58(), "".to_string())];
+    expected.sort();
+    assert_eq!(expected, reactions(conn.as_ref()));
+
+    save(conn.as_mut(), &critic, 5, &delete(4000, 4));
+    assert_eq!(vec![(fan.to_base58(), "❤".to_string())], reactions(conn.as_ref()));
+}
+
+/// Followers-only items are only found for their author, and users who follow them.
+pub(crate) fn check_followers_only(factory: &dyn Factory) {
+    use crate::protos::Visibility;
+
+    let mut conn = open(factory);
+    let author = user(0x10);
+    let follower = user(0x20);
+    let stranger = user(0x30);
+    let mut item = post(1000, "Just between us");
+    item.visibility = Visibility::VISIBILITY_FOLLOWERS;
+    save(conn.as_mut(), &author, 1, &item);
+    save(conn.as_mut(), &follower, 2, &profile(1000, "Follower", &[&author]));
+
+    // Not for anonymous visitors:
+    assert!(conn.user_item(&author, &signature(1)).unwrap().is_none());
+    assert_eq!(0, count_user_items(conn.as_ref(), &author, i64::MAX));
+
+    let found = |conn: &dyn Backend, viewer: &UserID| {
+        conn.followers_only_item(&author, &signature(1), viewer).unwrap().is_some()
+    };
+    assert!(found(conn.as_ref(), &author));
+    assert!(found(conn.as_ref(), &follower));
+    assert!(!found(conn.as_ref(), &stranger));
+
+    let listed = |conn: &dyn Backend, viewer: &UserID| {
+        let mut count = 0;
+        conn.followers_only_items(&author, viewer, Timestamp{ unix_utc_ms: i64::MAX }, &mut |_| {
+            count += 1;
+            Ok(true)
+        }).unwrap();
+        count
+    };
+    assert_eq!(1, listed(conn.as_ref(), &follower));
+    assert_eq!(0, listed(conn.as_ref(), &stranger));
+
+    // Unfollowing takes it away again:
+    save(conn.as_mut(), &follower, 3, &profile(2000, "Follower", &[]));
+    assert!(!found(conn.as_ref(), &follower));
+}
+
+/// Mutes hide items from the feeds that the muting viewer views, and only theirs.
+pub(crate) fn check_mutes(factory: &dyn Factory) {
+    let mut conn = open(factory);
+    let reader = user(0x10);
+    let author = user(0x20);
+    let other = user(0x30);
+    save(conn.as_mut(), &author, 1, &post(1000, "Hello"));
+    save(conn.as_mut(), &reader, 2, &profile(1000, "Reader", &[&author]));
+    save(conn.as_mut(), &other, 3, &profile(1000, "Other", &[&author]));
+
+    let feed_count = |conn: &dyn Backend, user: &UserID, viewer: Option<&UserID>| {
+        let mut count = 0;
+        conn.user_feed_items(user, viewer, Timestamp{ unix_utc_ms: i64::MAX }, &mut |row| {
+            if row.item.user.bytes() == author.bytes() { count += 1; }
+            Ok(true)
+        }).unwrap();
+        count
+    };
+    assert_eq!(1, feed_count(conn.as_ref(), &reader, Some(&reader)));
+
+    conn.mute_user(&reader, &author).unwrap();
+    let mut mutes = vec![];
+    conn.muted_users(&reader, &mut |muted| { mutes.push(muted); Ok(true) }).unwrap();
+    assert_eq!(vec![author.to_base58()], mutes.iter().map(UserID::to_base58).collect::<Vec<_>>());
+
+    // Hidden from any feed the reader views:
+    assert_eq!(0, feed_count(conn.as_ref(), &reader, Some(&reader)));
+    assert_eq!(0, feed_count(conn.as_ref(), &other, Some(&reader)));
+    // ... but not from anyone else's view of it:
+    assert_eq!(1, feed_count(conn.as_ref(), &reader, None));
+    assert_eq!(1, feed_count(conn.as_ref(), &reader, Some(&other)));
+
+    assert!(conn.unmute_user(&reader, &author).unwrap());
+    assert!(!conn.unmute_user(&reader, &author).unwrap());
+    assert_eq!(1, feed_count(conn.as_ref(), &reader, Some(&reader)));
+}
+
+/// Each voter's latest vote counts, if it was received before the poll closed.
+pub(crate) fn check_poll_votes(factory: &dyn Factory) {
+    let mut conn = open(factory);
+    let pollster = user(0x10);
+    let voter = user(0x20);
+    let late_voter = user(0x30);
+    let closes = 5000;
+
+    let mut poll = Item::new();
+    poll.timestamp_ms_utc = 1000;
+    poll.mut_poll().question = "Tea or coffee?".into();
+    poll.mut_poll().close_ms_utc = closes;
+    save(conn.as_mut(), &pollster, 1, &poll);
+
+    let vote = |timestamp_ms_utc: i64, option: u32| {
+        let mut item = Item::new();
+        item.timestamp_ms_utc = timestamp_ms_utc;
+        let vote = item.mut_vote();
+        vote.mut_poll().mut_user_id().set_bytes(pollster.bytes().to_vec());
+        vote.mut_poll().mut_signature().set_bytes(signature(1).bytes().to_vec());
+        vote.option = option;
+        item
+    };
+    let save_received = |conn: &mut dyn Backend, user: &UserID, n: u8, item: &Item, received: i64| {
+        let mut row = item_row(user, n, item);
+        row.received = Timestamp{ unix_utc_ms: received };
+        conn.save_user_item(&row, item).expect("save");
+    };
+
+    // Two votes at the same time. The greater signature wins:
+    save_received(conn.as_mut(), &voter, 2, &vote(2000, 0), 2000);
+    save_received(conn.as_mut(), &voter, 3, &vote(2000, 1), 2000);
+    // Backdated, but received too late:
+    save_received(conn.as_mut(), &late_voter, 4, &vote(2000, 0), closes + 1);
+
+    let mut votes = vec![];
+    conn.poll_votes(&pollster, &signature(1), Timestamp{ unix_utc_ms: closes }, &mut |vote| {
+        votes.push((vote.voter.to_base58(), vote.option));
+        Ok(true)
+    }).unwrap();
+    assert_eq!(vec![(voter.to_base58(), 1)], votes);
+}
+
+/// Followers are counted by when they started following, even after they
+/// post newer profiles. Reactions by when they were made.
+pub(crate) fn check_monthly_stats(factory: &dyn Factory) {
+    // 2020-01-15 and 2020-03-10, UTC:
+    let january = 1_579_046_400_000;
+    let march = 1_583_798_400_000;
+
+    let mut conn = open(factory);
+    let author = user(0x10);
+    let early = user(0x20);
+    let late = user(0x30);
+    save(conn.as_mut(), &author, 1, &post(january, "Hello"));
+    save(conn.as_mut(), &early, 2, &profile(january, "Early", &[&author]));
+    save(conn.as_mut(), &early, 3, &profile(march, "Early", &[&author, &late]));
+    save(conn.as_mut(), &late, 4, &profile(march, "Late", &[&author]));
+
+    let reaction = |timestamp_ms_utc: i64| {
+        let mut item = Item::new();
+        item.timestamp_ms_utc = timestamp_ms_utc;
+        let reaction = item.mut_reaction();
+        reaction.mut_item().mut_user_id().set_bytes(author.bytes().to_vec());
+        reaction.mut_item().mut_signature().set_bytes(signature(1).bytes().to_vec());
+        reaction.emoji = "👍".into();
+        item
+    };
+    save(conn.as_mut(), &early, 5, &reaction(january));
+    save(conn.as_mut(), &early, 6, &reaction(march));
+    save(conn.as_mut(), &late, 7, &reaction(march));
+
+    let expected = |january_count: i64, march_count: i64| vec![
+        ("2020-03".to_string(), march_count),
+        ("2020-01".to_string(), january_count),
+    ];
+
+    let mut followers = vec![];
+    conn.user_follower_counts_by_month(&author, &mut |month| {
+        followers.push((month.month, month.count));
+        Ok(true)
+    }).unwrap();
+    assert_eq!(expected(1, 1), followers);
+
+    let mut reactions = vec![];
+    conn.user_reaction_counts_by_month(&author, &mut |month| {
+        reactions.push((month.month, month.count));
+        Ok(true)
+    }).unwrap();
+    assert_eq!(expected(1, 2), reactions);
+}
+
+fn open(factory: &dyn Factory) -> Box<dyn Backend> {
+    let conn = factory.open().expect("open");
+    conn.setup().expect("setup");
+    conn
+}
+
+fn user(first_byte: u8) -> UserID {
+    let mut bytes = vec![first_byte; 32];
+    bytes[1] = 0xAB;
+    UserID::from_vec(bytes).unwrap()
+}
+
+fn signature(n: u8) -> Signature {
+    Signature::from_vec(vec![n; 64]).unwrap()
+}
+
+fn post(timestamp_ms_utc: i64, body: &str) -> Item {
+    let mut item = Item::new();
+    item.timestamp_ms_utc = timestamp_ms_utc;
+    item.mut_post().body = body.into();
+    item
+}
+
+fn profile(timestamp_ms_utc: i64, display_name: &str, follows: &[&UserID]) -> Item {
+    let mut item = Item::new();
+    item.timestamp_ms_utc = timestamp_ms_utc;
+    let profile = item.mut_profile();
+    profile.display_name = display_name.into();
+    for followed in follows {
+        profile.mut_follows().push_default().mut_user().set_bytes(followed.bytes().to_vec());
+    }
+    item
+}
+
+fn delete(timestamp_ms_utc: i64, n: u8) -> Item {
+    let mut item = Item::new();
+    item.timestamp_ms_utc = timestamp_ms_utc;
+    item.mut_delete().mut_signature().set_bytes(signature(n).bytes().to_vec());
+    item
+}
+
+/// Save `item` as if it had been signed by `user` with `signature(n)`.
+fn save(conn: &mut dyn Backend, user: &UserID, n: u8, item: &Item) -> ItemRow {
+    let row = item_row(user, n, item);
+    conn.save_user_item(&row, item).expect("save");
+    row
+}
+
+fn item_row(user: &UserID, n: u8, item: &Item) -> ItemRow {
+    ItemRow{
+        user: user.clone(),
+        signature: signature(n),
+        timestamp: Timestamp{ unix_utc_ms: item.timestamp_ms_utc },
+        received: Timestamp::now(),
+        item_bytes: item.write_to_bytes().unwrap(),
+    }
+}
+
+fn count_user_items(conn: &dyn Backend, user: &UserID, before: i64) -> usize {
+    let mut count = 0;
+    conn.user_items(user, Timestamp{ unix_utc_ms: before }, &mut |_| {
+        count += 1;
+        Ok(true)
+    }).unwrap();
+    count
+}
+
+/// Database files in the temp directory, deleted when dropped.
+struct TempFile {
+    path: String,
+}
+
+impl TempFile {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("feoblog-test-{}-{}.sqlite3", std::process::id(), name));
+        TempFile{ path: path.to_string_lossy().into_owned() }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let dir = std::env::temp_dir();
+        let prefix = std::path::Path::new(&self.path).file_name().unwrap().to_string_lossy().into_owned();
+        if let Ok(entries) = std::fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                    let _ = std::fs::remove_file(entry.path());
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn memory_backend_conforms() {
+    check_all(&|| Box::new(super::sharded::Factory::memory()));
+}
+
+#[test]
+fn sqlite_backend_conforms() {
+    let files = std::cell::RefCell::new(vec![]);
+    check_all(&|| {
+        let file = TempFile::new(&format!("sqlite{}", files.borrow().len()));
+        let factory = super::sqlite::Factory::new(file.path.clone(), 0);
+        files.borrow_mut().push(file);
+        Box::new(factory)
+    });
+}
+
+#[test]
+fn sharded_backend_conforms() {
+    let files = std::cell::RefCell::new(vec![]);
+    check_all(&|| {
+        let file = TempFile::new(&format!("sharded{}", files.borrow().len()));
+        let factory = super::sharded::Factory::new(&file.path, 4, 0).expect("factory");
+        files.borrow_mut().push(file);
+        Box::new(factory)
+    });
+}
+
+/// Each user's data is written to (and read from) the shard for their user
+/// ID. Server-wide data stays in the first shard.
+#[test]
+fn sharded_backend_routes_by_user() {
+    use super::PushPeer;
+    use super::sharded::{self, shard_index, shard_paths};
+
+    let file = TempFile::new("routing");
+    let factory = sharded::Factory::new(&file.path, 4, 0).expect("factory");
+    let mut conn = open(&factory);
+
+    // One in each shard:
+    let users = [user(0x10), user(0x50), user(0x90), user(0xF0)];
+    for (i, author) in users.iter().enumerate() {
+        assert_eq!(i, shard_index(author, 4));
+        save(conn.as_mut(), author, i as u8 + 1, &post(1000, "Hello"));
+        conn.mute_user(author, &users[(i + 1) % 4]).unwrap();
+    }
+    conn.add_push_peer(&PushPeer{
+        url: "https://feo.example.com".into(),
+        created: Timestamp::now(),
+    }).unwrap();
+
+    let mutes = |conn: &dyn Backend, viewer: &UserID| {
+        let mut muted = vec![];
+        conn.muted_users(viewer, &mut |user| {
+            muted.push(user.bytes().to_vec());
+            Ok(true)
+        }).unwrap();
+        muted
+    };
+
+    // Read each shard's file on its own:
+    let shards: Vec<Box<dyn Backend>> = shard_paths(&file.path, 4).into_iter()
+        .map(|path| open(&super::sqlite::Factory::new(path, 0)))
+        .collect();
+    for (i, shard) in shards.iter().enumerate() {
+        for (j, author) in users.iter().enumerate() {
+            let sig = signature(j as u8 + 1);
+            assert_eq!(i == j, shard.user_item_exists(author, &sig).unwrap(), "user {} in shard {}", j, i);
+            assert_eq!(i == j, !mutes(shard.as_ref(), author).is_empty());
+
+            // ... and the sharded backend finds them all:
+            assert!(conn.user_item(author, &sig).unwrap().is_some());
+            assert_eq!(vec![users[(j + 1) % 4].bytes().to_vec()], mutes(conn.as_ref(), author));
+        }
+
+        let mut peers = 0;
+        shard.push_peers(&mut |_| {
+            peers += 1;
+            Ok(true)
+        }).unwrap();
+        assert_eq!(if i == 0 { 1 } else { 0 }, peers, "push peers in shard {}", i);
+    }
+
+    // Not found in another user's shard:
+    assert!(conn.user_item(&users[0], &signature(2)).unwrap().is_none());
+    assert_eq!(1, count_user_items(conn.as_ref(), &users[3], i64::MAX));
+}
+
+/// Listings that span users merge each shard's rows, newest first, without
+/// losing or repeating any. (Even past a shard's first batch of rows, and
+/// with timestamps that tie across shards.)
+#[test]
+fn sharded_backend_merges_listings() {
+    use super::sharded;
+
+    let file = TempFile::new("merging");
+    let factory = sharded::Factory::new(&file.path, 4, 0).expect("factory");
+    let mut conn = open(&factory);
+
+    let users = [user(0x10), user(0x50), user(0x90), user(0xF0)];
+    let per_user: i64 = 60;
+    for (k, author) in users.iter().enumerate() {
+        conn.add_server_user(&ServerUser{
+            user: author.clone(),
+            notes: String::new(),
+            on_homepage: true,
+        }).unwrap();
+        for i in 1..=per_user {
+            // Pairs of users share timestamps:
+            let timestamp = i * 10 + (k as i64 % 2);
+            save(conn.as_mut(), author, i as u8, &post(timestamp, "Post"));
+        }
+    }
+    let mut rows = vec![];
+    conn.homepage_items(Timestamp::now(), &mut |row| {
+        rows.push((row.item.timestamp.unix_utc_ms, row.item.user.bytes().to_vec(), row.item.signature.bytes().to_vec()));
+        Ok(true)
+    }).unwrap();
+    assert_eq!(4 * per_user as usize, rows.len());
+    assert!(rows.windows(2).all(|pair| pair[0].0 >= pair[1].0), "newest first");
+    let unique: std::collections::HashSet<_> = rows.iter().map(|(_, user, sig)| (user.clone(), sig.clone())).collect();
+    assert_eq!(rows.len(), unique.len());
+
+    // Pages start before the given time, and stop when the callback says to:
+    let mut timestamps = vec![];
+    conn.homepage_items(Timestamp{ unix_utc_ms: 301 }, &mut |row| {
+        timestamps.push(row.item.timestamp.unix_utc_ms);
+        Ok(timestamps.len() < 5)
+    }).unwrap();
+    assert_eq!(vec![300, 300, 291, 291, 290], timestamps);
+
+    // The first user follows the rest:
+    let followed: Vec<&UserID> = users[1..].iter().collect();
+    save(conn.as_mut(), &users[0], 100, &profile(1, "Follower", &followed));
+
+    // A feed merges the user's items with those they follow, across shards:
+    let mut feed = vec![];
+    conn.user_feed_items(&users[0], None, Timestamp::now(), &mut |row| {
+        feed.push((row.item.timestamp.unix_utc_ms, row.item.user.bytes().to_vec()));
+        Ok(true)
+    }).unwrap();
+    // Every post, plus the profile:
+    assert_eq!(4 * per_user as usize + 1, feed.len());
+    assert!(feed.windows(2).all(|pair| pair[0].0 >= pair[1].0), "newest first");
+    for (k, author) in users.iter().enumerate() {
+        let count = feed.iter().filter(|(_, user)| user.as_slice() == author.bytes()).count();
+        let expected = if k == 0 { per_user + 1 } else { per_user };
+        assert_eq!(expected as usize, count, "items from shard {}", k);
+    }
+}
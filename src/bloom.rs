@@ -0,0 +1,70 @@
+//! A Bloom filter of item signatures.
+//!
+//! Lets a peer quickly check which items another server probably lacks,
+//! without listing every item. See `BloomFilter` in feoblog.proto for the
+//! exact algorithm, which other implementations must match.
+
+use std::convert::TryInto;
+
+/// Target false positive rate is ~1% with these settings.
+const BITS_PER_ITEM: usize = 10;
+const HASH_COUNT: u32 = 7;
+
+/// Don't let clients make us allocate huge filters.
+pub(crate) const MAX_FILTER_BYTES: usize = 1024 * 1024;
+
+pub(crate) struct BloomFilter {
+    bits: Vec<u8>,
+    hash_count: u32,
+}
+
+impl BloomFilter {
+    /// A filter sized for `expected_items` items.
+    pub fn with_capacity(expected_items: usize) -> Self {
+        let bytes = (expected_items * BITS_PER_ITEM + 7) / 8;
+        let bytes = bytes.max(1).min(MAX_FILTER_BYTES);
+        BloomFilter {
+            bits: vec![0; bytes],
+            hash_count: HASH_COUNT,
+        }
+    }
+
+    pub fn from_parts(bits: Vec<u8>, hash_count: u32) -> Self {
+        BloomFilter { bits, hash_count }
+    }
+
+    pub fn bits(&self) -> &[u8] { &self.bits }
+    pub fn hash_count(&self) -> u32 { self.hash_count }
+
+    pub fn insert(&mut self, signature: &[u8]) {
+        for index in self.indexes(signature) {
+            self.bits[index / 8] |= 1 << (index % 8);
+        }
+    }
+
+    /// False if the signature is definitely not in the set.
+    /// True if it probably is.
+    pub fn contains(&self, signature: &[u8]) -> bool {
+        if self.bits.is_empty() {
+            return false;
+        }
+        self.indexes(signature).all(|index| self.bits[index / 8] & (1 << (index % 8)) != 0)
+    }
+
+    /// Signatures are already uniformly random, so we use their bytes
+    /// directly with double hashing: index(i) = (h1 + i * h2) mod bit_count
+    fn indexes(&self, signature: &[u8]) -> impl Iterator<Item=usize> {
+        let bit_count = (self.bits.len() * 8) as u64;
+        let (h1, h2) = if signature.len() >= 16 {
+            (
+                u64::from_le_bytes(signature[0..8].try_into().expect("8 bytes")),
+                u64::from_le_bytes(signature[8..16].try_into().expect("8 bytes")),
+            )
+        } else {
+            (0, 0)
+        };
+        (0..u64::from(self.hash_count)).map(move |i| {
+            (h1.wrapping_add(i.wrapping_mul(h2)) % bit_count.max(1)) as usize
+        })
+    }
+}
@@ -0,0 +1,184 @@
+//! Portable bundles of signed items.
+//!
+//! A bundle holds the exact bytes that each author signed, so it can be
+//! exported from one server and imported into another without trusting
+//! whoever carried it in between.
+
+use std::collections::HashSet;
+
+use failure::{Error, bail};
+use protobuf::Message as _;
+
+use crate::backend::{Backend, ItemRow, Signature, Timestamp, UserID};
+use crate::protos::{BundledItem, Item, ItemBundle, ProtoValid as _};
+
+/// Stop walking a thread after this many items, so that one busy thread
+/// can't make a huge bundle.
+const MAX_THREAD_ITEMS: usize = 1000;
+
+/// Bundle an item along with the rest of its thread: the comments that reply
+/// to it, directly or through other comments.
+///
+/// Items come one level of replies at a time, so each comes after the item it
+/// replies to. Returns None if we don't have the root item.
+pub(crate) fn thread_bundle(backend: &dyn Backend, user: &UserID, signature: &Signature) -> Result<Option<ItemBundle>, Error> {
+    let row = match backend.user_item(user, signature)? {
+        None => return Ok(None),
+        Some(row) => row,
+    };
+
+    let mut bundle = ItemBundle::new();
+    bundle.items.push(bundled_item(&row));
+
+    // Signatures, which are unique enough on their own:
+    let mut seen: HashSet<Vec<u8>> = HashSet::new();
+    seen.insert(signature.bytes().to_vec());
+    let mut level = vec![(user.clone(), signature.clone())];
+    while !level.is_empty() {
+        let mut replies = vec![];
+        for (user, signature) in &level {
+            backend.comment_replies(user, signature, &mut |(reply_user, reply_signature)| {
+                if seen.insert(reply_signature.bytes().to_vec()) {
+                    replies.push((reply_user, reply_signature));
+                }
+                Ok(true)
+            })?;
+        }
+
+        let mut next_level = vec![];
+        for (user, signature) in replies {
+            if bundle.items.len() >= MAX_THREAD_ITEMS {
+                return Ok(Some(bundle));
+            }
+            // Removed since we listed it:
+            let row = match backend.user_item(&user, &signature)? {
+                None => continue,
+                Some(row) => row,
+            };
+            bundle.items.push(bundled_item(&row));
+            next_level.push((user, signature));
+        }
+        level = next_level;
+    }
+
+    Ok(Some(bundle))
+}
+
+pub(crate) fn bundled_item(row: &ItemRow) -> BundledItem {
+    let mut item = BundledItem::new();
+    item.mut_user_id().set_bytes(row.user.bytes().into());
+    item.mut_signature().set_bytes(row.signature.bytes().into());
+    item.item_bytes = row.item_bytes.clone();
+    item
+}
+
+/// Save every item in the bundle that we don't already have.
+///
+/// All items are checked before any are saved, so a bad bundle imports nothing.
+/// Returns the number of newly-saved items.
+pub(crate) fn import_bundle(backend: &mut dyn Backend, bundle: &ItemBundle) -> Result<usize, Error> {
+    let now = Timestamp::now();
+    let mut rows = vec![];
+    for entry in bundle.get_items() {
+        let user = UserID::from_vec(entry.get_user_id().get_bytes().to_vec())?;
+        let signature = Signature::from_vec(entry.get_signature().get_bytes().to_vec())?;
+        rows.push(check_item(user, signature, entry.get_item_bytes(), now)?);
+    }
+
+    let mut imported = 0;
+    for (row, item) in rows {
+        if backend.user_item_exists(&row.user, &row.signature)? || backend.user_item_deleted(&row.user, &row.signature)? {
+            continue;
+        }
+        backend.save_user_item(&row, &item)?;
+        imported += 1;
+    }
+    Ok(imported)
+}
+
+/// What `check_upload` decided about an item.
+pub(crate) enum Upload {
+    /// Ready to save.
+    New(ItemRow, Item),
+
+    /// We already have it.
+    Exists,
+
+    /// Why we won't save it.
+    Refused(String),
+}
+
+/// Check an item that didn't come through `put_item` (ex: from an archive,
+/// or the maintenance journal) the same way that `put_item` would: its size,
+/// signature, and timestamps, and whether its author may post it here.
+pub(crate) fn check_upload(backend: &dyn Backend, user: UserID, signature: Signature, bytes: &[u8], received: Timestamp) -> Result<Upload, Error> {
+    if bytes.len() > crate::server::MAX_ITEM_SIZE {
+        return Ok(Upload::Refused(format!("The item is larger than {} bytes", crate::server::MAX_ITEM_SIZE)));
+    }
+    if backend.user_item_exists(&user, &signature)? {
+        return Ok(Upload::Exists);
+    }
+    if backend.user_item_deleted(&user, &signature)? {
+        return Ok(Upload::Refused("Its author deleted it".into()));
+    }
+
+    let (row, item) = match check_item(user, signature, bytes, received) {
+        Ok(checked) => checked,
+        Err(err) => return Ok(Upload::Refused(err.to_string())),
+    };
+    if item.expires_ms_utc != 0 && item.expires_ms_utc <= received.unix_utc_ms {
+        return Ok(Upload::Refused("The item has already expired".into()));
+    }
+    if !backend.user_known(&row.user)? {
+        return Ok(Upload::Refused(format!("{} is not a user on this server", row.user.to_base58())));
+    }
+    if let Some(deny_reason) = backend.quota_check_item(&row.user, &row.item_bytes, &item)? {
+        return Ok(Upload::Refused(deny_reason.to_string()));
+    }
+
+    Ok(Upload::New(row, item))
+}
+
+/// Check that an item from elsewhere is validly signed and well-formed, so
+/// that we can save it.
+pub(crate) fn check_item(user: UserID, signature: Signature, bytes: &[u8], received: Timestamp) -> Result<(ItemRow, Item), Error> {
+    if !signature.is_valid(&user, bytes) {
+        bail!("Invalid signature {} for user {}", signature.to_base58(), user.to_base58());
+    }
+
+    let mut item = Item::new();
+    item.merge_from_bytes(bytes)?;
+    item.validate()?;
+    if item.timestamp_ms_utc > received.unix_utc_ms {
+        bail!("Item {} has a timestamp in the future", signature.to_base58());
+    }
+
+    let row = ItemRow {
+        user,
+        signature,
+        timestamp: Timestamp{ unix_utc_ms: item.timestamp_ms_utc },
+        received,
+        item_bytes: bytes.to_vec(),
+    };
+    Ok((row, item))
+}
+
+/// Check that an item we've stored is still exactly what its author signed.
+/// (ex: after disk problems, or editing the database by hand)
+pub(crate) fn verify_row(row: &ItemRow) -> Result<(), Error> {
+    if !row.signature.is_valid(&row.user, &row.item_bytes) {
+        bail!("Invalid signature");
+    }
+
+    let mut item = Item::new();
+    if let Err(err) = item.merge_from_bytes(&row.item_bytes) {
+        bail!("Can't parse the item: {}", err);
+    }
+    if item.timestamp_ms_utc != row.timestamp.unix_utc_ms {
+        bail!(
+            "The item's timestamp ({}) doesn't match its row ({})",
+            item.timestamp_ms_utc, row.timestamp.unix_utc_ms,
+        );
+    }
+    Ok(())
+}
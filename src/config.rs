@@ -0,0 +1,268 @@
+//! Options for `feoblog serve`, from a TOML file. (`feoblog serve --config feoblog.toml`)
+//!
+//! Keys are the same as the command-line options, without the leading `--`.
+//! Options that may be repeated take arrays. ex:
+//!
+//! ```toml
+//! sqlite-file = "/var/lib/feoblog/feoblog.sqlite3"
+//! bind = ["127.0.0.1:8080"]
+//! block = ["192.0.2.0/24"]
+//! max-attachment-bytes = 20971520
+//!
+//! # Unix sockets, TLS, and the PROXY protocol are set per listener:
+//! [[listen]]
+//! unix = "/run/feoblog/feoblog.sock"
+//! proxy-protocol = true
+//! ```
+//!
+//! Options given on the command line override the file.
+
+use std::path::{Path, PathBuf};
+
+use failure::{Error, ResultExt, bail};
+use serde::Deserialize;
+use structopt::clap::ArgMatches;
+
+use crate::ServeCommand;
+use crate::backend::UserID;
+use crate::server::blocklist::Cidr;
+use crate::server::listen::Listen;
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Config {
+    backend: Option<String>,
+    sqlite_file: Option<String>,
+    shards: Option<usize>,
+    sqlite_cache_kib: Option<u64>,
+
+    open: Option<bool>,
+    #[serde(rename = "bind")]
+    binds: Option<Vec<String>>,
+    #[serde(rename = "bind-tls")]
+    tls_binds: Option<Vec<String>>,
+    #[serde(rename = "bind-proxy")]
+    proxy_binds: Option<Vec<String>>,
+    #[serde(rename = "listen")]
+    listens: Option<Vec<Listen>>,
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    #[serde(rename = "acme-domain")]
+    acme_domains: Option<Vec<String>>,
+    acme_email: Option<String>,
+    acme_directory: Option<String>,
+    #[serde(rename = "block")]
+    blocks: Option<Vec<String>>,
+    count_views: Option<bool>,
+    max_concurrent_uploads: Option<usize>,
+    min_upload_rate: Option<usize>,
+    #[serde(rename = "reject-item-type")]
+    reject_item_types: Option<Vec<String>>,
+    proxy_feeds: Option<bool>,
+    map_tiles: Option<String>,
+    max_attachment_bytes: Option<u64>,
+    anonymous_comments: Option<bool>,
+    reply_counts: Option<bool>,
+    linkcheck: Option<bool>,
+    lang: Option<String>,
+    date_format: Option<String>,
+    maintenance_journal: Option<bool>,
+    activitypub_key: Option<String>,
+    static_dir: Option<PathBuf>,
+    client_dir: Option<PathBuf>,
+    no_web_client: Option<bool>,
+    #[serde(rename = "nostr-relay")]
+    nostr_relays: Option<Vec<String>>,
+    ipfs_api: Option<String>,
+    #[serde(rename = "host")]
+    hosts: Option<Vec<String>>,
+    onion_address: Option<String>,
+    drain_timeout: Option<u64>,
+    pow_difficulty: Option<u32>,
+    quarantine_pow_items: Option<bool>,
+    max_storage_bytes: Option<u64>,
+    #[serde(rename = "backup-key")]
+    backup_keys: Option<Vec<String>>,
+    #[serde(rename = "admin")]
+    admins: Option<Vec<String>>,
+    keep_item_events_days: Option<u64>,
+    keep_sync_reports_days: Option<u64>,
+    keep_security_reports_days: Option<u64>,
+    keep_stale_queue_days: Option<u64>,
+
+    /// The file's name and contents, for error messages.
+    #[serde(skip)]
+    file_name: String,
+    #[serde(skip)]
+    source: String,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let file_name = path.display().to_string();
+        let source = std::fs::read_to_string(path)
+            .with_context(|_| format!("Error reading {}", file_name))?;
+
+        let mut config: Config = match toml::from_str(&source) {
+            Ok(config) => config,
+            Err(err) => {
+                let context = match err.line_col() {
+                    Some((line, col)) => excerpt(&source, line, Some(col)),
+                    None => String::new(),
+                };
+                bail!("Error in {}: {}{}", file_name, err, context);
+            },
+        };
+        config.file_name = file_name;
+        config.source = source;
+        Ok(config)
+    }
+
+    /// Fill in `command` with options from the file, except those given on
+    /// the command line. (`matches` are the `serve` subcommand's.)
+    pub fn apply(self, command: &mut ServeCommand, matches: &ArgMatches) -> Result<(), Error> {
+        let given = |name: &str| matches.occurrences_of(name) > 0;
+
+        macro_rules! set {
+            ($field:ident) => {
+                if let Some(value) = self.$field.clone() {
+                    if !given(stringify!($field)) { command.$field = value; }
+                }
+            };
+        }
+        macro_rules! set_option {
+            ($field:ident) => {
+                if let Some(value) = self.$field.clone() {
+                    if !given(stringify!($field)) { command.$field = Some(value); }
+                }
+            };
+        }
+
+        if let Some(value) = &self.backend {
+            if !given("backend") {
+                command.shared_options.backend = crate::parse_backend_kind(value).map_err(|err| self.error("backend", err))?;
+            }
+        }
+        if let Some(value) = self.sqlite_file.clone() {
+            if !given("sqlite_file") { command.shared_options.sqlite_file = value; }
+        }
+        if let Some(value) = self.shards {
+            if !given("shards") { command.shared_options.shards = value; }
+        }
+        if let Some(value) = self.sqlite_cache_kib {
+            if !given("sqlite_cache_kib") { command.shared_options.sqlite_cache_kib = value; }
+        }
+        if let Some(value) = self.keep_item_events_days {
+            if !given("keep_item_events_days") { command.retention_options.keep_item_events_days = value; }
+        }
+        if let Some(value) = self.keep_sync_reports_days {
+            if !given("keep_sync_reports_days") { command.retention_options.keep_sync_reports_days = value; }
+        }
+        if let Some(value) = self.keep_security_reports_days {
+            if !given("keep_security_reports_days") { command.retention_options.keep_security_reports_days = value; }
+        }
+        if let Some(value) = self.keep_stale_queue_days {
+            if !given("keep_stale_queue_days") { command.retention_options.keep_stale_queue_days = value; }
+        }
+
+        set!(open);
+        set!(binds);
+        set!(tls_binds);
+        set!(proxy_binds);
+        if let Some(value) = self.listens.clone() {
+            // Config-only, so there's nothing on the command line to override it.
+            command.listens = value;
+        }
+        set_option!(cert);
+        set_option!(key);
+        set!(acme_domains);
+        set_option!(acme_email);
+        set!(acme_directory);
+        set!(count_views);
+        set!(max_concurrent_uploads);
+        set!(min_upload_rate);
+        set!(proxy_feeds);
+        set_option!(map_tiles);
+        set!(max_attachment_bytes);
+        set!(anonymous_comments);
+        set!(reply_counts);
+        set!(linkcheck);
+        set!(lang);
+        set!(date_format);
+        set!(maintenance_journal);
+        set_option!(activitypub_key);
+        set_option!(static_dir);
+        set_option!(client_dir);
+        set!(no_web_client);
+        set!(nostr_relays);
+        set_option!(ipfs_api);
+        set!(hosts);
+        set_option!(onion_address);
+        set!(drain_timeout);
+        set!(pow_difficulty);
+        set!(quarantine_pow_items);
+        set!(max_storage_bytes);
+
+        // These are parsed by structopt on the command line, so check them here:
+        if let Some(values) = &self.blocks {
+            if !given("blocks") {
+                command.blocks = values.iter()
+                    .map(|value| value.parse::<Cidr>().map_err(|err| self.error("block", err)))
+                    .collect::<Result<_, _>>()?;
+            }
+        }
+        if let Some(values) = &self.backup_keys {
+            if !given("backup_keys") {
+                command.backup_keys = values.iter()
+                    .map(|value| value.parse::<UserID>().map_err(|err| self.error("backup-key", err)))
+                    .collect::<Result<_, _>>()?;
+            }
+        }
+        if let Some(values) = &self.admins {
+            if !given("admins") {
+                command.admins = values.iter()
+                    .map(|value| value.parse::<UserID>().map_err(|err| self.error("admin", err)))
+                    .collect::<Result<_, _>>()?;
+            }
+        }
+        if let Some(values) = &self.reject_item_types {
+            if !given("reject_item_types") {
+                command.reject_item_types = values.iter()
+                    .map(|value| crate::parse_item_type(value).map_err(|err| self.error("reject-item-type", err)))
+                    .collect::<Result<_, _>>()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// An error about `key`'s value, pointing at the line it's on.
+    fn error(&self, key: &str, err: Error) -> Error {
+        let line = self.source.lines().position(|line| {
+            let line = line.trim_start();
+            line.strip_prefix(key)
+                .map(|rest| rest.trim_start().starts_with('='))
+                .unwrap_or(false)
+        });
+        let context = match line {
+            Some(line) => excerpt(&self.source, line, None),
+            None => String::new(),
+        };
+        failure::format_err!("Error in {}, {}: {}{}", self.file_name, key, err, context)
+    }
+}
+
+/// The (0-based) `line` of `source`, indented under an error message, with a
+/// caret under `col` if we know it.
+fn excerpt(source: &str, line: usize, col: Option<usize>) -> String {
+    let text = match source.lines().nth(line) {
+        Some(text) => text,
+        None => return String::new(),
+    };
+    let number = (line + 1).to_string();
+    let mut excerpt = format!("\n {} | {}", number, text);
+    if let Some(col) = col {
+        excerpt.push_str(&format!("\n {} | {}^", " ".repeat(number.len()), " ".repeat(col)));
+    }
+    excerpt
+}
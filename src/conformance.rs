@@ -0,0 +1,328 @@
+//! Checks that a (possibly remote) server follows the FeoBlog protocol.
+//!
+//! Run with `feoblog conformance --server URL`.
+//! This only makes requests that are safe to run against a production server:
+//! Uploads are made with a newly-generated user ID, which the server should refuse.
+
+use std::time::Duration;
+
+use actix_web::client::{Client, ClientResponse};
+use actix_web::error::PayloadError;
+use actix_web::http::{HeaderMap, StatusCode};
+use actix_web::web::Bytes;
+use failure::{Error, bail, format_err};
+use futures::Stream;
+use protobuf::Message as _;
+use sodiumoxide::crypto::sign;
+
+use crate::backend::{Signature, Timestamp, UserID};
+use crate::protos::{Item, ItemList};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_BODY_BYTES: usize = 1024 * 1024 * 10;
+
+/// The result of a single check.
+pub(crate) struct CheckResult {
+    pub name: &'static str,
+    pub result: Result<(), Error>,
+}
+
+/// Results of all checks against a server.
+pub(crate) struct Report {
+    pub server: String,
+    pub checks: Vec<CheckResult>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.result.is_ok())
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Conformance report for {}", self.server)?;
+        for check in &self.checks {
+            match &check.result {
+                Ok(()) => writeln!(f, "PASS {}", check.name)?,
+                Err(err) => writeln!(f, "FAIL {}: {}", check.name, err)?,
+            }
+        }
+        let passed = self.checks.iter().filter(|c| c.result.is_ok()).count();
+        writeln!(f, "{}/{} checks passed.", passed, self.checks.len())
+    }
+}
+
+struct Checker {
+    base_url: String,
+    client: Client,
+
+    /// A newly-generated user, unknown to the server.
+    user: UserID,
+    secret_key: sign::SecretKey,
+}
+
+/// Run all checks against the server at `base_url`.
+pub(crate) async fn check_server(base_url: &str) -> Result<Report, Error> {
+    if sodiumoxide::init().is_err() {
+        bail!("Error initializing sodiumoxide");
+    }
+    let (public_key, secret_key) = sign::gen_keypair();
+
+    let checker = Checker {
+        base_url: base_url.trim_end_matches('/').to_string(),
+        client: Client::default(),
+        user: UserID::from_vec(public_key.as_ref().to_vec())?,
+        secret_key,
+    };
+
+    let mut checks = vec![];
+    macro_rules! check {
+        ($name:expr, $fut:expr) => {
+            checks.push(CheckResult{ name: $name, result: $fut.await });
+        }
+    }
+
+    check!("GET /homepage/proto3 returns an ItemList", checker.homepage_list());
+    check!("?before=1 returns no items", checker.homepage_before_epoch());
+    check!("GET /u/{userID}/proto3 for an unknown user returns an empty ItemList", checker.unknown_user_list());
+    check!("GET of a missing item returns 404", checker.missing_item());
+    check!("GET of a missing profile returns 404", checker.missing_profile());
+    check!("OPTIONS preflight allows PUT", checker.cors_preflight());
+    check!("PUT from an unknown user is refused", checker.put_unknown_user());
+    check!("PUT with an invalid user ID is refused", checker.put_invalid_user_id());
+    check!("Items are immutable, cacheable, and correctly signed", checker.item_cache_headers());
+    check!("Profiles include a valid signature header", checker.profile_signature());
+
+    Ok(Report {
+        server: checker.base_url.clone(),
+        checks,
+    })
+}
+
+/// The parts of a response we check.
+struct Response {
+    status: StatusCode,
+    headers: HeaderMap,
+}
+
+impl Response {
+    fn status(&self) -> StatusCode { self.status }
+}
+
+impl Checker {
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    async fn get(&self, path: &str) -> Result<(Response, Vec<u8>), Error> {
+        let url = self.url(path);
+        let response = self.client.get(&url)
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| format_err!("Error fetching {}: {}", url, e))?;
+        read_body(response).await
+    }
+
+    async fn get_item_list(&self, path: &str) -> Result<ItemList, Error> {
+        let (response, body) = self.get(path).await?;
+        expect_status(&response, StatusCode::OK)?;
+        let mut list = ItemList::new();
+        list.merge_from_bytes(&body)
+            .map_err(|e| format_err!("Couldn't parse ItemList: {}", e))?;
+        Ok(list)
+    }
+
+    async fn homepage_list(&self) -> Result<(), Error> {
+        self.get_item_list("/homepage/proto3").await?;
+        Ok(())
+    }
+
+    async fn homepage_before_epoch(&self) -> Result<(), Error> {
+        let list = self.get_item_list("/homepage/proto3?before=1").await?;
+        if !list.get_items().is_empty() {
+            bail!("Expected no items, got {}", list.get_items().len());
+        }
+        Ok(())
+    }
+
+    async fn unknown_user_list(&self) -> Result<(), Error> {
+        let list = self.get_item_list(&format!("/u/{}/proto3", self.user.to_base58())).await?;
+        if !list.get_items().is_empty() {
+            bail!("Expected no items, got {}", list.get_items().len());
+        }
+        Ok(())
+    }
+
+    async fn missing_item(&self) -> Result<(), Error> {
+        let (_, signature) = self.signed_item()?;
+        let path = format!("/u/{}/i/{}/proto3", self.user.to_base58(), signature.to_base58());
+        let (response, _) = self.get(&path).await?;
+        expect_status(&response, StatusCode::NOT_FOUND)
+    }
+
+    async fn missing_profile(&self) -> Result<(), Error> {
+        let path = format!("/u/{}/profile/proto3", self.user.to_base58());
+        let (response, _) = self.get(&path).await?;
+        expect_status(&response, StatusCode::NOT_FOUND)
+    }
+
+    async fn cors_preflight(&self) -> Result<(), Error> {
+        let (_, signature) = self.signed_item()?;
+        let url = self.url(&format!("/u/{}/i/{}/proto3", self.user.to_base58(), signature.to_base58()));
+        let response = self.client.request(actix_web::http::Method::OPTIONS, &url)
+            .header("Origin", "https://conformance.invalid")
+            .header("Access-Control-Request-Method", "PUT")
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| format_err!("Error fetching {}: {}", url, e))?;
+        let (response, _) = read_body(response).await?;
+
+        if !response.status().is_success() {
+            bail!("Expected a 2XX status, got {}", response.status());
+        }
+        expect_cors(&response)?;
+        let methods = header(&response, "Access-Control-Allow-Methods").unwrap_or_default();
+        if !methods.split(',').any(|m| m.trim().eq_ignore_ascii_case("PUT")) {
+            bail!("Access-Control-Allow-Methods does not include PUT: {:?}", methods);
+        }
+        Ok(())
+    }
+
+    async fn put_unknown_user(&self) -> Result<(), Error> {
+        let (bytes, signature) = self.signed_item()?;
+        let path = format!("/u/{}/i/{}/proto3", self.user.to_base58(), signature.to_base58());
+        let response = self.put(&path, bytes).await?;
+        expect_status(&response, StatusCode::FORBIDDEN)
+    }
+
+    async fn put_invalid_user_id(&self) -> Result<(), Error> {
+        let (bytes, signature) = self.signed_item()?;
+        let path = format!("/u/{}/i/{}/proto3", "notAUserID", signature.to_base58());
+        let response = self.put(&path, bytes).await?;
+        if !response.status().is_client_error() {
+            bail!("Expected a 4XX status, got {}", response.status());
+        }
+        Ok(())
+    }
+
+    async fn put(&self, path: &str, bytes: Vec<u8>) -> Result<Response, Error> {
+        let url = self.url(path);
+        let response = self.client.put(&url)
+            .content_type("application/protobuf3")
+            .timeout(REQUEST_TIMEOUT)
+            .send_body(bytes)
+            .await
+            .map_err(|e| format_err!("Error sending {}: {}", url, e))?;
+        let (response, _) = read_body(response).await?;
+        Ok(response)
+    }
+
+    /// Find an item on the homepage to check, if there is one.
+    async fn some_item(&self) -> Result<Option<(UserID, Signature)>, Error> {
+        let list = self.get_item_list("/homepage/proto3?count=1").await?;
+        let entry = match list.get_items().first() {
+            None => return Ok(None),
+            Some(entry) => entry,
+        };
+        let user = UserID::from_vec(entry.get_user_id().get_bytes().to_vec())?;
+        let signature = Signature::from_vec(entry.get_signature().get_bytes().to_vec())?;
+        Ok(Some((user, signature)))
+    }
+
+    async fn item_cache_headers(&self) -> Result<(), Error> {
+        let (user, signature) = match self.some_item().await? {
+            // Nothing to check. That's OK.
+            None => return Ok(()),
+            Some(found) => found,
+        };
+
+        let path = format!("/u/{}/i/{}/proto3", user.to_base58(), signature.to_base58());
+        let (response, body) = self.get(&path).await?;
+        expect_status(&response, StatusCode::OK)?;
+        expect_cors(&response)?;
+
+        let cache_control = header(&response, "Cache-Control").unwrap_or_default();
+        if !cache_control.contains("immutable") {
+            bail!("Expected an immutable Cache-Control header, got {:?}", cache_control);
+        }
+        if !signature.is_valid(&user, &body) {
+            bail!("Invalid signature for {}", path);
+        }
+        Ok(())
+    }
+
+    async fn profile_signature(&self) -> Result<(), Error> {
+        let (user, _) = match self.some_item().await? {
+            None => return Ok(()),
+            Some(found) => found,
+        };
+
+        let path = format!("/u/{}/profile/proto3", user.to_base58());
+        let (response, body) = self.get(&path).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            // Users aren't required to have profiles.
+            return Ok(());
+        }
+        expect_status(&response, StatusCode::OK)?;
+
+        let signature = match header(&response, "signature") {
+            None => bail!("Missing signature header"),
+            Some(sig) => Signature::from_base58(&sig)?,
+        };
+        if !signature.is_valid(&user, &body) {
+            bail!("Invalid signature header for {}", path);
+        }
+        Ok(())
+    }
+
+    /// A new Post, signed by our (unknown) user.
+    fn signed_item(&self) -> Result<(Vec<u8>, Signature), Error> {
+        let mut item = Item::new();
+        item.set_timestamp_ms_utc(Timestamp::now().unix_utc_ms);
+        item.mut_post().set_title("FeoBlog conformance test".into());
+        item.mut_post().set_body("Servers should not accept this post.".into());
+        let bytes = item.write_to_bytes()?;
+        let signature = sign::sign_detached(&bytes, &self.secret_key);
+        let signature = Signature::from_vec(signature.as_ref().to_vec())?;
+        Ok((bytes, signature))
+    }
+}
+
+async fn read_body<S>(mut response: ClientResponse<S>) -> Result<(Response, Vec<u8>), Error>
+where S: Stream<Item=Result<Bytes, PayloadError>> + Unpin
+{
+    let body = response.body()
+        .limit(MAX_BODY_BYTES)
+        .await
+        .map_err(|e| format_err!("Error reading body: {}", e))?;
+    let parts = Response {
+        status: response.status(),
+        headers: response.headers().clone(),
+    };
+    Ok((parts, body.to_vec()))
+}
+
+fn header(response: &Response, name: &str) -> Option<String> {
+    response.headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+fn expect_status(response: &Response, status: StatusCode) -> Result<(), Error> {
+    if response.status() != status {
+        bail!("Expected status {}, got {}", status, response.status());
+    }
+    Ok(())
+}
+
+fn expect_cors(response: &Response) -> Result<(), Error> {
+    match header(response, "Access-Control-Allow-Origin") {
+        Some(origin) if origin == "*" => Ok(()),
+        Some(origin) => bail!("Expected Access-Control-Allow-Origin: *, got {:?}", origin),
+        None => bail!("Missing Access-Control-Allow-Origin header"),
+    }
+}
@@ -0,0 +1,92 @@
+//! Keeps display names from passing for user IDs.
+//!
+//! Pages show a user's display name if they have one, else their user ID. A
+//! user who names themselves after someone else's ID (or a copy of it with
+//! lookalike characters, ex: a Cyrillic "а" for an "a", or a few invisible
+//! characters mixed in) could pass as that user. So profiles may not have
+//! names like that, and we show the real user ID instead of any that were
+//! saved before we checked.
+
+use crate::backend::UserID;
+
+/// How long a user ID is, in base58.
+const ID_LENGTHS: [usize; 2] = [43, 44];
+
+/// True if `name` contains a user ID, once lookalike characters are replaced
+/// with the ones they look like and invisible ones are dropped.
+///
+/// Only valid user IDs count, so long names that just happen to be made of
+/// letters and digits are fine.
+pub(crate) fn looks_like_user_id(name: &str) -> bool {
+    let visible: Vec<char> = name.chars()
+        .filter(|c| !is_invisible(*c))
+        .map(skeleton)
+        .collect();
+
+    for run in visible.split(|c| !is_base58(*c)) {
+        for length in ID_LENGTHS.iter() {
+            for window in run.windows(*length) {
+                let candidate: String = window.iter().collect();
+                if UserID::from_base58(&candidate).is_ok() {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Is `c` in the base58 alphabet? (ASCII letters and digits, except for 0, O,
+/// I and l, which look like each other.)
+fn is_base58(c: char) -> bool {
+    match c {
+        '0' | 'O' | 'I' | 'l' => false,
+        c => c.is_ascii_alphanumeric(),
+    }
+}
+
+/// The ASCII character that `c` looks like, or `c`.
+fn skeleton(c: char) -> char {
+    match c {
+        // Fullwidth forms. (ex: "Ａ")
+        '\u{FF01}'..='\u{FF5E}' => std::char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+
+        // Cyrillic:
+        'а' => 'a', 'в' => 'B', 'е' => 'e', 'к' => 'k', 'м' => 'M', 'н' => 'H',
+        'о' => 'o', 'р' => 'p', 'с' => 'c', 'т' => 'T', 'у' => 'y', 'х' => 'x',
+        'і' => 'i', 'ј' => 'j', 'ѕ' => 's', 'ԁ' => 'd', 'ԛ' => 'q', 'ԝ' => 'w',
+        'А' => 'A', 'В' => 'B', 'Е' => 'E', 'К' => 'K', 'М' => 'M', 'Н' => 'H',
+        'О' => 'O', 'Р' => 'P', 'С' => 'C', 'Т' => 'T', 'Х' => 'X', 'І' => 'I',
+        'Ј' => 'J', 'Ѕ' => 'S', 'Ү' => 'Y', 'Ԛ' => 'Q', 'Ԝ' => 'W',
+
+        // Greek:
+        'Α' => 'A', 'Β' => 'B', 'Ε' => 'E', 'Ζ' => 'Z', 'Η' => 'H', 'Ι' => 'I',
+        'Κ' => 'K', 'Μ' => 'M', 'Ν' => 'N', 'Ο' => 'O', 'Ρ' => 'P', 'Τ' => 'T',
+        'Υ' => 'Y', 'Χ' => 'X', 'ο' => 'o', 'ν' => 'v', 'ι' => 'i', 'κ' => 'k',
+
+        // Digits and letters from other scripts:
+        'ℓ' => 'l', 'ı' => 'i', 'ɡ' => 'g', 'ʏ' => 'y',
+        '٠' | '۰' => '0', '١' | '۱' => '1',
+
+        c => c,
+    }
+}
+
+/// Characters that take up no space, so they can hide in the middle of an ID.
+fn is_invisible(c: char) -> bool {
+    match c {
+        '\u{00AD}' // soft hyphen
+        | '\u{034F}' // combining grapheme joiner
+        | '\u{180E}' // Mongolian vowel separator
+        | '\u{200B}'..='\u{200F}' // zero-width spaces, joiners, and direction marks
+        | '\u{202A}'..='\u{202E}' // direction embeddings and overrides
+        | '\u{2060}'..='\u{2064}' // word joiner, invisible operators
+        | '\u{2066}'..='\u{2069}' // direction isolates
+        | '\u{FE00}'..='\u{FE0F}' // variation selectors
+        | '\u{FEFF}' // byte order mark
+        => true,
+        // Combining marks, ex: "a\u{0301}" still reads as an "a":
+        '\u{0300}'..='\u{036F}' => true,
+        _ => false,
+    }
+}
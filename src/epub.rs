@@ -0,0 +1,141 @@
+//! EPUB books of single posts, so people can read long articles on e-readers.
+//! (See: `opds.rs`)
+//!
+//! See: <https://www.w3.org/publishing/epub3/epub-spec.html>
+
+use failure::Error;
+
+use crate::backend::Timestamp;
+use crate::follows::escape;
+use crate::zip::ZipWriter;
+
+const CONTAINER_XML: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+pub(crate) struct Book {
+    /// A unique identifier for the book. We use the item's URL.
+    pub id: String,
+    pub title: String,
+    pub author: String,
+
+    /// A BCP 47 language tag for the book's text. (ex: "en")
+    pub lang: String,
+    pub published: Timestamp,
+
+    /// `published`, formatted for people, in the author's time zone.
+    pub date: String,
+
+    /// XHTML for the article. Must already be sanitized. (ex: `md_to_html()`)
+    pub content_html: String,
+
+    /// An SPDX license identifier, or "" if the author didn't give one.
+    pub license: String,
+
+    /// Images (file name, media type, bytes) that `content_html` may refer to
+    /// as `files/<name>`, like it does on the item's page.
+    pub images: Vec<(String, String, Vec<u8>)>,
+}
+
+impl Book {
+    pub fn to_epub(&self) -> Result<Vec<u8>, Error> {
+        let mtime = self.published.unix_utc_ms / 1000;
+        let mut zip = ZipWriter::new(vec![]);
+        // Must be first, so that the file can be identified by its first bytes:
+        zip.append("mimetype", b"application/epub+zip", mtime)?;
+        zip.append("META-INF/container.xml", CONTAINER_XML.as_bytes(), mtime)?;
+        zip.append("OEBPS/content.opf", self.package().as_bytes(), mtime)?;
+        zip.append("OEBPS/nav.xhtml", self.nav().as_bytes(), mtime)?;
+        zip.append("OEBPS/article.xhtml", self.article().as_bytes(), mtime)?;
+        for (name, _, bytes) in &self.images {
+            zip.append(&format!("OEBPS/files/{}", name), bytes, mtime)?;
+        }
+        zip.finish()
+    }
+
+    fn package(&self) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        xml.push_str(&format!(
+            "<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"id\" xml:lang=\"{}\">\n",
+            escape(&self.lang),
+        ));
+        xml.push_str("  <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n");
+        xml.push_str(&format!("    <dc:identifier id=\"id\">{}</dc:identifier>\n", escape(&self.id)));
+        xml.push_str(&format!("    <dc:title>{}</dc:title>\n", escape(&self.title)));
+        xml.push_str(&format!("    <dc:creator>{}</dc:creator>\n", escape(&self.author)));
+        xml.push_str(&format!("    <dc:language>{}</dc:language>\n", escape(&self.lang)));
+        xml.push_str(&format!("    <dc:date>{}</dc:date>\n", self.published.format_rfc3339()));
+        if !self.license.is_empty() {
+            xml.push_str(&format!("    <dc:rights>{}</dc:rights>\n", escape(&self.license)));
+        }
+        // Items can't change, so they were last modified when they were written:
+        xml.push_str(&format!("    <meta property=\"dcterms:modified\">{}</meta>\n", self.published.format_rfc3339()));
+        xml.push_str("  </metadata>\n");
+
+        xml.push_str("  <manifest>\n");
+        xml.push_str("    <item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>\n");
+        xml.push_str("    <item id=\"article\" href=\"article.xhtml\" media-type=\"application/xhtml+xml\"/>\n");
+        for (index, (name, media_type, _)) in self.images.iter().enumerate() {
+            xml.push_str(&format!(
+                "    <item id=\"file-{}\" href=\"files/{}\" media-type=\"{}\"/>\n",
+                index, escape(&href(name)), escape(media_type),
+            ));
+        }
+        xml.push_str("  </manifest>\n");
+
+        xml.push_str("  <spine>\n");
+        xml.push_str("    <itemref idref=\"article\"/>\n");
+        xml.push_str("  </spine>\n");
+        xml.push_str("</package>\n");
+        xml
+    }
+
+    /// EPUB 3 requires a table of contents, even for one chapter.
+    fn nav(&self) -> String {
+        let body = format!(
+            "<nav epub:type=\"toc\">\n<ol>\n<li><a href=\"article.xhtml\">{}</a></li>\n</ol>\n</nav>\n",
+            escape(&self.title),
+        );
+        self.xhtml(&body)
+    }
+
+    fn article(&self) -> String {
+        let mut body = format!("<h1>{}</h1>\n", escape(&self.title));
+        body.push_str(&format!("<p><i>{}, {}</i></p>\n", escape(&self.author), escape(&self.date)));
+        body.push_str(&self.content_html);
+        if !self.license.is_empty() {
+            body.push_str(&format!("\n<p><small>License: {}</small></p>\n", escape(&self.license)));
+        }
+        self.xhtml(&body)
+    }
+
+    fn xhtml(&self, body: &str) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        xml.push_str("<!DOCTYPE html>\n");
+        xml.push_str(&format!(
+            "<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" xml:lang=\"{}\" lang=\"{}\">\n",
+            escape(&self.lang), escape(&self.lang),
+        ));
+        xml.push_str(&format!("<head><title>{}</title></head>\n", escape(&self.title)));
+        xml.push_str("<body>\n");
+        xml.push_str(body);
+        xml.push_str("</body>\n</html>\n");
+        xml
+    }
+}
+
+/// Characters to escape in file names, which can have spaces and such.
+const HREF_ESCAPES: &percent_encoding::AsciiSet = &percent_encoding::NON_ALPHANUMERIC
+    .remove(b'.')
+    .remove(b'-')
+    .remove(b'_');
+
+fn href(name: &str) -> String {
+    percent_encoding::utf8_percent_encode(name, HREF_ESCAPES).to_string()
+}
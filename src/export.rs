@@ -0,0 +1,96 @@
+//! Archives all of a user's items, so they can back them up or move them
+//! without scraping a server. (`feoblog export`)
+//!
+//! An export is a tar file laid out like the server's URLs:
+//!
+//! ```text
+//! README.txt
+//! bundle.proto3                            All items, as an ItemBundle.
+//! u/<userID>/i/<signature>/proto3          Each item's exact signed bytes.
+//! u/<userID>/i/<signature>/files/<name>    Its attachments.
+//! ```
+//!
+//! Items keep their signatures, so an archive can be checked no matter how it
+//! was stored. `feoblog import` restores the items and files into another
+//! server.
+
+use std::io::Write;
+
+use failure::Error;
+use protobuf::Message as _;
+
+use crate::backend::{Backend, Timestamp, UserID};
+use crate::bundle::bundled_item;
+use crate::protos::{Item, ItemBundle};
+use crate::tar::TarWriter;
+
+const README: &str = "\
+A FeoBlog export: every item that one user had on a server.
+
+bundle.proto3
+    All of the items, as an ItemBundle protobuf. (See: feoblog.proto)
+    Restore this whole archive into a FeoBlog server with `feoblog import`.
+
+u/<userID>/i/<signature>/proto3
+    Each Item's protobuf bytes, exactly as the user signed them.
+
+u/<userID>/i/<signature>/files/<name>
+    Files attached to the item. (See: Item.attachments)
+";
+
+pub(crate) struct ExportReport {
+    pub items: usize,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Write a tar archive of `user`'s items (including followers-only ones) and
+/// their attachments to `out`. Removed items are left out.
+pub(crate) fn export_user(backend: &dyn Backend, user: &UserID, out: impl Write) -> Result<ExportReport, Error> {
+    let mut rows = vec![];
+    let all = Timestamp{ unix_utc_ms: i64::MAX };
+    backend.user_items(user, all, &mut |row| {
+        rows.push(row);
+        Ok(true)
+    })?;
+    backend.followers_only_items(user, user, all, &mut |row| {
+        rows.push(row);
+        Ok(true)
+    })?;
+    // Oldest first, like a journal:
+    rows.sort_by_key(|row| row.timestamp.unix_utc_ms);
+
+    let mut tar = TarWriter::new(out);
+    let now = Timestamp::now().unix_utc_ms / 1000;
+    tar.append("README.txt", README.as_bytes(), now)?;
+
+    let mut report = ExportReport { items: 0, files: 0, bytes: 0 };
+    let mut bundle = ItemBundle::new();
+    for row in &rows {
+        let mut item = Item::new();
+        item.merge_from_bytes(&row.item_bytes)?;
+        let mtime = row.timestamp.unix_utc_ms / 1000;
+        let dir = format!("u/{}/i/{}", user.to_base58(), row.signature.to_base58());
+
+        tar.append(&format!("{}/proto3", dir), &row.item_bytes, mtime)?;
+        bundle.items.push(bundled_item(row));
+        report.items += 1;
+        report.bytes += row.item_bytes.len() as u64;
+
+        for file in item.get_attachments().get_file() {
+            let name = file.get_name();
+            // Not uploaded (yet), or removed:
+            let data = match backend.followers_only_attachment(user, &row.signature, name, user)? {
+                Some(data) => data,
+                None => continue,
+            };
+            tar.append(&format!("{}/files/{}", dir, name), &data, mtime)?;
+            report.files += 1;
+            report.bytes += data.len() as u64;
+        }
+    }
+
+    tar.append("bundle.proto3", &bundle.write_to_bytes()?, now)?;
+    tar.finish()?;
+    Ok(report)
+}
@@ -0,0 +1,212 @@
+//! Import and export follow lists as OPML or JSON, so that people can move
+//! their subscriptions between accounts or feed readers.
+//!
+//! Each follow is exported as an `<outline>` with a `feoblogUserID` attribute.
+//! When importing, we also recognize outlines whose `htmlUrl` or `xmlUrl`
+//! point to a FeoBlog user page. (ex: `https://feo.example.com/u/{userID}/`)
+//!
+//! The JSON form is for scripts and other tools:
+//!
+//! ```json
+//! {"follows": [{"userID": "...", "displayName": "...", "url": "https://feo.example.com/u/.../"}]}
+//! ```
+//!
+//! `url` is only included if there's a base URL to link to.
+
+use std::collections::HashSet;
+
+use failure::{Error, format_err};
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{Timestamp, UserID};
+use crate::protos::{Follow, Item, Profile};
+
+/// Write a profile's follows as an OPML document.
+///
+/// If `base_url` is given, outlines link to each user's page on that server.
+pub(crate) fn export_opml(profile: &Profile, base_url: Option<&str>) -> Result<String, Error> {
+    let mut opml = String::new();
+    opml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    opml.push_str("<opml version=\"2.0\">\n");
+    opml.push_str("<head><title>");
+    opml.push_str(&escape(&format!("Follows of {}", profile.get_display_name())));
+    opml.push_str("</title></head>\n");
+    opml.push_str("<body>\n");
+
+    for follow in profile.get_follows() {
+        let user = UserID::from_vec(follow.get_user().get_bytes().to_vec())?;
+        let user = user.to_base58();
+        let name = if follow.get_display_name().is_empty() { user.as_str() } else { follow.get_display_name() };
+
+        opml.push_str(&format!("  <outline text=\"{}\" feoblogUserID=\"{}\"", escape(name), user));
+        if let Some(base_url) = base_url {
+            let base_url = base_url.trim_end_matches('/');
+            opml.push_str(&format!(" htmlUrl=\"{}\"", escape(&format!("{}/u/{}/", base_url, user))));
+        }
+        opml.push_str("/>\n");
+    }
+
+    opml.push_str("</body>\n");
+    opml.push_str("</opml>\n");
+    Ok(opml)
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonFollows {
+    follows: Vec<JsonFollow>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct JsonFollow {
+    #[serde(rename = "userID")]
+    user_id: String,
+
+    #[serde(default)]
+    display_name: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+}
+
+/// Write a profile's follows as JSON. (See above.)
+///
+/// If `base_url` is given, each follow links to the user's page on that server.
+pub(crate) fn export_json(profile: &Profile, base_url: Option<&str>) -> Result<String, Error> {
+    let mut follows = vec![];
+    for follow in profile.get_follows() {
+        let user = UserID::from_vec(follow.get_user().get_bytes().to_vec())?.to_base58();
+        let url = base_url.map(|base_url| format!("{}/u/{}/", base_url.trim_end_matches('/'), user));
+        follows.push(JsonFollow {
+            user_id: user,
+            display_name: follow.get_display_name().to_string(),
+            url,
+        });
+    }
+    Ok(serde_json::to_string_pretty(&JsonFollows{ follows })?)
+}
+
+/// Read the follows from JSON written by `export_json`.
+pub(crate) fn import_json(json: &str) -> Result<Vec<Follow>, Error> {
+    let parsed: JsonFollows = serde_json::from_str(json)?;
+    let mut follows = vec![];
+    for json_follow in parsed.follows {
+        let user = json_follow.user_id.parse::<UserID>()
+            .map_err(|_| format_err!("Invalid userID: {}", json_follow.user_id))?;
+        let mut follow = Follow::new();
+        follow.mut_user().set_bytes(user.bytes().into());
+        follow.set_display_name(json_follow.display_name);
+        follows.push(follow);
+    }
+    Ok(follows)
+}
+
+/// Read the follows from an OPML document.
+/// Outlines that don't refer to a FeoBlog user are skipped.
+pub(crate) fn import_opml(opml: &str) -> Vec<Follow> {
+    let mut follows = vec![];
+    for attrs in outlines(opml) {
+        let find = |name: &str| attrs.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str());
+
+        let user = find("feoblogUserID")
+            .and_then(|id| id.parse::<UserID>().ok())
+            .or_else(|| find("htmlUrl").and_then(user_from_url))
+            .or_else(|| find("xmlUrl").and_then(user_from_url));
+        let user = match user {
+            Some(user) => user,
+            None => continue,
+        };
+
+        let mut follow = Follow::new();
+        follow.mut_user().set_bytes(user.bytes().into());
+        follow.set_display_name(find("text").or_else(|| find("title")).unwrap_or("").into());
+        follows.push(follow);
+    }
+    follows
+}
+
+/// Create a new Profile Item, with `follows` added to the user's existing
+/// profile (if any). The caller must sign it, since we don't have the user's key.
+pub(crate) fn profile_with_follows(existing: Option<Item>, follows: Vec<Follow>) -> Item {
+    let mut item = existing.unwrap_or_else(Item::new);
+    let profile = item.mut_profile();
+
+    let mut known: HashSet<Vec<u8>> = profile.get_follows().iter()
+        .map(|f| f.get_user().get_bytes().to_vec())
+        .collect();
+    for follow in follows {
+        if known.insert(follow.get_user().get_bytes().to_vec()) {
+            profile.mut_follows().push(follow);
+        }
+    }
+
+    item.set_timestamp_ms_utc(Timestamp::now().unix_utc_ms);
+    item
+}
+
+/// ex: `https://feo.example.com/u/{userID}/` or `.../u/{userID}/proto3`
+fn user_from_url(url: &str) -> Option<UserID> {
+    let mut parts = url.split('/');
+    parts.find(|part| *part == "u")?;
+    parts.next()?.parse().ok()
+}
+
+/// The attributes of each `<outline>` element.
+/// This is far from a full XML parser, but handles the OPML that feed readers export.
+fn outlines(opml: &str) -> Vec<Vec<(String, String)>> {
+    let mut results = vec![];
+    let mut rest = opml;
+    while let Some(start) = rest.find("<outline") {
+        rest = &rest[start + "<outline".len()..];
+        let end = match rest.find('>') {
+            Some(end) => end,
+            None => break,
+        };
+        results.push(attributes(&rest[..end]));
+        rest = &rest[end..];
+    }
+    results
+}
+
+fn attributes(mut tag: &str) -> Vec<(String, String)> {
+    let mut attrs = vec![];
+    loop {
+        tag = tag.trim_start();
+        let eq = match tag.find('=') {
+            Some(eq) => eq,
+            None => break,
+        };
+        let name = tag[..eq].trim().to_string();
+        tag = tag[eq + 1..].trim_start();
+
+        let quote = match tag.chars().next() {
+            Some(q) if q == '"' || q == '\'' => q,
+            _ => break,
+        };
+        tag = &tag[1..];
+        let close = match tag.find(quote) {
+            Some(close) => close,
+            None => break,
+        };
+        attrs.push((name, unescape(&tag[..close])));
+        tag = &tag[close + 1..];
+    }
+    attrs
+}
+
+/// Escape text for use in XML content or attribute values.
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
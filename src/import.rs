@@ -0,0 +1,133 @@
+//! Restores items from an archive made by `feoblog export`. (`feoblog import`)
+//!
+//! Items are checked like uploads to `put_item` (See: `bundle::check_upload`),
+//! so an archive can't bring in anything that the server wouldn't accept over
+//! HTTP. Attachments must match the hash and size in their item.
+
+use std::io::Read;
+
+use failure::Error;
+use sodiumoxide::crypto::hash::sha512;
+
+use crate::backend::{Backend, Signature, Timestamp, UserID};
+use crate::bundle::{Upload, check_upload};
+use crate::tar::TarReader;
+
+#[derive(Default)]
+pub(crate) struct ImportReport {
+    /// Newly saved.
+    pub items: usize,
+
+    /// Items and files that were already on this server.
+    pub existing: usize,
+    pub files: usize,
+
+    /// What we didn't import, and why.
+    pub refused: Vec<String>,
+}
+
+/// Save the items and attachments in a tar archive from `feoblog export`.
+pub(crate) fn import_archive(backend: &mut dyn Backend, input: impl Read, max_attachment_bytes: u64) -> Result<ImportReport, Error> {
+    let mut report = ImportReport::default();
+    let mut tar = TarReader::new(input);
+    while let Some((path, size)) = tar.next_file()? {
+        let parts: Vec<&str> = path.split('/').collect();
+        match parts.as_slice() {
+            ["u", user, "i", signature, "proto3"] => {
+                let (user, signature) = match parse_ids(user, signature) {
+                    Ok(ids) => ids,
+                    Err(err) => {
+                        report.refused.push(format!("{}: {}", path, err));
+                        continue;
+                    },
+                };
+                if size > crate::server::MAX_ITEM_SIZE as u64 {
+                    report.refused.push(format!("{}: The item is larger than {} bytes", path, crate::server::MAX_ITEM_SIZE));
+                    continue;
+                }
+                let bytes = tar.read_file(size)?;
+                match check_upload(backend, user, signature, &bytes, Timestamp::now())? {
+                    Upload::New(row, item) => {
+                        backend.save_user_item(&row, &item)?;
+                        report.items += 1;
+                    },
+                    Upload::Exists => report.existing += 1,
+                    Upload::Refused(reason) => report.refused.push(format!("{}: {}", path, reason)),
+                }
+            },
+            ["u", user, "i", signature, "files", name] => {
+                let (user, signature) = match parse_ids(user, signature) {
+                    Ok(ids) => ids,
+                    Err(err) => {
+                        report.refused.push(format!("{}: {}", path, err));
+                        continue;
+                    },
+                };
+                if backend.attachment_exists(&user, &signature, name)? {
+                    report.existing += 1;
+                    continue;
+                }
+                if let Some(reason) = check_attachment(backend, &user, &signature, name, size, max_attachment_bytes)? {
+                    report.refused.push(format!("{}: {}", path, reason));
+                    continue;
+                }
+                let bytes = tar.read_file(size)?;
+                if !attachment_matches(backend, &user, &signature, name, &bytes)? {
+                    report.refused.push(format!("{}: The file doesn't match the hash in its item", path));
+                    continue;
+                }
+                backend.save_attachment(&user, &signature, name, &bytes)?;
+                report.files += 1;
+            },
+            // README.txt, and bundle.proto3, which has the same items:
+            _ => {},
+        }
+    }
+    Ok(report)
+}
+
+fn parse_ids(user: &str, signature: &str) -> Result<(UserID, Signature), Error> {
+    Ok((UserID::from_base58(user)?, Signature::from_base58(signature)?))
+}
+
+/// Why we won't save an attachment (before reading it), if we won't.
+/// Same rules as `put_attachment`.
+fn check_attachment(
+    backend: &dyn Backend,
+    user: &UserID,
+    signature: &Signature,
+    name: &str,
+    size: u64,
+    max_attachment_bytes: u64,
+) -> Result<Option<String>, Error> {
+    let file = match attachment_file(backend, user, signature, name)? {
+        Some(file) => file,
+        None => return Ok(Some("The item isn't on this server, or has no attachment with that name".into())),
+    };
+    if file.size != size {
+        return Ok(Some(format!("The item says the file is {} bytes", file.size)));
+    }
+    if size > max_attachment_bytes {
+        return Ok(Some(format!("Attachments must be <= {} bytes", max_attachment_bytes)));
+    }
+    Ok(None)
+}
+
+fn attachment_matches(backend: &dyn Backend, user: &UserID, signature: &Signature, name: &str, bytes: &[u8]) -> Result<bool, Error> {
+    let file = match attachment_file(backend, user, signature, name)? {
+        Some(file) => file,
+        None => return Ok(false),
+    };
+    Ok(file.size == bytes.len() as u64 && sha512::hash(bytes).as_ref() == file.get_hash())
+}
+
+/// The item's listing for an attachment. Followers-only items may have them too.
+fn attachment_file(backend: &dyn Backend, user: &UserID, signature: &Signature, name: &str) -> Result<Option<crate::protos::File>, Error> {
+    let row = match backend.followers_only_item(user, signature, user)? {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    let mut item = crate::protos::Item::new();
+    protobuf::Message::merge_from_bytes(&mut item, &row.item_bytes)?;
+    Ok(item.get_attachments().get_file().iter().find(|file| file.get_name() == name).cloned())
+}
@@ -0,0 +1,67 @@
+//! JSON Feed output, for feed readers that prefer JSON to XML.
+//!
+//! See: <https://jsonfeed.org/version/1.1>
+
+use failure::Error;
+use serde::Serialize;
+
+use crate::atom::Entry;
+
+/// Entries are shared with the Atom and RSS feeds.
+pub(crate) struct Feed {
+    pub title: String,
+    pub home_page_url: String,
+
+    /// This feed's own (absolute) URL.
+    pub feed_url: String,
+
+    /// A BCP 47 language tag. (ex: "en")
+    pub language: String,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Serialize)]
+struct JsonFeed<'a> {
+    version: &'static str,
+    title: &'a str,
+    home_page_url: &'a str,
+    feed_url: &'a str,
+    language: &'a str,
+    items: Vec<JsonItem<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonItem<'a> {
+    id: &'a str,
+    url: &'a str,
+    title: &'a str,
+    content_html: &'a str,
+    date_published: String,
+    authors: Vec<JsonAuthor<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonAuthor<'a> {
+    name: &'a str,
+}
+
+impl Feed {
+    pub fn to_json(&self) -> Result<String, Error> {
+        let feed = JsonFeed {
+            version: "https://jsonfeed.org/version/1.1",
+            title: &self.title,
+            home_page_url: &self.home_page_url,
+            feed_url: &self.feed_url,
+            language: &self.language,
+            items: self.entries.iter().map(|entry| JsonItem {
+                id: &entry.id,
+                url: &entry.id,
+                title: &entry.title,
+                content_html: &entry.content_html,
+                date_published: entry.updated.format_rfc3339(),
+                authors: vec![JsonAuthor{ name: &entry.author }],
+            }).collect(),
+        };
+        Ok(serde_json::to_string(&feed)?)
+    }
+}
@@ -7,24 +7,77 @@ mod tests;
 use crate::backend::ServerUser;
 use crate::backend::Factory;
 use crate::backend::UserID;
+use crate::backend::Signature;
+use crate::backend::Timestamp;
+use crate::backend::IpBlock;
+use crate::backend::Quota;
+use crate::backend::CrossPostService;
+use crate::backend::CrossPostTarget;
+use crate::server::blocklist::Cidr;
+use crate::protos::ItemType;
 use std::io;
+use std::path::PathBuf;
 
 use failure::{Error, bail, ResultExt};
 use structopt::StructOpt;
 
+mod atom;
 mod backend;
+mod bloom;
+mod bundle;
+mod epub;
+mod export;
+mod import;
+mod config;
+mod conformance;
+mod confusables;
+mod follows;
+mod json_feed;
 mod markdown;
+mod opds;
 mod protos;
+mod rss;
+mod seed;
+mod tags;
+mod tar;
+mod zip;
 mod server;
+mod sync;
 
 
 fn main() -> Result<(), Error> {
-    let command = Command::from_args();
+    let matches = Command::clap().get_matches();
+    let command = Command::from_clap(&matches);
     use Command::*;
 
     match command {
-        Serve(command) => server::serve(command)?,
+        Serve(mut command) => {
+            if let Some(path) = command.config.clone() {
+                let matches = matches.subcommand_matches("serve").expect("serve options");
+                config::Config::load(&path)?.apply(&mut command, matches)?;
+            }
+            server::serve(command)?
+        },
         User(command) => command.main()?,
+        Mod(command) => command.main()?,
+        Block(command) => command.main()?,
+        Sync(command) => command.main()?,
+        Thread(command) => command.main()?,
+        Follows(command) => command.main()?,
+        Export(command) => command.main()?,
+        Import(command) => command.main()?,
+        Feeds(command) => command.main()?,
+        Conformance(command) => command.main()?,
+        Peers(command) => command.main()?,
+        Comments(command) => command.main()?,
+        Announce(command) => command.main()?,
+        Maintenance(command) => command.main()?,
+        Nostr(command) => command.main()?,
+        Crosspost(command) => command.main()?,
+        Db(command) => command.main()?,
+        Stats(command) => command.main()?,
+        Linkcheck(command) => command.main()?,
+        Devel(command) => command.main()?,
     };
 
     Ok(())
@@ -41,7 +94,68 @@ enum Command
     /// Start a server.
     Serve(ServeCommand),
 
-    User(UserCommand)
+    User(UserCommand),
+
+    /// Moderation tools for server admins.
+    #[structopt(name="mod")]
+    Mod(ModCommand),
+
+    /// Manage IP addresses that are blocked from accessing the server.
+    Block(BlockCommand),
+
+    /// Compare or copy items between this server and others.
+    Sync(SyncCommand),
+
+    /// Export or import a post and its thread as a portable bundle.
+    Thread(ThreadCommand),
+
+    /// Export or import a user's follows as OPML.
+    Follows(FollowsCommand),
+
+    /// Write all of a user's items and attachments to a tar archive.
+    /// (ex: to back them up, or move to another server)
+    Export(ExportCommand),
+
+    /// Save the items and attachments from an archive made by `feoblog export`.
+    /// They're checked like any upload, so their users must be known here.
+    Import(ImportCommand),
+
+    /// Manage users' saved (custom) feeds.
+    Feeds(FeedsCommand),
+
+    /// Check that a server follows the FeoBlog protocol.
+    Conformance(ConformanceCommand),
+
+    /// Manage peer servers that new items are pushed to.
+    Peers(PeersCommand),
+
+    /// Review comments from visitors without a FeoBlog key.
+    Comments(CommentsCommand),
+
+    /// Show a notice on every page of the server. (ex: planned maintenance)
+    Announce(AnnounceCommand),
+
+    /// Pause writes to the database, so that it can be backed up or migrated.
+    Maintenance(MaintenanceCommand),
+
+    /// Manage the keys that users' posts are republished to Nostr with.
+    Nostr(NostrCommand),
+
+    /// Manage services (ex: Mastodon, Bluesky) that users' new posts are cross-posted to.
+    Crosspost(CrosspostCommand),
+
+    /// Check or repair the database.
+    Db(DbCommand),
+
+    /// Show how much the server has received each day, for capacity planning.
+    Stats(StatsCommand),
+
+    /// Find links in server users' posts that no longer work.
+    /// Dead links are shown on the users' stats pages.
+    Linkcheck(LinkcheckCommand),
+
+    /// Tools for developing FeoBlog. Not for use on a real server.
+    Devel(DevelCommand),
 }
 
 #[derive(StructOpt, Debug, Clone)]
@@ -50,6 +164,14 @@ struct ServeCommand {
     #[structopt(flatten)]
     shared_options: SharedOptions,
 
+    #[structopt(flatten)]
+    retention_options: RetentionOptions,
+
+    /// Read options from this TOML file. Options given on the command line
+    /// override it.
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
     /// Should we open a browser window?
     #[structopt(long)]
     open: bool,
@@ -57,15 +179,302 @@ struct ServeCommand {
     /// Bind to this local address.
     /// If unspecified, will try to bind to some port on localhost.
     #[structopt(long="bind")]
-    binds: Vec<String>
+    binds: Vec<String>,
+
+    /// Serve HTTPS on this local address. (ex: "0.0.0.0:443") May be repeated,
+    /// and used alongside --bind. Requires --cert and --key, or --acme-domain.
+    #[structopt(long="bind-tls")]
+    tls_binds: Vec<String>,
+
+    /// Serve HTTP on this local address, to a load balancer that starts each
+    /// connection with a PROXY protocol (v1 or v2) header, so that logs and
+    /// --block see clients' real addresses. May be repeated. Don't expose it
+    /// to anything else: the header is trusted.
+    #[structopt(long="bind-proxy")]
+    proxy_binds: Vec<String>,
+
+    /// More listeners, with per-listener options. Only from `[[listen]]`
+    /// tables in --config. (See: server/listen.rs)
+    #[structopt(skip)]
+    listens: Vec<server::listen::Listen>,
+
+    /// A PEM file with the TLS certificate, followed by any intermediate certificates.
+    #[structopt(long)]
+    cert: Option<PathBuf>,
+
+    /// A PEM file with the TLS certificate's private key.
+    #[structopt(long)]
+    key: Option<PathBuf>,
+
+    /// Get (and renew) a TLS certificate for this domain automatically, from
+    /// Let's Encrypt. May be repeated. Instead of --cert and --key.
+    /// The domain must also reach this server on port 80, with --bind.
+    #[structopt(long="acme-domain")]
+    acme_domains: Vec<String>,
+
+    /// Give the certificate authority this email address, for renewal reminders.
+    #[structopt(long)]
+    acme_email: Option<String>,
+
+    /// The ACME directory of the certificate authority to use.
+    #[structopt(long, default_value="https://acme-v02.api.letsencrypt.org/directory")]
+    acme_directory: String,
+
+    /// Refuse requests from this IP address or CIDR network.
+    /// May be repeated. These are in addition to any blocks added with
+    /// `feoblog block add`.
+    #[structopt(long="block")]
+    blocks: Vec<Cidr>,
+
+    /// Keep anonymous daily view counts for items.
+    /// No information about viewers (IP addresses, etc.) is stored.
+    #[structopt(long)]
+    count_views: bool,
+
+    /// How many uploads the server will receive at once.
+    /// Additional uploads are told to try again later.
+    #[structopt(long, default_value="16")]
+    max_concurrent_uploads: usize,
+
+    /// Drop uploads that send fewer than this many bytes per second.
+    /// (After a short grace period.) 0 = no limit.
+    #[structopt(long, default_value="1024")]
+    min_upload_rate: usize,
+
+    /// Refuse uploads of this item type. (ex: "post", "profile")
+    /// May be repeated.
+    #[structopt(long="reject-item-type", parse(try_from_str = parse_item_type))]
+    reject_item_types: Vec<ItemType>,
+
+    /// Fill in missing feed items by fetching them from followed users'
+    /// home servers when a feed is viewed.
+    #[structopt(long)]
+    proxy_feeds: bool,
+
+    /// Show maps for posts with locations, using tiles from this server.
+    /// ex: "https://tile.example.com/{z}/{x}/{y}.png"
+    /// Tiles are cached, and served from this server.
+    #[structopt(long)]
+    map_tiles: Option<String>,
+
+    /// The largest file attachment (in bytes) that the server will accept.
+    #[structopt(long, default_value="10485760")]
+    max_attachment_bytes: u64,
+
+    /// Let visitors without a FeoBlog key comment on posts. Their comments
+    /// wait in a queue for review. (See: `feoblog comments`)
+    #[structopt(long)]
+    anonymous_comments: bool,
+
+    /// On the home page and users' pages, show how many comments each item
+    /// has. (Comments are never listed there themselves.)
+    #[structopt(long)]
+    reply_counts: bool,
+
+    /// Check links in server users' posts weekly, and show dead ones on their
+    /// stats pages. (See: `feoblog linkcheck`)
+    #[structopt(long)]
+    linkcheck: bool,
+
+    /// The language of the server's pages and feeds, as a BCP 47 tag. (ex: "en", "de")
+    #[structopt(long, default_value="en")]
+    lang: String,
+
+    /// How to format dates on the server's pages. (ex: "%d.%m.%Y %H:%M")
+    /// See: https://docs.rs/time/0.2/time/index.html#formatting
+    #[structopt(long, default_value="%Y-%m-%d %H:%M:%S %z")]
+    date_format: String,
+
+    /// During maintenance, accept small item uploads anyway, and save them
+    /// once it's over. (See: `feoblog maintenance`)
+    #[structopt(long)]
+    maintenance_journal: bool,
+
+    /// Publish server users as ActivityPub actors, so that people on Mastodon
+    /// (etc.) can follow them. Takes an RSA private key (PKCS#8, PEM or DER)
+    /// that signs requests on their behalf.
+    #[structopt(long)]
+    activitypub_key: Option<String>,
+
+    /// Serve files from this directory before the ones built into the server
+    /// at /static/. (ex: to customize style.css) Changes show up immediately.
+    #[structopt(long, parse(from_os_str))]
+    static_dir: Option<PathBuf>,
+
+    /// Like --static-dir, for the web client at /client/.
+    /// (ex: "web-client/build", while developing it)
+    #[structopt(long, parse(from_os_str))]
+    client_dir: Option<PathBuf>,
+
+    /// Don't serve the in-browser client. (ex: for servers that are only
+    /// used through their API, or by other clients)
+    #[structopt(long)]
+    no_web_client: bool,
+
+    /// Republish posts to this Nostr relay, for users with Nostr keys.
+    /// (See: `feoblog nostr`) May be repeated. ex: "wss://relay.example.com"
+    #[structopt(long="nostr-relay")]
+    nostr_relays: Vec<String>,
+
+    /// Add uploaded items and attachments to the IPFS node with this RPC API.
+    /// ex: "http://127.0.0.1:5001"
+    #[structopt(long)]
+    ipfs_api: Option<String>,
+
+    /// A host name (with a port, if it's not the default) that clients use to
+    /// reach this server. (ex: "blog.example.com") May be repeated. Signed
+    /// requests (FeoBlog-Viewer and FeoBlog-Peer headers) must be signed for
+    /// one of these, or one of the --acme-domain or --onion-address names,
+    /// and are refused if there are none.
+    #[structopt(long="host")]
+    hosts: Vec<String>,
+
+    /// This server's Tor onion service address. (ex: "<56 characters>.onion")
+    /// Point the onion service at one of the --bind addresses in torrc. HTML
+    /// pages served elsewhere then get an Onion-Location header, so that
+    /// Tor Browser can offer to switch to it.
+    #[structopt(long)]
+    onion_address: Option<String>,
+
+    /// On SIGTERM or Ctrl-C, wait this many seconds for requests in progress
+    /// (ex: uploads) to finish before stopping.
+    #[structopt(long, default_value="30")]
+    drain_timeout: u64,
+
+    /// Accept items from users this server doesn't know, if they include a
+    /// proof-of-work of this many bits. (Each bit doubles the work.) 0 = only
+    /// accept items from known users.
+    #[structopt(long, default_value="0")]
+    pow_difficulty: u32,
+
+    /// Hold items that unknown users post with a proof-of-work until you
+    /// approve them. (See: `feoblog mod quarantined`)
+    #[structopt(long)]
+    quarantine_pow_items: bool,
+
+    /// Stop accepting uploads when the database files reach this size.
+    /// Homepage users may keep posting until the last 5%. 0 = no limit.
+    #[structopt(long, default_value="0")]
+    max_storage_bytes: u64,
+
+    /// Let the server with this key download backups of the database from
+    /// /backup. (See: `feoblog peers key` and `feoblog db backup`)
+    /// May be repeated.
+    #[structopt(long="backup-key")]
+    backup_keys: Vec<UserID>,
+
+    /// Let this user do things that are otherwise only for the server's
+    /// operator, when they sign in with a FeoBlog-Viewer header. (ex: manage
+    /// the IP blocklist at /admin/blocks, or see any user's stats.)
+    /// May be repeated.
+    #[structopt(long="admin")]
+    admins: Vec<UserID>,
+}
+
+fn parse_item_type(value: &str) -> Result<ItemType, Error> {
+    match value.to_lowercase().as_str() {
+        "post" => Ok(ItemType::POST),
+        "profile" => Ok(ItemType::PROFILE),
+        "poll" => Ok(ItemType::POLL),
+        "vote" => Ok(ItemType::VOTE),
+        "comment" => Ok(ItemType::COMMENT),
+        "delete" => Ok(ItemType::DELETE),
+        "reaction" => Ok(ItemType::REACTION),
+        _ => bail!("Unknown item type: {}", value),
+    }
+}
+
+fn parse_tag(value: &str) -> Result<String, Error> {
+    tags::normalize(value).ok_or_else(|| failure::format_err!("Invalid tag: {}", value))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BackendKind {
+    Sqlite,
+    Memory,
+}
+
+fn parse_backend_kind(value: &str) -> Result<BackendKind, Error> {
+    match value.to_lowercase().as_str() {
+        "sqlite" => Ok(BackendKind::Sqlite),
+        "memory" => Ok(BackendKind::Memory),
+        _ => bail!("Unknown backend: {} (Expected \"sqlite\" or \"memory\")", value),
+    }
 }
 
 // TODO: Rename BackendOptions?
 #[derive(StructOpt, Debug, Clone)]
 pub(crate) struct SharedOptions
 {
+    /// Where to keep data: "sqlite" (in --sqlite-file), or "memory", an
+    /// in-memory SQLite database that's lost when FeoBlog exits. (ex: to try
+    /// out `feoblog serve`)
+    #[structopt(long, default_value = "sqlite", parse(try_from_str = parse_backend_kind))]
+    pub backend: BackendKind,
+
     #[structopt(long, default_value = "feoblog.sqlite3")]
     pub sqlite_file: String,
+
+    /// Spread users across this many database files. (For very large servers.)
+    /// Must be the same every time the database is opened.
+    #[structopt(long, default_value = "1")]
+    pub shards: usize,
+
+    /// SQLite's page cache size, in KiB, for each open connection (and shard).
+    /// 0 = SQLite's default. (2000 KiB)
+    #[structopt(long, default_value = "0")]
+    pub sqlite_cache_kib: u64,
+}
+
+impl SharedOptions {
+    pub fn factory(&self) -> Result<backend::sharded::Factory, Error> {
+        match self.backend {
+            BackendKind::Sqlite => backend::sharded::Factory::new(&self.sqlite_file, self.shards, self.sqlite_cache_kib),
+            BackendKind::Memory => {
+                if self.shards != 1 {
+                    bail!("--shards can't be used with --backend memory");
+                }
+                Ok(backend::sharded::Factory::memory())
+            },
+        }
+    }
+}
+
+/// How long to keep bookkeeping, for `serve` and `db prune`.
+#[derive(StructOpt, Debug, Clone)]
+pub(crate) struct RetentionOptions
+{
+    /// Delete item events (see: `feoblog user events`) older than this many
+    /// days. 0 = keep them forever.
+    #[structopt(long, default_value = "0")]
+    pub keep_item_events_days: u64,
+
+    /// Delete reports from `feoblog sync verify` older than this many days.
+    /// 0 = keep them forever.
+    #[structopt(long, default_value = "180")]
+    pub keep_sync_reports_days: u64,
+
+    /// Delete security reports (see: `feoblog mod reports`) older than this
+    /// many days. 0 = keep them forever.
+    #[structopt(long, default_value = "90")]
+    pub keep_security_reports_days: u64,
+
+    /// Drop queued pushes, deliveries, cross-posts, and IPFS pins that have
+    /// been waiting this many days. (ex: after turning off --ipfs-api)
+    /// 0 = keep them until they succeed or give up.
+    #[structopt(long, default_value = "30")]
+    pub keep_stale_queue_days: u64,
+}
+
+impl RetentionOptions {
+    pub fn retention(&self) -> Result<backend::Retention, Error> {
+        Ok(backend::Retention {
+            item_event_days: self.keep_item_events_days,
+            sync_report_days: self.keep_sync_reports_days,
+            security_report_days: self.keep_security_reports_days,
+            stale_queue_days: self.keep_stale_queue_days,
+        })
+    }
 }
 
 #[derive(StructOpt, Debug, Clone)]
@@ -78,6 +487,25 @@ pub(crate) enum UserCommand {
 
     /// Remove a user
     Remove(UserRemoveCommand),
+
+    /// Limit how much a user may post.
+    SetQuota(UserSetQuotaCommand),
+
+    /// Show what has happened to a user's items on this server.
+    Events(UserEventsCommand),
+
+    /// List the users a user has muted. (Users manage their own mutes,
+    /// signed in, at `/u/{userID}/mutes/`.)
+    Mutes(UserMutesCommand),
+
+    /// Give a user a name, so that they can be found as `name@host`. (ex: from Mastodon)
+    Alias(UserAliasCommand),
+
+    /// Remove a user's name.
+    Unalias(UserUnaliasCommand),
+
+    /// List users' names.
+    Aliases(UserAliasesCommand),
 }
 
 impl UserCommand {
@@ -87,6 +515,12 @@ impl UserCommand {
             List(command) => command.main(),
             Add(command) => command.main(),
             Remove(command) => command.main(),
+            SetQuota(command) => command.main(),
+            Events(command) => command.main(),
+            Mutes(command) => command.main(),
+            Alias(command) => command.main(),
+            Unalias(command) => command.main(),
+            Aliases(command) => command.main(),
         }
     }
 }
@@ -99,7 +533,7 @@ struct UserListCommand {
 
 impl UserListCommand {
     fn main(&self) -> Result<(), Error> {
-        let factory = backend::sqlite::Factory::new(self.shared_options.sqlite_file.clone());
+        let factory = self.shared_options.factory()?;
         let conn = factory.open()?;
         
         conn.server_users(&mut |server_user| {
@@ -128,15 +562,19 @@ struct UserAddCommand {
     on_homepage: bool,
 
     /// Notes for the server admin
-    #[structopt(long, default_value="")]
+    #[structopt(long, alias="note", default_value="")]
     comment: String,
 }
 
 impl UserAddCommand {
     fn main(&self) -> Result<(), Error> {
-        let factory = backend::sqlite::Factory::new(self.shared_options.sqlite_file.clone());
+        let factory = self.shared_options.factory()?;
         let conn = factory.open()?;
 
+        if conn.server_user(&self.user_id)?.is_some() {
+            bail!("{} is already a user on this server.", self.user_id.to_base58());
+        }
+
         let user = ServerUser{
             user: self.user_id.clone(),
             on_homepage: self.on_homepage,
@@ -144,6 +582,7 @@ impl UserAddCommand {
         };
 
         conn.add_server_user(&user)?;
+        println!("Added {}. They can now post to this server.", self.user_id.to_base58());
         Ok(())
     }
 }
@@ -159,8 +598,2113 @@ struct UserRemoveCommand {
 
 impl UserRemoveCommand {
     fn main(&self) -> Result<(), Error> {
-        todo!();
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        if !conn.remove_server_user(&self.user_id)? {
+            bail!("{} is not a user on this server.", self.user_id.to_base58());
+        }
+        // Their items may still be shown to users who follow them. (See: `feoblog mod`)
+        println!("Removed {}. Their existing items were kept.", self.user_id.to_base58());
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct UserSetQuotaCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    user_id: UserID,
+
+    /// The most bytes of items and attachments the user may store. 0 = unlimited.
+    #[structopt(long, default_value="0")]
+    max_bytes: u64,
+
+    /// The most items the user may post in 24 hours. 0 = unlimited.
+    #[structopt(long, default_value="0")]
+    max_items_per_day: u64,
+}
+
+impl UserSetQuotaCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        let quota = Quota {
+            max_bytes: self.max_bytes,
+            max_items_per_day: self.max_items_per_day,
+        };
+        if !conn.set_user_quota(&self.user_id, &quota)? {
+            bail!("{} is not a user on this server.", self.user_id.to_base58());
+        }
+
+        let limit = |value: u64| if value == 0 { "unlimited".to_string() } else { value.to_string() };
+        println!("Max bytes: {}", limit(quota.max_bytes));
+        println!("Max items per day: {}", limit(quota.max_items_per_day));
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct UserEventsCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    user_id: UserID,
+}
+
+impl UserEventsCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        conn.user_item_events(&self.user_id, &mut |event| {
+            println!(
+                "{} {:<8} {}",
+                event.created.format_with_offset(0),
+                event.kind.as_str(),
+                event.signature.to_base58(),
+            );
+            Ok(true)
+        })?;
+
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct UserMutesCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    user_id: UserID,
+}
+
+impl UserMutesCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+        conn.muted_users(&self.user_id, &mut |muted| {
+            println!("{}", muted.to_base58());
+            Ok(true)
+        })?;
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct UserAliasCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    user_id: UserID,
+
+    /// Letters, numbers, ".", "-" and "_". Case-insensitive.
+    name: String,
+}
+
+impl UserAliasCommand {
+    fn main(&self) -> Result<(), Error> {
+        let name = server::webfinger::alias_name(&self.name)?;
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        if conn.server_user(&self.user_id)?.is_none() {
+            bail!("{} is not a user on this server.", self.user_id.to_base58());
+        }
+        if let Some(user) = conn.alias_user(&name)? {
+            if user.bytes() != self.user_id.bytes() {
+                bail!("{} is already the name of {}", name, user.to_base58());
+            }
+        }
+
+        conn.add_user_alias(&backend::UserAlias{
+            name,
+            user: self.user_id.clone(),
+            created: Timestamp::now(),
+        })?;
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct UserUnaliasCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    name: String,
+}
+
+impl UserUnaliasCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        if !conn.remove_user_alias(&self.name.to_lowercase())? {
+            bail!("No such name: {}", self.name);
+        }
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct UserAliasesCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+}
+
+impl UserAliasesCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+        conn.user_aliases(&mut |alias| {
+            println!("{} {}", alias.user.to_base58(), alias.name);
+            Ok(true)
+        })?;
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub(crate) enum ModCommand {
+    /// Remove an item from this server.
+    /// The item can be restored until it is purged.
+    Remove(ModItemCommand),
+
+    /// Restore a previously removed item.
+    Restore(ModItemCommand),
+
+    /// List items held by `serve --quarantine-pow-items`, oldest first.
+    /// (Columns: received time, bytes, user ID, signature)
+    Quarantined(ModQuarantinedCommand),
+
+    /// Serve a quarantined item, and push it to peers.
+    Approve(ModItemCommand),
+
+    /// Permanently delete items that were removed more than a grace period ago.
+    Purge(ModPurgeCommand),
+
+    /// List a user's items, and how much space they and their attachments take up.
+    /// (Columns: time, item bytes, attachment bytes, total bytes, signature)
+    Sizes(ModSizesCommand),
+
+    /// List possible injection attempts, newest first: markup removed from
+    /// uploaded items, and Content-Security-Policy violations that browsers
+    /// reported. (Columns: time, kind, user ID and signature or "-", detail)
+    Reports(ModReportsCommand),
+}
+
+impl ModCommand {
+    fn main(&self) -> Result<(), Error> {
+        use ModCommand::*;
+        match self {
+            Remove(command) => command.remove(),
+            Restore(command) => command.restore(),
+            Quarantined(command) => command.main(),
+            Approve(command) => command.approve(),
+            Purge(command) => command.main(),
+            Sizes(command) => command.main(),
+            Reports(command) => command.main(),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct ModItemCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    user_id: UserID,
+    signature: Signature,
+}
+
+impl ModItemCommand {
+    fn remove(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        if !conn.remove_user_item(&self.user_id, &self.signature)? {
+            bail!("No such item, or it was already removed.");
+        }
+        println!("Removed. Use `feoblog mod restore` to undo.");
+        Ok(())
+    }
+
+    fn restore(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        if !conn.restore_user_item(&self.user_id, &self.signature)? {
+            bail!("No such removed item. (It may have already been purged.)");
+        }
+        println!("Restored.");
+        Ok(())
+    }
+
+    fn approve(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        if !conn.approve_user_item(&self.user_id, &self.signature)? {
+            bail!("No such quarantined item. (See: `feoblog mod quarantined`)");
+        }
+        if let Some(row) = conn.user_item(&self.user_id, &self.signature)? {
+            let mut item = protos::Item::new();
+            protobuf::Message::merge_from_bytes(&mut item, &row.item_bytes)?;
+            if !server::viewer::is_followers_only(&item) {
+                conn.queue_push(&self.user_id, &self.signature)?;
+            }
+        }
+        println!("Approved.");
+        Ok(())
     }
 }
 
+#[derive(StructOpt, Debug, Clone)]
+struct ModQuarantinedCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+}
 
+impl ModQuarantinedCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        conn.quarantined_items(&mut |row| {
+            println!(
+                "{} {:>10} {} {}",
+                row.received.format_with_offset(0),
+                row.item_bytes.len(),
+                row.user.to_base58(),
+                row.signature.to_base58(),
+            );
+            Ok(true)
+        })?;
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct ModPurgeCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    /// Only purge items that were removed at least this many days ago.
+    #[structopt(long, default_value="30")]
+    grace_days: u32,
+}
+
+impl ModPurgeCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        let grace_ms = i64::from(self.grace_days) * 24 * 60 * 60 * 1000;
+        let removed_before = Timestamp{
+            unix_utc_ms: Timestamp::now().unix_utc_ms - grace_ms,
+        };
+
+        let count = conn.purge_removed_items(removed_before)?;
+        println!("Purged {} items.", count);
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct ModSizesCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    user_id: UserID,
+
+    /// List the largest items first, instead of the newest.
+    #[structopt(long)]
+    by_size: bool,
+
+    /// Only list this many items. (The total is for all of them.)
+    #[structopt(long)]
+    limit: Option<usize>,
+}
+
+impl ModSizesCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        let mut listed = 0;
+        let (mut count, mut total) = (0u64, 0u64);
+        conn.user_item_sizes(&self.user_id, self.by_size, &mut |size| {
+            let bytes = size.item_bytes + size.attachment_bytes;
+            count += 1;
+            total += bytes;
+            if self.limit.map(|limit| listed < limit).unwrap_or(true) {
+                listed += 1;
+                println!(
+                    "{} {:>10} {:>10} {:>10} {}",
+                    size.timestamp.format_with_offset(0),
+                    size.item_bytes,
+                    size.attachment_bytes,
+                    bytes,
+                    size.signature.to_base58(),
+                );
+            }
+            Ok(true)
+        })?;
+
+        println!("{} items, {} bytes", count, total);
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct ModReportsCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    /// Only list this many reports.
+    #[structopt(long, default_value = "50")]
+    limit: usize,
+}
+
+impl ModReportsCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        let mut listed = 0;
+        conn.security_reports(Timestamp::now(), &mut |report| {
+            let item = match &report.item {
+                Some((user, signature)) => format!("{} {}", user.to_base58(), signature.to_base58()),
+                None => "-".to_string(),
+            };
+            println!(
+                "{} {:<9} {} {}",
+                report.created.format_with_offset(0),
+                report.kind.as_str(),
+                item,
+                report.detail,
+            );
+            listed += 1;
+            Ok(listed < self.limit)
+        })?;
+
+        if listed == 0 {
+            println!("No reports.");
+        }
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub(crate) enum BlockCommand {
+    /// List blocked IP networks.
+    List(BlockListCommand),
+
+    /// Block an IP address or CIDR network.
+    /// Running servers pick it up within a minute.
+    Add(BlockAddCommand),
+
+    /// Unblock an IP address or CIDR network.
+    Remove(BlockRemoveCommand),
+}
+
+impl BlockCommand {
+    fn main(&self) -> Result<(), Error> {
+        use BlockCommand::*;
+        match self {
+            List(command) => command.main(),
+            Add(command) => command.main(),
+            Remove(command) => command.main(),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct BlockListCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+}
+
+impl BlockListCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        conn.ip_blocks(&mut |block| {
+            println!("{} {}", block.cidr, block.notes);
+            Ok(true)
+        })?;
+
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct BlockAddCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    cidr: Cidr,
+
+    /// Notes for the server admin
+    #[structopt(long, default_value="")]
+    comment: String,
+}
+
+impl BlockAddCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        conn.add_ip_block(&IpBlock{
+            cidr: self.cidr.to_string(),
+            notes: self.comment.clone(),
+            created: Timestamp::now(),
+        })?;
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct BlockRemoveCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    cidr: Cidr,
+}
+
+impl BlockRemoveCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        if !conn.remove_ip_block(&self.cidr.to_string())? {
+            bail!("{} was not blocked.", self.cidr);
+        }
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct AnnounceCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    /// The message to show. Omit to show the current announcement.
+    message: Option<String>,
+
+    /// Stop showing the message after this many hours.
+    #[structopt(long)]
+    hours: Option<u32>,
+
+    /// Remove the current announcement.
+    #[structopt(long, conflicts_with="message")]
+    clear: bool,
+}
+
+impl AnnounceCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        if self.clear {
+            conn.set_announcement(None)?;
+            return Ok(());
+        }
+
+        let message = match &self.message {
+            Some(message) => message,
+            None => {
+                match conn.announcement()? {
+                    None => println!("No announcement."),
+                    Some(announcement) => {
+                        println!("{}", announcement.message);
+                        if let Some(expires) = announcement.expires {
+                            println!("Expires: {}", expires.format_with_offset(0));
+                        }
+                    },
+                }
+                return Ok(());
+            },
+        };
+
+        let now = Timestamp::now();
+        conn.set_announcement(Some(&backend::Announcement{
+            message: message.clone(),
+            created: now,
+            expires: self.hours.map(|hours| Timestamp{
+                unix_utc_ms: now.unix_utc_ms + i64::from(hours) * 60 * 60 * 1000,
+            }),
+        }))?;
+        println!("Running servers will show the announcement within a minute.");
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub(crate) enum MaintenanceCommand {
+    /// Make servers refuse writes (with a 503) until `maintenance stop`.
+    Start(MaintenanceStartCommand),
+
+    /// Let servers write to the database again.
+    Stop(MaintenanceStopCommand),
+}
+
+impl MaintenanceCommand {
+    fn main(&self) -> Result<(), Error> {
+        use MaintenanceCommand::*;
+        match self {
+            Start(command) => command.main(),
+            Stop(command) => command.main(),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct MaintenanceStartCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    /// Tell clients to retry after this many seconds.
+    #[structopt(long, default_value="300")]
+    retry_after: u64,
+}
+
+impl MaintenanceStartCommand {
+    fn main(&self) -> Result<(), Error> {
+        server::maintenance::start(&self.shared_options.sqlite_file, self.retry_after)?;
+        println!("Servers will refuse writes until `feoblog maintenance stop`.");
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct MaintenanceStopCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+}
+
+impl MaintenanceStopCommand {
+    fn main(&self) -> Result<(), Error> {
+        server::maintenance::stop(&self.shared_options.sqlite_file)?;
+        println!("Writes are allowed again. Any journaled items will be saved within a few seconds.");
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub(crate) enum DbCommand {
+    /// Create a new database. (`serve` won't create one.)
+    Init(DbInitCommand),
+
+    /// Rebuild profiles, follows, votes, reactions, references, search, and user summaries
+    /// from the items themselves. (ex: after a bug left them inconsistent)
+    ///
+    /// Blocks writes while it runs, so consider `feoblog maintenance start` first.
+    Reindex(DbReindexCommand),
+
+    /// Delete old bookkeeping now. (`serve` also does this every few hours.)
+    Prune(DbPruneCommand),
+
+    /// Check that every stored item is still exactly what its author signed.
+    /// (ex: after disk problems, or editing the database by hand)
+    Verify(DbVerifyCommand),
+
+    /// Copy the database to a new file, while the server keeps running.
+    /// With --from, download a backup of another server's database instead.
+    Backup(DbBackupCommand),
+}
+
+impl DbCommand {
+    fn main(&self) -> Result<(), Error> {
+        use DbCommand::*;
+        match self {
+            Init(command) => command.main(),
+            Reindex(command) => command.main(),
+            Prune(command) => command.main(),
+            Verify(command) => command.main(),
+            Backup(command) => command.main(),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct DbInitCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+}
+
+impl DbInitCommand {
+    fn main(&self) -> Result<(), Error> {
+        let sqlite_file = &self.shared_options.sqlite_file;
+        if std::path::Path::new(sqlite_file).exists() {
+            bail!("{} already exists.", sqlite_file);
+        }
+        let factory = self.shared_options.factory()?;
+        factory.open()?.setup().context("Error setting up DB")?;
+        println!("Created {}", sqlite_file);
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct DbReindexCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+}
+
+impl DbReindexCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let mut conn = factory.open()?;
+        let report = conn.reindex()?;
+        println!("Reindexed {} items:", report.items);
+        println!("  {} profiles, {} follows", report.profiles, report.follows);
+        println!("  {} votes, {} reactions, {} references", report.votes, report.reactions, report.references);
+        println!("  {} posts in the search index", report.posts);
+        println!("  {} users with items", report.users);
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct DbPruneCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    #[structopt(flatten)]
+    retention_options: RetentionOptions,
+}
+
+impl DbPruneCommand {
+    fn main(&self) -> Result<(), Error> {
+        let retention = self.retention_options.retention()?;
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+        let report = conn.prune(&retention, Timestamp::now())?;
+        println!(
+            "Deleted {} item events, {} sync reports, {} security reports, and {} stale queue entries.",
+            report.item_events, report.sync_reports, report.security_reports, report.queued,
+        );
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct DbBackupCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    /// The base URL of a server to download a backup from. (ex: https://feo.example.com/)
+    /// It must list this server's key (`feoblog peers key`) with `serve --backup-key`.
+    #[structopt(long)]
+    from: Option<String>,
+
+    /// Where to save the backup. Must not exist yet.
+    #[structopt(parse(from_os_str))]
+    file: PathBuf,
+}
+
+impl DbBackupCommand {
+    fn main(&self) -> Result<(), Error> {
+        if self.file.exists() {
+            bail!("{} already exists", self.file.display());
+        }
+
+        let url = match &self.from {
+            Some(url) => url,
+            None => {
+                let factory = self.shared_options.factory()?;
+                factory.open()?.backup(&self.file)?;
+                println!("Saved a backup to {}", self.file.display());
+                return Ok(());
+            },
+        };
+
+        if sodiumoxide::init().is_err() {
+            bail!("Error initializing sodiumoxide");
+        }
+        let key = server::peer_auth::ServerKey::load(&self.shared_options.sqlite_file)?
+            .ok_or_else(|| failure::format_err!("This server has no key yet. Make one with `feoblog peers key`."))?;
+        let peer = sync::Peer::new(url).authenticated(key);
+
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&self.file)
+            .with_context(|_| format!("Error creating {}", self.file.display()))?;
+        let base_url = peer.base_url().to_string();
+        let mut system = actix_web::rt::System::new("db backup");
+        let (size, file) = system.block_on(async move {
+            let size = peer.backup(&mut file).await;
+            (size, file)
+        });
+        let size = match size {
+            Ok(size) => size,
+            Err(err) => {
+                // Don't leave a partial backup that looks like a real one:
+                drop(file);
+                let _ = std::fs::remove_file(&self.file);
+                return Err(err);
+            },
+        };
+        println!("Saved a backup of {} ({} bytes) to {}", base_url, size, self.file.display());
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct DbVerifyCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    /// Delete corrupt items, then reindex. There's no undo, so back up the
+    /// database first.
+    #[structopt(long)]
+    delete: bool,
+}
+
+impl DbVerifyCommand {
+    fn main(&self) -> Result<(), Error> {
+        if sodiumoxide::init().is_err() {
+            bail!("Error initializing sodiumoxide");
+        }
+        let factory = self.shared_options.factory()?;
+        let mut conn = factory.open()?;
+
+        let mut checked = 0;
+        let mut corrupt = vec![];
+        conn.all_items(&mut |row| {
+            checked += 1;
+            if let Err(err) = bundle::verify_row(&row) {
+                println!("{} {}: {}", row.user.to_base58(), row.signature.to_base58(), err);
+                corrupt.push((row.user, row.signature));
+            }
+            Ok(true)
+        })?;
+        println!("Checked {} items. {} were corrupt.", checked, corrupt.len());
+
+        if corrupt.is_empty() {
+            return Ok(());
+        }
+        if !self.delete {
+            println!("Run again with --delete to delete them.");
+            return Ok(());
+        }
+
+        for (user, signature) in &corrupt {
+            conn.delete_user_item(user, signature)?;
+        }
+        println!("Deleted {} items.", corrupt.len());
+        let report = conn.reindex().context("Error reindexing")?;
+        println!("Reindexed {} items.", report.items);
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct StatsCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    /// How many days back to report on, including today.
+    #[structopt(long, default_value="30")]
+    days: i64,
+
+    /// Print CSV instead. (ex: for a spreadsheet) Days with nothing are left out.
+    #[structopt(long)]
+    csv: bool,
+}
+
+impl StatsCommand {
+    fn main(&self) -> Result<(), Error> {
+        if self.days < 1 {
+            bail!("--days must be at least 1");
+        }
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        let today = Timestamp::now().unix_utc_days();
+        let mut days = vec![];
+        conn.daily_stats(today - self.days + 1, &mut |stats| {
+            days.push(stats);
+            Ok(true)
+        })?;
+
+        let date = |day: i64| Timestamp{ unix_utc_ms: day * 24 * 60 * 60 * 1000 }.format_with(0, "%Y-%m-%d");
+
+        if self.csv {
+            println!("day,items,item_bytes,attachments,attachment_bytes,rejections");
+            for s in &days {
+                println!(
+                    "{},{},{},{},{},{}",
+                    date(s.day), s.items, s.item_bytes, s.attachments, s.attachment_bytes, s.rejections,
+                );
+            }
+            return Ok(());
+        }
+
+        println!("{:<10} {:>8} {:>12} {:>8} {:>14} {:>9}", "Day", "Items", "Item bytes", "Files", "File bytes", "Rejected");
+        let mut total = backend::DailyStats::default();
+        for s in &days {
+            println!(
+                "{:<10} {:>8} {:>12} {:>8} {:>14} {:>9}",
+                date(s.day), s.items, s.item_bytes, s.attachments, s.attachment_bytes, s.rejections,
+            );
+            total.add(s);
+        }
+        println!(
+            "{:<10} {:>8} {:>12} {:>8} {:>14} {:>9}",
+            "Total", total.items, total.item_bytes, total.attachments, total.attachment_bytes, total.rejections,
+        );
+
+        let per_day = (total.item_bytes + total.attachment_bytes) as f64 / self.days as f64;
+        println!();
+        println!(
+            "Averaging {:.0} bytes/day. At that rate, storage grows {:.1} MiB in 30 days, and {:.1} MiB in a year.",
+            per_day,
+            per_day * 30.0 / (1024.0 * 1024.0),
+            per_day * 365.0 / (1024.0 * 1024.0),
+        );
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct LinkcheckCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    /// Check only this user's posts. May be repeated.
+    /// Defaults to all users hosted on this server.
+    #[structopt(long="user")]
+    users: Vec<UserID>,
+}
+
+impl LinkcheckCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        factory.open()?.setup().context("Error setting up DB")?;
+
+        let mut system = actix_web::rt::System::new("linkcheck");
+        let (check_factory, users) = (factory.clone(), self.users.clone());
+        let report = system.block_on(async move {
+            if users.is_empty() {
+                return server::linkcheck::check_all(&check_factory).await;
+            }
+            let mut checked = std::collections::HashMap::new();
+            let mut report = server::linkcheck::LinkCheckReport::default();
+            for user in &users {
+                let user_report = server::linkcheck::check_user(&check_factory, user, &mut checked).await?;
+                report.posts += user_report.posts;
+                report.links += user_report.links;
+                report.dead += user_report.dead;
+            }
+            Ok(report)
+        })?;
+        println!("Checked {} links in {} posts. {} were dead.", report.links, report.posts, report.dead);
+
+        let conn = factory.open()?;
+        let mut users = self.users.clone();
+        if users.is_empty() {
+            conn.server_users(&mut |server_user| {
+                users.push(server_user.user);
+                Ok(true)
+            })?;
+        }
+        for user in users {
+            conn.user_dead_links(&user, &mut |link| {
+                println!("{} {} {}: {}", user.to_base58(), link.signature.to_base58(), link.url, link.error);
+                Ok(true)
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+enum DevelCommand {
+    /// Fill a database with generated users and items. (ex: to try out the UI, or for benchmarks)
+    Seed(DevelSeedCommand),
+}
+
+impl DevelCommand {
+    fn main(&self) -> Result<(), Error> {
+        use DevelCommand::*;
+        match self {
+            Seed(command) => command.main(),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct DevelSeedCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    /// How many users to create.
+    #[structopt(long, default_value="10")]
+    users: usize,
+
+    /// How many items to create for each user, besides their profile.
+    #[structopt(long, default_value="50")]
+    posts: usize,
+
+    /// The same seed generates the same users and items.
+    #[structopt(long, default_value="1")]
+    seed: u64,
+}
+
+impl DevelSeedCommand {
+    fn main(&self) -> Result<(), Error> {
+        if sodiumoxide::init().is_err() {
+            bail!("Error initializing sodiumoxide");
+        }
+        let factory = self.shared_options.factory()?;
+        let mut conn = factory.open()?;
+        conn.setup().context("Error setting up DB")?;
+
+        let report = seed::seed(conn.as_mut(), self.users, self.posts, self.seed)?;
+        println!("Created {} items for {} users:", report.items, report.users.len());
+        for user in &report.users {
+            println!("{} {}", user.user.to_base58(), user.private_key);
+        }
+        println!("(User IDs and private keys. These keys are not secret! Don't use them for real posts.)");
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub(crate) enum CommentsCommand {
+    /// List comments waiting for review.
+    List(CommentsListCommand),
+
+    /// Print a comment as a Markdown quote, to publish in a reply post,
+    /// and remove it from the queue.
+    Approve(CommentsReviewCommand),
+
+    /// Remove a comment from the queue.
+    Reject(CommentsReviewCommand),
+}
+
+impl CommentsCommand {
+    fn main(&self) -> Result<(), Error> {
+        use CommentsCommand::*;
+        match self {
+            List(command) => command.main(),
+            Approve(command) => command.approve(),
+            Reject(command) => command.reject(),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct CommentsListCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+}
+
+impl CommentsListCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        conn.queued_comments(&mut |comment| {
+            println!(
+                "#{} on /u/{}/i/{}/ from {:?} at {}",
+                comment.id,
+                comment.user.to_base58(),
+                comment.signature.to_base58(),
+                comment.name,
+                comment.created.format_with_offset(0),
+            );
+            for line in comment.text.lines() {
+                println!("    {}", line);
+            }
+            println!();
+            Ok(true)
+        })?;
+
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct CommentsReviewCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    /// The comment's ID, from `feoblog comments list`.
+    id: i64,
+}
+
+impl CommentsReviewCommand {
+    fn find(&self, conn: &dyn backend::Backend) -> Result<backend::QueuedComment, Error> {
+        let mut found = None;
+        conn.queued_comments(&mut |comment| {
+            if comment.id == self.id {
+                found = Some(comment);
+                return Ok(false);
+            }
+            Ok(true)
+        })?;
+        found.ok_or_else(|| failure::format_err!("No comment #{} in the queue.", self.id))
+    }
+
+    fn approve(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+        let comment = self.find(conn.as_ref())?;
+        print!("{}", comment.quote());
+
+        conn.remove_queued_comment(comment.id)?;
+        Ok(())
+    }
+
+    fn reject(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        if !conn.remove_queued_comment(self.id)? {
+            bail!("No comment #{} in the queue.", self.id);
+        }
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub(crate) enum PeersCommand {
+    /// List peers.
+    List(PeersListCommand),
+
+    /// Push items that this server receives to a peer.
+    /// Takes effect for items received after this is run.
+    Add(PeersAddCommand),
+
+    /// Stop pushing items to a peer.
+    Remove(PeersRemoveCommand),
+
+    /// Show this server's key, for peers to trust. Creates it if needed.
+    Key(PeersKeyCommand),
+
+    /// List the keys of peers that may see followers-only items.
+    Trusted(PeersTrustedCommand),
+
+    /// Let a peer (ex: a mirror) see followers-only items, by its key.
+    /// (The peer gets it from `feoblog peers key`.)
+    Trust(PeersTrustCommand),
+
+    /// Stop trusting a peer's key.
+    Untrust(PeersUntrustCommand),
+}
+
+impl PeersCommand {
+    fn main(&self) -> Result<(), Error> {
+        use PeersCommand::*;
+        match self {
+            List(command) => command.main(),
+            Add(command) => command.main(),
+            Remove(command) => command.main(),
+            Key(command) => command.main(),
+            Trusted(command) => command.main(),
+            Trust(command) => command.main(),
+            Untrust(command) => command.main(),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct PeersListCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+}
+
+impl PeersListCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        conn.push_peers(&mut |peer| {
+            println!("{}", peer.url);
+            Ok(true)
+        })?;
+
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct PeersAddCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    /// The peer's base URL. ex: https://feo.example.com/
+    url: String,
+}
+
+impl PeersAddCommand {
+    fn main(&self) -> Result<(), Error> {
+        let url = self.url.trim_end_matches('/');
+        if !(url.starts_with("https://") || url.starts_with("http://")) {
+            bail!("Peer URL must start with https:// or http://");
+        }
+
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        conn.add_push_peer(&backend::PushPeer{
+            url: url.to_string(),
+            created: Timestamp::now(),
+        })?;
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct PeersRemoveCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    url: String,
+}
+
+impl PeersRemoveCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        if !conn.remove_push_peer(self.url.trim_end_matches('/'))? {
+            bail!("{} was not a peer.", self.url);
+        }
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct PeersKeyCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+}
+
+impl PeersKeyCommand {
+    fn main(&self) -> Result<(), Error> {
+        if sodiumoxide::init().is_err() {
+            bail!("Error initializing sodiumoxide");
+        }
+        let key = server::peer_auth::ServerKey::load_or_create(&self.shared_options.sqlite_file)?;
+        println!("{}", key.id().to_base58());
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct PeersTrustedCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+}
+
+impl PeersTrustedCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        conn.trusted_peers(&mut |peer| {
+            if peer.notes.is_empty() {
+                println!("{}", peer.key.to_base58());
+            } else {
+                println!("{} {}", peer.key.to_base58(), peer.notes);
+            }
+            Ok(true)
+        })?;
+
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct PeersTrustCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    /// The peer's server key.
+    key: UserID,
+
+    /// A note to remember the peer by. ex: "mirror.example.com"
+    #[structopt(long, default_value = "")]
+    notes: String,
+}
+
+impl PeersTrustCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        conn.add_trusted_peer(&backend::TrustedPeer{
+            key: self.key.clone(),
+            notes: self.notes.clone(),
+            created: Timestamp::now(),
+        })?;
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct PeersUntrustCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    key: UserID,
+}
+
+impl PeersUntrustCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        if !conn.remove_trusted_peer(&self.key)? {
+            bail!("{} was not trusted.", self.key.to_base58());
+        }
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub(crate) enum NostrCommand {
+    /// List users whose posts are republished, with their Nostr public keys.
+    List(NostrListCommand),
+
+    /// Give a user a Nostr key, and republish their new posts with it.
+    /// (Relays are set with `feoblog serve --nostr-relay`.)
+    Enable(NostrUserCommand),
+
+    /// Stop republishing a user's posts, and delete their Nostr key.
+    Disable(NostrUserCommand),
+
+    /// Copy a user's notes from a Nostr relay into posts on this server.
+    /// Asks for the user's FeoBlog private key, to sign them.
+    Import(NostrImportCommand),
+}
+
+impl NostrCommand {
+    fn main(&self) -> Result<(), Error> {
+        use NostrCommand::*;
+        match self {
+            List(command) => command.main(),
+            Enable(command) => command.enable(),
+            Disable(command) => command.disable(),
+            Import(command) => command.main(),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct NostrListCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+}
+
+impl NostrListCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+        conn.nostr_keys(&mut |key| {
+            println!("{} {}", key.user.to_base58(), server::nostr::public_key(&key)?);
+            Ok(true)
+        })?;
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct NostrUserCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    user_id: UserID,
+}
+
+impl NostrUserCommand {
+    fn enable(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        if conn.server_user(&self.user_id)?.is_none() {
+            bail!("{} is not a user on this server.", self.user_id.to_base58());
+        }
+        let key = match conn.nostr_key(&self.user_id)? {
+            Some(key) => key,
+            None => {
+                let key = server::nostr::new_key(&self.user_id)?;
+                conn.add_nostr_key(&key)?;
+                key
+            },
+        };
+        println!("Nostr public key: {}", server::nostr::public_key(&key)?);
+        Ok(())
+    }
+
+    fn disable(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        if !conn.remove_nostr_key(&self.user_id)? {
+            bail!("{} doesn't have a Nostr key.", self.user_id.to_base58());
+        }
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct NostrImportCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    /// The FeoBlog user to import notes as.
+    user_id: UserID,
+
+    /// The Nostr user whose notes to import, as 64 hex digits. (Not "npub…")
+    nostr_pubkey: String,
+
+    /// The relay to fetch notes from. ex: "wss://relay.example.com"
+    #[structopt(long)]
+    relay: String,
+
+    /// Import at most this many of the newest notes.
+    #[structopt(long, default_value="500")]
+    limit: usize,
+}
+
+impl NostrImportCommand {
+    fn main(&self) -> Result<(), Error> {
+        use sodiumoxide::crypto::sign;
+
+        if sodiumoxide::init().is_err() {
+            bail!("Error initializing sodiumoxide");
+        }
+        let factory = self.shared_options.factory()?;
+        let mut conn = factory.open()?;
+        if conn.server_user(&self.user_id)?.is_none() {
+            bail!("{} is not a user on this server.", self.user_id.to_base58());
+        }
+
+        eprint!("Private key for {}: ", self.user_id.to_base58());
+        let mut private_key = String::new();
+        io::stdin().read_line(&mut private_key)?;
+        let seed = bs58::decode(private_key.trim()).with_check(None).into_vec()
+            .map_err(|_| failure::format_err!("Invalid private key"))?;
+        let seed = sign::Seed::from_slice(&seed).ok_or_else(|| failure::format_err!("Invalid private key"))?;
+        let (public_key, secret_key) = sign::keypair_from_seed(&seed);
+        if public_key.as_ref() != self.user_id.bytes() {
+            bail!("That private key isn't for {}", self.user_id.to_base58());
+        }
+
+        let mut system = actix_web::rt::System::new("nostr import");
+        let Self{user_id, relay, nostr_pubkey, limit, ..} = self.clone();
+        let report = system.block_on(async move {
+            server::nostr::import_notes(
+                conn.as_mut(), &user_id, &secret_key, &relay, &nostr_pubkey, limit,
+            ).await
+        })?;
+        println!("Imported {} notes. Skipped {}.", report.saved, report.skipped);
+        for error in report.errors {
+            println!("  {}", error);
+        }
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub(crate) enum CrosspostCommand {
+    /// List the services a user's posts are cross-posted to.
+    List(CrosspostListCommand),
+
+    /// Cross-post a user's new posts to a service: a summary, with a link back here.
+    /// Failures are retried for several hours.
+    Add(CrosspostAddCommand),
+
+    /// Stop cross-posting to a service.
+    Remove(CrosspostRemoveCommand),
+}
+
+impl CrosspostCommand {
+    fn main(&self) -> Result<(), Error> {
+        use CrosspostCommand::*;
+        match self {
+            List(command) => command.main(),
+            Add(command) => command.main(),
+            Remove(command) => command.main(),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct CrosspostListCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    user_id: UserID,
+}
+
+impl CrosspostListCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+        conn.crosspost_targets(&self.user_id, &mut |target| {
+            // Tokens are secret, so aren't shown:
+            println!("{} {} {} {}", target.name, target.service.as_str(), target.url, target.account);
+            Ok(true)
+        })?;
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct CrosspostAddCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    user_id: UserID,
+
+    /// A name for this target. Adding another with the same name replaces it.
+    name: String,
+
+    /// "mastodon", "bluesky", or "webhook".
+    service: CrossPostService,
+
+    /// Mastodon: the server. (ex: "https://mastodon.social")
+    /// Bluesky: the PDS. (ex: "https://bsky.social")
+    /// Webhook: the URL to POST JSON to.
+    url: String,
+
+    /// Bluesky: the handle to post as. (ex: "alice.bsky.social")
+    #[structopt(long, default_value="")]
+    account: String,
+
+    /// Mastodon: an access token with the write:statuses scope.
+    /// Bluesky: an app password.
+    /// Webhook: (optional) sent as a bearer token.
+    #[structopt(long, default_value="")]
+    token: String,
+}
+
+impl CrosspostAddCommand {
+    fn main(&self) -> Result<(), Error> {
+        use CrossPostService::*;
+
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        if conn.server_user(&self.user_id)?.is_none() {
+            bail!("{} is not a user on this server.", self.user_id.to_base58());
+        }
+        if !self.url.starts_with("https://") && !self.url.starts_with("http://") {
+            bail!("The URL must start with https:// or http://");
+        }
+        match self.service {
+            Mastodon if self.token.is_empty() => bail!("Mastodon requires --token"),
+            Bluesky if self.account.is_empty() || self.token.is_empty() => bail!("Bluesky requires --account and --token"),
+            _ => {},
+        }
+
+        conn.add_crosspost_target(&CrossPostTarget{
+            user: self.user_id.clone(),
+            name: self.name.clone(),
+            service: self.service,
+            url: self.url.clone(),
+            account: self.account.clone(),
+            token: self.token.clone(),
+            created: Timestamp::now(),
+        })?;
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct CrosspostRemoveCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    user_id: UserID,
+    name: String,
+}
+
+impl CrosspostRemoveCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        if !conn.remove_crosspost_target(&self.user_id, &self.name)? {
+            bail!("No such target: {}", self.name);
+        }
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub(crate) enum SyncCommand {
+    /// Compare the items on this server with those on a peer.
+    Verify(SyncVerifyCommand),
+
+    /// Check the signature of a report from `feoblog sync verify --sign`,
+    /// and print the key that signed it.
+    CheckReport(SyncCheckReportCommand),
+
+    /// Fetch missing items for known users from the servers in their profiles.
+    Pull(SyncPullCommand),
+}
+
+impl SyncCommand {
+    fn main(&self) -> Result<(), Error> {
+        use SyncCommand::*;
+        match self {
+            Verify(command) => command.main(),
+            CheckReport(command) => command.main(),
+            Pull(command) => command.main(),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct SyncPullCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    /// Pull only this user's items. May be repeated.
+    /// Defaults to all users hosted on this server, and the users they follow.
+    #[structopt(long="user")]
+    users: Vec<UserID>,
+
+    /// Page through every item on each server, instead of stopping once
+    /// we've caught up with items we already have.
+    #[structopt(long)]
+    full: bool,
+}
+
+impl SyncPullCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+
+        let mut users = self.users.clone();
+        if users.is_empty() {
+            users = sync::known_users(factory.open()?.as_ref())?;
+        }
+
+        // If we've made a server key, peers that trust it send followers-only items too:
+        let key = server::peer_auth::ServerKey::load(&self.shared_options.sqlite_file)?;
+
+        let mut system = actix_web::rt::System::new("sync pull");
+        for user in users {
+            let pull = {
+                let (factory, user, full, key) = (factory.clone(), user.clone(), self.full, key.clone());
+                async move { sync::pull_user(&factory, &user, full, key.as_ref()).await }
+            };
+            let report = system.block_on(pull)
+                .with_context(|_| format!("Error pulling items for {}", user.to_base58()))?;
+            println!("{}: saved {} items", report.user.to_base58(), report.saved);
+            for error in report.errors {
+                println!("  {}", error);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct SyncVerifyCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    /// The base URL of the peer to compare with. ex: https://feo.example.com/
+    #[structopt(long)]
+    against: String,
+
+    /// Compare only this user's items. May be repeated.
+    /// Defaults to all users explicitly hosted on this server.
+    #[structopt(long="user")]
+    users: Vec<UserID>,
+
+    /// Sign the report with this server's key (See: `feoblog peers key`),
+    /// so that others can check it with `feoblog sync check-report`.
+    #[structopt(long)]
+    sign: bool,
+}
+
+impl SyncVerifyCommand {
+    fn main(&self) -> Result<(), Error> {
+        let key = match self.sign {
+            false => None,
+            true => Some(
+                server::peer_auth::ServerKey::load(&self.shared_options.sqlite_file)?
+                    .ok_or_else(|| failure::format_err!("This server has no key yet. Make one with `feoblog peers key`."))?
+            ),
+        };
+
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        let mut users = self.users.clone();
+        if users.is_empty() {
+            conn.server_users(&mut |server_user| {
+                users.push(server_user.user);
+                Ok(true)
+            })?;
+        }
+
+        let peer = sync::Peer::new(&self.against);
+        let mut report = sync::VerifyReport {
+            peer: peer.base_url().to_string(),
+            created: Timestamp::now(),
+            users: vec![],
+        };
+
+        let mut system = actix_web::rt::System::new("sync verify");
+        for user in users {
+            // If the summaries match, we can skip listing all items:
+            let local_summary = conn.user_summary(&user)?;
+            let fetch = {
+                let (peer, user) = (peer.clone(), user.clone());
+                async move { peer.user_summary(&user).await }
+            };
+            let remote_summary = system.block_on(fetch)
+                .with_context(|_| format!("Error fetching summary for {}", user.to_base58()))?;
+            if remote_summary.as_ref() == Some(&local_summary) {
+                report.users.push(sync::UserVerifyReport::matching(user, &local_summary));
+                continue;
+            }
+
+            let local = sync::local_signatures(conn.as_ref(), &user)?;
+            let fetch = {
+                let (peer, user) = (peer.clone(), user.clone());
+                async move { peer.user_signatures(&user).await }
+            };
+            let remote = system.block_on(fetch)
+                .with_context(|_| format!("Error fetching items for {}", user.to_base58()))?;
+            report.users.push(sync::UserVerifyReport::new(user, &local, &remote)?);
+        }
+
+        let mut text = report.to_string();
+        if let Some(key) = &key {
+            text = sync::sign_report(&text, key);
+        }
+        print!("{}", text);
+
+        conn.save_sync_report(&backend::SyncReport{
+            peer: report.peer.clone(),
+            created: report.created,
+            complete: report.is_complete(),
+            report: text,
+        })?;
+
+        if !report.is_complete() {
+            bail!("{} does not have the same items as this server.", report.peer);
+        }
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct SyncCheckReportCommand {
+    /// A file containing the report.
+    file: PathBuf,
+}
+
+impl SyncCheckReportCommand {
+    fn main(&self) -> Result<(), Error> {
+        let report = std::fs::read_to_string(&self.file)
+            .with_context(|_| format!("Error reading {}", self.file.display()))?;
+        let key = sync::report_signer(&report)?;
+        println!("Valid signature by {}", key.to_base58());
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub(crate) enum ThreadCommand {
+    /// Write an item and its thread (the comments that reply to it) to a bundle file.
+    Export(ThreadExportCommand),
+
+    /// Save the items from a bundle file.
+    Import(ThreadImportCommand),
+}
+
+impl ThreadCommand {
+    fn main(&self) -> Result<(), Error> {
+        use ThreadCommand::*;
+        match self {
+            Export(command) => command.main(),
+            Import(command) => command.main(),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct ThreadExportCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    user_id: UserID,
+    signature: Signature,
+
+    /// Where to write the bundle.
+    #[structopt(long)]
+    out: String,
+}
+
+impl ThreadExportCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        let bundle = match bundle::thread_bundle(conn.as_ref(), &self.user_id, &self.signature)? {
+            Some(bundle) => bundle,
+            None => bail!("No such item"),
+        };
+        std::fs::write(&self.out, protobuf::Message::write_to_bytes(&bundle)?)
+            .with_context(|_| format!("Error writing {}", self.out))?;
+
+        println!("Exported {} items to {}", bundle.get_items().len(), self.out);
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct ThreadImportCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    /// A bundle file, as written by `thread export`.
+    file: String,
+}
+
+impl ThreadImportCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let mut conn = factory.open()?;
+
+        let bytes = std::fs::read(&self.file)
+            .with_context(|_| format!("Error reading {}", self.file))?;
+        let mut bundle = protos::ItemBundle::new();
+        protobuf::Message::merge_from_bytes(&mut bundle, &bytes)?;
+
+        let imported = bundle::import_bundle(conn.as_mut(), &bundle)?;
+        println!("Imported {} new items.", imported);
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct ExportCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    #[structopt(long="user")]
+    user_id: UserID,
+
+    /// Where to write the archive. (ex: "archive.tar")
+    #[structopt(long)]
+    out: PathBuf,
+}
+
+impl ExportCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+        if !conn.user_known(&self.user_id)? {
+            bail!("This server has no items from {}", self.user_id.to_base58());
+        }
+
+        let file = std::fs::File::create(&self.out)
+            .with_context(|_| format!("Error creating {}", self.out.display()))?;
+        let report = export::export_user(conn.as_ref(), &self.user_id, io::BufWriter::new(file))?;
+        println!(
+            "Exported {} items and {} files ({} bytes) to {}",
+            report.items, report.files, report.bytes, self.out.display(),
+        );
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct ImportCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    /// The largest file attachment (in bytes) to import. (See: `serve --max-attachment-bytes`)
+    #[structopt(long, default_value="10485760")]
+    max_attachment_bytes: u64,
+
+    /// A tar file, from `feoblog export`.
+    file: PathBuf,
+}
+
+impl ImportCommand {
+    fn main(&self) -> Result<(), Error> {
+        if sodiumoxide::init().is_err() {
+            bail!("Error initializing sodiumoxide");
+        }
+        let factory = self.shared_options.factory()?;
+        let mut conn = factory.open()?;
+
+        let file = std::fs::File::open(&self.file)
+            .with_context(|_| format!("Error reading {}", self.file.display()))?;
+        let report = import::import_archive(conn.as_mut(), io::BufReader::new(file), self.max_attachment_bytes)?;
+        println!(
+            "Imported {} items and {} files. {} were already here.",
+            report.items, report.files, report.existing,
+        );
+        if !report.refused.is_empty() {
+            println!("Skipped {}:", report.refused.len());
+            for reason in &report.refused {
+                println!("  {}", reason);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub(crate) enum FollowsCommand {
+    /// Print the follows from a user's profile as OPML.
+    Export(FollowsExportCommand),
+
+    /// Create a new (unsigned) profile Item that adds the follows from an OPML
+    /// or JSON file.
+    Import(FollowsImportCommand),
+}
+
+impl FollowsCommand {
+    fn main(&self) -> Result<(), Error> {
+        use FollowsCommand::*;
+        match self {
+            Export(command) => command.main(),
+            Import(command) => command.main(),
+        }
+    }
+}
+
+/// The user's latest profile Item, if we have one.
+fn latest_profile(conn: &dyn backend::Backend, user: &UserID) -> Result<Option<protos::Item>, Error> {
+    let row = match conn.user_profile(user)? {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    let mut item = protos::Item::new();
+    protobuf::Message::merge_from_bytes(&mut item, &row.item_bytes)?;
+    Ok(Some(item))
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct FollowsExportCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    user_id: UserID,
+
+    /// Link each follow to their page on this server. ex: https://feo.example.com/
+    #[structopt(long)]
+    base_url: Option<String>,
+
+    /// Export JSON instead of OPML. (See: `/u/{userID}/follows/json`)
+    #[structopt(long)]
+    json: bool,
+}
+
+impl FollowsExportCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        let item = match latest_profile(conn.as_ref(), &self.user_id)? {
+            Some(item) => item,
+            None => bail!("No profile for {}", self.user_id.to_base58()),
+        };
+        if self.json {
+            println!("{}", follows::export_json(item.get_profile(), self.base_url.as_deref())?);
+        } else {
+            print!("{}", follows::export_opml(item.get_profile(), self.base_url.as_deref())?);
+        }
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct FollowsImportCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    user_id: UserID,
+
+    /// The OPML or JSON file to import. (JSON as from `feoblog follows export --json`)
+    file: String,
+
+    /// Where to write the new profile Item. It must be signed by the user
+    /// before it can be uploaded.
+    #[structopt(long)]
+    out: String,
+}
+
+impl FollowsImportCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        let text = std::fs::read_to_string(&self.file)
+            .with_context(|_| format!("Error reading {}", self.file))?;
+        let follows = if text.trim_start().starts_with('{') {
+            follows::import_json(&text).with_context(|_| format!("Error reading JSON from {}", self.file))?
+        } else {
+            follows::import_opml(&text)
+        };
+        let found = follows.len();
+
+        let existing = latest_profile(conn.as_ref(), &self.user_id)?;
+        let item = follows::profile_with_follows(existing, follows);
+        std::fs::write(&self.out, protobuf::Message::write_to_bytes(&item)?)
+            .with_context(|_| format!("Error writing {}", self.out))?;
+
+        println!(
+            "Found {} follows. Wrote a profile with {} follows to {}",
+            found,
+            item.get_profile().get_follows().len(),
+            self.out,
+        );
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub(crate) enum FeedsCommand {
+    /// List a user's saved feeds.
+    List(FeedsListCommand),
+
+    /// Save a feed, replacing any existing feed with the same name.
+    Save(FeedsSaveCommand),
+
+    /// Delete a saved feed.
+    Delete(FeedsDeleteCommand),
+}
+
+impl FeedsCommand {
+    fn main(&self) -> Result<(), Error> {
+        use FeedsCommand::*;
+        match self {
+            List(command) => command.main(),
+            Save(command) => command.main(),
+            Delete(command) => command.main(),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct FeedsListCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    user_id: UserID,
+}
+
+impl FeedsListCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+
+        conn.saved_feeds(&self.user_id, &mut |feed| {
+            let types: Vec<String> = feed.item_types.iter().map(|t| format!("{:?}", t)).collect();
+            let tags: Vec<String> = feed.tags.iter().map(|tag| format!("#{}", tag)).collect();
+            println!("{} ({} authors) {} {}", feed.name, feed.authors.len(), types.join(","), tags.join(" "));
+            Ok(true)
+        })?;
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct FeedsSaveCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    user_id: UserID,
+
+    /// Used in the feed's URL: /u/{userID}/feeds/{name}/
+    /// May contain letters, numbers, "-" and "_".
+    name: String,
+
+    /// Include items from this user. May be repeated.
+    #[structopt(long="author", required=true)]
+    authors: Vec<UserID>,
+
+    /// Include items of this type. (ex: "post", "profile")
+    /// May be repeated. Defaults to posts and polls.
+    #[structopt(long="type", parse(try_from_str = parse_item_type))]
+    item_types: Vec<ItemType>,
+
+    /// Only include posts with this hashtag. (ex: "rust" or "#rust")
+    /// May be repeated, to include posts with any of them.
+    #[structopt(long="tag", parse(try_from_str = parse_tag))]
+    tags: Vec<String>,
+}
+
+impl FeedsSaveCommand {
+    fn main(&self) -> Result<(), Error> {
+        if !backend::SavedFeed::is_valid_name(&self.name) {
+            bail!("Invalid feed name: {:?}", self.name);
+        }
+
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+        conn.save_feed(&backend::SavedFeed {
+            user: self.user_id.clone(),
+            name: self.name.clone(),
+            authors: self.authors.clone(),
+            item_types: self.item_types.clone(),
+            tags: self.tags.clone(),
+        })?;
+
+        println!("Saved /u/{}/feeds/{}/", self.user_id.to_base58(), self.name);
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct FeedsDeleteCommand {
+    #[structopt(flatten)]
+    shared_options: SharedOptions,
+
+    user_id: UserID,
+    name: String,
+}
+
+impl FeedsDeleteCommand {
+    fn main(&self) -> Result<(), Error> {
+        let factory = self.shared_options.factory()?;
+        let conn = factory.open()?;
+        if !conn.delete_saved_feed(&self.user_id, &self.name)? {
+            bail!("No such feed: {}", self.name);
+        }
+        Ok(())
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+struct ConformanceCommand {
+    /// The base URL of the server to check. ex: http://localhost:8080/
+    #[structopt(long)]
+    server: String,
+}
+
+impl ConformanceCommand {
+    fn main(&self) -> Result<(), Error> {
+        let mut system = actix_web::rt::System::new("conformance");
+        let server = self.server.clone();
+        let report = system.block_on(async move { conformance::check_server(&server).await })?;
+        print!("{}", report);
+
+        if !report.passed() {
+            bail!("{} did not pass all checks.", report.server);
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+use pulldown_cmark::CowStr;
+
 pub(crate) trait ToHTML {
     /// Convert this markdown to a safe subset of HTML.
     fn md_to_html(&self) -> String;
@@ -5,19 +7,124 @@ pub(crate) trait ToHTML {
 
 impl ToHTML for str {
     fn md_to_html(&self) -> String {
-        let parser = pulldown_cmark::Parser::new(self);
-        use pulldown_cmark::Event::*; 
-
-        // TODO: Fix unsafe links like javascript:. see commonmark JS library.
-        let parser = parser.map(|event| match event {
-            Html(value) => Code(value),
-            InlineHtml(value) => Text(value),
-            x => x,
-        });
-
-        let mut html = String::new();
-        pulldown_cmark::html::push_html(&mut html, parser);
-        html
+        to_html(self, &mut |_| {})
+    }
+}
+
+/// Something that `md_to_html` kept out of its HTML.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Removal {
+    /// Raw HTML that could run scripts or change the page. (We show it as text.)
+    Html(String),
+    /// A link or image URL with a scheme that could run scripts. (ex: "javascript:")
+    UnsafeUrl(String),
+}
+
+/// What `md_to_html` would remove from `markdown`. (ex: to warn operators of
+/// injection attempts) Harmless HTML, like `<br>`, isn't listed.
+pub(crate) fn removals(markdown: &str) -> Vec<Removal> {
+    let mut removals = vec![];
+    to_html(markdown, &mut |removal| removals.push(removal));
+    removals
+}
+
+fn to_html(markdown: &str, removed: &mut dyn FnMut(Removal)) -> String {
+    use pulldown_cmark::Event::*;
+    use pulldown_cmark::Tag::{Image, Link};
+
+    let parser = pulldown_cmark::Parser::new(markdown).map(|event| match event {
+        Html(value) => {
+            if is_active_html(&value) {
+                removed(Removal::Html(value.to_string()));
+            }
+            Code(value)
+        },
+        InlineHtml(value) => {
+            if is_active_html(&value) {
+                removed(Removal::Html(value.to_string()));
+            }
+            Text(value)
+        },
+        // Start and End both carry the URL. Only report it once:
+        Start(Link(kind, url, title)) => Start(Link(kind, safe_url(url, false, Some(&mut *removed)), title)),
+        End(Link(kind, url, title)) => End(Link(kind, safe_url(url, false, None), title)),
+        Start(Image(kind, url, title)) => Start(Image(kind, safe_url(url, true, Some(&mut *removed)), title)),
+        End(Image(kind, url, title)) => End(Image(kind, safe_url(url, true, None), title)),
+        x => x,
+    });
+
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, parser);
+    html
+}
+
+/// Browsers run these when they're followed or loaded.
+const UNSAFE_SCHEMES: &[&str] = &["javascript", "vbscript", "data", "file"];
+
+/// `url`, or an empty one if it's unsafe.
+fn safe_url<'a>(url: CowStr<'a>, is_image: bool, removed: Option<&mut dyn FnMut(Removal)>) -> CowStr<'a> {
+    // Inline images are fine. Browsers won't run scripts in them:
+    if is_image && url.trim_start().to_lowercase().starts_with("data:image/") {
+        return url;
+    }
+    if !is_unsafe_url(&url) {
+        return url;
+    }
+    if let Some(removed) = removed {
+        removed(Removal::UnsafeUrl(url.to_string()));
+    }
+    "".into()
+}
+
+fn is_unsafe_url(url: &str) -> bool {
+    // Browsers ignore whitespace and control characters in schemes. (ex: "java\tscript:")
+    let url: String = url.chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .take(32)
+        .collect::<String>()
+        .to_lowercase();
+    let scheme = match url.find(':') {
+        Some(end) => &url[..end],
+        None => return false,
+    };
+    UNSAFE_SCHEMES.contains(&scheme)
+}
+
+/// Tags and attributes that an attacker would use. We escape all HTML, but
+/// only these are worth telling operators about.
+fn is_active_html(html: &str) -> bool {
+    const ACTIVE: &[&str] = &[
+        "<script", "<iframe", "<object", "<embed", "<style", "<form", "<meta",
+        "<link", "<base", "<svg", "<math", "javascript:", "vbscript:",
+    ];
+    let html = html.to_lowercase();
+    if ACTIVE.iter().any(|active| html.contains(active)) {
+        return true;
     }
+    // Event handlers. (ex: `<img onerror=...>`)
+    html.match_indices(" on").any(|(i, _)| {
+        let rest = &html[i + 3..];
+        let name_len = rest.chars().take_while(|c| c.is_ascii_alphabetic()).count();
+        name_len > 0 && rest[name_len..].trim_start().starts_with('=')
+    })
 }
 
+
+/// The absolute http(s) links (and images) in some markdown, each once, in order.
+pub(crate) fn links(markdown: &str) -> Vec<String> {
+    use pulldown_cmark::{Event, Tag};
+
+    let mut links: Vec<String> = vec![];
+    for event in pulldown_cmark::Parser::new(markdown) {
+        let url = match event {
+            Event::Start(Tag::Link(_, url, _)) => url,
+            Event::Start(Tag::Image(_, url, _)) => url,
+            _ => continue,
+        };
+        let is_http = url.starts_with("http://") || url.starts_with("https://");
+        if is_http && !links.iter().any(|link| link.as_str() == &*url) {
+            links.push(url.to_string());
+        }
+    }
+    links
+}
@@ -0,0 +1,84 @@
+//! OPDS catalogs, so that e-reader apps can browse a user's articles and
+//! download them as EPUBs. (See: `epub.rs`)
+//!
+//! An OPDS catalog is an Atom feed with acquisition links.
+//! See: <https://specs.opds.io/opds-1.2>
+
+use crate::backend::Timestamp;
+use crate::follows::escape;
+
+pub(crate) const CATALOG_TYPE: &str = "application/atom+xml;profile=opds-catalog;kind=acquisition";
+
+/// An OPDS acquisition feed.
+pub(crate) struct Catalog {
+    /// A BCP 47 language tag for the feed's text. (ex: "en")
+    pub lang: String,
+
+    /// An absolute URL that uniquely identifies the catalog. Also its `self` link.
+    pub id: String,
+    pub title: String,
+    pub updated: Timestamp,
+
+    /// The HTML page with the same items.
+    pub html_url: String,
+
+    /// The next page of the catalog, if there is one.
+    pub next_url: Option<String>,
+    pub entries: Vec<Entry>,
+}
+
+pub(crate) struct Entry {
+    /// An absolute URL that uniquely identifies the entry. Also its `alternate` link.
+    pub id: String,
+    pub title: String,
+    pub author: String,
+    pub updated: Timestamp,
+
+    /// HTML content for the entry. Must already be sanitized.
+    pub content_html: String,
+
+    /// An SPDX license identifier, or "" if the author didn't give one.
+    pub license: String,
+
+    /// Where to download the entry as an EPUB.
+    pub epub_url: String,
+}
+
+impl Catalog {
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        xml.push_str(&format!(
+            "<feed xmlns=\"http://www.w3.org/2005/Atom\" xmlns:opds=\"http://opds-spec.org/2010/catalog\" xml:lang=\"{}\">\n",
+            escape(&self.lang),
+        ));
+        xml.push_str(&format!("  <id>{}</id>\n", escape(&self.id)));
+        xml.push_str(&format!("  <title>{}</title>\n", escape(&self.title)));
+        xml.push_str(&format!("  <updated>{}</updated>\n", self.updated.format_rfc3339()));
+        xml.push_str(&format!("  <link rel=\"self\" href=\"{}\" type=\"{}\"/>\n", escape(&self.id), CATALOG_TYPE));
+        xml.push_str(&format!("  <link rel=\"start\" href=\"{}\" type=\"{}\"/>\n", escape(&self.id), CATALOG_TYPE));
+        xml.push_str(&format!("  <link rel=\"alternate\" href=\"{}\" type=\"text/html\"/>\n", escape(&self.html_url)));
+        if let Some(next_url) = &self.next_url {
+            xml.push_str(&format!("  <link rel=\"next\" href=\"{}\" type=\"{}\"/>\n", escape(next_url), CATALOG_TYPE));
+        }
+        for entry in &self.entries {
+            xml.push_str("  <entry>\n");
+            xml.push_str(&format!("    <id>{}</id>\n", escape(&entry.id)));
+            xml.push_str(&format!("    <title>{}</title>\n", escape(&entry.title)));
+            xml.push_str(&format!("    <author><name>{}</name></author>\n", escape(&entry.author)));
+            xml.push_str(&format!("    <updated>{}</updated>\n", entry.updated.format_rfc3339()));
+            xml.push_str(&format!("    <link rel=\"alternate\" href=\"{}\" type=\"text/html\"/>\n", escape(&entry.id)));
+            xml.push_str(&format!(
+                "    <link rel=\"http://opds-spec.org/acquisition/open-access\" href=\"{}\" type=\"application/epub+zip\"/>\n",
+                escape(&entry.epub_url),
+            ));
+            xml.push_str(&format!("    <content type=\"html\">{}</content>\n", escape(&entry.content_html)));
+            if !entry.license.is_empty() {
+                xml.push_str(&format!("    <rights>{}</rights>\n", escape(&entry.license)));
+            }
+            xml.push_str("  </entry>\n");
+        }
+        xml.push_str("</feed>\n");
+        xml
+    }
+}
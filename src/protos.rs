@@ -33,6 +33,12 @@ impl ProtoValid for Item {
             );
         }
 
+        if self.expires_ms_utc != 0 && self.expires_ms_utc <= self.timestamp_ms_utc {
+            return Some(
+                "Expiration must be after the timestamp".into()
+            );
+        }
+
         // TODO: Validations for specific item types.
         if self.has_profile() {
             let err = self.get_profile().get_error();
@@ -41,12 +47,70 @@ impl ProtoValid for Item {
             }
         }
 
+        if self.has_post() {
+            let err = license_error(self.get_post().get_license());
+            if err.is_some() {
+                return err;
+            }
+        }
+
+        if self.has_post() && self.get_post().has_location() {
+            let err = self.get_post().get_location().get_error();
+            if err.is_some() {
+                return err;
+            }
+        }
+
+        if self.has_attachments() {
+            let err = self.get_attachments().get_error();
+            if err.is_some() {
+                return err;
+            }
+        }
+
+        if self.has_poll() {
+            let err = self.get_poll().get_error();
+            if err.is_some() {
+                return err;
+            }
+        }
+
+        if self.has_vote() {
+            let err = self.get_vote().get_poll().get_error();
+            if err.is_some() {
+                return err;
+            }
+        }
+
+        if self.has_comment() {
+            let err = self.get_comment().get_error();
+            if err.is_some() {
+                return err;
+            }
+        }
+
+        if self.has_delete() && self.get_delete().get_signature().get_bytes().len() != 64 {
+            return Some("Delete.signature must be 64 bytes".into());
+        }
+
+        if self.has_reaction() {
+            let err = self.get_reaction().get_error();
+            if err.is_some() {
+                return err;
+            }
+        }
+
         None
     }
 }
 
 impl ProtoValid for Profile {
     fn get_error(&self) -> Option<Cow<'static, str>> {
+        use crate::confusables::looks_like_user_id;
+
+        if looks_like_user_id(self.get_display_name()) {
+            return Some("Profile.display_name can't look like a user ID".into())
+        }
 
         for follow in self.get_follows() {
             if follow.get_user().get_bytes().len() != 32 {
@@ -54,6 +118,130 @@ impl ProtoValid for Profile {
             }
         }
 
+        license_error(self.get_license())
+    }
+}
+
+/// Check an (optional) SPDX license identifier. We don't keep a list of
+/// them, but they're all short and plain.
+fn license_error(license: &str) -> Option<Cow<'static, str>> {
+    if license.len() > 64 {
+        return Some("License must be <= 64 bytes".into())
+    }
+    let plain = |c: char| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '+';
+    if !license.chars().all(plain) {
+        return Some(format!("Invalid license identifier: {:?}", license).into())
+    }
+
+    None
+}
+
+impl ProtoValid for Poll {
+    fn get_error(&self) -> Option<Cow<'static, str>> {
+        if self.get_question().trim().is_empty() {
+            return Some("Poll.question is required".into())
+        }
+
+        let options = self.get_options();
+        if options.len() < 2 || options.len() > 20 {
+            return Some("Poll must have between 2 and 20 options".into())
+        }
+        if options.iter().any(|o| o.trim().is_empty()) {
+            return Some("Poll options must not be empty".into())
+        }
+
+        if self.close_ms_utc == 0 {
+            return Some("Poll.close_ms_utc is required".into())
+        }
+
+        None
+    }
+}
+
+impl ProtoValid for Attachments {
+    fn get_error(&self) -> Option<Cow<'static, str>> {
+        let mut names = std::collections::HashSet::new();
+        for file in self.get_file() {
+            if file.get_hash().len() != 64 {
+                return Some("File.hash must be 64 bytes".into())
+            }
+            if file.size == 0 {
+                return Some("File.size is required".into())
+            }
+
+            let name = file.get_name();
+            if name.is_empty() || name.len() > 255 {
+                return Some("File.name must be 1-255 bytes".into())
+            }
+            if name.starts_with('.') || name.contains('/') || name.contains('\\') {
+                return Some(format!("Invalid file name: {:?}", name).into())
+            }
+            if !names.insert(name) {
+                return Some(format!("Duplicate file name: {:?}", name).into())
+            }
+        }
+
+        None
+    }
+}
+
+impl ProtoValid for Location {
+    fn get_error(&self) -> Option<Cow<'static, str>> {
+        let lat = self.latitude;
+        let lon = self.longitude;
+        if !lat.is_finite() || !lon.is_finite() {
+            return Some("Location coordinates must be numbers".into())
+        }
+        if lat < -90.0 || lat > 90.0 || lon < -180.0 || lon > 180.0 {
+            return Some("Location coordinates are out of range".into())
+        }
+        if self.get_place_name().len() > 256 {
+            return Some("Location.place_name must be <= 256 bytes".into())
+        }
+
+        None
+    }
+}
+
+impl ProtoValid for Comment {
+    fn get_error(&self) -> Option<Cow<'static, str>> {
+        if !self.has_reply_to() {
+            return Some("Comment.reply_to is required".into())
+        }
+        if self.get_text().trim().is_empty() {
+            return Some("Comment.text is required".into())
+        }
+
+        self.get_reply_to().get_error()
+    }
+}
+
+impl ProtoValid for Reaction {
+    fn get_error(&self) -> Option<Cow<'static, str>> {
+        if !self.has_item() {
+            return Some("Reaction.item is required".into())
+        }
+        let emoji = self.get_emoji();
+        if emoji.len() > 32 {
+            return Some("Reaction.emoji must be <= 32 bytes".into())
+        }
+        if emoji.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Some(format!("Invalid reaction: {:?}", emoji).into())
+        }
+
+        self.get_item().get_error()
+    }
+}
+
+impl ProtoValid for ItemRef {
+    fn get_error(&self) -> Option<Cow<'static, str>> {
+        if self.get_user_id().get_bytes().len() != 32 {
+            return Some("ItemRef.user_id must be 32 bytes".into())
+        }
+        if self.get_signature().get_bytes().len() != 64 {
+            return Some("ItemRef.signature must be 64 bytes".into())
+        }
+
         None
     }
 }
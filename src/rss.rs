@@ -0,0 +1,49 @@
+//! RSS 2.0 feeds, for feed readers that don't speak Atom.
+//!
+//! See: <https://www.rssboard.org/rss-specification>
+
+use crate::atom::Entry;
+use crate::follows::escape;
+
+/// An RSS `<channel>`. Items are the same as Atom entries.
+pub(crate) struct Channel {
+    pub title: String,
+
+    /// An absolute URL to the HTML page this channel mirrors.
+    pub link: String,
+    pub description: String,
+
+    /// A BCP 47 language tag. (ex: "en")
+    pub lang: String,
+    pub items: Vec<Entry>,
+}
+
+impl Channel {
+    pub fn to_xml(&self) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        // RSS's <author> must be an e-mail address. Dublin Core lets us use names:
+        xml.push_str("<rss version=\"2.0\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n");
+        xml.push_str("<channel>\n");
+        xml.push_str(&format!("  <title>{}</title>\n", escape(&self.title)));
+        xml.push_str(&format!("  <link>{}</link>\n", escape(&self.link)));
+        xml.push_str(&format!("  <description>{}</description>\n", escape(&self.description)));
+        xml.push_str(&format!("  <language>{}</language>\n", escape(&self.lang)));
+        for item in &self.items {
+            xml.push_str("  <item>\n");
+            xml.push_str(&format!("    <title>{}</title>\n", escape(&item.title)));
+            xml.push_str(&format!("    <link>{}</link>\n", escape(&item.id)));
+            xml.push_str(&format!("    <guid isPermaLink=\"true\">{}</guid>\n", escape(&item.id)));
+            xml.push_str(&format!("    <dc:creator>{}</dc:creator>\n", escape(&item.author)));
+            xml.push_str(&format!("    <pubDate>{}</pubDate>\n", item.updated.format_rfc2822()));
+            xml.push_str(&format!("    <description>{}</description>\n", escape(&item.content_html)));
+            if !item.license.is_empty() {
+                xml.push_str(&format!("    <dc:rights>{}</dc:rights>\n", escape(&item.license)));
+            }
+            xml.push_str("  </item>\n");
+        }
+        xml.push_str("</channel>\n");
+        xml.push_str("</rss>\n");
+        xml
+    }
+}
@@ -0,0 +1,254 @@
+//! Generates realistic data for development and benchmarks. (`feoblog devel seed`)
+//!
+//! Makes users, with profiles that follow each other, then a year of signed
+//! posts, comments, polls and votes between them, of varied sizes. Everything
+//! is checked (see: `bundle::check_item`) and saved like items from any other
+//! server.
+//!
+//! Keys come from a seeded PRNG, so that the same `--seed` makes the same
+//! users and items. Don't use them for anything real!
+
+use failure::Error;
+use protobuf::Message as _;
+use sodiumoxide::crypto::sign;
+
+use crate::backend::{Backend, ServerUser, Signature, Timestamp, UserID};
+use crate::protos::Item;
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// Items are spread over this many days, up to now.
+const SPREAD_DAYS: i64 = 365;
+
+/// How many others each user follows, at most.
+const MAX_FOLLOWS: usize = 10;
+
+const WORDS: &[&str] = &[
+    "the", "a", "blog", "post", "server", "feed", "garden", "coffee", "river", "quietly",
+    "signed", "protocol", "weekend", "bicycle", "morning", "thoughts", "about", "and", "with", "from",
+    "distributed", "notes", "today", "I", "we", "built", "found", "small", "rust", "sqlite",
+    "friends", "followed", "markdown", "photo", "walk", "rain", "library", "music", "never", "always",
+];
+
+const NAMES: &[&str] = &[
+    "Ada", "Basil", "Cora", "Dmitri", "Esme", "Farid", "Greta", "Hiro", "Ines", "Jonah",
+    "Kenji", "Lena", "Marisol", "Nils", "Odile", "Priya", "Quinn", "Rosa", "Sami", "Tove",
+];
+
+pub(crate) struct SeedReport {
+    pub users: Vec<SeededUser>,
+    pub items: usize,
+}
+
+pub(crate) struct SeededUser {
+    pub user: UserID,
+    /// In the format the web client logs in with.
+    pub private_key: String,
+}
+
+/// Add `users` server users, with `posts` items each (plus a profile).
+pub(crate) fn seed(backend: &mut dyn Backend, users: usize, posts: usize, seed: u64) -> Result<SeedReport, Error> {
+    let mut rng = Rng::new(seed);
+    let now = Timestamp::now().unix_utc_ms;
+    let start = now - SPREAD_DAYS * DAY_MS;
+
+    let mut authors = vec![];
+    let mut report = SeedReport { users: vec![], items: 0 };
+    for i in 0..users {
+        let seed = sign::Seed(rng.bytes32());
+        let (public_key, secret_key) = sign::keypair_from_seed(&seed);
+        let user = UserID::from_vec(public_key.as_ref().to_vec())?;
+        backend.add_server_user(&ServerUser {
+            user: user.clone(),
+            notes: format!("feoblog devel seed #{}", i + 1),
+            on_homepage: true,
+        })?;
+        report.users.push(SeededUser {
+            user: user.clone(),
+            private_key: bs58::encode(seed.as_ref()).with_check().into_string(),
+        });
+        authors.push(Author { user, secret_key });
+    }
+
+    let mut saver = Saver { backend, authors: &authors, items: 0 };
+
+    for (i, author) in authors.iter().enumerate() {
+        let mut item = Item::new();
+        item.timestamp_ms_utc = start;
+        let profile = item.mut_profile();
+        profile.display_name = format!("{} {}", NAMES[i % NAMES.len()], i / NAMES.len() + 1);
+        let paragraphs = 1 + rng.below(3) as usize;
+        profile.about = rng.paragraphs(paragraphs);
+        let follows = rng.below(MAX_FOLLOWS.min(users.saturating_sub(1)) as u64 + 1);
+        for _ in 0..follows {
+            let followed = &authors[rng.below(users as u64) as usize];
+            let already = profile.get_follows().iter().any(|f| f.get_user().get_bytes() == followed.user.bytes());
+            if followed.user.bytes() != author.user.bytes() && !already {
+                profile.mut_follows().push_default().mut_user().set_bytes(followed.user.bytes().to_vec());
+            }
+        }
+        saver.save(i, &item)?;
+    }
+
+    // In time order, so that comments and votes refer to earlier items:
+    let mut slots: Vec<(i64, usize)> = (0..users)
+        .flat_map(|author| (0..posts).map(move |_| author))
+        .map(|author| (start + 1 + rng.below((now - start - 1) as u64) as i64, author))
+        .collect();
+    slots.sort();
+
+    let mut posted: Vec<(usize, Signature)> = vec![];
+    let mut polls: Vec<Poll> = vec![];
+    for (timestamp, author) in slots {
+        let mut item = Item::new();
+        item.timestamp_ms_utc = timestamp;
+        item.utc_offset_minutes = (rng.below(25) as i32 - 12) * 60;
+
+        let roll = rng.below(100);
+        let open_poll = polls.iter().rev().find(|poll| poll.close_ms_utc > timestamp && poll.author != author);
+        if roll < 15 && !posted.is_empty() {
+            let (to_author, to_signature) = &posted[rng.below(posted.len() as u64) as usize];
+            let comment = item.mut_comment();
+            let reply_to = comment.mut_reply_to();
+            reply_to.mut_user_id().set_bytes(authors[*to_author].user.bytes().to_vec());
+            reply_to.mut_signature().set_bytes(to_signature.bytes().to_vec());
+            let words = 3 + rng.below(30) as usize;
+            comment.text = rng.sentence(words);
+        } else if roll < 20 {
+            let poll = item.mut_poll();
+            let words = 3 + rng.below(8) as usize;
+            poll.question = format!("{}?", rng.sentence(words).trim_end_matches('.'));
+            for _ in 0..(2 + rng.below(4)) {
+                let words = 1 + rng.below(3) as usize;
+                poll.options.push(rng.words(words));
+            }
+            poll.close_ms_utc = timestamp + (1 + rng.below(14) as i64) * DAY_MS;
+        } else if roll < 28 && open_poll.is_some() {
+            let poll = open_poll.expect("checked");
+            let vote = item.mut_vote();
+            let reply_to = vote.mut_poll();
+            reply_to.mut_user_id().set_bytes(authors[poll.author].user.bytes().to_vec());
+            reply_to.mut_signature().set_bytes(poll.signature.bytes().to_vec());
+            vote.option = rng.below(poll.options as u64) as u32;
+        } else {
+            let post = item.mut_post();
+            if rng.below(100) < 70 {
+                let words = 2 + rng.below(8) as usize;
+                post.title = rng.sentence(words).trim_end_matches('.').to_string();
+            }
+            // Mostly short, sometimes long:
+            let paragraphs = match rng.below(100) {
+                0..=59 => 1,
+                60..=89 => 2 + rng.below(5),
+                // Up to ~25KiB, under the server's item size limit:
+                _ => 10 + rng.below(20),
+            };
+            post.body = rng.paragraphs(paragraphs as usize);
+        }
+
+        let signature = saver.save(author, &item)?;
+        if item.has_post() {
+            posted.push((author, signature));
+        } else if item.has_poll() {
+            polls.push(Poll {
+                author,
+                signature,
+                options: item.get_poll().options.len(),
+                close_ms_utc: item.get_poll().close_ms_utc,
+            });
+        }
+    }
+
+    report.items = saver.items;
+    Ok(report)
+}
+
+struct Author {
+    user: UserID,
+    secret_key: sign::SecretKey,
+}
+
+struct Poll {
+    author: usize,
+    signature: Signature,
+    options: usize,
+    close_ms_utc: i64,
+}
+
+struct Saver<'a> {
+    backend: &'a mut dyn Backend,
+    authors: &'a [Author],
+    items: usize,
+}
+
+impl Saver<'_> {
+    /// Sign, check, and save an item.
+    fn save(&mut self, author: usize, item: &Item) -> Result<Signature, Error> {
+        let author = &self.authors[author];
+        let bytes = item.write_to_bytes()?;
+        let signature = Signature::from_vec(sign::sign_detached(&bytes, &author.secret_key).as_ref().to_vec())?;
+        // As if it had arrived a little after it was written:
+        let received = Timestamp{ unix_utc_ms: item.timestamp_ms_utc + 1000 };
+        let (row, item) = crate::bundle::check_item(author.user.clone(), signature.clone(), &bytes, received)?;
+        self.backend.save_user_item(&row, &item)?;
+        self.items += 1;
+        Ok(signature)
+    }
+}
+
+/// xorshift64*. Not for cryptography, but fast and repeatable.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Zero would only ever generate zeros:
+        Rng(seed ^ 0x9E37_79B9_7F4A_7C15)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// A number in 0..n. (n > 0)
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn bytes32(&mut self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for chunk in bytes.chunks_mut(8) {
+            chunk.copy_from_slice(&self.next().to_le_bytes());
+        }
+        bytes
+    }
+
+    fn words(&mut self, count: usize) -> String {
+        (0..count)
+            .map(|_| WORDS[self.below(WORDS.len() as u64) as usize])
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn sentence(&mut self, words: usize) -> String {
+        let mut sentence = self.words(words.max(1));
+        sentence[..1].make_ascii_uppercase();
+        sentence.push('.');
+        sentence
+    }
+
+    fn paragraphs(&mut self, count: usize) -> String {
+        (0..count)
+            .map(|_| {
+                let sentences = 1 + self.below(6) as usize;
+                (0..sentences)
+                    .map(|_| { let words = 4 + self.below(16) as usize; self.sentence(words) })
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
@@ -1,4 +1,4 @@
-use std::{borrow::Cow, fmt, fmt::Write, marker::PhantomData, net::TcpListener};
+use std::{borrow::Cow, collections::{HashMap, HashSet}, fmt, fmt::Write, marker::PhantomData, path::PathBuf, sync::Arc};
 
 // TODO: This module is getting long.
 // Split it out into parts:
@@ -9,11 +9,13 @@ use std::{borrow::Cow, fmt, fmt::Write, marker::PhantomData, net::TcpListener};
 
 use futures_core::stream::Stream;
 use futures_util::StreamExt;
+use futures::future::{Either, TryFutureExt, ok};
 
-use actix_web::{dev::HttpResponseBuilder, http::Method, middleware::DefaultHeaders, web::Query};
+use actix_web::{dev::{HttpResponseBuilder, Service}, http::Method, web::Query};
 use actix_web::web::{
     self,
     get,
+    post,
     put,
     resource,
     route,
@@ -25,100 +27,438 @@ use actix_web::web::{
     Payload,
 };
 use actix_web::{App, HttpServer, Responder};
+use actix_http::{HttpService, Protocol, error::DispatchError};
+use actix_service::{fn_service, map_config};
+use actix_web::dev::AppConfig;
 use askama::Template;
 use failure::{bail, ResultExt, format_err};
 use rust_embed::RustEmbed;
 use serde::Deserialize;
 
-use actix_web::http::StatusCode;
+use actix_web::http::{header, StatusCode};
 use async_trait::async_trait;
 
-use protobuf::Message;
+use protobuf::Message as _;
 
-use crate::{ServeCommand, backend::ItemDisplayRow, protos::{ItemList, ItemListEntry, ItemType, Item_oneof_item_type}};
-use crate::backend::{self, Backend, Factory, UserID, Signature, ItemRow, Timestamp};
+use crate::{ServeCommand, backend::ItemDisplayRow, protos::{ItemList, ItemListEntry, ItemType, Item_oneof_item_type, ViewCount, ViewCounts}};
+use crate::backend::{self, Backend, Factory, UserID, Signature, ItemRow, Timestamp, QuotaDenyReason};
 use crate::protos::{Item, Post, ProtoValid};
+use crate::bloom::BloomFilter;
+use crate::markdown::ToHTML;
+use profile_diff::{DiffLine, ProfileDiff};
+use messages::Message;
+use sodiumoxide::crypto::hash::{sha256, sha512};
 
+pub(crate) mod assets;
 mod filters;
+pub(crate) mod blocklist;
+mod uploads;
+mod deprecations;
+mod feed_proxy;
+mod maps;
+mod locale;
+mod replication;
+mod announcement;
+pub(crate) mod maintenance;
+pub(crate) mod activitypub;
+mod der;
+mod acme;
+pub(crate) mod webfinger;
+mod nodeinfo;
+mod setup;
+pub(crate) mod nostr;
+mod crosspost;
+mod ipfs;
+mod onion;
+mod messages;
+pub(crate) mod snapshot;
+mod shutdown;
+mod expiry;
+mod prune;
+pub(crate) mod viewer;
+pub(crate) mod linkcheck;
+pub(crate) mod peer_auth;
+pub(crate) mod pow;
+mod storage;
+mod backup;
+mod discovery;
+pub(crate) mod listen;
+mod mirrors;
+mod comment_queue;
+mod mutes;
+mod saved_feeds;
+pub(crate) mod policy;
+pub(crate) mod profile_diff;
+pub(crate) mod remote;
+pub(crate) mod reports;
+mod tls;
 
 
 pub(crate) fn serve(command: ServeCommand) -> Result<(), failure::Error> {
 
     env_logger::init();
 
-    let ServeCommand{open, shared_options: options, mut binds} = command;
+    let ServeCommand{
+        config: _,
+        open,
+        shared_options: options,
+        retention_options,
+        binds,
+        tls_binds,
+        proxy_binds,
+        listens,
+        cert,
+        key,
+        acme_domains,
+        acme_email,
+        acme_directory,
+        blocks,
+        count_views,
+        max_concurrent_uploads,
+        min_upload_rate,
+        reject_item_types,
+        proxy_feeds,
+        map_tiles,
+        max_attachment_bytes,
+        anonymous_comments,
+        reply_counts,
+        linkcheck,
+        lang,
+        date_format,
+        maintenance_journal,
+        activitypub_key,
+        static_dir,
+        client_dir,
+        no_web_client,
+        nostr_relays,
+        ipfs_api,
+        hosts,
+        onion_address,
+        drain_timeout,
+        pow_difficulty,
+        quarantine_pow_items,
+        max_storage_bytes,
+        backup_keys,
+        admins,
+    } = command;
+
+    locale::init(locale::Locale{ lang, date_format })?;
+    let retention = retention_options.retention()?;
+
+    let mut listens = listens;
+    listens.extend(binds.iter().map(|bind| listen::Listen::tcp(bind, false)));
+    listens.extend(tls_binds.iter().map(|bind| listen::Listen::tcp(bind, true)));
+    listens.extend(proxy_binds.iter().map(|bind| listen::Listen::proxied(bind)));
+    if listens.is_empty() {
+        listens.push(listen::Listen::tcp("127.0.0.1:8080", false));
+    }
+    for listen in &listens {
+        listen.check()?;
+    }
+    let any_tls = listens.iter().any(|listen| listen.tls);
+
+    // What FeoBlog-Viewer and FeoBlog-Peer headers must be signed for:
+    let mut hosts = hosts;
+    hosts.extend(acme_domains.iter().cloned());
+
+    let acme = if acme_domains.is_empty() {
+        None
+    } else {
+        if cert.is_some() || key.is_some() {
+            bail!("--acme-domain can't be used with --cert or --key.");
+        }
+        if !any_tls {
+            bail!("--acme-domain requires --bind-tls.");
+        }
+        let acme = acme::Acme::new(acme_domains, acme_email, acme_directory, &options.sqlite_file)?;
+        Some(Arc::new(acme))
+    };
+    let onion_address = match onion_address {
+        Some(address) => Some(onion::parse_address(&address)?),
+        None => None,
+    };
+    hosts.extend(onion_address.iter().cloned());
+    let hosts: Vec<String> = hosts.iter().map(|host| host.trim().to_lowercase()).collect();
+    if pow_difficulty > pow::MAX_DIFFICULTY {
+        bail!("--pow-difficulty may be at most {}.", pow::MAX_DIFFICULTY);
+    }
+
+    let tls_config = match (&cert, &key, &acme) {
+        (Some(cert), Some(key), _) => Some(tls::server_config(cert, key)?),
+        (None, None, Some(acme)) => Some(acme::server_config(acme.clone())),
+        (None, None, None) => None,
+        _ => bail!("--cert and --key must be used together."),
+    };
+    if any_tls && tls_config.is_none() {
+        bail!("--bind-tls requires --cert and --key, or --acme-domain.");
+    }
 
-    // TODO: Error if the file doesn't exist, and make a separate 'init' command.
-    let factory = backend::sqlite::Factory::new(options.sqlite_file.clone());
-    // For now, this creates one if it doesn't exist already:
+    // Otherwise SQLite would create it, and a typo would start an empty server:
+    if options.backend == crate::BackendKind::Memory {
+        println!("Keeping data in memory. It will be lost when the server stops!");
+    } else if !std::path::Path::new(&options.sqlite_file).exists() {
+        bail!(
+            "{} does not exist. Check --sqlite-file, or create a new database with `feoblog db init`.",
+            options.sqlite_file,
+        );
+    }
+    let factory = options.factory()?;
+    // Migrate it, if it's from an older version:
     factory.open()?.setup().context("Error setting up DB")?;
-    
+
+    let blocklist = blocklist::SharedBlocklist::new(blocks);
+    blocklist.reload(factory.open()?.as_ref()).context("Error loading IP blocks")?;
+    let blocklist = Arc::new(blocklist);
+    let blocklist_factory = factory.clone();
+    let reload_blocklist = blocklist.clone();
+    let admins = Arc::new(admins);
+    let uploads = Arc::new(uploads::UploadLimiter::new(max_concurrent_uploads, min_upload_rate));
+    let accepted_item_types: Vec<ItemType> = ACCEPTABLE_ITEM_TYPES.iter()
+        .filter(|t| !reject_item_types.contains(t))
+        .cloned()
+        .collect();
+    let feed_proxy = if proxy_feeds { Some(Arc::new(feed_proxy::FeedProxy::new())) } else { None };
+    let map_tiles = map_tiles.map(|template| Arc::new(maps::MapTiles::new(template)));
+    let maintenance = Arc::new(maintenance::Maintenance::new(&options.sqlite_file, maintenance_journal));
+    let storage = if max_storage_bytes == 0 {
+        None
+    } else {
+        Some(Arc::new(storage::StorageCap::new(&options.sqlite_file, options.shards, max_storage_bytes)))
+    };
+    let homepage = Arc::new(snapshot::HomepageSnapshot::new());
+    let shutdown_factory = factory.clone();
+    let setup_code = setup::new_code(factory.open()?.as_ref())?;
+    let announced_setup_code = setup_code.clone();
+    let nostr_relays = Arc::new(nostr_relays);
+    let backup_keys = Arc::new(backup_keys);
+    let mirrors = Arc::new(mirrors::Mirrors::new());
+    let csp_reports = Arc::new(reports::ReportLimiter::new());
+    let server_key = peer_auth::ServerKey::load(&options.sqlite_file)?.map(|key| key.id());
+    let activitypub = match activitypub_key {
+        Some(path) => Some(Arc::new(activitypub::ServerKey::load(&path)?)),
+        None => None,
+    };
+    let push_factory = factory.clone();
+    let announcement_factory = factory.clone();
+    let journal_factory = factory.clone();
+    let push_maintenance = maintenance.clone();
+    let delivery_factory = factory.clone();
+    let delivery_maintenance = maintenance.clone();
+    let delivery_key = activitypub.clone();
+    let crosspost_factory = factory.clone();
+    let crosspost_maintenance = maintenance.clone();
+    let journal_maintenance = maintenance.clone();
+    let acme_renewals = acme.clone();
+    let ipfs_factory = factory.clone();
+    let ipfs_maintenance = maintenance.clone();
+    let ipfs = ipfs_api.is_some();
+    let expiry_factory = factory.clone();
+    let expiry_maintenance = maintenance.clone();
+    let expiry_homepage = homepage.clone();
+    let prune_factory = factory.clone();
+    let prune_maintenance = maintenance.clone();
+    let linkcheck_factory = factory.clone();
+    let linkcheck_maintenance = maintenance.clone();
 
     let app_factory = move || {
+        let blocked_ips = blocklist.clone();
+        let onion_address = onion_address.clone();
         let mut app = App::new()
+            // Note: Registered before Logger so that blocked requests still get logged.
+            .wrap_fn(move |req, srv| {
+                let blocked = req.peer_addr()
+                    .map(|addr| blocked_ips.contains(&addr.ip()))
+                    .unwrap_or(false);
+                if blocked {
+                    Either::Left(ok(req.into_response(
+                        HttpResponse::Forbidden()
+                        .content_type(PLAINTEXT)
+                        .body("Your IP address has been blocked.")
+                    )))
+                } else {
+                    Either::Right(srv.call(req))
+                }
+            })
+            .wrap_fn(|req, srv| {
+                srv.call(req).map_ok(|mut res| {
+                    let deprecation = res.request().match_pattern()
+                        .and_then(|pattern| deprecations::find(&pattern));
+                    if let Some(deprecation) = deprecation {
+                        deprecation.add_headers(res.headers_mut());
+                    }
+                    res
+                })
+            })
+            .wrap_fn(|req, srv| {
+                srv.call(req).map_ok(|mut res| {
+                    policy::add_page_headers(res.headers_mut());
+                    reports::add_csp_header(res.headers_mut());
+                    res
+                })
+            })
+            .wrap_fn(move |req, srv| {
+                let onion_address = onion_address.clone();
+                srv.call(req).map_ok(move |mut res| {
+                    if let Some(address) = &onion_address {
+                        onion::add_location(address, &mut res);
+                    }
+                    res
+                })
+            })
             .wrap(actix_web::middleware::Logger::default())
             .data(AppData{
-                backend_factory: Box::new(factory.clone()),
+                backend_factory: Arc::new(factory.clone()),
+                count_views,
+                uploads: uploads.clone(),
+                accepted_item_types: accepted_item_types.clone(),
+                feed_proxy: feed_proxy.clone(),
+                map_tiles: map_tiles.clone(),
+                max_attachment_bytes,
+                anonymous_comments,
+                reply_counts,
+                maintenance: maintenance.clone(),
+                activitypub: activitypub.clone(),
+                static_dir: static_dir.clone(),
+                client_dir: client_dir.clone(),
+                web_client: !no_web_client,
+                setup_code: setup_code.clone(),
+                nostr_relays: nostr_relays.clone(),
+                acme: acme.clone(),
+                ipfs,
+                homepage: homepage.clone(),
+                pow_difficulty,
+                quarantine_pow_items,
+                storage: storage.clone(),
+                backup_keys: backup_keys.clone(),
+                admins: admins.clone(),
+                blocklist: blocklist.clone(),
+                mirrors: mirrors.clone(),
+                csp_reports: csp_reports.clone(),
+                server_key: server_key.clone(),
+                hosts: hosts.clone(),
             })
             .configure(routes)
         ;
 
-        app = app.default_service(route().to(|| file_not_found("")));
+        app = app.default_service(route().to(|req: HttpRequest| async move {
+            file_not_found(&req, Message::FileNotFound)
+        }));
 
         return app;
     };
 
-    if binds.is_empty() {
-        binds.push("127.0.0.1:8080".into());
+    let mut server = HttpServer::new(app_factory.clone())
+        // See: shutdown.rs
+        .disable_signals()
+        .shutdown_timeout(drain_timeout);
+
+    // HttpServer can't read PROXY headers, so those listeners get their own
+    // server. Both run at once, on their own workers:
+    let mut proxy_server = actix_web::dev::Server::build()
+        .disable_signals()
+        .shutdown_timeout(drain_timeout);
+    let mut proxied = false;
+
+    for listen in &listens {
+        let context = || format!("Error binding to {}", listen);
+        match (&listen.address, &listen.unix) {
+            (Some(address), _) if listen.proxy_protocol => {
+                let socket = listen::open_socket(address).with_context(|_| context())?;
+                let app_factory = app_factory.clone();
+                proxy_server = proxy_server.listen(listen.to_string(), socket, move || {
+                    use actix_web::rt::net::TcpStream;
+                    actix_service::pipeline_factory(fn_service(|mut io: TcpStream| async move {
+                        let peer = listen::read_proxy_header(&mut io, listen::HEADER_TIMEOUT).await.map_err(DispatchError::Io)?;
+                        Ok::<_, DispatchError>((io, Protocol::Http1, peer))
+                    }))
+                    .and_then(HttpService::build().finish(map_config(app_factory(), |_| AppConfig::default())))
+                })?;
+                proxied = true;
+            },
+            (Some(address), _) => {
+                let socket = listen::open_socket(address).with_context(|_| context())?;
+                server = match (listen.tls, &tls_config) {
+                    (true, Some(config)) => server.listen_rustls(socket, config.clone())?,
+                    _ => server.listen(socket)?,
+                };
+            },
+            #[cfg(unix)]
+            (None, Some(path)) if listen.proxy_protocol => {
+                let socket = listen::open_unix_socket(path).with_context(|_| context())?;
+                let app_factory = app_factory.clone();
+                proxy_server = proxy_server.listen_uds(listen.to_string(), socket, move || {
+                    use actix_web::rt::net::UnixStream;
+                    actix_service::pipeline_factory(fn_service(|mut io: UnixStream| async move {
+                        let peer = listen::read_proxy_header(&mut io, listen::HEADER_TIMEOUT).await.map_err(DispatchError::Io)?;
+                        Ok::<_, DispatchError>((io, Protocol::Http1, peer))
+                    }))
+                    .and_then(HttpService::build().finish(map_config(app_factory(), |_| AppConfig::default())))
+                })?;
+                proxied = true;
+            },
+            #[cfg(unix)]
+            (None, Some(path)) => {
+                let socket = listen::open_unix_socket(path).with_context(|_| context())?;
+                server = server.listen_uds(socket)?;
+            },
+            // Already refused by Listen::check():
+            _ => bail!("Can't listen on {}", listen),
+        }
     }
 
-    let mut server = HttpServer::new(app_factory); 
-    
-    for bind in &binds {
-        let socket = open_socket(bind).with_context(|_| {
-            format!("Error binding to address/port: {}", bind)
-        })?;
-        server = server.listen(socket)?;
-    }
+    let urls: Vec<String> = listens.iter().map(|listen| listen.url()).collect();
 
     if open {
         // TODO: This opens up a (AFAICT) blocking CLI browser on Linux. Boo. Don't do that.
         // TODO: Handle wildcard addresses (0.0.0.0, ::0) and --open them via localhost.
-        let url = format!("http://{}/", binds[0]);
-        let opened = webbrowser::open(&url);
-        if !opened.is_ok() {
+        let url = listens.iter().find(|listen| listen.address.is_some()).map(|listen| listen.url());
+        let opened = url.map(|url| webbrowser::open(&url));
+        if !matches!(opened, Some(Ok(_))) {
             println!("Warning: Couldn't open browser.");
         }
     }
 
-    for bind in &binds {
-        println!("Started at: http://{}/", bind);
+    for url in &urls {
+        println!("Started at: {}", url);
+    }
+    if let Some(code) = &announced_setup_code {
+        println!("This server has no users yet. To add one from the homepage, use the setup code: {}", code);
     }
  
     let mut system = actix_web::rt::System::new("web server");
-    system.block_on(server.run())?;
-   
-    Ok(())
-}
+    let mut running = vec![server.run()];
+    if proxied {
+        running.push(proxy_server.run());
+    }
+    actix_web::rt::spawn(shutdown::on_signal(running.clone()));
+    actix_web::rt::spawn(replication::push_loop(Box::new(push_factory), push_maintenance));
+    actix_web::rt::spawn(journal_maintenance.replay_loop(Box::new(journal_factory)));
+    actix_web::rt::spawn(announcement::refresh_loop(Box::new(announcement_factory)));
+    actix_web::rt::spawn(blocklist::reload_loop(Box::new(blocklist_factory), reload_blocklist));
+    actix_web::rt::spawn(crosspost::crosspost_loop(Box::new(crosspost_factory), crosspost_maintenance));
+    actix_web::rt::spawn(expiry::expire_loop(Box::new(expiry_factory), expiry_maintenance, expiry_homepage));
+    actix_web::rt::spawn(prune::prune_loop(Box::new(prune_factory), prune_maintenance, retention));
+    if linkcheck {
+        actix_web::rt::spawn(linkcheck::linkcheck_loop(Box::new(linkcheck_factory), linkcheck_maintenance));
+    }
+    if let Some(key) = delivery_key {
+        actix_web::rt::spawn(activitypub::delivery_loop(Box::new(delivery_factory), delivery_maintenance, key));
+    }
+    if let Some(acme) = acme_renewals {
+        actix_web::rt::spawn(acme::renew_loop(acme));
+    }
+    if let Some(api) = ipfs_api {
+        actix_web::rt::spawn(ipfs::pin_loop(Box::new(ipfs_factory), ipfs_maintenance, api));
+    }
+    for result in system.block_on(futures::future::join_all(running)) {
+        result?;
+    }
 
-// Work around https://github.com/actix/actix-web/issues/1913
-fn open_socket(bind: &str) -> Result<TcpListener, failure::Error> {
-    use socket2::{Domain, Protocol, Socket, Type};
-    use std::net::SocketAddr;
-    
-    // Eh, this is what actix was using:
-    let backlog = 1024;
-    
-    let addr = bind.parse()?;
-    let domain = match addr {
-        SocketAddr::V4(_) => Domain::ipv4(),
-        SocketAddr::V6(_) => Domain::ipv6(),
-    };
-    let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
-    socket.bind(&addr.into())?;
-    socket.listen(backlog)?;
+    // Leave the database tidy, in case it's about to be copied or moved:
+    shutdown_factory.open()?.checkpoint().context("Error checkpointing database")?;
+    println!("Stopped.");
 
-    Ok(socket.into_tcp_listener())
+    Ok(())
 }
 
 /// Data available for our whole application.
@@ -127,55 +467,355 @@ fn open_socket(bind: &str) -> Result<TcpListener, failure::Error> {
 // Data<Foo> can fail at runtime if you delete a Foo and don't clean up after
 // yourself.
 struct AppData {
-    backend_factory: Box<dyn backend::Factory>,
+    // Send + Sync, so that slow work (ex: backups) can move to a thread pool:
+    backend_factory: Arc<dyn backend::Factory + Send + Sync>,
+
+    /// Should we keep (anonymous) view counts for items?
+    count_views: bool,
+
+    /// Shared by all workers.
+    uploads: Arc<uploads::UploadLimiter>,
+
+    /// Item types that we'll accept uploads of.
+    accepted_item_types: Vec<ItemType>,
+
+    /// If set, fetch missing feed items from followed users' home servers.
+    feed_proxy: Option<Arc<feed_proxy::FeedProxy>>,
+
+    /// If set, render maps for posts with locations.
+    map_tiles: Option<Arc<maps::MapTiles>>,
+
+    /// The largest file attachment we'll accept.
+    max_attachment_bytes: u64,
+
+    /// Can visitors without keys queue comments for review?
+    anonymous_comments: bool,
+
+    /// Show how many comments each item has in listings?
+    reply_counts: bool,
+
+    /// Are writes paused (or journaled) for maintenance?
+    maintenance: Arc<maintenance::Maintenance>,
+
+    /// If set, publish server users as ActivityPub actors.
+    activitypub: Option<Arc<activitypub::ServerKey>>,
+
+    /// If set, serve files from these directories before embedded ones.
+    static_dir: Option<PathBuf>,
+    client_dir: Option<PathBuf>,
+
+    /// Serve the in-browser client at /client/?
+    web_client: bool,
+
+    /// Lets a new server's first user be added from its homepage.
+    setup_code: Option<String>,
+
+    /// Nostr relays that we republish posts to.
+    nostr_relays: Arc<Vec<String>>,
+
+    /// If set, answers ACME challenges for our TLS certificate.
+    acme: Option<Arc<acme::Acme>>,
+
+    /// Add uploads to IPFS? (With --ipfs-api)
+    ipfs: bool,
+
+    /// Shared by all workers.
+    homepage: Arc<snapshot::HomepageSnapshot>,
+
+    /// Proof-of-work required from unknown users. 0 = they can't post.
+    pow_difficulty: u32,
+
+    /// Hold proof-of-work items until an operator approves them.
+    quarantine_pow_items: bool,
+
+    /// With --max-storage-bytes.
+    storage: Option<Arc<storage::StorageCap>>,
+
+    /// Server keys that may download `/backup`.
+    backup_keys: Arc<Vec<UserID>>,
+
+    /// Users who may use `/admin/` endpoints. (With a FeoBlog-Viewer header.)
+    admins: Arc<Vec<UserID>>,
+
+    /// So that `/admin/blocks` changes take effect right away.
+    blocklist: Arc<blocklist::SharedBlocklist>,
+
+    /// Shared by all workers.
+    mirrors: Arc<mirrors::Mirrors>,
+
+    /// Per-IP limits on `/csp-report`. Shared by all workers.
+    csp_reports: Arc<reports::ReportLimiter>,
+
+    /// Our peer key (`feoblog peers key`), if we've made one.
+    server_key: Option<UserID>,
+
+    /// Our own host names, which signed requests must be signed for.
+    hosts: Vec<String>,
 }
 
+impl AppData {
+    /// Give the feed proxy (if enabled) a chance to fetch missing items for a feed.
+    async fn fill_feed(&self, user: &UserID) {
+        let proxy = match &self.feed_proxy {
+            Some(proxy) => proxy,
+            None => return,
+        };
+        let fill = proxy.fill(self.backend_factory.as_ref(), user);
+        match actix_web::rt::time::timeout(feed_proxy::FILL_TIMEOUT, fill).await {
+            Ok(Ok(())) => {},
+            Ok(Err(err)) => log::warn!("Error filling feed for {}: {}", user.to_base58(), err),
+            Err(_) => log::warn!("Timed out filling feed for {}", user.to_base58()),
+        }
+    }
+}
+
+/// Item types this server knows how to validate, and so can accept.
+const ACCEPTABLE_ITEM_TYPES: &[ItemType] = &[ItemType::POST, ItemType::PROFILE, ItemType::POLL, ItemType::VOTE, ItemType::COMMENT, ItemType::DELETE, ItemType::REACTION];
+
 fn routes(cfg: &mut web::ServiceConfig) {
     cfg
         .route("/", get().to(view_homepage))
         .route("/homepage/proto3", get().to(homepage_item_list))
+        .route("/search", get().to(view_search))
+        .route("/setup/user", post().to(setup::add_user))
+        .route("/feed.rss", get().to(homepage_rss))
+        .route("/feed.json", get().to(homepage_json_feed))
+        .service(
+            web::resource("/.well-known/webfinger")
+            .route(get().to(webfinger::webfinger))
+            .wrap(policy::cors())
+        )
+        .service(
+            web::resource("/.well-known/feoblog.json")
+            .route(get().to(discovery::well_known))
+            .wrap(policy::cors())
+        )
+        .service(
+            web::resource("/.well-known/nodeinfo")
+            .route(get().to(nodeinfo::well_known))
+            .wrap(policy::cors())
+        )
+        .route("/.well-known/acme-challenge/{token}", get().to(acme::challenge))
+        .service(
+            web::resource("/nodeinfo/2.1")
+            .route(get().to(nodeinfo::nodeinfo_2_1))
+            .wrap(policy::cors())
+        )
+        .service(
+            web::resource("/search/proto3")
+            .route(get().to(search_item_list))
+            .wrap(policy::cors())
+        )
+        .service(
+            web::resource("/server/info/proto3")
+            .route(get().to(get_server_info))
+            .wrap(policy::cors())
+        )
+        .route("/server/status.json", get().to(storage::get_status))
+        .service(
+            web::resource("/csp-report")
+            .app_data(web::PayloadConfig::new(reports::MAX_REPORT_BYTES))
+            .route(post().to(reports::post_csp_report))
+        )
+        .route("/backup", get().to(backup::get_backup))
+        .route("/admin/blocks", get().to(blocklist::get_blocks))
+        .service(
+            web::resource("/admin/blocks/{network}/{prefix_len}")
+            .route(put().to(blocklist::put_block))
+            .route(route().method(Method::DELETE).to(blocklist::delete_block))
+        )
 
         .route("/u/{user_id}/", get().to(get_user_items))
         .service(
             web::resource("/u/{user_id}/proto3")
             .route(get().to(user_item_list))
-            .wrap(cors_ok_headers())
+            .wrap(policy::cors())
+        )
+        .service(
+            web::resource("/u/{user_id}/followers-only/proto3")
+            .route(get().to(followers_only_item_list))
+            .route(route().method(Method::OPTIONS).to(policy::preflight))
+            .wrap(policy::cors())
         )
 
         .route("/u/{userID}/i/{signature}/", get().to(show_item))
         .service(
             web::resource("/u/{userID}/i/{signature}/proto3")
             .route(get().to(get_item))
+            // Lets other servers check that we have an item. (See: mirrors.rs)
+            .route(route().method(Method::HEAD).to(get_item))
             .route(put().to(put_item))
-            .route(route().method(Method::OPTIONS).to(cors_preflight_allow))
-            .wrap(cors_ok_headers())
+            .route(route().method(Method::OPTIONS).to(policy::preflight))
+            .wrap(policy::cors())
+        )
+
+        .route("/u/{userID}/i/{signature}/map.png", get().to(get_item_map))
+        .route("/u/{userID}/i/{signature}/object", get().to(activitypub::get_object))
+        .service(
+            web::resource("/u/{userID}/i/{signature}/ipfs.json")
+            .route(get().to(ipfs::get_cids))
+            .wrap(policy::cors())
+        )
+        .route("/u/{userID}/i/{signature}/comments.atom", get().to(get_item_comments_atom))
+        .route("/u/{userID}/i/{signature}/article.epub", get().to(get_item_epub))
+        .service(
+            web::resource("/u/{userID}/i/{signature}/mirrors")
+            .route(get().to(mirrors::get_mirrors))
+            .wrap(policy::cors())
+        )
+        .route("/u/{userID}/i/{signature}/comments/queue", post().to(queue_anonymous_comment))
+        .route("/u/{user_id}/comments/queue/", get().to(comment_queue::get_queue))
+        .route("/u/{user_id}/comments/queue/{id}/approve", post().to(comment_queue::approve))
+        .route("/u/{user_id}/comments/queue/{id}", route().method(Method::DELETE).to(comment_queue::reject))
+        .service(
+            web::resource("/u/{userID}/i/{signature}/files/{file_name}")
+            .route(get().to(get_attachment))
+            .route(put().to(put_attachment))
+            .route(route().method(Method::OPTIONS).to(policy::preflight))
+            .wrap(policy::cors())
+        )
+        .service(
+            web::resource("/u/{userID}/i/{signature}/replies/proto3")
+            .route(get().to(get_item_replies))
+            .wrap(policy::cors())
+        )
+        .service(
+            web::resource("/u/{userID}/i/{signature}/votes/proto3")
+            .route(get().to(get_poll_tally))
+            .wrap(policy::cors())
+        )
+        .service(
+            web::resource("/u/{userID}/i/{signature}/reactions/proto3")
+            .route(get().to(get_reaction_counts))
+            .wrap(policy::cors())
+        )
+        .service(
+            web::resource("/u/{userID}/i/{signature}/thread/proto3")
+            .route(get().to(get_thread_bundle))
+            .wrap(policy::cors())
         )
 
         .route("/u/{user_id}/profile/", get().to(show_profile))
+        .route("/u/{user_id}/profile/history/", get().to(show_profile_history))
         .service(
             web::resource("/u/{user_id}/profile/proto3")
             .route(get().to(get_profile_item))
-            .wrap(cors_ok_headers())
+            .wrap(policy::cors())
+        )
+        .service(
+            web::resource("/u/{user_id}/new-item/proto3")
+            .route(get().to(get_new_item))
+            .wrap(policy::cors())
+        )
+        .route("/u/{user_id}/gallery/", get().to(get_user_gallery))
+        .route("/u/{user_id}/follows/opml", get().to(get_follows_opml))
+        .service(
+            web::resource("/u/{user_id}/follows/json")
+            .route(get().to(get_follows_json))
+            .wrap(policy::cors())
+        )
+        .service(
+            web::resource("/u/{user_id}/follows/proto3")
+            .route(get().to(get_follows_proto))
+            .wrap(policy::cors())
+        )
+        .route("/u/{user_id}/feeds/", get().to(saved_feeds::get_feeds))
+        .service(
+            web::resource("/u/{user_id}/feeds/{name}")
+            .route(put().to(saved_feeds::put_feed))
+            .route(route().method(Method::DELETE).to(saved_feeds::delete_feed))
+        )
+        .route("/u/{user_id}/feeds/{name}/", get().to(get_saved_feed))
+        .route("/u/{user_id}/feeds/{name}/feed.rss", get().to(saved_feed_rss))
+        .service(
+            web::resource("/u/{user_id}/feeds/{name}/proto3")
+            .route(get().to(saved_feed_item_list))
+            .wrap(policy::cors())
         )
         .route("/u/{user_id}/feed/", get().to(get_user_feed))
         .route("/u/{user_id}/feed/proto3", get().to(feed_item_list))
+        .route("/u/{user_id}/mutes/", get().to(mutes::get_mutes))
+        .service(
+            web::resource("/u/{user_id}/mutes/{muted_id}")
+            .route(put().to(mutes::put_mute))
+            .route(route().method(Method::DELETE).to(mutes::delete_mute))
+        )
+        .route("/u/{user_id}/feed.atom", get().to(get_user_feed_atom))
+        .route("/u/{user_id}/posts.atom", get().to(get_user_posts_atom))
+        .route("/u/{user_id}/feed.json", get().to(get_user_feed_json))
+        .route("/u/{user_id}/posts.json", get().to(get_user_posts_json))
+        .route("/u/{user_id}/articles.opds", get().to(get_user_articles_opds))
+        .route("/u/{user_id}/actor", get().to(activitypub::get_actor))
+        .route("/u/{user_id}/outbox", get().to(activitypub::get_outbox))
+        .route("/u/{user_id}/followers", get().to(activitypub::get_followers))
+        .route("/u/{user_id}/inbox", post().to(activitypub::post_inbox))
+        .service(
+            web::resource("/u/{user_id}/events/proto3")
+            .route(get().to(get_user_item_events))
+            .wrap(policy::cors())
+        )
+        .service(
+            web::resource("/u/{user_id}/summary/proto3")
+            .route(get().to(get_user_summary))
+            .wrap(policy::cors())
+        )
+        .service(
+            web::resource("/u/{user_id}/bloom/proto3")
+            .route(get().to(get_user_bloom_filter))
+            .wrap(policy::cors())
+        )
+        .route("/u/{user_id}/stats/", get().to(show_user_stats))
+        .service(
+            web::resource("/u/{user_id}/stats/views/proto3")
+            .route(get().to(user_view_counts))
+            .route(route().method(Method::OPTIONS).to(policy::preflight))
+            .wrap(policy::cors())
+        )
+        .service(
+            web::resource("/u/{user_id}/stats/dead-links/proto3")
+            .route(get().to(user_dead_links))
+            .wrap(policy::cors())
+        )
 
     ;
     statics(cfg);
 }
 
-#[async_trait]
+#[async_trait(?Send)]
 trait StaticFilesResponder {
     type Response: Responder;
-    async fn response(path: Path<(String,)>) -> Result<Self::Response, Error>;
+    async fn response(data: Data<AppData>, path: Path<(String,)>, req: HttpRequest) -> Result<Self::Response, Error>;
+}
+
+/// How we serve a folder of embedded files.
+trait AssetFolder {
+    /// Serve index.html for unknown paths that don't look like files, for
+    /// single-page apps that route by path.
+    const SPA_FALLBACK: bool = false;
+
+    /// Is this folder served at all?
+    fn enabled(_data: &AppData) -> bool { true }
+
+    /// A directory on disk whose files override the embedded ones.
+    fn override_dir(data: &AppData) -> Option<&std::path::Path>;
 }
 
-#[async_trait]
-impl <T: RustEmbed> StaticFilesResponder for T {
+#[async_trait(?Send)]
+impl <T: RustEmbed + assets::AssetHashes + AssetFolder> StaticFilesResponder for T {
     type Response = HttpResponse;
 
-    async fn response(path: Path<(String,)>) -> Result<Self::Response, Error> {
+    async fn response(data: Data<AppData>, path: Path<(String,)>, req: HttpRequest) -> Result<Self::Response, Error> {
         let (mut path,) = path.into_inner();
+
+        if !T::enabled(&data) {
+            return file_not_found(&req, Message::FileNotFound);
+        }
+
+        if let Some(dir) = T::override_dir(&data) {
+            if let Some(response) = assets::from_disk(dir, &path)? {
+                return Ok(response);
+            }
+        }
         
             
         let mut maybe_bytes = T::get(path.as_str());
@@ -190,31 +830,69 @@ impl <T: RustEmbed> StaticFilesResponder for T {
             }
         }
 
+        if maybe_bytes.is_none() {
+            // If adding the slash would get us an index.html, do so:
+            let with_index = format!("{}/index.html", path);
+            if T::get(with_index.as_str()).is_some() {
+                // Use a relative redirect from the inner-most path part:
+                let part = path.split("/").last().expect("at least one element");
+                let part = format!("{}/", part);
+                return Ok(
+                    HttpResponse::SeeOther()
+                        .header("location", part)
+                        .finish()
+                );
+            }
+        }
+
+        let last_part = path.split("/").last().unwrap_or_default();
+        let spa_fallback = maybe_bytes.is_none() && T::SPA_FALLBACK && !last_part.contains('.');
+        if spa_fallback {
+            if let Some(dir) = T::override_dir(&data) {
+                if let Some(response) = assets::from_disk(dir, "index.html")? {
+                    return Ok(response);
+                }
+            }
+            path = "index.html".into();
+            maybe_bytes = T::get(path.as_str());
+        }
+
         if let Some(bytes) = maybe_bytes {
+            let hash = T::hash(path.as_str());
+            let etag = hash.map(|hash| format!("\"{}\"", hash));
+            if let Some(etag) = &etag {
+                if if_none_match(&req, etag) {
+                    return Ok(HttpResponse::NotModified().header("ETag", etag.as_str()).finish());
+                }
+            }
+
             // Set some response headers.
             // In particular, a mime type is required for things like JS to work.
-            let mime_type = format!("{}", mime_guess::from_path(path).first_or_octet_stream());
-            let response = HttpResponse::Ok()
-                .content_type(mime_type)
-
-                // TODO: This likely will result in lots of byte copying.
-                // Should implement our own MessageBody
-                // for Cow<'static, [u8]>
-                .body(bytes.into_owned());
-            return Ok(response)
-        }
-
-        // If adding the slash would get us an index.html, do so:
-        let with_index = format!("{}/index.html", path);
-        if T::get(with_index.as_str()).is_some() {
-            // Use a relative redirect from the inner-most path part:
-            let part = path.split("/").last().expect("at least one element");
-            let part = format!("{}/", part);
-            return Ok(
-                HttpResponse::SeeOther()
-                    .header("location", part)
-                    .finish()
-            );
+            let mime_type = format!("{}", mime_guess::from_path(&path).first_or_octet_stream());
+            let mut response = HttpResponse::Ok();
+            response.content_type(mime_type);
+            if let Some(etag) = etag {
+                response.header("ETag", etag);
+            }
+
+            // A URL from static_url()/client_url() always has the same content:
+            let version = T::version(path.as_str()).map(|version| format!("v={}", version));
+            let versioned = version.map_or(false, |version| {
+                req.query_string().split('&').any(|param| param == version)
+            });
+            if versioned {
+                policy::Cache::Immutable.apply(&mut response);
+            } else if spa_fallback {
+                // Served at many URLs, so check for a new version each time:
+                policy::Cache::Mutable.apply(&mut response);
+            }
+
+            // Release builds embed files as &'static [u8], which we can send without copying:
+            let body = match bytes {
+                Cow::Borrowed(bytes) => web::Bytes::from_static(bytes),
+                Cow::Owned(bytes) => web::Bytes::from(bytes),
+            };
+            return Ok(response.body(body))
         }
 
         Ok(
@@ -229,10 +907,37 @@ impl <T: RustEmbed> StaticFilesResponder for T {
 #[folder = "static/"]
 struct StaticFiles;
 
+impl assets::AssetHashes for StaticFiles {
+    const HASHES: &'static [(&'static str, &'static str)] = assets::STATIC_FILES;
+}
+
+impl AssetFolder for StaticFiles {
+    fn override_dir(data: &AppData) -> Option<&std::path::Path> {
+        data.static_dir.as_deref()
+    }
+}
+
 #[derive(RustEmbed, Debug)]
 #[folder = "web-client/build/"]
 struct WebClientBuild;
 
+impl assets::AssetHashes for WebClientBuild {
+    const HASHES: &'static [(&'static str, &'static str)] = assets::WEB_CLIENT_FILES;
+}
+
+impl AssetFolder for WebClientBuild {
+    // Paths under /client/ may be the client's routes:
+    const SPA_FALLBACK: bool = true;
+
+    fn enabled(data: &AppData) -> bool {
+        data.web_client
+    }
+
+    fn override_dir(data: &AppData) -> Option<&std::path::Path> {
+        data.client_dir.as_deref()
+    }
+}
+
 
 fn statics(cfg: &mut web::ServiceConfig) {
     cfg
@@ -252,7 +957,18 @@ fn bound<T: Ord>(input: T, lower: T, upper: T) -> T {
 async fn view_homepage(
     data: Data<AppData>,
     Query(pagination): Query<Pagination>,
-) -> Result<impl Responder, Error> {
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    // Everyone viewing the front page sees the same thing:
+    let snapshot = if pagination.before.is_none() && pagination.count.is_none() {
+        match data.homepage.html() {
+            snapshot::Lookup::Serve(html) => return Ok(html_response(html)),
+            snapshot::Lookup::Render(render) => Some(render),
+        }
+    } else {
+        None
+    };
+
     let max_items = pagination.count.map(|c| bound(c, 1, 100)).unwrap_or(20);
 
     let mut items = Vec::with_capacity(max_items);
@@ -271,7 +987,7 @@ async fn view_homepage(
             return Ok(false);
         }
 
-        items.push(IndexPageItem{row, item});
+        items.push(IndexPageItem{row, item, replies: None});
         Ok(true)
     };
 
@@ -280,6 +996,21 @@ async fn view_homepage(
         .unwrap_or_else(|| Timestamp::now());
     let backend = data.backend_factory.open().compat()?;
     backend.homepage_items(max_time, &mut item_callback).compat()?;
+    if data.reply_counts {
+        add_reply_counts(backend.as_ref(), &mut items).compat()?;
+    }
+
+    if items.is_empty() && pagination.before.is_none() {
+        if let Some(progress) = setup::progress(backend.as_ref()).compat()? {
+            let page = SetupPage {
+                nav: vec![Nav::Text("FeoBlog".into())],
+                has_user: progress.has_user,
+                setup_code: data.setup_code.is_some(),
+                web_client: data.web_client,
+            };
+            return Ok(page.respond_to(&req).await?);
+        }
+    }
 
     let display_message = if items.is_empty() {
         if pagination.before.is_none() {
@@ -291,13 +1022,17 @@ async fn view_homepage(
         None
     };
 
-    let mut nav = vec![
-        Nav::Text("FeoBlog".into()),
-        Nav::Link{
+    let mut nav = vec![Nav::Text("FeoBlog".into())];
+    if data.web_client {
+        nav.push(Nav::Link{
             text: "Client".into(),
-            href: "/client/".into(),
-        }
-    ];
+            href: assets::client_url(""),
+        });
+    }
+    nav.push(Nav::Link{
+        text: "Search".into(),
+        href: "/search".into(),
+    });
 
     if has_more {
         if let Some(page_item) = items.last() {
@@ -313,12 +1048,25 @@ async fn view_homepage(
         }
     }
 
-    Ok(IndexPage {
+    let page = IndexPage {
         nav,
         items,
         display_message,
         show_authors: true,
-    })
+        search: None,
+    };
+    if let Some(snapshot) = snapshot {
+        let html = web::Bytes::from(page.render()?);
+        snapshot.save(html.clone());
+        return Ok(html_response(html));
+    }
+    Ok(page.respond_to(&req).await?)
+}
+
+fn html_response(html: web::Bytes) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(html)
 }
 
 fn item_to_entry(item: &Item, user_id: &UserID, signature: &Signature) -> ItemListEntry {
@@ -334,23 +1082,85 @@ fn item_to_entry(item: &Item, user_id: &UserID, signature: &Signature) -> ItemLi
         uid.set_bytes(user_id.bytes().into());
         uid
     });
-    entry.set_item_type(
-        match item.item_type {
-            Some(Item_oneof_item_type::post(_)) => ItemType::POST,
-            Some(Item_oneof_item_type::profile(_)) => ItemType::PROFILE,
-            None => ItemType::UNKNOWN,
-        }
-    );
+    entry.set_item_type(item_type(item));
+    entry.license = own_license(item).to_string();
+    entry.content_warning = item.get_post().get_content_warning().to_string();
 
     entry
 }
 
+/// The license that an item gives itself, if any. (See: `Post.license`)
+fn own_license(item: &Item) -> &str {
+    if item.has_post() {
+        item.get_post().get_license()
+    } else if item.has_profile() {
+        item.get_profile().get_license()
+    } else {
+        ""
+    }
+}
+
+/// Finds the licenses that items are shared under: their own, or else their
+/// author's default. Remembers each author's, for lists of items.
+struct Licenses<'a> {
+    backend: &'a dyn Backend,
+    defaults: HashMap<Vec<u8>, String>,
+}
+
+impl<'a> Licenses<'a> {
+    fn new(backend: &'a dyn Backend) -> Self {
+        Licenses { backend, defaults: HashMap::new() }
+    }
+
+    /// An SPDX license identifier, or "" if the author didn't give one.
+    fn license(&mut self, user: &UserID, item: &Item) -> Result<String, failure::Error> {
+        let license = own_license(item);
+        if !license.is_empty() {
+            return Ok(license.to_string());
+        }
+        if let Some(license) = self.defaults.get(user.bytes()) {
+            return Ok(license.clone());
+        }
+
+        let mut profile = Item::new();
+        if let Some(row) = self.backend.user_profile(user)? {
+            profile.merge_from_bytes(&row.item_bytes)?;
+        }
+        let license = profile.get_profile().get_license().to_string();
+        self.defaults.insert(user.bytes().to_vec(), license.clone());
+        Ok(license)
+    }
+}
+
+fn item_type(item: &Item) -> ItemType {
+    match item.item_type {
+        Some(Item_oneof_item_type::post(_)) => ItemType::POST,
+        Some(Item_oneof_item_type::profile(_)) => ItemType::PROFILE,
+        Some(Item_oneof_item_type::poll(_)) => ItemType::POLL,
+        Some(Item_oneof_item_type::vote(_)) => ItemType::VOTE,
+        Some(Item_oneof_item_type::comment(_)) => ItemType::COMMENT,
+        Some(Item_oneof_item_type::delete(_)) => ItemType::DELETE,
+        Some(Item_oneof_item_type::reaction(_)) => ItemType::REACTION,
+        None => ItemType::UNKNOWN,
+    }
+}
+
 // Get the protobuf ItemList for items on the homepage.
 async fn homepage_item_list(
     data: Data<AppData>,
     Query(pagination): Query<Pagination>,
+    req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
 
+    let snapshot = if pagination.before.is_none() && pagination.count.is_none() {
+        match data.homepage.proto() {
+            snapshot::Lookup::Serve((list, etag)) => return item_list_etag_response(&req, &list, true, etag),
+            snapshot::Lookup::Render(render) => Some(render),
+        }
+    } else {
+        None
+    };
+
     let mut paginator = Paginator::new(
         pagination,
         |row: ItemDisplayRow| -> Result<ItemListEntry,failure::Error> {
@@ -359,96 +1169,329 @@ async fn homepage_item_list(
             Ok(item_to_entry(&item, &row.item.user, &row.item.signature))
         }, 
         |entry: &ItemListEntry| { 
-            entry.get_item_type() == ItemType::POST
+            shown_by_default(entry.get_item_type())
         }
     );
     // We're only holding ItemListEntries in memory, so we can up this limit and save some round trips.
     paginator.max_items = 1000;
 
     let backend = data.backend_factory.open().compat()?;
+
+    let mut users = vec![];
+    backend.server_users(&mut |server_user| {
+        if server_user.on_homepage {
+            users.push(server_user.user);
+        }
+        Ok(true)
+    }).compat()?;
+    let etag = summary_etag(backend.as_ref(), &users).compat()?;
+    if if_none_match(&req, &etag) {
+        return Ok(not_modified(etag));
+    }
+
     backend.homepage_items(paginator.before(), &mut paginator.callback()).compat()?;
 
+    let first_page = paginator.params.before.is_none();
     let mut list = ItemList::new();
     list.no_more_items = !paginator.has_more;
     list.items = protobuf::RepeatedField::from(paginator.items);
-    Ok(
-        proto_ok().body(list.write_to_bytes()?)
-    )
-}
-
-// Start building a response w/ proto3 binary data.
-fn proto_ok() -> HttpResponseBuilder {
-    let mut builder = HttpResponse::Ok();
-    builder.content_type("application/protobuf3");
-    builder
-}
-
-// // CORS headers must be present for *all* responses, including 404, 500, etc.
-// // Applying it to each case individiaully may be error-prone, so here's a filter to do so for us.
-// fn cors_allow<SF, Serv>(req: ServiceRequest, serv: &mut SF::Service) 
-// where SF: ServiceFactory,
-//       Serv: SF::Service
-// {
-//     let mut fut = serv.call(req);
-// }
-fn cors_ok_headers() -> DefaultHeaders {
-    DefaultHeaders::new()
-    .header("Access-Control-Allow-Origin", "*")
-    .header("Access-Control-Expose-Headers", "*")
-
-    // Number of seconds a browser can cache the cors allows.
-    // https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Access-Control-Max-Age
-    // FF caps this at 24 hours, and is the most permissive there, so that's what we'll use.
-    // Does this mean that my Cache-Control max-age is truncated to this value? That would be sad.
-    .header("Access-Control-Max-Age", "86400")
+    if let Some(snapshot) = snapshot {
+        snapshot.save((Arc::new(list.clone()), etag.clone()));
+    }
+    item_list_etag_response(&req, &list, first_page, etag)
 }
 
-// Before browsers will post data to a server, they make a CORS OPTIONS request to see if that's OK.
-// This responds to that request to let the client know this request is allowed.
-async fn cors_preflight_allow() -> HttpResponse {
-    HttpResponse::NoContent()
-        .header("Access-Control-Allow-Methods", "OPTIONS, GET, PUT")
-        .body("")
+#[derive(Deserialize)]
+pub(crate) struct SearchParams {
+    /// Words to search for.
+    #[serde(default)]
+    q: String,
 }
 
-async fn feed_item_list(
+/// Posts that contain all of the words in `q`, newest first.
+/// `/search`
+async fn view_search(
     data: Data<AppData>,
-    Path((user_id,)): Path<(UserID,)>,
     Query(pagination): Query<Pagination>,
-) -> Result<HttpResponse, Error> {
+    Query(search): Query<SearchParams>,
+) -> Result<impl Responder, Error> {
     let mut paginator = Paginator::new(
         pagination,
-        |row: ItemDisplayRow| -> Result<ItemListEntry,failure::Error> {
+        |row: ItemDisplayRow| -> Result<IndexPageItem,failure::Error> {
             let mut item = Item::new();
             item.merge_from_bytes(&row.item.item_bytes)?;
-            Ok(item_to_entry(&item, &row.item.user, &row.item.signature))
-        }, 
-        |_: &ItemListEntry| { true } // include all items
+            Ok(IndexPageItem{row, item, replies: None})
+        },
+        |_: &IndexPageItem| true,
     );
-    // We're only holding ItemListEntries in memory, so we can up this limit and
-    // save some round trips.
-    paginator.max_items = 1000;
 
+    let query = search.q.trim();
+    let display_message = if query.is_empty() {
+        None
+    } else {
+        let backend = data.backend_factory.open().compat()?;
+        backend.search(query, paginator.before(), &mut paginator.callback()).compat()?;
+        if paginator.items.is_empty() && paginator.params.before.is_none() {
+            Some("No posts found.".into())
+        } else {
+            paginator.message()
+        }
+    };
+
+    let mut nav = vec![
+        Nav::Text("Search".into()),
+        Nav::Link{
+            text: "Home".into(),
+            href: "/".into(),
+        },
+    ];
+    paginator.more_items_link("/search").into_iter().for_each(|href| {
+        let q = percent_encoding::utf8_percent_encode(query, percent_encoding::NON_ALPHANUMERIC);
+        nav.push(Nav::Link{href: format!("{}&q={}", href, q), text: "More".into()})
+    });
+
+    Ok(IndexPage {
+        nav,
+        display_message,
+        items: paginator.items,
+        show_authors: true,
+        search: Some(query.to_string()),
+    })
+}
+
+/// `/search/proto3`
+async fn search_item_list(
+    data: Data<AppData>,
+    Query(pagination): Query<Pagination>,
+    Query(search): Query<SearchParams>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let mut paginator = Paginator::new(
+        pagination,
+        |row: ItemDisplayRow| -> Result<ItemListEntry,failure::Error> {
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item.item_bytes)?;
+            Ok(item_to_entry(&item, &row.item.user, &row.item.signature))
+        },
+        |_: &ItemListEntry| true,
+    );
+
+    let backend = data.backend_factory.open().compat()?;
+    backend.search(&search.q, paginator.before(), &mut paginator.callback()).compat()?;
+
+    let first_page = paginator.params.before.is_none();
+    let mut list = ItemList::new();
+    list.no_more_items = !paginator.has_more;
+    list.items = protobuf::RepeatedField::from(paginator.items);
+    item_list_response(&req, &list, first_page)
+}
+
+/// How many items at the top of a list we suggest that clients prefetch.
+const PREFETCH_ITEMS: usize = 10;
+
+/// Respond with an ItemList.
+///
+/// Since entries are identified by their (unique) signatures, a digest of them
+/// makes a good weak ETag for the list. On the first page, we also send
+/// `Link: rel=prefetch` hints for the first few items, which clients are likely
+/// to fetch next.
+fn item_list_response(req: &HttpRequest, list: &ItemList, first_page: bool) -> Result<HttpResponse, Error> {
+    let mut hash = sha256::State::new();
+    for entry in list.get_items() {
+        hash.update(entry.get_signature().get_bytes());
+    }
+    let end: &[u8] = if list.no_more_items { b"end" } else { b"more" };
+    hash.update(end);
+    let digest = hash.finalize();
+    let etag = format!("W/\"{}\"", bs58::encode(digest.as_ref()).into_string());
+    item_list_etag_response(req, list, first_page, etag)
+}
+
+/// Like item_list_response, but with an ETag the caller already computed.
+fn item_list_etag_response(req: &HttpRequest, list: &ItemList, first_page: bool, etag: String) -> Result<HttpResponse, Error> {
+    if if_none_match(req, &etag) {
+        return Ok(not_modified(etag));
+    }
+
+    let mut response = proto_ok();
+    response.header("ETag", etag);
+    if first_page && !list.get_items().is_empty() {
+        let links: Vec<String> = list.get_items().iter()
+            .take(PREFETCH_ITEMS)
+            .map(|entry| format!("<{}>; rel=prefetch", entry_item_path(entry)))
+            .collect();
+        response.header("Link", links.join(", "));
+    }
+
+    Ok(response.body(list.write_to_bytes()?))
+}
+
+/// Tells the client where a saved item is. (See: ItemSaved in feoblog.proto)
+/// Clients that accept JSON or proto3 get its URLs in the body. Others get
+/// `response`'s message, as before.
+fn item_saved(req: &HttpRequest, response: HttpResponse, user: &UserID, signature: &Signature) -> Result<HttpResponse, Error> {
+    let item_url = format!("{}/u/{}/i/{}", base_url(req), user.to_base58(), signature.to_base58());
+    let mut saved = crate::protos::ItemSaved::new();
+    saved.html_url = format!("{}/", item_url);
+    saved.proto3_url = format!("{}/proto3", item_url);
+
+    let accept = req.headers().get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    let mut response = if accept.contains("application/protobuf3") {
+        HttpResponse::build(response.status())
+            .content_type("application/protobuf3")
+            .body(saved.write_to_bytes()?)
+    } else if accept.contains("application/json") {
+        let body = serde_json::json!({
+            "htmlUrl": saved.html_url,
+            "proto3Url": saved.proto3_url,
+        });
+        HttpResponse::build(response.status())
+            .content_type("application/json")
+            .body(body.to_string())
+    } else {
+        response
+    };
+
+    let headers = response.headers_mut();
+    if let Ok(location) = header::HeaderValue::from_str(&saved.html_url) {
+        headers.insert(header::LOCATION, location);
+    }
+    headers.append(header::VARY, header::HeaderValue::from_static("Accept"));
+    Ok(response)
+}
+
+/// The path to an entry's `/u/{userID}/i/{signature}/proto3`.
+fn entry_item_path(entry: &ItemListEntry) -> String {
+    format!(
+        "/u/{}/i/{}/proto3",
+        bs58::encode(entry.get_user_id().get_bytes()).into_string(),
+        bs58::encode(entry.get_signature().get_bytes()).into_string(),
+    )
+}
+
+/// A weak ETag for a list of `users`' items, built from their summaries.
+///
+/// A user's summary changes whenever one of their items is added or removed,
+/// so, unlike the ETag from item_list_response, we can check this one before
+/// scanning for items. (ETags are per-URL, so pagination needn't be included.)
+fn summary_etag(backend: &dyn Backend, users: &[UserID]) -> Result<String, failure::Error> {
+    let mut hash = sha256::State::new();
+    for user in users {
+        let summary = backend.user_summary(user)?;
+        hash.update(user.bytes());
+        hash.update(&summary.item_count.to_be_bytes());
+        hash.update(&summary.digest);
+    }
+    let digest = hash.finalize();
+    Ok(format!("W/\"s{}\"", bs58::encode(digest.as_ref()).into_string()))
+}
+
+fn not_modified(etag: String) -> HttpResponse {
+    HttpResponse::NotModified()
+        .header("ETag", etag)
+        .finish()
+}
+
+/// True if the request's If-None-Match header includes `etag`.
+/// (Compared weakly, as RFC 7232 requires for If-None-Match.)
+fn if_none_match(req: &HttpRequest, etag: &str) -> bool {
+    let strip_weak = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    let etag = strip_weak(etag);
+    req.headers().get_all("If-None-Match")
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|tag| tag.trim() == "*" || strip_weak(tag) == etag)
+}
+
+// Start building a response w/ proto3 binary data.
+fn proto_ok() -> HttpResponseBuilder {
+    let mut builder = HttpResponse::Ok();
+    builder.content_type("application/protobuf3");
+    builder
+}
+
+async fn feed_item_list(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    Query(pagination): Query<Pagination>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let mut paginator = Paginator::new(
+        pagination,
+        |row: ItemDisplayRow| -> Result<ItemListEntry,failure::Error> {
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item.item_bytes)?;
+            Ok(item_to_entry(&item, &row.item.user, &row.item.signature))
+        }, 
+        |_: &ItemListEntry| { true } // include all items
+    );
+    // We're only holding ItemListEntries in memory, so we can up this limit and
+    // save some round trips.
+    paginator.max_items = 1000;
+
+    if paginator.params.before.is_none() {
+        data.fill_feed(&user_id).await;
+    }
     let backend = data.backend_factory.open().compat()?;
 
+    // Anyone may view anyone's feed. If they're signed in, their mutes apply.
+    let viewer = match viewer::signed_in(&req, backend.as_ref(), &data.hosts) {
+        Ok(viewer) => viewer,
+        Err(err) => return Ok(HttpResponse::Unauthorized().content_type(PLAINTEXT).body(err.to_string())),
+    };
+
+    let users = feed_users(backend.as_ref(), &user_id, viewer.as_ref()).compat()?;
+    let etag = summary_etag(backend.as_ref(), &users).compat()?;
+    if if_none_match(&req, &etag) {
+        return Ok(not_modified(etag));
+    }
+
     // Note: user_feed_items is doing a little bit of extra work to fetch
     // display_name, which we then throw away. We *could* make a more efficient
     // version that we use for just this case, but eh, reuse is nice.
-    backend.user_feed_items(&user_id, paginator.before(), &mut paginator.callback()).compat()?;
+    backend.user_feed_items(&user_id, viewer.as_ref(), paginator.before(), &mut paginator.callback()).compat()?;
 
+    let first_page = paginator.params.before.is_none();
     let mut list = ItemList::new();
     list.no_more_items = !paginator.has_more;
     list.items = protobuf::RepeatedField::from(paginator.items);
-    Ok(
-        proto_ok()
-        .body(list.write_to_bytes()?)
-    )
+    let mut response = item_list_etag_response(&req, &list, first_page, etag)?;
+    response.headers_mut().insert(header::VARY, header::HeaderValue::from_static(viewer::HEADER));
+    Ok(response)
+}
+
+/// The users whose items can appear in `user`'s feed, as `viewer` sees it:
+/// `user`, and those they follow, minus any that `viewer` has muted.
+fn feed_users(backend: &dyn Backend, user: &UserID, viewer: Option<&UserID>) -> Result<Vec<UserID>, failure::Error> {
+    let mut muted = HashSet::new();
+    if let Some(viewer) = viewer {
+        backend.muted_users(viewer, &mut |muted_user| {
+            muted.insert(muted_user.bytes().to_vec());
+            Ok(true)
+        })?;
+    }
+
+    // The user's own summary covers changes to their profile, and so to their follows.
+    let mut users = vec![user.clone()];
+    if let Some(row) = backend.user_profile(user)? {
+        let mut item = Item::new();
+        item.merge_from_bytes(&row.item_bytes)?;
+        for follow in item.get_profile().get_follows() {
+            if !muted.contains(follow.get_user().get_bytes()) {
+                users.push(UserID::from_vec(follow.get_user().get_bytes().to_vec())?);
+            }
+        }
+    }
+    Ok(users)
 }
 
 async fn user_item_list(
     data: Data<AppData>,
     Path((user_id,)): Path<(UserID,)>,
     Query(pagination): Query<Pagination>,
+    req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
     let mut paginator = Paginator::new(
         pagination,
@@ -465,16 +1508,55 @@ async fn user_item_list(
 
     let backend = data.backend_factory.open().compat()?;
 
+    let etag = summary_etag(backend.as_ref(), &[user_id.clone()]).compat()?;
+    if if_none_match(&req, &etag) {
+        return Ok(not_modified(etag));
+    }
+
     // Note: user_feed_items is doing a little bit of extra work to fetch
     // display_name, which we then throw away. We *could* make a more efficient
     // version that we use for just this case, but eh, reuse is nice.
     backend.user_items(&user_id, paginator.before(), &mut paginator.callback()).compat()?;
 
+    let first_page = paginator.params.before.is_none();
+    let mut list = ItemList::new();
+    list.no_more_items = !paginator.has_more;
+    list.items = protobuf::RepeatedField::from(paginator.items);
+    item_list_etag_response(&req, &list, first_page, etag)
+}
+
+/// A user's followers-only items, for a viewer who may see them.
+///
+/// `/u/{userID}/followers-only/proto3`
+async fn followers_only_item_list(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    Query(pagination): Query<Pagination>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    let viewer = match viewer::viewer(&req, backend.as_ref(), &data.hosts, &user_id) {
+        Ok(Some(viewer)) => viewer,
+        Ok(None) => return Ok(messages::response(&req, StatusCode::UNAUTHORIZED, Message::ViewerRequired)),
+        Err(err) => return Ok(HttpResponse::Unauthorized().content_type(PLAINTEXT).body(err.to_string())),
+    };
+
+    let mut paginator = Paginator::new(
+        pagination,
+        |row: ItemRow| -> Result<ItemListEntry,failure::Error> {
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item_bytes)?;
+            Ok(item_to_entry(&item, &row.user, &row.signature))
+        },
+        |_| { true }
+    );
+    backend.followers_only_items(&user_id, &viewer, paginator.before(), &mut paginator.callback()).compat()?;
+
     let mut list = ItemList::new();
     list.no_more_items = !paginator.has_more;
     list.items = protobuf::RepeatedField::from(paginator.items);
     Ok(
-        proto_ok()
+        policy::Cache::PrivateMutable.apply(&mut proto_ok())
         .body(list.write_to_bytes()?)
     )
 }
@@ -593,13 +1675,14 @@ async fn get_user_feed(
     data: Data<AppData>,
     Path((user_id,)): Path<(UserID,)>,
     Query(pagination): Query<Pagination>,
+    req: HttpRequest,
 ) -> Result<impl Responder, Error> {
     let mut paginator = Paginator::new(
         pagination,
         |row: ItemDisplayRow| -> Result<IndexPageItem,failure::Error> {
             let mut item = Item::new();
             item.merge_from_bytes(&row.item.item_bytes)?;
-            Ok(IndexPageItem{row, item})
+            Ok(IndexPageItem{row, item, replies: None})
         }, 
         |page_item: &IndexPageItem| { 
             display_by_default(&page_item.item)
@@ -609,8 +1692,12 @@ async fn get_user_feed(
     let max_time = paginator.params.before
         .map(|t| Timestamp{ unix_utc_ms: t})
         .unwrap_or_else(|| Timestamp::now());
+    if paginator.params.before.is_none() {
+        data.fill_feed(&user_id).await;
+    }
     let backend = data.backend_factory.open().compat()?;
-    backend.user_feed_items(&user_id, max_time, &mut paginator.callback()).compat()?;
+    let viewer = viewer::signed_in(&req, backend.as_ref(), &data.hosts).compat()?;
+    backend.user_feed_items(&user_id, viewer.as_ref(), max_time, &mut paginator.callback()).compat()?;
 
     let mut nav = vec![
         Nav::Text("User Feed".into()),
@@ -625,9 +1712,107 @@ async fn get_user_feed(
         display_message: paginator.message(),
         items: paginator.items,
         show_authors: true,
+        search: None,
     })
 }
 
+/// Should a saved feed that shows `item_types` include `item`?
+fn saved_feed_shows(feed: &backend::SavedFeed, item: &Item) -> bool {
+    let shown = if feed.item_types.is_empty() {
+        display_by_default(item)
+    } else {
+        feed.item_types.contains(&item_type(item))
+    };
+    shown && (feed.tags.is_empty() || crate::tags::has_any(item, &feed.tags))
+}
+
+/// A user's saved (custom) feed.
+/// `/u/{userID}/feeds/{name}/`
+async fn get_saved_feed(
+    data: Data<AppData>,
+    Path((user_id, name)): Path<(UserID, String)>,
+    Query(pagination): Query<Pagination>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    let feed = match backend.saved_feed(&user_id, &name).compat()? {
+        Some(feed) => feed,
+        None => {
+            return file_not_found(&req, Message::NoSuchFeed);
+        }
+    };
+
+    let mut paginator = Paginator::new(
+        pagination,
+        |row: ItemDisplayRow| -> Result<IndexPageItem,failure::Error> {
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item.item_bytes)?;
+            Ok(IndexPageItem{row, item, replies: None})
+        }, 
+        |page_item: &IndexPageItem| { 
+            saved_feed_shows(&feed, &page_item.item)
+        }
+    );
+
+    let max_time = paginator.params.before
+        .map(|t| Timestamp{ unix_utc_ms: t})
+        .unwrap_or_else(|| Timestamp::now());
+    backend.saved_feed_items(&user_id, &name, max_time, &mut paginator.callback()).compat()?;
+
+    let mut nav = vec![
+        Nav::Text(feed.name.clone()),
+    ];
+    paginator.more_items_link("").into_iter().for_each(|href| {
+        let href = format!("/u/{}/feeds/{}/{}", user_id.to_base58(), name, href);
+        nav.push(Nav::Link{href, text: "More".into()})
+    });
+
+    let page = IndexPage {
+        nav,
+        display_message: paginator.message(),
+        items: paginator.items,
+        show_authors: true,
+        search: None,
+    };
+    Ok(page.respond_to(&req).await?)
+}
+
+/// `/u/{userID}/feeds/{name}/proto3`
+async fn saved_feed_item_list(
+    data: Data<AppData>,
+    Path((user_id, name)): Path<(UserID, String)>,
+    Query(pagination): Query<Pagination>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    let feed = match backend.saved_feed(&user_id, &name).compat()? {
+        Some(feed) => feed,
+        None => return Ok(messages::response(&req, StatusCode::NOT_FOUND, Message::NoSuchFeed)),
+    };
+
+    let mut paginator = Paginator::new(
+        pagination,
+        |row: ItemDisplayRow| -> Result<(bool, ItemListEntry),failure::Error> {
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item.item_bytes)?;
+            // Tags are in the item's text, which entries don't keep:
+            let shown = saved_feed_shows(&feed, &item);
+            Ok((shown, item_to_entry(&item, &row.item.user, &row.item.signature)))
+        }, 
+        |(shown, _): &(bool, ItemListEntry)| *shown
+    );
+    // We're only holding ItemListEntries in memory, so we can up this limit and save some round trips.
+    paginator.max_items = 1000;
+
+    backend.saved_feed_items(&user_id, &name, paginator.before(), &mut paginator.callback()).compat()?;
+
+    let first_page = paginator.params.before.is_none();
+    let mut list = ItemList::new();
+    list.no_more_items = !paginator.has_more;
+    list.items = paginator.items.into_iter().map(|(_, entry)| entry).collect();
+    item_list_response(&req, &list, first_page)
+}
+
 /// Display a single user's posts/etc.
 /// `/u/{userID}/`
 async fn get_user_items(
@@ -649,7 +1834,8 @@ async fn get_user_items(
                     // We don't display the user's name on their own page.
                     display_name: None,
                 },
-                item 
+                item,
+                replies: None,
             });
         }
 
@@ -662,6 +1848,9 @@ async fn get_user_items(
     let (user,) = path.into_inner();
     let backend = data.backend_factory.open().compat()?;
     backend.user_items(&user, max_time, &mut collect_items).compat()?;
+    if data.reply_counts {
+        add_reply_counts(backend.as_ref(), &mut items).compat()?;
+    }
 
     
     let mut nav = vec![];
@@ -684,6 +1873,10 @@ async fn get_user_items(
             text: "Feed".into(),
             href: format!("/u/{}/feed/", user.to_base58()),
         },
+        Nav::Link{
+            text: "Gallery".into(),
+            href: format!("/u/{}/gallery/", user.to_base58()),
+        },
         Nav::Link{
             text: "Home".into(),
             href: "/".into()
@@ -695,10 +1888,81 @@ async fn get_user_items(
         items,
         show_authors: false,
         display_message: None,
+        search: None,
+    })
+}
+
+/// Images attached to a user's items, shown in a grid.
+/// `/u/{user_id}/gallery/`
+async fn get_user_gallery(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    Query(pagination): Query<Pagination>,
+) -> Result<impl Responder, Error> {
+    let mut paginator = Paginator::new(
+        pagination,
+        |row: ItemRow| -> Result<IndexPageItem,failure::Error> {
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item_bytes)?;
+            Ok(IndexPageItem{
+                row: ItemDisplayRow{ item: row, display_name: None },
+                item,
+                replies: None,
+            })
+        },
+        |page_item: &IndexPageItem| {
+            page_item.item.get_attachments().get_file().iter().any(|f| is_image(f.get_name()))
+        }
+    );
+    // Pages are a grid, not a column. Show a few more:
+    paginator.max_items = 30;
+
+    let backend = data.backend_factory.open().compat()?;
+    backend.user_items(&user_id, paginator.before(), &mut paginator.callback()).compat()?;
+
+    let mut nav = vec![];
+    if let Some(row) = backend.user_profile(&user_id).compat()? {
+        let mut item = Item::new();
+        item.merge_from_bytes(&row.item_bytes)?;
+        nav.push(Nav::Text(item.get_profile().display_name.clone()));
+    }
+    nav.push(Nav::Link{
+        text: "Posts".into(),
+        href: format!("/u/{}/", user_id.to_base58()),
+    });
+    if let Some(href) = paginator.more_items_link("") {
+        nav.push(Nav::Link{text: "More".into(), href});
+    }
+
+    let mut images = vec![];
+    for page_item in &paginator.items {
+        let files = page_item.item.get_attachments().get_file().iter()
+            .filter(|f| is_image(f.get_name()));
+        for file in files {
+            images.push(GalleryImage{
+                user_id: user_id.clone(),
+                signature: page_item.row.item.signature.clone(),
+                file_name: file.get_name().to_string(),
+                title: page_item.item.get_post().get_title().to_string(),
+            });
+        }
+    }
+
+    Ok(GalleryPage{
+        nav,
+        display_message: paginator.message(),
+        images,
     })
 }
 
-const MAX_ITEM_SIZE: usize = 1024 * 32; 
+/// Should we display this attached file as an image?
+fn is_image(file_name: &str) -> bool {
+    mime_guess::from_path(file_name).first()
+        .map(|mime| mime.type_() == "image")
+        .unwrap_or(false)
+}
+
+pub(crate) const MAX_ITEM_SIZE: usize = 1024 * 32; 
 const PLAINTEXT: &'static str = "text/plain; charset=utf-8";
 
 /// Accepts a proto3 Item
@@ -711,94 +1975,199 @@ async fn put_item(
     data: Data<AppData>,
     path: Path<(String, String,)>,
     req: HttpRequest,
-    mut body: Payload,
+    body: Payload,
 ) -> Result<HttpResponse, Error> 
 {
-    let (user_path, sig_path) = path.into_inner();
-    let user = UserID::from_base58(user_path.as_str()).context("decoding user ID").compat()?;
-    let signature = Signature::from_base58(sig_path.as_str()).context("decoding signature").compat()?;
+    let response = receive_item(data.clone(), path, req, body).await?;
+    count_rejection(&data, &response);
+    Ok(response)
+}
 
-    let length = match req.headers().get("content-length") {
-        Some(length) => length,
-        None => {
-            return Ok(
-                HttpResponse::LengthRequired()
-                .content_type(PLAINTEXT)
-                .body("Must include length header.".to_string())
-                // ... so that we can reject things that are too large outright.
-            );
-        }
-    };
+/// Count refused uploads in the server's daily stats. (See: `feoblog stats`)
+fn count_rejection(data: &AppData, response: &HttpResponse) {
+    let status = response.status();
+    // 503s (maintenance, or too many uploads at once) are only "try again later".
+    let rejected = status.is_client_error() || status == StatusCode::INSUFFICIENT_STORAGE;
+    if !rejected || data.maintenance.is_active() {
+        return;
+    }
+    let recorded = data.backend_factory.open()
+        .and_then(|backend| backend.record_rejection(Timestamp::now()));
+    if let Err(err) = recorded {
+        log::warn!("Error counting a rejected upload: {}", err);
+    }
+}
+
+async fn receive_item(
+    data: Data<AppData>,
+    path: Path<(String, String,)>,
+    req: HttpRequest,
+    mut body: Payload,
+) -> Result<HttpResponse, Error> 
+{
+    let (user_path, sig_path) = path.into_inner();
+    let user = match UserID::from_base58(user_path.as_str()) {
+        Ok(user) => user,
+        Err(_) => return Ok(messages::response(&req, StatusCode::BAD_REQUEST, Message::InvalidUserID)),
+    };
+    let signature = match Signature::from_base58(sig_path.as_str()) {
+        Ok(signature) => signature,
+        Err(_) => return Ok(messages::response(&req, StatusCode::BAD_REQUEST, Message::InvalidSignature)),
+    };
+
+    let length = match req.headers().get("content-length") {
+        Some(length) => length,
+        None => {
+            // ... so that we can reject things that are too large outright.
+            return Ok(messages::response(&req, StatusCode::LENGTH_REQUIRED, Message::LengthRequired));
+        }
+    };
 
     let length: usize = match length.to_str()?.parse() {
         Ok(length) => length,
         Err(_) => {
-            return Ok(
-                HttpResponse::BadRequest()
-                .content_type(PLAINTEXT)
-                .body("Error parsing Length header.".to_string())
-            );
+            return Ok(messages::response(&req, StatusCode::BAD_REQUEST, Message::InvalidLength));
         },
     };
 
     if length > MAX_ITEM_SIZE {
-        return Ok(
-            HttpResponse::PayloadTooLarge()
-            .content_type(PLAINTEXT)
-            .body(format!("Item must be <= {} bytes", MAX_ITEM_SIZE))
-        );
+        return Ok(messages::response_with(&req, StatusCode::PAYLOAD_TOO_LARGE, Message::ItemTooLarge, MAX_ITEM_SIZE));
     }
 
-    let mut backend = data.backend_factory.open().compat()?;
+    // During maintenance, we may journal the item without touching the backend:
+    let backend = match data.maintenance.retry_after() {
+        Some(retry_after) if !data.maintenance.can_journal(length) => {
+            return Ok(maintenance::unavailable(retry_after));
+        },
+        Some(retry_after) => Err(retry_after),
+        None => Ok(data.backend_factory.open().compat()?),
+    };
 
-    // If the content already exists, do nothing.
-    if backend.user_item_exists(&user, &signature).compat()? {
-        return Ok(
-            HttpResponse::Accepted()
-            .content_type(PLAINTEXT)
-            .body("Item already exists")
-        );
-    }
+    // Unknown users may post with enough proof-of-work:
+    let mut proven = false;
+    if let Ok(backend) = &backend {
+        // If the content already exists, do nothing.
+        if backend.user_item_exists(&user, &signature).compat()? {
+            let response = messages::response(&req, StatusCode::ACCEPTED, Message::ItemExists);
+            return item_saved(&req, response, &user, &signature);
+        }
+        if backend.user_item_deleted(&user, &signature).compat()? {
+            return Ok(messages::response(&req, StatusCode::GONE, Message::ItemDeleted));
+        }
 
-    if !backend.user_known(&user).compat()? {
-        return Ok(
-            HttpResponse::Forbidden()
-            .content_type(PLAINTEXT)
-            .body("Unknown user ID")
-        )
+        if !backend.user_known(&user).compat()? {
+            if data.pow_difficulty == 0 {
+                return Ok(messages::response(&req, StatusCode::FORBIDDEN, Message::UnknownUser));
+            }
+            if !pow::is_valid(&req, &signature, data.pow_difficulty) {
+                return Ok(messages::response_with(&req, StatusCode::FORBIDDEN, Message::ProofOfWorkRequired, data.pow_difficulty));
+            }
+            proven = true;
+        }
     }
     
-    let mut bytes: Vec<u8> = Vec::with_capacity(length);
-    while let Some(chunk) = body.next().await {
-        let chunk = chunk.context("Error parsing chunk").compat()?;
-        bytes.extend_from_slice(&chunk);
-    }
+    let _permit = match data.uploads.try_acquire() {
+        Some(permit) => permit,
+        None => {
+            let mut response = messages::response(&req, StatusCode::SERVICE_UNAVAILABLE, Message::TooManyUploads);
+            response.headers_mut().insert(header::RETRY_AFTER, header::HeaderValue::from_static("5"));
+            return Ok(response);
+        }
+    };
+
+    let read_body = async {
+        let mut bytes: Vec<u8> = Vec::with_capacity(length);
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.context("Error parsing chunk").compat()?;
+            bytes.extend_from_slice(&chunk);
+            if bytes.len() > length {
+                Err(format_err!("Body is longer than Content-Length").compat())?;
+            }
+        }
+        Ok::<_, Error>(bytes)
+    };
+
+    let bytes = match data.uploads.read_deadline(length) {
+        None => read_body.await?,
+        Some(deadline) => match actix_web::rt::time::timeout(deadline, read_body).await {
+            Ok(bytes) => bytes?,
+            Err(_) => {
+                return Ok(messages::response(&req, StatusCode::REQUEST_TIMEOUT, Message::UploadTooSlow));
+            }
+        },
+    };
 
     if !signature.is_valid(&user, &bytes) {
-        Err(format_err!("Invalid signature").compat())?;
+        return Ok(messages::response(&req, StatusCode::BAD_REQUEST, Message::InvalidSignature));
     }
 
     let mut item: Item = Item::new();
     item.merge_from_bytes(&bytes)?;
     item.validate()?;
 
+    let item_type = item_type(&item);
+    if !data.accepted_item_types.contains(&item_type) {
+        return Ok(messages::response_with(
+            &req, StatusCode::UNPROCESSABLE_ENTITY, Message::ItemTypeRejected, format!("{:?}", item_type),
+        ));
+    }
+
     if item.timestamp_ms_utc > Timestamp::now().unix_utc_ms {
-        return Ok(
-            HttpResponse::BadRequest()
-            .content_type(PLAINTEXT)
-            .body("The Item's timestamp is in the future")
-        )
+        return Ok(messages::response(&req, StatusCode::BAD_REQUEST, Message::FutureTimestamp));
+    }
+    if item.expires_ms_utc != 0 && item.expires_ms_utc <= Timestamp::now().unix_utc_ms {
+        // We'd only stop serving it, and it'd be re-uploaded by the next sync:
+        return Ok(messages::response(&req, StatusCode::BAD_REQUEST, Message::ItemExpired));
+    }
+
+    if let (true, Ok(backend)) = (item.has_delete(), &backend) {
+        // The backend would refuse it too, but with a less helpful error:
+        let target = Signature::from_vec(item.get_delete().get_signature().get_bytes().into()).compat()?;
+        if let Some(row) = backend.user_item(&user, &target).compat()? {
+            let mut target = Item::new();
+            target.merge_from_bytes(&row.item_bytes)?;
+            if target.has_profile() || target.has_delete() {
+                return Ok(messages::response(&req, StatusCode::UNPROCESSABLE_ENTITY, Message::NotDeletable));
+            }
+        }
+    }
+
+    let mut backend = match backend {
+        Ok(backend) => backend,
+        Err(retry_after) if req.headers().contains_key(pow::HEADER) => {
+            // Probably an unknown user. Replaying the journal would drop their item, so have them retry.
+            return Ok(maintenance::unavailable(retry_after));
+        },
+        Err(retry_after) => {
+            // Checked as far as we can. The rest happens when the journal is replayed.
+            if !data.maintenance.append(&user, &signature, &bytes).compat()? {
+                return Ok(maintenance::unavailable(retry_after));
+            }
+            return Ok(messages::response(&req, StatusCode::ACCEPTED, Message::Journaled));
+        },
+    };
+
+    if let Some(storage) = &data.storage {
+        let declared: u64 = item.get_attachments().get_file().iter().map(|file| file.size).sum();
+        if !storage.allows(backend.as_ref(), &user, bytes.len() as u64 + declared).compat()? {
+            return Ok(messages::response(&req, StatusCode::INSUFFICIENT_STORAGE, Message::StorageFull));
+        }
     }
 
-    if let Some(deny_reason) = backend.quota_check_item(&user, &bytes, &item).compat()? {
+    let deny_reason = backend.quota_check_item(&user, &bytes, &item).compat()?;
+    // The work stands in for being known:
+    let deny_reason = deny_reason.filter(|reason| !(proven && matches!(reason, QuotaDenyReason::UnknownUser)));
+    if let Some(deny_reason) = deny_reason {
         return Ok(
             HttpResponse::InsufficientStorage()
+            .content_type(PLAINTEXT)
             .body(format!("{}", deny_reason))
         )
     }
 
-    let message = format!("OK. Received {} bytes.", bytes.len());
-    
+    let response = messages::response_with(&req, StatusCode::CREATED, Message::ItemSaved, bytes.len());
+    let response = item_saved(&req, response, &user, &signature)?;
+
     let row = ItemRow{
         user: user,
         signature: signature,
@@ -808,10 +2177,40 @@ async fn put_item(
     };
 
     backend.save_user_item(&row, &item).context("Error saving user item").compat()?;
+    if proven && data.quarantine_pow_items {
+        // Not served, pushed, or delivered anywhere until it's approved:
+        backend.quarantine_user_item(&row.user, &row.signature).compat()?;
+        return Ok(messages::response(&req, StatusCode::ACCEPTED, Message::ItemQuarantined));
+    }
+    data.homepage.invalidate();
+    reports::audit_item(backend.as_ref(), &row, &item);
 
-    let response = HttpResponse::Created()
-        .content_type(PLAINTEXT)
-        .body(message);
+    if viewer::is_followers_only(&item) {
+        // Not for peers, or the rest of the web:
+        return Ok(response);
+    }
+
+    if let Err(err) = backend.queue_push(&row.user, &row.signature) {
+        // The item is saved. Peers can still sync it later.
+        log::warn!("Error queueing item for peers: {}", err);
+    }
+
+    if data.activitypub.is_some() {
+        if let Err(err) = activitypub::deliver_post(backend.as_ref(), &base_url(&req), &row, &item) {
+            log::warn!("Error queueing item for ActivityPub followers: {}", err);
+        }
+    }
+    if let Err(err) = nostr::publish_post(data.nostr_relays.clone(), backend.as_ref(), &base_url(&req), &row, &item) {
+        log::warn!("Error publishing item to Nostr: {}", err);
+    }
+    if let Err(err) = crosspost::queue_post(backend.as_ref(), &base_url(&req), &row, &item) {
+        log::warn!("Error queueing cross-posts: {}", err);
+    }
+    if data.ipfs {
+        if let Err(err) = ipfs::queue(backend.as_ref(), &row.user, &row.signature, "") {
+            log::warn!("Error queueing item for IPFS: {}", err);
+        }
+    }
 
     Ok(response)
 }
@@ -829,34 +2228,184 @@ async fn show_item(
     let row = match row {
         Some(row) => row,
         None => { 
-            // TODO: We could display a nicer error page here, showing where
-            // the user might find this item on other servers. Maybe I'll leave that
-            // for the in-browser client.
+            if backend.user_item_deleted(&user_id, &signature).compat()? {
+                return item_gone(&req);
+            }
 
-            return Ok(
-                file_not_found("No such item").await
-                .respond_to(&req).await?
-            );
+            // Show where the reader might find the item on other servers:
+            drop(backend);
+            let mirrors = data.mirrors.find(data.backend_factory.as_ref(), &base_url(&req), &user_id, &signature).await;
+            let mirrors = mirrors.unwrap_or_else(|err| {
+                log::warn!("Error finding mirrors of {}: {}", signature.to_base58(), err);
+                vec![]
+            });
+            return error_page(&req, StatusCode::NOT_FOUND, Message::NotFoundTitle, Message::NoSuchItem, mirrors);
         }
     };
 
     let mut item = Item::new();
     item.merge_from_bytes(row.item_bytes.as_slice())?;
+    let item_bytes = row.item_bytes.len() as u64;
+
+    let mut attachments = vec![];
+    for file in item.get_attachments().get_file() {
+        attachments.push(AttachmentView{
+            name: file.get_name().to_string(),
+            size: file.get_size(),
+            uploaded: backend.attachment_exists(&user_id, &signature, file.get_name()).compat()?,
+        });
+    }
+
+    if data.count_views && !data.maintenance.is_active() {
+        if let Err(err) = backend.record_item_view(&user_id, &signature, Timestamp::now()) {
+            // Not worth failing the page view over:
+            log::warn!("Error recording item view: {}", err);
+        }
+    }
 
     let row = backend.user_profile(&user_id).compat()?;
-    let display_name = {
+    let profile = {
         let mut item = Item::new();
         if let Some(row) = row {
             item.merge_from_bytes(row.item_bytes.as_slice())?;
         }
         item
-    }.get_profile().display_name.clone();
+    };
+    let display_name = profile.get_profile().display_name.clone();
+    let default_license = profile.get_profile().get_license().to_string();
     
     use crate::protos::Item_oneof_item_type as ItemType;
     match item.item_type {
         None => Ok(HttpResponse::InternalServerError().body("No known item type provided.")),
         Some(ItemType::profile(p)) => Ok(HttpResponse::Ok().body("Profile update.")),
-        Some(ItemType::post(p)) => {
+        Some(ItemType::vote(v)) => {
+            let poll = v.get_poll();
+            let href = format!(
+                "/u/{}/i/{}/",
+                bs58::encode(poll.get_user_id().get_bytes()).into_string(),
+                bs58::encode(poll.get_signature().get_bytes()).into_string(),
+            );
+            Ok(HttpResponse::Ok().body(format!("Vote in poll: {}", href)))
+        },
+        Some(ItemType::delete(delete)) => {
+            let href = format!(
+                "/u/{}/i/{}/",
+                user_id.to_base58(),
+                bs58::encode(delete.get_signature().get_bytes()).into_string(),
+            );
+            Ok(HttpResponse::Ok().body(format!("Deletes: {}", href)))
+        },
+        Some(ItemType::reaction(reaction)) => {
+            let target = reaction.get_item();
+            let href = format!(
+                "/u/{}/i/{}/",
+                bs58::encode(target.get_user_id().get_bytes()).into_string(),
+                bs58::encode(target.get_signature().get_bytes()).into_string(),
+            );
+            let emoji = if reaction.get_emoji().is_empty() { "Like" } else { reaction.get_emoji() };
+            Ok(HttpResponse::Ok().body(format!("{} on: {}", emoji, href)))
+        },
+        Some(ItemType::comment(comment)) => {
+            let parent = comment.get_reply_to();
+            let comments = comments_from_follows(backend.as_ref(), &user_id, &signature, &profile).compat()?;
+            let page = PostPage {
+                nav: vec![
+                    Nav::Text(display_name.clone()),
+                    Nav::Link {
+                        text: "In reply to".into(),
+                        href: format!(
+                            "/u/{}/i/{}/",
+                            bs58::encode(parent.get_user_id().get_bytes()).into_string(),
+                            bs58::encode(parent.get_signature().get_bytes()).into_string(),
+                        ),
+                    },
+                    Nav::Link {
+                        text: "Profile".into(),
+                        href: format!("/u/{}/profile/", user_id.to_base58()),
+                    },
+                    Nav::Link {
+                        text: "Home".into(),
+                        href: "/".into()
+                    }
+                ],
+                user_id,
+                display_name,
+                signature,
+                text: comment.text,
+                title: String::new(),
+                content_warning: String::new(),
+                location: None,
+                timestamp_utc_ms: item.timestamp_ms_utc,
+                utc_offset_minutes: item.utc_offset_minutes,
+                comments,
+                remote_replies: vec![],
+                anonymous_comments: false,
+                item_bytes,
+                attachments,
+                license: default_license,
+            };
+
+            Ok(page.respond_to(&req).await?)
+        },
+        Some(ItemType::poll(poll)) => {
+            let tally = poll_tally(backend.as_ref(), &user_id, &signature, &poll).compat()?;
+            let counts = if tally.closed { Some(tally.counts) } else { None };
+
+            let page = PollPage {
+                nav: vec![
+                    Nav::Text(display_name.clone()),
+                    Nav::Link {
+                        text: "Profile".into(),
+                        href: format!("/u/{}/profile/", user_id.to_base58()),
+                    },
+                    Nav::Link {
+                        text: "Home".into(),
+                        href: "/".into()
+                    }
+                ],
+                user_id,
+                display_name,
+                signature,
+                question: poll.question,
+                options: poll.options.into_vec(),
+                counts,
+                close_ms_utc: poll.close_ms_utc,
+                timestamp_utc_ms: item.timestamp_ms_utc,
+                utc_offset_minutes: item.utc_offset_minutes,
+            };
+
+            Ok(page.respond_to(&req).await?)
+        },
+        Some(ItemType::post(mut p)) => {
+            let location = if p.has_location() {
+                let location = p.take_location();
+                let places = maps::decimal_places(location.precision);
+                Some(PostLocation {
+                    place_name: location.place_name.clone(),
+                    coordinates: format!(
+                        "{:.*}, {:.*}",
+                        places, location.latitude,
+                        places, location.longitude,
+                    ),
+                    has_map: data.map_tiles.is_some(),
+                })
+            } else {
+                None
+            };
+
+            let license = if p.license.is_empty() { default_license } else { p.license.clone() };
+            let comments = comments_from_follows(backend.as_ref(), &user_id, &signature, &profile).compat()?;
+            let mut remote_replies = vec![];
+            backend.activitypub_replies(&user_id, &signature, &mut |reply| {
+                remote_replies.push(RemoteReplyView{
+                    actor: reply.actor,
+                    actor_name: reply.actor_name,
+                    url: reply.url,
+                    text: reply.text,
+                    timestamp_utc_ms: reply.published.unix_utc_ms,
+                });
+                Ok(true)
+            }).compat()?;
             let page = PostPage {
                 nav: vec![
                     Nav::Text(display_name.clone()),
@@ -874,60 +2423,1475 @@ async fn show_item(
                 signature,
                 text: p.body,
                 title: p.title,
+                content_warning: p.content_warning,
+                location,
                 timestamp_utc_ms: item.timestamp_ms_utc,
                 utc_offset_minutes: item.utc_offset_minutes,
+                comments,
+                remote_replies,
+                anonymous_comments: data.anonymous_comments,
+                item_bytes,
+                attachments,
+                license,
             };
 
-            Ok(page.respond_to(&req).await?)
-        },
+            Ok(page.respond_to(&req).await?)
+        },
+    }
+
+
+}
+
+/// Show at most this many comments under an item.
+const MAX_COMMENTS: usize = 200;
+
+/// Replies nested deeper than this aren't shown.
+const MAX_COMMENT_DEPTH: usize = 5;
+
+/// Comments on an item from its author and the people they follow, so that
+/// the author decides whose comments appear alongside their item.
+fn comments_from_follows(
+    backend: &dyn Backend,
+    user_id: &UserID,
+    signature: &Signature,
+    profile: &Item,
+) -> Result<Vec<CommentView>, failure::Error> {
+    let authors = comment_authors(user_id, profile);
+    let mut comments = vec![];
+    collect_comments(backend, user_id, signature, &authors, 0, &mut comments)?;
+    Ok(comments)
+}
+
+/// Whose comments are shown under `user_id`'s items: theirs, and those of the
+/// users their `profile` follows.
+fn comment_authors(user_id: &UserID, profile: &Item) -> HashSet<Vec<u8>> {
+    let mut authors: HashSet<Vec<u8>> = profile.get_profile().get_follows().iter()
+        .map(|f| f.get_user().get_bytes().to_vec())
+        .collect();
+    authors.insert(user_id.bytes().to_vec());
+    authors
+}
+
+/// How many comments `comments_from_follows` would show under an item,
+/// without loading them.
+fn count_comments(
+    backend: &dyn Backend,
+    user_id: &UserID,
+    signature: &Signature,
+    authors: &HashSet<Vec<u8>>,
+) -> Result<usize, failure::Error> {
+    let mut count = 0;
+    let mut level = vec![(user_id.clone(), signature.clone())];
+    for _ in 0..MAX_COMMENT_DEPTH {
+        let mut replies = vec![];
+        for (user, signature) in &level {
+            backend.comment_replies(user, signature, &mut |(reply_user, reply_signature)| {
+                if authors.contains(reply_user.bytes()) {
+                    replies.push((reply_user, reply_signature));
+                }
+                Ok(true)
+            })?;
+        }
+        count += replies.len();
+        if replies.is_empty() || count >= MAX_COMMENTS {
+            break;
+        }
+        level = replies;
+    }
+    Ok(count.min(MAX_COMMENTS))
+}
+
+/// Roll each item's comments up into a count. (See: `--reply-counts`)
+fn add_reply_counts(backend: &dyn Backend, items: &mut [IndexPageItem]) -> Result<(), failure::Error> {
+    // Each author's follows decide whose comments count:
+    let mut authors_by_user: HashMap<Vec<u8>, HashSet<Vec<u8>>> = HashMap::new();
+    for page_item in items.iter_mut() {
+        let user_id = &page_item.row.item.user;
+        if !authors_by_user.contains_key(user_id.bytes()) {
+            let mut profile = Item::new();
+            if let Some(row) = backend.user_profile(user_id)? {
+                profile.merge_from_bytes(&row.item_bytes)?;
+            }
+            authors_by_user.insert(user_id.bytes().to_vec(), comment_authors(user_id, &profile));
+        }
+        let authors = &authors_by_user[user_id.bytes()];
+        page_item.replies = Some(count_comments(backend, user_id, &page_item.row.item.signature, authors)?);
+    }
+    Ok(())
+}
+
+/// Collect a thread of comments, depth-first, so they can be shown in order.
+fn collect_comments(
+    backend: &dyn Backend,
+    user_id: &UserID,
+    signature: &Signature,
+    authors: &HashSet<Vec<u8>>,
+    depth: usize,
+    comments: &mut Vec<CommentView>,
+) -> Result<(), failure::Error> {
+    if depth >= MAX_COMMENT_DEPTH || comments.len() >= MAX_COMMENTS {
+        return Ok(());
+    }
+
+    let mut replies = vec![];
+    backend.item_references(user_id, signature, Timestamp::now(), &mut |row| {
+        if !authors.contains(row.item.user.bytes()) {
+            return Ok(true);
+        }
+        let mut item = Item::new();
+        item.merge_from_bytes(&row.item.item_bytes)?;
+        if item.has_comment() {
+            replies.push((row, item));
+        }
+        Ok(replies.len() < MAX_COMMENTS)
+    })?;
+
+    // References come newest first, but conversations read oldest first:
+    for (row, mut item) in replies.into_iter().rev() {
+        if comments.len() >= MAX_COMMENTS {
+            break;
+        }
+        comments.push(CommentView{
+            display_name: row.display_name.clone().unwrap_or_else(|| row.item.user.to_base58()),
+            user_id: row.item.user.clone(),
+            signature: row.item.signature.clone(),
+            text: item.take_comment().text,
+            timestamp_utc_ms: item.timestamp_ms_utc,
+            utc_offset_minutes: item.utc_offset_minutes,
+            depth,
+        });
+        collect_comments(backend, &row.item.user, &row.item.signature, authors, depth + 1, comments)?;
+    }
+
+    Ok(())
+}
+
+const MAX_ANONYMOUS_COMMENT_BYTES: usize = 4096;
+const MAX_ANONYMOUS_NAME_BYTES: usize = 100;
+
+/// Don't let a single item's queue grow without bound.
+const MAX_QUEUED_COMMENTS: u64 = 100;
+
+#[derive(Deserialize)]
+struct AnonymousComment {
+    name: String,
+    text: String,
+}
+
+/// Queue a comment from a visitor without a FeoBlog key, for the author to review.
+/// `/u/{userID}/i/{signature}/comments/queue`
+async fn queue_anonymous_comment(
+    data: Data<AppData>,
+    Path((user_id, signature)): Path<(UserID, Signature)>,
+    req: HttpRequest,
+    Form(comment): Form<AnonymousComment>,
+) -> Result<HttpResponse, Error> {
+    if !data.anonymous_comments {
+        return Ok(HttpResponse::NotFound().content_type(PLAINTEXT).body("This server doesn't accept anonymous comments."));
+    }
+    if let Some(retry_after) = data.maintenance.retry_after() {
+        return Ok(maintenance::unavailable(retry_after));
+    }
+
+    let backend = data.backend_factory.open().compat()?;
+    let is_post = match backend.user_item(&user_id, &signature).compat()? {
+        None => false,
+        Some(row) => {
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item_bytes)?;
+            item.has_post()
+        }
+    };
+    if !is_post {
+        return Ok(messages::response(&req, StatusCode::NOT_FOUND, Message::NoSuchPost));
+    }
+
+    let text = comment.text.trim();
+    if text.is_empty() || text.len() > MAX_ANONYMOUS_COMMENT_BYTES {
+        return Ok(
+            HttpResponse::BadRequest()
+            .content_type(PLAINTEXT)
+            .body(format!("Comments must be between 1 and {} bytes.", MAX_ANONYMOUS_COMMENT_BYTES))
+        );
+    }
+    if comment.name.len() > MAX_ANONYMOUS_NAME_BYTES {
+        return Ok(
+            HttpResponse::BadRequest()
+            .content_type(PLAINTEXT)
+            .body(format!("Names must be <= {} bytes.", MAX_ANONYMOUS_NAME_BYTES))
+        );
+    }
+
+    if backend.queued_comment_count(&user_id, &signature).compat()? >= MAX_QUEUED_COMMENTS {
+        return Ok(
+            HttpResponse::ServiceUnavailable()
+            .content_type(PLAINTEXT)
+            .body("Too many comments are waiting for review. Try again later.")
+        );
+    }
+
+    backend.queue_comment(&backend::QueuedComment{
+        id: 0,
+        user: user_id,
+        signature,
+        name: comment.name.trim().to_string(),
+        text: text.to_string(),
+        created: Timestamp::now(),
+    }).compat()?;
+
+    Ok(
+        HttpResponse::Accepted()
+        .content_type(PLAINTEXT)
+        .body("Thanks! The author will review your comment, and may quote it in a reply.")
+    )
+}
+
+/// Replies to an item, from anyone.
+/// `/u/{userID}/i/{signature}/replies/proto3`
+async fn get_item_replies(
+    data: Data<AppData>,
+    Path((user_id, signature)): Path<(UserID, Signature)>,
+    Query(pagination): Query<Pagination>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let mut paginator = Paginator::new(
+        pagination,
+        |row: ItemDisplayRow| -> Result<ItemListEntry,failure::Error> {
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item.item_bytes)?;
+            Ok(item_to_entry(&item, &row.item.user, &row.item.signature))
+        },
+        |entry: &ItemListEntry| entry.item_type == ItemType::COMMENT,
+    );
+
+    let backend = data.backend_factory.open().compat()?;
+    backend.item_references(&user_id, &signature, paginator.before(), &mut paginator.callback()).compat()?;
+
+    let first_page = paginator.params.before.is_none();
+    let mut list = ItemList::new();
+    list.no_more_items = !paginator.has_more;
+    list.items = protobuf::RepeatedField::from(paginator.items);
+    item_list_response(&req, &list, first_page)
+}
+
+/// Get the binary representation of the item.
+///
+/// `/u/{userID}/i/{sig}/proto3`
+async fn get_item(
+    data: Data<AppData>,
+    path: Path<(UserID, Signature,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+
+    // TODO: Limit items we return to "known users", in case we unfollowed someone due to sketchy content.
+
+    let (user_id, signature) = path.into_inner();
+
+    let backend = data.backend_factory.open().compat()?;
+    let mut followers_only = false;
+    let mut item = backend.user_item(&user_id, &signature).compat()?;
+    if item.is_none() {
+        let viewer = match viewer::viewer(&req, backend.as_ref(), &data.hosts, &user_id) {
+            Ok(viewer) => viewer,
+            Err(err) => return Ok(HttpResponse::Unauthorized().content_type(PLAINTEXT).body(err.to_string())),
+        };
+        if let Some(viewer) = viewer {
+            item = backend.followers_only_item(&user_id, &signature, &viewer).compat()?;
+            followers_only = item.is_some();
+        }
+    }
+    let item = match item {
+        Some(item) => item,
+        None => { 
+            if backend.user_item_deleted(&user_id, &signature).compat()? {
+                return Ok(messages::response(&req, StatusCode::GONE, Message::ItemDeleted));
+            }
+            return Ok(messages::response(&req, StatusCode::NOT_FOUND, Message::NoSuchItem));
+        }
+    };
+
+    // Items are immutable, and identified by their signature, so it makes a strong ETag.
+    // But only once we know we still have it. A client's cached copy of a
+    // deleted or removed item should get a 410 or 404, not a 304.
+    let etag = format!("\"{}\"", signature.to_base58());
+    if if_none_match(&req, &etag) {
+        return Ok(
+            HttpResponse::NotModified()
+            .header("ETag", etag)
+            .finish()
+        );
+    }
+
+    // We could in theory validate the bytes ourselves, but if a client is directly fetching the 
+    // protobuf bytes via this endpoint, it's probably going to be so that it can verify the bytes
+    // for itself anyway.
+    let mut response = proto_ok();
+    if followers_only {
+        // Still immutable, but shared caches mustn't serve it to anyone else:
+        policy::Cache::PrivateImmutable.apply(&mut response);
+    } else {
+        // Once an Item is stored, it is immutable. Cache forever.
+        policy::Cache::Immutable.apply(&mut response);
+    }
+    response.header("ETag", etag);
+    if data.ipfs {
+        if let Some(path) = ipfs::path(backend.as_ref(), &user_id, &signature, "").compat()? {
+            response.header("X-Ipfs-Path", path);
+        }
+    }
+    Ok(response.body(item.item_bytes))
+
+}
+
+/// A summary of the items we have for a user, so that peers can cheaply
+/// check whether they're in sync.
+///
+/// `/u/{userID}/summary/proto3`
+async fn get_user_summary(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    let summary = backend.user_summary(&user_id).compat()?;
+
+    let mut proto = crate::protos::UserSummary::new();
+    proto.item_count = summary.item_count;
+    proto.digest = summary.digest;
+
+    Ok(
+        proto_ok()
+        .body(proto.write_to_bytes()?)
+    )
+}
+
+/// An `ItemBundle` of an item and its thread, for importing into another server.
+///
+/// `/u/{userID}/i/{signature}/thread/proto3`
+async fn get_thread_bundle(
+    data: Data<AppData>,
+    Path((user_id, signature)): Path<(UserID, Signature)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    let bundle = match crate::bundle::thread_bundle(backend.as_ref(), &user_id, &signature).compat()? {
+        Some(bundle) => bundle,
+        None => return Ok(messages::response(&req, StatusCode::NOT_FOUND, Message::NoSuchItem)),
+    };
+
+    Ok(
+        proto_ok()
+        .body(bundle.write_to_bytes()?)
+    )
+}
+
+/// `/u/{userID}/events/proto3`
+async fn get_user_item_events(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+) -> Result<HttpResponse, Error> {
+    use crate::backend::ItemEventKind;
+    use crate::protos::ItemEventType;
+
+    let backend = data.backend_factory.open().compat()?;
+    let mut events = crate::protos::ItemEvents::new();
+    backend.user_item_events(&user_id, &mut |event| {
+        let mut proto = crate::protos::ItemEvent::new();
+        proto.mut_signature().set_bytes(event.signature.bytes().into());
+        proto.event_type = match event.kind {
+            ItemEventKind::Received => ItemEventType::ITEM_EVENT_RECEIVED,
+            ItemEventKind::Removed => ItemEventType::ITEM_EVENT_REMOVED,
+            ItemEventKind::Restored => ItemEventType::ITEM_EVENT_RESTORED,
+            ItemEventKind::Purged => ItemEventType::ITEM_EVENT_PURGED,
+            ItemEventKind::Expired => ItemEventType::ITEM_EVENT_EXPIRED,
+            ItemEventKind::Deleted => ItemEventType::ITEM_EVENT_DELETED,
+            ItemEventKind::Quarantined => ItemEventType::ITEM_EVENT_QUARANTINED,
+            ItemEventKind::Approved => ItemEventType::ITEM_EVENT_APPROVED,
+            ItemEventKind::Synced => ItemEventType::ITEM_EVENT_SYNCED,
+        };
+        proto.timestamp_ms_utc = event.created.unix_utc_ms;
+        events.events.push(proto);
+        Ok(true)
+    }).compat()?;
+
+    Ok(
+        proto_ok()
+        .body(events.write_to_bytes()?)
+    )
+}
+
+/// A map of a post's location.
+/// `/u/{userID}/i/{signature}/map.png`
+async fn get_item_map(
+    data: Data<AppData>,
+    Path((user_id, signature)): Path<(UserID, Signature)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let map_tiles = match &data.map_tiles {
+        Some(map_tiles) => map_tiles,
+        None => return Ok(HttpResponse::NotFound().body("Maps are not enabled on this server")),
+    };
+
+    let row = data.backend_factory.open().compat()?.user_item(&user_id, &signature).compat()?;
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(messages::response(&req, StatusCode::NOT_FOUND, Message::NoSuchItem)),
+    };
+    let mut item = Item::new();
+    item.merge_from_bytes(&row.item_bytes)?;
+    if !item.get_post().has_location() {
+        return Ok(HttpResponse::NotFound().body("Item has no location"));
+    }
+
+    let tile = map_tiles.tile(data.backend_factory.as_ref(), item.get_post().get_location()).await.compat()?;
+    let mut response = HttpResponse::Ok();
+    // The location in an item never changes, but the map might:
+    policy::Cache::MaxAge(86400).apply(&mut response);
+    Ok(response.content_type("image/png").body(tile))
+}
+
+/// Accepts the bytes of a file attached to an item.
+/// The item must already exist, and its attachments must list the file.
+/// Returns 201 if the PUT was successful.
+/// Returns 202 if the file already exists.
+async fn put_attachment(
+    data: Data<AppData>,
+    path: Path<(UserID, Signature, String)>,
+    req: HttpRequest,
+    body: Payload,
+) -> Result<HttpResponse, Error> {
+    let response = receive_attachment(data.clone(), path, req, body).await?;
+    count_rejection(&data, &response);
+    Ok(response)
+}
+
+async fn receive_attachment(
+    data: Data<AppData>,
+    Path((user_id, signature, file_name)): Path<(UserID, Signature, String)>,
+    req: HttpRequest,
+    mut body: Payload,
+) -> Result<HttpResponse, Error> {
+    if let Some(retry_after) = data.maintenance.retry_after() {
+        return Ok(maintenance::unavailable(retry_after));
+    }
+
+    let backend = data.backend_factory.open().compat()?;
+    // Anyone may upload an item's attachments, since they must match it. Even followers-only ones:
+    let row = match backend.followers_only_item(&user_id, &signature, &user_id).compat()? {
+        Some(row) => row,
+        None => return Ok(messages::response(&req, StatusCode::NOT_FOUND, Message::NoItemForAttachment)),
+    };
+    let mut item = Item::new();
+    item.merge_from_bytes(&row.item_bytes)?;
+    let file = item.get_attachments().get_file().iter().find(|f| f.get_name() == file_name);
+    let file = match file {
+        Some(file) => file,
+        None => return Ok(
+            HttpResponse::NotFound()
+            .content_type(PLAINTEXT)
+            .body("The item has no attachment with that name")
+        ),
+    };
+
+    if backend.attachment_exists(&user_id, &signature, &file_name).compat()? {
+        return Ok(
+            HttpResponse::Accepted()
+            .content_type(PLAINTEXT)
+            .body("File already exists")
+        );
+    }
+
+    if file.size > data.max_attachment_bytes {
+        return Ok(
+            HttpResponse::PayloadTooLarge()
+            .content_type(PLAINTEXT)
+            .body(format!("Attachments must be <= {} bytes", data.max_attachment_bytes))
+        );
+    }
+
+    if let Some(storage) = &data.storage {
+        if !storage.allows(backend.as_ref(), &user_id, file.size).compat()? {
+            return Ok(messages::response(&req, StatusCode::INSUFFICIENT_STORAGE, Message::StorageFull));
+        }
+    }
+
+    let length: Option<u64> = req.headers().get("content-length")
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse().ok());
+    if length != Some(file.size) {
+        return Ok(
+            HttpResponse::BadRequest()
+            .content_type(PLAINTEXT)
+            .body(format!("Content-Length must be {}", file.size))
+        );
+    }
+    let length = file.size as usize;
+
+    let _permit = match data.uploads.try_acquire() {
+        Some(permit) => permit,
+        None => {
+            let mut response = messages::response(&req, StatusCode::SERVICE_UNAVAILABLE, Message::TooManyUploads);
+            response.headers_mut().insert(header::RETRY_AFTER, header::HeaderValue::from_static("5"));
+            return Ok(response);
+        }
+    };
+
+    // Don't hold a DB connection open during a (potentially slow) upload:
+    drop(backend);
+
+    let read_body = async {
+        let mut bytes: Vec<u8> = Vec::with_capacity(length);
+        let mut hasher = sha512::State::new();
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.context("Error parsing chunk").compat()?;
+            hasher.update(&chunk);
+            bytes.extend_from_slice(&chunk);
+            if bytes.len() > length {
+                Err(format_err!("Body is longer than Content-Length").compat())?;
+            }
+        }
+        Ok::<_, Error>((bytes, hasher.finalize()))
+    };
+
+    let (bytes, hash) = match data.uploads.read_deadline(length) {
+        None => read_body.await?,
+        Some(deadline) => match actix_web::rt::time::timeout(deadline, read_body).await {
+            Ok(result) => result?,
+            Err(_) => {
+                return Ok(messages::response(&req, StatusCode::REQUEST_TIMEOUT, Message::UploadTooSlow));
+            }
+        },
+    };
+
+    if bytes.len() != length || hash.as_ref() != file.get_hash() {
+        return Ok(
+            HttpResponse::BadRequest()
+            .content_type(PLAINTEXT)
+            .body("File does not match the hash and size in the item")
+        );
+    }
+
+    let backend = data.backend_factory.open().compat()?;
+    backend.save_attachment(&user_id, &signature, &file_name, &bytes).context("Error saving attachment").compat()?;
+    if data.ipfs && !viewer::is_followers_only(&item) {
+        if let Err(err) = ipfs::queue(backend.as_ref(), &user_id, &signature, &file_name) {
+            log::warn!("Error queueing attachment for IPFS: {}", err);
+        }
+    }
+
+    Ok(
+        HttpResponse::Created()
+        .content_type(PLAINTEXT)
+        .body(format!("OK. Received {} bytes.", bytes.len()))
+    )
+}
+
+/// Serves a file attached to an item.
+/// `/u/{userID}/i/{signature}/files/{file_name}`
+async fn get_attachment(
+    data: Data<AppData>,
+    Path((user_id, signature, file_name)): Path<(UserID, Signature, String)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    let mut followers_only = false;
+    let mut bytes = backend.attachment(&user_id, &signature, &file_name).compat()?;
+    if bytes.is_none() {
+        let viewer = match viewer::viewer(&req, backend.as_ref(), &data.hosts, &user_id) {
+            Ok(viewer) => viewer,
+            Err(err) => return Ok(HttpResponse::Unauthorized().content_type(PLAINTEXT).body(err.to_string())),
+        };
+        if let Some(viewer) = viewer {
+            bytes = backend.followers_only_attachment(&user_id, &signature, &file_name, &viewer).compat()?;
+            followers_only = bytes.is_some();
+        }
+    }
+    let bytes = match bytes {
+        Some(bytes) => bytes,
+        None => return Ok(messages::response(&req, StatusCode::NOT_FOUND, Message::NoSuchFile)),
+    };
+
+    let mime_type = mime_guess::from_path(&file_name).first_or_octet_stream();
+    let mut response = HttpResponse::Ok();
+    if followers_only {
+        policy::Cache::PrivateImmutable.apply(&mut response);
+    } else {
+        // The item pins the file's hash, so it can never change:
+        policy::Cache::Immutable.apply(&mut response);
+    }
+    response
+        .content_type(mime_type.to_string())
+        // Files are user-supplied. Don't let them run scripts as this origin:
+        .header("Content-Security-Policy", "sandbox")
+        .header("X-Content-Type-Options", "nosniff");
+    if data.ipfs {
+        if let Some(path) = ipfs::path(backend.as_ref(), &user_id, &signature, &file_name).compat()? {
+            response.header("X-Ipfs-Path", path);
+        }
+    }
+    Ok(response.body(bytes))
+}
+
+/// Responses to an item, as an Atom feed.
+/// `/u/{userID}/i/{signature}/comments.atom`
+async fn get_item_comments_atom(
+    data: Data<AppData>,
+    Path((user_id, signature)): Path<(UserID, Signature)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    let row = match backend.user_item(&user_id, &signature).compat()? {
+        Some(row) => row,
+        None => return Ok(messages::response(&req, StatusCode::NOT_FOUND, Message::NoSuchItem)),
+    };
+    let mut item = Item::new();
+    item.merge_from_bytes(&row.item_bytes)?;
+
+    let base_url = base_url(&req);
+    let item_url = format!("{}/u/{}/i/{}/", base_url, user_id.to_base58(), signature.to_base58());
+
+    let max_entries = 50;
+    let mut entries = vec![];
+    backend.item_references(&user_id, &signature, Timestamp::now(), &mut |display_row| {
+        let mut response = Item::new();
+        response.merge_from_bytes(&display_row.item.item_bytes)?;
+        let author = display_row.display_name.clone()
+            .unwrap_or_else(|| display_row.item.user.to_base58());
+
+        let (title, content_html) = if response.has_vote() {
+            let option = item.get_poll().get_options()
+                .get(response.get_vote().option as usize)
+                .map(|o| o.as_str())
+                .unwrap_or("(unknown option)");
+            (
+                format!("{} voted", author),
+                format!("<p>{}</p>", crate::follows::escape(option)),
+            )
+        } else if response.has_comment() {
+            (
+                format!("Comment from {}", author),
+                response.get_comment().get_text().md_to_html(),
+            )
+        } else {
+            // Future response types:
+            (
+                format!("Response from {}", author),
+                response.get_post().get_body().md_to_html(),
+            )
+        };
+
+        entries.push(crate::atom::Entry{
+            id: format!("{}/u/{}/i/{}/", base_url, display_row.item.user.to_base58(), display_row.item.signature.to_base58()),
+            title,
+            author,
+            updated: display_row.item.timestamp,
+            content_html,
+            // Responses are from others, who may license them differently than this item:
+            license: String::new(),
+        });
+        Ok(entries.len() < max_entries)
+    }).compat()?;
+
+    let title = if item.has_poll() {
+        item.get_poll().get_question().to_string()
+    } else {
+        item.get_post().get_title().to_string()
+    };
+    let feed = crate::atom::Feed{
+        lang: locale::lang().into(),
+        id: format!("{}comments.atom", item_url),
+        title: format!("Responses to: {}", if title.is_empty() { &item_url } else { &title }),
+        updated: entries.first().map(|e| e.updated).unwrap_or(row.timestamp),
+        entries,
+    };
+
+    Ok(atom_response(&feed))
+}
+
+/// ex: "https://feo.example.com", for building absolute URLs.
+fn base_url(req: &HttpRequest) -> String {
+    let info = req.connection_info();
+    format!("{}://{}", info.scheme(), info.host())
+}
+
+fn atom_response(feed: &crate::atom::Feed) -> HttpResponse {
+    HttpResponse::Ok()
+    .content_type("application/atom+xml; charset=utf-8")
+    .body(feed.to_xml())
+}
+
+/// An Atom entry for an item shown in a list.
+fn atom_entry(base_url: &str, page_item: &IndexPageItem, license: String) -> crate::atom::Entry {
+    let row = &page_item.row.item;
+    let item = &page_item.item;
+    let author = page_item.row.display_name.clone()
+        .filter(|name| !name.trim().is_empty())
+        .unwrap_or_else(|| row.user.to_base58());
+
+    let (title, content_html) = if item.has_poll() {
+        let poll = item.get_poll();
+        let mut html = String::from("<ul>");
+        for option in poll.get_options() {
+            write!(html, "<li>{}</li>", crate::follows::escape(option)).expect("write! to a string shouldn't panic.");
+        }
+        html.push_str("</ul>");
+        (poll.get_question().to_string(), html)
+    } else {
+        let post = item.get_post();
+        let title = if post.get_title().is_empty() {
+            format!("Post by {}", author)
+        } else {
+            post.get_title().to_string()
+        };
+        (title, post.get_body().md_to_html())
+    };
+
+    crate::atom::Entry {
+        id: format!("{}/u/{}/i/{}/", base_url, row.user.to_base58(), row.signature.to_base58()),
+        title,
+        author,
+        updated: row.timestamp,
+        content_html,
+        license,
+    }
+}
+
+/// Where a syndication feed (Atom, RSS, JSON Feed) gets its items.
+enum FeedSource {
+    Homepage,
+
+    /// Items from the users that a user follows.
+    UserFeed(UserID),
+
+    /// A user's own posts.
+    UserPosts(UserID),
+
+    /// A user's saved (custom) feed.
+    SavedFeed(backend::SavedFeed),
+}
+
+/// A syndication feed's metadata and entries, ready to render in any format.
+struct Syndication {
+    /// An absolute URL to the HTML page that this feed mirrors.
+    html_url: String,
+    title: String,
+    entries: Vec<crate::atom::Entry>,
+}
+
+/// Syndication feeds are usually polled for new entries, so don't bother with long pages.
+const MAX_FEED_ENTRIES: usize = 50;
+
+/// Collect the entries for a syndication feed. Shared by all feed formats.
+fn syndication(
+    backend: &dyn Backend,
+    source: &FeedSource,
+    pagination: Pagination,
+    base_url: &str,
+) -> Result<Syndication, failure::Error> {
+    let mut paginator = Paginator::new(
+        pagination,
+        |row: ItemDisplayRow| -> Result<IndexPageItem,failure::Error> {
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item.item_bytes)?;
+            Ok(IndexPageItem{row, item, replies: None})
+        },
+        |page_item: &IndexPageItem| match source {
+            FeedSource::UserPosts(_) => page_item.item.has_post(),
+            FeedSource::SavedFeed(feed) => saved_feed_shows(feed, &page_item.item),
+            _ => display_by_default(&page_item.item),
+        },
+    );
+    paginator.max_items = MAX_FEED_ENTRIES;
+    if paginator.params.count.is_none() {
+        paginator.params.count = Some(20);
+    }
+
+    let before = paginator.before();
+    let (html_url, title) = match source {
+        FeedSource::Homepage => {
+            backend.homepage_items(before, &mut paginator.callback())?;
+            (format!("{}/", base_url), "FeoBlog".to_string())
+        },
+        FeedSource::UserFeed(user_id) => {
+            // Feed readers don't sign in, so no one's mutes apply.
+            backend.user_feed_items(user_id, None, before, &mut paginator.callback())?;
+            let name = profile_display_name(backend, user_id)?;
+            (format!("{}/u/{}/feed/", base_url, user_id.to_base58()), format!("{}'s feed", name))
+        },
+        FeedSource::UserPosts(user_id) => {
+            let name = profile_display_name(backend, user_id)?;
+            let mut callback = paginator.callback();
+            backend.user_items(user_id, before, &mut |row| {
+                callback(ItemDisplayRow{ item: row, display_name: Some(name.clone()) })
+            })?;
+            (format!("{}/u/{}/", base_url, user_id.to_base58()), name)
+        },
+        FeedSource::SavedFeed(feed) => {
+            backend.saved_feed_items(&feed.user, &feed.name, before, &mut paginator.callback())?;
+            let name = profile_display_name(backend, &feed.user)?;
+            (
+                format!("{}/u/{}/feeds/{}/", base_url, feed.user.to_base58(), feed.name),
+                format!("{}: {}", name, feed.name),
+            )
+        },
+    };
+
+    let mut licenses = Licenses::new(backend);
+    let mut entries = Vec::with_capacity(paginator.items.len());
+    for page_item in &paginator.items {
+        let license = licenses.license(&page_item.row.item.user, &page_item.item)?;
+        entries.push(atom_entry(base_url, page_item, license));
+    }
+
+    Ok(Syndication {
+        html_url,
+        title,
+        entries,
+    })
+}
+
+/// `/feed.atom`, `/u/{user_id}/feed.atom`, and `/u/{user_id}/posts.atom`
+fn atom_feed(syndication: Syndication, self_url: String) -> HttpResponse {
+    let feed = crate::atom::Feed {
+        lang: locale::lang().into(),
+        id: self_url,
+        title: syndication.title,
+        updated: syndication.entries.first().map(|e| e.updated).unwrap_or_else(Timestamp::now),
+        entries: syndication.entries,
+    };
+    atom_response(&feed)
+}
+
+/// Items from the users that a user follows, as an Atom feed.
+/// `/u/{user_id}/feed.atom`
+async fn get_user_feed_atom(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    Query(pagination): Query<Pagination>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let base_url = base_url(&req);
+    let backend = data.backend_factory.open().compat()?;
+    let self_url = format!("{}/u/{}/feed.atom", base_url, user_id.to_base58());
+    let syndication = syndication(backend.as_ref(), &FeedSource::UserFeed(user_id), pagination, &base_url).compat()?;
+    Ok(atom_feed(syndication, self_url))
+}
+
+/// A user's own posts, as an Atom feed.
+/// `/u/{user_id}/posts.atom`
+async fn get_user_posts_atom(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    Query(pagination): Query<Pagination>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let base_url = base_url(&req);
+    let backend = data.backend_factory.open().compat()?;
+    let self_url = format!("{}/u/{}/posts.atom", base_url, user_id.to_base58());
+    let syndication = syndication(backend.as_ref(), &FeedSource::UserPosts(user_id), pagination, &base_url).compat()?;
+    Ok(atom_feed(syndication, self_url))
+}
+
+/// The homepage, as an RSS feed.
+/// `/feed.rss`
+async fn homepage_rss(
+    data: Data<AppData>,
+    Query(pagination): Query<Pagination>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let base_url = base_url(&req);
+    let backend = data.backend_factory.open().compat()?;
+    let syndication = syndication(backend.as_ref(), &FeedSource::Homepage, pagination, &base_url).compat()?;
+    let description = format!("Recent posts on {}", base_url);
+    Ok(rss_feed(syndication, description))
+}
+
+/// A user's saved feed, as an RSS feed.
+/// `/u/{user_id}/feeds/{name}/feed.rss`
+async fn saved_feed_rss(
+    data: Data<AppData>,
+    Path((user_id, name)): Path<(UserID, String)>,
+    Query(pagination): Query<Pagination>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let base_url = base_url(&req);
+    let backend = data.backend_factory.open().compat()?;
+    let feed = match backend.saved_feed(&user_id, &name).compat()? {
+        Some(feed) => feed,
+        None => return Ok(messages::response(&req, StatusCode::NOT_FOUND, Message::NoSuchFeed)),
+    };
+    let syndication = syndication(backend.as_ref(), &FeedSource::SavedFeed(feed), pagination, &base_url).compat()?;
+    let description = syndication.title.clone();
+    Ok(rss_feed(syndication, description))
+}
+
+/// `/feed.rss` and `/u/{user_id}/feeds/{name}/feed.rss`
+fn rss_feed(syndication: Syndication, description: String) -> HttpResponse {
+    let channel = crate::rss::Channel {
+        title: syndication.title,
+        link: syndication.html_url,
+        description,
+        lang: locale::lang().into(),
+        items: syndication.entries,
+    };
+
+    HttpResponse::Ok()
+    .content_type("application/rss+xml; charset=utf-8")
+    .body(channel.to_xml())
+}
+
+/// The homepage, as a JSON Feed.
+/// `/feed.json`
+async fn homepage_json_feed(
+    data: Data<AppData>,
+    Query(pagination): Query<Pagination>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    json_feed(&data, FeedSource::Homepage, pagination, &req)
+}
+
+/// `/u/{user_id}/feed.json`
+async fn get_user_feed_json(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    Query(pagination): Query<Pagination>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    json_feed(&data, FeedSource::UserFeed(user_id), pagination, &req)
+}
+
+/// `/u/{user_id}/posts.json`
+async fn get_user_posts_json(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    Query(pagination): Query<Pagination>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    json_feed(&data, FeedSource::UserPosts(user_id), pagination, &req)
+}
+
+/// Posts with titles are articles, which we offer to e-readers.
+fn is_article(item: &Item) -> bool {
+    item.has_post() && !item.get_post().get_title().trim().is_empty()
+}
+
+/// A user's articles, as an OPDS catalog that e-reader apps can browse.
+/// `/u/{user_id}/articles.opds`
+async fn get_user_articles_opds(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    Query(pagination): Query<Pagination>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let base_url = base_url(&req);
+    let backend = data.backend_factory.open().compat()?;
+
+    let mut paginator = Paginator::new(
+        pagination,
+        |row: ItemDisplayRow| -> Result<IndexPageItem,failure::Error> {
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item.item_bytes)?;
+            Ok(IndexPageItem{row, item, replies: None})
+        },
+        |page_item: &IndexPageItem| is_article(&page_item.item),
+    );
+    paginator.max_items = MAX_FEED_ENTRIES;
+    if paginator.params.count.is_none() {
+        paginator.params.count = Some(20);
+    }
+
+    let name = profile_display_name(backend.as_ref(), &user_id).compat()?;
+    let before = paginator.before();
+    let mut callback = paginator.callback();
+    backend.user_items(&user_id, before, &mut |row| {
+        callback(ItemDisplayRow{ item: row, display_name: Some(name.clone()) })
+    }).compat()?;
+    drop(callback);
+
+    let self_url = format!("{}/u/{}/articles.opds", base_url, user_id.to_base58());
+    let mut licenses = Licenses::new(backend.as_ref());
+    let mut entries = Vec::with_capacity(paginator.items.len());
+    for page_item in &paginator.items {
+        let license = licenses.license(&user_id, &page_item.item).compat()?;
+        let entry = atom_entry(&base_url, page_item, license);
+        entries.push(crate::opds::Entry {
+            epub_url: format!("{}article.epub", entry.id),
+            id: entry.id,
+            title: entry.title,
+            author: entry.author,
+            updated: entry.updated,
+            content_html: entry.content_html,
+            license: entry.license,
+        });
+    }
+
+    let catalog = crate::opds::Catalog {
+        lang: locale::lang().into(),
+        next_url: paginator.more_items_link(&self_url),
+        id: self_url,
+        title: format!("Articles by {}", name),
+        updated: entries.first().map(|e| e.updated).unwrap_or_else(Timestamp::now),
+        html_url: format!("{}/u/{}/", base_url, user_id.to_base58()),
+        entries,
+    };
+    Ok(
+        HttpResponse::Ok()
+        .content_type(format!("{}; charset=utf-8", crate::opds::CATALOG_TYPE))
+        .body(catalog.to_xml())
+    )
+}
+
+/// A post as an EPUB book, with its images.
+/// `/u/{userID}/i/{signature}/article.epub`
+async fn get_item_epub(
+    data: Data<AppData>,
+    Path((user_id, signature)): Path<(UserID, Signature)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    let row = match backend.user_item(&user_id, &signature).compat()? {
+        Some(row) => row,
+        None => return Ok(messages::response(&req, StatusCode::NOT_FOUND, Message::NoSuchItem)),
+    };
+    let mut item = Item::new();
+    item.merge_from_bytes(&row.item_bytes)?;
+    if !item.has_post() {
+        return Ok(
+            HttpResponse::NotFound()
+            .content_type(PLAINTEXT)
+            .body("Only posts can be downloaded as EPUBs")
+        );
+    }
+
+    let author = profile_display_name(backend.as_ref(), &user_id).compat()?;
+    let post = item.get_post();
+    let title = if post.get_title().trim().is_empty() {
+        format!("Post by {}", author)
+    } else {
+        post.get_title().to_string()
+    };
+
+    let mut images = vec![];
+    for file in item.get_attachments().get_file() {
+        let media_type = mime_guess::from_path(file.get_name()).first_or_octet_stream();
+        if media_type.type_() != mime_guess::mime::IMAGE {
+            continue;
+        }
+        // Not uploaded (yet), or removed:
+        if let Some(bytes) = backend.attachment(&user_id, &signature, file.get_name()).compat()? {
+            images.push((file.get_name().to_string(), media_type.to_string(), bytes));
+        }
+    }
+
+    let published = Timestamp{ unix_utc_ms: item.timestamp_ms_utc };
+    let book = crate::epub::Book {
+        id: format!("{}/u/{}/i/{}/", base_url(&req), user_id.to_base58(), signature.to_base58()),
+        title,
+        author,
+        lang: locale::lang().into(),
+        published,
+        date: published.format_with(item.utc_offset_minutes as i16, &locale::get().date_format),
+        content_html: post.get_body().md_to_html(),
+        license: Licenses::new(backend.as_ref()).license(&user_id, &item).compat()?,
+        images,
+    };
+    let epub = book.to_epub().compat()?;
+
+    Ok(
+        HttpResponse::Ok()
+        .content_type("application/epub+zip")
+        .header("Content-Disposition", format!("attachment; filename=\"{}.epub\"", signature.to_base58()))
+        .body(epub)
+    )
+}
+
+/// Render a JSON Feed. <https://jsonfeed.org/version/1.1>
+fn json_feed(data: &AppData, source: FeedSource, pagination: Pagination, req: &HttpRequest) -> Result<HttpResponse, Error> {
+    let base_url = base_url(req);
+    let backend = data.backend_factory.open().compat()?;
+    let syndication = syndication(backend.as_ref(), &source, pagination, &base_url).compat()?;
+
+    let feed = crate::json_feed::Feed {
+        title: syndication.title,
+        home_page_url: syndication.html_url,
+        feed_url: format!("{}{}", base_url, req.path()),
+        language: locale::lang().into(),
+        entries: syndication.entries,
+    };
+
+    Ok(
+        HttpResponse::Ok()
+        .content_type("application/feed+json; charset=utf-8")
+        .body(feed.to_json().compat()?)
+    )
+}
+
+/// A user's display name, or their user ID if they don't have one.
+fn profile_display_name(backend: &dyn Backend, user_id: &UserID) -> Result<String, failure::Error> {
+    let name = match backend.user_profile(user_id)? {
+        None => String::new(),
+        Some(row) => {
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item_bytes)?;
+            item.take_profile().display_name
+        },
+    };
+    if name.trim().is_empty() || crate::confusables::looks_like_user_id(&name) {
+        Ok(user_id.to_base58())
+    } else {
+        Ok(name)
+    }
+}
+
+/// Count a poll's votes, once it has closed. Until then, counts are empty, so
+/// that early results can't sway later voters.
+///
+/// Only votes from users known to this server count. (See: `Backend::user_known`)
+/// Anyone can sign a vote, so we'd otherwise count as many as anyone cared to make.
+pub(crate) fn poll_tally(
+    backend: &dyn Backend,
+    user_id: &UserID,
+    signature: &Signature,
+    poll: &crate::protos::Poll,
+) -> Result<crate::protos::PollTally, failure::Error> {
+    let mut tally = crate::protos::PollTally::new();
+    tally.closed = Timestamp::now().unix_utc_ms > poll.close_ms_utc;
+    if !tally.closed {
+        return Ok(tally);
+    }
+
+    tally.counts = vec![0; poll.get_options().len()];
+    let closes = Timestamp{ unix_utc_ms: poll.close_ms_utc };
+    backend.poll_votes(user_id, signature, closes, &mut |vote| {
+        if !backend.user_known(&vote.voter)? {
+            return Ok(true);
+        }
+        // Ignore votes for options that don't exist:
+        if let Some(total) = tally.counts.get_mut(vote.option as usize) {
+            *total += 1;
+        }
+        Ok(true)
+    })?;
+
+    Ok(tally)
+}
+
+/// `/u/{userID}/i/{signature}/votes/proto3`
+async fn get_poll_tally(
+    data: Data<AppData>,
+    Path((user_id, signature)): Path<(UserID, Signature)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    let row = match backend.user_item(&user_id, &signature).compat()? {
+        Some(row) => row,
+        None => return Ok(messages::response(&req, StatusCode::NOT_FOUND, Message::NoSuchItem)),
+    };
+    let mut item = Item::new();
+    item.merge_from_bytes(&row.item_bytes)?;
+    if !item.has_poll() {
+        return Ok(HttpResponse::NotFound().body("Item is not a poll"));
+    }
+
+    let tally = poll_tally(backend.as_ref(), &user_id, &signature, item.get_poll()).compat()?;
+    Ok(
+        proto_ok()
+        .body(tally.write_to_bytes()?)
+    )
+}
+
+#[derive(Deserialize)]
+struct ReactionsParams {
+    /// Only count reactions from this user and the users they follow.
+    viewer: Option<UserID>,
+}
+
+/// `/u/{userID}/i/{signature}/reactions/proto3`
+async fn get_reaction_counts(
+    data: Data<AppData>,
+    Path((user_id, signature)): Path<(UserID, Signature)>,
+    Query(params): Query<ReactionsParams>,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+
+    let mut counts: Vec<(String, u64)> = vec![];
+    backend.item_reactions(&user_id, &signature, &mut |reaction| {
+        if let Some(viewer) = &params.viewer {
+            let from_follow = reaction.user.bytes() == viewer.bytes() || backend.follows(viewer, &reaction.user)?;
+            if !from_follow {
+                return Ok(true);
+            }
+        }
+        match counts.iter_mut().find(|(emoji, _)| *emoji == reaction.emoji) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((reaction.emoji, 1)),
+        }
+        Ok(true)
+    }).compat()?;
+    // Most common first, then a stable order for ties:
+    counts.sort_by(|(a_emoji, a), (b_emoji, b)| b.cmp(a).then_with(|| a_emoji.cmp(b_emoji)));
+
+    let mut response = crate::protos::ReactionCounts::new();
+    for (emoji, count) in counts {
+        let mut entry = crate::protos::ReactionCount::new();
+        entry.emoji = emoji;
+        entry.count = count;
+        response.counts.push(entry);
+    }
+
+    let mut builder = proto_ok();
+    policy::Cache::Mutable.apply(&mut builder);
+    Ok(builder.body(response.write_to_bytes()?))
+}
+
+/// `/server/info/proto3`
+async fn get_server_info(data: Data<AppData>) -> Result<HttpResponse, Error> {
+    let mut info = crate::protos::ServerInfo::new();
+    info.accepted_item_types = data.accepted_item_types.clone();
+    info.proof_of_work_difficulty = data.pow_difficulty;
+    for deprecation in deprecations::DEPRECATIONS {
+        let mut route = crate::protos::DeprecatedRoute::new();
+        route.pattern = deprecation.pattern.into();
+        route.deprecated_ms_utc = deprecation.deprecated_utc_ms;
+        route.sunset_ms_utc = deprecation.sunset_utc_ms.unwrap_or(0);
+        route.note = deprecation.note.into();
+        info.deprecated_routes.push(route);
+    }
+
+    let announcement = data.backend_factory.open().compat()?.announcement().compat()?;
+    if let Some(announcement) = announcement.filter(|a| !a.is_expired(Timestamp::now())) {
+        let proto = info.mut_announcement();
+        proto.message = announcement.message;
+        proto.expires_ms_utc = announcement.expires.map(|e| e.unix_utc_ms).unwrap_or(0);
+    }
+
+    Ok(
+        proto_ok()
+        .body(info.write_to_bytes()?)
+    )
+}
+
+#[derive(Deserialize)]
+struct TimeRange {
+    /// Only include items before this timestamp. Default is now.
+    before: Option<i64>,
+
+    /// Only include items at or after this timestamp.
+    after: Option<i64>,
+}
+
+/// A Bloom filter of the signatures of a user's items, so that peers can
+/// cheaply find which items we're missing.
+///
+/// `/u/{userID}/bloom/proto3`
+async fn get_user_bloom_filter(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    Query(range): Query<TimeRange>,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    let before = range.before.map(|t| Timestamp{ unix_utc_ms: t }).unwrap_or_else(Timestamp::now);
+    let after = range.after.unwrap_or(i64::MIN);
+
+    // We need to know how many items there are to size the filter:
+    let mut signatures = vec![];
+    backend.user_items(&user_id, before, &mut |row| {
+        if row.timestamp.unix_utc_ms < after {
+            return Ok(false);
+        }
+        signatures.push(row.signature);
+        Ok(true)
+    }).compat()?;
+
+    let mut filter = BloomFilter::with_capacity(signatures.len());
+    for signature in &signatures {
+        filter.insert(signature.bytes());
+    }
+
+    let mut proto = crate::protos::BloomFilter::new();
+    proto.bits = filter.bits().to_vec();
+    proto.hash_count = filter.hash_count();
+    proto.item_count = signatures.len() as u64;
+
+    Ok(
+        proto_ok()
+        .body(proto.write_to_bytes()?)
+    )
+}
+
+/// Daily view counts for a user's items, if the server keeps them.
+/// Only for the user, (signed in) or the server's admins.
+///
+/// `/u/{userID}/stats/views/proto3`
+async fn user_view_counts(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    if !data.count_views {
+        return Ok(
+            HttpResponse::NotFound()
+            .content_type(PLAINTEXT)
+            .body("This server does not keep view counts.")
+        );
+    }
+
+    let backend = data.backend_factory.open().compat()?;
+    if let Err(response) = viewer::require_user_or_admin(&req, backend.as_ref(), &data.hosts, &data.admins, &user_id) {
+        return Ok(response);
     }
 
+    let mut counts = ViewCounts::new();
+    backend.user_item_view_counts(&user_id, &mut |count| {
+        let mut entry = ViewCount::new();
+        entry.set_signature({
+            let mut sig = crate::protos::Signature::new();
+            sig.set_bytes(count.signature.bytes().into());
+            sig
+        });
+        entry.day = count.day;
+        entry.views = count.views as u64;
+        counts.counts.push(entry);
+        Ok(true)
+    }).compat()?;
 
+    Ok(
+        policy::Cache::NoStore.apply(&mut proto_ok())
+        .body(counts.write_to_bytes()?)
+    )
 }
 
-/// Get the binary representation of the item.
+/// Links in a user's posts that didn't work when we last checked.
 ///
-/// `/u/{userID}/i/{sig}/proto3`
-async fn get_item(
+/// `/u/{userID}/stats/dead-links/proto3`
+async fn user_dead_links(
     data: Data<AppData>,
-    path: Path<(UserID, Signature,)>,
+    Path((user_id,)): Path<(UserID,)>,
 ) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    let mut links = crate::protos::DeadLinks::new();
+    backend.user_dead_links(&user_id, &mut |link| {
+        let mut entry = crate::protos::DeadLink::new();
+        entry.mut_signature().set_bytes(link.signature.bytes().into());
+        entry.url = link.url;
+        entry.error = link.error;
+        entry.checked_ms_utc = link.checked.unix_utc_ms;
+        links.links.push(entry);
+        Ok(true)
+    }).compat()?;
 
-    // TODO: Check whether Access-Control-Max-Age effectively truncates our Cache-Control max-age.
-    // If it does, we'll likely get more hits to this resource than necessary.
-    // But, according to https://developer.mozilla.org/en-US/docs/Web/HTTP/Caching,
-    // browsers will send an If-None-Match header if they're updating caches. Does that apply to
-    // expired Access-Control caches too? If so, we could just check for the presence of that tag
-    // and return the "This content hasn't updated" response w/o having to touch the DB.
-    // We'd also probably need to *send* an etag w/ the resposne to allow browsers to do this.
-    // And all this needs a bit of testing.
-    
-    // TODO: Limit items we return to "known users", in case we unfollowed someone due to sketchy content.
+    Ok(
+        proto_ok()
+        .body(links.write_to_bytes()?)
+    )
+}
 
-    let (user_id, signature) = path.into_inner();
+/// Render some statistics about a user's items.
+///
+/// Only for the user, or the server's admins, since it includes view counts.
+/// Requests must be signed with a FeoBlog-Viewer header.
+///
+/// `/u/{userID}/stats/`
+async fn show_user_stats(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
     let backend = data.backend_factory.open().compat()?;
-    let item = backend.user_item(&user_id, &signature).compat()?;
-    let item = match item {
-        Some(item) => item,
-        None => { 
-            return Ok(
-                HttpResponse::NotFound().body("No such item")
-            );
+    if let Err(response) = viewer::require_user_or_admin(&req, backend.as_ref(), &data.hosts, &data.admins, &user_id) {
+        return Ok(response);
+    }
+
+    let max_months = 24;
+    let mut months = Vec::with_capacity(max_months);
+    backend.user_item_counts_by_month(&user_id, &mut |count| {
+        months.push(count);
+        Ok(months.len() < max_months)
+    }).compat()?;
+
+    let mut follower_months = Vec::with_capacity(max_months);
+    backend.user_follower_counts_by_month(&user_id, &mut |count| {
+        follower_months.push(count);
+        Ok(follower_months.len() < max_months)
+    }).compat()?;
+
+    let mut reaction_months = Vec::with_capacity(max_months);
+    backend.user_reaction_counts_by_month(&user_id, &mut |count| {
+        reaction_months.push(count);
+        Ok(reaction_months.len() < max_months)
+    }).compat()?;
+
+    let max_top_items = 10;
+    let mut top_items = Vec::with_capacity(max_top_items);
+    if data.count_views {
+        let mut counts = Vec::with_capacity(max_top_items);
+        backend.user_top_viewed_items(&user_id, &mut |count| {
+            counts.push(count);
+            Ok(counts.len() < max_top_items)
+        }).compat()?;
+
+        for count in counts {
+            // Items may have since been removed:
+            let row = match backend.user_item(&user_id, &count.signature).compat()? {
+                Some(row) => row,
+                None => continue,
+            };
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item_bytes)?;
+            top_items.push(TopItem{
+                signature: count.signature,
+                title: item.get_post().get_title().to_string(),
+                views: count.views,
+            });
         }
+    }
+
+    let followers = backend.user_follower_count(&user_id).compat()?;
+
+    let max_dead_links = 100;
+    let mut dead_links = Vec::with_capacity(max_dead_links);
+    backend.user_dead_links(&user_id, &mut |link| {
+        dead_links.push(link);
+        Ok(dead_links.len() < max_dead_links)
+    }).compat()?;
+
+    let mut nav = vec![];
+    if let Some(row) = backend.user_profile(&user_id).compat()? {
+        let mut item = Item::new();
+        item.merge_from_bytes(&row.item_bytes)?;
+        nav.push(Nav::Text(item.get_profile().display_name.clone()));
+    }
+    nav.extend(vec![
+        Nav::Link{
+            text: "Posts".into(),
+            href: format!("/u/{}/", user_id.to_base58()),
+        },
+        Nav::Link{
+            text: "Profile".into(),
+            href: format!("/u/{}/profile/", user_id.to_base58()),
+        },
+        Nav::Link{
+            text: "Home".into(),
+            href: "/".into(),
+        },
+    ]);
+
+    let page = StatsPage {
+        nav,
+        user_id,
+        months,
+        follower_months,
+        reaction_months,
+        top_items,
+        count_views: data.count_views,
+        followers,
+        dead_links,
     };
 
-    // We could in theory validate the bytes ourselves, but if a client is directly fetching the 
-    // protobuf bytes via this endpoint, it's probably going to be so that it can verify the bytes
-    // for itself anyway.
     Ok(
-        proto_ok()
-        // Once an Item is stored, it is immutable. Cache forever.
-        // "aggressive caching" according to https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Cache-Control
-        // 31536000 = 365 days, as seconds
-        .header("Cache-Control", "public, max-age=31536000, immutable")
-        .body(item.item_bytes)
+        policy::Cache::NoStore.apply(&mut HttpResponse::Ok())
+        .content_type("text/html; charset=utf-8")
+        .body(page.render()?)
     )
-
 }
 
 /// Get the latest profile we have for a user ID.
@@ -935,6 +3899,7 @@ async fn get_item(
 async fn get_profile_item(
     data: Data<AppData>,
     Path((user_id,)): Path<(UserID,)>,
+    req: HttpRequest,
 ) -> Result<HttpResponse, Error> {
     
     let backend = data.backend_factory.open().compat()?;
@@ -943,7 +3908,7 @@ async fn get_profile_item(
         Some(item) => item,
         None => { 
             return Ok(
-                HttpResponse::NotFound().body("No such item")
+                messages::response(&req, StatusCode::NOT_FOUND, Message::NoSuchItem)
             );
         }
     };
@@ -958,11 +3923,194 @@ async fn get_profile_item(
     )
 
 }
-async fn file_not_found(msg: impl Into<String>) -> impl Responder<Error=actix_web::error::Error> {
-    NotFoundPage {
-        message: msg.into()
+/// `/u/{userID}/follows/opml`
+async fn get_follows_opml(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    let row = match backend.user_profile(&user_id).compat()? {
+        Some(row) => row,
+        None => return Ok(messages::response(&req, StatusCode::NOT_FOUND, Message::NoSuchProfile)),
+    };
+    let mut item = Item::new();
+    item.merge_from_bytes(&row.item_bytes)?;
+
+    let base_url = base_url(&req);
+    let opml = crate::follows::export_opml(item.get_profile(), Some(&base_url)).compat()?;
+
+    Ok(
+        HttpResponse::Ok()
+        .content_type("text/x-opml; charset=utf-8")
+        .body(opml)
+    )
+}
+
+/// `/u/{userID}/follows/json`
+async fn get_follows_json(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    let row = match backend.user_profile(&user_id).compat()? {
+        Some(row) => row,
+        None => return Ok(messages::response(&req, StatusCode::NOT_FOUND, Message::NoSuchProfile)),
+    };
+    let mut item = Item::new();
+    item.merge_from_bytes(&row.item_bytes)?;
+
+    let base_url = base_url(&req);
+    let json = crate::follows::export_json(item.get_profile(), Some(&base_url)).compat()?;
+
+    Ok(
+        HttpResponse::Ok()
+        .content_type("application/json")
+        .body(json)
+    )
+}
+
+/// Just the follows (and servers) from the user's latest profile.
+///
+/// `/u/{userID}/follows/proto3`
+async fn get_follows_proto(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    let row = match backend.user_profile(&user_id).compat()? {
+        Some(row) => row,
+        None => return Ok(messages::response(&req, StatusCode::NOT_FOUND, Message::NoSuchProfile)),
+    };
+
+    // A profile's follows can only change with a new profile:
+    let etag = format!("\"{}\"", row.signature.to_base58());
+    if if_none_match(&req, &etag) {
+        return Ok(not_modified(etag));
+    }
+
+    let mut item = Item::new();
+    item.merge_from_bytes(&row.item_bytes)?;
+    let mut profile = item.take_profile();
+
+    let mut list = crate::protos::FollowList::new();
+    list.mut_profile_signature().set_bytes(row.signature.bytes().into());
+    list.profile_timestamp_ms_utc = item.timestamp_ms_utc;
+    list.set_follows(profile.take_follows());
+    list.set_servers(profile.take_servers());
+
+    Ok(
+        proto_ok()
+        .header("ETag", etag)
+        .header("signature", row.signature.to_base58())
+        .body(list.write_to_bytes()?)
+    )
+}
+
+#[derive(Deserialize)]
+struct NewItemParams {
+    /// ex: "post", "poll"
+    #[serde(rename = "type", default = "default_new_item_type")]
+    item_type: String,
+
+    /// The client's UTC offset, if it knows better than the user's last item.
+    utc_offset_minutes: Option<i32>,
+}
+
+fn default_new_item_type() -> String { "post".into() }
+
+/// An unsigned Item for a user to fill in, sign, and PUT. The timestamp is
+/// now, and the UTC offset is the same as the user's latest item. A new
+/// profile starts as a copy of their current one.
+/// `/u/{user_id}/new-item/proto3?type=post`
+async fn get_new_item(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    Query(params): Query<NewItemParams>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let item_type = match crate::parse_item_type(&params.item_type) {
+        Ok(item_type) => item_type,
+        Err(err) => return Ok(HttpResponse::BadRequest().content_type(PLAINTEXT).body(err.to_string())),
+    };
+    if !data.accepted_item_types.contains(&item_type) {
+        return Ok(messages::response_with(
+            &req, StatusCode::UNPROCESSABLE_ENTITY, Message::ItemTypeRejected, format!("{:?}", item_type),
+        ));
     }
-        .with_status(StatusCode::NOT_FOUND)
+
+    let backend = data.backend_factory.open().compat()?;
+    let mut item = Item::new();
+    if item_type == ItemType::PROFILE {
+        if let Some(row) = backend.user_profile(&user_id).compat()? {
+            item.merge_from_bytes(&row.item_bytes)?;
+        }
+    }
+
+    let mut latest: Option<Item> = None;
+    if params.utc_offset_minutes.is_none() {
+        backend.user_items(&user_id, Timestamp::now(), &mut |row| {
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item_bytes)?;
+            latest = Some(item);
+            Ok(false)
+        }).compat()?;
+    }
+
+    item.timestamp_ms_utc = Timestamp::now().unix_utc_ms;
+    item.utc_offset_minutes = params.utc_offset_minutes
+        .or_else(|| latest.map(|item| item.utc_offset_minutes))
+        .unwrap_or(0);
+    // This is a new item, not a copy of the old one:
+    item.expires_ms_utc = 0;
+    item.clear_attachments();
+
+    match item_type {
+        ItemType::POST => { item.mut_post(); },
+        ItemType::PROFILE => { item.mut_profile(); },
+        ItemType::POLL => { item.mut_poll(); },
+        ItemType::VOTE => { item.mut_vote(); },
+        ItemType::COMMENT => { item.mut_comment(); },
+        ItemType::DELETE => { item.mut_delete(); },
+        ItemType::REACTION => { item.mut_reaction(); },
+        ItemType::UNKNOWN => {},
+    }
+
+    Ok(
+        policy::Cache::NoStore.apply(&mut proto_ok())
+        .body(item.write_to_bytes()?)
+    )
+}
+
+/// A "not found" page, in the request's language.
+fn file_not_found(req: &HttpRequest, message: Message) -> Result<HttpResponse, Error> {
+    error_page(req, StatusCode::NOT_FOUND, Message::NotFoundTitle, message, vec![])
+}
+
+/// An item that its author deleted. (See: `Delete`)
+fn item_gone(req: &HttpRequest) -> Result<HttpResponse, Error> {
+    error_page(req, StatusCode::GONE, Message::GoneTitle, Message::ItemDeleted, vec![])
+}
+
+/// `mirrors` are other servers' URLs for what wasn't found. (See: mirrors.rs)
+fn error_page(req: &HttpRequest, status: StatusCode, title: Message, message: Message, mirrors: Vec<String>) -> Result<HttpResponse, Error> {
+    let lang = messages::lang(req);
+    let page = NotFoundPage {
+        lang: lang.tag(),
+        title: title.text(lang),
+        message: message.text(lang),
+        mirrors_message: Message::MirrorsFound.text(lang),
+        mirrors,
+    };
+    Ok(
+        HttpResponse::build(status)
+        .content_type("text/html; charset=utf-8")
+        .header("Content-Language", lang.tag())
+        .header("Vary", "Accept-Language")
+        .body(page.render()?)
+    )
 }
 
 /// `/u/{userID}/profile/`
@@ -980,7 +4128,7 @@ async fn show_profile(
     let row = match row {
         Some(r) => r,
         None => {
-            return Ok(HttpResponse::NotFound().body("No such user, or profile."))
+            return Ok(messages::response(&req, StatusCode::NOT_FOUND, Message::NoSuchUserOrProfile))
         }
     };
 
@@ -990,6 +4138,10 @@ async fn show_profile(
     let nav = vec![
         Nav::Text(display_name.clone()),
         // TODO: Add an Edit link. Make abstract w/ a link provider trait.
+        Nav::Link{
+            text: "History".into(),
+            href: format!("/u/{}/profile/history/", user_id.to_base58()),
+        },
         Nav::Link{
             text: "Home".into(),
             href: "/".into(),
@@ -999,6 +4151,7 @@ async fn show_profile(
     let timestamp_utc_ms = item.timestamp_ms_utc;
     let utc_offset_minutes = item.utc_offset_minutes;
     let text = std::mem::take(&mut item.mut_profile().about);
+    let license = item.get_profile().get_license().to_string();
 
     let follows = std::mem::take(&mut item.get_profile()).follows.to_vec();
     let follows = follows.into_iter().map(|mut follow: crate::protos::Follow | -> Result<ProfileFollow, Error>{
@@ -1019,16 +4172,78 @@ async fn show_profile(
         utc_offset_minutes,
         user_id: row.user,
         signature: row.signature,
+        license,
     };
 
     Ok(page.respond_to(&req).await?)
 }
 
+/// Changes between revisions of a user's profile, newest first.
+/// `/u/{user_id}/profile/history/`
+async fn show_profile_history(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let max_revisions = 20;
+
+    let mut profiles: Vec<(Signature, Item)> = vec![];
+    let backend = data.backend_factory.open().compat()?;
+    backend.user_items(&user_id, Timestamp::now(), &mut |row| {
+        let mut item = Item::new();
+        item.merge_from_bytes(&row.item_bytes)?;
+        if item.has_profile() {
+            profiles.push((row.signature, item));
+        }
+        // One extra, so the oldest one we show has something to compare to:
+        Ok(profiles.len() <= max_revisions)
+    }).compat()?;
+
+    if profiles.is_empty() {
+        return file_not_found(&req, Message::NoSuchUserOrProfile);
+    }
+
+    let revisions = profiles.iter().enumerate().take(max_revisions).map(|(i, (signature, item))| {
+        let diff = profiles.get(i + 1).map(|(_, older)| {
+            ProfileDiff::new(older.get_profile(), item.get_profile())
+        });
+        ProfileRevision {
+            signature: signature.clone(),
+            timestamp_utc_ms: item.timestamp_ms_utc,
+            utc_offset_minutes: item.utc_offset_minutes,
+            diff,
+        }
+    }).collect();
+
+    let display_name = profiles[0].1.get_profile().display_name.clone();
+    let page = ProfileHistoryPage {
+        nav: vec![
+            Nav::Text(display_name.clone()),
+            Nav::Link{
+                text: "Profile".into(),
+                href: format!("/u/{}/profile/", user_id.to_base58()),
+            },
+            Nav::Link{
+                text: "Home".into(),
+                href: "/".into(),
+            },
+        ],
+        user_id,
+        display_name,
+        revisions,
+    };
+
+    Ok(page.respond_to(&req).await?)
+}
 
 #[derive(Template)]
 #[template(path = "not_found.html")]
 struct NotFoundPage {
-    message: String,
+    lang: &'static str,
+    title: &'static str,
+    message: &'static str,
+    mirrors_message: &'static str,
+    mirrors: Vec<String>,
 }
 
 #[derive(Template)]
@@ -1042,6 +4257,40 @@ struct IndexPage {
 
     /// Should we show author info w/ links to their profiles?
     show_authors: bool,
+
+    /// If set, show a search form with this query.
+    search: Option<String>,
+}
+
+/// Shown instead of an empty homepage on new servers.
+#[derive(Template)]
+#[template(path = "setup.html")]
+struct SetupPage {
+    nav: Vec<Nav>,
+
+    /// Has a server user been added?
+    has_user: bool,
+
+    /// Can the user be added from the page?
+    setup_code: bool,
+
+    /// Can we link to the web client?
+    web_client: bool,
+}
+
+#[derive(Template)]
+#[template(path = "gallery.html")]
+struct GalleryPage {
+    nav: Vec<Nav>,
+    images: Vec<GalleryImage>,
+    display_message: Option<String>,
+}
+
+struct GalleryImage {
+    user_id: UserID,
+    signature: Signature,
+    file_name: String,
+    title: String,
 }
 
 #[derive(Template)]
@@ -1055,6 +4304,27 @@ struct ProfilePage {
     follows: Vec<ProfileFollow>,
     timestamp_utc_ms: i64,
     utc_offset_minutes: i32,
+
+    /// The default license for the user's posts. An SPDX identifier, or "".
+    license: String,
+}
+
+#[derive(Template)]
+#[template(path = "profile_history.html")]
+struct ProfileHistoryPage {
+    nav: Vec<Nav>,
+    user_id: UserID,
+    display_name: String,
+    revisions: Vec<ProfileRevision>,
+}
+
+struct ProfileRevision {
+    signature: Signature,
+    timestamp_utc_ms: i64,
+    utc_offset_minutes: i32,
+
+    /// Changes since the previous revision, if we have it.
+    diff: Option<ProfileDiff>,
 }
 
 #[derive(Template)]
@@ -1066,10 +4336,109 @@ struct PostPage {
     display_name: String,
     text: String,
     title: String,
+    /// If set, hide the post's contents behind it. (See: `Post.content_warning`)
+    content_warning: String,
+    location: Option<PostLocation>,
+    timestamp_utc_ms: i64,
+    utc_offset_minutes: i32,
+
+    /// Comments from people this user follows.
+    comments: Vec<CommentView>,
+
+    /// Replies from the Fediverse. (See: activitypub)
+    remote_replies: Vec<RemoteReplyView>,
+
+    /// Show a form for visitors to queue comments?
+    anonymous_comments: bool,
+
+    /// The size of the item itself.
+    item_bytes: u64,
+    attachments: Vec<AttachmentView>,
+
+    /// An SPDX license identifier, or "".
+    license: String,
+}
+
+struct AttachmentView {
+    name: String,
+    /// From the item's list of attachments.
+    size: u64,
+    /// Have we received the file yet?
+    uploaded: bool,
+}
+
+struct CommentView {
+    user_id: UserID,
+    signature: Signature,
+    display_name: String,
+    text: String,
+    timestamp_utc_ms: i64,
+    utc_offset_minutes: i32,
+
+    /// How deeply this comment is nested in the thread. 0 = a reply to the item itself.
+    depth: usize,
+}
+
+struct RemoteReplyView {
+    /// URLs of the author and the reply, on their own server.
+    actor: String,
+    url: String,
+    actor_name: String,
+    text: String,
+    timestamp_utc_ms: i64,
+}
+
+struct PostLocation {
+    place_name: String,
+
+    /// Rounded to the precision the author chose.
+    coordinates: String,
+
+    /// Can we link to ./map.png?
+    has_map: bool,
+}
+
+#[derive(Template)]
+#[template(path = "poll.html")]
+struct PollPage {
+    nav: Vec<Nav>,
+    user_id: UserID,
+    signature: Signature,
+    display_name: String,
+    question: String,
+    options: Vec<String>,
+
+    /// Vote counts for each option, once the poll has closed.
+    counts: Option<Vec<u64>>,
+    close_ms_utc: i64,
     timestamp_utc_ms: i64,
     utc_offset_minutes: i32,
+}
 
-    // TODO: Include comments from people this user follows.
+#[derive(Template)]
+#[template(path = "stats.html")]
+struct StatsPage {
+    nav: Vec<Nav>,
+    user_id: UserID,
+    months: Vec<backend::MonthCount>,
+    /// New followers per month. (Those who still follow.)
+    follower_months: Vec<backend::MonthCount>,
+    reaction_months: Vec<backend::MonthCount>,
+    top_items: Vec<TopItem>,
+
+    /// Does this server keep view counts? (If not, top_items will be empty.)
+    count_views: bool,
+    followers: u64,
+
+    /// From the last `feoblog linkcheck`, if any.
+    dead_links: Vec<backend::DeadLink>,
+}
+
+struct TopItem {
+    signature: Signature,
+    /// May be ""
+    title: String,
+    views: i64,
 }
 
 struct ProfileFollow {
@@ -1082,20 +4451,33 @@ struct ProfileFollow {
 struct IndexPageItem {
     row: ItemDisplayRow,
     item: Item,
+
+    /// How many comments it has, if we counted. (See: `add_reply_counts`)
+    replies: Option<usize>,
 }
 
 impl IndexPageItem {
     fn item(&self) -> &Item { &self.item }
     fn row(&self) -> &ItemDisplayRow { &self.row }
 
+    /// ex: "3 replies". None if we didn't count, or there aren't any.
+    fn replies_text(&self) -> Option<String> {
+        match self.replies {
+            None | Some(0) => None,
+            Some(1) => Some("1 reply".into()),
+            Some(count) => Some(format!("{} replies", count)),
+        }
+    }
+
     fn display_name(&self) -> Cow<'_, str>{
         self.row.display_name
             .as_ref()
             .map(|n| n.trim())
             .map(|n| if n.is_empty() { None } else { Some (n) })
             .flatten()
+            // Saved before we refused these. (See: confusables.rs)
+            .filter(|n| !crate::confusables::looks_like_user_id(n))
             .map(|n| n.into())
-            // TODO: Detect/protect against someone setting a userID that mimics a pubkey?
             .unwrap_or_else(|| self.row.item.user.to_base58().into())
     }
 }
@@ -1103,6 +4485,14 @@ impl IndexPageItem {
 
 
 
+/// Same as `display_by_default`, for ItemListEntries.
+fn shown_by_default(item_type: ItemType) -> bool {
+    match item_type {
+        ItemType::POST | ItemType::POLL => true,
+        ItemType::UNKNOWN | ItemType::PROFILE | ItemType::VOTE | ItemType::COMMENT | ItemType::DELETE | ItemType::REACTION => false,
+    }
+}
+
 fn display_by_default(item: &Item) -> bool {
     let item_type = match &item.item_type {
         // Don't display items we can't find a type for. (newer than this server knows about):
@@ -1114,6 +4504,13 @@ fn display_by_default(item: &Item) -> bool {
     match item_type {
         ItemType::post(_) => true,
         ItemType::profile(_) => false,
+        ItemType::poll(_) => true,
+        ItemType::vote(_) => false,
+        // Shown under the item they reply to:
+        ItemType::comment(_) => false,
+        ItemType::delete(_) => false,
+        // Counted on the item they react to:
+        ItemType::reaction(_) => false,
     }
 }
 
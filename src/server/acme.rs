@@ -0,0 +1,446 @@
+//! Gets and renews TLS certificates from Let's Encrypt, or another ACME
+//! certificate authority. (`feoblog serve --acme-domain`)
+//!
+//! We use the HTTP-01 challenge, so the domains must also reach this server
+//! over plain HTTP on port 80. (ex: `--bind 0.0.0.0:80`) Keys and certificates
+//! are kept in a directory next to the SQLite file. (ex: `feoblog.sqlite3.acme/`)
+//!
+//! See: <https://www.rfc-editor.org/rfc/rfc8555>
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use actix_web::client::Client;
+use actix_web::web::{Bytes, Data, Path};
+use actix_web::HttpResponse;
+use failure::{Error as FailError, ResultExt, bail, format_err};
+use ring::rand::SystemRandom;
+use ring::signature::{self, EcdsaKeyPair, KeyPair as _};
+use rustls::internal::pemfile;
+use rustls::sign::CertifiedKey;
+use rustls::{ClientHello, NoClientAuth, ResolvesServerCert, ServerConfig};
+use serde_json::{Value, json};
+use sodiumoxide::crypto::hash::sha256;
+
+use super::{AppData, Error, PLAINTEXT, der};
+
+/// How often we check whether the certificate needs renewing.
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// Try again sooner if getting a certificate failed.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Let's Encrypt's certificates last 90 days. They suggest renewing after 60.
+const RENEW_AFTER: Duration = Duration::from_secs(60 * 24 * 60 * 60);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_DELAY: Duration = Duration::from_secs(2);
+const MAX_POLLS: usize = 30;
+
+/// Certificates, and the challenges we answer to get them.
+pub(crate) struct Acme {
+    domains: Vec<String>,
+    email: Option<String>,
+    directory_url: String,
+
+    /// Where keys and certificates are kept.
+    dir: PathBuf,
+
+    /// HTTP-01 tokens, and their key authorizations.
+    challenges: RwLock<HashMap<String, String>>,
+    cert: RwLock<Option<CertifiedKey>>,
+}
+
+impl Acme {
+    pub fn new(domains: Vec<String>, email: Option<String>, directory_url: String, sqlite_file: &str) -> Result<Self, FailError> {
+        let dir = PathBuf::from(format!("{}.acme", sqlite_file));
+        fs::create_dir_all(&dir).with_context(|_| format!("Error creating {}", dir.display()))?;
+
+        let acme = Acme {
+            domains,
+            email,
+            directory_url,
+            dir,
+            challenges: RwLock::new(HashMap::new()),
+            cert: RwLock::new(None),
+        };
+        if acme.cert_path().exists() {
+            let cert = acme.load_cert().context("Error loading saved certificate")?;
+            *acme.cert.write().expect("cert lock") = Some(cert);
+        }
+        Ok(acme)
+    }
+
+    fn cert_path(&self) -> PathBuf { self.dir.join("cert.pem") }
+    fn cert_key_path(&self) -> PathBuf { self.dir.join("cert-key.pem") }
+    fn account_key_path(&self) -> PathBuf { self.dir.join("account-key.pem") }
+
+    /// The domains the saved certificate was issued for, one per line.
+    fn domains_path(&self) -> PathBuf { self.dir.join("domains.txt") }
+
+    fn load_cert(&self) -> Result<CertifiedKey, FailError> {
+        let chain = fs::read(self.cert_path())?;
+        let certs = pemfile::certs(&mut &chain[..])
+            .map_err(|_| format_err!("Invalid certificate in {}", self.cert_path().display()))?;
+        let key = der::pem_decode(&fs::read_to_string(self.cert_key_path())?)?;
+        let key = rustls::sign::any_ecdsa_type(&rustls::PrivateKey(key))
+            .map_err(|_| format_err!("Invalid key in {}", self.cert_key_path().display()))?;
+        Ok(CertifiedKey::new(certs, Arc::new(key)))
+    }
+
+    /// True if we have no certificate for our domains, or it's due for renewal.
+    fn needs_renewal(&self) -> bool {
+        let saved_domains = fs::read_to_string(self.domains_path()).unwrap_or_default();
+        if saved_domains.lines().collect::<Vec<_>>() != self.domains {
+            return true;
+        }
+        let issued = fs::metadata(self.cert_path()).and_then(|meta| meta.modified());
+        match issued {
+            Ok(issued) => issued.elapsed().map(|age| age > RENEW_AFTER).unwrap_or(false),
+            Err(_) => true,
+        }
+    }
+
+    async fn renew(&self) -> Result<(), FailError> {
+        let account_key = load_or_create_key(&self.account_key_path(), &signature::ECDSA_P256_SHA256_FIXED_SIGNING)?;
+        let mut client = AcmeClient::new(&self.directory_url, account_key).await?;
+        client.new_account(self.email.as_deref()).await?;
+
+        let identifiers: Vec<Value> = self.domains.iter()
+            .map(|domain| json!({"type": "dns", "value": domain}))
+            .collect();
+        let new_order = client.directory_url("newOrder")?;
+        let reply = client.post(&new_order, Some(&json!({"identifiers": identifiers}))).await?;
+        let order_url = reply.location.clone().ok_or_else(|| format_err!("No URL for the new order"))?;
+        let order = reply.json()?;
+
+        for authz_url in order["authorizations"].as_array().cloned().unwrap_or_default() {
+            let authz_url = authz_url.as_str().ok_or_else(|| format_err!("Invalid authorization URL"))?;
+            self.authorize(&mut client, authz_url).await?;
+        }
+        client.poll(&order_url, &["ready", "valid"]).await?;
+
+        let rng = SystemRandom::new();
+        let cert_key = EcdsaKeyPair::generate_pkcs8(&signature::ECDSA_P256_SHA256_ASN1_SIGNING, &rng)
+            .map_err(|_| format_err!("Error generating a key"))?;
+        let csr = csr(&self.domains, cert_key.as_ref())?;
+        let finalize = order["finalize"].as_str().ok_or_else(|| format_err!("No finalize URL"))?;
+        client.post(finalize, Some(&json!({"csr": base64_url(&csr)}))).await?;
+        let order = client.poll(&order_url, &["valid"]).await?;
+
+        let cert_url = order["certificate"].as_str().ok_or_else(|| format_err!("No certificate URL"))?;
+        let chain = client.post(cert_url, None).await?.body;
+
+        // Write the key first. A certificate without its key is no use:
+        fs::write(self.cert_key_path(), der::pem_encode("PRIVATE KEY", cert_key.as_ref()))?;
+        fs::write(self.cert_path(), &chain)?;
+        fs::write(self.domains_path(), self.domains.join("\n"))?;
+
+        let cert = self.load_cert()?;
+        *self.cert.write().expect("cert lock") = Some(cert);
+        Ok(())
+    }
+
+    /// Prove that we control one of the domains.
+    async fn authorize(&self, client: &mut AcmeClient, authz_url: &str) -> Result<(), FailError> {
+        let authz = client.post(authz_url, None).await?.json()?;
+        if authz["status"] == "valid" {
+            // Already proven, recently.
+            return Ok(());
+        }
+        let challenge = authz["challenges"].as_array()
+            .and_then(|challenges| challenges.iter().find(|c| c["type"] == "http-01"))
+            .ok_or_else(|| format_err!("No http-01 challenge for {}", authz["identifier"]["value"]))?;
+        let token = challenge["token"].as_str().ok_or_else(|| format_err!("No challenge token"))?;
+        let url = challenge["url"].as_str().ok_or_else(|| format_err!("No challenge URL"))?;
+
+        let key_authorization = format!("{}.{}", token, client.thumbprint());
+        self.challenges.write().expect("challenges lock").insert(token.to_string(), key_authorization);
+
+        // An empty object tells the server we're ready to be checked:
+        let result = match client.post(url, Some(&json!({}))).await {
+            Ok(_) => client.poll(authz_url, &["valid"]).await.map(|_| ()),
+            Err(err) => Err(err),
+        };
+        self.challenges.write().expect("challenges lock").remove(token);
+        result
+    }
+}
+
+/// A TLS config that serves the current certificate, as it's renewed.
+pub(crate) fn server_config(acme: Arc<Acme>) -> ServerConfig {
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.cert_resolver = Arc::new(Resolver(acme));
+    config
+}
+
+struct Resolver(Arc<Acme>);
+
+impl ResolvesServerCert for Resolver {
+    fn resolve(&self, _: ClientHello) -> Option<CertifiedKey> {
+        self.0.cert.read().ok()?.clone()
+    }
+}
+
+/// Runs forever. Spawn it on the server's runtime.
+pub(crate) async fn renew_loop(acme: Arc<Acme>) {
+    loop {
+        let mut wait = CHECK_INTERVAL;
+        if acme.needs_renewal() {
+            log::info!("Getting a certificate for {}", acme.domains.join(", "));
+            match acme.renew().await {
+                Ok(()) => log::info!("Got a certificate for {}", acme.domains.join(", ")),
+                Err(err) => {
+                    log::warn!("Error getting a certificate: {}", err);
+                    wait = RETRY_INTERVAL;
+                }
+            }
+        }
+        actix_web::rt::time::delay_for(wait).await;
+    }
+}
+
+/// `/.well-known/acme-challenge/{token}`
+pub(crate) async fn challenge(
+    data: Data<AppData>,
+    Path((token,)): Path<(String,)>,
+) -> Result<HttpResponse, Error> {
+    let key_authorization = data.acme.as_ref()
+        .and_then(|acme| acme.challenges.read().ok()?.get(&token).cloned());
+    Ok(match key_authorization {
+        Some(key_authorization) => HttpResponse::Ok().content_type(PLAINTEXT).body(key_authorization),
+        None => HttpResponse::NotFound().content_type(PLAINTEXT).body("No such challenge"),
+    })
+}
+
+/// Signs requests to the ACME server with our account key.
+struct AcmeClient {
+    http: Client,
+    directory: Value,
+    key: EcdsaKeyPair,
+
+    /// Our account's URL, once we have one.
+    kid: Option<String>,
+    nonce: Option<String>,
+}
+
+struct Reply {
+    location: Option<String>,
+    body: Bytes,
+}
+
+impl Reply {
+    fn json(&self) -> Result<Value, FailError> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+}
+
+impl AcmeClient {
+    async fn new(directory_url: &str, key: EcdsaKeyPair) -> Result<Self, FailError> {
+        let http = Client::builder().timeout(REQUEST_TIMEOUT).finish();
+        let mut response = http.get(directory_url).send().await
+            .map_err(|err| format_err!("Error fetching {}: {}", directory_url, err))?;
+        if !response.status().is_success() {
+            bail!("Error fetching {}: {}", directory_url, response.status());
+        }
+        let directory = response.json().await
+            .map_err(|err| format_err!("Error reading {}: {}", directory_url, err))?;
+        Ok(AcmeClient{ http, directory, key, kid: None, nonce: None })
+    }
+
+    fn directory_url(&self, name: &str) -> Result<String, FailError> {
+        self.directory[name].as_str()
+            .map(str::to_string)
+            .ok_or_else(|| format_err!("No {} in the ACME directory", name))
+    }
+
+    /// Creates an account the first time. After that, finds the existing one.
+    async fn new_account(&mut self, email: Option<&str>) -> Result<(), FailError> {
+        let mut account = json!({"termsOfServiceAgreed": true});
+        if let Some(email) = email {
+            account["contact"] = json!([format!("mailto:{}", email)]);
+        }
+        let new_account = self.directory_url("newAccount")?;
+        let reply = self.post(&new_account, Some(&account)).await?;
+        self.kid = Some(reply.location.ok_or_else(|| format_err!("No account URL"))?);
+        Ok(())
+    }
+
+    /// POST a signed request. With no `payload`, this is a "POST-as-GET".
+    async fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<Reply, FailError> {
+        // Nonces can expire. The server tells us so, with a new one to retry with:
+        let mut retried = false;
+        loop {
+            let nonce = match self.nonce.take() {
+                Some(nonce) => nonce,
+                None => self.new_nonce().await?,
+            };
+            let body = self.jws(url, &nonce, payload)?;
+            let mut response = self.http.post(url)
+                .content_type("application/jose+json")
+                .send_body(body.to_string()).await
+                .map_err(|err| format_err!("Error sending to {}: {}", url, err))?;
+
+            self.nonce = header(response.headers(), "Replay-Nonce");
+            let location = header(response.headers(), "Location");
+            let status = response.status();
+            let body = response.body().limit(1024 * 1024).await
+                .map_err(|err| format_err!("Error reading {}: {}", url, err))?;
+            if status.is_success() {
+                return Ok(Reply{ location, body });
+            }
+
+            let problem: Value = serde_json::from_slice(&body).unwrap_or(Value::Null);
+            if problem["type"] == "urn:ietf:params:acme:error:badNonce" && !retried {
+                retried = true;
+                continue;
+            }
+            bail!("{} responded with {}: {}", url, status, problem["detail"]);
+        }
+    }
+
+    async fn new_nonce(&self) -> Result<String, FailError> {
+        let url = self.directory_url("newNonce")?;
+        let response = self.http.head(&url).send().await
+            .map_err(|err| format_err!("Error fetching {}: {}", url, err))?;
+        header(response.headers(), "Replay-Nonce").ok_or_else(|| format_err!("No nonce from {}", url))
+    }
+
+    /// Wait for an order or authorization to reach one of `statuses`.
+    async fn poll(&mut self, url: &str, statuses: &[&str]) -> Result<Value, FailError> {
+        for _ in 0..MAX_POLLS {
+            let value = self.post(url, None).await?.json()?;
+            let status = value["status"].as_str().unwrap_or_default();
+            if statuses.contains(&status) {
+                return Ok(value);
+            }
+            if status == "invalid" {
+                bail!("{} is invalid: {}", url, value);
+            }
+            actix_web::rt::time::delay_for(POLL_DELAY).await;
+        }
+        bail!("Timed out waiting for {}", url)
+    }
+
+    /// A JSON Web Signature, in the "flattened" JSON serialization.
+    fn jws(&self, url: &str, nonce: &str, payload: Option<&Value>) -> Result<Value, FailError> {
+        let mut protected = json!({"alg": "ES256", "nonce": nonce, "url": url});
+        match &self.kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = self.jwk(),
+        }
+        let protected = base64_url(protected.to_string().as_bytes());
+        let payload = match payload {
+            Some(payload) => base64_url(payload.to_string().as_bytes()),
+            None => String::new(),
+        };
+
+        let signature = self.key.sign(&SystemRandom::new(), format!("{}.{}", protected, payload).as_bytes())
+            .map_err(|_| format_err!("Error signing request"))?;
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": base64_url(signature.as_ref()),
+        }))
+    }
+
+    fn jwk(&self) -> Value {
+        let (x, y) = self.public_point();
+        json!({"crv": "P-256", "kty": "EC", "x": x, "y": y})
+    }
+
+    /// Identifies our account key in key authorizations. (RFC 7638)
+    fn thumbprint(&self) -> String {
+        // Members must be in this order, with no whitespace:
+        let (x, y) = self.public_point();
+        let jwk = format!(r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#, x, y);
+        base64_url(sha256::hash(jwk.as_bytes()).as_ref())
+    }
+
+    /// The base64url-encoded coordinates of our public key.
+    fn public_point(&self) -> (String, String) {
+        // An uncompressed point: 0x04, then x and y.
+        let point = &self.key.public_key().as_ref()[1..];
+        let (x, y) = point.split_at(point.len() / 2);
+        (base64_url(x), base64_url(y))
+    }
+}
+
+fn header(headers: &actix_web::http::HeaderMap, name: &str) -> Option<String> {
+    headers.get(name).and_then(|value| value.to_str().ok()).map(str::to_string)
+}
+
+fn base64_url(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn load_or_create_key(path: &std::path::Path, alg: &'static signature::EcdsaSigningAlgorithm) -> Result<EcdsaKeyPair, FailError> {
+    let pkcs8 = if path.exists() {
+        der::pem_decode(&fs::read_to_string(path)?)?
+    } else {
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(alg, &SystemRandom::new())
+            .map_err(|_| format_err!("Error generating a key"))?;
+        fs::write(path, der::pem_encode("PRIVATE KEY", pkcs8.as_ref()))
+            .with_context(|_| format!("Error writing {}", path.display()))?;
+        pkcs8.as_ref().to_vec()
+    };
+    EcdsaKeyPair::from_pkcs8(alg, &pkcs8).map_err(|_| format_err!("Invalid key in {}", path.display()))
+}
+
+// DER-encoded object identifiers:
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+const OID_EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+const OID_P256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+const OID_SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11];
+const OID_EXTENSION_REQUEST: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x0e];
+const OID_ECDSA_SHA256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x02];
+
+/// A PKCS#10 certificate signing request for `domains`.
+/// See: <https://www.rfc-editor.org/rfc/rfc2986>
+fn csr(domains: &[String], pkcs8: &[u8]) -> Result<Vec<u8>, FailError> {
+    fn seq(parts: &[&[u8]]) -> Vec<u8> { der::encode(0x30, &parts.concat()) }
+    let key = EcdsaKeyPair::from_pkcs8(&signature::ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8)
+        .map_err(|_| format_err!("Invalid certificate key"))?;
+
+    // CAs only look at the subject alternative names, but the common name is
+    // customary. It's limited to 64 characters, though.
+    let subject = match domains.first() {
+        Some(domain) if domain.len() <= 64 => {
+            let common_name = seq(&[&der::encode(0x06, OID_COMMON_NAME), &der::encode(0x0c, domain.as_bytes())]);
+            seq(&[&der::encode(0x31, &common_name)])
+        },
+        _ => seq(&[]),
+    };
+
+    let mut public_key = vec![0];
+    public_key.extend_from_slice(key.public_key().as_ref());
+    let spki = seq(&[
+        &seq(&[&der::encode(0x06, OID_EC_PUBLIC_KEY), &der::encode(0x06, OID_P256)]),
+        &der::encode(0x03, &public_key),
+    ]);
+
+    // Each name is a [2] dNSName:
+    let names: Vec<u8> = domains.iter().flat_map(|domain| der::encode(0x82, domain.as_bytes())).collect();
+    let alt_names = seq(&[&der::encode(0x06, OID_SUBJECT_ALT_NAME), &der::encode(0x04, &seq(&[&names]))]);
+    let extension_request = seq(&[&der::encode(0x06, OID_EXTENSION_REQUEST), &der::encode(0x31, &seq(&[&alt_names]))]);
+
+    let info = seq(&[
+        &der::encode(0x02, &[0]), // version 1
+        &subject,
+        &spki,
+        &der::encode(0xa0, &extension_request),
+    ]);
+
+    let signature = key.sign(&SystemRandom::new(), &info)
+        .map_err(|_| format_err!("Error signing certificate request"))?;
+    let mut signature_bits = vec![0];
+    signature_bits.extend_from_slice(signature.as_ref());
+    Ok(seq(&[
+        &info,
+        &seq(&[&der::encode(0x06, OID_ECDSA_SHA256)]),
+        &der::encode(0x03, &signature_bits),
+    ]))
+}
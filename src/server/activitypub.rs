@@ -0,0 +1,403 @@
+//! Publishes the server's users as ActivityPub actors, so that people on
+//! Mastodon (and other Fediverse servers) can follow them.
+//!
+//! Followers receive a user's posts. (See: delivery.rs) Of what they send
+//! back, we keep follows and replies. (See: inbox.rs) Likes, boosts, etc.
+//! are ignored.
+//!
+//! FeoBlog users' keys can't sign ActivityPub's HTTP signatures (which are
+//! RSA in practice), so the server signs on their behalf with its own key.
+//! (`feoblog serve --activitypub-key`) Every actor publishes the same public key.
+//!
+//! See: <https://www.w3.org/TR/activitypub/>
+
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+use actix_web::client::Client;
+use actix_web::http::header::HttpDate;
+use actix_web::http::Uri;
+use actix_web::web::{Data, Path, Query};
+use actix_web::{HttpRequest, HttpResponse};
+use failure::{Error as FailError, ResultExt, bail, format_err};
+use protobuf::Message as _;
+use ring::rand::SystemRandom;
+use ring::signature::{self, KeyPair as _, RsaKeyPair};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use sodiumoxide::crypto::hash::sha256;
+
+use crate::backend::{Backend, ItemRow, Signature, Timestamp, UserID};
+use crate::markdown::ToHTML;
+use crate::protos::Item;
+use super::{AppData, Error, Pagination, Paginator, base_url, der, webfinger};
+
+mod delivery;
+pub(crate) mod inbox;
+pub(crate) use self::delivery::{deliver_post, delivery_loop};
+pub(crate) use self::inbox::post_inbox;
+
+pub(crate) const ACTIVITY_JSON: &str = "application/activity+json";
+const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The largest remote actor document we'll fetch.
+const MAX_ACTOR_BYTES: usize = 1024 * 1024;
+
+/// Signs requests on behalf of the server's users.
+pub(crate) struct ServerKey {
+    key_pair: RsaKeyPair,
+    public_key_pem: String,
+}
+
+impl ServerKey {
+    /// Load an RSA private key in PKCS#8 format, PEM or DER encoded.
+    /// ex: `openssl genpkey -algorithm RSA -pkeyopt rsa_keygen_bits:2048 -out activitypub.pem`
+    pub fn load(path: &str) -> Result<Self, FailError> {
+        let bytes = fs::read(path).with_context(|_| format!("Error reading {}", path))?;
+        let der = if bytes.starts_with(b"-----BEGIN") {
+            der::pem_decode(&String::from_utf8_lossy(&bytes))?
+        } else {
+            bytes
+        };
+
+        let key_pair = RsaKeyPair::from_pkcs8(&der)
+            .map_err(|err| format_err!("Invalid RSA key in {}: {:?}", path, err))?;
+        let public_key_pem = der::pem_encode("PUBLIC KEY", &rsa_spki(key_pair.public_key().as_ref()));
+        Ok(ServerKey{ key_pair, public_key_pem })
+    }
+
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, FailError> {
+        let mut signature = vec![0; self.key_pair.public_modulus_len()];
+        self.key_pair.sign(&signature::RSA_PKCS1_SHA256, &SystemRandom::new(), message, &mut signature)
+            .map_err(|_| format_err!("Error signing request"))?;
+        Ok(signature)
+    }
+}
+
+pub(crate) fn actor_url(base_url: &str, user: &UserID) -> String {
+    format!("{}/u/{}/actor", base_url, user.to_base58())
+}
+
+fn key_id(base_url: &str, user: &UserID) -> String {
+    format!("{}#main-key", actor_url(base_url, user))
+}
+
+fn object_url(base_url: &str, user: &UserID, signature: &Signature) -> String {
+    format!("{}/u/{}/i/{}/object", base_url, user.to_base58(), signature.to_base58())
+}
+
+fn activity_response(value: &Value) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(ACTIVITY_JSON)
+        .body(value.to_string())
+}
+
+fn not_found() -> HttpResponse {
+    HttpResponse::NotFound().content_type(super::PLAINTEXT).body("Not found")
+}
+
+/// Only users hosted by this server are published as actors.
+fn is_actor(data: &AppData, backend: &dyn Backend, user: &UserID) -> Result<bool, FailError> {
+    Ok(data.activitypub.is_some() && backend.server_user(user)?.is_some())
+}
+
+/// `/u/{user_id}/actor`
+pub(crate) async fn get_actor(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    let key = match &data.activitypub {
+        Some(key) if is_actor(&data, backend.as_ref(), &user_id).compat()? => key,
+        _ => return Ok(not_found()),
+    };
+
+    let mut profile = crate::protos::Profile::new();
+    if let Some(row) = backend.user_profile(&user_id).compat()? {
+        let mut item = Item::new();
+        item.merge_from_bytes(&row.item_bytes)?;
+        profile = item.take_profile();
+    }
+
+    let base_url = base_url(&req);
+    let actor = actor_url(&base_url, &user_id);
+    let user_url = format!("{}/u/{}/", base_url, user_id.to_base58());
+    let name = if profile.display_name.trim().is_empty() { user_id.to_base58() } else { profile.display_name.clone() };
+
+    Ok(activity_response(&json!({
+        "@context": ["https://www.w3.org/ns/activitystreams", "https://w3id.org/security/v1"],
+        "id": actor,
+        "type": "Person",
+        "preferredUsername": webfinger::username(backend.as_ref(), &user_id).compat()?,
+        "name": name,
+        "summary": profile.about.md_to_html(),
+        "url": user_url,
+        "inbox": format!("{}inbox", user_url),
+        "outbox": format!("{}outbox", user_url),
+        "followers": format!("{}followers", user_url),
+        "publicKey": {
+            "id": key_id(&base_url, &user_id),
+            "owner": actor,
+            "publicKeyPem": key.public_key_pem,
+        },
+    })))
+}
+
+/// We don't publish who the followers are, only how many there are.
+/// `/u/{user_id}/followers`
+pub(crate) async fn get_followers(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    if !is_actor(&data, backend.as_ref(), &user_id).compat()? {
+        return Ok(not_found());
+    }
+
+    let mut count = 0;
+    backend.activitypub_followers(&user_id, &mut |_| {
+        count += 1;
+        Ok(true)
+    }).compat()?;
+
+    Ok(activity_response(&json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": format!("{}/u/{}/followers", base_url(&req), user_id.to_base58()),
+        "type": "OrderedCollection",
+        "totalItems": count,
+    })))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct OutboxParams {
+    page: Option<String>,
+}
+
+/// A user's posts, as `Create` activities.
+/// `/u/{user_id}/outbox`
+pub(crate) async fn get_outbox(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    Query(params): Query<OutboxParams>,
+    Query(pagination): Query<Pagination>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    if !is_actor(&data, backend.as_ref(), &user_id).compat()? {
+        return Ok(not_found());
+    }
+
+    let base_url = base_url(&req);
+    let outbox = format!("{}/u/{}/outbox", base_url, user_id.to_base58());
+    if params.page.is_none() {
+        return Ok(activity_response(&json!({
+            "@context": "https://www.w3.org/ns/activitystreams",
+            "id": outbox,
+            "type": "OrderedCollection",
+            "first": format!("{}?page=true", outbox),
+        })));
+    }
+
+    let mut paginator = Paginator::new(
+        pagination,
+        |row: ItemRow| -> Result<(ItemRow, Item), FailError> {
+            let mut item = Item::new();
+            item.merge_from_bytes(&row.item_bytes)?;
+            Ok((row, item))
+        },
+        |(_, item): &(ItemRow, Item)| item.has_post(),
+    );
+    paginator.max_items = 20;
+    backend.user_items(&user_id, paginator.before(), &mut paginator.callback()).compat()?;
+
+    let mut page_id = format!("{}?page=true", outbox);
+    if let Some(before) = paginator.params.before {
+        page_id = format!("{}&before={}", page_id, before);
+    }
+    let mut page = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": page_id,
+        "type": "OrderedCollectionPage",
+        "partOf": outbox,
+        "orderedItems": paginator.items.iter()
+            .map(|(row, item)| create_activity(&base_url, &row.user, &row.signature, item))
+            .collect::<Vec<_>>(),
+    });
+    if paginator.has_more {
+        if let Some((row, _)) = paginator.items.last() {
+            page["next"] = json!(format!("{}?page=true&before={}", outbox, row.timestamp.unix_utc_ms));
+        }
+    }
+
+    Ok(activity_response(&page))
+}
+
+/// A post, as an ActivityPub object.
+/// `/u/{user_id}/i/{signature}/object`
+pub(crate) async fn get_object(
+    data: Data<AppData>,
+    Path((user_id, signature)): Path<(UserID, Signature)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    if !is_actor(&data, backend.as_ref(), &user_id).compat()? {
+        return Ok(not_found());
+    }
+
+    let row = match backend.user_item(&user_id, &signature).compat()? {
+        Some(row) => row,
+        None => return Ok(not_found()),
+    };
+    let mut item = Item::new();
+    item.merge_from_bytes(&row.item_bytes)?;
+    if !item.has_post() {
+        return Ok(not_found());
+    }
+
+    let mut object = post_object(&base_url(&req), &user_id, &signature, &item);
+    object["@context"] = json!("https://www.w3.org/ns/activitystreams");
+    Ok(activity_response(&object))
+}
+
+/// Posts with titles are `Article`s. Others are `Note`s.
+fn post_object(base_url: &str, user: &UserID, signature: &Signature, item: &Item) -> Value {
+    let actor = actor_url(base_url, user);
+    let post = item.get_post();
+    let mut object = json!({
+        "id": object_url(base_url, user, signature),
+        "type": "Note",
+        "attributedTo": actor,
+        "url": format!("{}/u/{}/i/{}/", base_url, user.to_base58(), signature.to_base58()),
+        "published": Timestamp{ unix_utc_ms: item.timestamp_ms_utc }.format_rfc3339(),
+        "content": post.get_body().md_to_html(),
+        "to": [PUBLIC],
+        "cc": [format!("{}/u/{}/followers", base_url, user.to_base58())],
+    });
+    if !post.get_title().trim().is_empty() {
+        object["type"] = json!("Article");
+        object["name"] = json!(post.get_title());
+    }
+    if !post.get_content_warning().trim().is_empty() {
+        // How Mastodon shows content warnings:
+        object["summary"] = json!(post.get_content_warning());
+        object["sensitive"] = json!(true);
+    }
+    object
+}
+
+fn create_activity(base_url: &str, user: &UserID, signature: &Signature, item: &Item) -> Value {
+    let object = post_object(base_url, user, signature, item);
+    json!({
+        "id": format!("{}#create", object["id"].as_str().unwrap_or_default()),
+        "type": "Create",
+        "actor": actor_url(base_url, user),
+        "published": object["published"].clone(),
+        "to": object["to"].clone(),
+        "cc": object["cc"].clone(),
+        "object": object,
+    })
+}
+
+async fn deliver(key: &ServerKey, key_id: &str, inbox: &str, activity: &Value) -> Result<(), FailError> {
+    let uri: Uri = inbox.parse()?;
+    let body = activity.to_string().into_bytes();
+    let mut request = Client::default().post(inbox)
+        .timeout(REQUEST_TIMEOUT)
+        .content_type(ACTIVITY_JSON);
+    for (name, value) in sign_request(key, key_id, "post", &uri, Some(&body))? {
+        request = request.header(name, value);
+    }
+
+    let response = request.send_body(body).await
+        .map_err(|err| format_err!("Error sending to {}: {}", inbox, err))?;
+    if !response.status().is_success() {
+        bail!("{} responded with {}", inbox, response.status());
+    }
+    Ok(())
+}
+
+/// Fetch a remote ActivityPub document. Signed, since some servers require it.
+async fn fetch(key: &ServerKey, key_id: &str, url: &str) -> Result<Value, FailError> {
+    let uri: Uri = url.parse()?;
+    let mut request = Client::default().get(url)
+        .timeout(REQUEST_TIMEOUT)
+        .header("Accept", ACTIVITY_JSON);
+    for (name, value) in sign_request(key, key_id, "get", &uri, None)? {
+        request = request.header(name, value);
+    }
+
+    let mut response = request.send().await
+        .map_err(|err| format_err!("Error fetching {}: {}", url, err))?;
+    if !response.status().is_success() {
+        bail!("Error fetching {}: {}", url, response.status());
+    }
+    let body = response.body().limit(MAX_ACTOR_BYTES).await
+        .map_err(|err| format_err!("Error reading {}: {}", url, err))?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// Headers for a signed request, per the draft HTTP Signatures spec that
+/// the Fediverse uses.
+/// See: <https://datatracker.ietf.org/doc/html/draft-cavage-http-signatures-12>
+fn sign_request(
+    key: &ServerKey,
+    key_id: &str,
+    method: &str,
+    uri: &Uri,
+    body: Option<&[u8]>,
+) -> Result<Vec<(&'static str, String)>, FailError> {
+    let host = uri.authority().ok_or_else(|| format_err!("No host in {}", uri))?.as_str();
+    let target = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    let date = HttpDate::from(SystemTime::now()).to_string();
+
+    let mut headers = vec![];
+    let mut signed = format!("(request-target): {} {}\nhost: {}\ndate: {}", method, target, host, date);
+    let mut signed_names = "(request-target) host date".to_string();
+    headers.push(("Date", date));
+    if let Some(body) = body {
+        let digest = format!("SHA-256={}", base64::encode(sha256::hash(body)));
+        signed += &format!("\ndigest: {}", digest);
+        signed_names += " digest";
+        headers.push(("Digest", digest));
+    }
+
+    let signature = base64::encode(key.sign(signed.as_bytes())?);
+    headers.push(("Signature", format!(
+        r#"keyId="{}",algorithm="rsa-sha256",headers="{}",signature="{}""#,
+        key_id, signed_names, signature,
+    )));
+    Ok(headers)
+}
+
+/// DER AlgorithmIdentifier for rsaEncryption, with NULL parameters.
+const RSA_ALGORITHM: &[u8] = &[
+    0x30, 0x0d, 0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01, 0x05, 0x00,
+];
+
+/// Wrap a PKCS#1 RSAPublicKey in a SubjectPublicKeyInfo, the usual format
+/// for "PUBLIC KEY" PEMs.
+fn rsa_spki(rsa_public_key: &[u8]) -> Vec<u8> {
+    // The first byte of a BIT STRING is the number of unused bits:
+    let mut bits = vec![0];
+    bits.extend_from_slice(rsa_public_key);
+
+    let mut spki = RSA_ALGORITHM.to_vec();
+    spki.extend(der::encode(0x03, &bits));
+    der::encode(0x30, &spki)
+}
+
+/// The PKCS#1 RSAPublicKey inside a SubjectPublicKeyInfo, if it's an RSA key.
+fn rsa_from_spki(spki: &[u8]) -> Option<&[u8]> {
+    let (spki, _) = der::decode(spki, 0x30)?;
+    if !spki.starts_with(RSA_ALGORITHM) {
+        return None;
+    }
+    let (bits, _) = der::decode(&spki[RSA_ALGORITHM.len()..], 0x03)?;
+    match bits.split_first() {
+        Some((0, key)) => Some(key),
+        _ => None,
+    }
+}
@@ -0,0 +1,132 @@
+//! Delivers new posts to followers' inboxes.
+//!
+//! When `put_item` accepts a post from a server user, it queues a delivery to
+//! each of their followers' inboxes. A background task sends queued posts,
+//! and retries failures with exponential backoff, like replication.rs.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use failure::Error;
+use protobuf::Message as _;
+use serde_json::json;
+
+use crate::backend::{Backend, Factory, ItemRow, QueuedDelivery, Timestamp};
+use crate::protos::Item;
+use crate::server::maintenance::Maintenance;
+use super::{ServerKey, create_activity, deliver, key_id};
+
+/// How often we check the queue.
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Give up on delivering a post after this many failures.
+const MAX_ATTEMPTS: u32 = 20;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(60);
+const MAX_BACKOFF: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// Queue a new post for delivery to the user's followers.
+pub(crate) fn deliver_post(
+    backend: &dyn Backend,
+    base_url: &str,
+    row: &ItemRow,
+    item: &Item,
+) -> Result<(), Error> {
+    if !item.has_post() || backend.server_user(&row.user)?.is_none() {
+        return Ok(());
+    }
+
+    // Many followers can share one server's inbox:
+    let mut inboxes = HashSet::new();
+    backend.activitypub_followers(&row.user, &mut |follower| {
+        inboxes.insert(follower.inbox);
+        Ok(true)
+    })?;
+
+    for inbox in inboxes {
+        backend.queue_activitypub_delivery(&QueuedDelivery {
+            inbox,
+            user: row.user.clone(),
+            signature: row.signature.clone(),
+            base_url: base_url.to_string(),
+            attempts: 0,
+        })?;
+    }
+    Ok(())
+}
+
+/// Runs forever. Spawn it on the server's runtime.
+pub(crate) async fn delivery_loop(factory: Box<dyn Factory>, maintenance: Arc<Maintenance>, key: Arc<ServerKey>) {
+    let mut interval = actix_web::rt::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        if maintenance.is_active() {
+            continue;
+        }
+        if let Err(err) = deliver_due(factory.as_ref(), &key).await {
+            log::warn!("Error delivering posts to ActivityPub followers: {}", err);
+        }
+    }
+}
+
+async fn deliver_due(factory: &dyn Factory, key: &ServerKey) -> Result<(), Error> {
+    let mut due = vec![];
+    factory.open()?.due_activitypub_deliveries(Timestamp::now(), &mut |delivery| {
+        due.push(delivery);
+        Ok(due.len() < 100)
+    })?;
+
+    // Don't wait on an unreachable server for every post queued for it:
+    let mut unreachable = HashSet::new();
+
+    for delivery in due {
+        if unreachable.contains(&delivery.inbox) {
+            continue;
+        }
+
+        let row = match factory.open()?.user_item(&delivery.user, &delivery.signature)? {
+            Some(row) => row,
+            None => {
+                // Removed since it was queued.
+                factory.open()?.finish_activitypub_delivery(&delivery)?;
+                continue;
+            }
+        };
+        let mut item = Item::new();
+        item.merge_from_bytes(&row.item_bytes)?;
+
+        let mut activity = create_activity(&delivery.base_url, &row.user, &row.signature, &item);
+        activity["@context"] = json!("https://www.w3.org/ns/activitystreams");
+        let key_id = key_id(&delivery.base_url, &row.user);
+
+        match deliver(key, &key_id, &delivery.inbox, &activity).await {
+            Ok(()) => factory.open()?.finish_activitypub_delivery(&delivery)?,
+            Err(err) => {
+                unreachable.insert(delivery.inbox.clone());
+                failed(factory, &delivery, &err.to_string())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn failed(factory: &dyn Factory, delivery: &QueuedDelivery, error: &str) -> Result<(), Error> {
+    let backend = factory.open()?;
+    if delivery.attempts + 1 >= MAX_ATTEMPTS {
+        log::warn!("Giving up delivering {} to {}: {}", delivery.signature.to_base58(), delivery.inbox, error);
+        return backend.finish_activitypub_delivery(delivery);
+    }
+
+    let retry_at = Timestamp {
+        unix_utc_ms: Timestamp::now().unix_utc_ms + backoff(delivery.attempts).as_millis() as i64,
+    };
+    backend.activitypub_delivery_failed(delivery, retry_at, error)
+}
+
+/// How long to wait after `attempts` previous failures.
+fn backoff(attempts: u32) -> Duration {
+    let backoff = MIN_BACKOFF.checked_mul(1 << attempts.min(16)).unwrap_or(MAX_BACKOFF);
+    backoff.min(MAX_BACKOFF)
+}
@@ -0,0 +1,425 @@
+//! Receives activities from other ActivityPub servers: follows, and replies
+//! to our users' posts.
+//!
+//! Replies are stored as plain text, and shown below the post they reply to.
+
+use std::time::{Duration, SystemTime};
+
+use actix_web::http::header::HttpDate;
+use actix_web::web::{Bytes, Data, Path};
+use actix_web::{HttpRequest, HttpResponse};
+use failure::{Error as FailError, ResultExt, bail, format_err};
+use protobuf::Message as _;
+use ring::signature;
+use serde_json::{Value, json};
+use sodiumoxide::crypto::hash::sha256;
+
+use crate::backend::{ActivityPubFollower, ActivityPubReply, Backend, Signature, Timestamp, UserID};
+use crate::protos::Item;
+use crate::server::{AppData, Error, PLAINTEXT, base_url, der, maintenance, remote};
+use super::{ServerKey, actor_url, deliver, fetch, is_actor, key_id, not_found, rsa_from_spki};
+
+/// Reject signed requests whose Date is further than this from our clock.
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// Replies longer than this (as text) are ignored.
+const MAX_REPLY_BYTES: usize = 8 * 1024;
+
+/// Stop storing replies to an item once it has this many.
+const MAX_REPLIES: usize = 200;
+
+/// Accepts follows, and replies to the user's posts. Everything else is ignored.
+/// `/u/{user_id}/inbox`
+pub(crate) async fn post_inbox(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    req: HttpRequest,
+    body: Bytes,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    let key = match &data.activitypub {
+        Some(key) if is_actor(&data, backend.as_ref(), &user_id).compat()? => key.clone(),
+        _ => return Ok(not_found()),
+    };
+    if let Some(retry_after) = data.maintenance.retry_after() {
+        return Ok(maintenance::unavailable(retry_after));
+    }
+
+    let activity: Value = match serde_json::from_slice(&body) {
+        Ok(activity) => activity,
+        Err(_) => return Ok(HttpResponse::BadRequest().content_type(PLAINTEXT).body("Invalid JSON")),
+    };
+
+    let base_url = base_url(&req);
+    let our_key_id = key_id(&base_url, &user_id);
+    let activity_actor = activity["actor"].as_str().unwrap_or_default();
+    let sender = match verify_request(&key, &our_key_id, &req, &body, activity_actor).await {
+        Ok(sender) => sender,
+        Err(err) => {
+            log::info!("Rejected ActivityPub request: {}", err);
+            return Ok(
+                HttpResponse::Unauthorized()
+                .content_type(PLAINTEXT)
+                .body(format!("Invalid HTTP signature: {}", err))
+            );
+        },
+    };
+    let sender_id = sender["id"].as_str().unwrap_or_default();
+    if activity["actor"].as_str() != Some(sender_id) {
+        return Ok(HttpResponse::Unauthorized().content_type(PLAINTEXT).body("Activity wasn't signed by its actor"));
+    }
+
+    let our_actor = actor_url(&base_url, &user_id);
+    match activity["type"].as_str() {
+        Some("Follow") if activity["object"].as_str() == Some(our_actor.as_str()) => {
+            let inbox = sender["endpoints"]["sharedInbox"].as_str()
+                .or_else(|| sender["inbox"].as_str());
+            let inbox = match inbox {
+                Some(inbox) => inbox.to_string(),
+                None => return Ok(HttpResponse::BadRequest().content_type(PLAINTEXT).body("Actor has no inbox")),
+            };
+            // We'll be delivering to it:
+            if let Err(err) = remote::check_public(&inbox).await {
+                return Ok(HttpResponse::BadRequest().content_type(PLAINTEXT).body(err.to_string()));
+            }
+            backend.add_activitypub_follower(&ActivityPubFollower{
+                user: user_id.clone(),
+                actor: sender_id.to_string(),
+                inbox: inbox.clone(),
+                created: Timestamp::now(),
+            }).compat()?;
+
+            let accept = json!({
+                "@context": "https://www.w3.org/ns/activitystreams",
+                "id": format!("{}#accept-{}", our_actor, Timestamp::now().unix_utc_ms),
+                "type": "Accept",
+                "actor": our_actor,
+                "object": activity.clone(),
+            });
+            // Actors fetch the inbox from their own actor, not the shared one:
+            let actor_inbox = sender["inbox"].as_str().unwrap_or(&inbox).to_string();
+            actix_web::rt::spawn(async move {
+                if let Err(err) = deliver(&key, &our_key_id, &actor_inbox, &accept).await {
+                    log::warn!("Error accepting follow: {}", err);
+                }
+            });
+        },
+        Some("Undo") if activity["object"]["type"].as_str() == Some("Follow") => {
+            backend.remove_activitypub_follower(&user_id, sender_id).compat()?;
+        },
+        Some("Create") => {
+            if let Some(reply) = reply(backend.as_ref(), &base_url, &user_id, &sender, &activity["object"]).compat()? {
+                backend.add_activitypub_reply(&reply).compat()?;
+            }
+        },
+        Some("Delete") => {
+            // The object may be embedded (as a Tombstone), or just its ID:
+            let object = &activity["object"];
+            let id = object["id"].as_str().or_else(|| object.as_str()).unwrap_or_default();
+            if id == sender_id {
+                // The actor itself was deleted.
+                backend.remove_activitypub_follower(&user_id, sender_id).compat()?;
+            } else {
+                backend.remove_activitypub_reply(&user_id, id, sender_id).compat()?;
+            }
+        },
+        _ => {},
+    }
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+
+/// Check a request's HTTP signature. Returns the actor that signed it.
+/// `activity_actor` is the actor that the activity says it's from.
+async fn verify_request(key: &ServerKey, our_key_id: &str, req: &HttpRequest, body: &[u8], activity_actor: &str) -> Result<Value, FailError> {
+    let header = req.headers().get("Signature")
+        .ok_or_else(|| format_err!("No Signature header"))?
+        .to_str()?;
+    let params = SignatureParams::parse(header)?;
+    if params.algorithm.map_or(false, |a| a != "rsa-sha256" && a != "hs2019") {
+        bail!("Unsupported algorithm");
+    }
+    for required in &["(request-target)", "host", "date", "digest"] {
+        if !params.headers.contains(required) {
+            bail!("{} must be signed", required);
+        }
+    }
+
+    let date: HttpDate = header_value(req, "date")?.parse()
+        .map_err(|_| format_err!("Invalid Date header"))?;
+    let date = SystemTime::from(date);
+    let skew = match date.duration_since(SystemTime::now()) {
+        Ok(ahead) => ahead,
+        Err(behind) => behind.duration(),
+    };
+    if skew > MAX_CLOCK_SKEW {
+        bail!("Date is too far from now");
+    }
+
+    let digest = format!("SHA-256={}", base64::encode(sha256::hash(body)));
+    if header_value(req, "digest")? != digest {
+        bail!("Digest doesn't match the body");
+    }
+
+    let mut signed = vec![];
+    for name in &params.headers {
+        if *name == "(request-target)" {
+            let target = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/");
+            signed.push(format!("(request-target): {} {}", req.method().as_str().to_lowercase(), target));
+        } else {
+            signed.push(format!("{}: {}", name, header_value(req, name)?));
+        }
+    }
+
+    let actor_id = key_actor(params.key_id, activity_actor)?;
+    remote::check_public(actor_id).await?;
+    let actor = fetch(key, our_key_id, actor_id).await?;
+    check_actor_key(&actor, actor_id, params.key_id)?;
+    let pem = actor["publicKey"]["publicKeyPem"].as_str().ok_or_else(|| format_err!("Actor has no public key"))?;
+
+    let der = der::pem_decode(pem)?;
+    let rsa_key = if pem.contains("BEGIN RSA PUBLIC KEY") {
+        &der[..]
+    } else {
+        rsa_from_spki(&der).ok_or_else(|| format_err!("Unsupported public key"))?
+    };
+    let signature = base64::decode(params.signature)?;
+    signature::UnparsedPublicKey::new(&signature::RSA_PKCS1_2048_8192_SHA256, rsa_key)
+        .verify(signed.join("\n").as_bytes(), &signature)
+        .map_err(|_| format_err!("Signature doesn't match"))?;
+
+    Ok(actor)
+}
+
+/// The actor that a key ID (ex: `{actor}#main-key`) is for: the ID without its
+/// fragment. It must be on the same server as the actor that the activity
+/// says it's from, so that one server can't sign for another's actors.
+pub(crate) fn key_actor<'k>(key_id: &'k str, activity_actor: &str) -> Result<&'k str, FailError> {
+    let actor_id = key_id.split('#').next().unwrap_or_default();
+    match (remote::origin(actor_id), remote::origin(activity_actor)) {
+        (Some(key_origin), Some(actor_origin)) if key_origin == actor_origin => Ok(actor_id),
+        (Some(_), Some(_)) => bail!("Key {} isn't from {}'s server", key_id, activity_actor),
+        _ => bail!("Key {} or actor {} isn't an http(s) URL", key_id, activity_actor),
+    }
+}
+
+/// Check that the `actor` we fetched from `actor_id` is that actor, and that
+/// its public key is `key_id`.
+pub(crate) fn check_actor_key(actor: &Value, actor_id: &str, key_id: &str) -> Result<(), FailError> {
+    if actor["id"].as_str() != Some(actor_id) {
+        bail!("{} returned a different actor", actor_id);
+    }
+    let public_key = &actor["publicKey"];
+    if public_key["id"].as_str() != Some(key_id) || public_key["owner"].as_str() != Some(actor_id) {
+        bail!("Key {} doesn't belong to {}", key_id, actor_id);
+    }
+    Ok(())
+}
+
+fn header_value<'r>(req: &'r HttpRequest, name: &str) -> Result<&'r str, FailError> {
+    let value = req.headers().get(name).ok_or_else(|| format_err!("Missing {} header", name))?;
+    Ok(value.to_str()?)
+}
+
+/// The parts of a `Signature` header.
+struct SignatureParams<'a> {
+    key_id: &'a str,
+    algorithm: Option<&'a str>,
+    headers: Vec<&'a str>,
+    signature: &'a str,
+}
+
+impl<'a> SignatureParams<'a> {
+    /// ex: `keyId="…",algorithm="rsa-sha256",headers="(request-target) host date",signature="…"`
+    fn parse(header: &'a str) -> Result<Self, FailError> {
+        let (mut key_id, mut algorithm, mut headers, mut signature) = (None, None, None, None);
+        let mut rest = header.trim();
+        while !rest.is_empty() {
+            let eq = rest.find("=\"").ok_or_else(|| format_err!("Malformed Signature header"))?;
+            let name = rest[..eq].trim();
+            let value_start = eq + 2;
+            let value_len = rest[value_start..].find('"').ok_or_else(|| format_err!("Malformed Signature header"))?;
+            let value = &rest[value_start..value_start + value_len];
+            rest = rest[value_start + value_len + 1..].trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+
+            match name {
+                "keyId" => key_id = Some(value),
+                "algorithm" => algorithm = Some(value),
+                "headers" => headers = Some(value),
+                "signature" => signature = Some(value),
+                _ => {},
+            }
+        }
+
+        Ok(SignatureParams {
+            key_id: key_id.ok_or_else(|| format_err!("No keyId in Signature header"))?,
+            algorithm,
+            // The spec's default is just "date", which isn't enough for us anyway:
+            headers: headers.unwrap_or("date").split_whitespace().collect(),
+            signature: signature.ok_or_else(|| format_err!("No signature in Signature header"))?,
+        })
+    }
+}
+
+
+/// If `object` is a reply to one of `user`'s posts, make it an ActivityPubReply.
+fn reply(
+    backend: &dyn Backend,
+    base_url: &str,
+    user: &UserID,
+    sender: &Value,
+    object: &Value,
+) -> Result<Option<ActivityPubReply>, FailError> {
+    let sender_id = sender["id"].as_str().unwrap_or_default();
+    if object["type"].as_str() != Some("Note") || object["attributedTo"].as_str() != Some(sender_id) {
+        return Ok(None);
+    }
+    let (id, in_reply_to) = match (object["id"].as_str(), object["inReplyTo"].as_str()) {
+        (Some(id), Some(in_reply_to)) => (id, in_reply_to),
+        _ => return Ok(None),
+    };
+    let url = object["url"].as_str().unwrap_or(id);
+    // We link to these, so don't let them be javascript: URLs, etc.:
+    if ![sender_id, id, url].iter().all(|url| is_http(url)) {
+        return Ok(None);
+    }
+
+    // Replies may be to the object, or to the HTML page that its `url` points at:
+    let item_prefix = format!("{}/u/{}/i/", base_url, user.to_base58());
+    let signature = in_reply_to.strip_prefix(&item_prefix)
+        .and_then(|rest| rest.split('/').next())
+        .and_then(|signature| Signature::from_base58(signature).ok());
+    let signature = match signature {
+        Some(signature) => signature,
+        None => return Ok(None),
+    };
+
+    let row = match backend.user_item(user, &signature)? {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    let mut item = Item::new();
+    item.merge_from_bytes(&row.item_bytes)?;
+    if !item.has_post() {
+        return Ok(None);
+    }
+
+    let mut count = 0;
+    backend.activitypub_replies(user, &signature, &mut |_| {
+        count += 1;
+        Ok(count < MAX_REPLIES)
+    })?;
+    if count >= MAX_REPLIES {
+        log::info!("Ignoring reply {}: too many replies to {}", id, signature.to_base58());
+        return Ok(None);
+    }
+
+    let text = html_to_text(object["content"].as_str().unwrap_or_default());
+    if text.is_empty() || text.len() > MAX_REPLY_BYTES {
+        return Ok(None);
+    }
+
+    let actor_name = [&sender["name"], &sender["preferredUsername"]].iter()
+        .filter_map(|name| name.as_str())
+        .map(str::trim)
+        .find(|name| !name.is_empty())
+        .unwrap_or(sender_id);
+
+    let now = Timestamp::now();
+    let published = object["published"].as_str()
+        .and_then(Timestamp::parse_rfc3339)
+        .filter(|published| published.unix_utc_ms <= now.unix_utc_ms)
+        .unwrap_or(now);
+
+    Ok(Some(ActivityPubReply {
+        user: user.clone(),
+        signature,
+        id: id.to_string(),
+        actor: sender_id.to_string(),
+        actor_name: actor_name.to_string(),
+        url: url.to_string(),
+        text,
+        published,
+        received: now,
+    }))
+}
+
+fn is_http(url: &str) -> bool {
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+/// Replies arrive as HTML, which we don't trust enough to display.
+/// Keep the text, and the line breaks between paragraphs.
+fn html_to_text(html: &str) -> String {
+    let mut text = String::new();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        text += &decode_entities(&rest[..start]);
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => {
+                rest = "";
+                break;
+            },
+        };
+        let tag = rest[start + 1 .. end].trim().to_lowercase();
+        let name = tag.split(|c: char| c.is_whitespace() || c == '/').find(|name| !name.is_empty()).unwrap_or_default();
+        match name {
+            "br" => text += "\n",
+            "p" if tag.starts_with('/') => text += "\n\n",
+            _ => {},
+        }
+        rest = &rest[end + 1..];
+    }
+    text += &decode_entities(rest);
+    text.trim().to_string()
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded += &rest[..start];
+        rest = &rest[start..];
+        let end = match rest.find(';') {
+            Some(end) if end <= 10 => end,
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+                continue;
+            },
+        };
+        let entity = &rest[1..end];
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => {
+                let code = if let Some(hex) = entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                    u32::from_str_radix(hex, 16).ok()
+                } else if let Some(dec) = entity.strip_prefix('#') {
+                    dec.parse().ok()
+                } else {
+                    None
+                };
+                code.and_then(std::char::from_u32)
+            },
+        };
+        match c {
+            Some(c) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            },
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+    decoded += rest;
+    decoded
+}
@@ -0,0 +1,38 @@
+//! The operator's announcement, shown at the top of every HTML page.
+//!
+//! Set with `feoblog announce`. Since that can happen while the server is
+//! running, we reload it from the backend every so often rather than querying
+//! on every page view.
+
+use std::sync::RwLock;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+
+use crate::backend::{Announcement, Factory, Timestamp};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+static CURRENT: Lazy<RwLock<Option<Announcement>>> = Lazy::new(|| RwLock::new(None));
+
+/// Runs forever. Spawn it on the server's runtime.
+pub(crate) async fn refresh_loop(factory: Box<dyn Factory>) {
+    let mut interval = actix_web::rt::time::interval(REFRESH_INTERVAL);
+    loop {
+        interval.tick().await;
+        match factory.open().and_then(|backend| backend.announcement()) {
+            Ok(announcement) => {
+                *CURRENT.write().expect("announcement lock poisoned") = announcement;
+            },
+            Err(err) => log::warn!("Error loading announcement: {}", err),
+        }
+    }
+}
+
+/// The announcement's message, unless there isn't one or it has expired.
+pub(crate) fn current() -> Option<String> {
+    let current = CURRENT.read().expect("announcement lock poisoned");
+    current.as_ref()
+        .filter(|a| !a.is_expired(Timestamp::now()))
+        .map(|a| a.message.clone())
+}
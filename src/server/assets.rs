@@ -0,0 +1,95 @@
+//! Hashes of the static files embedded in the server, computed at build time.
+//! (See: build.rs)
+//!
+//! Templates link to assets with `static_url()` and `client_url()`, which add a
+//! `?v=` version derived from the hash. Since a versioned URL always returns
+//! the same content, we can let browsers cache it forever. A new release
+//! changes the version, so they'll fetch the new file.
+//!
+//! With `feoblog serve --static-dir` or `--client-dir`, files on disk override
+//! the embedded ones, so they can be edited without rebuilding the server.
+
+use std::fs;
+use std::io;
+use std::path::{Component, Path};
+
+use actix_web::HttpResponse;
+
+use super::policy::Cache;
+
+include!(concat!(env!("OUT_DIR"), "/asset_hashes.rs"));
+
+/// How many hex digits of the hash to use as a version.
+const VERSION_LENGTH: usize = 16;
+
+/// Embedded files whose hashes we know.
+pub(crate) trait AssetHashes {
+    /// (path, hex-encoded SHA-256), sorted by path.
+    const HASHES: &'static [(&'static str, &'static str)];
+
+    fn hash(path: &str) -> Option<&'static str> {
+        // In debug builds, rust-embed reads files from disk, so they may
+        // have changed since build.rs hashed them.
+        if cfg!(debug_assertions) {
+            return None;
+        }
+        let index = Self::HASHES.binary_search_by(|(p, _)| (*p).cmp(path)).ok()?;
+        Some(Self::HASHES[index].1)
+    }
+
+    /// The version to use in `?v=` for a path. Directories use their index.html.
+    fn version(path: &str) -> Option<&'static str> {
+        let hash = if path.is_empty() || path.ends_with('/') {
+            Self::hash(&format!("{}index.html", path))
+        } else {
+            Self::hash(path)
+        };
+        hash.map(|hash| &hash[..VERSION_LENGTH])
+    }
+
+    /// Add a version to the URL of an asset, if we know its hash.
+    fn versioned_url(prefix: &str, path: &str) -> String {
+        match Self::version(path) {
+            Some(version) => format!("{}{}?v={}", prefix, path, version),
+            None => format!("{}{}", prefix, path),
+        }
+    }
+}
+
+/// The URL of a file in `static/`.
+pub(crate) fn static_url(path: &str) -> String {
+    super::StaticFiles::versioned_url("/static/", path)
+}
+
+/// The URL of a file in the web client's build.
+pub(crate) fn client_url(path: &str) -> String {
+    super::WebClientBuild::versioned_url("/client/", path)
+}
+
+/// Serve a file from a directory that overrides embedded files.
+/// Returns None if it doesn't have the file, so that we can serve the embedded one.
+pub(crate) fn from_disk(dir: &Path, path: &str) -> io::Result<Option<HttpResponse>> {
+    // Don't let requests escape the directory:
+    let relative = Path::new(path);
+    if !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+        return Ok(None);
+    }
+
+    let mut file = dir.join(relative);
+    if path.is_empty() || path.ends_with('/') {
+        file = file.join("index.html");
+    }
+    if !file.is_file() {
+        return Ok(None);
+    }
+
+    // Read per request, so that changes show up immediately. These are meant
+    // for customization and development, so don't let browsers cache them.
+    let bytes = fs::read(&file)?;
+    let mime_type = format!("{}", mime_guess::from_path(&file).first_or_octet_stream());
+    Ok(Some(
+        Cache::Mutable.apply(&mut HttpResponse::Ok())
+        .content_type(mime_type)
+        .body(bytes)
+    ))
+}
@@ -0,0 +1,153 @@
+//! Hot backups over HTTP. (`GET /backup`, with `feoblog serve --backup-key`)
+//!
+//! Requests must be signed with a `FeoBlog-Peer` header (see: peer_auth.rs)
+//! by one of the backup keys. `feoblog db backup --from <url>` makes them,
+//! with the key from `feoblog peers key` on the machine that keeps backups.
+//!
+//! The response is a complete SQLite database, copied with SQLite's online
+//! backup API, so it's consistent even while items are being uploaded.
+//!
+//! The copy is made in a new private directory (only we may read it) in the
+//! system's temp directory, and deleted once it's sent, or if anything fails.
+//! Copying and reading it happen on actix's thread pool, so that a large
+//! database doesn't hold up other requests.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::web::{self, Bytes, Data};
+use failure::{ResultExt, format_err};
+use tempfile::TempDir;
+
+use crate::backend::Timestamp;
+use super::{AppData, Error, PLAINTEXT, peer_auth};
+use super::policy::Cache;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// `/backup`
+pub(crate) async fn get_backup(data: Data<AppData>, req: HttpRequest) -> Result<HttpResponse, Error> {
+    if data.backup_keys.is_empty() {
+        return Ok(
+            HttpResponse::NotFound()
+            .content_type(PLAINTEXT)
+            .body("Backups are disabled on this server. (See: `feoblog serve --backup-key`)")
+        );
+    }
+
+    let backend = data.backend_factory.open().compat()?;
+    let key = match peer_auth::signer(&req, backend.as_ref(), &data.hosts) {
+        Ok(Some(key)) => key,
+        Ok(None) => return Ok(
+            HttpResponse::Unauthorized()
+            .content_type(PLAINTEXT)
+            .body(format!("Backups require a {} header.", peer_auth::HEADER))
+        ),
+        Err(err) => return Ok(HttpResponse::Unauthorized().content_type(PLAINTEXT).body(err.to_string())),
+    };
+    if !data.backup_keys.contains(&key) {
+        return Ok(
+            HttpResponse::Forbidden()
+            .content_type(PLAINTEXT)
+            .body(format!("{} may not download backups from this server.", key.to_base58()))
+        );
+    }
+
+    drop(backend);
+
+    let factory = data.backend_factory.clone();
+    let file = web::block(move || -> Result<TempFile, failure::Error> {
+        // Dropping `file` deletes the directory, so any error below cleans up:
+        let mut file = TempFile::new()?;
+        factory.open()?.backup(&file.path)
+            .with_context(|_| format!("Error backing up to {}", file.path.display()))?;
+        file.open()?;
+        Ok(file)
+    }).await.map_err(|err| format_err!("{}", err)).compat()?;
+    log::info!("{} downloaded a backup", key.to_base58());
+
+    let body = Box::pin(futures::stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let read = web::block(move || -> Result<_, io::Error> {
+            let chunk = file.next_chunk();
+            Ok((chunk, file))
+        }).await;
+        match read {
+            Ok((Some(chunk), file)) => Some((chunk, Some(file))),
+            Ok((None, _)) => None,
+            // (The file was dropped, and so deleted, with the closure.)
+            Err(err) => Some((Err(io::Error::new(io::ErrorKind::Other, err.to_string())), None)),
+        }
+    }));
+    let name = format!("feoblog-{}.sqlite3", Timestamp::now().format_with(0, "%Y%m%d-%H%M%S"));
+    Ok(
+        Cache::NoStore.apply(&mut HttpResponse::Ok())
+        .content_type("application/vnd.sqlite3")
+        .header("Content-Disposition", format!("attachment; filename=\"{}\"", name))
+        .streaming(body)
+    )
+}
+
+/// The backup, which we delete once it's been sent. (Or the client hangs up.)
+struct TempFile {
+    /// Readable only by us.
+    dir: Option<TempDir>,
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl TempFile {
+    fn new() -> Result<Self, failure::Error> {
+        // Created with mode 0700 on Unix:
+        let dir = tempfile::Builder::new().prefix("feoblog-backup-").tempdir()
+            .context("Error creating a directory for the backup")?;
+        let path = dir.path().join("backup.sqlite3");
+        Ok(TempFile{ dir: Some(dir), path, file: None })
+    }
+
+    /// Open the finished backup for reading.
+    fn open(&mut self) -> Result<(), failure::Error> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&self.path, std::fs::Permissions::from_mode(0o600))?;
+        }
+        self.file = Some(File::open(&self.path)?);
+        Ok(())
+    }
+
+    fn next_chunk(&mut self) -> Option<Result<Bytes, io::Error>> {
+        let file = self.file.as_mut()?;
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        match file.read(&mut buf) {
+            Ok(0) => {
+                self.file = None;
+                None
+            },
+            Ok(len) => {
+                buf.truncate(len);
+                Some(Ok(Bytes::from(buf)))
+            },
+            Err(err) => {
+                // Can't continue, so this ends the response:
+                self.file = None;
+                Some(Err(err))
+            },
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // Close it first. Windows can't delete open files.
+        self.file = None;
+        if let Some(dir) = self.dir.take() {
+            let path = dir.path().to_path_buf();
+            if let Err(err) = dir.close() {
+                log::warn!("Error deleting {}: {}", path.display(), err);
+            }
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! An IP address denylist, for dealing with scrapers and flooders.
+//!
+//! Networks come from `feoblog serve --block`, which can't change while the
+//! server runs, and from the backend, which can: with `feoblog block`, or an
+//! admin's requests to `/admin/blocks`. (See: `feoblog serve --admin`) We
+//! reload the backend's every minute, and right after an admin changes them.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::web::{Data, Path, Query};
+use failure::{Error, ResultExt, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{Backend, Factory, IpBlock, Timestamp};
+use super::{AppData, PLAINTEXT, policy, viewer};
+
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// An IP network in CIDR notation. (ex: `192.0.2.0/24`, `2001:db8::/32`)
+/// A bare IP address is treated as a network of just that address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Cidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    fn max_prefix_len(addr: &IpAddr) -> u8 {
+        match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = Error;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = value.trim().splitn(2, '/');
+        let addr: IpAddr = parts.next().unwrap_or("").parse()?;
+        let max_len = Cidr::max_prefix_len(&addr);
+        let prefix_len = match parts.next() {
+            None => max_len,
+            Some(len) => len.parse()?,
+        };
+        if prefix_len > max_len {
+            bail!("Prefix length {} is too long for {}", prefix_len, addr);
+        }
+
+        // Normalize so that host bits are zero:
+        let network = match addr {
+            IpAddr::V4(ip) => IpAddr::V4(mask_v4(u32::from(ip), prefix_len).into()),
+            IpAddr::V6(ip) => IpAddr::V6(mask_v6(u128::from(ip), prefix_len).into()),
+        };
+
+        Ok(Cidr{ network, prefix_len })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
+
+fn mask_v4(ip: u32, prefix_len: u8) -> u32 {
+    if prefix_len == 0 { 0 } else { ip & (u32::MAX << (32 - prefix_len)) }
+}
+
+fn mask_v6(ip: u128, prefix_len: u8) -> u128 {
+    if prefix_len == 0 { 0 } else { ip & (u128::MAX << (128 - prefix_len)) }
+}
+
+/// A set of blocked networks.
+///
+/// Networks are grouped by prefix length, so checking an address costs one
+/// hash lookup per distinct prefix length, regardless of how many networks
+/// are blocked.
+#[derive(Debug, Default)]
+pub(crate) struct IpBlocklist {
+    v4: BTreeMap<u8, HashSet<u32>>,
+    v6: BTreeMap<u8, HashSet<u128>>,
+}
+
+impl IpBlocklist {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, cidr: Cidr) {
+        match cidr.network {
+            IpAddr::V4(ip) => {
+                self.v4.entry(cidr.prefix_len).or_default().insert(u32::from(ip));
+            },
+            IpAddr::V6(ip) => {
+                self.v6.entry(cidr.prefix_len).or_default().insert(u128::from(ip));
+            },
+        }
+    }
+
+    /// True if `ip` falls within any blocked network.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let ip = match ip {
+            // Treat IPv4-mapped IPv6 addresses (::ffff:a.b.c.d) as IPv4:
+            IpAddr::V6(v6) => match v6.to_ipv4() {
+                Some(v4) if v6.segments()[..6] == [0, 0, 0, 0, 0, 0xffff] => IpAddr::V4(v4),
+                _ => IpAddr::V6(*v6),
+            },
+            IpAddr::V4(v4) => IpAddr::V4(*v4),
+        };
+
+        match ip {
+            IpAddr::V4(ip) => {
+                let ip = u32::from(ip);
+                self.v4.iter().any(|(len, networks)| networks.contains(&mask_v4(ip, *len)))
+            },
+            IpAddr::V6(ip) => {
+                let ip = u128::from(ip);
+                self.v6.iter().any(|(len, networks)| networks.contains(&mask_v6(ip, *len)))
+            },
+        }
+    }
+}
+
+/// The blocklist that requests are checked against, shared by all workers.
+pub(crate) struct SharedBlocklist {
+    /// From `--block`.
+    configured: Vec<Cidr>,
+    current: RwLock<IpBlocklist>,
+}
+
+impl SharedBlocklist {
+    /// Blocks only `configured` until the first `reload`.
+    pub fn new(configured: Vec<Cidr>) -> Self {
+        let mut current = IpBlocklist::new();
+        for cidr in &configured {
+            current.add(*cidr);
+        }
+        SharedBlocklist{ configured, current: RwLock::new(current) }
+    }
+
+    /// Replace the backend's blocks with its current ones.
+    pub fn reload(&self, backend: &dyn Backend) -> Result<(), Error> {
+        let mut blocklist = IpBlocklist::new();
+        for cidr in &self.configured {
+            blocklist.add(*cidr);
+        }
+        backend.ip_blocks(&mut |block| {
+            blocklist.add(block.cidr.parse()?);
+            Ok(true)
+        })?;
+        *self.current.write().expect("blocklist lock poisoned") = blocklist;
+        Ok(())
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.current.read().expect("blocklist lock poisoned").contains(ip)
+    }
+}
+
+/// Runs forever. Spawn it on the server's runtime.
+pub(crate) async fn reload_loop(factory: Box<dyn Factory>, blocklist: Arc<SharedBlocklist>) {
+    let mut interval = actix_web::rt::time::interval(RELOAD_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(err) = factory.open().and_then(|backend| blocklist.reload(backend.as_ref())) {
+            log::warn!("Error reloading IP blocks: {}", err);
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Blocks {
+    blocks: Vec<BlockJson>,
+}
+
+#[derive(Serialize)]
+struct BlockJson {
+    cidr: String,
+    notes: String,
+    created_ms_utc: i64,
+}
+
+/// `GET /admin/blocks`
+///
+/// The backend's blocks. (Not those from `--block`.)
+pub(crate) async fn get_blocks(data: Data<AppData>, req: HttpRequest) -> Result<HttpResponse, super::Error> {
+    let backend = data.backend_factory.open().compat()?;
+    if let Err(response) = viewer::require_admin(&req, backend.as_ref(), &data.hosts, &data.admins) {
+        return Ok(response);
+    }
+
+    let mut blocks = vec![];
+    backend.ip_blocks(&mut |block| {
+        blocks.push(BlockJson{
+            cidr: block.cidr,
+            notes: block.notes,
+            created_ms_utc: block.created.unix_utc_ms,
+        });
+        Ok(true)
+    }).compat()?;
+
+    Ok(policy::Cache::NoStore.apply(&mut HttpResponse::Ok()).json(Blocks{blocks}))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct BlockParams {
+    /// Notes for other admins. (ex: why it's blocked)
+    #[serde(default)]
+    notes: String,
+}
+
+/// `PUT /admin/blocks/{network}/{prefix_len}`
+pub(crate) async fn put_block(
+    data: Data<AppData>,
+    Path((network, prefix_len)): Path<(String, u8)>,
+    Query(params): Query<BlockParams>,
+    req: HttpRequest,
+) -> Result<HttpResponse, super::Error> {
+    let backend = data.backend_factory.open().compat()?;
+    let admin = match viewer::require_admin(&req, backend.as_ref(), &data.hosts, &data.admins) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(response),
+    };
+    let cidr: Cidr = match format!("{}/{}", network, prefix_len).parse() {
+        Ok(cidr) => cidr,
+        Err(err) => return Ok(HttpResponse::BadRequest().content_type(PLAINTEXT).body(err.to_string())),
+    };
+
+    backend.add_ip_block(&IpBlock{
+        cidr: cidr.to_string(),
+        notes: params.notes,
+        created: Timestamp::now(),
+    }).compat()?;
+    data.blocklist.reload(backend.as_ref()).compat()?;
+    log::info!("{} blocked {}", admin.to_base58(), cidr);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// `DELETE /admin/blocks/{network}/{prefix_len}`
+pub(crate) async fn delete_block(
+    data: Data<AppData>,
+    Path((network, prefix_len)): Path<(String, u8)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, super::Error> {
+    let backend = data.backend_factory.open().compat()?;
+    let admin = match viewer::require_admin(&req, backend.as_ref(), &data.hosts, &data.admins) {
+        Ok(admin) => admin,
+        Err(response) => return Ok(response),
+    };
+    let cidr: Cidr = match format!("{}/{}", network, prefix_len).parse() {
+        Ok(cidr) => cidr,
+        Err(err) => return Ok(HttpResponse::BadRequest().content_type(PLAINTEXT).body(err.to_string())),
+    };
+
+    if !backend.remove_ip_block(&cidr.to_string()).compat()? {
+        return Ok(HttpResponse::NotFound().content_type(PLAINTEXT).body(format!("{} was not blocked.", cidr)));
+    }
+    data.blocklist.reload(backend.as_ref()).compat()?;
+    log::info!("{} unblocked {}", admin.to_base58(), cidr);
+
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,130 @@
+//! Lets authors review the comments that visitors without a FeoBlog key left
+//! on their posts. (See: `serve --anonymous-comments`)
+//!
+//! * `GET /u/{userID}/comments/queue/`
+//! * `POST /u/{userID}/comments/queue/{id}/approve`
+//! * `DELETE /u/{userID}/comments/queue/{id}`
+//!
+//! Each must be signed by `{userID}`. (See: viewer.rs) Queued comments aren't
+//! signed, so the server can't publish them itself. Approving one returns it
+//! as a Markdown quote, which the author's client publishes in a reply post.
+
+use actix_web::web::{Data, Path};
+use actix_web::{HttpRequest, HttpResponse};
+use failure::ResultExt;
+use serde::Serialize;
+
+use crate::backend::{Backend, QueuedComment, UserID};
+use super::{AppData, Error, PLAINTEXT, policy, viewer};
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Comment {
+    id: i64,
+
+    /// The post being commented on.
+    signature: String,
+    name: String,
+    text: String,
+    created_ms_utc: i64,
+}
+
+#[derive(Serialize)]
+struct Comments {
+    comments: Vec<Comment>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Approved {
+    /// The post to reply to.
+    signature: String,
+
+    /// Markdown, to include in the reply.
+    quote: String,
+}
+
+/// `GET /u/{userID}/comments/queue/`
+pub(crate) async fn get_queue(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    if let Err(response) = viewer::require_user(&req, backend.as_ref(), &data.hosts, &user_id) {
+        return Ok(response);
+    }
+
+    let mut comments = vec![];
+    backend.user_queued_comments(&user_id, &mut |comment| {
+        comments.push(Comment {
+            id: comment.id,
+            signature: comment.signature.to_base58(),
+            name: comment.name,
+            text: comment.text,
+            created_ms_utc: comment.created.unix_utc_ms,
+        });
+        Ok(true)
+    }).compat()?;
+
+    Ok(policy::Cache::NoStore.apply(&mut HttpResponse::Ok()).json(Comments{comments}))
+}
+
+/// `POST /u/{userID}/comments/queue/{id}/approve`
+pub(crate) async fn approve(
+    data: Data<AppData>,
+    Path((user_id, id)): Path<(UserID, i64)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    if let Err(response) = viewer::require_user(&req, backend.as_ref(), &data.hosts, &user_id) {
+        return Ok(response);
+    }
+
+    let comment = match find(backend.as_ref(), &user_id, id).compat()? {
+        Some(comment) => comment,
+        None => return Ok(not_found()),
+    };
+    backend.remove_queued_comment(comment.id).compat()?;
+
+    Ok(policy::Cache::NoStore.apply(&mut HttpResponse::Ok()).json(Approved{
+        signature: comment.signature.to_base58(),
+        quote: comment.quote(),
+    }))
+}
+
+/// `DELETE /u/{userID}/comments/queue/{id}`
+pub(crate) async fn reject(
+    data: Data<AppData>,
+    Path((user_id, id)): Path<(UserID, i64)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    if let Err(response) = viewer::require_user(&req, backend.as_ref(), &data.hosts, &user_id) {
+        return Ok(response);
+    }
+
+    // Authors may only reject comments on their own posts:
+    if find(backend.as_ref(), &user_id, id).compat()?.is_none() {
+        return Ok(not_found());
+    }
+    backend.remove_queued_comment(id).compat()?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// A comment on one of `user`'s posts.
+fn find(backend: &dyn Backend, user: &UserID, id: i64) -> Result<Option<QueuedComment>, failure::Error> {
+    let mut found = None;
+    backend.user_queued_comments(user, &mut |comment| {
+        if comment.id == id {
+            found = Some(comment);
+            return Ok(false);
+        }
+        Ok(true)
+    })?;
+    Ok(found)
+}
+
+fn not_found() -> HttpResponse {
+    HttpResponse::NotFound().content_type(PLAINTEXT).body("No such comment in the queue")
+}
@@ -0,0 +1,251 @@
+//! Cross-posts ("POSSE") users' new posts to other services: a summary, and a
+//! link back to the post here.
+//!
+//! Targets are managed per-user with `feoblog crosspost`. When `put_item`
+//! accepts a post, it queues a cross-post to each of the user's targets. A
+//! background task sends them, and retries failures with exponential backoff,
+//! like replication.rs.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::client::Client;
+use failure::{Error, bail, format_err};
+use protobuf::Message as _;
+use serde_json::{Value, json};
+
+use crate::backend::{Backend, CrossPostService, CrossPostTarget, Factory, ItemRow, QueuedCrossPost, Timestamp};
+use crate::protos::Item;
+use super::maintenance::Maintenance;
+
+/// How often we check the queue.
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Give up on a cross-post after this many failures.
+const MAX_ATTEMPTS: u32 = 10;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(60);
+const MAX_BACKOFF: Duration = Duration::from_secs(6 * 60 * 60);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Mastodon's default limit.
+const MASTODON_MAX_CHARS: usize = 500;
+
+/// Bluesky's limit is 300 graphemes. Counting chars is close enough, and errs short.
+const BLUESKY_MAX_CHARS: usize = 300;
+
+/// Queue a new post to be cross-posted to each of the user's targets.
+pub(crate) fn queue_post(
+    backend: &dyn Backend,
+    base_url: &str,
+    row: &ItemRow,
+    item: &Item,
+) -> Result<(), Error> {
+    if !item.has_post() {
+        return Ok(());
+    }
+
+    let mut targets = vec![];
+    backend.crosspost_targets(&row.user, &mut |target| {
+        targets.push(target.name);
+        Ok(true)
+    })?;
+
+    for target in targets {
+        backend.queue_crosspost(&QueuedCrossPost {
+            user: row.user.clone(),
+            signature: row.signature.clone(),
+            target,
+            base_url: base_url.to_string(),
+            attempts: 0,
+        })?;
+    }
+    Ok(())
+}
+
+/// Runs forever. Spawn it on the server's runtime.
+pub(crate) async fn crosspost_loop(factory: Box<dyn Factory>, maintenance: Arc<Maintenance>) {
+    let mut interval = actix_web::rt::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        if maintenance.is_active() {
+            continue;
+        }
+        if let Err(err) = crosspost_due(factory.as_ref()).await {
+            log::warn!("Error cross-posting: {}", err);
+        }
+    }
+}
+
+async fn crosspost_due(factory: &dyn Factory) -> Result<(), Error> {
+    let mut due = vec![];
+    factory.open()?.due_crossposts(Timestamp::now(), &mut |crosspost| {
+        due.push(crosspost);
+        Ok(due.len() < 100)
+    })?;
+
+    // Don't keep retrying a service that's down, for every post queued for it:
+    let mut unreachable = HashSet::new();
+
+    for crosspost in due {
+        let backend = factory.open()?;
+        let target = backend.crosspost_target(&crosspost.user, &crosspost.target)?;
+        let row = backend.user_item(&crosspost.user, &crosspost.signature)?;
+        let (target, row) = match (target, row) {
+            (Some(target), Some(row)) => (target, row),
+            _ => {
+                // The target or the post was removed since this was queued.
+                backend.finish_crosspost(&crosspost)?;
+                continue;
+            }
+        };
+        if unreachable.contains(&target.url) {
+            continue;
+        }
+
+        let mut item = Item::new();
+        item.merge_from_bytes(&row.item_bytes)?;
+        let url = format!("{}/u/{}/i/{}/", crosspost.base_url, row.user.to_base58(), row.signature.to_base58());
+
+        match send(&target, &row, &item, &url).await {
+            Ok(()) => backend.finish_crosspost(&crosspost)?,
+            Err(err) => {
+                unreachable.insert(target.url.clone());
+                failed(backend.as_ref(), &crosspost, &err.to_string())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn failed(backend: &dyn Backend, crosspost: &QueuedCrossPost, error: &str) -> Result<(), Error> {
+    if crosspost.attempts + 1 >= MAX_ATTEMPTS {
+        log::warn!(
+            "Giving up cross-posting {} to {}: {}",
+            crosspost.signature.to_base58(), crosspost.target, error,
+        );
+        return backend.finish_crosspost(crosspost);
+    }
+
+    let retry_at = Timestamp {
+        unix_utc_ms: Timestamp::now().unix_utc_ms + backoff(crosspost.attempts).as_millis() as i64,
+    };
+    backend.crosspost_failed(crosspost, retry_at, error)
+}
+
+/// How long to wait after `attempts` previous failures.
+fn backoff(attempts: u32) -> Duration {
+    let backoff = MIN_BACKOFF.checked_mul(1 << attempts.min(16)).unwrap_or(MAX_BACKOFF);
+    backoff.min(MAX_BACKOFF)
+}
+
+async fn send(target: &CrossPostTarget, row: &ItemRow, item: &Item, url: &str) -> Result<(), Error> {
+    match target.service {
+        CrossPostService::Mastodon => send_mastodon(target, row, item, url).await,
+        CrossPostService::Bluesky => send_bluesky(target, item, url).await,
+        CrossPostService::Webhook => send_webhook(target, row, item, url).await,
+    }
+}
+
+/// See: <https://docs.joinmastodon.org/methods/statuses/#create>
+async fn send_mastodon(target: &CrossPostTarget, row: &ItemRow, item: &Item, url: &str) -> Result<(), Error> {
+    let endpoint = format!("{}/api/v1/statuses", target.url.trim_end_matches('/'));
+    let status = json!({
+        "status": summary(item, url, MASTODON_MAX_CHARS),
+        "visibility": "public",
+    });
+    post_json(
+        Client::default().post(&endpoint)
+            .header("Authorization", format!("Bearer {}", target.token))
+            // In case a retry follows a success we didn't hear about:
+            .header("Idempotency-Key", row.signature.to_base58()),
+        &status,
+    ).await?;
+    Ok(())
+}
+
+/// See: <https://docs.bsky.app/docs/advanced-guides/posts>
+async fn send_bluesky(target: &CrossPostTarget, item: &Item, url: &str) -> Result<(), Error> {
+    let pds = target.url.trim_end_matches('/');
+    let session = post_json(
+        Client::default().post(format!("{}/xrpc/com.atproto.server.createSession", pds)),
+        &json!({"identifier": target.account, "password": target.token}),
+    ).await?;
+    let (did, jwt) = match (session["did"].as_str(), session["accessJwt"].as_str()) {
+        (Some(did), Some(jwt)) => (did, jwt),
+        _ => bail!("No session from {}", pds),
+    };
+
+    // Bluesky doesn't find links in text itself. Mark ours, by byte offsets:
+    let text = summary(item, url, BLUESKY_MAX_CHARS);
+    let link_end = text.len();
+    let link_start = link_end - url.len();
+    let record = json!({
+        "$type": "app.bsky.feed.post",
+        "text": text,
+        "createdAt": Timestamp::now().format_rfc3339(),
+        "facets": [{
+            "index": {"byteStart": link_start, "byteEnd": link_end},
+            "features": [{"$type": "app.bsky.richtext.facet#link", "uri": url}],
+        }],
+    });
+    post_json(
+        Client::default().post(format!("{}/xrpc/com.atproto.repo.createRecord", pds))
+            .header("Authorization", format!("Bearer {}", jwt)),
+        &json!({"repo": did, "collection": "app.bsky.feed.post", "record": record}),
+    ).await?;
+    Ok(())
+}
+
+/// POSTs the post's details as JSON, for other software to do with as it likes.
+async fn send_webhook(target: &CrossPostTarget, row: &ItemRow, item: &Item, url: &str) -> Result<(), Error> {
+    let post = item.get_post();
+    let mut request = Client::default().post(&target.url);
+    if !target.token.is_empty() {
+        request = request.header("Authorization", format!("Bearer {}", target.token));
+    }
+    post_json(request, &json!({
+        "user_id": row.user.to_base58(),
+        "signature": row.signature.to_base58(),
+        "url": url,
+        "title": post.get_title(),
+        "body": post.get_body(),
+        "published": Timestamp{ unix_utc_ms: item.timestamp_ms_utc }.format_rfc3339(),
+    })).await?;
+    Ok(())
+}
+
+async fn post_json(request: actix_web::client::ClientRequest, body: &Value) -> Result<Value, Error> {
+    let url = request.get_uri().to_string();
+    let mut response = request
+        .timeout(REQUEST_TIMEOUT)
+        .send_json(body).await
+        .map_err(|err| format_err!("Error sending to {}: {}", url, err))?;
+    if !response.status().is_success() {
+        bail!("{} responded with {}", url, response.status());
+    }
+    // Some webhooks respond with nothing, or not JSON:
+    let body = response.body().await
+        .map_err(|err| format_err!("Error reading response from {}: {}", url, err))?;
+    Ok(serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+/// The post's title, or as much of its body as fits, followed by a link to it.
+fn summary(item: &Item, url: &str, max_chars: usize) -> String {
+    let post = item.get_post();
+    let title = post.get_title().trim();
+    let text = if title.is_empty() { post.get_body().trim() } else { title };
+
+    // Leave room for the link, and the blank line before it:
+    let room = max_chars.saturating_sub(url.chars().count() + 2);
+    let text = if text.chars().count() > room {
+        let truncated: String = text.chars().take(room.saturating_sub(1)).collect();
+        format!("{}…", truncated.trim_end())
+    } else {
+        text.to_string()
+    };
+    format!("{}\n\n{}", text, url)
+}
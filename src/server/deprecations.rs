@@ -0,0 +1,56 @@
+//! Routes that are on their way out.
+//!
+//! Deprecated routes keep working, but responses carry `Deprecation` and
+//! `Sunset` headers, and `/server/info/proto3` lists them, so that clients can
+//! notice programmatically and move to newer routes before old ones go away.
+
+use actix_web::http::{HeaderMap, HeaderName, HeaderValue};
+use time::OffsetDateTime;
+
+pub(crate) struct Deprecation {
+    /// The route pattern, exactly as registered. ex: "/u/{user_id}/proto3"
+    pub pattern: &'static str,
+
+    /// When the route was deprecated.
+    pub deprecated_utc_ms: i64,
+
+    /// When the route may stop working, if that's been decided.
+    pub sunset_utc_ms: Option<i64>,
+
+    /// What clients should do instead.
+    pub note: &'static str,
+}
+
+/// Add an entry here when deprecating a route. ex:
+/// ```text
+/// Deprecation {
+///     pattern: "/u/{user_id}/feed/proto3",
+///     deprecated_utc_ms: 1_609_459_200_000,
+///     sunset_utc_ms: Some(1_640_995_200_000),
+///     note: "Use /api/v1/u/{userID}/feed instead.",
+/// },
+/// ```
+pub(crate) const DEPRECATIONS: &[Deprecation] = &[
+];
+
+pub(crate) fn find(pattern: &str) -> Option<&'static Deprecation> {
+    DEPRECATIONS.iter().find(|d| d.pattern == pattern)
+}
+
+impl Deprecation {
+    /// Add headers, per RFC 9745 (Deprecation) and RFC 8594 (Sunset).
+    pub fn add_headers(&self, headers: &mut HeaderMap) {
+        let deprecated = format!("@{}", self.deprecated_utc_ms.div_euclid(1000));
+        if let Ok(value) = HeaderValue::from_str(&deprecated) {
+            headers.insert(HeaderName::from_static("deprecation"), value);
+        }
+
+        if let Some(sunset) = self.sunset_utc_ms {
+            let sunset = OffsetDateTime::from_unix_timestamp(sunset.div_euclid(1000))
+                .format("%a, %d %b %Y %H:%M:%S GMT");
+            if let Ok(value) = HeaderValue::from_str(&sunset) {
+                headers.insert(HeaderName::from_static("sunset"), value);
+            }
+        }
+    }
+}
@@ -0,0 +1,60 @@
+//! Just enough DER and PEM to handle keys and certificates.
+
+use failure::Error;
+
+pub(crate) fn pem_decode(pem: &str) -> Result<Vec<u8>, Error> {
+    let base64: String = pem.lines()
+        .filter(|line| !line.starts_with("-----"))
+        .map(str::trim)
+        .collect();
+    Ok(base64::decode(base64)?)
+}
+
+pub(crate) fn pem_encode(label: &str, der: &[u8]) -> String {
+    let base64 = base64::encode(der);
+    let mut pem = format!("-----BEGIN {}-----\n", label);
+    for line in base64.as_bytes().chunks(64) {
+        pem += &String::from_utf8_lossy(line);
+        pem += "\n";
+    }
+    pem += &format!("-----END {}-----\n", label);
+    pem
+}
+
+pub(crate) fn encode(tag: u8, contents: &[u8]) -> Vec<u8> {
+    let mut der = vec![tag];
+    let len = contents.len();
+    if len < 0x80 {
+        der.push(len as u8);
+    } else {
+        let len_bytes: Vec<u8> = len.to_be_bytes().iter().copied().skip_while(|b| *b == 0).collect();
+        der.push(0x80 | len_bytes.len() as u8);
+        der.extend(len_bytes);
+    }
+    der.extend_from_slice(contents);
+    der
+}
+
+/// Returns the contents of a DER element with the given tag, and whatever follows it.
+pub(crate) fn decode(der: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+    let (&found_tag, rest) = der.split_first()?;
+    if found_tag != tag {
+        return None;
+    }
+    let (&first, mut rest) = rest.split_first()?;
+    let len = if first < 0x80 {
+        usize::from(first)
+    } else {
+        let count = usize::from(first & 0x7f);
+        if count == 0 || count > 4 || rest.len() < count {
+            return None;
+        }
+        let len = rest[..count].iter().fold(0, |len, b| (len << 8) | usize::from(*b));
+        rest = &rest[count..];
+        len
+    };
+    if rest.len() < len {
+        return None;
+    }
+    Some(rest.split_at(len))
+}
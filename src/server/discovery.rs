@@ -0,0 +1,66 @@
+//! `/.well-known/feoblog.json` describes this server to FeoBlog clients, so
+//! that they can configure themselves from just a domain name.
+
+use actix_web::web::Data;
+use actix_web::{HttpRequest, HttpResponse};
+use serde_json::json;
+
+use super::{AppData, Error, MAX_ITEM_SIZE, base_url};
+use super::policy::Cache;
+
+/// The version of the protocol in docs/url_layout.md that this server speaks.
+/// Bumped only for changes that old clients can't ignore.
+pub(crate) const PROTOCOL_VERSION: u32 = 1;
+
+/// `/.well-known/feoblog.json`
+pub(crate) async fn well_known(data: Data<AppData>, req: HttpRequest) -> Result<HttpResponse, Error> {
+    let base_url = base_url(&req);
+
+    let item_types: Vec<String> = data.accepted_item_types.iter()
+        .map(|item_type| format!("{:?}", item_type).to_lowercase())
+        .collect();
+
+    let document = json!({
+        "software": {
+            "name": "feoblog",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "protocolVersion": PROTOCOL_VERSION,
+        "apiRoot": base_url,
+        "serverInfo": format!("{}/server/info/proto3", base_url),
+        "acceptedItemTypes": item_types,
+        "maxItemBytes": MAX_ITEM_SIZE,
+        "maxAttachmentBytes": data.max_attachment_bytes,
+        "proofOfWorkDifficulty": data.pow_difficulty,
+        "features": features(&data),
+        // From `feoblog peers key`, for other servers to trust:
+        "serverKey": data.server_key.as_ref().map(|key| key.to_base58()),
+    });
+
+    let mut response = HttpResponse::Ok();
+    // Only changes when the server is restarted with different options:
+    Cache::MaxAge(3600).apply(&mut response);
+    Ok(
+        response
+        .content_type("application/json")
+        .body(document.to_string())
+    )
+}
+
+/// Optional parts of the protocol (and extras) that this server supports.
+fn features(data: &AppData) -> Vec<&'static str> {
+    let mut features = vec!["search", "followers-only", "mirrors", "bloom-filters", "opds"];
+    let enabled = [
+        ("activitypub", data.activitypub.is_some()),
+        ("anonymous-comments", data.anonymous_comments),
+        ("backup", !data.backup_keys.is_empty()),
+        ("ipfs", data.ipfs),
+        ("maps", data.map_tiles.is_some()),
+        ("nostr", !data.nostr_relays.is_empty()),
+        ("proof-of-work", data.pow_difficulty > 0),
+        ("view-counts", data.count_views),
+        ("web-client", data.web_client),
+    ];
+    features.extend(enabled.iter().filter(|(_, on)| *on).map(|(name, _)| *name));
+    features
+}
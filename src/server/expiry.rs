@@ -0,0 +1,34 @@
+//! Removes items once they've expired. (See: `Item.expires_ms_utc`)
+//!
+//! Expired items are removed like any other, so they stop being served, and
+//! `feoblog mod purge` deletes them for good.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::backend::{Factory, Timestamp};
+use super::maintenance::Maintenance;
+use super::snapshot::HomepageSnapshot;
+
+/// How long an item may be served after it expires, at most.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Runs forever. Spawn it on the server's runtime.
+pub(crate) async fn expire_loop(factory: Box<dyn Factory>, maintenance: Arc<Maintenance>, homepage: Arc<HomepageSnapshot>) {
+    let mut interval = actix_web::rt::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        if maintenance.is_active() {
+            continue;
+        }
+        let expired = factory.open().and_then(|backend| backend.expire_items(Timestamp::now()));
+        match expired {
+            Ok(0) => {},
+            Ok(count) => {
+                log::info!("Removed {} expired items", count);
+                homepage.invalidate();
+            },
+            Err(err) => log::warn!("Error removing expired items: {}", err),
+        }
+    }
+}
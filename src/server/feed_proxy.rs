@@ -0,0 +1,125 @@
+//! Fills gaps in feeds by fetching items from followed users' home servers.
+//!
+//! Enabled with `feoblog serve --proxy-feeds`. Before rendering a user's feed,
+//! we check the first page of each followed user's items on the first server
+//! listed in their profile, and save any (validly signed) items we're missing.
+//! That way even a sparsely-synced server can show complete feeds.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use failure::Error;
+use protobuf::Message as _;
+
+use crate::backend::{Factory, Signature, UserID};
+use crate::protos::{BundledItem, Item, ItemBundle};
+use crate::sync::Peer;
+
+/// Don't re-check a user's home server more often than this.
+const CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Don't make readers wait longer than this for remote servers.
+pub(crate) const FILL_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub(crate) struct FeedProxy {
+    /// When we last checked each user's home server.
+    checked: Mutex<HashMap<Vec<u8>, Instant>>,
+}
+
+impl FeedProxy {
+    pub fn new() -> Self {
+        FeedProxy {
+            checked: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Fetch missing recent items for everyone that `user` follows.
+    pub async fn fill(&self, factory: &dyn Factory, user: &UserID) -> Result<(), Error> {
+        let mut fetches = vec![];
+        {
+            let backend = factory.open()?;
+            let follows = match profile(backend.user_profile(user)?.map(|row| row.item_bytes))? {
+                Some(item) => item.get_profile().get_follows().to_vec(),
+                None => return Ok(()),
+            };
+
+            for follow in follows {
+                let followed = UserID::from_vec(follow.get_user().get_bytes().to_vec())?;
+                if !self.should_check(&followed) {
+                    continue;
+                }
+                let home = profile(backend.user_profile(&followed)?.map(|row| row.item_bytes))?
+                    .and_then(|item| item.get_profile().get_servers().first().map(|s| s.url.clone()));
+                if let Some(home) = home {
+                    fetches.push((followed, home));
+                }
+            }
+        }
+
+        for (followed, home) in fetches {
+            if let Err(err) = self.fill_user(factory, &followed, &home).await {
+                log::warn!("Error fetching items for {} from {}: {}", followed.to_base58(), home, err);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn fill_user(&self, factory: &dyn Factory, user: &UserID, home: &str) -> Result<(), Error> {
+        let peer = Peer::new(home);
+        let list = peer.user_item_list(user, None).await?;
+
+        let mut missing = vec![];
+        {
+            let backend = factory.open()?;
+            for entry in list.get_items() {
+                let signature = Signature::from_vec(entry.get_signature().get_bytes().to_vec())?;
+                if !backend.user_item_exists(user, &signature)? && !backend.user_item_deleted(user, &signature)? {
+                    missing.push(signature);
+                }
+            }
+        }
+
+        let mut bundle = ItemBundle::new();
+        for signature in missing {
+            let bytes = match peer.item(user, &signature).await? {
+                Some(bytes) => bytes,
+                None => continue,
+            };
+            let mut item = BundledItem::new();
+            item.mut_user_id().set_bytes(user.bytes().into());
+            item.mut_signature().set_bytes(signature.bytes().into());
+            item.item_bytes = bytes;
+            bundle.items.push(item);
+        }
+
+        // Checks signatures before saving anything:
+        let mut backend = factory.open()?;
+        crate::bundle::import_bundle(backend.as_mut(), &bundle)?;
+        Ok(())
+    }
+
+    /// True if we haven't recently checked this user's home server.
+    /// (Failed checks count too, so that unreachable servers don't slow down every page view.)
+    fn should_check(&self, user: &UserID) -> bool {
+        let mut checked = self.checked.lock().expect("FeedProxy lock poisoned");
+        let now = Instant::now();
+        checked.retain(|_, when| now.duration_since(*when) < CACHE_TTL);
+        if checked.contains_key(user.bytes()) {
+            return false;
+        }
+        checked.insert(user.bytes().to_vec(), now);
+        true
+    }
+}
+
+fn profile(bytes: Option<Vec<u8>>) -> Result<Option<Item>, Error> {
+    let bytes = match bytes {
+        Some(bytes) => bytes,
+        None => return Ok(None),
+    };
+    let mut item = Item::new();
+    item.merge_from_bytes(&bytes)?;
+    Ok(Some(item))
+}
@@ -4,11 +4,27 @@ use askama::Result;
 
 use crate::markdown::ToHTML;
 use crate::backend::Timestamp;
+use super::locale;
 
 pub(crate) fn markdown(s: &str) -> Result<String> {
     Ok(s.md_to_html())
 }
 
+/// A human-readable size. (ex: "1.5 KiB")
+pub(crate) fn file_size(bytes: &u64) -> Result<String> {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB"];
+    if *bytes < 1024 {
+        return Ok(format!("{} bytes", bytes));
+    }
+    let mut size = *bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    Ok(format!("{:.1} {}", size, UNITS[unit]))
+}
+
 
 // Seems filters always accept by reference:
 pub(crate) fn with_offset(utc_ms: &i64, offset_mins: &i32) -> Result<String> {
@@ -16,6 +32,6 @@ pub(crate) fn with_offset(utc_ms: &i64, offset_mins: &i32) -> Result<String> {
         unix_utc_ms: *utc_ms,
     };
     Ok(
-        timestamp.format_with_offset(*offset_mins as i16)
+        timestamp.format_with(*offset_mins as i16, &locale::get().date_format)
     )
 }
\ No newline at end of file
@@ -0,0 +1,191 @@
+//! Adds items' bytes and attachments to an IPFS node, so that they can also be
+//! fetched by their content. (`feoblog serve --ipfs-api`)
+//!
+//! When an item or attachment is uploaded, it's queued. A background task adds
+//! (and pins) it through the node's RPC API, and records its CID. CIDs are then
+//! served in `X-Ipfs-Path` headers, and at `/u/<userID>/i/<signature>/ipfs.json`.
+//!
+//! See: <https://docs.ipfs.tech/reference/kubo/rpc/#api-v0-add>
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::client::Client;
+use actix_web::web::{Data, Path};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse};
+use failure::{Error as FailError, ResultExt, bail, format_err};
+use protobuf::Message as _;
+use serde_json::{Map, Value, json};
+
+use crate::backend::{Backend, Factory, QueuedIpfsPin, Signature, Timestamp, UserID};
+use crate::protos::Item;
+use super::maintenance::Maintenance;
+use super::messages::{self, Message};
+use super::{AppData, Error};
+
+/// How often we check the queue.
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Give up on a pin after this many failures.
+const MAX_ATTEMPTS: u32 = 10;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(60);
+const MAX_BACKOFF: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Attachments can be large, and the node hashes them as they arrive.
+const ADD_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Queue an item's bytes (with an empty `file_name`), or one of its attachments, to be added.
+pub(crate) fn queue(backend: &dyn Backend, user: &UserID, signature: &Signature, file_name: &str) -> Result<(), FailError> {
+    backend.queue_ipfs_pin(&QueuedIpfsPin {
+        user: user.clone(),
+        signature: signature.clone(),
+        file_name: file_name.to_string(),
+        attempts: 0,
+    })
+}
+
+/// The value of an `X-Ipfs-Path` header, if we've added this to IPFS.
+pub(crate) fn path(backend: &dyn Backend, user: &UserID, signature: &Signature, file_name: &str) -> Result<Option<String>, FailError> {
+    let cid = backend.ipfs_cid(user, signature, file_name)?;
+    Ok(cid.map(|cid| format!("/ipfs/{}", cid)))
+}
+
+/// Runs forever. Spawn it on the server's runtime.
+pub(crate) async fn pin_loop(factory: Box<dyn Factory>, maintenance: Arc<Maintenance>, api: String) {
+    let mut interval = actix_web::rt::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        if maintenance.is_active() {
+            continue;
+        }
+        if let Err(err) = pin_due(factory.as_ref(), &api).await {
+            log::warn!("Error adding to IPFS: {}", err);
+        }
+    }
+}
+
+async fn pin_due(factory: &dyn Factory, api: &str) -> Result<(), FailError> {
+    let mut due = vec![];
+    factory.open()?.due_ipfs_pins(Timestamp::now(), &mut |pin| {
+        due.push(pin);
+        Ok(due.len() < 100)
+    })?;
+
+    for pin in due {
+        let backend = factory.open()?;
+        let bytes = if pin.file_name.is_empty() {
+            backend.user_item(&pin.user, &pin.signature)?.map(|row| row.item_bytes)
+        } else {
+            backend.attachment(&pin.user, &pin.signature, &pin.file_name)?
+        };
+        let bytes = match bytes {
+            Some(bytes) => bytes,
+            None => {
+                // Removed since it was queued.
+                backend.finish_ipfs_pin(&pin)?;
+                continue;
+            }
+        };
+
+        match add(api, &pin, bytes).await {
+            Ok(cid) => {
+                backend.set_ipfs_cid(&pin.user, &pin.signature, &pin.file_name, &cid)?;
+                backend.finish_ipfs_pin(&pin)?;
+            },
+            Err(err) => {
+                failed(backend.as_ref(), &pin, &err.to_string())?;
+                // There's only the one node, and it's probably down. Try the rest later:
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn failed(backend: &dyn Backend, pin: &QueuedIpfsPin, error: &str) -> Result<(), FailError> {
+    if pin.attempts + 1 >= MAX_ATTEMPTS {
+        log::warn!(
+            "Giving up adding {}/{} to IPFS: {}",
+            pin.signature.to_base58(), pin.file_name, error,
+        );
+        return backend.finish_ipfs_pin(pin);
+    }
+
+    let retry_at = Timestamp {
+        unix_utc_ms: Timestamp::now().unix_utc_ms + backoff(pin.attempts).as_millis() as i64,
+    };
+    backend.ipfs_pin_failed(pin, retry_at, error)
+}
+
+/// How long to wait after `attempts` previous failures.
+fn backoff(attempts: u32) -> Duration {
+    let backoff = MIN_BACKOFF.checked_mul(1 << attempts.min(16)).unwrap_or(MAX_BACKOFF);
+    backoff.min(MAX_BACKOFF)
+}
+
+/// Add and pin `bytes`. Returns their CID.
+async fn add(api: &str, pin: &QueuedIpfsPin, bytes: Vec<u8>) -> Result<String, FailError> {
+    let url = format!("{}/api/v0/add?pin=true&cid-version=1", api.trim_end_matches('/'));
+
+    // The file name isn't part of the CID. It's only to make the node's logs readable:
+    let file_name = if pin.file_name.is_empty() { "item.proto3" } else { pin.file_name.as_str() };
+    let boundary = bs58::encode(sodiumoxide::randombytes::randombytes(16)).into_string();
+    let mut body = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+        boundary,
+        file_name.replace('"', ""),
+    ).into_bytes();
+    body.extend_from_slice(&bytes);
+    body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+    let mut response = Client::builder().timeout(ADD_TIMEOUT).finish()
+        .post(&url)
+        .content_type(format!("multipart/form-data; boundary={}", boundary))
+        .send_body(body).await
+        .map_err(|err| format_err!("Error sending to {}: {}", url, err))?;
+    if !response.status().is_success() {
+        bail!("{} responded with {}", url, response.status());
+    }
+    let added: Value = response.json().await
+        .map_err(|err| format_err!("Error reading response from {}: {}", url, err))?;
+    match added["Hash"].as_str() {
+        Some(cid) => Ok(cid.to_string()),
+        None => bail!("No CID from {}", url),
+    }
+}
+
+/// CIDs for an item's bytes and attachments, for any that we've added to IPFS.
+/// `/u/{userID}/i/{signature}/ipfs.json`
+pub(crate) async fn get_cids(
+    data: Data<AppData>,
+    Path((user_id, signature)): Path<(UserID, Signature)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    let row = match backend.user_item(&user_id, &signature).compat()? {
+        Some(row) => row,
+        None => return Ok(messages::response(&req, StatusCode::NOT_FOUND, Message::NoSuchItem)),
+    };
+    let mut item = Item::new();
+    item.merge_from_bytes(&row.item_bytes)?;
+
+    let mut files = Map::new();
+    for file in item.get_attachments().get_file() {
+        if let Some(cid) = backend.ipfs_cid(&user_id, &signature, file.get_name()).compat()? {
+            files.insert(file.get_name().to_string(), json!(cid));
+        }
+    }
+    let cids = json!({
+        "item": backend.ipfs_cid(&user_id, &signature, "").compat()?,
+        "files": files,
+    });
+
+    Ok(
+        HttpResponse::Ok()
+        .content_type("application/json")
+        .body(cids.to_string())
+    )
+}
@@ -0,0 +1,147 @@
+//! Finds links in users' posts that no longer work, so that they can fix them.
+//!
+//! Enabled with `feoblog serve --linkcheck`, or run once with `feoblog linkcheck`.
+//! We check each absolute link in server users' posts, and record the ones
+//! that fail. (See: `Backend::user_dead_links`) Authors can see them on their
+//! stats page. (`/u/{userID}/stats/`)
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::client::Client;
+use actix_web::http::StatusCode;
+use failure::{Error, bail, format_err};
+use protobuf::Message as _;
+
+use crate::backend::{DeadLink, Factory, Signature, Timestamp, UserID};
+use crate::markdown;
+use crate::protos::Item;
+use super::maintenance::Maintenance;
+
+/// Links don't often die, so there's no need to hammer other servers.
+const POLL_INTERVAL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Default)]
+pub(crate) struct LinkCheckReport {
+    /// Posts that had links.
+    pub posts: usize,
+    pub links: usize,
+    pub dead: usize,
+}
+
+/// Runs forever. Spawn it on the server's runtime.
+pub(crate) async fn linkcheck_loop(factory: Box<dyn Factory>, maintenance: Arc<Maintenance>) {
+    let mut interval = actix_web::rt::time::interval(POLL_INTERVAL);
+    // The first tick is immediate. Don't re-check everything each time the server restarts:
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        if maintenance.is_active() {
+            continue;
+        }
+        match check_all(factory.as_ref()).await {
+            Ok(report) => log::info!("Checked {} links, and found {} dead", report.links, report.dead),
+            Err(err) => log::warn!("Error checking links: {}", err),
+        }
+    }
+}
+
+/// Check links in all server users' posts.
+pub(crate) async fn check_all(factory: &dyn Factory) -> Result<LinkCheckReport, Error> {
+    let mut users = vec![];
+    factory.open()?.server_users(&mut |server_user| {
+        users.push(server_user.user);
+        Ok(true)
+    })?;
+
+    let mut checked = HashMap::new();
+    let mut report = LinkCheckReport::default();
+    for user in users {
+        let user_report = check_user(factory, &user, &mut checked).await?;
+        report.posts += user_report.posts;
+        report.links += user_report.links;
+        report.dead += user_report.dead;
+    }
+    Ok(report)
+}
+
+/// Check links in one user's posts.
+/// `checked` has the results of links we've already checked (None if they
+/// worked), so that we only request each one once.
+pub(crate) async fn check_user(
+    factory: &dyn Factory,
+    user: &UserID,
+    checked: &mut HashMap<String, Option<String>>,
+) -> Result<LinkCheckReport, Error> {
+    // Don't hold a connection while we wait on other servers:
+    let mut posts: Vec<(Signature, Vec<String>)> = vec![];
+    factory.open()?.user_items(user, Timestamp::now(), &mut |row| {
+        let mut item = Item::new();
+        item.merge_from_bytes(&row.item_bytes)?;
+        if item.has_post() {
+            posts.push((row.signature, markdown::links(item.get_post().get_body())));
+        }
+        Ok(true)
+    })?;
+
+    let mut report = LinkCheckReport::default();
+    for (signature, links) in posts {
+        let mut dead = vec![];
+        if !links.is_empty() {
+            report.posts += 1;
+        }
+        for url in links {
+            report.links += 1;
+            let error = match checked.get(&url) {
+                Some(error) => error.clone(),
+                None => {
+                    let error = check_link(&url).await.err().map(|err| err.to_string());
+                    checked.insert(url.clone(), error.clone());
+                    error
+                }
+            };
+            if let Some(error) = error {
+                dead.push(DeadLink {
+                    signature: signature.clone(),
+                    url,
+                    error,
+                    checked: Timestamp::now(),
+                });
+            }
+        }
+        report.dead += dead.len();
+        // Even if there are none, to forget links that work again:
+        factory.open()?.set_dead_links(user, &signature, &dead)?;
+    }
+
+    Ok(report)
+}
+
+/// Ok if the link leads somewhere. (Including redirects, which we don't follow.)
+async fn check_link(url: &str) -> Result<(), Error> {
+    let client = Client::default();
+    let response = client.head(url)
+        .header("User-Agent", concat!("FeoBlog/", env!("CARGO_PKG_VERSION")))
+        .timeout(REQUEST_TIMEOUT)
+        .send().await
+        .map_err(|err| format_err!("{}", err))?;
+    let mut status = response.status();
+
+    // Some servers only answer GETs:
+    if status == StatusCode::METHOD_NOT_ALLOWED || status == StatusCode::NOT_IMPLEMENTED {
+        let response = client.get(url)
+            .header("User-Agent", concat!("FeoBlog/", env!("CARGO_PKG_VERSION")))
+            .timeout(REQUEST_TIMEOUT)
+            .send().await
+            .map_err(|err| format_err!("{}", err))?;
+        status = response.status();
+    }
+
+    if status.is_client_error() || status.is_server_error() {
+        bail!("{}", status);
+    }
+    Ok(())
+}
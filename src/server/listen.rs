@@ -0,0 +1,246 @@
+//! Where `feoblog serve` listens, and how. (`--bind`, `--bind-tls`,
+//! `--bind-proxy`, and `[[listen]]` tables in the config file)
+//!
+//! A listener can be a TCP address or (on Unix) a socket file, can serve
+//! HTTPS, and can expect connections to start with a [PROXY protocol] header
+//! from a load balancer, so that we see clients' real addresses. ex:
+//!
+//! ```toml
+//! [[listen]]
+//! address = "0.0.0.0:443"
+//! tls = true
+//!
+//! [[listen]]
+//! unix = "/run/feoblog/feoblog.sock"
+//! proxy-protocol = true
+//! ```
+//!
+//! [PROXY protocol]: https://www.haproxy.org/download/2.4/doc/proxy-protocol.txt
+
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use failure::{Error, bail};
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct Listen {
+    /// A local address and port. (ex: "127.0.0.1:8080")
+    pub address: Option<String>,
+
+    /// A Unix socket file, instead of an address.
+    pub unix: Option<PathBuf>,
+
+    /// Serve HTTPS. Requires --cert and --key, or --acme-domain.
+    #[serde(default)]
+    pub tls: bool,
+
+    /// Connections start with a PROXY protocol (v1 or v2) header.
+    #[serde(default)]
+    pub proxy_protocol: bool,
+}
+
+impl Listen {
+    pub fn tcp(address: &str, tls: bool) -> Self {
+        Listen {
+            address: Some(address.to_string()),
+            unix: None,
+            tls,
+            proxy_protocol: false,
+        }
+    }
+
+    /// A TCP address that expects PROXY protocol headers.
+    pub fn proxied(address: &str) -> Self {
+        Listen {
+            proxy_protocol: true,
+            ..Listen::tcp(address, false)
+        }
+    }
+
+    pub fn check(&self) -> Result<(), Error> {
+        match (&self.address, &self.unix) {
+            (Some(_), None) => {},
+            (None, Some(_)) if cfg!(unix) => {},
+            (None, Some(_)) => bail!("Unix sockets aren't supported on this system: {}", self),
+            _ => bail!("Each [[listen]] needs exactly one of `address` or `unix`."),
+        }
+        if self.tls && self.unix.is_some() {
+            bail!("{}: tls isn't supported on Unix sockets.", self);
+        }
+        if self.tls && self.proxy_protocol {
+            // actix-web can't read the header before the TLS handshake:
+            bail!("{}: proxy-protocol can't be used with tls. Have the proxy terminate TLS.", self);
+        }
+        Ok(())
+    }
+
+    /// The URL to show when the server starts.
+    pub fn url(&self) -> String {
+        match (&self.address, &self.unix) {
+            (Some(address), _) => format!("{}://{}/", if self.tls { "https" } else { "http" }, address),
+            (None, Some(path)) => format!("http+unix://{}", path.display()),
+            (None, None) => String::new(),
+        }
+    }
+}
+
+impl fmt::Display for Listen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.address, &self.unix) {
+            (Some(address), _) => write!(f, "{}", address),
+            (None, Some(path)) => write!(f, "{}", path.display()),
+            (None, None) => write!(f, "(no address)"),
+        }
+    }
+}
+
+// Work around https://github.com/actix/actix-web/issues/1913
+pub(crate) fn open_socket(bind: &str) -> Result<TcpListener, Error> {
+    use socket2::{Domain, Protocol, Socket, Type};
+
+    // Eh, this is what actix was using:
+    let backlog = 1024;
+
+    let addr = bind.parse()?;
+    let domain = match addr {
+        SocketAddr::V4(_) => Domain::ipv4(),
+        SocketAddr::V6(_) => Domain::ipv6(),
+    };
+    let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
+    socket.bind(&addr.into())?;
+    socket.listen(backlog)?;
+
+    Ok(socket.into_tcp_listener())
+}
+
+/// Listen on a Unix socket, replacing one left behind by an earlier run.
+#[cfg(unix)]
+pub(crate) fn open_unix_socket(path: &std::path::Path) -> Result<std::os::unix::net::UnixListener, Error> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            bail!("{} exists, and isn't a socket.", path.display());
+        }
+        std::fs::remove_file(path)?;
+    }
+    Ok(std::os::unix::net::UnixListener::bind(path)?)
+}
+
+/// The v2 header's first 12 bytes.
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Including "PROXY " and the trailing CRLF.
+const V1_MAX_LENGTH: usize = 107;
+
+/// Proxies send the header as soon as they connect. Without a limit, a client
+/// that connects and sends nothing would hold on to its connection forever.
+pub(crate) const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Read a PROXY protocol header from the start of a connection, and return
+/// the client's address. None if the proxy didn't say. (ex: health checks)
+///
+/// Reads exactly the header, so the HTTP request that follows is untouched.
+/// Fails if the whole header doesn't arrive within `timeout`.
+pub(crate) async fn read_proxy_header<S: AsyncRead + Unpin>(io: &mut S, timeout: Duration) -> io::Result<Option<SocketAddr>> {
+    match actix_web::rt::time::timeout(timeout, read_header(io)).await {
+        Ok(result) => result,
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "Timed out reading the PROXY protocol header")),
+    }
+}
+
+async fn read_header<S: AsyncRead + Unpin>(io: &mut S) -> io::Result<Option<SocketAddr>> {
+    // The shortest v1 header ("PROXY UNKNOWN\r\n") is longer than this:
+    let mut start = [0u8; 12];
+    io.read_exact(&mut start).await?;
+
+    if &start[..] == V2_SIGNATURE {
+        let mut header = [0u8; 4];
+        io.read_exact(&mut header).await?;
+        let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let mut addresses = vec![0u8; length];
+        io.read_exact(&mut addresses).await?;
+        return parse_v2(header[0], header[1], &addresses);
+    }
+
+    if !start.starts_with(b"PROXY ") {
+        return Err(invalid("Expected a PROXY protocol header"));
+    }
+    // v1 is a line of text. Read it a byte at a time, so we don't read past it:
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX_LENGTH {
+            return Err(invalid("PROXY protocol header is too long"));
+        }
+        let mut byte = [0u8; 1];
+        io.read_exact(&mut byte).await?;
+        line.push(byte[0]);
+    }
+    parse_v1(&line)
+}
+
+/// ex: "PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n"
+pub(crate) fn parse_v1(line: &[u8]) -> io::Result<Option<SocketAddr>> {
+    let line = std::str::from_utf8(line).map_err(|_| invalid("PROXY protocol header isn't text"))?;
+    let line = line.strip_suffix("\r\n").ok_or_else(|| invalid("PROXY protocol header must end with CRLF"))?;
+    let parts: Vec<&str> = line.split(' ').collect();
+    match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", "TCP4", source, _destination, port, _destination_port]
+        | ["PROXY", "TCP6", source, _destination, port, _destination_port] => {
+            let ip: IpAddr = source.parse().map_err(|_| invalid("Invalid source address in PROXY protocol header"))?;
+            let port: u16 = port.parse().map_err(|_| invalid("Invalid source port in PROXY protocol header"))?;
+            Ok(Some(SocketAddr::new(ip, port)))
+        },
+        _ => Err(invalid("Invalid PROXY protocol header")),
+    }
+}
+
+/// The v2 header's version/command and family/protocol bytes, and the
+/// address block that follows them.
+pub(crate) fn parse_v2(version_command: u8, family: u8, addresses: &[u8]) -> io::Result<Option<SocketAddr>> {
+    if version_command >> 4 != 2 {
+        return Err(invalid("Unsupported PROXY protocol version"));
+    }
+    match version_command & 0x0F {
+        // LOCAL: the proxy's own connection. (ex: health checks)
+        0x0 => return Ok(None),
+        // PROXY:
+        0x1 => {},
+        _ => return Err(invalid("Unsupported PROXY protocol command")),
+    }
+
+    match family >> 4 {
+        // AF_INET
+        0x1 => {
+            if addresses.len() < 12 {
+                return Err(invalid("PROXY protocol header is too short"));
+            }
+            let ip = Ipv4Addr::new(addresses[0], addresses[1], addresses[2], addresses[3]);
+            let port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(Some(SocketAddr::new(ip.into(), port)))
+        },
+        // AF_INET6
+        0x2 => {
+            if addresses.len() < 36 {
+                return Err(invalid("PROXY protocol header is too short"));
+            }
+            let mut ip = [0u8; 16];
+            ip.copy_from_slice(&addresses[..16]);
+            let port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port)))
+        },
+        // AF_UNSPEC, AF_UNIX: no address that we can use.
+        _ => Ok(None),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
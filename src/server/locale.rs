@@ -0,0 +1,56 @@
+//! The language and date format used when rendering pages and feeds.
+//!
+//! These are set once, when the server starts, and read from templates and
+//! filters which don't have access to [`AppData`](super::AppData).
+
+use failure::{bail, Error};
+use once_cell::sync::OnceCell;
+
+/// ex: "2020-10-31 13:45:00 -0700"
+pub(crate) const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d %H:%M:%S %z";
+
+pub(crate) struct Locale {
+    /// A BCP 47 language tag. (ex: "en", "de-CH")
+    pub lang: String,
+
+    /// A format string for the `time` crate. Note that month and day names
+    /// are always in English, so numeric formats work best for other languages.
+    pub date_format: String,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Self {
+            lang: "en".into(),
+            date_format: DEFAULT_DATE_FORMAT.into(),
+        }
+    }
+}
+
+static LOCALE: OnceCell<Locale> = OnceCell::new();
+
+/// Set the server's locale. May only be called once, before rendering anything.
+pub(crate) fn init(locale: Locale) -> Result<(), Error> {
+    let valid_lang = !locale.lang.is_empty()
+        && locale.lang.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if !valid_lang {
+        bail!("Invalid language tag: {:?}", locale.lang);
+    }
+    if locale.date_format.trim().is_empty() {
+        bail!("Date format must not be empty");
+    }
+
+    if LOCALE.set(locale).is_err() {
+        bail!("Locale was already set");
+    }
+    Ok(())
+}
+
+pub(crate) fn get() -> &'static Locale {
+    LOCALE.get_or_init(Locale::default)
+}
+
+/// The language of rendered pages. (ex: for `<html lang="...">`)
+pub(crate) fn lang() -> &'static str {
+    &get().lang
+}
@@ -0,0 +1,221 @@
+//! Maintenance mode.
+//!
+//! `feoblog maintenance start` creates a flag file next to the database. While
+//! it exists, the server refuses writes with a `503 Service Unavailable` and a
+//! `Retry-After` header, so that the operator can back up or migrate the
+//! database. Reads keep working.
+//!
+//! With `feoblog serve --maintenance-journal`, small item uploads are accepted
+//! anyway: we check everything we can without the database and append them to
+//! a journal file. Once maintenance is over, the journal is saved to the
+//! database as if the items had just been uploaded.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, ErrorKind};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web::HttpResponse;
+use failure::{Error, ResultExt};
+use protobuf::{CodedInputStream, Message as _};
+
+use crate::backend::{Backend, Factory, Signature, Timestamp, UserID};
+use crate::bundle::{Upload, check_upload};
+use crate::protos::BundledItem;
+
+/// Items larger than this aren't journaled. Their clients can retry later.
+pub(crate) const MAX_JOURNAL_ITEM_SIZE: usize = 32 * 1024;
+
+/// Stop journaling once the journal gets this big.
+const MAX_JOURNAL_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Used if the flag file doesn't say how long maintenance will take.
+const DEFAULT_RETRY_AFTER_SECS: u64 = 300;
+
+const REPLAY_INTERVAL: Duration = Duration::from_secs(10);
+
+pub(crate) struct Maintenance {
+    flag_file: PathBuf,
+
+    /// Set if we journal items during maintenance.
+    /// The lock keeps appends and replays from interleaving.
+    journal: Option<Mutex<PathBuf>>,
+}
+
+fn flag_file(sqlite_file: &str) -> PathBuf {
+    format!("{}.maintenance", sqlite_file).into()
+}
+
+fn journal_file(sqlite_file: &str) -> PathBuf {
+    format!("{}.journal", sqlite_file).into()
+}
+
+/// Put servers using this database into maintenance mode.
+pub(crate) fn start(sqlite_file: &str, retry_after_secs: u64) -> Result<(), Error> {
+    let path = flag_file(sqlite_file);
+    fs::write(&path, retry_after_secs.to_string())
+        .with_context(|_| format!("Error writing {}", path.display()))?;
+    Ok(())
+}
+
+pub(crate) fn stop(sqlite_file: &str) -> Result<(), Error> {
+    let path = flag_file(sqlite_file);
+    if path.exists() {
+        fs::remove_file(&path)
+            .with_context(|_| format!("Error removing {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// A 503 for clients that tried to write during maintenance.
+pub(crate) fn unavailable(retry_after_secs: u64) -> HttpResponse {
+    HttpResponse::ServiceUnavailable()
+        .header("Retry-After", retry_after_secs.to_string())
+        .content_type("text/plain; charset=utf-8")
+        .body("The server is down for maintenance. Try again later.")
+}
+
+impl Maintenance {
+    pub fn new(sqlite_file: &str, journal: bool) -> Self {
+        Self {
+            flag_file: flag_file(sqlite_file),
+            journal: if journal { Some(Mutex::new(journal_file(sqlite_file))) } else { None },
+        }
+    }
+
+    /// If we're in maintenance mode, how many seconds clients should wait
+    /// before they retry writes.
+    pub fn retry_after(&self) -> Option<u64> {
+        match fs::read_to_string(&self.flag_file) {
+            Ok(text) => Some(text.trim().parse().unwrap_or(DEFAULT_RETRY_AFTER_SECS)),
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => {
+                // Safer to assume the operator wants us to keep our hands off.
+                log::warn!("Error reading {}: {}", self.flag_file.display(), err);
+                Some(DEFAULT_RETRY_AFTER_SECS)
+            },
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.retry_after().is_some()
+    }
+
+    /// Can we journal an item of this size?
+    pub fn can_journal(&self, length: usize) -> bool {
+        self.journal.is_some() && length <= MAX_JOURNAL_ITEM_SIZE
+    }
+
+    /// Append an item to the journal. It should already have been checked
+    /// as far as possible without the database.
+    ///
+    /// Returns false if the journal is full.
+    pub fn append(&self, user: &UserID, signature: &Signature, item_bytes: &[u8]) -> Result<bool, Error> {
+        let path = match &self.journal {
+            None => return Ok(false),
+            Some(path) => path.lock().expect("journal lock poisoned"),
+        };
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&*path)
+            .with_context(|_| format!("Error opening {}", path.display()))?;
+        if file.metadata()?.len() + item_bytes.len() as u64 > MAX_JOURNAL_SIZE {
+            return Ok(false);
+        }
+
+        let mut entry = BundledItem::new();
+        entry.mut_user_id().set_bytes(user.bytes().into());
+        entry.mut_signature().set_bytes(signature.bytes().into());
+        entry.item_bytes = item_bytes.to_vec();
+        entry.write_length_delimited_to_writer(&mut file)?;
+
+        // We're about to tell the client that we have it:
+        file.sync_data()?;
+        Ok(true)
+    }
+
+    /// Runs forever. Spawn it on the server's runtime.
+    pub(crate) async fn replay_loop(self: Arc<Self>, factory: Box<dyn Factory>) {
+        if self.journal.is_none() {
+            return;
+        }
+
+        let mut interval = actix_web::rt::time::interval(REPLAY_INTERVAL);
+        loop {
+            interval.tick().await;
+            if self.is_active() {
+                continue;
+            }
+            if let Err(err) = factory.open().and_then(|mut backend| self.replay(backend.as_mut())) {
+                log::warn!("Error saving journaled items: {}", err);
+            }
+        }
+    }
+
+    /// Save everything in the journal, then delete it.
+    ///
+    /// If there's an error, the journal is kept to try again later. Items
+    /// that were already saved will be skipped then.
+    fn replay(&self, backend: &mut dyn Backend) -> Result<(), Error> {
+        let path = match &self.journal {
+            None => return Ok(()),
+            Some(path) => path.lock().expect("journal lock poisoned"),
+        };
+
+        let file = match File::open(&*path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => Err(err)?,
+        };
+
+        let mut reader = BufReader::new(file);
+        let mut input = CodedInputStream::from_buffered_reader(&mut reader);
+        let mut saved = 0;
+        while !input.eof()? {
+            let entry: BundledItem = match input.read_message() {
+                Ok(entry) => entry,
+                Err(err) => {
+                    // Probably a partial write when the server stopped, which
+                    // means we never told that client that we had its item.
+                    log::warn!("Skipping the rest of {}: {}", path.display(), err);
+                    break;
+                }
+            };
+            if save_entry(backend, &entry)? {
+                saved += 1;
+            }
+        }
+
+        fs::remove_file(&*path).or_else(|err| match err.kind() {
+            ErrorKind::NotFound => Ok(()),
+            _ => Err(err),
+        })?;
+        if saved > 0 {
+            log::info!("Saved {} items from the maintenance journal", saved);
+        }
+        Ok(())
+    }
+}
+
+/// Does the parts of `put_item` that need the database.
+///
+/// Returns whether the item was saved. Items we refuse are logged, not retried.
+fn save_entry(backend: &mut dyn Backend, entry: &BundledItem) -> Result<bool, Error> {
+    let user = UserID::from_vec(entry.get_user_id().get_bytes().to_vec())?;
+    let signature = Signature::from_vec(entry.get_signature().get_bytes().to_vec())?;
+    let sig = signature.to_base58();
+    let (row, item) = match check_upload(backend, user, signature, entry.get_item_bytes(), Timestamp::now())? {
+        Upload::New(row, item) => (row, item),
+        Upload::Exists => return Ok(false),
+        Upload::Refused(reason) => {
+            log::warn!("Dropping journaled item {}: {}", sig, reason);
+            return Ok(false);
+        },
+    };
+
+    backend.save_user_item(&row, &item).context("Error saving journaled item")?;
+    if let Err(err) = backend.queue_push(&row.user, &row.signature) {
+        log::warn!("Error queueing item for peers: {}", err);
+    }
+    Ok(true)
+}
@@ -0,0 +1,123 @@
+//! Static map tiles for posts with a Location.
+//!
+//! Enabled with `feoblog serve --map-tiles URL_TEMPLATE`. We fetch a single
+//! tile covering the post's location from the tile server, cache it in the
+//! backend, and serve it from our own URL, so that readers' browsers never
+//! contact the tile server directly.
+
+use std::time::Duration;
+
+use actix_web::client::Client;
+use failure::{Error, bail, format_err};
+
+use crate::backend::{Factory, MapTile, Timestamp};
+use crate::protos::{Location, LocationPrecision};
+
+/// Tiles rarely change. Re-fetch them after this long.
+const CACHE_TTL_MS: i64 = 30 * 24 * 60 * 60 * 1000;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Tiles are usually ~20KiB PNGs.
+const MAX_TILE_BYTES: usize = 1024 * 1024;
+
+pub(crate) struct MapTiles {
+    /// ex: "https://tile.example.com/{z}/{x}/{y}.png"
+    url_template: String,
+}
+
+impl MapTiles {
+    pub fn new(url_template: String) -> Self {
+        MapTiles { url_template }
+    }
+
+    /// The PNG tile that shows `location`, at a zoom level that matches its precision.
+    pub async fn tile(&self, factory: &dyn Factory, location: &Location) -> Result<Vec<u8>, Error> {
+        let (z, x, y) = tile_coordinates(location);
+
+        let cached = factory.open()?.map_tile(z, x, y)?;
+        if let Some(tile) = &cached {
+            if Timestamp::now().unix_utc_ms - tile.fetched.unix_utc_ms < CACHE_TTL_MS {
+                return Ok(tile.bytes.clone());
+            }
+        }
+
+        let bytes = match self.fetch(z, x, y).await {
+            Ok(bytes) => bytes,
+            Err(err) => match cached {
+                // A stale tile is better than none:
+                Some(tile) => {
+                    log::warn!("Error refreshing map tile: {}", err);
+                    return Ok(tile.bytes);
+                },
+                None => return Err(err),
+            },
+        };
+
+        factory.open()?.save_map_tile(&MapTile {
+            z, x, y,
+            bytes: bytes.clone(),
+            fetched: Timestamp::now(),
+        })?;
+        Ok(bytes)
+    }
+
+    async fn fetch(&self, z: u32, x: u32, y: u32) -> Result<Vec<u8>, Error> {
+        let url = self.url_template
+            .replace("{z}", &z.to_string())
+            .replace("{x}", &x.to_string())
+            .replace("{y}", &y.to_string());
+
+        let mut response = Client::default().get(&url)
+            // Tile servers often require an identifying User-Agent:
+            .header("User-Agent", concat!("FeoBlog/", env!("CARGO_PKG_VERSION")))
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| format_err!("Error fetching {}: {}", url, e))?;
+
+        if !response.status().is_success() {
+            bail!("Error fetching {}: {}", url, response.status());
+        }
+
+        let body = response.body()
+            .limit(MAX_TILE_BYTES)
+            .await
+            .map_err(|e| format_err!("Error reading {}: {}", url, e))?;
+        Ok(body.to_vec())
+    }
+}
+
+fn zoom(precision: LocationPrecision) -> u32 {
+    use LocationPrecision::*;
+    match precision {
+        LOCATION_PRECISION_EXACT => 16,
+        LOCATION_PRECISION_STREET => 15,
+        LOCATION_PRECISION_UNSPECIFIED | LOCATION_PRECISION_CITY => 10,
+        LOCATION_PRECISION_REGION => 7,
+    }
+}
+
+/// How many decimal places of latitude/longitude to show.
+pub(crate) fn decimal_places(precision: LocationPrecision) -> usize {
+    use LocationPrecision::*;
+    match precision {
+        LOCATION_PRECISION_EXACT => 5,
+        LOCATION_PRECISION_STREET => 3,
+        LOCATION_PRECISION_UNSPECIFIED | LOCATION_PRECISION_CITY => 1,
+        LOCATION_PRECISION_REGION => 0,
+    }
+}
+
+/// The (z, x, y) of the "slippy map" tile containing the location.
+/// See: https://wiki.openstreetmap.org/wiki/Slippy_map_tilenames
+pub(crate) fn tile_coordinates(location: &Location) -> (u32, u32, u32) {
+    let z = zoom(location.precision);
+    let n = f64::from(1u32 << z);
+    // Web Mercator can't show the poles:
+    let lat = location.latitude.max(-85.0511).min(85.0511).to_radians();
+    let x = (location.longitude + 180.0) / 360.0 * n;
+    let y = (1.0 - lat.tan().asinh() / std::f64::consts::PI) / 2.0 * n;
+    let clamp = |v: f64| (v.max(0.0) as u32).min((1 << z) - 1);
+    (z, clamp(x), clamp(y))
+}
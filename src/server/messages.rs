@@ -0,0 +1,230 @@
+//! Messages shown to users in error pages and API responses, in the languages
+//! we have translations for.
+//!
+//! The language is chosen per request, from its `Accept-Language` header.
+//! Failing that, we use the server's `--lang`, then English. (Unlike dates,
+//! which always use the server's locale. See: [`locale`](super::locale))
+
+use std::fmt::Display;
+
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse};
+
+use super::{PLAINTEXT, locale};
+
+/// A language we have messages in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Lang {
+    En,
+    De,
+}
+
+impl Lang {
+    /// A BCP 47 language tag. (ex: for `Content-Language`)
+    pub fn tag(self) -> &'static str {
+        match self {
+            Lang::En => "en",
+            Lang::De => "de",
+        }
+    }
+
+    /// Matches a language tag's primary subtag. (ex: "de-CH" → De)
+    fn from_tag(tag: &str) -> Option<Self> {
+        let primary = tag.split('-').next().unwrap_or_default().trim();
+        if primary.eq_ignore_ascii_case("en") {
+            Some(Lang::En)
+        } else if primary.eq_ignore_ascii_case("de") {
+            Some(Lang::De)
+        } else {
+            None
+        }
+    }
+}
+
+/// The language to respond to `req` in.
+pub(crate) fn lang(req: &HttpRequest) -> Lang {
+    let accept = req.headers().get("Accept-Language")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    // ex: "de-CH, de;q=0.9, en;q=0.8, *;q=0.5"
+    let mut ranges: Vec<(&str, f32)> = accept.split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let tag = parts.next()?.trim();
+            let quality = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .next()
+                .map(|q| q.trim().parse().unwrap_or(0.0))
+                .unwrap_or(1.0);
+            Some((tag, quality))
+        })
+        .filter(|(_, quality)| *quality > 0.0)
+        .collect();
+    // Stable, so equal qualities keep the client's order:
+    ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+    ranges.iter()
+        .find_map(|(tag, _)| Lang::from_tag(tag))
+        .or_else(|| Lang::from_tag(locale::lang()))
+        .unwrap_or(Lang::En)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Message {
+    NotFoundTitle,
+    GoneTitle,
+    MirrorsTitle,
+    MirrorsFound,
+    NoMirrors,
+    FileNotFound,
+    NoSuchItem,
+    NoSuchPost,
+    NoSuchFeed,
+    NoSuchProfile,
+    NoSuchUserOrProfile,
+    NoSuchFile,
+    NoItemForAttachment,
+
+    InvalidUserID,
+    InvalidSignature,
+    /// A followers-only listing, without a FeoBlog-Viewer header.
+    ViewerRequired,
+    /// Something only a particular user may do, without a FeoBlog-Viewer header.
+    SignInRequired,
+    /// Signed in, but as someone else.
+    NotYours,
+    /// Signed in, but not as one of the server's admins.
+    NotAdmin,
+    LengthRequired,
+    InvalidLength,
+    /// {} = the limit, in bytes.
+    ItemTooLarge,
+    ItemExists,
+    ItemDeleted,
+    UnknownUser,
+    /// An unknown user, without enough proof-of-work. {} = the difficulty.
+    ProofOfWorkRequired,
+    TooManyUploads,
+    UploadTooSlow,
+    /// {} = the item type.
+    ItemTypeRejected,
+    FutureTimestamp,
+    ItemExpired,
+    /// A Delete of a Profile or another Delete.
+    NotDeletable,
+    StorageFull,
+    Journaled,
+    ItemQuarantined,
+    /// {} = the item's size, in bytes.
+    ItemSaved,
+}
+
+impl Message {
+    pub fn text(self, lang: Lang) -> &'static str {
+        use Message::*;
+        match lang {
+            Lang::En => match self {
+                NotFoundTitle => "File Not Found",
+                GoneTitle => "Deleted",
+                MirrorsTitle => "Mirrors",
+                MirrorsFound => "Other servers have this item:",
+                NoMirrors => "No other servers that we know of have this item.",
+                FileNotFound => "File not found.",
+                NoSuchItem => "No such item",
+                NoSuchPost => "No such post.",
+                NoSuchFeed => "No such feed",
+                NoSuchProfile => "No such profile",
+                NoSuchUserOrProfile => "No such user, or profile.",
+                NoSuchFile => "No such file",
+                NoItemForAttachment => "No such item. Upload the item before its attachments.",
+                InvalidUserID => "Invalid user ID",
+                InvalidSignature => "Invalid signature",
+                ViewerRequired => "Sign in with a FeoBlog-Viewer header to see followers-only items.",
+                SignInRequired => "Sign in with a FeoBlog-Viewer header to do that.",
+                NotYours => "Only this user may do that.",
+                NotAdmin => "Only this server's admins may do that.",
+                LengthRequired => "Must include length header.",
+                InvalidLength => "Error parsing Length header.",
+                ItemTooLarge => "Item must be <= {} bytes",
+                ItemExists => "Item already exists",
+                ItemDeleted => "The Item was deleted by its author",
+                UnknownUser => "Unknown user ID",
+                ProofOfWorkRequired => "Unknown user ID. Include a FeoBlog-Proof-Of-Work header, of difficulty {}, to post anyway.",
+                TooManyUploads => "Too many uploads in progress. Try again later.",
+                UploadTooSlow => "Upload was too slow.",
+                ItemTypeRejected => "This server does not accept items of type {}",
+                FutureTimestamp => "The Item's timestamp is in the future",
+                ItemExpired => "The Item has already expired",
+                NotDeletable => "Profiles and Deletes can't be deleted",
+                StorageFull => "This server is running out of storage, and isn't accepting uploads.",
+                Journaled => "The server is down for maintenance. Your item will be saved once it's over.",
+                ItemQuarantined => "Received. Your item will be shown once the server's operator approves it.",
+                ItemSaved => "OK. Received {} bytes.",
+            },
+            Lang::De => match self {
+                NotFoundTitle => "Nicht gefunden",
+                GoneTitle => "Gelöscht",
+                MirrorsTitle => "Spiegelserver",
+                MirrorsFound => "Andere Server haben diesen Eintrag:",
+                NoMirrors => "Uns ist kein anderer Server mit diesem Eintrag bekannt.",
+                FileNotFound => "Datei nicht gefunden.",
+                NoSuchItem => "Eintrag nicht gefunden",
+                NoSuchPost => "Beitrag nicht gefunden.",
+                NoSuchFeed => "Feed nicht gefunden",
+                NoSuchProfile => "Profil nicht gefunden",
+                NoSuchUserOrProfile => "Benutzer oder Profil nicht gefunden.",
+                NoSuchFile => "Datei nicht gefunden",
+                NoItemForAttachment => "Eintrag nicht gefunden. Lade den Eintrag vor seinen Anhängen hoch.",
+                InvalidUserID => "Ungültige Benutzer-ID",
+                InvalidSignature => "Ungültige Signatur",
+                ViewerRequired => "Melde dich mit einem FeoBlog-Viewer-Header an, um Einträge nur für Follower zu sehen.",
+                SignInRequired => "Melde dich dafür mit einem FeoBlog-Viewer-Header an.",
+                NotYours => "Das darf nur dieser Nutzer.",
+                NotAdmin => "Das dürfen nur die Admins dieses Servers.",
+                LengthRequired => "Der Content-Length-Header fehlt.",
+                InvalidLength => "Der Content-Length-Header ist ungültig.",
+                ItemTooLarge => "Einträge dürfen höchstens {} Bytes groß sein",
+                ItemExists => "Der Eintrag existiert bereits",
+                ItemDeleted => "Der Eintrag wurde von seinem Autor gelöscht",
+                UnknownUser => "Unbekannte Benutzer-ID",
+                ProofOfWorkRequired => "Unbekannte Benutzer-ID. Sende einen FeoBlog-Proof-Of-Work-Header mit Schwierigkeit {}, um trotzdem zu posten.",
+                TooManyUploads => "Zu viele laufende Uploads. Bitte später erneut versuchen.",
+                UploadTooSlow => "Der Upload war zu langsam.",
+                ItemTypeRejected => "Dieser Server akzeptiert keine Einträge vom Typ {}",
+                FutureTimestamp => "Der Zeitstempel des Eintrags liegt in der Zukunft",
+                ItemExpired => "Der Eintrag ist bereits abgelaufen",
+                NotDeletable => "Profile und Löschungen können nicht gelöscht werden",
+                StorageFull => "Der Speicherplatz dieses Servers wird knapp. Uploads sind derzeit nicht möglich.",
+                Journaled => "Der Server wird gerade gewartet. Dein Eintrag wird danach gespeichert.",
+                ItemQuarantined => "Empfangen. Dein Eintrag wird angezeigt, sobald der Betreiber des Servers ihn freigibt.",
+                ItemSaved => "OK. {} Bytes empfangen.",
+            },
+        }
+    }
+
+    /// The message, with its `{}` replaced by `arg`.
+    pub fn format(self, lang: Lang, arg: impl Display) -> String {
+        self.text(lang).replacen("{}", &arg.to_string(), 1)
+    }
+}
+
+/// A plain text response with `message`, in `req`'s language.
+pub(crate) fn response(req: &HttpRequest, status: StatusCode, message: Message) -> HttpResponse {
+    let lang = lang(req);
+    text_response(lang, status, message.text(lang).to_string())
+}
+
+/// Like `response`, for messages that take an argument.
+pub(crate) fn response_with(req: &HttpRequest, status: StatusCode, message: Message, arg: impl Display) -> HttpResponse {
+    let lang = lang(req);
+    text_response(lang, status, message.format(lang, arg))
+}
+
+fn text_response(lang: Lang, status: StatusCode, text: String) -> HttpResponse {
+    HttpResponse::build(status)
+        .content_type(PLAINTEXT)
+        .header("Content-Language", lang.tag())
+        .header("Vary", "Accept-Language")
+        .body(text)
+}
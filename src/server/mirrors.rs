@@ -0,0 +1,193 @@
+//! Finds other servers that have an item, so that readers can find it even
+//! when we don't. (`/u/{userID}/i/{signature}/mirrors`, and "not found" pages.)
+//!
+//! The candidates are the servers listed in the author's profile, and our
+//! peers. (`feoblog peers add`) We ask each one for the item with a HEAD
+//! request, and remember the answers for a while, so that a popular link to a
+//! missing item doesn't have us asking the same servers over and over.
+//!
+//! Anyone can request any URL, so we're careful about what it makes us do:
+//! we only look for items by users this server knows, (its users, and the
+//! users they follow) never for ones that were deleted, and never on servers
+//! with local or private addresses. (See: remote.rs) The cache is limited in
+//! size too.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::http::header;
+use actix_web::web::{Data, Path};
+use askama::Template;
+use failure::{Error, ResultExt};
+use futures::future::join_all;
+use protobuf::Message as _;
+
+use crate::backend::{Backend, Factory, Signature, UserID};
+use crate::protos::Item;
+use super::{AppData, base_url, messages::{self, Message}, policy::Cache, remote};
+
+/// Don't re-check whether a server has an item more often than this.
+const CACHE_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Remember at most this many answers. The oldest are forgotten first.
+const MAX_CACHED: usize = 10_000;
+
+/// Don't make readers wait longer than this for remote servers.
+pub(crate) const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub(crate) struct Mirrors {
+    /// Whether each server had each item (by proto3 URL), and when we checked.
+    probed: Mutex<HashMap<String, (Instant, bool)>>,
+}
+
+impl Mirrors {
+    pub fn new() -> Self {
+        Mirrors {
+            probed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Find servers (other than `own_url`) that serve the item.
+    /// Returns the URLs of the item's HTML page on each of them.
+    pub async fn find(&self, factory: &dyn Factory, own_url: &str, user: &UserID, signature: &Signature) -> Result<Vec<String>, Error> {
+        let servers = {
+            let backend = factory.open()?;
+            if !backend.user_known(user)? || backend.user_item_deleted(user, signature)? {
+                return Ok(vec![]);
+            }
+            candidates(backend.as_ref(), user)?
+        };
+        let own_url = own_url.trim_end_matches('/');
+        let servers: Vec<String> = servers.into_iter().filter(|server| server != own_url).collect();
+
+        let probes = servers.iter().map(|server| self.probe(server, user, signature));
+        let found = join_all(probes).await;
+
+        Ok(
+            servers.iter().zip(found)
+            .filter(|(_, found)| *found)
+            .map(|(server, _)| format!("{}/u/{}/i/{}/", server, user.to_base58(), signature.to_base58()))
+            .collect()
+        )
+    }
+
+    async fn probe(&self, server: &str, user: &UserID, signature: &Signature) -> bool {
+        let url = format!("{}/u/{}/i/{}/proto3", server, user.to_base58(), signature.to_base58());
+        if let Some(found) = self.cached(&url) {
+            return found;
+        }
+
+        let peer = crate::sync::Peer::new(server);
+        let check = async {
+            remote::check_public(server).await?;
+            peer.has_item(user, signature).await
+        };
+        let found = match actix_web::rt::time::timeout(PROBE_TIMEOUT, check).await {
+            Ok(Ok(found)) => found,
+            Ok(Err(err)) => {
+                log::debug!("Error checking {} for a mirror: {}", server, err);
+                false
+            },
+            Err(_) => {
+                log::debug!("Timed out checking {} for a mirror", server);
+                false
+            },
+        };
+
+        let mut probed = self.probed.lock().expect("mirrors lock");
+        probed.retain(|_, (checked, _)| checked.elapsed() < CACHE_TTL);
+        if probed.len() >= MAX_CACHED {
+            let mut checked: Vec<Instant> = probed.values().map(|(checked, _)| *checked).collect();
+            checked.sort();
+            // Make room for a tenth more, so that we don't do this for every probe:
+            let cutoff = checked[MAX_CACHED / 10];
+            probed.retain(|_, (checked, _)| *checked > cutoff);
+        }
+        probed.insert(url, (Instant::now(), found));
+        found
+    }
+
+    fn cached(&self, url: &str) -> Option<bool> {
+        let probed = self.probed.lock().expect("mirrors lock");
+        match probed.get(url) {
+            Some((checked, found)) if checked.elapsed() < CACHE_TTL => Some(*found),
+            _ => None,
+        }
+    }
+}
+
+/// Servers that might have the user's items, without trailing slashes.
+fn candidates(backend: &dyn Backend, user: &UserID) -> Result<Vec<String>, Error> {
+    let mut servers = vec![];
+    let mut add = |url: &str| {
+        let url = url.trim_end_matches('/').to_string();
+        let is_http = url.starts_with("https://") || url.starts_with("http://");
+        if is_http && !servers.contains(&url) {
+            servers.push(url);
+        }
+    };
+
+    if let Some(row) = backend.user_profile(user)? {
+        let mut item = Item::new();
+        item.merge_from_bytes(&row.item_bytes)?;
+        for server in item.get_profile().get_servers() {
+            add(server.get_url());
+        }
+    }
+    backend.push_peers(&mut |peer| {
+        add(&peer.url);
+        Ok(true)
+    })?;
+
+    Ok(servers)
+}
+
+/// `/u/{userID}/i/{signature}/mirrors`
+///
+/// Lists other servers' pages for the item. As HTML, or as
+/// `{"mirrors": ["<url>", ...]}` with `Accept: application/json`.
+pub(crate) async fn get_mirrors(
+    data: Data<AppData>,
+    Path((user_id, signature)): Path<(UserID, Signature)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, super::Error> {
+    let mirrors = data.mirrors.find(data.backend_factory.as_ref(), &base_url(&req), &user_id, &signature).await.compat()?;
+
+    let accept = req.headers().get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    let mut response = HttpResponse::Ok();
+    Cache::MaxAge(CACHE_TTL.as_secs() as u32).apply(&mut response);
+    response.header(header::VARY, "Accept, Accept-Language");
+
+    if accept.contains("application/json") {
+        let body = serde_json::json!({ "mirrors": mirrors });
+        return Ok(response.content_type("application/json").body(body.to_string()));
+    }
+
+    let lang = messages::lang(&req);
+    let message = if mirrors.is_empty() { Message::NoMirrors } else { Message::MirrorsFound };
+    let page = MirrorsPage {
+        lang: lang.tag(),
+        title: Message::MirrorsTitle.text(lang),
+        message: message.text(lang),
+        mirrors,
+    };
+    Ok(
+        response
+        .content_type("text/html; charset=utf-8")
+        .header("Content-Language", lang.tag())
+        .body(page.render()?)
+    )
+}
+
+#[derive(Template)]
+#[template(path = "mirrors.html")]
+struct MirrorsPage {
+    lang: &'static str,
+    title: &'static str,
+    message: &'static str,
+    mirrors: Vec<String>,
+}
@@ -0,0 +1,77 @@
+//! Users' own mutes. A user who's signed in (See: viewer.rs) doesn't see
+//! items from users they've muted in the feeds they view.
+//!
+//! * `GET /u/{userID}/mutes/`
+//! * `PUT /u/{userID}/mutes/{mutedID}`
+//! * `DELETE /u/{userID}/mutes/{mutedID}`
+//!
+//! Each must be signed by `{userID}`. Mutes are private, so no one else may
+//! list them either.
+
+use actix_web::web::{Data, Path};
+use actix_web::{HttpRequest, HttpResponse};
+use failure::ResultExt;
+use serde::Serialize;
+
+use crate::backend::UserID;
+use super::{AppData, Error, PLAINTEXT, policy, viewer};
+
+#[derive(Serialize)]
+struct Mutes {
+    mutes: Vec<String>,
+}
+
+/// `GET /u/{userID}/mutes/`
+pub(crate) async fn get_mutes(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    if let Err(response) = viewer::require_user(&req, backend.as_ref(), &data.hosts, &user_id) {
+        return Ok(response);
+    }
+
+    let mut mutes = vec![];
+    backend.muted_users(&user_id, &mut |muted| {
+        mutes.push(muted.to_base58());
+        Ok(true)
+    }).compat()?;
+
+    Ok(policy::Cache::NoStore.apply(&mut HttpResponse::Ok()).json(Mutes{mutes}))
+}
+
+/// `PUT /u/{userID}/mutes/{mutedID}`
+pub(crate) async fn put_mute(
+    data: Data<AppData>,
+    Path((user_id, muted_id)): Path<(UserID, UserID)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    if let Err(response) = viewer::require_user(&req, backend.as_ref(), &data.hosts, &user_id) {
+        return Ok(response);
+    }
+    if muted_id.bytes() == user_id.bytes() {
+        return Ok(HttpResponse::BadRequest().content_type(PLAINTEXT).body("You can't mute yourself"));
+    }
+
+    backend.mute_user(&user_id, &muted_id).compat()?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// `DELETE /u/{userID}/mutes/{mutedID}`
+pub(crate) async fn delete_mute(
+    data: Data<AppData>,
+    Path((user_id, muted_id)): Path<(UserID, UserID)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    if let Err(response) = viewer::require_user(&req, backend.as_ref(), &data.hosts, &user_id) {
+        return Ok(response);
+    }
+
+    if !backend.unmute_user(&user_id, &muted_id).compat()? {
+        return Ok(HttpResponse::NotFound().content_type(PLAINTEXT).body("Not muted"));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
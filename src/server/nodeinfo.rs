@@ -0,0 +1,74 @@
+//! NodeInfo describes the server to Fediverse crawlers and statistics sites.
+//!
+//! See: <https://github.com/jhass/nodeinfo/blob/main/PROTOCOL.md>
+
+use actix_web::web::Data;
+use actix_web::{HttpRequest, HttpResponse};
+use failure::ResultExt;
+use serde_json::json;
+
+use crate::backend::Timestamp;
+use super::{AppData, Error, base_url};
+
+const SCHEMA_2_1: &str = "http://nodeinfo.diaspora.software/ns/schema/2.1";
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// Points to the NodeInfo documents we support.
+/// `/.well-known/nodeinfo`
+pub(crate) async fn well_known(req: HttpRequest) -> Result<HttpResponse, Error> {
+    let links = json!({
+        "links": [
+            {"rel": SCHEMA_2_1, "href": format!("{}/nodeinfo/2.1", base_url(&req))},
+        ],
+    });
+    Ok(
+        HttpResponse::Ok()
+        .content_type("application/json")
+        .body(links.to_string())
+    )
+}
+
+/// `/nodeinfo/2.1`
+pub(crate) async fn nodeinfo_2_1(data: Data<AppData>) -> Result<HttpResponse, Error> {
+    let now = Timestamp::now().unix_utc_ms;
+    let since = [
+        Timestamp{ unix_utc_ms: now - 180 * DAY_MS },
+        Timestamp{ unix_utc_ms: now - 30 * DAY_MS },
+    ];
+    let backend = data.backend_factory.open().compat()?;
+    let stats = backend.server_stats(&since).compat()?;
+
+    // NodeInfo only lists Fediverse protocols, so FeoBlog's own doesn't count:
+    let protocols: Vec<&str> = if data.activitypub.is_some() { vec!["activitypub"] } else { vec![] };
+
+    let info = json!({
+        "version": "2.1",
+        "software": {
+            "name": "feoblog",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "protocols": protocols,
+        "services": {
+            "inbound": [],
+            "outbound": ["atom1.0", "rss2.0"],
+        },
+        // Users are added by the server's admin. (`feoblog user add`)
+        "openRegistrations": false,
+        "usage": {
+            "users": {
+                "total": stats.users,
+                "activeHalfyear": stats.active_users[0],
+                "activeMonth": stats.active_users[1],
+            },
+            "localPosts": stats.posts,
+        },
+        "metadata": {},
+    });
+
+    Ok(
+        HttpResponse::Ok()
+        .content_type(format!("application/json; profile=\"{}#\"", SCHEMA_2_1))
+        .body(info.to_string())
+    )
+}
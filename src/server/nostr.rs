@@ -0,0 +1,311 @@
+//! Republishes users' posts to Nostr relays, so that Nostr clients can follow them.
+//!
+//! Nostr identities are secp256k1 keys, so FeoBlog's ed25519 user IDs can't
+//! sign Nostr events. Instead, `feoblog nostr enable` gives a user a secondary
+//! key, kept by the server, which signs events on their behalf. Events link
+//! back to the original, signed, item.
+//!
+//! Going the other way, `feoblog nostr import` copies a user's existing Nostr
+//! notes into posts. Since those need the user's own signature, it asks for
+//! their private key, which is used only while importing.
+//!
+//! See: <https://github.com/nostr-protocol/nips/blob/master/01.md>
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::client::Client;
+use awc::ws;
+use failure::{Error, bail, format_err};
+use futures::{SinkExt as _, StreamExt as _};
+use protobuf::Message as _;
+use secp256k1::{Message, Secp256k1, schnorrsig};
+use serde_json::{Value, json};
+use sodiumoxide::crypto::hash::sha256;
+use sodiumoxide::crypto::sign;
+
+use crate::backend::{Backend, ItemRow, NostrKey, Signature, Timestamp, UserID};
+use crate::protos::{Item, Post};
+
+const RELAY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A short note.
+const KIND_TEXT_NOTE: u32 = 1;
+
+/// Long-form content. We use it for posts with titles.
+/// See: <https://github.com/nostr-protocol/nips/blob/master/23.md>
+const KIND_LONG_FORM: u32 = 30023;
+
+/// Make a new key for a user.
+pub(crate) fn new_key(user: &UserID) -> Result<NostrKey, Error> {
+    let secp = Secp256k1::signing_only();
+    loop {
+        // Almost every 32 bytes is a valid key:
+        let secret_key = sodiumoxide::randombytes::randombytes(32);
+        if schnorrsig::KeyPair::from_seckey_slice(&secp, &secret_key).is_ok() {
+            return Ok(NostrKey {
+                user: user.clone(),
+                secret_key,
+                created: Timestamp::now(),
+            });
+        }
+    }
+}
+
+/// The hex-encoded public key that Nostr clients know the user by.
+pub(crate) fn public_key(key: &NostrKey) -> Result<String, Error> {
+    let secp = Secp256k1::signing_only();
+    let key_pair = schnorrsig::KeyPair::from_seckey_slice(&secp, &key.secret_key)?;
+    Ok(hex(&schnorrsig::PublicKey::from_keypair(&secp, &key_pair).serialize()))
+}
+
+/// Send a new post to the relays, in the background, if the user has a Nostr key.
+///
+/// This is best-effort: failures are logged, not retried.
+pub(crate) fn publish_post(
+    relays: Arc<Vec<String>>,
+    backend: &dyn Backend,
+    base_url: &str,
+    row: &ItemRow,
+    item: &Item,
+) -> Result<(), Error> {
+    if relays.is_empty() || !item.has_post() {
+        return Ok(());
+    }
+    let key = match backend.nostr_key(&row.user)? {
+        Some(key) => key,
+        None => return Ok(()),
+    };
+
+    let event = post_event(&key, base_url, row, item)?;
+    let message = json!(["EVENT", event]).to_string();
+    actix_web::rt::spawn(async move {
+        for relay in relays.iter() {
+            if let Err(err) = send(relay, &message).await {
+                log::warn!("Error publishing to Nostr relay {}: {}", relay, err);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn post_event(key: &NostrKey, base_url: &str, row: &ItemRow, item: &Item) -> Result<Value, Error> {
+    let post = item.get_post();
+    let url = format!("{}/u/{}/i/{}/", base_url, row.user.to_base58(), row.signature.to_base58());
+    let created_at = item.timestamp_ms_utc.div_euclid(1000);
+
+    // Clients that understand NIP-48 can link to the original:
+    let mut tags = vec![json!(["proxy", url, "web"])];
+    let title = post.get_title().trim();
+    let (kind, content) = if title.is_empty() {
+        (KIND_TEXT_NOTE, format!("{}\n\n{}", post.get_body(), url))
+    } else {
+        tags.push(json!(["d", row.signature.to_base58()]));
+        tags.push(json!(["title", title]));
+        tags.push(json!(["published_at", created_at.to_string()]));
+        (KIND_LONG_FORM, post.get_body().to_string())
+    };
+
+    let secp = Secp256k1::signing_only();
+    let key_pair = schnorrsig::KeyPair::from_seckey_slice(&secp, &key.secret_key)?;
+    let pubkey = hex(&schnorrsig::PublicKey::from_keypair(&secp, &key_pair).serialize());
+
+    // The ID is the hash of the event, serialized in this exact form:
+    let serialized = json!([0, pubkey, created_at, kind, tags, content]).to_string();
+    let id = sha256::hash(serialized.as_bytes());
+    let signature = secp.schnorrsig_sign_no_aux_rand(&Message::from_slice(id.as_ref())?, &key_pair);
+
+    Ok(json!({
+        "id": hex(id.as_ref()),
+        "pubkey": pubkey,
+        "created_at": created_at,
+        "kind": kind,
+        "tags": tags,
+        "content": content,
+        "sig": signature.to_string(),
+    }))
+}
+
+async fn send(relay: &str, message: &str) -> Result<(), Error> {
+    let (_, mut connection) = Client::builder()
+        .timeout(RELAY_TIMEOUT)
+        .finish()
+        .ws(relay)
+        .connect().await
+        .map_err(|err| format_err!("Error connecting: {}", err))?;
+
+    connection.send(ws::Message::Text(message.to_string().into())).await
+        .map_err(|err| format_err!("Error sending: {}", err))?;
+    // We don't wait for the relay's "OK". It'd only tell us it didn't want the event.
+    connection.send(ws::Message::Close(None)).await
+        .map_err(|err| format_err!("Error closing: {}", err))?;
+    Ok(())
+}
+
+/// What `import_notes` did.
+#[derive(Default)]
+pub(crate) struct ImportReport {
+    pub saved: usize,
+    /// Already imported, or not notes we import. (ex: replies)
+    pub skipped: usize,
+    pub errors: Vec<String>,
+}
+
+/// Copy a Nostr user's notes (kind 1) from a relay into posts by `user`.
+///
+/// `secret_key` signs the new items. Replies, and notes that we republished
+/// from FeoBlog in the first place, are skipped. Importing again only adds
+/// notes that are new since last time.
+pub(crate) async fn import_notes(
+    backend: &mut dyn Backend,
+    user: &UserID,
+    secret_key: &sign::SecretKey,
+    relay: &str,
+    nostr_pubkey: &str,
+    limit: usize,
+) -> Result<ImportReport, Error> {
+    let nostr_pubkey = nostr_pubkey.trim().to_lowercase();
+    if unhex(&nostr_pubkey).map(|key| key.len()) != Some(32) {
+        bail!("Nostr public keys should be 64 hex digits.");
+    }
+
+    let mut report = ImportReport::default();
+    for event in fetch_notes(relay, &nostr_pubkey, limit).await? {
+        match import_note(backend, user, secret_key, &nostr_pubkey, &event) {
+            Ok(true) => report.saved += 1,
+            Ok(false) => report.skipped += 1,
+            Err(err) => report.errors.push(format!("{}: {}", event["id"], err)),
+        }
+    }
+    Ok(report)
+}
+
+/// Returns false if the note was skipped.
+fn import_note(
+    backend: &mut dyn Backend,
+    user: &UserID,
+    secret_key: &sign::SecretKey,
+    nostr_pubkey: &str,
+    event: &Value,
+) -> Result<bool, Error> {
+    verify_event(event)?;
+    if event["pubkey"].as_str() != Some(nostr_pubkey) || event["kind"].as_u64() != Some(u64::from(KIND_TEXT_NOTE)) {
+        return Ok(false);
+    }
+    let tags = event["tags"].as_array().cloned().unwrap_or_default();
+    let has_tag = |name: &str| tags.iter().any(|tag| tag[0].as_str() == Some(name));
+    if has_tag("e") || has_tag("proxy") {
+        return Ok(false);
+    }
+    let content = event["content"].as_str().unwrap_or_default().trim();
+    if content.is_empty() {
+        return Ok(false);
+    }
+
+    let mut post = Post::new();
+    post.set_body(content.to_string());
+    let mut item = Item::new();
+    item.set_timestamp_ms_utc(event["created_at"].as_i64().unwrap_or_default() * 1000);
+    item.set_post(post);
+    let bytes = item.write_to_bytes()?;
+    if bytes.len() > super::MAX_ITEM_SIZE {
+        bail!("Too large to import.");
+    }
+
+    // Signatures are deterministic, so a note imported before gets the same one:
+    let signature = Signature::from_vec(sign::sign_detached(&bytes, secret_key).as_ref().to_vec())?;
+    if backend.user_item_exists(user, &signature)? || backend.user_item_deleted(user, &signature)? {
+        return Ok(false);
+    }
+    let (row, item) = crate::bundle::check_item(user.clone(), signature, &bytes, Timestamp::now())?;
+    backend.save_user_item(&row, &item)?;
+    Ok(true)
+}
+
+/// Check an event's ID and signature.
+fn verify_event(event: &Value) -> Result<(), Error> {
+    let serialized = json!([
+        0, event["pubkey"], event["created_at"], event["kind"], event["tags"], event["content"],
+    ]).to_string();
+    let id = sha256::hash(serialized.as_bytes());
+    if event["id"].as_str() != Some(hex(id.as_ref()).as_str()) {
+        bail!("Event ID doesn't match its contents.");
+    }
+
+    let secp = Secp256k1::new();
+    let pubkey = unhex(event["pubkey"].as_str().unwrap_or_default())
+        .ok_or_else(|| format_err!("Invalid public key"))?;
+    let signature = unhex(event["sig"].as_str().unwrap_or_default())
+        .ok_or_else(|| format_err!("Invalid signature"))?;
+    secp.schnorrsig_verify(
+        &schnorrsig::Signature::from_slice(&signature)?,
+        &Message::from_slice(id.as_ref())?,
+        &schnorrsig::PublicKey::from_slice(&pubkey)?,
+    ).map_err(|_| format_err!("Invalid signature"))?;
+    Ok(())
+}
+
+/// Ask a relay for a user's notes, newest first.
+async fn fetch_notes(relay: &str, nostr_pubkey: &str, limit: usize) -> Result<Vec<Value>, Error> {
+    let (_, mut connection) = Client::builder()
+        .timeout(RELAY_TIMEOUT)
+        .finish()
+        .ws(relay)
+        .max_frame_size(1024 * 1024)
+        .connect().await
+        .map_err(|err| format_err!("Error connecting: {}", err))?;
+
+    let subscription = "feoblog-import";
+    let request = json!(["REQ", subscription, {"authors": [nostr_pubkey], "kinds": [KIND_TEXT_NOTE], "limit": limit}]);
+    connection.send(ws::Message::Text(request.to_string().into())).await
+        .map_err(|err| format_err!("Error sending: {}", err))?;
+
+    let mut events = vec![];
+    loop {
+        let frame = match actix_web::rt::time::timeout(RELAY_TIMEOUT, connection.next()).await {
+            Ok(Some(frame)) => frame.map_err(|err| format_err!("Error reading from relay: {}", err))?,
+            // Closed. Keep what we got:
+            Ok(None) => break,
+            Err(_) => bail!("Timed out waiting for the relay."),
+        };
+        let text = match frame {
+            ws::Frame::Text(text) => text,
+            ws::Frame::Ping(ping) => {
+                connection.send(ws::Message::Pong(ping)).await
+                    .map_err(|err| format_err!("Error sending: {}", err))?;
+                continue;
+            },
+            ws::Frame::Close(_) => break,
+            _ => continue,
+        };
+
+        let message: Value = serde_json::from_slice(&text)?;
+        match message[0].as_str() {
+            Some("EVENT") if message[1] == subscription => events.push(message[2].clone()),
+            // "End of stored events". Anything after this would be new.
+            Some("EOSE") => break,
+            Some("CLOSED") => bail!("The relay refused: {}", message[2]),
+            _ => {},
+        }
+        if events.len() >= limit {
+            break;
+        }
+    }
+
+    // Best-effort. We have what we came for:
+    let _ = connection.send(ws::Message::Close(None)).await;
+    Ok(events)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
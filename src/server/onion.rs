@@ -0,0 +1,54 @@
+//! Helps serve the same site as a Tor onion service. (`feoblog serve --onion-address`)
+//!
+//! Tor does the work: a `HiddenServicePort` line in torrc forwards the onion
+//! address to one of our `--bind` addresses. (ex: `HiddenServicePort 80
+//! 127.0.0.1:8080`) Requests then arrive with the onion address as their Host,
+//! so the absolute URLs we build (feeds, ActivityPub, etc.) point back to it.
+//!
+//! Knowing the address, we add an `Onion-Location` header to HTML pages served
+//! over the clearnet, so that Tor Browser can offer to switch to the onion service.
+//!
+//! See: <https://community.torproject.org/onion-services/advanced/onion-location/>
+
+use actix_web::dev::ServiceResponse;
+use actix_web::http::header::{CONTENT_TYPE, HeaderName, HeaderValue};
+use failure::{Error, bail};
+
+/// Version 3 addresses are 56 base32 characters. (Version 2 is no longer supported by Tor.)
+const ADDRESS_CHARS: usize = 56;
+
+/// Check an address from --onion-address. Returns it without any scheme or trailing slash.
+pub(crate) fn parse_address(value: &str) -> Result<String, Error> {
+    let address = value.trim()
+        .trim_start_matches("http://")
+        .trim_start_matches("https://")
+        .trim_end_matches('/')
+        .to_lowercase();
+    let name = match address.strip_suffix(".onion") {
+        Some(name) => name,
+        None => bail!("Onion addresses end with .onion: {}", value),
+    };
+    let valid = name.len() == ADDRESS_CHARS
+        && name.chars().all(|c| c.is_ascii_lowercase() || ('2'..='7').contains(&c));
+    if !valid {
+        bail!("Not a (version 3) onion address: {}", value);
+    }
+    Ok(address)
+}
+
+/// Add an `Onion-Location` header to HTML responses, unless they're already going over Tor.
+pub(crate) fn add_location<B>(address: &str, res: &mut ServiceResponse<B>) {
+    let is_html = res.headers().get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with("text/html"))
+        .unwrap_or(false);
+    if !is_html || res.request().connection_info().host() == address {
+        return;
+    }
+
+    let path = res.request().uri().path_and_query().map(|p| p.as_str()).unwrap_or("/");
+    let location = format!("http://{}{}", address, path);
+    if let Ok(value) = HeaderValue::from_str(&location) {
+        res.headers_mut().insert(HeaderName::from_static("onion-location"), value);
+    }
+}
@@ -0,0 +1,141 @@
+//! Lets other FeoBlog servers (ex: mirrors) see more than anonymous visitors.
+//!
+//! Each server has its own ed25519 key, kept next to its database in
+//! `<sqlite-file>.peer-key`. (See: `feoblog peers key`) Requests from a peer
+//! carry:
+//!
+//! ```text
+//! FeoBlog-Peer: <serverKey> <timestamp_ms_utc> <signature>
+//! ```
+//!
+//! ... signed like a FeoBlog-Viewer header (see: viewer.rs), but over
+//! `feoblog-peer:<host>:<timestamp_ms_utc>`, where the host is one of ours.
+//! (`feoblog serve --host`) If the key has been trusted here (`feoblog peers
+//! trust`), the peer may fetch followers-only items, as their author could.
+//! Keys given to `feoblog serve --backup-key` may download backups of the
+//! database. (See: backup.rs)
+
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use actix_web::HttpRequest;
+use failure::{Error, ResultExt, bail, format_err};
+use sodiumoxide::crypto::sign;
+
+use crate::backend::{Backend, Signature, Timestamp, UserID};
+use super::viewer;
+
+pub(crate) const HEADER: &str = "FeoBlog-Peer";
+
+pub(crate) const PREFIX: &str = "feoblog-peer";
+
+/// The trusted peer that signed `req`, if it was signed by one.
+/// Errors if the header is invalid, or its key isn't trusted.
+pub(crate) fn peer(req: &HttpRequest, backend: &dyn Backend, hosts: &[String]) -> Result<Option<UserID>, Error> {
+    let key = match signer(req, backend, hosts)? {
+        Some(key) => key,
+        None => return Ok(None),
+    };
+    if !backend.is_trusted_peer(&key)? {
+        bail!("{} is not a trusted peer of this server", key.to_base58());
+    }
+    Ok(Some(key))
+}
+
+/// The server key that signed `req`, whether we trust it as a peer or not.
+/// (ex: for `--backup-key`, which is checked separately)
+pub(crate) fn signer(req: &HttpRequest, backend: &dyn Backend, hosts: &[String]) -> Result<Option<UserID>, Error> {
+    viewer::signer(req, backend, hosts, HEADER, PREFIX)
+}
+
+/// This server's key, for signing requests to its peers.
+#[derive(Clone)]
+pub(crate) struct ServerKey {
+    public_key: sign::PublicKey,
+    secret_key: sign::SecretKey,
+
+    /// The last timestamp we signed. Each header may only be used once, so
+    /// two requests in the same millisecond need different timestamps.
+    last_timestamp: Arc<AtomicI64>,
+}
+
+impl ServerKey {
+    /// Where the key for `sqlite_file` is kept.
+    fn path(sqlite_file: &str) -> PathBuf {
+        PathBuf::from(format!("{}.peer-key", sqlite_file))
+    }
+
+    /// Returns None if we haven't made a key yet.
+    pub fn load(sqlite_file: &str) -> Result<Option<Self>, Error> {
+        let path = Self::path(sqlite_file);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let seed = fs::read_to_string(&path)
+            .with_context(|_| format!("Error reading {}", path.display()))?;
+        let seed = bs58::decode(seed.trim()).into_vec()
+            .map_err(|_| format_err!("Invalid key in {}", path.display()))?;
+        let seed = sign::Seed::from_slice(&seed)
+            .ok_or_else(|| format_err!("Invalid key in {}", path.display()))?;
+        Ok(Some(Self::from_seed(&seed)))
+    }
+
+    pub fn from_seed(seed: &sign::Seed) -> Self {
+        let (public_key, secret_key) = sign::keypair_from_seed(seed);
+        ServerKey{ public_key, secret_key, last_timestamp: Arc::new(AtomicI64::new(0)) }
+    }
+
+    pub fn load_or_create(sqlite_file: &str) -> Result<Self, Error> {
+        if let Some(key) = Self::load(sqlite_file)? {
+            return Ok(key);
+        }
+
+        let path = Self::path(sqlite_file);
+        let seed = random_seed();
+        fs::write(&path, bs58::encode(seed.as_ref()).into_string())
+            .with_context(|_| format!("Error writing {}", path.display()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        }
+
+        Self::load(sqlite_file)?.ok_or_else(|| format_err!("Error reading {}", path.display()))
+    }
+
+    pub fn id(&self) -> UserID {
+        UserID::from_vec(self.public_key.as_ref().to_vec()).expect("ed25519 public keys are valid UserIDs")
+    }
+
+    /// Sign `bytes` as this server. (ex: a `feoblog sync verify --sign` report)
+    pub fn sign(&self, bytes: &[u8]) -> Signature {
+        let signature = sign::sign_detached(bytes, &self.secret_key);
+        Signature::from_vec(signature.as_ref().to_vec()).expect("ed25519 signatures are valid Signatures")
+    }
+
+    /// A FeoBlog-Peer header value for a request to `host`. (ex: "feo.example.com:8080")
+    pub fn header_value(&self, host: &str) -> String {
+        let now = Timestamp::now().unix_utc_ms;
+        let last = self.last_timestamp
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| Some(now.max(last + 1)))
+            .expect("always Some");
+        let timestamp = now.max(last + 1);
+        let bytes = viewer::signed_bytes(PREFIX, host, timestamp);
+        let signature = sign::sign_detached(&bytes, &self.secret_key);
+        format!(
+            "{} {} {}",
+            self.id().to_base58(),
+            timestamp,
+            bs58::encode(signature.as_ref()).into_string(),
+        )
+    }
+}
+
+
+/// A new, random seed for a ServerKey.
+pub(crate) fn random_seed() -> sign::Seed {
+    let bytes = sodiumoxide::randombytes::randombytes(sign::SEEDBYTES);
+    sign::Seed::from_slice(&bytes).expect("SEEDBYTES long")
+}
@@ -0,0 +1,127 @@
+//! CORS and caching headers, by the kind of resource a route serves.
+//!
+//! Handlers pick a `Cache` policy instead of writing header strings, so that
+//! the same kinds of resources get the same headers everywhere.
+
+use actix_web::dev::HttpResponseBuilder;
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::middleware::DefaultHeaders;
+use actix_web::HttpResponse;
+
+use super::viewer;
+
+/// 365 days, as seconds. Forever, as far as caches are concerned.
+const FOREVER: u32 = 31536000;
+
+/// How long (in seconds) browsers may cache a CORS preflight response.
+///
+/// This only lets them skip the OPTIONS request. It has nothing to do with
+/// how long they cache the response to the real request, which is up to its
+/// own Cache-Control. Firefox allows up to 24 hours, the most of any browser.
+/// See: <https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Access-Control-Max-Age>
+const PREFLIGHT_MAX_AGE: u32 = 86400;
+
+/// Lets web clients on any origin read a response.
+const CORS_HEADERS: &[(&str, &str)] = &[
+    ("Access-Control-Allow-Origin", "*"),
+    ("Access-Control-Expose-Headers", "*"),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Cache {
+    /// Identified by a signature or hash, so it can never change.
+    /// (ex: items, attachments, versioned static files)
+    Immutable,
+
+    /// Immutable, but only some viewers may see it. (ex: followers-only items)
+    PrivateImmutable,
+
+    /// Changes as items arrive, so caches must check for a new version.
+    /// (ex: item lists, server status)
+    Mutable,
+
+    /// Mutable, and depends on who's viewing it. (ex: followers-only item lists)
+    PrivateMutable,
+
+    /// Changes rarely, so being a bit out of date is OK. (ex: map tiles)
+    MaxAge(u32),
+
+    /// Made for one request, and not worth keeping. (ex: new-item templates)
+    NoStore,
+
+    /// HTML pages. They show the latest items, so they're `Mutable`.
+    /// This is the default for HTML responses. (See: `add_page_headers`)
+    Page,
+}
+
+impl Cache {
+    /// The headers for this policy.
+    pub fn headers(self) -> Vec<(&'static str, String)> {
+        let cache_control = match self {
+            Cache::Immutable => format!("public, max-age={}, immutable", FOREVER),
+            Cache::PrivateImmutable => format!("private, max-age={}, immutable", FOREVER),
+            Cache::Mutable | Cache::Page => "no-cache".to_string(),
+            Cache::PrivateMutable => "private, no-cache".to_string(),
+            Cache::MaxAge(seconds) => format!("public, max-age={}", seconds),
+            Cache::NoStore => "no-store".to_string(),
+        };
+        let mut headers = vec![("Cache-Control", cache_control)];
+        if let Cache::PrivateImmutable | Cache::PrivateMutable = self {
+            // Shared caches mustn't serve one viewer's response to another:
+            headers.push(("Vary", viewer::VARY.to_string()));
+        }
+        headers
+    }
+
+    pub fn apply(self, response: &mut HttpResponseBuilder) -> &mut HttpResponseBuilder {
+        for (name, value) in self.headers() {
+            response.header(name, value);
+        }
+        response
+    }
+}
+
+/// CORS headers for every response from a resource, including errors.
+pub(crate) fn cors() -> DefaultHeaders {
+    CORS_HEADERS.iter().fold(DefaultHeaders::new(), |headers, (name, value)| headers.header(*name, *value))
+}
+
+/// The headers for a CORS preflight response. (Along with `cors()`'s.)
+pub(crate) fn preflight_headers() -> Vec<(&'static str, String)> {
+    vec![
+        ("Access-Control-Allow-Methods", "OPTIONS, GET, PUT".to_string()),
+        // ex: FeoBlog-Viewer, for followers-only items.
+        ("Access-Control-Allow-Headers", "*".to_string()),
+        ("Access-Control-Max-Age", PREFLIGHT_MAX_AGE.to_string()),
+    ]
+}
+
+/// Before browsers will send data to a server, they make a CORS OPTIONS request to see if that's OK.
+/// This responds to that request to let the client know this request is allowed.
+pub(crate) async fn preflight() -> HttpResponse {
+    let mut response = HttpResponse::NoContent();
+    for (name, value) in preflight_headers() {
+        response.header(name, value);
+    }
+    response.body("")
+}
+
+/// Give HTML responses the `Page` policy, unless their handler chose another.
+pub(crate) fn add_page_headers(headers: &mut HeaderMap) {
+    if headers.contains_key(header::CACHE_CONTROL) {
+        return;
+    }
+    let is_html = headers.get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.starts_with("text/html"));
+    if !is_html {
+        return;
+    }
+    for (name, value) in Cache::Page.headers() {
+        let name = HeaderName::from_bytes(name.as_bytes());
+        let value = HeaderValue::from_str(&value);
+        if let (Ok(name), Ok(value)) = (name, value) {
+            headers.insert(name, value);
+        }
+    }
+}
@@ -0,0 +1,61 @@
+//! Lets users that the server doesn't know post, if they do some work first.
+//! (`feoblog serve --pow-difficulty`)
+//!
+//! The difficulty is published in `/server/info/proto3`. To PUT an item,
+//! clients find a nonce such that the SHA-256 hash of the item's signature
+//! bytes, followed by the nonce's UTF-8 bytes, starts with at least that many
+//! zero bits. Then they send it along with the item:
+//!
+//! ```text
+//! FeoBlog-Proof-Of-Work: <nonce>
+//! ```
+//!
+//! The work is tied to the signature, so each item needs its own, and the
+//! server doesn't need to remember which nonces it has seen.
+
+use actix_web::HttpRequest;
+use sodiumoxide::crypto::hash::sha256;
+
+use crate::backend::Signature;
+
+pub(crate) const HEADER: &str = "FeoBlog-Proof-Of-Work";
+
+/// More than this would take clients days.
+pub(crate) const MAX_DIFFICULTY: u32 = 40;
+
+/// Nonces are short strings. Don't hash arbitrarily large headers.
+const MAX_NONCE_BYTES: usize = 64;
+
+/// Does `req` carry valid work for `signature`?
+pub(crate) fn is_valid(req: &HttpRequest, signature: &Signature, difficulty: u32) -> bool {
+    let nonce = match req.headers().get(HEADER) {
+        Some(nonce) => nonce.as_bytes(),
+        None => return false,
+    };
+    nonce.len() <= MAX_NONCE_BYTES && zero_bits(signature, nonce) >= difficulty
+}
+
+/// Find a nonce for `signature`, like a client would.
+#[cfg(test)]
+pub(crate) fn solve(signature: &Signature, difficulty: u32) -> String {
+    (0u64..)
+        .map(|n| n.to_string())
+        .find(|nonce| zero_bits(signature, nonce.as_bytes()) >= difficulty)
+        .expect("some nonce has enough zero bits")
+}
+
+/// How many zero bits the hash of `signature` and `nonce` starts with.
+fn zero_bits(signature: &Signature, nonce: &[u8]) -> u32 {
+    let mut bytes = signature.bytes().to_vec();
+    bytes.extend_from_slice(nonce);
+    let hash = sha256::hash(&bytes);
+
+    let mut bits = 0;
+    for byte in hash.as_ref() {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
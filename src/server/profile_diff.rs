@@ -0,0 +1,119 @@
+//! Compares consecutive revisions of a user's profile, so that readers can
+//! see what changed. (Notably: who they stopped following.)
+
+use std::collections::HashSet;
+
+use crate::protos::{Follow, Profile};
+
+pub(crate) struct ProfileDiff {
+    /// Set if the display name changed.
+    pub display_name: Option<NameChange>,
+
+    /// A line diff of the "about" text. Empty if it didn't change.
+    pub about: Vec<DiffLine>,
+
+    pub follows_added: Vec<DiffFollow>,
+    pub follows_removed: Vec<DiffFollow>,
+}
+
+pub(crate) struct NameChange {
+    pub old: String,
+    pub new: String,
+}
+
+pub(crate) enum DiffLine {
+    Same(String),
+    Added(String),
+    Removed(String),
+}
+
+pub(crate) struct DiffFollow {
+    /// base58-encoded
+    pub user_id: String,
+    pub display_name: String,
+}
+
+impl ProfileDiff {
+    pub fn new(old: &Profile, new: &Profile) -> Self {
+        let display_name = if old.display_name != new.display_name {
+            Some(NameChange{
+                old: old.display_name.clone(),
+                new: new.display_name.clone(),
+            })
+        } else {
+            None
+        };
+
+        let about = if old.about != new.about {
+            diff_lines(&old.about, &new.about)
+        } else {
+            vec![]
+        };
+
+        ProfileDiff {
+            display_name,
+            about,
+            follows_added: missing_follows(new.get_follows(), old.get_follows()),
+            follows_removed: missing_follows(old.get_follows(), new.get_follows()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.display_name.is_none()
+            && self.about.is_empty()
+            && self.follows_added.is_empty()
+            && self.follows_removed.is_empty()
+    }
+}
+
+/// Follows in `these` that aren't in `others`.
+fn missing_follows(these: &[Follow], others: &[Follow]) -> Vec<DiffFollow> {
+    let others: HashSet<&[u8]> = others.iter()
+        .map(|f| f.get_user().get_bytes())
+        .collect();
+
+    these.iter()
+        .filter(|f| !others.contains(f.get_user().get_bytes()))
+        .map(|f| DiffFollow {
+            user_id: bs58::encode(f.get_user().get_bytes()).into_string(),
+            display_name: f.display_name.clone(),
+        })
+        .collect()
+}
+
+/// A minimal line diff, via the longest common subsequence of lines.
+fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // lcs[i][j] = length of the LCS of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            lines.push(DiffLine::Same(old[i].into()));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            lines.push(DiffLine::Removed(old[i].into()));
+            i += 1;
+        } else {
+            lines.push(DiffLine::Added(new[j].into()));
+            j += 1;
+        }
+    }
+    lines.extend(old[i..].iter().map(|l| DiffLine::Removed(l.to_string())));
+    lines.extend(new[j..].iter().map(|l| DiffLine::Added(l.to_string())));
+    lines
+}
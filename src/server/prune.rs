@@ -0,0 +1,34 @@
+//! Deletes old bookkeeping, so that it doesn't outgrow the items it's about.
+//! (See: `RetentionOptions`, and `feoblog db prune`)
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::backend::{Factory, Retention, Timestamp};
+use super::maintenance::Maintenance;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Runs forever. Spawn it on the server's runtime.
+pub(crate) async fn prune_loop(factory: Box<dyn Factory>, maintenance: Arc<Maintenance>, retention: Retention) {
+    let mut interval = actix_web::rt::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        if maintenance.is_active() {
+            continue;
+        }
+        let pruned = factory.open().and_then(|backend| backend.prune(&retention, Timestamp::now()));
+        match pruned {
+            Ok(report) => {
+                let total = report.item_events + report.sync_reports + report.security_reports + report.queued;
+                if total > 0 {
+                    log::info!(
+                        "Pruned {} item events, {} sync reports, {} security reports, and {} stale queue entries",
+                        report.item_events, report.sync_reports, report.security_reports, report.queued,
+                    );
+                }
+            },
+            Err(err) => log::warn!("Error pruning old bookkeeping: {}", err),
+        }
+    }
+}
@@ -0,0 +1,95 @@
+//! Checks on other servers' URLs, before we fetch them on someone's say-so.
+//!
+//! Requests can make us fetch URLs that they choose. (ex: an ActivityPub
+//! signature's keyId) Without these checks, anyone could use us to reach
+//! things that only we can: services on localhost, or on our private network.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
+
+use actix_web::http::Uri;
+use failure::{Error, bail, format_err};
+
+/// The scheme and authority of an http(s) URL. (ex: "https://example.com:8443")
+pub(crate) fn origin(url: &str) -> Option<String> {
+    let uri: Uri = url.parse().ok()?;
+    let scheme = uri.scheme_str()?.to_lowercase();
+    if scheme != "https" && scheme != "http" {
+        return None;
+    }
+    let authority = uri.authority()?.as_str().to_lowercase();
+    Some(format!("{}://{}", scheme, authority))
+}
+
+/// Errors unless `url` is http(s), and its host only resolves to public addresses.
+///
+/// Note: This resolves the host separately from the request that follows, so
+/// a DNS server that changes its answer in between could still get past it.
+pub(crate) async fn check_public(url: &str) -> Result<(), Error> {
+    let uri: Uri = url.parse().map_err(|_| format_err!("Invalid URL: {}", url))?;
+    let default_port = match uri.scheme_str() {
+        Some("https") => 443,
+        Some("http") => 80,
+        _ => bail!("Not an http(s) URL: {}", url),
+    };
+    let host = uri.host().ok_or_else(|| format_err!("No host in {}", url))?;
+    // ex: "[::1]"
+    let host = host.trim_start_matches('[').trim_end_matches(']').to_string();
+    let port = uri.port_u16().unwrap_or(default_port);
+
+    let lookup_host = host.clone();
+    let addresses: Vec<IpAddr> = actix_web::web::block(move || {
+        (lookup_host.as_str(), port).to_socket_addrs().map(|addrs| addrs.map(|addr| addr.ip()).collect())
+    }).await.map_err(|err| format_err!("Error looking up {}: {}", host, err))?;
+
+    if addresses.is_empty() {
+        bail!("{} has no addresses", host);
+    }
+    if let Some(address) = addresses.iter().find(|address| !is_public(address)) {
+        bail!("{} is a local or private address ({})", host, address);
+    }
+    Ok(())
+}
+
+/// False for loopback, private, link-local, and other addresses that aren't
+/// on the public internet.
+pub(crate) fn is_public(address: &IpAddr) -> bool {
+    match address {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4() {
+            // IPv4-mapped (::ffff:a.b.c.d) and -compatible addresses:
+            Some(v4) if ip.segments()[..5] == [0; 5] => !ip.is_loopback() && !ip.is_unspecified() && is_public_v4(&v4),
+            _ => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: &Ipv4Addr) -> bool {
+    let octets = ip.octets();
+    !(ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // "This network":
+        || octets[0] == 0
+        // Carrier-grade NAT: 100.64.0.0/10
+        || (octets[0] == 100 && (octets[1] & 0xC0) == 64)
+        // Reserved, and IETF protocol assignments:
+        || octets[0] >= 240
+        || (octets[0] == 192 && octets[1] == 0 && octets[2] == 0))
+}
+
+fn is_public_v6(ip: &Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    !(ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local: fc00::/7
+        || (first & 0xFE00) == 0xFC00
+        // Link-local: fe80::/10
+        || (first & 0xFFC0) == 0xFE80
+        // Documentation: 2001:db8::/32
+        || (first == 0x2001 && ip.segments()[1] == 0x0DB8))
+}
@@ -0,0 +1,103 @@
+//! Pushes newly-received items to peer servers.
+//!
+//! Peers are managed with `feoblog peers`. When `put_item` accepts an item, it
+//! queues a push to each peer. A background task sends queued items, and
+//! retries failures with exponential backoff.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use failure::Error;
+
+use crate::backend::{Factory, QueuedPush, Timestamp};
+use crate::sync::Peer;
+use super::maintenance::Maintenance;
+
+/// How often we check the queue.
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Give up on pushing an item after this many failures.
+const MAX_ATTEMPTS: u32 = 20;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(30);
+const MAX_BACKOFF: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Runs forever. Spawn it on the server's runtime.
+pub(crate) async fn push_loop(factory: Box<dyn Factory>, maintenance: Arc<Maintenance>) {
+    let mut interval = actix_web::rt::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        if maintenance.is_active() {
+            continue;
+        }
+        if let Err(err) = push_due(factory.as_ref()).await {
+            log::warn!("Error pushing items to peers: {}", err);
+        }
+    }
+}
+
+async fn push_due(factory: &dyn Factory) -> Result<(), Error> {
+    let mut due = vec![];
+    factory.open()?.due_pushes(Timestamp::now(), &mut |push| {
+        due.push(push);
+        Ok(due.len() < 100)
+    })?;
+
+    // Don't wait on an unreachable peer for every item queued for it:
+    let mut unreachable = HashSet::new();
+
+    for push in due {
+        if unreachable.contains(&push.peer_url) {
+            continue;
+        }
+
+        let row = factory.open()?.user_item(&push.user, &push.signature)?;
+        let bytes = match row {
+            Some(row) => row.item_bytes,
+            None => {
+                // Removed since it was queued. Don't spread it further.
+                factory.open()?.finish_push(&push)?;
+                continue;
+            }
+        };
+
+        let peer = Peer::new(&push.peer_url);
+        match peer.put_item(&push.user, &push.signature, bytes).await {
+            Ok(accepted) => {
+                let backend = factory.open()?;
+                if accepted {
+                    backend.record_item_synced(&push.user, &push.signature, Timestamp::now())?;
+                } else {
+                    log::info!("{} refused item {}", push.peer_url, push.signature.to_base58());
+                }
+                backend.finish_push(&push)?;
+            },
+            Err(err) => {
+                unreachable.insert(push.peer_url.clone());
+                failed(factory, &push, &err.to_string())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn failed(factory: &dyn Factory, push: &QueuedPush, error: &str) -> Result<(), Error> {
+    let backend = factory.open()?;
+    if push.attempts + 1 >= MAX_ATTEMPTS {
+        log::warn!("Giving up pushing {} to {}: {}", push.signature.to_base58(), push.peer_url, error);
+        return backend.finish_push(push);
+    }
+
+    let retry_at = Timestamp {
+        unix_utc_ms: Timestamp::now().unix_utc_ms + backoff(push.attempts).as_millis() as i64,
+    };
+    backend.push_failed(push, retry_at, error)
+}
+
+/// How long to wait after `attempts` previous failures.
+fn backoff(attempts: u32) -> Duration {
+    let backoff = MIN_BACKOFF.checked_mul(1 << attempts.min(16)).unwrap_or(MAX_BACKOFF);
+    backoff.min(MAX_BACKOFF)
+}
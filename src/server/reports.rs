@@ -0,0 +1,265 @@
+//! Reports of possible attempts to inject scripts or markup, for operators.
+//! (`feoblog mod reports`)
+//!
+//! Two things add reports:
+//!
+//! * Markdown in uploaded items that `md_to_html` had to defuse. (ex: a
+//!   `<script>` tag, or a `javascript:` link) We escape or drop those anyway,
+//!   so they can't run, but whoever wrote them was probably up to something.
+//! * Browsers, which `POST /csp-report` when a page does something our
+//!   Content-Security-Policy wouldn't allow. The policy is report-only, so
+//!   that a mistake in it can't break pages. Our own pages shouldn't break it.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::web::{Bytes, Data};
+use actix_web::{HttpRequest, HttpResponse};
+use failure::ResultExt;
+use serde_json::Value;
+
+use crate::backend::{Backend, ItemRow, SecurityReport, SecurityReportKind, Signature, Timestamp, UserID};
+use crate::markdown::{self, Removal};
+use crate::protos::Item;
+use super::{AppData, Error, PLAINTEXT};
+
+/// Larger reports are refused. Browsers send a few hundred bytes.
+pub(crate) const MAX_REPORT_BYTES: usize = 16 * 1024;
+
+/// Keep each report's detail to this many characters.
+const MAX_DETAIL_CHARS: usize = 500;
+
+/// How many CSP violations each IP address may report per `REPORT_WINDOW`.
+/// A page that breaks the policy reports each violation, so allow a few, but
+/// not enough to fill the database.
+pub(crate) const MAX_REPORTS_PER_WINDOW: usize = 20;
+const REPORT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Past this many addresses, forget the ones that haven't reported lately.
+const MAX_TRACKED_ADDRESSES: usize = 10_000;
+
+/// Things our pages need, and nothing else. Items are only ever rendered as
+/// escaped markdown, so anything more (ex: an inline script) is an injection.
+const POLICY: &str = "default-src 'self'; \
+    img-src * data: blob:; media-src *; connect-src *; \
+    style-src 'self' 'unsafe-inline'; script-src 'self'; \
+    object-src 'none'; base-uri 'self'; form-action 'self'; \
+    report-uri /csp-report";
+
+/// Ask browsers to report CSP violations on HTML pages.
+pub(crate) fn add_csp_header(headers: &mut HeaderMap) {
+    let name = HeaderName::from_static("content-security-policy-report-only");
+    // Some responses (ex: attachments) have their own, stricter, policy:
+    if headers.contains_key(&name) || headers.contains_key("content-security-policy") {
+        return;
+    }
+    let is_html = headers.get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.starts_with("text/html"));
+    if is_html {
+        headers.insert(name, HeaderValue::from_static(POLICY));
+    }
+}
+
+/// Record anything that rendering `item`'s markdown would remove.
+pub(crate) fn audit_item(backend: &dyn Backend, row: &ItemRow, item: &Item) {
+    let mut texts = vec![];
+    if item.has_post() {
+        texts.push(item.get_post().get_body());
+    }
+    if item.has_comment() {
+        texts.push(item.get_comment().get_text());
+    }
+    if item.has_profile() {
+        texts.push(item.get_profile().get_about());
+    }
+
+    for removal in texts.into_iter().flat_map(markdown::removals) {
+        let detail = match removal {
+            Removal::Html(html) => format!("HTML: {}", html),
+            Removal::UnsafeUrl(url) => format!("Link: {}", url),
+        };
+        log::warn!(
+            "Removed markup from item {} by {}: {}",
+            row.signature.to_base58(), row.user.to_base58(), truncate(&detail),
+        );
+        let report = SecurityReport {
+            created: Timestamp::now(),
+            kind: SecurityReportKind::Sanitizer,
+            item: Some((row.user.clone(), row.signature.clone())),
+            detail: truncate(&detail),
+        };
+        if let Err(err) = backend.save_security_report(&report) {
+            log::warn!("Error saving security report: {}", err);
+        }
+    }
+}
+
+/// Limits how many CSP violations each IP address can report.
+/// Shared by all workers.
+pub(crate) struct ReportLimiter {
+    /// When each address's window started, and how many it has reported since.
+    recent: Mutex<HashMap<IpAddr, (Instant, usize)>>,
+}
+
+impl ReportLimiter {
+    pub fn new() -> Self {
+        ReportLimiter { recent: Mutex::new(HashMap::new()) }
+    }
+
+    /// Count `reports` from `address`. False if that's more than it may send.
+    pub fn allow(&self, address: IpAddr, reports: usize) -> bool {
+        let mut recent = self.recent.lock().expect("ReportLimiter lock poisoned");
+        let now = Instant::now();
+        if recent.len() >= MAX_TRACKED_ADDRESSES && !recent.contains_key(&address) {
+            recent.retain(|_, (start, _)| now.duration_since(*start) < REPORT_WINDOW);
+            if recent.len() >= MAX_TRACKED_ADDRESSES {
+                // Too many addresses at once to remember them all. Refuse
+                // new ones until some windows end:
+                return false;
+            }
+        }
+
+        let (start, count) = recent.entry(address).or_insert((now, 0));
+        if now.duration_since(*start) >= REPORT_WINDOW {
+            *start = now;
+            *count = 0;
+        }
+        if *count + reports > MAX_REPORTS_PER_WINDOW {
+            return false;
+        }
+        *count += reports;
+        true
+    }
+}
+
+/// `/csp-report`
+///
+/// Accepts both `application/csp-report` (from `report-uri`) and
+/// `application/reports+json` (from the newer Reporting API).
+pub(crate) async fn post_csp_report(data: Data<AppData>, req: HttpRequest, body: Bytes) -> Result<HttpResponse, Error> {
+    let report: Value = match serde_json::from_slice(&body) {
+        Ok(report) => report,
+        Err(_) => return Ok(HttpResponse::BadRequest().content_type(PLAINTEXT).body("Invalid JSON")),
+    };
+    let violations: Vec<Violation> = match &report {
+        Value::Array(reports) => reports.iter()
+            .filter(|report| report["type"] == "csp-violation")
+            .map(|report| Violation::from_reporting_api(&report["body"]))
+            .filter(|violation| !violation.is_noise())
+            .collect(),
+        report => vec![Violation::from_csp_report(&report["csp-report"])]
+            .into_iter()
+            .filter(|violation| !violation.is_noise())
+            .collect(),
+    };
+    if violations.is_empty() {
+        return Ok(HttpResponse::NoContent().finish());
+    }
+
+    let allowed = match req.peer_addr() {
+        Some(addr) => data.csp_reports.allow(addr.ip(), violations.len()),
+        // ex: a Unix socket, which only local clients can reach:
+        None => true,
+    };
+    if !allowed {
+        return Ok(HttpResponse::TooManyRequests().content_type(PLAINTEXT).body("Too many reports"));
+    }
+
+    if data.maintenance.retry_after().is_some() {
+        // Not worth journaling. Browsers don't retry, so say it went fine:
+        return Ok(HttpResponse::NoContent().finish());
+    }
+    let backend = data.backend_factory.open().compat()?;
+    for violation in &violations {
+        let report = SecurityReport {
+            created: Timestamp::now(),
+            kind: SecurityReportKind::Csp,
+            item: item_from_url(&violation.document),
+            detail: truncate(&violation.to_string()),
+        };
+        backend.save_security_report(&report).compat()?;
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// The parts of a CSP violation report that we keep.
+struct Violation {
+    document: String,
+    directive: String,
+    blocked: String,
+    source: String,
+}
+
+impl Violation {
+    fn from_csp_report(report: &Value) -> Self {
+        let text = |key: &str| report[key].as_str().unwrap_or_default().to_string();
+        let directive = text("effective-directive");
+        Violation {
+            document: text("document-uri"),
+            directive: if directive.is_empty() { text("violated-directive") } else { directive },
+            blocked: text("blocked-uri"),
+            source: location(&text("source-file"), &report["line-number"]),
+        }
+    }
+
+    fn from_reporting_api(body: &Value) -> Self {
+        let text = |key: &str| body[key].as_str().unwrap_or_default().to_string();
+        Violation {
+            document: text("documentURL"),
+            directive: text("effectiveDirective"),
+            blocked: text("blockedURL"),
+            source: location(&text("sourceFile"), &body["lineNumber"]),
+        }
+    }
+
+    /// Browser extensions inject their own scripts and styles into pages.
+    /// Those violate the policy, but aren't anyone's attack on us.
+    fn is_noise(&self) -> bool {
+        const EXTENSIONS: &[&str] = &["moz-extension", "chrome-extension", "safari-extension", "safari-web-extension"];
+        [&self.blocked, &self.source].iter().any(|url| EXTENSIONS.iter().any(|scheme| url.starts_with(scheme)))
+    }
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} blocked {:?} on {}", self.directive, self.blocked, self.document)?;
+        if !self.source.is_empty() {
+            write!(f, " (from {})", self.source)?;
+        }
+        Ok(())
+    }
+}
+
+/// ex: "https://example.com/app.js:12"
+fn location(file: &str, line: &Value) -> String {
+    match line.as_u64() {
+        Some(line) if !file.is_empty() => format!("{}:{}", file, line),
+        _ => file.to_string(),
+    }
+}
+
+/// The item whose page a URL is for. (ex: ".../u/{userID}/i/{signature}/")
+fn item_from_url(url: &str) -> Option<(UserID, Signature)> {
+    let path = url.splitn(2, "/u/").nth(1)?;
+    let mut parts = path.split('/');
+    let user = parts.next()?.parse().ok()?;
+    if parts.next()? != "i" {
+        return None;
+    }
+    let signature = parts.next()?.parse().ok()?;
+    Some((user, signature))
+}
+
+fn truncate(text: &str) -> String {
+    if text.chars().count() <= MAX_DETAIL_CHARS {
+        return text.to_string();
+    }
+    let mut text: String = text.chars().take(MAX_DETAIL_CHARS).collect();
+    text.push('…');
+    text
+}
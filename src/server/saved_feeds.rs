@@ -0,0 +1,135 @@
+//! Lets users who are signed in (See: viewer.rs) manage their own saved feeds.
+//!
+//! * `GET /u/{userID}/feeds/`
+//! * `PUT /u/{userID}/feeds/{name}`
+//! * `DELETE /u/{userID}/feeds/{name}`
+//!
+//! Each must be signed by `{userID}`. The feeds themselves are public (at
+//! `/u/{userID}/feeds/{name}/`), but only their owner may change them.
+
+use actix_web::web::{Data, Json, Path};
+use actix_web::{HttpRequest, HttpResponse};
+use failure::ResultExt;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{SavedFeed, UserID};
+use super::{AppData, Error, PLAINTEXT, policy, viewer};
+
+/// Enough for anyone's reading list, without making feeds expensive to query.
+const MAX_AUTHORS: usize = 1000;
+const MAX_TAGS: usize = 100;
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Feed {
+    /// Base58 user IDs.
+    authors: Vec<String>,
+
+    /// ex: "post", "comment". Empty = the types shown by default.
+    #[serde(default)]
+    types: Vec<String>,
+
+    /// ex: "rust" or "#rust". Empty = any post.
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct NamedFeed {
+    name: String,
+
+    #[serde(flatten)]
+    feed: Feed,
+}
+
+#[derive(Serialize)]
+struct Feeds {
+    feeds: Vec<NamedFeed>,
+}
+
+/// `GET /u/{userID}/feeds/`
+pub(crate) async fn get_feeds(
+    data: Data<AppData>,
+    Path((user_id,)): Path<(UserID,)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    if let Err(response) = viewer::require_user(&req, backend.as_ref(), &data.hosts, &user_id) {
+        return Ok(response);
+    }
+
+    let mut feeds = vec![];
+    backend.saved_feeds(&user_id, &mut |feed| {
+        feeds.push(NamedFeed {
+            feed: Feed {
+                authors: feed.authors.iter().map(|author| author.to_base58()).collect(),
+                types: feed.item_types.iter().map(|t| format!("{:?}", t).to_lowercase()).collect(),
+                tags: feed.tags,
+            },
+            name: feed.name,
+        });
+        Ok(true)
+    }).compat()?;
+
+    Ok(policy::Cache::NoStore.apply(&mut HttpResponse::Ok()).json(Feeds{feeds}))
+}
+
+/// `PUT /u/{userID}/feeds/{name}`
+pub(crate) async fn put_feed(
+    data: Data<AppData>,
+    Path((user_id, name)): Path<(UserID, String)>,
+    Json(feed): Json<Feed>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    if let Err(response) = viewer::require_user(&req, backend.as_ref(), &data.hosts, &user_id) {
+        return Ok(response);
+    }
+
+    let feed = match parse_feed(user_id, name, feed) {
+        Ok(feed) => feed,
+        Err(message) => return Ok(HttpResponse::BadRequest().content_type(PLAINTEXT).body(message)),
+    };
+    backend.save_feed(&feed).compat()?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// `DELETE /u/{userID}/feeds/{name}`
+pub(crate) async fn delete_feed(
+    data: Data<AppData>,
+    Path((user_id, name)): Path<(UserID, String)>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let backend = data.backend_factory.open().compat()?;
+    if let Err(response) = viewer::require_user(&req, backend.as_ref(), &data.hosts, &user_id) {
+        return Ok(response);
+    }
+
+    if !backend.delete_saved_feed(&user_id, &name).compat()? {
+        return Ok(HttpResponse::NotFound().content_type(PLAINTEXT).body("No such feed"));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+fn parse_feed(user: UserID, name: String, feed: Feed) -> Result<SavedFeed, String> {
+    if !SavedFeed::is_valid_name(&name) {
+        return Err(format!("Invalid feed name: {:?}", name));
+    }
+    if feed.authors.is_empty() || feed.authors.len() > MAX_AUTHORS {
+        return Err(format!("A feed must have from 1 to {} authors", MAX_AUTHORS));
+    }
+    if feed.tags.len() > MAX_TAGS {
+        return Err(format!("A feed may have at most {} tags", MAX_TAGS));
+    }
+
+    let authors = feed.authors.iter()
+        .map(|author| UserID::from_base58(author).map_err(|_| format!("Invalid user ID: {}", author)))
+        .collect::<Result<_, _>>()?;
+    let item_types = feed.types.iter()
+        .map(|t| crate::parse_item_type(t).map_err(|err| err.to_string()))
+        .collect::<Result<_, _>>()?;
+    let tags = feed.tags.iter()
+        .map(|tag| crate::tags::normalize(tag).ok_or_else(|| format!("Invalid tag: {}", tag)))
+        .collect::<Result<_, _>>()?;
+
+    Ok(SavedFeed { user, name, authors, item_types, tags })
+}
@@ -0,0 +1,98 @@
+//! Helps set up a new server from its homepage.
+//!
+//! Until a server user has posted something, the homepage explains how to get
+//! started. It can also add the first server user, which would otherwise take
+//! `feoblog user add`. Since anyone could reach the page, that requires a code
+//! that's printed to the console when the server starts.
+
+use actix_web::web::{Data, Form};
+use actix_web::HttpResponse;
+use failure::{Error as FailError, ResultExt};
+use serde::Deserialize;
+use sodiumoxide::utils::memcmp;
+
+use crate::backend::{Backend, ServerUser, UserID};
+use super::{AppData, Error, PLAINTEXT};
+
+/// If the server has no users yet, make a code that lets someone add one.
+pub(crate) fn new_code(backend: &dyn Backend) -> Result<Option<String>, FailError> {
+    if has_users(backend)? {
+        return Ok(None);
+    }
+    let code = bs58::encode(sodiumoxide::randombytes::randombytes(8)).into_string();
+    Ok(Some(code))
+}
+
+fn has_users(backend: &dyn Backend) -> Result<bool, FailError> {
+    let mut has_users = false;
+    backend.server_users(&mut |_| {
+        has_users = true;
+        Ok(false)
+    })?;
+    Ok(has_users)
+}
+
+/// Where the server is in the setup process.
+pub(crate) struct Progress {
+    pub has_user: bool,
+}
+
+/// Returns None once a server user has posted something.
+pub(crate) fn progress(backend: &dyn Backend) -> Result<Option<Progress>, FailError> {
+    let mut users = vec![];
+    backend.server_users(&mut |server_user| {
+        users.push(server_user.user);
+        Ok(true)
+    })?;
+    for user in &users {
+        if backend.user_summary(user)?.item_count > 0 {
+            return Ok(None);
+        }
+    }
+    Ok(Some(Progress{ has_user: !users.is_empty() }))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct AddUserForm {
+    user_id: String,
+    code: String,
+}
+
+/// Add the server's first user.
+/// `/setup/user`
+pub(crate) async fn add_user(
+    data: Data<AppData>,
+    Form(form): Form<AddUserForm>,
+) -> Result<HttpResponse, Error> {
+    let forbidden = |message: &str| {
+        HttpResponse::Forbidden().content_type(PLAINTEXT).body(message.to_string())
+    };
+
+    let code_ok = match &data.setup_code {
+        Some(code) => memcmp(code.as_bytes(), form.code.trim().as_bytes()),
+        None => false,
+    };
+    if !code_ok {
+        return Ok(forbidden("Incorrect setup code."));
+    }
+
+    let backend = data.backend_factory.open().compat()?;
+    if has_users(backend.as_ref()).compat()? {
+        return Ok(forbidden("This server already has users. Add more with `feoblog user add`."));
+    }
+
+    let user = match UserID::from_base58(form.user_id.trim()) {
+        Ok(user) => user,
+        Err(_) => {
+            return Ok(HttpResponse::BadRequest().content_type(PLAINTEXT).body("Invalid user ID."));
+        },
+    };
+    backend.add_server_user(&ServerUser{
+        user,
+        notes: "Added during setup".into(),
+        on_homepage: true,
+    }).compat()?;
+    data.homepage.invalidate();
+
+    Ok(HttpResponse::SeeOther().header("Location", "/").finish())
+}
@@ -0,0 +1,47 @@
+//! Stops the server gracefully on SIGTERM or SIGINT (Ctrl-C).
+//!
+//! Actix handles signals itself, but stops immediately on SIGINT, cutting off
+//! uploads in progress. Instead, we stop accepting connections, and give
+//! requests in progress up to `--drain-timeout` to finish. Once the server has
+//! stopped, `serve()` checkpoints the database before exiting.
+//!
+//! A second signal stops immediately. (ex: pressing Ctrl-C twice)
+
+use actix_web::dev::Server;
+use futures::future::{FutureExt as _, select};
+
+/// Runs until a signal arrives. Spawn it on the server's runtime.
+pub(crate) async fn on_signal(servers: Vec<Server>) {
+    wait_for_signal().await;
+    println!("Shutting down. Waiting for requests in progress to finish... (Repeat to stop now.)");
+
+    actix_web::rt::spawn(async {
+        wait_for_signal().await;
+        println!("Stopping now.");
+        std::process::exit(1);
+    });
+    let stopping = servers.iter().map(|server| server.stop(true));
+    futures::future::join_all(stopping).await;
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use actix_web::rt::signal::unix::{SignalKind, signal};
+
+    let ctrl_c = actix_web::rt::signal::ctrl_c().map(|_| ());
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            let terminate = async move { terminate.recv().await; };
+            select(Box::pin(ctrl_c), Box::pin(terminate)).await;
+        },
+        Err(err) => {
+            log::warn!("Error listening for SIGTERM: {}", err);
+            ctrl_c.await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = actix_web::rt::signal::ctrl_c().await;
+}
@@ -0,0 +1,137 @@
+//! Keeps a rendered copy of the homepage's first page (HTML and proto3), so
+//! that a rush of visitors to `/` (ex: from a link aggregator) is served from
+//! memory, without touching SQLite.
+//!
+//! Writes through this server (`put_item`, setup) invalidate the snapshot.
+//! Other writes (ex: `feoblog user add`, or a journal replay) may happen in
+//! another process, so snapshots also expire after a short while. Either way,
+//! only one request renders a new copy. The rest keep getting the previous
+//! one until it's ready, so a spike during writes doesn't reach SQLite.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use actix_web::web::Bytes;
+
+use crate::protos::ItemList;
+
+/// How long a snapshot may be served for, at most, unless it's being rendered again.
+const MAX_AGE: Duration = Duration::from_secs(30);
+
+pub(crate) struct HomepageSnapshot {
+    /// Bumped by every write that might change the homepage. Snapshots rendered
+    /// from an older generation are stale.
+    generation: AtomicU64,
+
+    html: Slot<Bytes>,
+
+    /// The first page of `/homepage/proto3`, and its ETag.
+    proto: Slot<(Arc<ItemList>, String)>,
+}
+
+struct Slot<T> {
+    cached: RwLock<Option<Cached<T>>>,
+
+    /// Set while a request renders a new copy.
+    rendering: AtomicBool,
+}
+
+struct Cached<T> {
+    generation: u64,
+    rendered: Instant,
+    value: T,
+}
+
+/// What a request for the homepage should do.
+pub(crate) enum Lookup<'a, T> {
+    /// Serve this copy.
+    Serve(T),
+
+    /// Render the page from the backend, then `save()` it.
+    Render(Render<'a, T>),
+}
+
+/// Lets other requests know that this one is rendering a new copy, until it's
+/// saved or dropped. (ex: if rendering fails)
+pub(crate) struct Render<'a, T> {
+    slot: &'a Slot<T>,
+
+    /// Read before querying the backend, so that a write made while rendering
+    /// isn't lost.
+    generation: u64,
+
+    /// False if another request is rendering too, because there was no copy
+    /// to serve meanwhile.
+    claimed: bool,
+}
+
+impl HomepageSnapshot {
+    pub fn new() -> Self {
+        Self {
+            generation: AtomicU64::new(0),
+            html: Slot::new(),
+            proto: Slot::new(),
+        }
+    }
+
+    /// Call after anything that may change the homepage.
+    pub fn invalidate(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn html(&self) -> Lookup<'_, Bytes> {
+        self.lookup(&self.html)
+    }
+
+    pub fn proto(&self) -> Lookup<'_, (Arc<ItemList>, String)> {
+        self.lookup(&self.proto)
+    }
+
+    fn lookup<'a, T: Clone>(&self, slot: &'a Slot<T>) -> Lookup<'a, T> {
+        let generation = self.generation.load(Ordering::SeqCst);
+        let previous = match slot.cached.read() {
+            Ok(cached) => cached.as_ref().map(|cached| {
+                let fresh = cached.generation == generation && cached.rendered.elapsed() < MAX_AGE;
+                (fresh, cached.value.clone())
+            }),
+            Err(_) => None,
+        };
+        if let Some((true, value)) = previous {
+            return Lookup::Serve(value);
+        }
+
+        let claimed = slot.rendering
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok();
+        match previous {
+            Some((_, value)) if !claimed => Lookup::Serve(value),
+            _ => Lookup::Render(Render{ slot, generation, claimed }),
+        }
+    }
+}
+
+impl<T> Slot<T> {
+    fn new() -> Self {
+        Self {
+            cached: RwLock::new(None),
+            rendering: AtomicBool::new(false),
+        }
+    }
+}
+
+impl<'a, T> Render<'a, T> {
+    pub fn save(self, value: T) {
+        if let Ok(mut cached) = self.slot.cached.write() {
+            *cached = Some(Cached{ generation: self.generation, rendered: Instant::now(), value });
+        }
+    }
+}
+
+impl<'a, T> Drop for Render<'a, T> {
+    fn drop(&mut self) {
+        if self.claimed {
+            self.slot.rendering.store(false, Ordering::SeqCst);
+        }
+    }
+}
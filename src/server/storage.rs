@@ -0,0 +1,94 @@
+//! A cap on the disk space the server uses. (`feoblog serve --max-storage-bytes`)
+//!
+//! Attachments are stored in the database too, so we only count its files:
+//! each shard, and SQLite's write-ahead logs. Once they're within `HEADROOM`
+//! of the cap, only homepage users may upload. Past the cap, nobody may.
+//!
+//! The current usage is served at `/server/status.json`, and we log a warning
+//! when it gets close, so that operators can add space before it's full.
+
+use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use actix_web::HttpResponse;
+use actix_web::web::Data;
+use failure::Error as FailError;
+use serde_json::json;
+
+use crate::backend::{Backend, UserID};
+use crate::backend::sharded::shard_paths;
+use super::{AppData, Error};
+use super::policy::Cache;
+
+/// The fraction of the cap kept for homepage users.
+const HEADROOM: f64 = 0.05;
+
+pub(crate) struct StorageCap {
+    max_bytes: u64,
+    files: Vec<String>,
+
+    /// So that we only warn once each time we cross the headroom.
+    nearly_full: AtomicBool,
+}
+
+impl StorageCap {
+    pub fn new(sqlite_file: &str, shards: usize, max_bytes: u64) -> Self {
+        let files = shard_paths(sqlite_file, shards).into_iter()
+            .flat_map(|path| vec![format!("{}-wal", path), format!("{}-shm", path), path])
+            .collect();
+        StorageCap { max_bytes, files, nearly_full: AtomicBool::new(false) }
+    }
+
+    pub fn used_bytes(&self) -> u64 {
+        self.files.iter()
+            .filter_map(|path| fs::metadata(path).ok())
+            .map(|meta| meta.len())
+            .sum()
+    }
+
+    /// Past this, only homepage users may upload.
+    fn soft_limit(&self) -> u64 {
+        (self.max_bytes as f64 * (1.0 - HEADROOM)) as u64
+    }
+
+    /// Is there room for `user` to upload `bytes` more?
+    pub fn allows(&self, backend: &dyn Backend, user: &UserID, bytes: u64) -> Result<bool, FailError> {
+        let used = self.used_bytes() + bytes;
+        let nearly_full = used > self.soft_limit();
+        if nearly_full != self.nearly_full.swap(nearly_full, Ordering::SeqCst) && nearly_full {
+            log::warn!(
+                "Storage is nearly full: {} of {} bytes used. Only homepage users may upload.",
+                used, self.max_bytes,
+            );
+        }
+
+        if used > self.max_bytes {
+            return Ok(false);
+        }
+        if !nearly_full {
+            return Ok(true);
+        }
+        let on_homepage = backend.server_user(user)?.map(|u| u.on_homepage).unwrap_or(false);
+        Ok(on_homepage)
+    }
+}
+
+/// `/server/status.json`
+pub(crate) async fn get_status(data: Data<AppData>) -> Result<HttpResponse, Error> {
+    let storage = data.storage.as_ref().map(|cap| {
+        let used_bytes = cap.used_bytes();
+        json!({
+            "usedBytes": used_bytes,
+            "maxBytes": cap.max_bytes,
+            "nearlyFull": used_bytes > cap.soft_limit(),
+        })
+    });
+    let status = json!({
+        "storage": storage,
+    });
+    Ok(
+        Cache::Mutable.apply(&mut HttpResponse::Ok())
+        .content_type("application/json")
+        .body(status.to_string())
+    )
+}
@@ -0,0 +1,43 @@
+//! Lets the server terminate HTTPS itself. (`feoblog serve --bind-tls`)
+//!
+//! For servers that don't sit behind a reverse proxy that does it for them.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use failure::{Error, ResultExt, bail, format_err};
+use rustls::internal::pemfile;
+use rustls::{NoClientAuth, ServerConfig};
+
+/// Load a certificate chain and its private key from PEM files.
+/// (ex: Let's Encrypt's `fullchain.pem` and `privkey.pem`)
+pub(crate) fn server_config(cert_path: &Path, key_path: &Path) -> Result<ServerConfig, Error> {
+    let certs = pemfile::certs(&mut open(cert_path)?)
+        .map_err(|_| format_err!("Invalid certificate(s) in {}", cert_path.display()))?;
+    if certs.is_empty() {
+        bail!("No certificates found in {}", cert_path.display());
+    }
+
+    // Keys may be PKCS#8 ("BEGIN PRIVATE KEY") or PKCS#1 ("BEGIN RSA PRIVATE KEY"):
+    let mut keys = pemfile::pkcs8_private_keys(&mut open(key_path)?)
+        .map_err(|_| format_err!("Invalid private key in {}", key_path.display()))?;
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut open(key_path)?)
+            .map_err(|_| format_err!("Invalid private key in {}", key_path.display()))?;
+    }
+    let key = match keys.into_iter().next() {
+        Some(key) => key,
+        None => bail!("No private key found in {}", key_path.display()),
+    };
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.set_single_cert(certs, key)
+        .with_context(|_| format!("Error using the key in {} with the certificate in {}", key_path.display(), cert_path.display()))?;
+    Ok(config)
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, Error> {
+    let file = File::open(path).with_context(|_| format!("Error opening {}", path.display()))?;
+    Ok(BufReader::new(file))
+}
@@ -0,0 +1,61 @@
+//! Limits on how clients may upload data to the server.
+//!
+//! Reading a request body ties up a worker task until it's done, so we limit
+//! how many bodies we'll read at once and drop clients that send them too slowly.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Every upload gets at least this long, regardless of its size.
+const READ_GRACE: Duration = Duration::from_secs(5);
+
+/// Shared between all of the server's workers.
+pub(crate) struct UploadLimiter {
+    max_concurrent: usize,
+    min_bytes_per_second: usize,
+    active: AtomicUsize,
+}
+
+impl UploadLimiter {
+    /// max_concurrent: How many request bodies we'll read at once.
+    /// min_bytes_per_second: Drop uploads slower than this. 0 = no limit.
+    pub fn new(max_concurrent: usize, min_bytes_per_second: usize) -> Self {
+        Self {
+            max_concurrent,
+            min_bytes_per_second,
+            active: AtomicUsize::new(0),
+        }
+    }
+
+    /// Reserve a slot to read an upload, if one is available.
+    /// The slot is released when the permit is dropped.
+    pub fn try_acquire(self: &Arc<Self>) -> Option<UploadPermit> {
+        let previous = self.active.fetch_add(1, Ordering::SeqCst);
+        if previous >= self.max_concurrent {
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(UploadPermit{ limiter: self.clone() })
+    }
+
+    /// How long we'll wait to receive a body of `length` bytes, if there is a limit.
+    pub fn read_deadline(&self, length: usize) -> Option<Duration> {
+        if self.min_bytes_per_second == 0 {
+            return None;
+        }
+        let millis = (length as u64).saturating_mul(1000) / self.min_bytes_per_second as u64;
+        Some(READ_GRACE + Duration::from_millis(millis))
+    }
+}
+
+/// Proof that we've reserved a slot in the [`UploadLimiter`].
+pub(crate) struct UploadPermit {
+    limiter: Arc<UploadLimiter>,
+}
+
+impl Drop for UploadPermit {
+    fn drop(&mut self) {
+        self.limiter.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,151 @@
+//! Lets clients prove which user is viewing, so that they can see followers-only
+//! items. (See: `Visibility` in feoblog.proto)
+//!
+//! Clients send:
+//!
+//! ```text
+//! FeoBlog-Viewer: <userID> <timestamp_ms_utc> <signature>
+//! ```
+//!
+//! ... where the signature is the user's signature of the UTF-8 bytes
+//! `feoblog-viewer:<host>:<timestamp_ms_utc>`. (ex: "feoblog-viewer:blog.example.com:1700000000000")
+//! Including the host keeps other servers from replaying it here. The host
+//! must be one that the server was told is its own (`feoblog serve --host`),
+//! not whatever the request's Host header says, since a replayed request could
+//! say anything there.
+//!
+//! Each header may only be used once, so a captured request can't be replayed
+//! here either. Servers only remember the headers used with them, so that
+//! alone wouldn't stop one from being replayed to another server. The signed
+//! host does that. The timestamp must be within `MAX_SKEW_MS` of the server's
+//! clock, and we only need to remember used headers until then. Clients that
+//! make several requests should sign a new header for each.
+//!
+//! Trusted peers may instead send a `FeoBlog-Peer` header. (See: peer_auth.rs)
+
+use std::str::FromStr;
+
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse};
+use failure::{Error, bail, format_err};
+
+use crate::backend::{Backend, Signature, Timestamp, UserID};
+use crate::protos::{Item, Visibility};
+use super::{PLAINTEXT, peer_auth};
+use super::messages::{self, Message};
+
+pub(crate) const HEADER: &str = "FeoBlog-Viewer";
+
+const PREFIX: &str = "feoblog-viewer";
+
+/// For `Vary` headers on responses that depend on who's viewing.
+pub(crate) const VARY: &str = "FeoBlog-Viewer, FeoBlog-Peer";
+
+/// How far a header's timestamp may be from our clock.
+const MAX_SKEW_MS: i64 = 5 * 60 * 1000;
+
+/// Who is viewing `author`'s items, if the request says.
+/// A trusted peer may see everything the author can.
+/// Errors if a header is invalid.
+pub(crate) fn viewer(req: &HttpRequest, backend: &dyn Backend, hosts: &[String], author: &UserID) -> Result<Option<UserID>, Error> {
+    if peer_auth::peer(req, backend, hosts)?.is_some() {
+        return Ok(Some(author.clone()));
+    }
+    signer(req, backend, hosts, HEADER, PREFIX)
+}
+
+/// Who signed `req`'s FeoBlog-Viewer header, if anyone. Unlike `viewer`,
+/// peers don't count. They may read as a user, but not act as one.
+pub(crate) fn signed_in(req: &HttpRequest, backend: &dyn Backend, hosts: &[String]) -> Result<Option<UserID>, Error> {
+    signer(req, backend, hosts, HEADER, PREFIX)
+}
+
+/// Check that `user` signed `req`, for things that only they may do. (ex:
+/// change their mutes) If not, returns the response to send instead.
+pub(crate) fn require_user(req: &HttpRequest, backend: &dyn Backend, hosts: &[String], user: &UserID) -> Result<(), HttpResponse> {
+    require(req, backend, hosts, Message::NotYours, |viewer| viewer == user).map(|_| ())
+}
+
+/// Like `require_user`, but the server's admins (`feoblog serve --admin`) may too.
+pub(crate) fn require_user_or_admin(
+    req: &HttpRequest,
+    backend: &dyn Backend,
+    hosts: &[String],
+    admins: &[UserID],
+    user: &UserID,
+) -> Result<(), HttpResponse> {
+    require(req, backend, hosts, Message::NotYours, |viewer| viewer == user || admins.contains(viewer)).map(|_| ())
+}
+
+/// Check that one of the server's admins signed `req`. Returns which one.
+pub(crate) fn require_admin(req: &HttpRequest, backend: &dyn Backend, hosts: &[String], admins: &[UserID]) -> Result<UserID, HttpResponse> {
+    require(req, backend, hosts, Message::NotAdmin, |viewer| admins.contains(viewer))
+}
+
+fn require(
+    req: &HttpRequest,
+    backend: &dyn Backend,
+    hosts: &[String],
+    forbidden: Message,
+    allowed: impl Fn(&UserID) -> bool,
+) -> Result<UserID, HttpResponse> {
+    match signed_in(req, backend, hosts) {
+        Ok(Some(viewer)) if allowed(&viewer) => Ok(viewer),
+        Ok(Some(_)) => Err(messages::response(req, StatusCode::FORBIDDEN, forbidden)),
+        Ok(None) => Err(messages::response(req, StatusCode::UNAUTHORIZED, Message::SignInRequired)),
+        Err(err) => Err(HttpResponse::Unauthorized().content_type(PLAINTEXT).body(err.to_string())),
+    }
+}
+
+/// The key that signed `req`'s `header`, if it has one.
+/// `hosts` are this server's own host names, one of which must be signed.
+/// `prefix` says what the signature is for, so that one can't be used as another.
+/// Errors if the header was already used.
+pub(crate) fn signer(req: &HttpRequest, backend: &dyn Backend, hosts: &[String], header: &str, prefix: &str) -> Result<Option<UserID>, Error> {
+    let value = match req.headers().get(header) {
+        None => return Ok(None),
+        Some(value) => value.to_str().map_err(|_| format_err!("Invalid {} header", header))?,
+    };
+    if hosts.is_empty() {
+        bail!("This server doesn't accept {} headers until it's told its host name. (See: `feoblog serve --host`)", header);
+    }
+
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    let (key, timestamp, signature) = match parts.as_slice() {
+        [key, timestamp, signature] => (
+            UserID::from_str(key)?,
+            timestamp.parse::<i64>().map_err(|_| format_err!("Invalid timestamp in {} header", header))?,
+            Signature::from_str(signature)?,
+        ),
+        _ => bail!("Expected \"<key> <timestamp_ms_utc> <signature>\" in the {} header", header),
+    };
+
+    let now = Timestamp::now().unix_utc_ms;
+    if (now - timestamp).abs() > MAX_SKEW_MS {
+        // Say by how much, in case the client's clock is wrong:
+        bail!(
+            "The {} header's timestamp is {}s off from the server's time ({}). It may be at most {}s off.",
+            header, (timestamp - now) / 1000, now, MAX_SKEW_MS / 1000,
+        );
+    }
+    let signed_here = hosts.iter().any(|host| signature.is_valid(&key, &signed_bytes(prefix, host, timestamp)));
+    if !signed_here {
+        bail!("Invalid signature in the {} header. (This server is {})", header, hosts.join(", "));
+    }
+
+    let expires = Timestamp{ unix_utc_ms: timestamp + MAX_SKEW_MS };
+    if !backend.use_request_signature(&signature, expires)? {
+        bail!("The {} header was already used. Sign a new one for each request.", header);
+    }
+
+    Ok(Some(key))
+}
+
+/// What gets signed to make a header for `host`.
+pub(crate) fn signed_bytes(prefix: &str, host: &str, timestamp_ms_utc: i64) -> Vec<u8> {
+    format!("{}:{}:{}", prefix, host, timestamp_ms_utc).into_bytes()
+}
+
+pub(crate) fn is_followers_only(item: &Item) -> bool {
+    item.get_visibility() == Visibility::VISIBILITY_FOLLOWERS
+}
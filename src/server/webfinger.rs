@@ -0,0 +1,115 @@
+//! WebFinger lets other software (ex: Mastodon) find our users from addresses
+//! like `name@host`.
+//!
+//! The name is either a user's ID, or an alias set with `feoblog user alias`.
+//!
+//! See: <https://www.rfc-editor.org/rfc/rfc7033>
+
+use actix_web::web::{Data, Query};
+use actix_web::{HttpRequest, HttpResponse};
+use failure::{Error as FailError, ResultExt, bail};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::backend::{Backend, UserID};
+use super::{AppData, Error, PLAINTEXT, activitypub, base_url};
+
+const MAX_ALIAS_LENGTH: usize = 64;
+
+/// Check and normalize a user's alias.
+pub(crate) fn alias_name(name: &str) -> Result<String, FailError> {
+    let name = name.trim().to_lowercase();
+    if name.is_empty() || name.len() > MAX_ALIAS_LENGTH {
+        bail!("Names must be between 1 and {} characters.", MAX_ALIAS_LENGTH);
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_') {
+        bail!("Names may only contain letters, numbers, \".\", \"-\" and \"_\".");
+    }
+    // Don't let names shadow user IDs:
+    if UserID::from_base58(&name).is_ok() {
+        bail!("Names can't look like user IDs.");
+    }
+    Ok(name)
+}
+
+/// The name we give a user in `name@host` addresses.
+/// Their first alias, if they have one, else their user ID.
+pub(crate) fn username(backend: &dyn Backend, user: &UserID) -> Result<String, FailError> {
+    let mut name = None;
+    backend.user_aliases(&mut |alias| {
+        if alias.user.bytes() == user.bytes() {
+            name = Some(alias.name);
+        }
+        Ok(name.is_none())
+    })?;
+    Ok(name.unwrap_or_else(|| user.to_base58()))
+}
+
+/// Find a server user by alias or ID.
+fn find_user(backend: &dyn Backend, name: &str) -> Result<Option<UserID>, FailError> {
+    let user = match backend.alias_user(&name.to_lowercase())? {
+        Some(user) => user,
+        None => match UserID::from_base58(name) {
+            Ok(user) => user,
+            Err(_) => return Ok(None),
+        },
+    };
+    if backend.server_user(&user)?.is_none() {
+        return Ok(None);
+    }
+    Ok(Some(user))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct WebFingerParams {
+    resource: String,
+}
+
+/// `/.well-known/webfinger?resource=acct:{name}@{host}`
+pub(crate) async fn webfinger(
+    data: Data<AppData>,
+    Query(params): Query<WebFingerParams>,
+    req: HttpRequest,
+) -> Result<HttpResponse, Error> {
+    let base_url = base_url(&req);
+    let host = req.connection_info().host().to_string();
+    let name = params.resource.strip_prefix("acct:")
+        .and_then(|acct| acct.strip_suffix(&format!("@{}", host)));
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(not_found()),
+    };
+
+    let backend = data.backend_factory.open().compat()?;
+    let user_id = match find_user(backend.as_ref(), name).compat()? {
+        Some(user_id) => user_id,
+        None => return Ok(not_found()),
+    };
+
+    let profile_url = format!("{}/u/{}/", base_url, user_id.to_base58());
+    let mut aliases = vec![profile_url.clone()];
+    let mut links = vec![
+        json!({"rel": "http://webfinger.net/rel/profile-page", "type": "text/html", "href": profile_url}),
+        json!({"rel": "http://schemas.google.com/g/2010#updates-from", "type": "application/atom+xml", "href": format!("{}posts.atom", profile_url)}),
+    ];
+    if data.activitypub.is_some() {
+        let actor = activitypub::actor_url(&base_url, &user_id);
+        aliases.push(actor.clone());
+        links.insert(0, json!({"rel": "self", "type": activitypub::ACTIVITY_JSON, "href": actor}));
+    }
+
+    let jrd = json!({
+        "subject": format!("acct:{}@{}", username(backend.as_ref(), &user_id).compat()?, host),
+        "aliases": aliases,
+        "links": links,
+    });
+    Ok(
+        HttpResponse::Ok()
+        .content_type("application/jrd+json")
+        .body(jrd.to_string())
+    )
+}
+
+fn not_found() -> HttpResponse {
+    HttpResponse::NotFound().content_type(PLAINTEXT).body("No such user")
+}
@@ -0,0 +1,584 @@
+//! Tools for comparing (and copying) items between FeoBlog servers.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::time::Duration;
+
+use actix_web::client::{Client, ClientRequest};
+use actix_web::http::StatusCode;
+use failure::{Error, bail, format_err};
+use protobuf::Message as _;
+use sodiumoxide::crypto::hash::sha256;
+
+use crate::backend::{Backend, Factory, Signature, Timestamp, UserID, UserSummary};
+use crate::protos::{Item, ItemList};
+use crate::server::peer_auth::{self, ServerKey};
+
+/// ItemLists can be long. Allow up to 10MiB.
+const MAX_LIST_BYTES: usize = 1024 * 1024 * 10;
+
+/// Matches the limit that servers place on uploads.
+const MAX_ITEM_BYTES: usize = 1024 * 32;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Peers copy the whole database before they start sending a backup.
+const BACKUP_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// A remote FeoBlog server.
+#[derive(Clone)]
+pub(crate) struct Peer {
+    /// ex: "https://feo.example.com", without a trailing slash.
+    base_url: String,
+    client: Client,
+
+    /// Signs our GETs, if we're a trusted peer of this server.
+    key: Option<ServerKey>,
+}
+
+impl Peer {
+    pub fn new(base_url: &str) -> Self {
+        Peer {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client: Client::default(),
+            key: None,
+        }
+    }
+
+    /// Sign requests with our server key, so that we may see followers-only items.
+    pub fn authenticated(mut self, key: ServerKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    pub fn is_authenticated(&self) -> bool {
+        self.key.is_some()
+    }
+
+    fn get(&self, url: &str) -> ClientRequest {
+        let request = self.client.get(url).timeout(REQUEST_TIMEOUT);
+        match &self.key {
+            None => request,
+            Some(key) => {
+                // ex: "https://feo.example.com:8080" -> "feo.example.com:8080"
+                let host = self.base_url.splitn(2, "://").last().unwrap_or_default();
+                let host = host.split('/').next().unwrap_or_default().to_lowercase();
+                request.header(peer_auth::HEADER, key.header_value(&host))
+            }
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Fetch one page of `/u/{userID}/proto3`.
+    pub async fn user_item_list(&self, user: &UserID, before: Option<i64>) -> Result<ItemList, Error> {
+        let mut url = format!("{}/u/{}/proto3", self.base_url, user.to_base58());
+        if let Some(before) = before {
+            url = format!("{}?before={}", url, before);
+        }
+        match self.get_item_list(&url).await? {
+            Some(list) => Ok(list),
+            None => bail!("Error fetching {}: refused", url),
+        }
+    }
+
+    /// Fetch one page of `/u/{userID}/followers-only/proto3`.
+    /// Returns None if the peer doesn't trust us. (Or doesn't support them.)
+    pub async fn followers_only_item_list(&self, user: &UserID, before: Option<i64>) -> Result<Option<ItemList>, Error> {
+        let mut url = format!("{}/u/{}/followers-only/proto3", self.base_url, user.to_base58());
+        if let Some(before) = before {
+            url = format!("{}?before={}", url, before);
+        }
+        self.get_item_list(&url).await
+    }
+
+    /// Returns None if the peer refused. (ex: 404 Not Found)
+    async fn get_item_list(&self, url: &str) -> Result<Option<ItemList>, Error> {
+        let mut response = self.get(url)
+            .send()
+            .await
+            .map_err(|e| format_err!("Error fetching {}: {}", url, e))?;
+
+        let status = response.status();
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN || status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            bail!("Error fetching {}: {}", url, status);
+        }
+
+        let body = response.body()
+            .limit(MAX_LIST_BYTES)
+            .await
+            .map_err(|e| format_err!("Error reading {}: {}", url, e))?;
+
+        let mut list = ItemList::new();
+        list.merge_from_bytes(&body)?;
+        Ok(Some(list))
+    }
+
+    /// Download `/backup` to `out`, and return its size. Requires our key to
+    /// be one of the peer's `--backup-key`s.
+    pub async fn backup(&self, out: &mut impl std::io::Write) -> Result<u64, Error> {
+        use futures_util::StreamExt;
+
+        if !self.is_authenticated() {
+            bail!("Backups must be signed with a server key");
+        }
+        let url = format!("{}/backup", self.base_url);
+        let mut response = self.get(&url)
+            .timeout(BACKUP_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| format_err!("Error fetching {}: {}", url, e))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.body().limit(4096).await.unwrap_or_default();
+            bail!("Error fetching {}: {} {}", url, status, String::from_utf8_lossy(&body).trim());
+        }
+
+        let mut size = 0;
+        while let Some(chunk) = response.next().await {
+            let chunk = chunk.map_err(|e| format_err!("Error reading {}: {}", url, e))?;
+            out.write_all(&chunk)?;
+            size += chunk.len() as u64;
+        }
+        out.flush()?;
+        Ok(size)
+    }
+
+    /// Fetch the signed bytes of `/u/{userID}/i/{signature}/proto3`.
+    /// Returns None if the peer doesn't have the item.
+    pub async fn item(&self, user: &UserID, signature: &Signature) -> Result<Option<Vec<u8>>, Error> {
+        let url = format!("{}/u/{}/i/{}/proto3", self.base_url, user.to_base58(), signature.to_base58());
+        let mut response = self.get(&url)
+            .send()
+            .await
+            .map_err(|e| format_err!("Error fetching {}: {}", url, e))?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            bail!("Error fetching {}: {}", url, response.status());
+        }
+
+        let body = response.body()
+            .limit(MAX_ITEM_BYTES)
+            .await
+            .map_err(|e| format_err!("Error reading {}: {}", url, e))?;
+        Ok(Some(body.to_vec()))
+    }
+
+    /// Check whether the peer serves `/u/{userID}/i/{signature}/proto3`,
+    /// without downloading it.
+    pub async fn has_item(&self, user: &UserID, signature: &Signature) -> Result<bool, Error> {
+        let url = format!("{}/u/{}/i/{}/proto3", self.base_url, user.to_base58(), signature.to_base58());
+        let response = self.client.head(&url)
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| format_err!("Error fetching {}: {}", url, e))?;
+
+        let status = response.status();
+        if status == StatusCode::NOT_FOUND || status == StatusCode::GONE {
+            return Ok(false);
+        }
+        if !status.is_success() {
+            bail!("Error fetching {}: {}", url, status);
+        }
+        Ok(true)
+    }
+
+    /// PUT an item to `/u/{userID}/i/{signature}/proto3`.
+    /// Returns false if the peer refused the item. (ex: it doesn't host the user)
+    /// Errors are worth retrying later.
+    pub async fn put_item(&self, user: &UserID, signature: &Signature, bytes: Vec<u8>) -> Result<bool, Error> {
+        let url = format!("{}/u/{}/i/{}/proto3", self.base_url, user.to_base58(), signature.to_base58());
+        let response = self.client.put(&url)
+            .timeout(REQUEST_TIMEOUT)
+            .send_body(bytes)
+            .await
+            .map_err(|e| format_err!("Error sending {}: {}", url, e))?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(true);
+        }
+        let retryable = status.is_server_error()
+            || status == StatusCode::REQUEST_TIMEOUT
+            || status == StatusCode::TOO_MANY_REQUESTS;
+        if retryable {
+            bail!("Error sending {}: {}", url, status);
+        }
+        Ok(false)
+    }
+
+    /// Fetch `/u/{userID}/summary/proto3`.
+    /// Returns None if the peer doesn't support summaries.
+    pub async fn user_summary(&self, user: &UserID) -> Result<Option<UserSummary>, Error> {
+        let url = format!("{}/u/{}/summary/proto3", self.base_url, user.to_base58());
+        let mut response = self.client.get(&url)
+            .timeout(REQUEST_TIMEOUT)
+            .send()
+            .await
+            .map_err(|e| format_err!("Error fetching {}: {}", url, e))?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            bail!("Error fetching {}: {}", url, response.status());
+        }
+
+        let body = response.body()
+            .await
+            .map_err(|e| format_err!("Error reading {}: {}", url, e))?;
+
+        let mut summary = crate::protos::UserSummary::new();
+        summary.merge_from_bytes(&body)?;
+        Ok(Some(UserSummary{
+            item_count: summary.item_count,
+            digest: summary.digest,
+        }))
+    }
+
+    /// Fetch the signatures of all of a user's items that this peer has.
+    pub async fn user_signatures(&self, user: &UserID) -> Result<BTreeSet<Vec<u8>>, Error> {
+        let mut signatures = BTreeSet::new();
+        let mut before = None;
+        loop {
+            let list = self.user_item_list(user, before).await?;
+            for entry in list.get_items() {
+                signatures.insert(entry.get_signature().get_bytes().to_vec());
+            }
+
+            let last = match list.get_items().last() {
+                Some(last) => last.timestamp_ms_utc,
+                None => break,
+            };
+            if list.no_more_items {
+                break;
+            }
+            before = Some(last);
+        }
+
+        Ok(signatures)
+    }
+}
+
+/// Signatures of all of a user's items that are stored locally.
+pub(crate) fn local_signatures(backend: &dyn Backend, user: &UserID) -> Result<BTreeSet<Vec<u8>>, Error> {
+    let mut signatures = BTreeSet::new();
+    let all_time = Timestamp{ unix_utc_ms: i64::MAX };
+    backend.user_items(user, all_time, &mut |row| {
+        signatures.insert(row.signature.bytes().to_vec());
+        Ok(true)
+    })?;
+    Ok(signatures)
+}
+
+/// A digest that summarizes a set of signatures.
+/// This is the same digest that servers return in a `UserSummary`.
+pub(crate) fn signatures_digest(signatures: &BTreeSet<Vec<u8>>) -> String {
+    let mut digest = vec![0; sha256::DIGESTBYTES];
+    for signature in signatures {
+        let hash = sha256::hash(signature);
+        for (d, h) in digest.iter_mut().zip(hash.as_ref()) {
+            *d ^= h;
+        }
+    }
+    bs58::encode(digest).into_string()
+}
+
+/// The result of comparing a user's items on this server and a peer.
+pub(crate) struct UserVerifyReport {
+    pub user: UserID,
+    pub local_count: usize,
+    pub remote_count: usize,
+    pub local_digest: String,
+    pub remote_digest: String,
+
+    /// Items the peer has that we don't.
+    pub missing_locally: Vec<Signature>,
+
+    /// Items we have that the peer doesn't.
+    pub missing_remotely: Vec<Signature>,
+}
+
+impl UserVerifyReport {
+    pub fn new(user: UserID, local: &BTreeSet<Vec<u8>>, remote: &BTreeSet<Vec<u8>>) -> Result<Self, Error> {
+        let to_sigs = |sigs: Vec<&Vec<u8>>| -> Result<Vec<Signature>, Error> {
+            sigs.into_iter().map(|s| Signature::from_vec(s.clone())).collect()
+        };
+        Ok(UserVerifyReport {
+            user,
+            local_count: local.len(),
+            remote_count: remote.len(),
+            local_digest: signatures_digest(local),
+            remote_digest: signatures_digest(remote),
+            missing_locally: to_sigs(remote.difference(local).collect())?,
+            missing_remotely: to_sigs(local.difference(remote).collect())?,
+        })
+    }
+
+    /// A report for a user whose summary matched on both servers.
+    pub fn matching(user: UserID, summary: &UserSummary) -> Self {
+        let digest = bs58::encode(&summary.digest).into_string();
+        UserVerifyReport {
+            user,
+            local_count: summary.item_count as usize,
+            remote_count: summary.item_count as usize,
+            local_digest: digest.clone(),
+            remote_digest: digest,
+            missing_locally: vec![],
+            missing_remotely: vec![],
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.missing_locally.is_empty() && self.missing_remotely.is_empty()
+    }
+}
+
+/// The result of comparing many users' items with a peer.
+pub(crate) struct VerifyReport {
+    pub peer: String,
+    pub created: Timestamp,
+    pub users: Vec<UserVerifyReport>,
+}
+
+impl VerifyReport {
+    pub fn is_complete(&self) -> bool {
+        self.users.iter().all(|u| u.is_complete())
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Peer: {}", self.peer)?;
+        writeln!(f, "Created: {}", self.created.format_with_offset(0))?;
+        writeln!(f, "Complete: {}", self.is_complete())?;
+        for user in &self.users {
+            writeln!(f)?;
+            writeln!(f, "User: {}", user.user.to_base58())?;
+            writeln!(f, "  local:  {} items, digest {}", user.local_count, user.local_digest)?;
+            writeln!(f, "  remote: {} items, digest {}", user.remote_count, user.remote_digest)?;
+            for sig in &user.missing_locally {
+                writeln!(f, "  missing locally:  {}", sig.to_base58())?;
+            }
+            for sig in &user.missing_remotely {
+                writeln!(f, "  missing remotely: {}", sig.to_base58())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Lines appended to a signed report. (See: `sign_report`)
+const SIGNED_BY: &str = "\nSigned-By: ";
+const REPORT_SIGNATURE: &str = "Signature: ";
+
+/// Append the server key's ID, and its signature of `report`.
+pub(crate) fn sign_report(report: &str, key: &ServerKey) -> String {
+    let signature = key.sign(report.as_bytes());
+    format!(
+        "{}{}{}\n{}{}\n",
+        report,
+        SIGNED_BY,
+        key.id().to_base58(),
+        REPORT_SIGNATURE,
+        signature.to_base58(),
+    )
+}
+
+/// The key that signed a report from `sign_report`. Errors if it's unsigned,
+/// or was changed after it was signed.
+pub(crate) fn report_signer(signed: &str) -> Result<UserID, Error> {
+    let at = signed.rfind(SIGNED_BY).ok_or_else(|| format_err!("The report isn't signed"))?;
+    let (report, trailer) = signed.split_at(at);
+    let mut lines = trailer[SIGNED_BY.len()..].lines();
+
+    let key = lines.next().unwrap_or_default();
+    let key = UserID::from_base58(key.trim()).map_err(|_| format_err!("Invalid Signed-By key: {}", key))?;
+    let signature = lines.next()
+        .and_then(|line| line.strip_prefix(REPORT_SIGNATURE))
+        .ok_or_else(|| format_err!("The report has no Signature"))?;
+    let signature = Signature::from_base58(signature.trim()).map_err(|_| format_err!("Invalid Signature: {}", signature))?;
+    if lines.any(|line| !line.trim().is_empty()) {
+        bail!("Unexpected text after the report's Signature");
+    }
+
+    if !signature.is_valid(&key, report.as_bytes()) {
+        bail!("The report's signature is invalid");
+    }
+    Ok(key)
+}
+
+/// Users whose items this server keeps: the ones it hosts, and the ones they follow.
+pub(crate) fn known_users(backend: &dyn Backend) -> Result<Vec<UserID>, Error> {
+    let mut hosted = vec![];
+    backend.server_users(&mut |server_user| {
+        hosted.push(server_user.user);
+        Ok(true)
+    })?;
+
+    let mut seen = BTreeSet::new();
+    let mut users = vec![];
+    for user in hosted {
+        let follows = profile(backend, &user)?
+            .map(|item| item.get_profile().get_follows().to_vec())
+            .unwrap_or_default();
+        if seen.insert(user.bytes().to_vec()) {
+            users.push(user);
+        }
+        for follow in follows {
+            let followed = follow.get_user().get_bytes().to_vec();
+            if seen.insert(followed.clone()) {
+                users.push(UserID::from_vec(followed)?);
+            }
+        }
+    }
+    Ok(users)
+}
+
+fn profile(backend: &dyn Backend, user: &UserID) -> Result<Option<Item>, Error> {
+    let row = match backend.user_profile(user)? {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    let mut item = Item::new();
+    item.merge_from_bytes(&row.item_bytes)?;
+    Ok(Some(item))
+}
+
+/// The results of pulling one user's items from their servers.
+pub(crate) struct PullReport {
+    pub user: UserID,
+    pub saved: usize,
+
+    /// Problems with particular servers or items. These don't stop the pull.
+    pub errors: Vec<String>,
+}
+
+/// Copy a user's items that we're missing from the servers listed in their profile.
+///
+/// Pages through each server's item list, newest first. Unless `full` is set,
+/// we stop at the first page where we already had every item, since older
+/// items were likely saved by an earlier pull.
+///
+/// With a `key`, we also pull followers-only items from servers that trust us.
+pub(crate) async fn pull_user(factory: &dyn Factory, user: &UserID, full: bool, key: Option<&ServerKey>) -> Result<PullReport, Error> {
+    let mut report = PullReport {
+        user: user.clone(),
+        saved: 0,
+        errors: vec![],
+    };
+
+    let mut servers: Vec<String> = vec![];
+    if let Some(item) = profile(factory.open()?.as_ref(), user)? {
+        for server in item.get_profile().get_servers() {
+            let url = server.get_url().trim_end_matches('/').to_string();
+            if !url.is_empty() && !servers.contains(&url) {
+                servers.push(url);
+            }
+        }
+    }
+
+    // Signatures we've already seen in some server's list:
+    let mut seen = BTreeSet::new();
+
+    for server in servers {
+        let mut peer = Peer::new(&server);
+        if let Some(key) = key {
+            peer = peer.authenticated(key.clone());
+        }
+        pull_list(factory, &peer, user, full, false, &mut seen, &mut report).await?;
+        if peer.is_authenticated() {
+            pull_list(factory, &peer, user, full, true, &mut seen, &mut report).await?;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Pull the items missing from one of a peer's item lists.
+async fn pull_list(
+    factory: &dyn Factory,
+    peer: &Peer,
+    user: &UserID,
+    full: bool,
+    followers_only: bool,
+    seen: &mut BTreeSet<Vec<u8>>,
+    report: &mut PullReport,
+) -> Result<(), Error> {
+    let mut before = None;
+    loop {
+        let list = if followers_only {
+            peer.followers_only_item_list(user, before).await
+        } else {
+            peer.user_item_list(user, before).await.map(Some)
+        };
+        let list = match list {
+            Ok(Some(list)) => list,
+            // The peer doesn't trust us:
+            Ok(None) => break,
+            Err(err) => {
+                report.errors.push(err.to_string());
+                break;
+            }
+        };
+
+        let mut missing = vec![];
+        {
+            let backend = factory.open()?;
+            for entry in list.get_items() {
+                let signature = entry.get_signature().get_bytes().to_vec();
+                if !seen.insert(signature.clone()) {
+                    continue;
+                }
+                let signature = Signature::from_vec(signature)?;
+                // Deleted items would just be refused:
+                if !backend.user_item_exists(user, &signature)? && !backend.user_item_deleted(user, &signature)? {
+                    missing.push(signature);
+                }
+            }
+        }
+
+        let had_all = missing.is_empty();
+        for signature in missing {
+            match pull_item(factory, peer, user, signature).await {
+                Ok(true) => report.saved += 1,
+                Ok(false) => {},
+                Err(err) => report.errors.push(err.to_string()),
+            }
+        }
+
+        if list.no_more_items || (had_all && !full) {
+            break;
+        }
+        let next = list.get_items().last().map(|entry| entry.timestamp_ms_utc);
+        if next.is_none() || next == before {
+            // Nothing more, or a server that ignores `before`.
+            break;
+        }
+        before = next;
+    }
+
+    Ok(())
+}
+
+/// Fetch, check, and save one item. Returns false if the peer didn't have it.
+async fn pull_item(factory: &dyn Factory, peer: &Peer, user: &UserID, signature: Signature) -> Result<bool, Error> {
+    let bytes = match peer.item(user, &signature).await? {
+        Some(bytes) => bytes,
+        None => return Ok(false),
+    };
+    let (row, item) = crate::bundle::check_item(user.clone(), signature, &bytes, Timestamp::now())?;
+    let mut backend = factory.open()?;
+    backend.save_user_item(&row, &item)?;
+    Ok(true)
+}
@@ -0,0 +1,74 @@
+//! Hashtags in posts. (ex: "#rust") Saved feeds can be limited to posts with
+//! certain tags.
+//!
+//! Items have no separate field for tags, so they're found in a post's title
+//! and body. Tags are compared case-insensitively, without the "#".
+
+use std::collections::BTreeSet;
+
+use crate::protos::Item;
+
+/// Longer "tags" are more likely to be something else.
+const MAX_TAG_LENGTH: usize = 64;
+
+/// The form tags are stored and compared in. (ex: "#Rust" => "rust")
+/// None if `tag` isn't a valid tag.
+pub(crate) fn normalize(tag: &str) -> Option<String> {
+    let tag = tag.strip_prefix('#').unwrap_or(tag);
+    let valid = !tag.is_empty()
+        && tag.chars().count() <= MAX_TAG_LENGTH
+        && tag.chars().all(is_tag_char)
+        // ex: "#1" is more likely an issue number, or a ranking:
+        && !tag.chars().all(|c| c.is_ascii_digit());
+    if !valid {
+        return None;
+    }
+    Some(tag.to_lowercase())
+}
+
+/// The tags in a post's title and body.
+pub(crate) fn post_tags(item: &Item) -> BTreeSet<String> {
+    let mut tags = BTreeSet::new();
+    if !item.has_post() {
+        return tags;
+    }
+    let post = item.get_post();
+    for text in &[post.get_title(), post.get_body()] {
+        find_tags(text, &mut tags);
+    }
+    tags
+}
+
+/// Does `item` have any of `tags`? (Which must already be normalized.)
+pub(crate) fn has_any(item: &Item, tags: &[String]) -> bool {
+    let found = post_tags(item);
+    tags.iter().any(|tag| found.contains(tag))
+}
+
+fn find_tags(text: &str, tags: &mut BTreeSet<String>) {
+    let mut previous = None;
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        // Not part of a word. (ex: a URL's "page#section")
+        let starts_tag = c == '#' && !previous.map(is_tag_char).unwrap_or(false);
+        previous = Some(c);
+        if !starts_tag {
+            continue;
+        }
+
+        let mut end = start + 1;
+        while let Some(&(at, c)) = chars.peek() {
+            if !is_tag_char(c) { break; }
+            end = at + c.len_utf8();
+            previous = Some(c);
+            chars.next();
+        }
+        if let Some(tag) = normalize(&text[start..end]) {
+            tags.insert(tag);
+        }
+    }
+}
+
+fn is_tag_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
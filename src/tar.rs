@@ -0,0 +1,207 @@
+//! Reads and writes tar archives, for `feoblog export` and `feoblog import`.
+//!
+//! Just enough of the POSIX (pax) format for regular files: a ustar header for
+//! each, plus an extended header for paths longer than ustar allows. Other
+//! kinds of entries (ex: directories) are skipped when reading.
+
+use std::io::{self, Read, Write};
+
+use failure::{Error, bail, format_err};
+
+const BLOCK: usize = 512;
+
+pub(crate) struct TarWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub fn new(out: W) -> Self {
+        TarWriter { out }
+    }
+
+    /// Add a file. `mtime` is in seconds since the UNIX epoch.
+    pub fn append(&mut self, path: &str, data: &[u8], mtime: i64) -> io::Result<()> {
+        if path.len() > 100 {
+            let record = pax_record("path", path);
+            self.write_entry(b"PaxHeader", b'x', record.as_bytes(), mtime)?;
+        }
+        // Readers use the extended header's path instead, if there was one:
+        let name = &path.as_bytes()[..path.len().min(100)];
+        self.write_entry(name, b'0', data, mtime)
+    }
+
+    /// Write the end-of-archive marker.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0u8; BLOCK * 2])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn write_entry(&mut self, name: &[u8], kind: u8, data: &[u8], mtime: i64) -> io::Result<()> {
+        let mut header = [0u8; BLOCK];
+        header[..name.len()].copy_from_slice(name);
+        octal(&mut header[100..108], 0o644);
+        octal(&mut header[108..116], 0); // uid
+        octal(&mut header[116..124], 0); // gid
+        octal(&mut header[124..136], data.len() as u64);
+        octal(&mut header[136..148], mtime.max(0) as u64);
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+
+        // The checksum is calculated as if its own field were spaces:
+        header[148..156].copy_from_slice(b"        ");
+        let checksum: u64 = header.iter().map(|b| *b as u64).sum();
+        octal(&mut header[148..155], checksum);
+
+        self.out.write_all(&header)?;
+        self.out.write_all(data)?;
+        let padding = (BLOCK - data.len() % BLOCK) % BLOCK;
+        self.out.write_all(&[0u8; BLOCK][..padding])
+    }
+}
+
+/// Zero-padded octal digits, then a NUL, filling `field`.
+fn octal(field: &mut [u8], value: u64) {
+    let width = field.len() - 1;
+    let digits = format!("{:0width$o}", value, width = width);
+    field[..width].copy_from_slice(&digits.as_bytes()[digits.len() - width..]);
+    field[width] = 0;
+}
+
+/// A pax extended header record: "<length> <key>=<value>\n", where the
+/// length counts itself.
+fn pax_record(key: &str, value: &str) -> String {
+    let body = format!(" {}={}\n", key, value);
+    let mut len = body.len() + 1;
+    loop {
+        let record = format!("{}{}", len, body);
+        if record.len() == len {
+            return record;
+        }
+        len = record.len();
+    }
+}
+
+pub(crate) struct TarReader<R: Read> {
+    input: R,
+
+    /// Bytes left in the current entry's data, including padding.
+    remaining: u64,
+}
+
+impl<R: Read> TarReader<R> {
+    pub fn new(input: R) -> Self {
+        TarReader { input, remaining: 0 }
+    }
+
+    /// The path and size of the next regular file, or None at the end of the archive.
+    /// Follow with `read_file`, or the file is skipped.
+    pub fn next_file(&mut self) -> Result<Option<(String, u64)>, Error> {
+        let mut long_path = None;
+        loop {
+            self.skip_rest()?;
+
+            let mut header = [0u8; BLOCK];
+            self.input.read_exact(&mut header)?;
+            if header.iter().all(|b| *b == 0) {
+                return Ok(None);
+            }
+
+            let mut sum_header = header;
+            sum_header[148..156].copy_from_slice(b"        ");
+            let sum: u64 = sum_header.iter().map(|b| *b as u64).sum();
+            if parse_octal(&header[148..156])? != sum {
+                bail!("Invalid tar header checksum");
+            }
+
+            let size = parse_octal(&header[124..136])?;
+            self.remaining = padded(size);
+
+            match header[156] {
+                // pax extended header, for the next entry:
+                b'x' => {
+                    let data = self.read_file(size)?;
+                    long_path = pax_path(&data)?;
+                },
+                b'0' | 0 => {
+                    let path = match long_path.take() {
+                        Some(path) => path,
+                        None => {
+                            let name = field_str(&header[0..100])?;
+                            let prefix = field_str(&header[345..500])?;
+                            if &header[257..262] == b"ustar" && !prefix.is_empty() {
+                                format!("{}/{}", prefix, name)
+                            } else {
+                                name
+                            }
+                        },
+                    };
+                    return Ok(Some((path, size)));
+                },
+                // Directories, links, global headers, etc.:
+                _ => long_path = None,
+            }
+        }
+    }
+
+    /// Read the file that `next_file` found. `size` is the size it returned.
+    pub fn read_file(&mut self, size: u64) -> Result<Vec<u8>, Error> {
+        if padded(size) != self.remaining {
+            bail!("read_file() called with the wrong size");
+        }
+        let mut data = vec![0u8; size as usize];
+        self.input.read_exact(&mut data)?;
+        self.remaining -= size;
+        self.skip_rest()?;
+        Ok(data)
+    }
+
+    fn skip_rest(&mut self) -> Result<(), Error> {
+        let skipped = io::copy(&mut (&mut self.input).take(self.remaining), &mut io::sink())?;
+        if skipped != self.remaining {
+            bail!("Unexpected end of tar archive");
+        }
+        self.remaining = 0;
+        Ok(())
+    }
+}
+
+/// Sizes are rounded up to whole blocks.
+fn padded(size: u64) -> u64 {
+    (size + BLOCK as u64 - 1) / BLOCK as u64 * BLOCK as u64
+}
+
+fn parse_octal(field: &[u8]) -> Result<u64, Error> {
+    let text = std::str::from_utf8(field)?.trim_matches(|c| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).map_err(|_| format_err!("Invalid number in tar header: {:?}", text))
+}
+
+/// A NUL-terminated (or full) text field.
+fn field_str(field: &[u8]) -> Result<String, Error> {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    Ok(std::str::from_utf8(&field[..end])?.to_string())
+}
+
+/// The "path" from pax extended header records, if there is one.
+fn pax_path(data: &[u8]) -> Result<Option<String>, Error> {
+    let mut rest = data;
+    let mut path = None;
+    while !rest.is_empty() {
+        let space = rest.iter().position(|b| *b == b' ').ok_or_else(|| format_err!("Invalid pax header"))?;
+        let len: usize = std::str::from_utf8(&rest[..space])?.parse()
+            .map_err(|_| format_err!("Invalid pax header"))?;
+        if len <= space || len > rest.len() {
+            bail!("Invalid pax header");
+        }
+        let record = std::str::from_utf8(&rest[space + 1..len])?.trim_end_matches('\n');
+        if let Some(value) = record.strip_prefix("path=") {
+            path = Some(value.to_string());
+        }
+        rest = &rest[len..];
+    }
+    Ok(path)
+}
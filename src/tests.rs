@@ -92,4 +92,1066 @@ fn time_duration() {
     // FeoBlog uses an i64 # ms since epoch, so its max is:
     let max_feo = Duration::milliseconds(i64::MAX);
     assert_eq!(292471208, max_feo.whole_days() / 365);
-}
\ No newline at end of file
+}
+
+#[test]
+fn ip_blocklist_cidr() {
+    use crate::server::blocklist::{Cidr, IpBlocklist};
+    use std::net::IpAddr;
+
+    let cidr: Cidr = "192.0.2.77/24".parse().unwrap();
+    // Host bits are normalized away:
+    assert_eq!("192.0.2.0/24", cidr.to_string());
+    assert_eq!("10.1.2.3/32", "10.1.2.3".parse::<Cidr>().unwrap().to_string());
+    assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+
+    let mut list = IpBlocklist::new();
+    list.add(cidr);
+    list.add("2001:db8::/32".parse().unwrap());
+
+    let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+    assert!(list.contains(&ip("192.0.2.1")));
+    assert!(list.contains(&ip("::ffff:192.0.2.200")));
+    assert!(!list.contains(&ip("192.0.3.1")));
+    assert!(list.contains(&ip("2001:db8:1::1")));
+    assert!(!list.contains(&ip("2001:db9::1")));
+}
+
+
+#[test]
+fn bloom_filter() {
+    use crate::bloom::BloomFilter;
+
+    let signatures: Vec<Vec<u8>> = (0..100u8).map(|i| {
+        // Signatures are uniformly random, so simulate that w/ a hash:
+        let hash = sodiumoxide::crypto::hash::sha512::hash(&[i]);
+        hash.as_ref().to_vec()
+    }).collect();
+
+    let mut filter = BloomFilter::with_capacity(50);
+    for sig in &signatures[..50] {
+        filter.insert(sig);
+    }
+
+    // No false negatives:
+    assert!(signatures[..50].iter().all(|s| filter.contains(s)));
+
+    // Few false positives:
+    let false_positives = signatures[50..].iter().filter(|s| filter.contains(s)).count();
+    assert!(false_positives < 5, "{} false positives", false_positives);
+
+    // Round-trips through its parts:
+    let copy = BloomFilter::from_parts(filter.bits().to_vec(), filter.hash_count());
+    assert!(signatures[..50].iter().all(|s| copy.contains(s)));
+}
+
+#[test]
+fn follows_opml_round_trip() {
+    use crate::follows::{export_opml, import_opml};
+    use crate::protos::Profile;
+
+    let mut profile = Profile::new();
+    for (byte, name) in &[(1u8, "Alice & Bob"), (2, "")] {
+        let follow = profile.mut_follows().push_default();
+        follow.mut_user().set_bytes(vec![*byte; 32]);
+        follow.set_display_name(name.to_string());
+    }
+
+    let opml = export_opml(&profile, Some("https://feo.example.com/")).unwrap();
+    let follows = import_opml(&opml);
+    assert_eq!(2, follows.len());
+    assert_eq!(vec![1; 32], follows[0].get_user().get_bytes());
+    assert_eq!("Alice & Bob", follows[0].get_display_name());
+    assert_eq!(vec![2; 32], follows[1].get_user().get_bytes());
+
+    // Outlines that only link to a user page work too:
+    let user = bs58::encode(vec![3u8; 32]).into_string();
+    let opml = format!(r#"<outline text="Carol" htmlUrl="https://feo.example.com/u/{}/"/><outline xmlUrl="https://blog.example.com/rss"/>"#, user);
+    let follows = import_opml(&opml);
+    assert_eq!(1, follows.len());
+    assert_eq!(vec![3; 32], follows[0].get_user().get_bytes());
+}
+
+#[test]
+fn follows_json_round_trip() {
+    use crate::follows::{export_json, import_json};
+    use crate::protos::Profile;
+
+    let mut profile = Profile::new();
+    for (byte, name) in &[(1u8, "Alice \"A\" & Bob"), (2, "")] {
+        let follow = profile.mut_follows().push_default();
+        follow.mut_user().set_bytes(vec![*byte; 32]);
+        follow.set_display_name(name.to_string());
+    }
+
+    let json = export_json(&profile, Some("https://feo.example.com/")).unwrap();
+    let user = bs58::encode(vec![1u8; 32]).into_string();
+    assert!(json.contains(&format!("\"url\": \"https://feo.example.com/u/{}/\"", user)));
+
+    let follows = import_json(&json).unwrap();
+    assert_eq!(2, follows.len());
+    assert_eq!(vec![1; 32], follows[0].get_user().get_bytes());
+    assert_eq!("Alice \"A\" & Bob", follows[0].get_display_name());
+    assert_eq!(vec![2; 32], follows[1].get_user().get_bytes());
+
+    // Without links, and with only the required fields:
+    assert!(!export_json(&profile, None).unwrap().contains("url"));
+    let follows = import_json(&format!(r#"{{"follows": [{{"userID": "{}"}}]}}"#, user)).unwrap();
+    assert_eq!(1, follows.len());
+    assert!(import_json(r#"{"follows": [{"userID": "nope"}]}"#).is_err());
+}
+
+#[test]
+fn post_tags() {
+    use crate::protos::Item;
+    use crate::tags::{normalize, post_tags};
+
+    assert_eq!(Some("rust".to_string()), normalize("#Rust"));
+    assert_eq!(Some("open-source".to_string()), normalize("open-source"));
+    assert_eq!(None, normalize("#"));
+    assert_eq!(None, normalize("#1"));
+    assert_eq!(None, normalize("two words"));
+
+    let mut item = Item::new();
+    item.mut_post().title = "#Rust news".into();
+    item.mut_post().body = "Also #wasm, and #rust again.\n\nSee https://example.com/page#section, or issue #12.".into();
+    let tags: Vec<String> = post_tags(&item).into_iter().collect();
+    assert_eq!(vec!["rust", "wasm"], tags);
+
+    // Only posts have tags:
+    let mut item = Item::new();
+    item.mut_comment().text = "#rust".into();
+    assert!(post_tags(&item).is_empty());
+}
+
+#[test]
+fn saved_feed_tags() {
+    use crate::backend::{Factory, SavedFeed, UserID};
+    use crate::backend::sharded;
+    use crate::protos::ItemType;
+
+    let factory = sharded::Factory::memory();
+    let backend = factory.open().unwrap();
+    backend.setup().unwrap();
+    let user = UserID::from_vec(vec![1; 32]).unwrap();
+
+    backend.save_feed(&SavedFeed {
+        user: user.clone(),
+        name: "rust".into(),
+        authors: vec![UserID::from_vec(vec![2; 32]).unwrap()],
+        item_types: vec![ItemType::POST],
+        tags: vec!["rust".into(), "wasm".into()],
+    }).unwrap();
+
+    let feed = backend.saved_feed(&user, "rust").unwrap().expect("saved feed");
+    assert_eq!(vec!["rust", "wasm"], feed.tags);
+    assert_eq!(1, feed.authors.len());
+
+    assert!(SavedFeed::is_valid_name("my_feed-2"));
+    assert!(!SavedFeed::is_valid_name("my feed"));
+    assert!(!SavedFeed::is_valid_name("../feed"));
+}
+
+#[test]
+fn author_comment_queue() {
+    use crate::backend::{Factory, QueuedComment, Signature, Timestamp, UserID};
+    use crate::backend::sharded;
+
+    let factory = sharded::Factory::memory();
+    let backend = factory.open().unwrap();
+    backend.setup().unwrap();
+
+    let author = UserID::from_vec(vec![1; 32]).unwrap();
+    let other = UserID::from_vec(vec![2; 32]).unwrap();
+    for (user, name) in &[(&author, "Alice"), (&other, ""), (&author, " ")] {
+        backend.queue_comment(&QueuedComment {
+            id: 0,
+            user: (*user).clone(),
+            signature: Signature::from_vec(vec![3; 64]).unwrap(),
+            name: name.to_string(),
+            text: "Nice post!\nThanks.".into(),
+            created: Timestamp::now(),
+        }).unwrap();
+    }
+
+    let mut comments = vec![];
+    backend.user_queued_comments(&author, &mut |comment| {
+        comments.push(comment);
+        Ok(true)
+    }).unwrap();
+    assert_eq!(2, comments.len());
+    assert!(comments.iter().all(|c| c.user.bytes() == author.bytes()));
+    assert!(comments[0].id < comments[1].id);
+
+    assert_eq!("> Nice post!\n> Thanks.\n>\n> — Alice\n", comments[0].quote());
+    assert!(comments[1].quote().ends_with("> — Anonymous\n"));
+}
+
+#[test]
+fn poll_validation() {
+    use crate::protos::{Item, ProtoValid};
+
+    let mut item = Item::new();
+    item.timestamp_ms_utc = 1;
+    item.mut_poll().question = "Tabs or spaces?".into();
+    item.mut_poll().close_ms_utc = 2;
+    item.mut_poll().options.push("Tabs".into());
+    assert!(item.validate().is_err(), "one option isn't a poll");
+
+    item.mut_poll().options.push("Spaces".into());
+    assert!(item.validate().is_ok());
+
+    item.mut_poll().options.push(" ".into());
+    assert!(item.validate().is_err(), "options can't be empty");
+}
+
+#[test]
+fn license_validation() {
+    use crate::protos::{Item, ProtoValid};
+
+    let mut item = Item::new();
+    item.timestamp_ms_utc = 1;
+    item.mut_post().license = "CC-BY-SA-4.0".into();
+    assert!(item.validate().is_ok());
+
+    item.mut_post().license = "GPL-2.0+".into();
+    assert!(item.validate().is_ok());
+
+    item.mut_post().license = "<b>mine</b>".into();
+    assert!(item.validate().is_err());
+
+    let mut item = Item::new();
+    item.timestamp_ms_utc = 1;
+    item.mut_profile().license = "x".repeat(65);
+    assert!(item.validate().is_err());
+}
+
+#[test]
+fn comment_validation() {
+    use crate::protos::{Item, ProtoValid};
+
+    let mut item = Item::new();
+    item.timestamp_ms_utc = 1;
+    item.mut_comment().text = "Spaces, obviously.".into();
+    assert!(item.validate().is_err(), "comments must reply to something");
+
+    let reply_to = item.mut_comment().mut_reply_to();
+    reply_to.mut_user_id().bytes = vec![0; 32];
+    reply_to.mut_signature().bytes = vec![0; 64];
+    assert!(item.validate().is_ok());
+
+    item.mut_comment().text = "".into();
+    assert!(item.validate().is_err(), "comments must have text");
+}
+
+#[test]
+fn thread_bundles() {
+    use protobuf::Message as _;
+    use crate::backend::{Factory, ItemRow, Signature, Timestamp, UserID};
+    use crate::backend::sharded;
+    use crate::bundle::thread_bundle;
+    use crate::protos::Item;
+
+    let factory = sharded::Factory::memory();
+    let mut backend = factory.open().unwrap();
+    backend.setup().unwrap();
+    let author = UserID::from_vec(vec![1u8; 32]).unwrap();
+    let commenter = UserID::from_vec(vec![2u8; 32]).unwrap();
+
+    let save = |backend: &mut dyn crate::backend::Backend, user: &UserID, n: u8, item: &Item| {
+        let row = ItemRow{
+            user: user.clone(),
+            signature: Signature::from_vec(vec![n; 64]).unwrap(),
+            timestamp: Timestamp{ unix_utc_ms: item.timestamp_ms_utc },
+            received: Timestamp{ unix_utc_ms: item.timestamp_ms_utc },
+            item_bytes: item.write_to_bytes().unwrap(),
+        };
+        backend.save_user_item(&row, item).unwrap();
+    };
+    let comment = |user: &UserID, n: u8| {
+        let mut item = Item::new();
+        item.timestamp_ms_utc = 2000 + n as i64;
+        item.mut_comment().text = "Me too".into();
+        item.mut_comment().mut_reply_to().mut_user_id().set_bytes(user.bytes().to_vec());
+        item.mut_comment().mut_reply_to().mut_signature().set_bytes(vec![n; 64]);
+        item
+    };
+
+    let mut post = Item::new();
+    post.timestamp_ms_utc = 1000;
+    post.mut_post().body = "Hello".into();
+    save(backend.as_mut(), &author, 1, &post);
+    // A reply, a reply to the reply, and a comment on something else:
+    save(backend.as_mut(), &commenter, 2, &comment(&author, 1));
+    save(backend.as_mut(), &author, 3, &comment(&commenter, 2));
+    save(backend.as_mut(), &commenter, 4, &comment(&author, 9));
+
+    let root = Signature::from_vec(vec![1u8; 64]).unwrap();
+    let bundle = thread_bundle(backend.as_ref(), &author, &root).unwrap().unwrap();
+    let signatures: Vec<u8> = bundle.get_items().iter()
+        .map(|item| item.get_signature().get_bytes()[0])
+        .collect();
+    // Each item comes after the one it replies to:
+    assert_eq!(vec![1, 2, 3], signatures);
+    assert_eq!(commenter.bytes(), bundle.get_items()[1].get_user_id().get_bytes());
+
+    // Bundling a reply starts from there:
+    let reply = Signature::from_vec(vec![2u8; 64]).unwrap();
+    let bundle = thread_bundle(backend.as_ref(), &commenter, &reply).unwrap().unwrap();
+    assert_eq!(2, bundle.get_items().len());
+
+    let missing = Signature::from_vec(vec![9u8; 64]).unwrap();
+    assert!(thread_bundle(backend.as_ref(), &author, &missing).unwrap().is_none());
+}
+
+#[test]
+fn item_field_numbers() {
+    use crate::protos::{Item, Visibility};
+    use protobuf::Message;
+
+    // Fields in the item_type oneof once shared numbers with the fields after
+    // it, so setting one clobbered the other.
+    let mut item = Item::new();
+    item.timestamp_ms_utc = 1;
+    item.expires_ms_utc = 2;
+    item.visibility = Visibility::VISIBILITY_FOLLOWERS;
+    let reaction = item.mut_reaction();
+    reaction.mut_item().mut_user_id().bytes = vec![1; 32];
+    reaction.mut_item().mut_signature().bytes = vec![2; 64];
+    reaction.emoji = "👍".into();
+
+    let bytes = item.write_to_bytes().unwrap();
+    let parsed = Item::parse_from_bytes(&bytes).unwrap();
+    assert_eq!(item, parsed);
+    assert_eq!("👍", parsed.get_reaction().get_emoji());
+    assert_eq!(Visibility::VISIBILITY_FOLLOWERS, parsed.get_visibility());
+
+    let mut item = Item::new();
+    item.timestamp_ms_utc = 1;
+    item.expires_ms_utc = 2;
+    item.mut_delete().mut_signature().bytes = vec![3; 64];
+    let parsed = Item::parse_from_bytes(&item.write_to_bytes().unwrap()).unwrap();
+    assert_eq!(item, parsed);
+    assert_eq!(2, parsed.get_expires_ms_utc());
+    assert_eq!(vec![3; 64], parsed.get_delete().get_signature().get_bytes());
+}
+
+#[test]
+fn profile_diff() {
+    use crate::protos::Profile;
+    use crate::server::profile_diff::{DiffLine, ProfileDiff};
+
+    let mut old = Profile::new();
+    old.display_name = "Alice".into();
+    old.about = "Hello!\nI like cats.\n".into();
+    for byte in &[1u8, 2] {
+        old.mut_follows().push_default().mut_user().set_bytes(vec![*byte; 32]);
+    }
+
+    let mut new = old.clone();
+    assert!(ProfileDiff::new(&old, &new).is_empty());
+
+    new.about = "Hello!\nI like dogs.\n".into();
+    new.mut_follows().remove(0);
+    let diff = ProfileDiff::new(&old, &new);
+    assert!(diff.display_name.is_none());
+    assert!(diff.follows_added.is_empty());
+    assert_eq!(1, diff.follows_removed.len());
+    assert_eq!(bs58::encode(vec![1u8; 32]).into_string(), diff.follows_removed[0].user_id);
+
+    let about: Vec<String> = diff.about.iter().map(|line| match line {
+        DiffLine::Same(text) => format!(" {}", text),
+        DiffLine::Added(text) => format!("+{}", text),
+        DiffLine::Removed(text) => format!("-{}", text),
+    }).collect();
+    assert_eq!(vec![" Hello!", "-I like cats.", "+I like dogs."], about);
+}
+
+#[test]
+fn proof_of_work() {
+    use actix_web::test::TestRequest;
+    use crate::backend::Signature;
+    use crate::server::pow;
+
+    let signature = Signature::from_vec(vec![7u8; 64]).unwrap();
+    let nonce = pow::solve(&signature, 12);
+    let req = TestRequest::default().header(pow::HEADER, nonce.as_str()).to_http_request();
+    assert!(pow::is_valid(&req, &signature, 12));
+    assert!(pow::is_valid(&req, &signature, 0));
+
+    // Tied to the signature:
+    let other = Signature::from_vec(vec![8u8; 64]).unwrap();
+    assert!(!pow::is_valid(&req, &other, 12));
+
+    let req = TestRequest::default().to_http_request();
+    assert!(!pow::is_valid(&req, &signature, 1));
+}
+
+#[test]
+fn memory_backend() {
+    use crate::backend::{Factory, ServerUser, UserID};
+    use crate::backend::sharded;
+
+    let user = UserID::from_vec(vec![1u8; 32]).unwrap();
+    let factory = sharded::Factory::memory();
+    factory.open().unwrap().setup().unwrap();
+    factory.open().unwrap().add_server_user(&ServerUser{
+        user: user.clone(),
+        notes: "".into(),
+        on_homepage: true,
+    }).unwrap();
+
+    // Other connections see the same database:
+    assert!(factory.open().unwrap().user_known(&user).unwrap());
+
+    // ... but other factories don't:
+    let other = sharded::Factory::memory();
+    other.open().unwrap().setup().unwrap();
+    assert!(!other.open().unwrap().user_known(&user).unwrap());
+}
+
+#[test]
+fn verify_stored_items() {
+    use protobuf::Message as _;
+    use sodiumoxide::crypto::sign;
+    use crate::backend::{ItemRow, Signature, Timestamp, UserID};
+    use crate::bundle::verify_row;
+    use crate::protos::Item;
+
+    sodiumoxide::init().unwrap();
+    let (public_key, secret_key) = sign::gen_keypair();
+    let mut item = Item::new();
+    item.timestamp_ms_utc = 1000;
+    item.mut_post().body = "Hello".into();
+    let bytes = item.write_to_bytes().unwrap();
+    let signature = sign::sign_detached(&bytes, &secret_key);
+
+    let mut row = ItemRow{
+        user: UserID::from_vec(public_key.as_ref().to_vec()).unwrap(),
+        signature: Signature::from_vec(signature.as_ref().to_vec()).unwrap(),
+        timestamp: Timestamp{ unix_utc_ms: 1000 },
+        received: Timestamp{ unix_utc_ms: 2000 },
+        item_bytes: bytes,
+    };
+    verify_row(&row).unwrap();
+
+    row.timestamp.unix_utc_ms = 1001;
+    assert!(verify_row(&row).is_err());
+
+    row.timestamp.unix_utc_ms = 1000;
+    row.item_bytes[0] ^= 1;
+    assert!(verify_row(&row).is_err());
+}
+
+#[test]
+fn tar_archives() {
+    use crate::tar::{TarReader, TarWriter};
+
+    let mut tar = TarWriter::new(vec![]);
+    tar.append("short.txt", b"Hello", 1_600_000_000).unwrap();
+    let long_path = format!("u/{}/files/photo.jpg", "x".repeat(120));
+    tar.append(&long_path, &[1u8; 513], 1_600_000_000).unwrap();
+    let bytes = tar.finish().unwrap();
+
+    // Headers and contents are padded to blocks, and there are two empty blocks at the end:
+    assert_eq!(512 * (2 + 2 + 3 + 2), bytes.len());
+    assert_eq!(b"short.txt\0", &bytes[..10]);
+    assert_eq!(b"ustar\x0000", &bytes[257..265]);
+    assert_eq!(b"00000000005\0", &bytes[124..136]);
+
+    // The checksum counts its own field as spaces:
+    let mut header = bytes[..512].to_vec();
+    let checksum = std::str::from_utf8(&header[148..154]).unwrap().to_string();
+    header[148..156].copy_from_slice(b"        ");
+    let expected: u64 = header.iter().map(|b| *b as u64).sum();
+    assert_eq!(expected, u64::from_str_radix(&checksum, 8).unwrap());
+
+    // Long paths get an extended header first:
+    assert_eq!(b'x', bytes[1024 + 156]);
+    let record = format!(" path={}\n", long_path);
+    let record = format!("{}{}", record.len() + 3, record);
+    assert_eq!(record.as_bytes(), &bytes[1536..1536 + record.len()]);
+    assert_eq!(b'0', bytes[2048 + 156]);
+
+    let mut reader = TarReader::new(&bytes[..]);
+    assert_eq!(Some(("short.txt".to_string(), 5)), reader.next_file().unwrap());
+    assert_eq!(b"Hello".to_vec(), reader.read_file(5).unwrap());
+    // Unread contents are skipped:
+    assert_eq!(Some((long_path, 513)), reader.next_file().unwrap());
+    assert_eq!(None, reader.next_file().unwrap());
+}
+
+#[test]
+fn epub_files() {
+    use crate::backend::Timestamp;
+
+    assert_eq!(0xCBF43926, crate::zip::crc32(b"123456789"));
+
+    let book = crate::epub::Book {
+        id: "https://example.com/u/abc/i/def/".into(),
+        title: "Fish & Chips".into(),
+        author: "Cody".into(),
+        lang: "en".into(),
+        published: Timestamp{ unix_utc_ms: 1_600_000_000_000 },
+        date: "2020-09-13".into(),
+        content_html: "<p>Hello</p>".into(),
+        license: "CC-BY-4.0".into(),
+        images: vec![("my photo.png".into(), "image/png".into(), vec![1, 2, 3])],
+    };
+    let bytes = book.to_epub().unwrap();
+
+    // Readers identify EPUBs by an uncompressed "mimetype" file at the start:
+    assert_eq!(b"PK\x03\x04", &bytes[..4]);
+    assert_eq!(0, u16::from_le_bytes([bytes[8], bytes[9]]));
+    assert_eq!(b"mimetypeapplication/epub+zip", &bytes[30..58]);
+
+    // The end of central directory record says how many files there are:
+    let end = &bytes[bytes.len() - 22..];
+    assert_eq!(b"PK\x05\x06", &end[..4]);
+    assert_eq!(6, u16::from_le_bytes([end[10], end[11]]));
+
+    let text = String::from_utf8_lossy(&bytes);
+    assert!(text.contains("<dc:title>Fish &amp; Chips</dc:title>"));
+    assert!(text.contains("href=\"files/my%20photo.png\" media-type=\"image/png\""));
+}
+
+#[test]
+fn cache_policies() {
+    use crate::server::policy::{self, Cache};
+    use actix_web::http::header::{HeaderMap, HeaderValue, CACHE_CONTROL, CONTENT_TYPE};
+
+    let headers = |cache: Cache| cache.headers().into_iter()
+        .map(|(name, value)| format!("{}: {}", name, value))
+        .collect::<Vec<_>>();
+
+    assert_eq!(vec!["Cache-Control: public, max-age=31536000, immutable"], headers(Cache::Immutable));
+    assert_eq!(
+        vec!["Cache-Control: private, max-age=31536000, immutable", "Vary: FeoBlog-Viewer, FeoBlog-Peer"],
+        headers(Cache::PrivateImmutable),
+    );
+    assert_eq!(vec!["Cache-Control: no-cache"], headers(Cache::Mutable));
+    assert_eq!(
+        vec!["Cache-Control: private, no-cache", "Vary: FeoBlog-Viewer, FeoBlog-Peer"],
+        headers(Cache::PrivateMutable),
+    );
+    assert_eq!(vec!["Cache-Control: public, max-age=86400"], headers(Cache::MaxAge(86400)));
+    assert_eq!(vec!["Cache-Control: no-store"], headers(Cache::NoStore));
+    assert_eq!(vec!["Cache-Control: no-cache"], headers(Cache::Page));
+
+    // Access-Control-Max-Age only applies to preflight requests:
+    assert_eq!(
+        vec![
+            ("Access-Control-Allow-Methods", "OPTIONS, GET, PUT".to_string()),
+            ("Access-Control-Allow-Headers", "*".to_string()),
+            ("Access-Control-Max-Age", "86400".to_string()),
+        ],
+        policy::preflight_headers(),
+    );
+
+    // HTML gets the Page policy by default:
+    let mut html = HeaderMap::new();
+    html.insert(CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+    policy::add_page_headers(&mut html);
+    assert_eq!(html.get(CACHE_CONTROL).unwrap(), "no-cache");
+
+    // ... but not if the handler chose something else, or for other content:
+    let mut chosen = HeaderMap::new();
+    chosen.insert(CONTENT_TYPE, HeaderValue::from_static("text/html"));
+    chosen.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    policy::add_page_headers(&mut chosen);
+    assert_eq!(chosen.get(CACHE_CONTROL).unwrap(), "no-store");
+
+    let mut proto = HeaderMap::new();
+    proto.insert(CONTENT_TYPE, HeaderValue::from_static("application/protobuf3"));
+    policy::add_page_headers(&mut proto);
+    assert!(proto.get(CACHE_CONTROL).is_none());
+}
+
+#[test]
+fn proxy_protocol_headers() {
+    use crate::server::listen::{parse_v1, parse_v2};
+    use std::net::SocketAddr;
+
+    let addr = |s: &str| Some(s.parse::<SocketAddr>().unwrap());
+
+    assert_eq!(addr("192.0.2.1:56324"), parse_v1(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n").unwrap());
+    assert_eq!(addr("[2001:db8::1]:56324"), parse_v1(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n").unwrap());
+    assert_eq!(None, parse_v1(b"PROXY UNKNOWN\r\n").unwrap());
+    assert!(parse_v1(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443").is_err());
+    assert!(parse_v1(b"PROXY TCP4 nope 198.51.100.1 56324 443\r\n").is_err());
+
+    // v2, PROXY command, TCP over IPv4:
+    let v4 = [192, 0, 2, 1, 198, 51, 100, 1, 0xDC, 0x04, 0x01, 0xBB];
+    assert_eq!(addr("192.0.2.1:56324"), parse_v2(0x21, 0x11, &v4).unwrap());
+    // LOCAL command, ex: the proxy's health checks.
+    assert_eq!(None, parse_v2(0x20, 0x00, &[]).unwrap());
+    assert!(parse_v2(0x21, 0x11, &v4[..8]).is_err());
+    assert!(parse_v2(0x11, 0x11, &v4).is_err());
+}
+
+#[test]
+fn proxy_protocol_reads() {
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+    use crate::server::listen::read_proxy_header;
+
+    let timeout = Duration::from_millis(100);
+    let mut system = actix_web::rt::System::new("test");
+
+    // The header's result, and what's left to read after it:
+    let mut read = |input: &[u8]| {
+        let input = input.to_vec();
+        system.block_on(async move {
+            let mut io: &[u8] = &input;
+            let peer = read_proxy_header(&mut io, timeout).await;
+            (peer, io.to_vec())
+        })
+    };
+
+    // Reads just the header, and leaves the request:
+    let (peer, rest) = read(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET / HTTP/1.1\r\n");
+    assert_eq!(Some("192.0.2.1:56324".parse().unwrap()), peer.unwrap());
+    assert_eq!(&b"GET / HTTP/1.1\r\n"[..], &rest[..]);
+
+    let mut v2 = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0C".to_vec();
+    v2.extend_from_slice(&[192, 0, 2, 1, 198, 51, 100, 1, 0xDC, 0x04, 0x01, 0xBB]);
+    v2.extend_from_slice(b"GET");
+    let (peer, rest) = read(&v2);
+    assert_eq!(Some("192.0.2.1:56324".parse().unwrap()), peer.unwrap());
+    assert_eq!(&b"GET"[..], &rest[..]);
+
+    // Plain HTTP, and a v1 line that never ends:
+    assert!(read(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n").0.is_err());
+    let long = format!("PROXY {}\r\n", "1".repeat(200));
+    assert!(read(long.as_bytes()).0.is_err());
+
+    /// A client that connects and never sends anything.
+    struct Silent;
+    impl tokio::io::AsyncRead for Silent {
+        fn poll_read(self: Pin<&mut Self>, _: &mut Context<'_>, _: &mut [u8]) -> Poll<io::Result<usize>> {
+            Poll::Pending
+        }
+    }
+    let err = system.block_on(async move { read_proxy_header(&mut Silent, timeout).await }).unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+}
+
+#[test]
+fn confusable_display_names() {
+    use crate::confusables::looks_like_user_id;
+
+    let id = "42FBCt1kWvD2ZkDdzHvgNaw8SEFYpV7C9kXEmHbq3Zyp";
+    assert!(looks_like_user_id(id));
+    assert!(looks_like_user_id(&format!("Official: {}", id)));
+    assert!(looks_like_user_id(&format!("Official{}", id)));
+
+    // Homoglyphs, fullwidth forms, and invisible characters:
+    assert!(looks_like_user_id("42FBСt1kWvD2ZkDdzНvgNаw8SEFYpV7C9kXEmНbq3Zyp")); // Cyrillic С, Н, а
+    assert!(looks_like_user_id("42ＦＢＣt1kWvD2ZkDdzHvgNaw8SEFYpV7C9kXEmHbq3Zyp"));
+    assert!(looks_like_user_id("42FBCt1kWvD2ZkD\u{200B}dzHvgNaw8SEFY\u{200D}pV7C9kXEmHbq3Zyp"));
+    assert!(looks_like_user_id("42FBCt1kWvD2ZkDdzΗvgΝaw8SEFYpV7C9kXEmHbq3Zyp")); // Greek Η, Ν
+
+    // Ordinary names:
+    assert!(!looks_like_user_id(""));
+    assert!(!looks_like_user_id("Ada Lovelace"));
+    assert!(!looks_like_user_id("Иван Петров"));
+    assert!(!looks_like_user_id("42FBCt1kWvD2ZkDd zHvgNaw8SEFYpV7C9 kXEmHbq3Zyp"));
+    // Long, but not an ID:
+    assert!(!looks_like_user_id(&id[..32]));
+    assert!(!looks_like_user_id("Llanfairpwllgwyngyllgogerychwyrndrobwllllantysiliogogogoch"));
+    assert!(!looks_like_user_id("TheQuickBrownFoxJumpsOverTheLazyDog1234567890"));
+
+    // Only a profile's own name is checked. What its author calls the people
+    // they follow is up to them:
+    use crate::protos::{Profile, ProtoValid};
+    let mut profile = Profile::new();
+    let follow = profile.mut_follows().push_default();
+    follow.mut_user().bytes = vec![1; 32];
+    follow.display_name = id.into();
+    assert!(profile.validate().is_ok());
+    profile.display_name = id.into();
+    assert!(profile.validate().is_err());
+}
+
+#[test]
+fn canonical_base58() {
+    use crate::backend::{Signature, UserID};
+
+    let id = "42FBCt1kWvD2ZkDdzHvgNaw8SEFYpV7C9kXEmHbq3Zyp";
+    assert_eq!(id, UserID::from_base58(id).unwrap().to_base58());
+    // Extra leading zeros:
+    assert!(UserID::from_base58(&format!("1{}", id)).is_err());
+    // Too long to bother decoding:
+    assert!(UserID::from_base58(&"2".repeat(10_000)).is_err());
+    assert!(Signature::from_base58(&"2".repeat(10_000)).is_err());
+    // Not base58:
+    assert!(UserID::from_base58("42FBCt1kWvD2ZkDdzHvgNaw8SEFYpV7C9kXEmHbq3Zy0").is_err());
+}
+
+#[test]
+fn markdown_removals() {
+    use crate::markdown::{ToHTML, Removal, removals};
+
+    // Unsafe links are emptied, and reported:
+    let markdown = "[click](javascript:alert(1)) and [also](JaVaScRiPt:alert(2)) ![img](data:text/html,hi)";
+    let html = markdown.md_to_html();
+    assert!(!html.to_lowercase().contains("script:"), "{}", html);
+    assert!(!html.contains("data:text"), "{}", html);
+    assert_eq!(3, removals(markdown).len());
+
+    // Ordinary and inline image links are left alone:
+    assert!(removals("[ok](https://example.com/) [local](files/a.png) ![pic](data:image/png;base64,AAAA)").is_empty());
+    assert!("[ok](https://example.com/)".md_to_html().contains("href=\"https://example.com/\""));
+
+    // HTML is always escaped, but only active HTML is reported:
+    assert!(removals("Line<br>break").is_empty());
+    assert_eq!(
+        vec![Removal::Html("<img src=x onerror=alert(1)>".into())],
+        removals("Look: <img src=x onerror=alert(1)>"),
+    );
+    assert_eq!(1, removals("<script>alert(1)</script>\n").len());
+    assert!(!"<script>alert(1)</script>\n".md_to_html().contains("<script>"));
+}
+
+#[test]
+fn markdown_unsafe_schemes() {
+    use crate::markdown::{ToHTML, removals};
+
+    // Links with these schemes could run scripts or read local files, so
+    // they're emptied, however they're spelled:
+    for url in &[
+        "javascript:alert(1)",
+        "vbscript:msgbox(1)",
+        "data:text/html;base64,PHNjcmlwdD4=",
+        "file:///etc/passwd",
+        "java\tscript:alert(1)",
+        " JAVASCRIPT:alert(1)",
+    ] {
+        let markdown = format!("[link](<{}>)", url);
+        assert_eq!(1, removals(&markdown).len(), "{}", url);
+        assert!(markdown.md_to_html().contains("href=\"\""), "{}", markdown.md_to_html());
+    }
+
+    // data: images are only allowed as images, not links:
+    assert_eq!(1, removals("[pic](data:image/png;base64,AAAA)").len());
+    assert!(removals("![pic](data:image/png;base64,AAAA)").is_empty());
+    assert_eq!(1, removals("![pic](data:text/html;base64,AAAA)").len());
+
+    // Everything else is left alone, including relative links and other schemes:
+    for url in &["https://example.com/", "http://example.com/", "mailto:me@example.com", "files/a.png", "#top", "?page=2"] {
+        let markdown = format!("[link]({})", url);
+        assert!(removals(&markdown).is_empty(), "{}", url);
+        assert!(markdown.md_to_html().contains(&format!("href=\"{}\"", url)), "{}", markdown.md_to_html());
+    }
+}
+
+#[test]
+fn viewer_headers() {
+    use actix_web::test::TestRequest;
+    use sodiumoxide::crypto::sign;
+    use crate::backend::{Factory, Timestamp, UserID};
+    use crate::backend::sharded;
+    use crate::server::viewer;
+
+    let factory = sharded::Factory::memory();
+    let backend = factory.open().unwrap();
+    backend.setup().unwrap();
+    let author = UserID::from_vec(vec![1u8; 32]).unwrap();
+    let hosts = vec!["blog.example.com".to_string()];
+
+    let (public_key, secret_key) = sign::gen_keypair();
+    let viewer_id = UserID::from_vec(public_key.as_ref().to_vec()).unwrap();
+    let header = |host: &str| {
+        let timestamp = Timestamp::now().unix_utc_ms;
+        let signature = sign::sign_detached(&viewer::signed_bytes("feoblog-viewer", host, timestamp), &secret_key);
+        format!("{} {} {}", viewer_id.to_base58(), timestamp, bs58::encode(signature.as_ref()).into_string())
+    };
+    // The Host header is the client's to choose, so it mustn't matter:
+    let view = |value: &str, hosts: &[String]| {
+        let req = TestRequest::default()
+            .header("Host", "evil.example.com")
+            .header(viewer::HEADER, value)
+            .to_http_request();
+        viewer::viewer(&req, backend.as_ref(), hosts, &author)
+    };
+
+    let good = header("blog.example.com");
+    let found = view(&good, &hosts).unwrap().expect("viewer");
+    assert_eq!(viewer_id.to_base58(), found.to_base58());
+
+    // Replayed here:
+    assert!(view(&good, &hosts).is_err());
+
+    // Signed for another server, and replayed here:
+    assert!(view(&header("evil.example.com"), &hosts).is_err());
+
+    // Servers that don't know who they are can't tell:
+    assert!(view(&header("blog.example.com"), &[]).is_err());
+
+    // Anonymous:
+    let req = TestRequest::default().to_http_request();
+    assert!(viewer::viewer(&req, backend.as_ref(), &hosts, &author).unwrap().is_none());
+}
+
+#[test]
+fn peer_headers() {
+    use actix_web::test::TestRequest;
+    use sodiumoxide::crypto::sign;
+    use crate::backend::{Factory, Timestamp, TrustedPeer};
+    use crate::backend::sharded;
+    use crate::server::peer_auth::{self, ServerKey};
+    use crate::server::viewer;
+
+    let factory = sharded::Factory::memory();
+    let backend = factory.open().unwrap();
+    backend.setup().unwrap();
+    let hosts = vec!["blog.example.com".to_string()];
+
+    let seed = peer_auth::random_seed();
+    let secret_key = sign::keypair_from_seed(&seed).1;
+    let key = ServerKey::from_seed(&seed);
+    backend.add_trusted_peer(&TrustedPeer{
+        key: key.id(),
+        notes: "".into(),
+        created: Timestamp::now(),
+    }).unwrap();
+    let peer = |value: &str| {
+        let req = TestRequest::default()
+            .header("Host", "blog.example.com")
+            .header(peer_auth::HEADER, value)
+            .to_http_request();
+        peer_auth::peer(&req, backend.as_ref(), &hosts)
+    };
+
+    let good = key.header_value("blog.example.com");
+    let found = peer(&good).unwrap().expect("peer");
+    assert_eq!(key.id().to_base58(), found.to_base58());
+
+    // Each header works once:
+    assert!(peer(&good).is_err());
+
+    // Signed for another server:
+    assert!(peer(&key.header_value("other.example.com")).is_err());
+
+    // Too old:
+    let stale = Timestamp::now().unix_utc_ms - 10 * 60 * 1000;
+    let signature = sign::sign_detached(&viewer::signed_bytes(peer_auth::PREFIX, "blog.example.com", stale), &secret_key);
+    let value = format!("{} {} {}", key.id().to_base58(), stale, bs58::encode(signature.as_ref()).into_string());
+    assert!(peer(&value).is_err());
+
+    // Valid, but not trusted:
+    let stranger = ServerKey::from_seed(&peer_auth::random_seed());
+    assert!(peer(&stranger.header_value("blog.example.com")).is_err());
+}
+
+#[test]
+fn sync_report_signatures() {
+    use crate::server::peer_auth::{ServerKey, random_seed};
+    use crate::sync::{report_signer, sign_report};
+
+    let key = ServerKey::from_seed(&random_seed());
+    let report = "Peer: https://feo.example.com\nComplete: true\n";
+    let signed = sign_report(report, &key);
+    assert!(signed.starts_with(report));
+    assert_eq!(key.id().to_base58(), report_signer(&signed).unwrap().to_base58());
+
+    assert!(report_signer(report).is_err());
+    assert!(report_signer(&signed.replace("Complete: true", "Complete: false")).is_err());
+
+    // Signed by someone else, but claiming to be `key`:
+    let other = ServerKey::from_seed(&random_seed());
+    let forged = sign_report(report, &other).replace(&other.id().to_base58(), &key.id().to_base58());
+    assert!(report_signer(&forged).is_err());
+}
+
+#[test]
+fn activitypub_key_ids() {
+    use serde_json::json;
+    use crate::server::activitypub::inbox::{check_actor_key, key_actor};
+
+    let actor = "https://social.example/users/alice";
+    let key_id = "https://social.example/users/alice#main-key";
+    assert_eq!(actor, key_actor(key_id, actor).unwrap());
+
+    // Another server can't sign for Alice:
+    assert!(key_actor("https://evil.example/users/alice#main-key", actor).is_err());
+    assert!(key_actor("https://social.example:8443/users/alice#main-key", actor).is_err());
+    assert!(key_actor("file:///etc/passwd#main-key", actor).is_err());
+
+    let fetched = |id: &str, key_id: &str, owner: &str| json!({
+        "id": id,
+        "publicKey": { "id": key_id, "owner": owner, "publicKeyPem": "" },
+    });
+    assert!(check_actor_key(&fetched(actor, key_id, actor), actor, key_id).is_ok());
+
+    // A forged key ID, whose document claims to be someone else:
+    let forged = "https://social.example/users/mallory#main-key";
+    assert!(check_actor_key(&fetched(actor, key_id, actor), "https://social.example/users/mallory", forged).is_err());
+
+    // A key that's owned by someone other than the actor that serves it:
+    let mallory = "https://social.example/users/mallory";
+    assert!(check_actor_key(&fetched(actor, key_id, mallory), actor, key_id).is_err());
+    assert!(check_actor_key(&fetched(mallory, key_id, actor), actor, key_id).is_err());
+}
+
+#[test]
+fn public_addresses() {
+    use std::net::IpAddr;
+    use crate::server::remote::{is_public, origin};
+
+    let public = |ip: &str| is_public(&ip.parse::<IpAddr>().unwrap());
+    assert!(public("93.184.216.34"));
+    assert!(public("2606:2800:220:1:248:1893:25c8:1946"));
+    for ip in &[
+        "127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254",
+        "100.64.0.1", "0.0.0.0", "::1", "::", "fc00::1", "fe80::1", "::ffff:127.0.0.1",
+        "::ffff:10.0.0.1",
+    ] {
+        assert!(!public(ip), "{} isn't public", ip);
+    }
+
+    assert_eq!(Some("https://example.com".to_string()), origin("HTTPS://Example.com/a#b"));
+    assert_eq!(None, origin("ftp://example.com/"));
+}
+
+#[test]
+fn poll_tallies() {
+    use protobuf::Message as _;
+    use crate::backend::{Factory, ItemRow, ServerUser, Signature, Timestamp, UserID};
+    use crate::backend::sharded;
+    use crate::protos::Item;
+    use crate::server::poll_tally;
+
+    let factory = sharded::Factory::memory();
+    let mut backend = factory.open().unwrap();
+    backend.setup().unwrap();
+    let pollster = UserID::from_vec(vec![1u8; 32]).unwrap();
+    let known = UserID::from_vec(vec![2u8; 32]).unwrap();
+    let stranger = UserID::from_vec(vec![3u8; 32]).unwrap();
+    for user in &[&pollster, &known] {
+        backend.add_server_user(&ServerUser{ user: (*user).clone(), notes: "".into(), on_homepage: false }).unwrap();
+    }
+
+    let save = |backend: &mut dyn crate::backend::Backend, user: &UserID, n: u8, item: &Item| {
+        let row = ItemRow{
+            user: user.clone(),
+            signature: Signature::from_vec(vec![n; 64]).unwrap(),
+            timestamp: Timestamp{ unix_utc_ms: item.timestamp_ms_utc },
+            received: Timestamp{ unix_utc_ms: item.timestamp_ms_utc },
+            item_bytes: item.write_to_bytes().unwrap(),
+        };
+        backend.save_user_item(&row, item).unwrap();
+    };
+
+    let mut poll = Item::new();
+    poll.timestamp_ms_utc = 1000;
+    poll.mut_poll().question = "Tea or coffee?".into();
+    poll.mut_poll().mut_options().push("Tea".into());
+    poll.mut_poll().mut_options().push("Coffee".into());
+    poll.mut_poll().close_ms_utc = 5000;
+    save(backend.as_mut(), &pollster, 1, &poll);
+
+    let vote = |option: u32| {
+        let mut item = Item::new();
+        item.timestamp_ms_utc = 2000;
+        item.mut_vote().mut_poll().mut_user_id().set_bytes(pollster.bytes().to_vec());
+        item.mut_vote().mut_poll().mut_signature().set_bytes(vec![1u8; 64]);
+        item.mut_vote().option = option;
+        item
+    };
+    save(backend.as_mut(), &known, 2, &vote(1));
+    save(backend.as_mut(), &stranger, 3, &vote(0));
+
+    let signature = Signature::from_vec(vec![1u8; 64]).unwrap();
+    let tally = poll_tally(backend.as_ref(), &pollster, &signature, poll.get_poll()).unwrap();
+    assert!(tally.closed);
+    assert_eq!(vec![0, 1], tally.counts);
+
+    // No results until it closes:
+    let mut open_poll = poll.get_poll().clone();
+    open_poll.close_ms_utc = Timestamp::now().unix_utc_ms + 60_000;
+    let tally = poll_tally(backend.as_ref(), &pollster, &signature, &open_poll).unwrap();
+    assert!(!tally.closed);
+    assert!(tally.counts.is_empty());
+}
+
+#[test]
+fn csp_report_limits() {
+    use std::net::IpAddr;
+    use crate::server::reports::{MAX_REPORTS_PER_WINDOW, ReportLimiter};
+
+    let limiter = ReportLimiter::new();
+    let address: IpAddr = "203.0.113.1".parse().unwrap();
+    let other: IpAddr = "203.0.113.2".parse().unwrap();
+
+    assert!(limiter.allow(address, MAX_REPORTS_PER_WINDOW - 1));
+    assert!(!limiter.allow(address, 2), "a batch that would go over is refused whole");
+    assert!(limiter.allow(address, 1));
+    assert!(!limiter.allow(address, 1));
+
+    // Other addresses have their own limit:
+    assert!(limiter.allow(other, MAX_REPORTS_PER_WINDOW));
+}
+
+#[test]
+fn shared_blocklist_reloads() {
+    use std::net::IpAddr;
+    use crate::backend::{Factory, IpBlock, Timestamp};
+    use crate::backend::sharded;
+    use crate::server::blocklist::SharedBlocklist;
+
+    let factory = sharded::Factory::memory();
+    let backend = factory.open().unwrap();
+    backend.setup().unwrap();
+
+    let configured: IpAddr = "192.0.2.1".parse().unwrap();
+    let added: IpAddr = "198.51.100.7".parse().unwrap();
+    let blocklist = SharedBlocklist::new(vec!["192.0.2.0/24".parse().unwrap()]);
+    assert!(blocklist.contains(&configured));
+    assert!(!blocklist.contains(&added));
+
+    backend.add_ip_block(&IpBlock{
+        cidr: "198.51.100.0/24".into(),
+        notes: "".into(),
+        created: Timestamp::now(),
+    }).unwrap();
+    blocklist.reload(backend.as_ref()).unwrap();
+    assert!(blocklist.contains(&configured));
+    assert!(blocklist.contains(&added));
+
+    assert!(backend.remove_ip_block("198.51.100.0/24").unwrap());
+    blocklist.reload(backend.as_ref()).unwrap();
+    assert!(blocklist.contains(&configured));
+    assert!(!blocklist.contains(&added));
+}
+
+#[test]
+fn homepage_snapshot_renders_once() {
+    use actix_web::web::Bytes;
+    use crate::server::snapshot::{HomepageSnapshot, Lookup};
+
+    let snapshot = HomepageSnapshot::new();
+    let render = match snapshot.html() {
+        Lookup::Render(render) => render,
+        Lookup::Serve(_) => panic!("nothing rendered yet"),
+    };
+    render.save(Bytes::from_static(b"first"));
+    assert!(matches!(snapshot.html(), Lookup::Serve(html) if html == "first"));
+
+    // After a write, one request renders while the others get the previous copy:
+    snapshot.invalidate();
+    let render = match snapshot.html() {
+        Lookup::Render(render) => render,
+        Lookup::Serve(_) => panic!("stale"),
+    };
+    assert!(matches!(snapshot.html(), Lookup::Serve(html) if html == "first"));
+    render.save(Bytes::from_static(b"second"));
+    assert!(matches!(snapshot.html(), Lookup::Serve(html) if html == "second"));
+
+    // A render that fails lets the next request try:
+    snapshot.invalidate();
+    drop(snapshot.html());
+    assert!(matches!(snapshot.html(), Lookup::Render(_)));
+}
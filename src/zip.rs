@@ -0,0 +1,167 @@
+//! Writes zip files, for EPUBs. (See: `epub.rs`)
+//!
+//! Entries are stored, not compressed. EPUB requires that for its first file
+//! anyway, and most of what's left is small XHTML or already-compressed images.
+//!
+//! See: <https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT>
+
+use std::io::Write;
+
+use failure::{Error, bail};
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+
+/// Version 1.0: stored entries, no zip64.
+const VERSION: u16 = 10;
+
+/// File names are UTF-8.
+const FLAG_UTF8: u16 = 1 << 11;
+
+pub(crate) struct ZipWriter<W: Write> {
+    out: W,
+    offset: u64,
+    entries: Vec<CentralEntry>,
+}
+
+struct CentralEntry {
+    name: String,
+    crc: u32,
+    size: u32,
+    dos_time: u16,
+    dos_date: u16,
+    offset: u32,
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub fn new(out: W) -> Self {
+        ZipWriter { out, offset: 0, entries: vec![] }
+    }
+
+    /// Add a file. `mtime` is in seconds since the Unix epoch.
+    pub fn append(&mut self, name: &str, data: &[u8], mtime: i64) -> Result<(), Error> {
+        if data.len() > u32::MAX as usize || self.offset > u32::MAX as u64 || self.entries.len() >= u16::MAX as usize {
+            bail!("Zip file too large");
+        }
+        if name.len() > u16::MAX as usize {
+            bail!("Zip entry name too long: {}", name);
+        }
+        let (dos_time, dos_date) = dos_date_time(mtime);
+        let entry = CentralEntry {
+            name: name.to_string(),
+            crc: crc32(data),
+            size: data.len() as u32,
+            dos_time,
+            dos_date,
+            offset: self.offset as u32,
+        };
+
+        let mut header = vec![];
+        header.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&FLAG_UTF8.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes()); // stored
+        header.extend_from_slice(&entry.dos_time.to_le_bytes());
+        header.extend_from_slice(&entry.dos_date.to_le_bytes());
+        header.extend_from_slice(&entry.crc.to_le_bytes());
+        header.extend_from_slice(&entry.size.to_le_bytes()); // compressed
+        header.extend_from_slice(&entry.size.to_le_bytes()); // uncompressed
+        header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes()); // extra field
+        header.extend_from_slice(name.as_bytes());
+
+        self.out.write_all(&header)?;
+        self.out.write_all(data)?;
+        self.offset += (header.len() + data.len()) as u64;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Write the central directory, and return the underlying writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        if self.offset > u32::MAX as u64 {
+            bail!("Zip file too large");
+        }
+        let directory_offset = self.offset as u32;
+        let mut directory = vec![];
+        for entry in &self.entries {
+            directory.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+            directory.extend_from_slice(&VERSION.to_le_bytes()); // made by
+            directory.extend_from_slice(&VERSION.to_le_bytes()); // needed
+            directory.extend_from_slice(&FLAG_UTF8.to_le_bytes());
+            directory.extend_from_slice(&0u16.to_le_bytes()); // stored
+            directory.extend_from_slice(&entry.dos_time.to_le_bytes());
+            directory.extend_from_slice(&entry.dos_date.to_le_bytes());
+            directory.extend_from_slice(&entry.crc.to_le_bytes());
+            directory.extend_from_slice(&entry.size.to_le_bytes());
+            directory.extend_from_slice(&entry.size.to_le_bytes());
+            directory.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            directory.extend_from_slice(&0u16.to_le_bytes()); // extra field
+            directory.extend_from_slice(&0u16.to_le_bytes()); // comment
+            directory.extend_from_slice(&0u16.to_le_bytes()); // disk number
+            directory.extend_from_slice(&0u16.to_le_bytes()); // internal attributes
+            directory.extend_from_slice(&0u32.to_le_bytes()); // external attributes
+            directory.extend_from_slice(&entry.offset.to_le_bytes());
+            directory.extend_from_slice(entry.name.as_bytes());
+        }
+
+        let count = self.entries.len() as u16;
+        let mut end = vec![];
+        end.extend_from_slice(&END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes()); // this disk
+        end.extend_from_slice(&0u16.to_le_bytes()); // disk with the directory
+        end.extend_from_slice(&count.to_le_bytes()); // entries on this disk
+        end.extend_from_slice(&count.to_le_bytes()); // entries
+        end.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        end.extend_from_slice(&directory_offset.to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes()); // comment
+
+        self.out.write_all(&directory)?;
+        self.out.write_all(&end)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// MS-DOS time and date fields, in UTC. DOS dates start in 1980.
+fn dos_date_time(unix_seconds: i64) -> (u16, u16) {
+    use time::{Duration, OffsetDateTime};
+    use std::ops::Add;
+
+    let datetime = OffsetDateTime::unix_epoch().add(Duration::seconds(unix_seconds));
+    if datetime.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let time = ((datetime.hour() as u16) << 11) | ((datetime.minute() as u16) << 5) | (datetime.second() as u16 / 2);
+    let year = (datetime.year() - 1980).min(127) as u16;
+    let date = (year << 9) | ((datetime.month() as u16) << 5) | datetime.day() as u16;
+    (time, date)
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// The CRC-32 that zip (and gzip, PNG, ...) use.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc = CRC_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}